@@ -0,0 +1,21 @@
+// Fuzz: валидация произвольных ClientMessage, полученных из MessagePack
+// Короткая и полная валидация должны принимать одни и те же сообщения
+
+#![no_main]
+
+use construct_core::protocol::messages::ClientMessage;
+use construct_core::protocol::validation::{validate_client_message, validate_client_message_all};
+use construct_core::protocol::wire::unpack_raw;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(msg) = unpack_raw::<ClientMessage>(data) else {
+        return;
+    };
+    assert_eq!(
+        validate_client_message(&msg).is_ok(),
+        validate_client_message_all(&msg).is_empty(),
+        "{:?}",
+        msg
+    );
+});
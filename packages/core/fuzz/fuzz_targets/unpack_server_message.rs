@@ -0,0 +1,21 @@
+// Fuzz: разбор недоверенных фреймов сервера и их валидация
+// Любой вход должен давать Ok или Err, но не панику
+
+#![no_main]
+
+use construct_core::protocol::validation::validate_server_message;
+use construct_core::protocol::wire::{decode_server_frame, unpack_server_message, InboundFrame};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = unpack_server_message(data) {
+        let _ = validate_server_message(&msg);
+    }
+
+    // Текстовый фрейм в режиме совместимости разбирается как JSON
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(msg) = decode_server_frame(InboundFrame::Text(text), true) {
+            let _ = validate_server_message(&msg);
+        }
+    }
+});
@@ -0,0 +1,131 @@
+// Seed корпус для fuzz targets: известные варианты сообщений протокола
+// Запуск: `cargo run --bin seed_corpus` из packages/core/fuzz
+
+use construct_core::protocol::messages::*;
+use construct_core::protocol::wire::{pack_client_message, pack_message_json};
+use std::fs;
+use std::path::Path;
+
+fn chat_message(seq: Option<u64>) -> ChatMessage {
+    ChatMessage {
+        id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+        from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+        to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+        ratchet_dh_public: vec![7u8; 32],
+        message_number: 3,
+        content: "AQID".to_string(),
+        timestamp: 1_700_000_000,
+        seq,
+    }
+}
+
+fn client_messages() -> Vec<(&'static str, ClientMessage)> {
+    vec![
+        (
+            "register",
+            ClientMessage::Register(RegisterData {
+                username: "alice".to_string(),
+                password: "password1".to_string(),
+                public_key: "a2V5".to_string(),
+            }),
+        ),
+        (
+            "login",
+            ClientMessage::Login(LoginData {
+                username: "alice".to_string(),
+                password: "password1".to_string(),
+            }),
+        ),
+        ("send_message", ClientMessage::SendMessage(chat_message(None))),
+        (
+            "backup_upload",
+            ClientMessage::BackupUpload(BackupUploadData {
+                encrypted_blob: vec![1, 2, 3],
+                version: 1,
+            }),
+        ),
+        (
+            "request_resend",
+            ClientMessage::RequestResend(RequestResendData {
+                from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+                to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+                message_numbers: vec![1, 2],
+            }),
+        ),
+    ]
+}
+
+fn server_messages() -> Vec<(&'static str, ServerMessage)> {
+    vec![
+        ("message", ServerMessage::Message(chat_message(Some(17)))),
+        (
+            "public_key_bundle",
+            ServerMessage::PublicKeyBundle(PublicKeyBundleData {
+                user_id: "bob".to_string(),
+                identity_public: "aWQ=".to_string(),
+                signed_prekey_public: "c3Br".to_string(),
+                signature: "c2ln".to_string(),
+                verifying_key: "dms=".to_string(),
+                suite_id: Some("1".to_string()),
+                one_time_prekey_public: None,
+                one_time_prekey_id: None,
+                signed_prekey_id: None,
+            }),
+        ),
+        (
+            "search_results",
+            ServerMessage::SearchResults(SearchResultsData {
+                users: vec![PublicUserInfo {
+                    id: "bob".to_string(),
+                    username: "bob".to_string(),
+                }],
+            }),
+        ),
+        (
+            "ack",
+            ServerMessage::Ack(AckData {
+                message_id: "m1".to_string(),
+                status: "delivered".to_string(),
+            }),
+        ),
+        (
+            "error",
+            ServerMessage::Error(ErrorData {
+                code: "E1".to_string(),
+                message: "boom".to_string(),
+            }),
+        ),
+        ("session_expired", ServerMessage::SessionExpired),
+        (
+            "backup_download_response",
+            ServerMessage::BackupDownloadResponse(BackupDownloadResponseData {
+                encrypted_blob: vec![4, 5, 6],
+                version: 1,
+            }),
+        ),
+    ]
+}
+
+fn write(dir: &Path, name: &str, bytes: &[u8]) {
+    fs::create_dir_all(dir).expect("create corpus dir");
+    fs::write(dir.join(name), bytes).expect("write seed");
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+
+    let client_dir = root.join("unpack_client_message");
+    for (name, msg) in client_messages() {
+        write(&client_dir, name, &pack_client_message(&msg).expect("pack client message"));
+    }
+
+    // Сервер шлет структуры картами; JSON сиды - для режима совместимости
+    let server_dir = root.join("unpack_server_message");
+    for (name, msg) in server_messages() {
+        write(&server_dir, name, &rmp_serde::to_vec_named(&msg).expect("pack server message"));
+        let json = pack_message_json(&msg).expect("pack json");
+        write(&server_dir, &format!("{}.json", name), json.as_bytes());
+    }
+
+    println!("Seed corpus written to {}", root.display());
+}
@@ -1,10 +1,19 @@
 // API для управления контактами
 
-use crate::storage::models::StoredContact;
+use crate::storage::models::{IdentityKeyRecord, StoredContact};
 use crate::utils::error::{ConstructError, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Максимальная длина заметки о контакте
+pub const MAX_CONTACT_NOTE_LENGTH: usize = 4096;
+/// Максимальное количество полей метаданных контакта
+pub const MAX_CONTACT_METADATA_ENTRIES: usize = 32;
+/// Максимальная длина ключа метаданных
+pub const MAX_CONTACT_METADATA_KEY_LENGTH: usize = 64;
+/// Максимальная длина значения метаданных
+pub const MAX_CONTACT_METADATA_VALUE_LENGTH: usize = 1024;
+
 /// Информация о контакте
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Contact {
@@ -13,6 +22,24 @@ pub struct Contact {
     pub public_key_bundle: Option<PublicKeyBundle>,
     pub added_at: i64,
     pub last_message_at: Option<i64>,
+    /// Подтверждена ли личность контакта (сбрасывается при смене identity ключа)
+    #[serde(default)]
+    pub verified: bool,
+    /// Заметка пользователя о контакте
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// Произвольные поля (никнейм, теги и т.п.)
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Все identity ключи контакта в порядке появления
+    #[serde(default)]
+    pub identity_key_history: Vec<IdentityKeyRecord>,
+    /// Сообщения сохраняются, но без уведомлений
+    #[serde(default)]
+    pub muted: bool,
+    /// Входящие сообщения отбрасываются
+    #[serde(default)]
+    pub blocked: bool,
 }
 
 /// Публичный ключевой bundle контакта
@@ -22,6 +49,9 @@ pub struct PublicKeyBundle {
     pub signed_prekey_public: String,
     pub signature: String,
     pub verifying_key: String,
+    /// Id signed prekey (отсутствует в карточках старых версий)
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
 }
 
 /// Менеджер контактов
@@ -66,10 +96,157 @@ impl ContactManager {
             ConstructError::ValidationError(format!("Contact not found: {}", user_id))
         })?;
 
+        let known_key = matches!(
+            contact.identity_key_history.last(),
+            Some(record) if record.identity_public == bundle.identity_public
+        );
+        if !known_key {
+            contact.identity_key_history.push(IdentityKeyRecord {
+                identity_public: bundle.identity_public.clone(),
+                first_seen: crate::utils::time::current_timestamp(),
+                accepted_at: None,
+            });
+        }
+
         contact.public_key_bundle = Some(bundle);
         Ok(())
     }
 
+    /// Принять текущий identity ключ контакта после его смены
+    pub fn accept_identity_key(&mut self, user_id: &str, accepted_at: i64) -> Result<()> {
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+        let identity_public = contact
+            .public_key_bundle
+            .as_ref()
+            .map(|bundle| bundle.identity_public.as_str())
+            .ok_or_else(|| {
+                ConstructError::ValidationError(format!("Contact has no key bundle: {}", user_id))
+            })?;
+
+        let record = contact
+            .identity_key_history
+            .iter_mut()
+            .rev()
+            .find(|record| record.identity_public == identity_public)
+            .ok_or_else(|| {
+                ConstructError::InternalError(format!("Identity key missing from history: {}", user_id))
+            })?;
+        record.accepted_at.get_or_insert(accepted_at);
+        Ok(())
+    }
+
+    /// Принимал ли пользователь текущий identity ключ контакта
+    pub fn is_identity_key_accepted(&self, user_id: &str) -> bool {
+        let Some(contact) = self.contacts.get(user_id) else {
+            return false;
+        };
+        let Some(bundle) = &contact.public_key_bundle else {
+            return false;
+        };
+        contact
+            .identity_key_history
+            .iter()
+            .any(|record| record.identity_public == bundle.identity_public && record.accepted_at.is_some())
+    }
+
+    /// Сменился ли identity ключ контакта без подтверждения пользователем
+    ///
+    /// Первый ключ из журнала принимается при знакомстве; каждый следующий
+    /// ждет `accept_identity_key` после сверки safety number вне приложения
+    pub fn has_pending_identity_change(&self, user_id: &str) -> bool {
+        let Some(contact) = self.contacts.get(user_id) else {
+            return false;
+        };
+        let Some(bundle) = &contact.public_key_bundle else {
+            return false;
+        };
+        contact
+            .identity_key_history
+            .iter()
+            .rposition(|record| record.identity_public == bundle.identity_public)
+            .is_some_and(|index| index > 0 && contact.identity_key_history[index].accepted_at.is_none())
+    }
+
+    /// Отметить контакт как подтвержденный/неподтвержденный
+    pub fn set_verified(&mut self, user_id: &str, verified: bool) -> Result<()> {
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+
+        contact.verified = verified;
+        Ok(())
+    }
+
+    /// Отключить/включить уведомления от контакта
+    pub fn set_muted(&mut self, user_id: &str, muted: bool) -> Result<()> {
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+
+        contact.muted = muted;
+        Ok(())
+    }
+
+    /// Заблокировать/разблокировать контакт
+    pub fn set_blocked(&mut self, user_id: &str, blocked: bool) -> Result<()> {
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+
+        contact.blocked = blocked;
+        Ok(())
+    }
+
+    /// Установить или удалить (`None`) заметку о контакте
+    pub fn set_note(&mut self, user_id: &str, note: Option<String>) -> Result<()> {
+        if let Some(note) = &note {
+            check_length("Contact note", note, MAX_CONTACT_NOTE_LENGTH)?;
+        }
+
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+
+        contact.notes = note;
+        Ok(())
+    }
+
+    /// Установить или удалить (`None`) поле метаданных контакта
+    pub fn set_metadata(&mut self, user_id: &str, key: &str, value: Option<String>) -> Result<()> {
+        if key.is_empty() {
+            return Err(ConstructError::ValidationError(
+                "Contact metadata key cannot be empty".to_string(),
+            ));
+        }
+        check_length("Contact metadata key", key, MAX_CONTACT_METADATA_KEY_LENGTH)?;
+
+        let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
+            ConstructError::ValidationError(format!("Contact not found: {}", user_id))
+        })?;
+
+        match value {
+            Some(value) => {
+                check_length("Contact metadata value", &value, MAX_CONTACT_METADATA_VALUE_LENGTH)?;
+                if !contact.metadata.contains_key(key)
+                    && contact.metadata.len() >= MAX_CONTACT_METADATA_ENTRIES
+                {
+                    return Err(ConstructError::ValidationError(format!(
+                        "Too many contact metadata entries (max {})",
+                        MAX_CONTACT_METADATA_ENTRIES
+                    )));
+                }
+                contact.metadata.insert(key.to_string(), value);
+            }
+            None => {
+                contact.metadata.remove(key);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Обновить время последнего сообщения
     pub fn update_last_message_time(&mut self, user_id: &str, timestamp: i64) -> Result<()> {
         let contact = self.contacts.get_mut(user_id).ok_or_else(|| {
@@ -145,6 +322,18 @@ impl Default for ContactManager {
     }
 }
 
+fn check_length(field: &str, value: &str, max: usize) -> Result<()> {
+    if value.len() > max {
+        return Err(ConstructError::ValidationError(format!(
+            "{} too long: {} (max {})",
+            field,
+            value.len(),
+            max
+        )));
+    }
+    Ok(())
+}
+
 /// Создать новый контакт
 pub fn create_contact(id: String, username: String) -> Contact {
     Contact {
@@ -153,6 +342,12 @@ pub fn create_contact(id: String, username: String) -> Contact {
         public_key_bundle: None,
         added_at: crate::utils::time::current_timestamp(),
         last_message_at: None,
+        verified: false,
+        notes: None,
+        metadata: HashMap::new(),
+        identity_key_history: Vec::new(),
+        muted: false,
+        blocked: false,
     }
 }
 
@@ -162,9 +357,39 @@ impl From<StoredContact> for Contact {
         Contact {
             id: stored.id,
             username: stored.username,
-            public_key_bundle: None, // Ключи хранятся отдельно
-            added_at: crate::utils::time::current_timestamp(),
-            last_message_at: None,
+            public_key_bundle: stored
+                .public_key_bundle
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok()),
+            added_at: stored.added_at,
+            last_message_at: stored.last_message_at,
+            verified: stored.verified,
+            notes: stored.notes,
+            metadata: stored.metadata,
+            identity_key_history: stored.identity_key_history,
+            muted: stored.muted,
+            blocked: stored.blocked,
+        }
+    }
+}
+
+/// Конвертировать Contact в StoredContact (bundle сериализуется в JSON)
+impl From<&Contact> for StoredContact {
+    fn from(contact: &Contact) -> Self {
+        StoredContact {
+            id: contact.id.clone(),
+            username: contact.username.clone(),
+            public_key_bundle: contact
+                .public_key_bundle
+                .as_ref()
+                .and_then(|bundle| serde_json::to_vec(bundle).ok()),
+            added_at: contact.added_at,
+            last_message_at: contact.last_message_at,
+            verified: contact.verified,
+            notes: contact.notes.clone(),
+            metadata: contact.metadata.clone(),
+            identity_key_history: contact.identity_key_history.clone(),
+            muted: contact.muted,
+            blocked: contact.blocked,
         }
     }
 }
@@ -216,4 +441,61 @@ mod tests {
         manager.remove_contact("user1");
         assert!(!manager.has_contact("user1"));
     }
+
+    #[test]
+    fn test_pending_identity_change() {
+        let mut manager = ContactManager::new();
+        manager
+            .add_contact(create_contact("1".to_string(), "alice".to_string()))
+            .unwrap();
+        let bundle = |identity: &str| PublicKeyBundle {
+            identity_public: identity.to_string(),
+            signed_prekey_public: String::new(),
+            signature: String::new(),
+            verifying_key: String::new(),
+            signed_prekey_id: None,
+        };
+
+        // Первый ключ принимается при знакомстве
+        manager.update_contact_keys("1", bundle("first")).unwrap();
+        assert!(!manager.has_pending_identity_change("1"));
+
+        manager.update_contact_keys("1", bundle("second")).unwrap();
+        assert!(manager.has_pending_identity_change("1"));
+        manager.accept_identity_key("1", 100).unwrap();
+        assert!(!manager.has_pending_identity_change("1"));
+
+        // Возврат к прежнему ключу - тоже смена, требующая подтверждения
+        manager.update_contact_keys("1", bundle("first")).unwrap();
+        assert!(manager.has_pending_identity_change("1"));
+    }
+
+    #[test]
+    fn test_contact_metadata_limits() {
+        let mut manager = ContactManager::new();
+        manager
+            .add_contact(create_contact("1".to_string(), "alice".to_string()))
+            .unwrap();
+
+        assert!(manager
+            .set_note("1", Some("x".repeat(MAX_CONTACT_NOTE_LENGTH + 1)))
+            .is_err());
+        assert!(manager.set_metadata("1", "", Some("v".to_string())).is_err());
+        assert!(manager
+            .set_metadata("1", &"k".repeat(MAX_CONTACT_METADATA_KEY_LENGTH + 1), Some("v".to_string()))
+            .is_err());
+
+        for i in 0..MAX_CONTACT_METADATA_ENTRIES {
+            manager
+                .set_metadata("1", &format!("key{}", i), Some("v".to_string()))
+                .unwrap();
+        }
+        assert!(manager
+            .set_metadata("1", "one_more", Some("v".to_string()))
+            .is_err());
+        // Перезапись существующего поля не упирается в лимит
+        manager
+            .set_metadata("1", "key0", Some("updated".to_string()))
+            .unwrap();
+    }
 }
@@ -1,18 +1,35 @@
-use crate::crypto::keys::KeyManager;
+use crate::crypto::double_ratchet::{PaddingMode, DEFAULT_MAX_SKIPPED_MESSAGES};
+use crate::crypto::keys::{KeyManager, SignedOneTimePrekey};
 use crate::crypto::session::SessionManager;
 use crate::crypto::x3dh::PublicKeyBundle;
+use crate::crypto::classic_suite::ClassicSuiteProvider;
 use crate::crypto::{ClientCrypto, CryptoProvider};
+use crate::protocol::messages::{MessageBody, PublicKeyBundleData};
 use crate::utils::error::{ConstructError, Result};
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Префикс AAD при привязке timestamp сообщения
+const TIMESTAMP_AAD_LABEL: &[u8] = b"construct-timestamp:";
+
+/// Публичный ключевой bundle.
+/// Равенство сравнивает весь публичный материал; смену identity без учета
+/// ротации prekey проверяет `same_identity`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyBundle {
     pub identity_public: Vec<u8>,
     pub signed_prekey_public: Vec<u8>,
     pub signature: Vec<u8>,
     pub verifying_key: Vec<u8>,
     pub suite_id: u16, // Added
+    /// Одноразовый prekey получателя для X3DH (отсутствует, если пул исчерпан)
+    #[serde(default)]
+    pub one_time_prekey_public: Option<Vec<u8>>,
+    #[serde(default)]
+    pub one_time_prekey_id: Option<u32>,
+    /// Id signed prekey: получатель найдет prekey и после его ротации
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
 }
 
 impl From<PublicKeyBundle> for KeyBundle {
@@ -23,6 +40,9 @@ impl From<PublicKeyBundle> for KeyBundle {
             signature: bundle.signature,
             verifying_key: bundle.verifying_key,
             suite_id: bundle.suite_id, // Added
+            one_time_prekey_public: bundle.one_time_prekey_public,
+            one_time_prekey_id: bundle.one_time_prekey_id,
+            signed_prekey_id: bundle.signed_prekey_id,
         }
     }
 }
@@ -35,6 +55,9 @@ impl From<crate::crypto::RegistrationBundle> for KeyBundle {
             signature: bundle.signature,
             verifying_key: bundle.verifying_key,
             suite_id: bundle.suite_id, // Added
+            one_time_prekey_public: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
         }
     }
 }
@@ -47,10 +70,166 @@ impl From<KeyBundle> for PublicKeyBundle {
             signature: bundle.signature,
             verifying_key: bundle.verifying_key,
             suite_id: bundle.suite_id, // Added
+            one_time_prekey_public: bundle.one_time_prekey_public,
+            one_time_prekey_id: bundle.one_time_prekey_id,
+            signed_prekey_id: bundle.signed_prekey_id,
         }
     }
 }
 
+impl KeyBundle {
+    /// Хеш публичного материала bundle (hex SHA-256) для сверки между клиентами.
+    /// Одноразовый prekey не входит: сервер выдает каждому запросившему свой
+    pub fn content_hash(&self) -> String {
+        use sha2::{Digest, Sha256};
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"construct-key-bundle:");
+        for field in [
+            &self.identity_public,
+            &self.signed_prekey_public,
+            &self.signature,
+            &self.verifying_key,
+        ] {
+            hasher.update((field.len() as u32).to_be_bytes());
+            hasher.update(field);
+        }
+        hasher.update(self.suite_id.to_be_bytes());
+
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Тот же identity ключ (bundle мог измениться только ротацией prekey)
+    pub fn same_identity(&self, other: &KeyBundle) -> bool {
+        self.identity_public == other.identity_public
+    }
+
+    /// Структурная проверка bundle до X3DH: suite, длины ключей по их ролям и подпись prekey.
+    /// Перепутанные identity и verifying ключи дают явную ошибку "key role mismatch"
+    #[must_use = "an unchecked bundle must not be used for X3DH"]
+    pub fn verify<P: CryptoProvider>(&self) -> Result<()> {
+        let malformed = |reason: &str| {
+            ConstructError::ValidationError(format!("malformed bundle: {}", reason))
+        };
+
+        // PQ бандл в сборке без PQ не должен уйти в классический путь
+        crate::crypto::ensure_suite_available(self.suite_id)
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+
+        if self.suite_id != P::suite_id() {
+            return Err(malformed(&format!(
+                "suite {} does not match provider suite {}",
+                self.suite_id,
+                P::suite_id()
+            )));
+        }
+
+        if let Some(lengths) = crate::crypto::suite_key_lengths(self.suite_id) {
+            let kem_len_ok = self.identity_public.len() == lengths.kem_public_key
+                && self.signed_prekey_public.len() == lengths.kem_public_key;
+            if !kem_len_ok || self.verifying_key.len() != lengths.verifying_key {
+                if self.identity_public.len() == lengths.verifying_key
+                    && self.verifying_key.len() == lengths.kem_public_key
+                {
+                    return Err(malformed("key role mismatch"));
+                }
+                return Err(malformed("invalid key length"));
+            }
+            if self.signature.len() != lengths.signature {
+                return Err(malformed("invalid signature length"));
+            }
+            if self
+                .one_time_prekey_public
+                .as_ref()
+                .is_some_and(|key| key.len() != lengths.kem_public_key)
+            {
+                return Err(malformed("invalid key length"));
+            }
+        }
+
+        if self.one_time_prekey_public.is_some() != self.one_time_prekey_id.is_some() {
+            return Err(malformed("one-time prekey without id"));
+        }
+
+        let verifying_key = P::signature_public_key_from_bytes(self.verifying_key.clone());
+        if P::verify(&verifying_key, &self.signed_prekey_public, &self.signature).is_ok() {
+            return Ok(());
+        }
+
+        // Подпись сходится с identity ключом в роли verifying - ключи перепутаны местами
+        let swapped = P::signature_public_key_from_bytes(self.identity_public.clone());
+        if P::verify(&swapped, &self.signed_prekey_public, &self.signature).is_ok() {
+            return Err(malformed("key role mismatch"));
+        }
+
+        Err(malformed("signed prekey signature is invalid"))
+    }
+
+    /// Конвертировать в формат протокола (base64 строки) для пользователя `user_id`
+    pub fn to_bundle_data(&self, user_id: &str) -> PublicKeyBundleData {
+        use base64::Engine;
+        let b64 = &base64::engine::general_purpose::STANDARD;
+
+        PublicKeyBundleData {
+            user_id: user_id.to_string(),
+            identity_public: b64.encode(&self.identity_public),
+            signed_prekey_public: b64.encode(&self.signed_prekey_public),
+            signature: b64.encode(&self.signature),
+            verifying_key: b64.encode(&self.verifying_key),
+            suite_id: Some(self.suite_id.to_string()),
+            one_time_prekey_public: self.one_time_prekey_public.as_ref().map(|key| b64.encode(key)),
+            one_time_prekey_id: self.one_time_prekey_id,
+            signed_prekey_id: self.signed_prekey_id,
+        }
+    }
+}
+
+/// Bundle из ответа сервера (ServerMessage::PublicKeyBundle) -> KeyBundle для init_session
+impl TryFrom<&PublicKeyBundleData> for KeyBundle {
+    type Error = ConstructError;
+
+    fn try_from(data: &PublicKeyBundleData) -> Result<Self> {
+        fn decode(field: &str, value: &str) -> Result<Vec<u8>> {
+            use base64::Engine;
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(value)
+                .map_err(|e| {
+                    ConstructError::ValidationError(format!("Invalid base64 in {}: {}", field, e))
+                })?;
+            if bytes.is_empty() {
+                return Err(ConstructError::ValidationError(format!("{} is empty", field)));
+            }
+            Ok(bytes)
+        }
+
+        let suite_id = match &data.suite_id {
+            Some(id) => id.parse::<u16>().map_err(|_| {
+                ConstructError::ValidationError(format!("Invalid suite id: {}", id))
+            })?,
+            None => ClassicSuiteProvider::suite_id(),
+        };
+
+        Ok(Self {
+            identity_public: decode("identity_public", &data.identity_public)?,
+            signed_prekey_public: decode("signed_prekey_public", &data.signed_prekey_public)?,
+            signature: decode("signature", &data.signature)?,
+            verifying_key: decode("verifying_key", &data.verifying_key)?,
+            suite_id,
+            one_time_prekey_public: data
+                .one_time_prekey_public
+                .as_deref()
+                .map(|key| decode("one_time_prekey_public", key))
+                .transpose()?,
+            one_time_prekey_id: data.one_time_prekey_id,
+            signed_prekey_id: data.signed_prekey_id,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegistrationBundleB64 {
     pub identity_public: String,
@@ -58,6 +237,7 @@ pub struct RegistrationBundleB64 {
     pub signature: String,
     pub verifying_key: String,
     pub suite_id: String, // Added
+    pub signed_prekey_id: u32,
 }
 
 pub struct CryptoCore<P: CryptoProvider> {
@@ -67,12 +247,77 @@ pub struct CryptoCore<P: CryptoProvider> {
     _phantom: PhantomData<P>,
 }
 
+/// Параметры создания CryptoCore
+pub struct CryptoCoreBuilder<P: CryptoProvider> {
+    max_skipped_messages: u32,
+    padding: PaddingMode,
+    header_encryption: bool,
+    _phantom: PhantomData<P>,
+}
+
+impl<P: CryptoProvider> CryptoCoreBuilder<P> {
+    pub fn new() -> Self {
+        Self {
+            max_skipped_messages: DEFAULT_MAX_SKIPPED_MESSAGES,
+            padding: PaddingMode::default(),
+            header_encryption: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Лимит пропущенных сообщений в каждой Double Ratchet сессии
+    pub fn max_skipped_messages(mut self, limit: u32) -> Self {
+        self.max_skipped_messages = limit;
+        self
+    }
+
+    /// Выравнивание длины сообщений в новых сессиях (у собеседника должно совпадать)
+    pub fn padding(mut self, padding: PaddingMode) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Шифрование заголовков в сессиях, которые открываем мы
+    pub fn header_encryption(mut self, enabled: bool) -> Self {
+        self.header_encryption = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<CryptoCore<P>> {
+        let mut core = CryptoCore::new()?;
+        core.client.set_max_skipped_messages(self.max_skipped_messages);
+        core.client.set_padding(self.padding);
+        core.client.set_header_encryption(self.header_encryption);
+        Ok(core)
+    }
+}
+
+impl<P: CryptoProvider> Default for CryptoCoreBuilder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<P: CryptoProvider> CryptoCore<P> {
+    pub fn builder() -> CryptoCoreBuilder<P> {
+        CryptoCoreBuilder::new()
+    }
+
     pub fn new() -> Result<Self> {
         let mut key_manager = KeyManager::<P>::new();
         key_manager.initialize()?;
+        Self::from_key_manager(key_manager)
+    }
 
-        let client = ClientCrypto::<P>::new().map_err(ConstructError::CryptoError)?;
+    /// Создать ядро на ключах существующего KeyManager (без сессий)
+    pub fn from_key_manager(key_manager: KeyManager<P>) -> Result<Self> {
+        // Клиент использует те же долговременные ключи, что публикуются в bundle
+        let client = ClientCrypto::<P>::from_keys(
+            key_manager.identity_secret_key()?.clone(),
+            key_manager.current_signed_prekey()?.key_pair.0.clone(),
+            key_manager.signing_secret_key()?.clone(),
+            key_manager.verifying_key()?.clone(),
+        );
 
         Ok(Self {
             key_manager,
@@ -100,7 +345,10 @@ impl<P: CryptoProvider> CryptoCore<P> {
 
     pub fn export_registration_bundle(&self) -> Result<KeyBundle> {
         let bundle = self.key_manager.export_registration_bundle()?;
-        Ok(bundle.into())
+        Ok(KeyBundle {
+            signed_prekey_id: Some(self.key_manager.current_signed_prekey()?.key_id),
+            ..bundle.into()
+        })
     }
 
     pub fn export_registration_bundle_b64(&self) -> Result<RegistrationBundleB64> {
@@ -112,6 +360,7 @@ impl<P: CryptoProvider> CryptoCore<P> {
             signature: base64::engine::general_purpose::STANDARD.encode(&bundle.signature),
             verifying_key: base64::engine::general_purpose::STANDARD.encode(&bundle.verifying_key),
             suite_id: bundle.suite_id.to_string(),
+            signed_prekey_id: self.key_manager.current_signed_prekey()?.key_id,
         })
     }
 
@@ -120,8 +369,38 @@ impl<P: CryptoProvider> CryptoCore<P> {
         Ok(bundle.into())
     }
 
+    /// Отпечаток собственного identity ключа
+    pub fn fingerprint(&self) -> Result<String> {
+        self.key_manager.fingerprint()
+    }
+
+    /// Общий safety number с контактом по его identity ключу
+    pub fn safety_number(&self, remote_identity: &[u8]) -> Result<String> {
+        let local = self.key_manager.identity_public_key()?;
+        Ok(crate::crypto::combined_safety_number(local.as_ref(), remote_identity))
+    }
+
     pub fn rotate_prekey(&mut self) -> Result<()> {
-        self.key_manager.rotate_signed_prekey()
+        self.key_manager.rotate_signed_prekey()?;
+        // X3DH получателя использует signed prekey - клиент должен знать новый
+        let signed_prekey = self.key_manager.current_signed_prekey()?.key_pair.0.clone();
+        self.client.set_signed_prekey(signed_prekey);
+        Ok(())
+    }
+
+    /// Сгенерировать одноразовые prekey и вернуть подписанные публичные ключи
+    /// для публикации на сервере
+    pub fn generate_one_time_prekeys(&mut self, count: usize) -> Result<Vec<SignedOneTimePrekey<Vec<u8>>>> {
+        Ok(self
+            .key_manager
+            .generate_one_time_prekeys(count)?
+            .into_iter()
+            .map(|prekey| SignedOneTimePrekey {
+                key_id: prekey.key_id,
+                public_key: prekey.public_key.as_ref().to_vec(),
+                signature: prekey.signature,
+            })
+            .collect())
     }
 
     pub fn sign_data(&self, data: &[u8]) -> Result<Vec<u8>> {
@@ -129,7 +408,54 @@ impl<P: CryptoProvider> CryptoCore<P> {
     }
 
     pub fn has_session(&self, contact_id: &str) -> bool {
-        self.session_manager.has_session(contact_id)
+        self.client.session_id_for_contact(contact_id).is_some()
+            || self.session_manager.has_session(contact_id)
+    }
+
+    /// Завершить сессию с контактом (в клиенте и в менеджере сессий)
+    pub fn end_session(&mut self, contact_id: &str) -> bool {
+        let ended = self.client.end_session(contact_id).is_some();
+        self.session_manager.remove_session(contact_id).is_some() || ended
+    }
+
+    /// Экстренная смена ключей: ротация signed prekey и завершение всех сессий.
+    /// Возвращает контакты, чьи сессии завершены (по contact_id)
+    pub fn rekey_all(&mut self) -> Result<Vec<String>> {
+        self.rotate_prekey()?;
+        Ok(self.end_all_sessions())
+    }
+
+    /// Сменить identity ключ (см. `KeyManager::rotate_identity_key`) и завершить все сессии.
+    /// Возвращает подпись нового bundle старым signing ключом
+    pub fn rotate_identity(&mut self) -> Result<Vec<u8>> {
+        let signature = self.key_manager.rotate_identity_key()?;
+        self.client.set_identity_keys(
+            self.key_manager.identity_secret_key()?.clone(),
+            self.key_manager.current_signed_prekey()?.key_pair.0.clone(),
+            self.key_manager.signing_secret_key()?.clone(),
+            self.key_manager.verifying_key()?.clone(),
+        );
+        // Сессии выведены из прежнего identity и больше не принимаются контактами
+        self.end_all_sessions();
+        Ok(signature)
+    }
+
+    /// Завершить все сессии; возвращает их контакты (по contact_id)
+    fn end_all_sessions(&mut self) -> Vec<String> {
+        let mut contacts: Vec<String> = self
+            .client
+            .sessions_by_recency()
+            .into_iter()
+            .map(|(contact_id, _)| contact_id)
+            .chain(self.session_manager.get_active_contacts())
+            .collect();
+        contacts.sort();
+        contacts.dedup();
+
+        for contact_id in &contacts {
+            self.end_session(contact_id);
+        }
+        contacts
     }
 
     pub fn active_sessions_count(&self) -> usize {
@@ -141,14 +467,26 @@ impl<P: CryptoProvider> CryptoCore<P> {
             .cleanup_sessions_older_than(max_age_seconds);
     }
 
+    /// Подтверждена ли сессия ответом собеседника
+    pub fn is_session_confirmed(&self, session_id: &str) -> bool {
+        self.client.is_session_confirmed(session_id)
+    }
+
+    /// Удалить half-open сессии, на которые собеседник так и не ответил
+    /// за `older_than` секунд. Возвращает контакты удаленных сессий
+    pub fn cleanup_unconfirmed_sessions(&mut self, older_than: i64) -> Vec<String> {
+        self.client.cleanup_unconfirmed(older_than)
+    }
+
     pub fn init_session(&mut self, contact_id: &str, remote_bundle: &KeyBundle) -> Result<String> {
         eprintln!("[CryptoCore] init_session called for contact: {}", contact_id);
+        remote_bundle.verify::<P>()?;
         eprintln!("[CryptoCore] Converting KeyBundle to PublicKeyBundle...");
         let public_bundle: PublicKeyBundle = remote_bundle.clone().into();
         eprintln!("[CryptoCore] PublicKeyBundle created, calling client.init_session...");
         let result = self.client
             .init_session(contact_id, &public_bundle)
-            .map_err(ConstructError::CryptoError);
+            .map_err(ConstructError::from);
         eprintln!("[CryptoCore] client.init_session returned: {:?}", result.is_ok());
         result
     }
@@ -160,10 +498,70 @@ impl<P: CryptoProvider> CryptoCore<P> {
         first_message: &crate::crypto::double_ratchet::EncryptedRatchetMessage,
     ) -> Result<String> {
         eprintln!("[CryptoCore] init_receiving_session called for contact: {}", contact_id);
+        remote_bundle.verify::<P>()?;
         let public_bundle: PublicKeyBundle = remote_bundle.clone().into();
-        self.client
-            .init_receiving_session(contact_id, &public_bundle, first_message)
-            .map_err(ConstructError::CryptoError)
+
+        // Signed prekey ищется по id из сообщения: bundle мог быть выдан до ротации
+        let signed_prekey = first_message
+            .signed_prekey_id
+            .map(|id| {
+                self.key_manager
+                    .get_prekey(id)
+                    .map(|prekey| &prekey.key_pair.0)
+                    .ok_or_else(|| {
+                        ConstructError::CryptoError(format!("Signed prekey {} is not available", id))
+                    })
+            })
+            .transpose()?;
+        let one_time_prekey = first_message
+            .one_time_prekey_id
+            .and_then(|id| self.key_manager.one_time_prekey(id));
+        let session_id = self
+            .client
+            .init_receiving_session_with_prekey(
+                contact_id,
+                &public_bundle,
+                first_message,
+                signed_prekey,
+                one_time_prekey,
+            )
+            .map_err(ConstructError::from)?;
+
+        // Одноразовый prekey удаляется только после успешного handshake
+        if let Some(id) = first_message.one_time_prekey_id {
+            self.key_manager.consume_one_time_prekey(id);
+        }
+        Ok(session_id)
+    }
+
+    /// Вернуть существующую сессию с контактом или создать новую как инициатор
+    ///
+    /// Проверка и создание выполняются под одним `&mut self` без точек await,
+    /// поэтому в однопоточной модели две сессии для одного контакта не появятся.
+    pub fn get_or_init_sending_session(
+        &mut self,
+        contact_id: &str,
+        remote_bundle: &KeyBundle,
+    ) -> Result<String> {
+        if let Some(session_id) = self.client.session_id_for_contact(contact_id) {
+            return Ok(session_id.to_string());
+        }
+
+        self.init_session(contact_id, remote_bundle)
+    }
+
+    /// Вернуть существующую сессию с контактом или создать сессию получателя по первому сообщению
+    pub fn get_or_init_receiving_session(
+        &mut self,
+        contact_id: &str,
+        remote_bundle: &KeyBundle,
+        first_message: &crate::crypto::double_ratchet::EncryptedRatchetMessage,
+    ) -> Result<String> {
+        if let Some(session_id) = self.client.session_id_for_contact(contact_id) {
+            return Ok(session_id.to_string());
+        }
+
+        self.init_receiving_session(contact_id, remote_bundle, first_message)
     }
 
     pub fn encrypt_message(
@@ -173,7 +571,131 @@ impl<P: CryptoProvider> CryptoCore<P> {
     ) -> Result<crate::crypto::double_ratchet::EncryptedRatchetMessage> {
         self.client
             .encrypt_ratchet_message(session_id, plaintext.as_bytes())
-            .map_err(ConstructError::CryptoError)
+            .map_err(ConstructError::from)
+    }
+
+    /// Зашифровать сообщение и сразу получить байты в каноническом wire-формате
+    pub fn encrypt_message_wire(&mut self, session_id: &str, plaintext: &str) -> Result<Vec<u8>> {
+        self.encrypt_message(session_id, plaintext)?
+            .to_wire_bytes()
+            .map_err(|e| ConstructError::SerializationError(e.to_string()))
+    }
+
+    /// Расшифровать сообщение, полученное в каноническом wire-формате
+    pub fn decrypt_message_wire(&mut self, session_id: &str, bytes: &[u8]) -> Result<String> {
+        let message = crate::crypto::double_ratchet::EncryptedRatchetMessage::from_wire_bytes(bytes)
+            .map_err(|e| ConstructError::SerializationError(e.to_string()))?;
+        self.decrypt_message(session_id, &message)
+    }
+
+    /// Зашифровать содержимое сообщения (текст, цитата и т.д.)
+    pub fn encrypt_body(
+        &mut self,
+        session_id: &str,
+        body: &MessageBody,
+    ) -> Result<crate::crypto::double_ratchet::EncryptedRatchetMessage> {
+        self.client
+            .encrypt_ratchet_message(session_id, &body.to_plaintext()?)
+            .map_err(ConstructError::from)
+    }
+
+    /// Расшифровать содержимое сообщения
+    pub fn decrypt_body(
+        &mut self,
+        session_id: &str,
+        message: &crate::crypto::double_ratchet::EncryptedRatchetMessage,
+    ) -> Result<MessageBody> {
+        let plaintext = self
+            .client
+            .decrypt_ratchet_message(session_id, message)
+            .map_err(ConstructError::from)?;
+
+        MessageBody::from_plaintext(&plaintext)
+    }
+
+    /// Включить привязку `ChatMessage.timestamp` к AAD для сессии.
+    /// Обе стороны должны включить ее одновременно, иначе сообщения не расшифруются
+    pub fn set_timestamp_binding(&mut self, session_id: &str, enabled: bool) -> Result<()> {
+        self.client
+            .set_timestamp_binding(session_id, enabled)
+            .map_err(|e| ConstructError::SessionError(e.to_string()))
+    }
+
+    /// AAD для сообщения с данным timestamp (пустой, если привязка выключена)
+    fn timestamp_aad(&self, session_id: &str, timestamp: u64) -> Vec<u8> {
+        match self.client.session(session_id) {
+            Some(session) if session.timestamp_binding() => {
+                let mut aad = TIMESTAMP_AAD_LABEL.to_vec();
+                aad.extend_from_slice(&timestamp.to_be_bytes());
+                aad
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Зашифровать содержимое сообщения, которое будет отправлено с `timestamp`
+    pub fn encrypt_body_at(
+        &mut self,
+        session_id: &str,
+        body: &MessageBody,
+        timestamp: u64,
+    ) -> Result<crate::crypto::double_ratchet::EncryptedRatchetMessage> {
+        let aad = self.timestamp_aad(session_id, timestamp);
+        self.client
+            .encrypt_ratchet_message_with_aad(session_id, &body.to_plaintext()?, &aad)
+            .map_err(ConstructError::from)
+    }
+
+    /// Расшифровать содержимое сообщения, полученного с `timestamp`
+    pub fn decrypt_body_at(
+        &mut self,
+        session_id: &str,
+        message: &crate::crypto::double_ratchet::EncryptedRatchetMessage,
+        timestamp: u64,
+    ) -> Result<MessageBody> {
+        let aad = self.timestamp_aad(session_id, timestamp);
+        let plaintext = self
+            .client
+            .decrypt_ratchet_message_with_aad(session_id, message, &aad)
+            .map_err(ConstructError::from)?;
+
+        MessageBody::from_plaintext(&plaintext)
+    }
+
+    /// Зашифровать сообщение, привязав его к данным приложения (AAD),
+    /// например к id треда или серверному номеру последовательности
+    pub fn encrypt_message_with_aad(
+        &mut self,
+        session_id: &str,
+        plaintext: &str,
+        aad: &[u8],
+    ) -> Result<crate::crypto::double_ratchet::EncryptedRatchetMessage> {
+        self.client
+            .encrypt_ratchet_message_with_aad(session_id, plaintext.as_bytes(), aad)
+            .map_err(ConstructError::from)
+    }
+
+    /// Расшифровать сообщение; ошибка, если AAD не совпадает с переданным при шифровании
+    pub fn decrypt_message_with_aad(
+        &mut self,
+        session_id: &str,
+        message: &crate::crypto::double_ratchet::EncryptedRatchetMessage,
+        aad: &[u8],
+    ) -> Result<String> {
+        let plaintext = self
+            .client
+            .decrypt_ratchet_message_with_aad(session_id, message, aad)
+            .map_err(ConstructError::from)?;
+
+        String::from_utf8(plaintext)
+            .map_err(|e| ConstructError::SerializationError(format!("Invalid UTF-8: {}", e)))
+    }
+
+    /// Принудительно обновить DH ключи сессии (forward secrecy после важного сообщения)
+    pub fn rotate_dh_immediately(&mut self, session_id: &str) -> Result<Vec<u8>> {
+        self.client
+            .rotate_dh_immediately(session_id)
+            .map_err(ConstructError::from)
     }
 
     pub fn decrypt_message(
@@ -184,7 +706,7 @@ impl<P: CryptoProvider> CryptoCore<P> {
         let plaintext = self
             .client
             .decrypt_ratchet_message(session_id, message)
-            .map_err(ConstructError::CryptoError)?;
+            .map_err(ConstructError::from)?;
 
         String::from_utf8(plaintext)
             .map_err(|e| ConstructError::SerializationError(format!("Invalid UTF-8: {}", e)))
@@ -198,6 +720,11 @@ impl<P: CryptoProvider> CryptoCore<P> {
         &mut self.client
     }
 
+    /// Шифровать заголовки в новых исходящих сессиях (`ClientCrypto::set_header_encryption`)
+    pub fn set_header_encryption(&mut self, enabled: bool) {
+        self.client.set_header_encryption(enabled);
+    }
+
     // DEPRECATED: These methods don't work with generic CryptoProvider
     // They should be refactored to work with Vec<u8> instead of concrete types
     // pub fn export_private_keys(&self) -> Result<crate::crypto::master_key::PrivateKeys> {
@@ -235,7 +762,7 @@ impl<P: CryptoProvider> Default for CryptoCore<P> {
 }
 
 pub fn create_client<P: CryptoProvider>() -> Result<ClientCrypto<P>> {
-    ClientCrypto::<P>::new().map_err(ConstructError::CryptoError)
+    ClientCrypto::<P>::new().map_err(ConstructError::from)
 }
 
 pub fn get_registration_bundle<P: CryptoProvider>(client: &ClientCrypto<P>) -> Result<KeyBundle> {
@@ -246,6 +773,9 @@ pub fn get_registration_bundle<P: CryptoProvider>(client: &ClientCrypto<P>) -> R
         signature: bundle.signature,
         verifying_key: bundle.verifying_key,
         suite_id: bundle.suite_id,
+        one_time_prekey_public: None,
+        one_time_prekey_id: None,
+        signed_prekey_id: None,
     })
 }
 
@@ -320,4 +850,440 @@ mod tests {
         assert_eq!(bundle.signature.len(), 64);
         assert_eq!(bundle.verifying_key.len(), 32);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_bundle_data_conversion() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bundle = bob.export_registration_bundle().unwrap();
+
+        let data = bundle.to_bundle_data("bob");
+        assert_eq!(data.user_id, "bob");
+
+        let decoded = KeyBundle::try_from(&data).unwrap();
+        assert_eq!(decoded.identity_public, bundle.identity_public);
+        assert_eq!(decoded.signature, bundle.signature);
+        assert_eq!(decoded.suite_id, bundle.suite_id);
+
+        // Полученный bundle подходит для init_session
+        assert!(alice.init_session("bob", &decoded).is_ok());
+
+        // Без suite_id - классический набор
+        let mut legacy = data.clone();
+        legacy.suite_id = None;
+        assert_eq!(KeyBundle::try_from(&legacy).unwrap().suite_id, 1);
+    }
+
+    #[test]
+    fn test_bundle_data_conversion_rejects_invalid() {
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let data = bob.export_registration_bundle().unwrap().to_bundle_data("bob");
+
+        let mut bad_base64 = data.clone();
+        bad_base64.identity_public = "not base64!".to_string();
+        assert!(matches!(
+            KeyBundle::try_from(&bad_base64),
+            Err(ConstructError::ValidationError(_))
+        ));
+
+        let mut empty = data.clone();
+        empty.signature = String::new();
+        assert!(KeyBundle::try_from(&empty).is_err());
+
+        let mut bad_suite = data;
+        bad_suite.suite_id = Some("classic".to_string());
+        assert!(KeyBundle::try_from(&bad_suite).is_err());
+    }
+
+    #[test]
+    fn test_key_bundle_equality() {
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bundle = bob.export_registration_bundle().unwrap();
+
+        // Идентичные bundle
+        let same = bundle.clone();
+        assert_eq!(bundle, same);
+        assert!(bundle.same_identity(&same));
+
+        // Ротация prekey - identity тот же, bundle другой
+        bob.rotate_prekey().unwrap();
+        let rotated = bob.export_registration_bundle().unwrap();
+        assert_ne!(bundle, rotated);
+        assert!(bundle.same_identity(&rotated));
+
+        // Новый identity
+        let reinstalled = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap();
+        assert_ne!(bundle, reinstalled);
+        assert!(!bundle.same_identity(&reinstalled));
+    }
+
+    #[test]
+    fn test_get_or_init_sending_session() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        assert!(!alice.has_session("bob"));
+        let created = alice.get_or_init_sending_session("bob", &bob_bundle).unwrap();
+        assert!(alice.has_session("bob"));
+
+        // Повторный вызов возвращает ту же сессию
+        let reused = alice.get_or_init_sending_session("bob", &bob_bundle).unwrap();
+        assert_eq!(created, reused);
+        assert_eq!(alice.client().session_count(), 1);
+    }
+
+    #[test]
+    fn test_get_or_init_receiving_session() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let bob_session = bob.init_session("alice", &alice_bundle).unwrap();
+        let first = bob.encrypt_message(&bob_session, "hi").unwrap();
+        let second = bob.encrypt_message(&bob_session, "again").unwrap();
+
+        let created = alice
+            .get_or_init_receiving_session("bob", &bob_bundle, &first)
+            .unwrap();
+        let reused = alice
+            .get_or_init_receiving_session("bob", &bob_bundle, &second)
+            .unwrap();
+        assert_eq!(created, reused);
+        assert_eq!(alice.client().session_count(), 1);
+
+        // Существующая сессия инициатора тоже переиспользуется
+        let sending = alice
+            .get_or_init_sending_session("bob", &bob_bundle)
+            .unwrap();
+        assert_eq!(sending, created);
+    }
+
+    #[test]
+    fn test_builder_skipped_message_limit() {
+        let receive_out_of_order = |limit: u32| {
+            let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+            let mut bob = CryptoCore::<ClassicSuiteProvider>::builder()
+                .max_skipped_messages(limit)
+                .build()
+                .unwrap();
+            let alice_bundle = alice.export_registration_bundle().unwrap();
+            let bob_bundle = bob.export_registration_bundle().unwrap();
+
+            let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+            let messages: Vec<_> = (0..5)
+                .map(|i| alice.encrypt_message(&alice_session, &i.to_string()).unwrap())
+                .collect();
+
+            let bob_session = bob
+                .init_receiving_session("alice", &alice_bundle, &messages[0])
+                .unwrap();
+            // Последнее сообщение пришло первым - 0..=3 пропущены
+            bob.decrypt_message(&bob_session, &messages[4])
+        };
+
+        assert!(receive_out_of_order(2).is_err());
+        assert_eq!(receive_out_of_order(4).unwrap(), "4");
+    }
+
+    #[test]
+    fn test_builder_padding() {
+        let padded = || {
+            CryptoCore::<ClassicSuiteProvider>::builder()
+                .padding(PaddingMode::PowerOfTwo)
+                .build()
+                .unwrap()
+        };
+        let mut alice = padded();
+        let mut bob = padded();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let short = alice.encrypt_message(&alice_session, "hi there").unwrap();
+        let long = alice.encrypt_message(&alice_session, "see you at 7").unwrap();
+        assert_eq!(short.ciphertext.len(), long.ciphertext.len());
+
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &short)
+            .unwrap();
+        assert_eq!(bob.decrypt_message(&bob_session, &short).unwrap(), "hi there");
+
+        // Режим сохраняется вместе с сессией
+        let exported = bob.client().export_session(&bob_session).unwrap();
+        let mut restored = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        restored.client_mut().import_session(&bob_session, &exported).unwrap();
+        assert_eq!(
+            restored.client().session(&bob_session).unwrap().padding(),
+            PaddingMode::PowerOfTwo
+        );
+        assert_eq!(restored.decrypt_message(&bob_session, &long).unwrap(), "see you at 7");
+    }
+
+    #[test]
+    fn test_encrypt_with_aad() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let first = alice
+            .encrypt_message_with_aad(&alice_session, "hello", b"thread-1")
+            .unwrap();
+        let second = alice
+            .encrypt_message_with_aad(&alice_session, "again", b"thread-1")
+            .unwrap();
+
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &first)
+            .unwrap();
+        assert_eq!(
+            bob.decrypt_message_with_aad(&bob_session, &first, b"thread-1")
+                .unwrap(),
+            "hello"
+        );
+
+        // Другой AAD (или его отсутствие) - ошибка расшифровки
+        assert!(bob
+            .decrypt_message_with_aad(&bob_session, &second, b"thread-2")
+            .is_err());
+        let third = alice
+            .encrypt_message_with_aad(&alice_session, "third", b"thread-1")
+            .unwrap();
+        assert!(bob.decrypt_message(&bob_session, &third).is_err());
+    }
+
+    #[test]
+    fn test_wire_roundtrip() {
+        use crate::crypto::double_ratchet::EncryptedRatchetMessage;
+
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let wire = alice.encrypt_message_wire(&alice_session, "hello").unwrap();
+
+        let first = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        assert_eq!(first.to_wire_bytes().unwrap(), wire);
+
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &first)
+            .unwrap();
+        assert_eq!(bob.decrypt_message_wire(&bob_session, &wire).unwrap(), "hello");
+
+        let reply = bob.encrypt_message_wire(&bob_session, "hi back").unwrap();
+        assert_eq!(
+            alice.decrypt_message_wire(&alice_session, &reply).unwrap(),
+            "hi back"
+        );
+
+        assert!(alice.decrypt_message_wire(&alice_session, b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_bundle_verify_detects_key_role_mismatch() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        assert!(bob_bundle.verify::<ClassicSuiteProvider>().is_ok());
+
+        let mut swapped = bob_bundle.clone();
+        std::mem::swap(&mut swapped.identity_public, &mut swapped.verifying_key);
+        let err = swapped.verify::<ClassicSuiteProvider>().unwrap_err();
+        assert!(err.to_string().contains("key role mismatch"), "{}", err);
+
+        // Ошибка возникает до X3DH, сессия не создается
+        let err = alice.init_session("bob", &swapped).unwrap_err();
+        assert!(err.to_string().contains("key role mismatch"), "{}", err);
+        assert!(!alice.has_session("bob"));
+
+        let mut bad_signature = bob_bundle.clone();
+        bad_signature.signature[0] ^= 0xff;
+        let err = bad_signature.verify::<ClassicSuiteProvider>().unwrap_err();
+        assert!(!err.to_string().contains("key role mismatch"));
+
+        let mut short_key = bob_bundle;
+        short_key.verifying_key.truncate(16);
+        assert!(short_key.verify::<ClassicSuiteProvider>().is_err());
+    }
+
+    #[test]
+    fn test_timestamp_binding() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let body = |text: &str| MessageBody::new_text(text);
+
+        // Без привязки подмена timestamp не влияет на расшифровку
+        let first = alice.encrypt_body_at(&alice_session, &body("one"), 100).unwrap();
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &first)
+            .unwrap();
+        assert_eq!(
+            bob.decrypt_body_at(&bob_session, &first, 999).unwrap(),
+            body("one")
+        );
+
+        alice.set_timestamp_binding(&alice_session, true).unwrap();
+        bob.set_timestamp_binding(&bob_session, true).unwrap();
+
+        let second = alice.encrypt_body_at(&alice_session, &body("two"), 200).unwrap();
+        assert!(bob.decrypt_body_at(&bob_session, &second, 201).is_err());
+
+        let third = alice.encrypt_body_at(&alice_session, &body("three"), 300).unwrap();
+        assert_eq!(
+            bob.decrypt_body_at(&bob_session, &third, 300).unwrap(),
+            body("three")
+        );
+
+        assert!(alice.set_timestamp_binding("missing", true).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "post-quantum"))]
+    fn test_pq_bundle_rejected_without_pq_build() {
+        assert!(!crate::crypto::build_supports_pq());
+
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut pq_bundle = bob.export_registration_bundle().unwrap();
+        pq_bundle.suite_id = crate::crypto::PQ_HYBRID_SUITE_ID;
+
+        let err = alice.init_session("bob", &pq_bundle).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("post-quantum suite not supported in this build"),
+            "{}",
+            err
+        );
+        assert!(!alice.has_session("bob"));
+    }
+
+    #[test]
+    fn test_x3dh_with_one_time_prekey() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        assert_eq!(bob.generate_one_time_prekeys(2).unwrap().len(), 2);
+
+        let bob_bundle = bob.export_public_bundle().unwrap();
+        assert_eq!(bob_bundle.one_time_prekey_id, Some(1));
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let first = alice.encrypt_message(&alice_session, "hello").unwrap();
+        assert!(first.x3dh_ephemeral_key.is_some());
+        assert_eq!(first.one_time_prekey_id, Some(1));
+
+        let bob_session = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap();
+        assert_eq!(bob.decrypt_message(&bob_session, &first).unwrap(), "hello");
+
+        // Использованный prekey удален - повтор первого сообщения не создает сессию
+        assert_eq!(bob.key_manager().one_time_prekeys_count(), 1);
+        assert_eq!(bob.export_public_bundle().unwrap().one_time_prekey_id, Some(2));
+        let err = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap_err();
+        assert!(err.to_string().contains("One-time prekey 1 is not available"), "{}", err);
+
+        // После ответа заголовок X3DH больше не прикладывается
+        let reply = bob.encrypt_message(&bob_session, "hi").unwrap();
+        assert!(reply.x3dh_ephemeral_key.is_none());
+        assert_eq!(alice.decrypt_message(&alice_session, &reply).unwrap(), "hi");
+        let next = alice.encrypt_message(&alice_session, "again").unwrap();
+        assert!(next.x3dh_ephemeral_key.is_none() && next.one_time_prekey_id.is_none());
+    }
+
+    #[test]
+    fn test_first_message_to_bundle_issued_before_rotation() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+
+        // Alice получила bundle до ротации signed prekey у Bob
+        let old_bundle = bob.export_public_bundle().unwrap();
+        bob.rotate_prekey().unwrap();
+        assert_ne!(bob.export_public_bundle().unwrap().signed_prekey_id, old_bundle.signed_prekey_id);
+
+        let alice_session = alice.init_session("bob", &old_bundle).unwrap();
+        let first = alice.encrypt_message(&alice_session, "hello").unwrap();
+        assert_eq!(first.signed_prekey_id, old_bundle.signed_prekey_id);
+
+        let bob_session = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap();
+        assert_eq!(bob.decrypt_message(&bob_session, &first).unwrap(), "hello");
+
+        // Неизвестный prekey - ошибка, а не молчаливая подмена текущим
+        let mut carol = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let carol_session = carol.init_session("bob", &old_bundle).unwrap();
+        let mut unknown = carol.encrypt_message(&carol_session, "hi").unwrap();
+        unknown.signed_prekey_id = Some(999);
+        let carol_bundle = carol.export_registration_bundle().unwrap();
+        let err = bob.init_receiving_session("carol", &carol_bundle, &unknown).unwrap_err();
+        assert!(err.to_string().contains("Signed prekey 999 is not available"), "{}", err);
+    }
+
+    #[test]
+    fn test_safety_number_matches_on_both_sides() {
+        let alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_identity = alice.export_public_bundle().unwrap().identity_public;
+        let bob_identity = bob.export_public_bundle().unwrap().identity_public;
+
+        assert_eq!(
+            alice.safety_number(&bob_identity).unwrap(),
+            bob.safety_number(&alice_identity).unwrap()
+        );
+        assert_eq!(alice.fingerprint().unwrap().len(), 60);
+        assert_ne!(alice.fingerprint().unwrap(), bob.fingerprint().unwrap());
+    }
+
+    #[test]
+    fn test_cleanup_unconfirmed_sessions() {
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let carol = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+
+        let to_bob = alice
+            .init_session("bob", &bob.export_registration_bundle().unwrap())
+            .unwrap();
+        let to_carol = alice
+            .init_session("carol", &carol.export_registration_bundle().unwrap())
+            .unwrap();
+
+        // Боб отвечает - его сессия подтверждена, сессия с Кэрол остается half-open
+        let first = alice.encrypt_body(&to_bob, &MessageBody::new_text("hi bob")).unwrap();
+        alice.encrypt_body(&to_carol, &MessageBody::new_text("hi carol")).unwrap();
+        assert!(!alice.is_session_confirmed(&to_bob));
+
+        let bob_session = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap();
+        bob.decrypt_body(&bob_session, &first).unwrap();
+        assert!(bob.is_session_confirmed(&bob_session));
+        let reply = bob.encrypt_body(&bob_session, &MessageBody::new_text("hi alice")).unwrap();
+        alice.decrypt_body(&to_bob, &reply).unwrap();
+        assert!(alice.is_session_confirmed(&to_bob));
+        assert!(!alice.is_session_confirmed(&to_carol));
+
+        // Таймаут еще не истек
+        assert!(alice.cleanup_unconfirmed_sessions(3600).is_empty());
+        assert!(alice.has_session("carol"));
+
+        assert_eq!(alice.cleanup_unconfirmed_sessions(0), vec!["carol".to_string()]);
+        assert!(!alice.has_session("carol"));
+        assert!(alice.encrypt_body(&to_carol, &MessageBody::new_text("late")).is_err());
+
+        // Подтвержденная сессия продолжает работать
+        let next = alice.encrypt_body(&to_bob, &MessageBody::new_text("still here")).unwrap();
+        assert_eq!(
+            bob.decrypt_body(&bob_session, &next).unwrap(),
+            MessageBody::new_text("still here")
+        );
+    }
+}
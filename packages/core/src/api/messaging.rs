@@ -1,19 +1,47 @@
 // API для отправки и получения сообщений
 
-use crate::crypto::{ClientCrypto, CryptoProvider};
+use crate::crypto::{suite_key_lengths, ClientCrypto, CryptoProvider, SuiteID, CLASSIC_SUITE_ID};
 use crate::crypto::double_ratchet::EncryptedRatchetMessage;
+use crate::protocol::validation::validate_ratchet_dh_public_len;
 use crate::utils::error::{ConstructError, Result};
 use serde::{Deserialize, Serialize};
 
 /// Зашифрованное сообщение для передачи
+///
+/// JSON форма (общая для JS/Swift/Rust): поля в camelCase, байты в base64:
+///
+/// ```json
+/// {"sessionId":"s1","ciphertext":"AQID","dhPublicKey":"<base64, 32 байта для classic suite>",
+///  "nonce":"AAAAAAAAAAAAAAAA","messageNumber":7,"previousChainLength":2,"suiteId":1}
+/// ```
+///
+/// Сообщения без `suiteId` (старые клиенты) считаются classic suite
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct EncryptedMessage {
     pub session_id: String,
+    #[serde(with = "crate::utils::b64::bytes")]
     pub ciphertext: Vec<u8>,
-    pub dh_public_key: [u8; 32],
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub dh_public_key: Vec<u8>,
+    #[serde(with = "crate::utils::b64::bytes")]
     pub nonce: Vec<u8>,
     pub message_number: u32,
     pub previous_chain_length: u32,
+    /// Suite, на которой зашифровано сообщение
+    #[serde(default = "default_suite_id")]
+    pub suite_id: SuiteID,
+    /// Заголовок X3DH в первых сообщениях инициатора (base64)
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "crate::utils::b64::opt_bytes")]
+    pub x3dh_ephemeral_key: Option<Vec<u8>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_time_prekey_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signed_prekey_id: Option<u32>,
+}
+
+fn default_suite_id() -> SuiteID {
+    CLASSIC_SUITE_ID
 }
 
 impl From<EncryptedRatchetMessage> for EncryptedMessage {
@@ -25,6 +53,10 @@ impl From<EncryptedRatchetMessage> for EncryptedMessage {
             nonce: msg.nonce,
             message_number: msg.message_number,
             previous_chain_length: msg.previous_chain_length,
+            suite_id: msg.suite_id,
+            x3dh_ephemeral_key: msg.x3dh_ephemeral_key,
+            one_time_prekey_id: msg.one_time_prekey_id,
+            signed_prekey_id: msg.signed_prekey_id,
         }
     }
 }
@@ -37,7 +69,13 @@ impl From<EncryptedMessage> for EncryptedRatchetMessage {
             nonce: msg.nonce,
             message_number: msg.message_number,
             previous_chain_length: msg.previous_chain_length,
-            suite_id: 1, // Default to classic suite
+            suite_id: msg.suite_id,
+            key_confirmation: None,
+            x3dh_ephemeral_key: msg.x3dh_ephemeral_key,
+            one_time_prekey_id: msg.one_time_prekey_id,
+            signed_prekey_id: msg.signed_prekey_id,
+            transcript_tag: None,
+            encrypted_header: None,
         }
     }
 }
@@ -50,7 +88,7 @@ pub fn encrypt_message<P: CryptoProvider>(
 ) -> Result<EncryptedMessage> {
     let encrypted = client
         .encrypt_ratchet_message(session_id, plaintext.as_bytes())
-        .map_err(ConstructError::CryptoError)?;
+        .map_err(ConstructError::from)?;
 
     let mut msg: EncryptedMessage = encrypted.into();
     msg.session_id = session_id.to_string();
@@ -67,7 +105,7 @@ pub fn decrypt_message<P: CryptoProvider>(
 
     let plaintext = client
         .decrypt_ratchet_message(session_id, &ratchet_msg)
-        .map_err(ConstructError::CryptoError)?;
+        .map_err(ConstructError::from)?;
 
     String::from_utf8(plaintext)
         .map_err(|e| ConstructError::SerializationError(format!("Invalid UTF-8: {}", e)))
@@ -82,7 +120,7 @@ pub fn init_session<P: CryptoProvider>(
     let public_bundle = remote_bundle.clone().into();
     client
         .init_double_ratchet_session(contact_id, &public_bundle)
-        .map_err(ConstructError::SessionError)
+        .map_err(|e| ConstructError::SessionError(e.to_string()))
 }
 
 /// Инициализировать сессию получателя при получении первого сообщения
@@ -97,7 +135,7 @@ pub fn init_receiving_session<P: CryptoProvider>(
 
     client
         .init_receiving_session(contact_id, &public_bundle, &ratchet_msg)
-        .map_err(ConstructError::SessionError)
+        .map_err(|e| ConstructError::SessionError(e.to_string()))
 }
 
 /// Сериализовать зашифрованное сообщение в JSON
@@ -108,6 +146,110 @@ pub fn serialize_encrypted_message(msg: &EncryptedMessage) -> Result<String> {
 
 /// Десериализовать зашифрованное сообщение из JSON
 pub fn deserialize_encrypted_message(json: &str) -> Result<EncryptedMessage> {
-    serde_json::from_str(json)
-        .map_err(|e| ConstructError::SerializationError(e.to_string()))
+    let message: EncryptedMessage = serde_json::from_str(json)
+        .map_err(|e| ConstructError::SerializationError(e.to_string()))?;
+    validate_ratchet_dh_public_len(message.dh_public_key.len())?;
+    if suite_key_lengths(message.suite_id).is_none() {
+        return Err(ConstructError::ValidationError(format!(
+            "Unsupported suite_id: {}",
+            message.suite_id
+        )));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN_JSON: &str = concat!(
+        r#"{"sessionId":"s1","ciphertext":"AQID","#,
+        r#""dhPublicKey":"AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=","#,
+        r#""nonce":"CQkJCQkJCQkJCQkJ","messageNumber":7,"previousChainLength":2,"suiteId":1}"#
+    );
+
+    fn sample() -> EncryptedMessage {
+        EncryptedMessage {
+            session_id: "s1".to_string(),
+            ciphertext: vec![1, 2, 3],
+            dh_public_key: vec![3u8; 32],
+            nonce: vec![9u8; 12],
+            message_number: 7,
+            previous_chain_length: 2,
+            suite_id: CLASSIC_SUITE_ID,
+            x3dh_ephemeral_key: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+        }
+    }
+
+    #[test]
+    fn test_encrypted_message_json_golden() {
+        assert_eq!(serialize_encrypted_message(&sample()).unwrap(), GOLDEN_JSON);
+
+        let decoded = deserialize_encrypted_message(GOLDEN_JSON).unwrap();
+        assert_eq!(decoded.dh_public_key, vec![3u8; 32]);
+        assert_eq!(decoded.ciphertext, vec![1, 2, 3]);
+        assert_eq!(decoded.nonce, vec![9u8; 12]);
+        assert_eq!(decoded.message_number, 7);
+
+        // MessagePack использует ту же форму (base64 строки)
+        let packed = rmp_serde::to_vec_named(&sample()).unwrap();
+        let unpacked: EncryptedMessage = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(serialize_encrypted_message(&unpacked).unwrap(), GOLDEN_JSON);
+    }
+
+    #[test]
+    fn test_encrypted_message_rejects_bad_key_length() {
+        let json = GOLDEN_JSON.replace(
+            "AwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwMDAwM=",
+            "AwMDAwMD",
+        );
+        let err = deserialize_encrypted_message(&json).unwrap_err();
+        assert!(err.to_string().contains("Ratchet DH public key must be 32-2048 bytes"));
+
+        // Ключ длиннее 32 байт (PQ suite) проходит, точную длину проверяет сессия
+        let mut wide = sample();
+        wide.dh_public_key = vec![3u8; 48];
+        let json = serialize_encrypted_message(&wide).unwrap();
+        assert_eq!(deserialize_encrypted_message(&json).unwrap().dh_public_key, vec![3u8; 48]);
+    }
+
+    #[test]
+    fn test_suite_id_survives_json_roundtrip() {
+        use crate::crypto::classic_suite::ClassicSuiteProvider;
+        use crate::crypto::PublicKeyBundle;
+
+        let mut alice = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let bob = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let bundle = bob.get_registration_bundle();
+        let session_id = alice
+            .init_double_ratchet_session(
+                "bob",
+                &PublicKeyBundle {
+                    identity_public: bundle.identity_public,
+                    signed_prekey_public: bundle.signed_prekey_public,
+                    signature: bundle.signature,
+                    verifying_key: bundle.verifying_key,
+                    suite_id: bundle.suite_id,
+                    one_time_prekey_public: None,
+                    one_time_prekey_id: None,
+                    signed_prekey_id: None,
+                },
+            )
+            .unwrap();
+
+        let encrypted = encrypt_message(&mut alice, &session_id, "hello").unwrap();
+        assert_eq!(encrypted.suite_id, CLASSIC_SUITE_ID);
+        let json = serialize_encrypted_message(&encrypted).unwrap();
+        let decoded = deserialize_encrypted_message(&json).unwrap();
+        assert_eq!(decoded.suite_id, 1);
+        assert_eq!(EncryptedRatchetMessage::from(decoded).suite_id, 1);
+
+        // Старая форма без suiteId - classic suite; неизвестный suite отклоняется
+        let legacy = GOLDEN_JSON.replace(r#","suiteId":1"#, "");
+        assert_eq!(deserialize_encrypted_message(&legacy).unwrap().suite_id, CLASSIC_SUITE_ID);
+        let unknown = GOLDEN_JSON.replace(r#""suiteId":1"#, r#""suiteId":99"#);
+        assert!(deserialize_encrypted_message(&unknown).is_err());
+    }
 }
@@ -1,4 +1,5 @@
-use crate::crypto::CryptoProvider;
+use crate::crypto::crypto_provider::ProviderRng;
+use crate::crypto::{CryptoProvider, SuiteID, CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID};
 use crate::error::CryptoError;
 use chacha20poly1305::{
     aead::{Aead, Payload},
@@ -6,28 +7,242 @@ use chacha20poly1305::{
 };
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use rand::rngs::OsRng;
-use rand_core::RngCore;
-use sha2::Sha256;
-use x25519_dalek::{EphemeralSecret, PublicKey as KemPublicKeyDalek, StaticSecret};
+use rand_core::{CryptoRng, RngCore};
+use sha2::{Sha256, Sha512};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use x25519_dalek::{PublicKey as KemPublicKeyDalek, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Hash function used by HKDF in the classic suite.
+/// Each hash is a separate suite ID: peers with different hashes derive different keys.
+pub trait SuiteHash: Send + Sync + 'static {
+    const SUITE_ID: SuiteID;
+
+    /// HKDF-Extract + Expand into `okm`.
+    fn hkdf(salt: Option<&[u8]>, ikm: &[u8], info: &[u8], okm: &mut [u8]) -> Result<(), CryptoError>;
+
+    /// HMAC of `data` under `key`.
+    fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError>;
+}
+
+impl SuiteHash for Sha256 {
+    const SUITE_ID: SuiteID = CLASSIC_SUITE_ID;
+
+    fn hkdf(salt: Option<&[u8]>, ikm: &[u8], info: &[u8], okm: &mut [u8]) -> Result<(), CryptoError> {
+        Hkdf::<Sha256>::new(salt, ikm)
+            .expand(info, okm)
+            .map_err(|e| CryptoError::KeyDerivationError(e.to_string()))
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key)
+            .map_err(|e| CryptoError::InvalidKeyData(e.to_string()))?;
+        mac.update(data);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+impl SuiteHash for Sha512 {
+    const SUITE_ID: SuiteID = CLASSIC_SHA512_SUITE_ID;
+
+    fn hkdf(salt: Option<&[u8]>, ikm: &[u8], info: &[u8], okm: &mut [u8]) -> Result<(), CryptoError> {
+        Hkdf::<Sha512>::new(salt, ikm)
+            .expand(info, okm)
+            .map_err(|e| CryptoError::KeyDerivationError(e.to_string()))
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut mac = <Hmac<Sha512> as Mac>::new_from_slice(key)
+            .map_err(|e| CryptoError::InvalidKeyData(e.to_string()))?;
+        mac.update(data);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+}
+
+/// Randomness source of the classic suite. Providers are stateless, so the source is a type.
+pub trait SuiteRng: Send + Sync + 'static {
+    fn fill(dest: &mut [u8]) -> Result<(), CryptoError>;
+}
+
+/// OS CSPRNG, the production randomness source.
+pub struct OsSuiteRng;
+
+impl SuiteRng for OsSuiteRng {
+    fn fill(dest: &mut [u8]) -> Result<(), CryptoError> {
+        OsRng
+            .try_fill_bytes(dest)
+            .map_err(|e| CryptoError::RandomnessFailure(e.to_string()))
+    }
+}
+
+/// Classic suite (X25519 + Ed25519 + ChaCha20-Poly1305), generic over the HKDF hash
+/// and the randomness source.
+pub struct ClassicSuite<H: SuiteHash, R: SuiteRng = OsSuiteRng>(PhantomData<(H, R)>);
 
-// Suite ID for the classic suite as per API_V3_SPEC.md
-const CLASSIC_SUITE_ID: u16 = 1;
+/// Concrete implementation of `CryptoProvider` for the classic suite (HKDF-SHA256).
+pub type ClassicSuiteProvider = ClassicSuite<Sha256>;
 
-/// Concrete implementation of `CryptoProvider` for the classic suite.
-pub struct ClassicSuiteProvider;
+/// Classic suite variant with HKDF-SHA512.
+pub type ClassicSha512SuiteProvider = ClassicSuite<Sha512>;
 
-impl CryptoProvider for ClassicSuiteProvider {
+#[cfg(test)]
+thread_local! {
+    static SEEDED_RNG: std::cell::RefCell<rand::rngs::StdRng> =
+        std::cell::RefCell::new(rand::SeedableRng::seed_from_u64(0));
+}
+
+/// Deterministic per-thread randomness source for reproducible tests and test vectors.
+#[cfg(test)]
+pub struct SeededRng;
+
+#[cfg(test)]
+impl SeededRng {
+    /// Restarts the current thread's random sequence from `seed`.
+    pub fn reseed(seed: u64) {
+        SEEDED_RNG.with(|rng| *rng.borrow_mut() = rand::SeedableRng::seed_from_u64(seed));
+    }
+}
+
+#[cfg(test)]
+impl SuiteRng for SeededRng {
+    fn fill(dest: &mut [u8]) -> Result<(), CryptoError> {
+        SEEDED_RNG
+            .with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+            .map_err(|e| CryptoError::RandomnessFailure(e.to_string()))
+    }
+}
+
+/// Classic suite with a seeded RNG: the same seed yields the same keys and nonces.
+#[cfg(test)]
+pub type SeededClassicSuiteProvider = ClassicSuite<Sha256, SeededRng>;
+
+/// Secret key bytes of the classic suite, wiped from memory on drop.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        // Constant-time for equal lengths
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(&other.0).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print secret material
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+/// Fills `len` random bytes, surfacing CSPRNG failures instead of panicking.
+fn random_bytes<R: RngCore + CryptoRng>(rng: &mut R, len: usize) -> Result<Vec<u8>, CryptoError> {
+    let mut bytes = vec![0u8; len];
+    rng.try_fill_bytes(&mut bytes)
+        .map_err(|e| CryptoError::RandomnessFailure(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn random_32<R: RngCore + CryptoRng>(rng: &mut R) -> Result<[u8; 32], CryptoError> {
+    let mut bytes = [0u8; 32];
+    rng.try_fill_bytes(&mut bytes)
+        .map_err(|e| CryptoError::RandomnessFailure(e.to_string()))?;
+    Ok(bytes)
+}
+
+fn parse_verifying_key(bytes: &[u8]) -> Result<VerifyingKey, CryptoError> {
+    let bytes: &[u8; 32] = bytes
+        .try_into()
+        .map_err(|_| CryptoError::InvalidInputError("Invalid verifying key length".to_string()))?;
+    VerifyingKey::from_bytes(bytes)
+        .map_err(|e| CryptoError::InvalidInputError(format!("Invalid verifying key: {}", e)))
+}
+
+fn parse_signature(bytes: &[u8]) -> Result<Signature, CryptoError> {
+    let bytes: &[u8; 64] = bytes
+        .try_into()
+        .map_err(|_| CryptoError::InvalidInputError("Invalid signature length".to_string()))?;
+    Ok(Signature::from_bytes(bytes))
+}
+
+impl<H: SuiteHash, R: SuiteRng> ClassicSuite<H, R> {
+    /// `generate_kem_keys` with an explicit RNG.
+    pub(crate) fn generate_kem_keys_with<G: RngCore + CryptoRng>(
+        rng: &mut G,
+    ) -> Result<(SecretBytes, Vec<u8>), CryptoError> {
+        let private_key = StaticSecret::from(random_32(rng)?);
+        let public_key = KemPublicKeyDalek::from(&private_key);
+        Ok((
+            SecretBytes::from(private_key.to_bytes().to_vec()),
+            public_key.to_bytes().to_vec(),
+        ))
+    }
+
+    /// `generate_signature_keys` with an explicit RNG.
+    pub(crate) fn generate_signature_keys_with<G: RngCore + CryptoRng>(
+        rng: &mut G,
+    ) -> Result<(SecretBytes, Vec<u8>), CryptoError> {
+        let signing_key = SigningKey::from_bytes(&random_32(rng)?);
+        let verifying_key = signing_key.verifying_key();
+        Ok((
+            SecretBytes::from(signing_key.to_bytes().to_vec()),
+            verifying_key.to_bytes().to_vec(),
+        ))
+    }
+
+    /// `generate_nonce` with an explicit RNG.
+    pub(crate) fn generate_nonce_with<G: RngCore + CryptoRng>(
+        rng: &mut G,
+        len: usize,
+    ) -> Result<Vec<u8>, CryptoError> {
+        random_bytes(rng, len)
+    }
+}
+
+impl<H: SuiteHash, R: SuiteRng> CryptoProvider for ClassicSuite<H, R> {
     type KemPublicKey = Vec<u8>;
-    type KemPrivateKey = Vec<u8>;
+    type KemPrivateKey = SecretBytes;
     type SignaturePublicKey = Vec<u8>;
-    type SignaturePrivateKey = Vec<u8>;
-    type AeadKey = Vec<u8>;
+    type SignaturePrivateKey = SecretBytes;
+    type AeadKey = SecretBytes;
 
     fn generate_kem_keys() -> Result<(Self::KemPrivateKey, Self::KemPublicKey), CryptoError> {
-        let private_key = StaticSecret::random_from_rng(OsRng);
-        let public_key = KemPublicKeyDalek::from(&private_key);
-        Ok((private_key.to_bytes().to_vec(), public_key.to_bytes().to_vec()))
+        Self::generate_kem_keys_with(&mut ProviderRng::<Self>::new())
     }
 
     fn from_private_key_to_public_key(
@@ -42,19 +257,25 @@ impl CryptoProvider for ClassicSuiteProvider {
         Ok(public_key.to_bytes().to_vec())
     }
 
+    fn kem_public_key_len() -> usize {
+        32 // X25519
+    }
+
+    fn aead_key_len() -> usize {
+        32 // ChaCha20-Poly1305
+    }
+
     fn kem_public_key_from_bytes(bytes: Vec<u8>) -> Self::KemPublicKey {
         // For ClassicSuiteProvider, KemPublicKey is Vec<u8>, so just return it
         bytes
     }
 
     fn kem_private_key_from_bytes(bytes: Vec<u8>) -> Self::KemPrivateKey {
-        // For ClassicSuiteProvider, KemPrivateKey is Vec<u8>, so just return it
-        bytes
+        SecretBytes::from(bytes)
     }
 
     fn aead_key_from_bytes(bytes: Vec<u8>) -> Self::AeadKey {
-        // For ClassicSuiteProvider, AeadKey is Vec<u8>, so just return it
-        bytes
+        SecretBytes::from(bytes)
     }
 
     fn signature_public_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePublicKey {
@@ -62,14 +283,13 @@ impl CryptoProvider for ClassicSuiteProvider {
         bytes
     }
 
+    fn signature_private_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePrivateKey {
+        SecretBytes::from(bytes)
+    }
+
     fn generate_signature_keys(
     ) -> Result<(Self::SignaturePrivateKey, Self::SignaturePublicKey), CryptoError> {
-        let signing_key = SigningKey::generate(&mut OsRng);
-        let verifying_key = signing_key.verifying_key();
-        Ok((
-            signing_key.to_bytes().to_vec(),
-            verifying_key.to_bytes().to_vec(),
-        ))
+        Self::generate_signature_keys_with(&mut ProviderRng::<Self>::new())
     }
 
     fn sign(private_key: &Self::SignaturePrivateKey, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
@@ -87,39 +307,59 @@ impl CryptoProvider for ClassicSuiteProvider {
         message: &[u8],
         signature: &[u8],
     ) -> Result<(), CryptoError> {
-        eprintln!("[ClassicSuite] verify called");
-        eprintln!("[ClassicSuite] public_key length: {}", public_key.len());
-        eprintln!("[ClassicSuite] message length: {}", message.len());
-        eprintln!("[ClassicSuite] signature length: {}", signature.len());
-
-        let vk_slice: &[u8] = public_key.as_ref();
-        let vk_bytes: &[u8; 32] = vk_slice
-            .try_into()
-            .map_err(|_| CryptoError::InvalidInputError("Invalid verifying key length".to_string()))?;
-        eprintln!("[ClassicSuite] Converting to VerifyingKey...");
-        let verifying_key = VerifyingKey::from_bytes(vk_bytes)
-            .map_err(|e| CryptoError::InvalidInputError(format!("Invalid verifying key: {}", e)))?;
-        eprintln!("[ClassicSuite] VerifyingKey created");
+        let verifying_key = parse_verifying_key(public_key)?;
+        let signature = parse_signature(signature)?;
 
-        let sig_bytes: &[u8; 64] = signature
-            .try_into()
-            .map_err(|_| CryptoError::InvalidInputError("Invalid signature length".to_string()))?;
-        eprintln!("[ClassicSuite] Creating Signature object...");
-        let signature_obj = Signature::from_bytes(sig_bytes);
-        eprintln!("[ClassicSuite] Signature object created");
+        verifying_key
+            .verify(message, &signature)
+            .map_err(|e| CryptoError::SignatureVerificationError(e.to_string()))
+    }
 
-        eprintln!("[ClassicSuite] Calling verifying_key.verify()...");
-        let result = verifying_key
-            .verify(message, &signature_obj)
-            .map_err(|e| CryptoError::SignatureVerificationError(e.to_string()));
-        eprintln!("[ClassicSuite] verify completed: {:?}", result.is_ok());
-        result
+    fn verify_batch(entries: &[(&Self::SignaturePublicKey, &[u8], &[u8])]) -> Result<(), CryptoError> {
+        let mut verifying_keys = Vec::with_capacity(entries.len());
+        let mut signatures = Vec::with_capacity(entries.len());
+        let mut messages = Vec::with_capacity(entries.len());
+
+        for (index, (public_key, message, signature)) in entries.iter().enumerate() {
+            let parsed = parse_verifying_key(public_key).and_then(|key| {
+                Ok((key, parse_signature(signature)?))
+            });
+            let (key, signature) = parsed.map_err(|e| CryptoError::BatchVerificationError {
+                index,
+                reason: e.to_string(),
+            })?;
+            verifying_keys.push(key);
+            signatures.push(signature);
+            messages.push(*message);
+        }
+
+        if ed25519_dalek::verify_batch(&messages, &signatures, &verifying_keys).is_ok() {
+            return Ok(());
+        }
+
+        // Пакетная проверка не сообщает, какая подпись неверна - ищем по одной
+        for (index, ((key, signature), message)) in verifying_keys
+            .iter()
+            .zip(&signatures)
+            .zip(&messages)
+            .enumerate()
+        {
+            key.verify(message, signature).map_err(|e| CryptoError::BatchVerificationError {
+                index,
+                reason: e.to_string(),
+            })?;
+        }
+
+        // Все подписи верны по отдельности (например, small-order ключи)
+        Err(CryptoError::SignatureVerificationError(
+            "Batch verification failed".to_string(),
+        ))
     }
 
     fn kem_encapsulate(
         public_key: &Self::KemPublicKey,
     ) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
-        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_secret = StaticSecret::from(random_32(&mut ProviderRng::<Self>::new())?);
         let pk_slice: &[u8] = public_key.as_ref();
         let pk_bytes: &[u8; 32] = pk_slice
             .try_into()
@@ -234,47 +474,239 @@ impl CryptoProvider for ClassicSuiteProvider {
         info: &[u8],
         len: usize,
     ) -> Result<Vec<u8>, CryptoError> {
-        let hkdf = Hkdf::<Sha256>::new(Some(salt), ikm);
         let mut okm = vec![0u8; len];
-        hkdf.expand(info, &mut okm)
-            .map_err(|e| CryptoError::KeyDerivationError(e.to_string()))?;
+        H::hkdf(Some(salt), ikm, info, &mut okm)?;
         Ok(okm)
     }
 
+    fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        H::hmac(key, data)
+    }
+
     fn kdf_rk(
         root_key: &Self::AeadKey,
         dh_output: &[u8],
     ) -> Result<(Self::AeadKey, Self::AeadKey), CryptoError> {
-        let hkdf = Hkdf::<Sha256>::new(Some(root_key.as_ref()), dh_output);
         let mut output = vec![0u8; 64];
-        hkdf.expand(b"Double-Ratchet-Root-Key-Expansion", &mut output)
-            .map_err(|e| CryptoError::KeyDerivationError(e.to_string()))?;
+        H::hkdf(
+            Some(root_key.as_ref()),
+            dh_output,
+            b"Double-Ratchet-Root-Key-Expansion",
+            &mut output,
+        )?;
 
-        let new_root_key = output[..32].to_vec();
-        let chain_key = output[32..].to_vec();
+        let new_root_key = SecretBytes::from(output[..32].to_vec());
+        let chain_key = SecretBytes::from(output[32..].to_vec());
+        output.zeroize();
 
         Ok((new_root_key, chain_key))
     }
 
     fn kdf_ck(chain_key: &Self::AeadKey) -> Result<(Self::AeadKey, Self::AeadKey), CryptoError> {
-        let hkdf = Hkdf::<Sha256>::new(Some(chain_key.as_ref()), b"");
         let mut output = vec![0u8; 64];
-        hkdf.expand(b"Double-Ratchet-Chain-Key-Expansion", &mut output)
-            .map_err(|e| CryptoError::KeyDerivationError(e.to_string()))?;
+        H::hkdf(
+            Some(chain_key.as_ref()),
+            b"",
+            b"Double-Ratchet-Chain-Key-Expansion",
+            &mut output,
+        )?;
 
-        let message_key = output[..32].to_vec();
-        let next_chain = output[32..].to_vec();
+        let message_key = SecretBytes::from(output[..32].to_vec());
+        let next_chain = SecretBytes::from(output[32..].to_vec());
+        output.zeroize();
 
         Ok((message_key, next_chain))
     }
 
     fn generate_nonce(len: usize) -> Result<Vec<u8>, CryptoError> {
-        let mut nonce_bytes = vec![0u8; len];
-        OsRng.fill_bytes(&mut nonce_bytes);
-        Ok(nonce_bytes)
+        Self::generate_nonce_with(&mut ProviderRng::<Self>::new(), len)
+    }
+
+    fn fill_random(dest: &mut [u8]) -> Result<(), CryptoError> {
+        R::fill(dest)
     }
 
     fn suite_id() -> u16 {
-        CLASSIC_SUITE_ID
+        H::SUITE_ID
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RNG, у которого всегда отказывает источник энтропии
+    struct FailingRng;
+
+    impl RngCore for FailingRng {
+        fn next_u32(&mut self) -> u32 {
+            panic!("infallible RNG API must not be used")
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            panic!("infallible RNG API must not be used")
+        }
+
+        fn fill_bytes(&mut self, _dest: &mut [u8]) {
+            panic!("infallible RNG API must not be used")
+        }
+
+        fn try_fill_bytes(&mut self, _dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            Err(rand_core::Error::new("entropy source unavailable"))
+        }
+    }
+
+    impl CryptoRng for FailingRng {}
+
+    #[test]
+    fn test_rng_failure_propagates() {
+        assert!(matches!(
+            ClassicSuiteProvider::generate_kem_keys_with(&mut FailingRng),
+            Err(CryptoError::RandomnessFailure(_))
+        ));
+        assert!(matches!(
+            ClassicSuiteProvider::generate_signature_keys_with(&mut FailingRng),
+            Err(CryptoError::RandomnessFailure(_))
+        ));
+        let err = ClassicSuiteProvider::generate_nonce_with(&mut FailingRng, 12).unwrap_err();
+        assert!(matches!(err, CryptoError::RandomnessFailure(_)));
+        assert!(err.to_string().contains("entropy source unavailable"));
+    }
+
+    #[test]
+    fn test_seeded_provider_is_reproducible() {
+        use crate::crypto::ClientCrypto;
+
+        let bundle = |seed| {
+            SeededRng::reseed(seed);
+            ClientCrypto::<SeededClassicSuiteProvider>::new()
+                .unwrap()
+                .get_registration_bundle()
+        };
+
+        let first = bundle(42);
+        let second = bundle(42);
+        assert_eq!(first.identity_public, second.identity_public);
+        assert_eq!(first.signed_prekey_public, second.signed_prekey_public);
+        assert_eq!(first.signature, second.signature);
+        assert_eq!(first.verifying_key, second.verifying_key);
+
+        assert_ne!(bundle(7).identity_public, first.identity_public);
+    }
+
+    #[test]
+    fn test_generated_keys_are_consistent() {
+        let (private_key, public_key) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        assert_eq!(
+            ClassicSuiteProvider::from_private_key_to_public_key(&private_key).unwrap(),
+            public_key
+        );
+
+        let (signing_key, verifying_key) = ClassicSuiteProvider::generate_signature_keys().unwrap();
+        let signature = ClassicSuiteProvider::sign(&signing_key, b"data").unwrap();
+        assert!(ClassicSuiteProvider::verify(&verifying_key, b"data", &signature).is_ok());
+
+        assert_eq!(ClassicSuiteProvider::generate_nonce(12).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn test_sha512_suite_kdfs() {
+        assert_eq!(ClassicSuiteProvider::suite_id(), CLASSIC_SUITE_ID);
+        assert_eq!(ClassicSha512SuiteProvider::suite_id(), CLASSIC_SHA512_SUITE_ID);
+
+        let ikm = [0x0bu8; 22];
+        let salt = [0x01u8; 13];
+        let mut expected = vec![0u8; 42];
+        Hkdf::<Sha512>::new(Some(&salt), &ikm)
+            .expand(b"info", &mut expected)
+            .unwrap();
+        assert_eq!(
+            ClassicSha512SuiteProvider::hkdf_derive_key(&salt, &ikm, b"info", 42).unwrap(),
+            expected
+        );
+
+        // Тот же вход через SHA-256 дает другие ключи
+        let root_key = SecretBytes::from(vec![7u8; 32]);
+        let (root_512, chain_512) = ClassicSha512SuiteProvider::kdf_rk(&root_key, &[9u8; 32]).unwrap();
+        let (root_256, chain_256) = ClassicSuiteProvider::kdf_rk(&root_key, &[9u8; 32]).unwrap();
+        assert_eq!((root_512.len(), chain_512.len()), (32, 32));
+        assert_ne!(root_512, root_256);
+        assert_ne!(chain_512, chain_256);
+
+        let (message_key, next_chain) = ClassicSha512SuiteProvider::kdf_ck(&chain_512).unwrap();
+        assert_eq!((message_key.len(), next_chain.len()), (32, 32));
+        assert_ne!(message_key, next_chain);
+        assert_ne!(message_key, ClassicSuiteProvider::kdf_ck(&chain_512).unwrap().0);
+    }
+
+    #[test]
+    fn test_sha512_suite_ratchet_roundtrip() {
+        use crate::crypto::double_ratchet::DoubleRatchetSession;
+
+        type Session = DoubleRatchetSession<ClassicSha512SuiteProvider>;
+
+        let (alice_identity, _) = ClassicSha512SuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) =
+            ClassicSha512SuiteProvider::generate_kem_keys().unwrap();
+        let root_key = [3u8; 32];
+        let suite_id = ClassicSha512SuiteProvider::suite_id();
+
+        let mut alice = Session::new_x3dh_session(
+            suite_id,
+            &root_key,
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap();
+        let first = alice.encrypt(b"hello").unwrap();
+        assert_eq!(first.suite_id, CLASSIC_SHA512_SUITE_ID);
+
+        let mut bob =
+            Session::new_receiving_session(suite_id, &root_key, &bob_identity, &first, "alice".to_string())
+                .unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hello");
+
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_verify_batch() {
+        let messages: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 16]).collect();
+        let signed: Vec<(Vec<u8>, Vec<u8>)> = messages
+            .iter()
+            .map(|message| {
+                let (signing_key, verifying_key) =
+                    ClassicSuiteProvider::generate_signature_keys().unwrap();
+                let signature = ClassicSuiteProvider::sign(&signing_key, message).unwrap();
+                (verifying_key, signature)
+            })
+            .collect();
+
+        let mut entries: Vec<(&Vec<u8>, &[u8], &[u8])> = signed
+            .iter()
+            .zip(&messages)
+            .map(|((key, signature), message)| (key, message.as_slice(), signature.as_slice()))
+            .collect();
+        assert!(ClassicSuiteProvider::verify_batch(&entries).is_ok());
+        assert!(ClassicSuiteProvider::verify_batch(&[]).is_ok());
+
+        // Подпись от другого сообщения
+        entries[5].1 = &messages[4];
+        assert!(matches!(
+            ClassicSuiteProvider::verify_batch(&entries),
+            Err(CryptoError::BatchVerificationError { index: 5, .. })
+        ));
+
+        // Некорректная длина подписи
+        entries[5].1 = &messages[5];
+        entries[2].2 = &[0u8; 10];
+        assert!(matches!(
+            ClassicSuiteProvider::verify_batch(&entries),
+            Err(CryptoError::BatchVerificationError { index: 2, .. })
+        ));
     }
-}
\ No newline at end of file
+}
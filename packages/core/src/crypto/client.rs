@@ -1,33 +1,68 @@
-use crate::crypto::double_ratchet::{DoubleRatchetSession, EncryptedRatchetMessage, SerializableSession};
+use crate::crypto::double_ratchet::{
+    DoubleRatchetSession, EncryptedRatchetMessage, PaddingMode, SerializableSession,
+    DEFAULT_MAX_SKIPPED_MESSAGES,
+};
 use crate::utils;
+use crate::crypto::transcript::HandshakeTranscript;
 use crate::crypto::x3dh::{PublicKeyBundle, RegistrationBundle, X3DH};
-use crate::crypto::CryptoProvider;
+use crate::crypto::{CryptoProvider, SuiteID};
+use crate::error::{CryptoError, CryptoStringError};
+use crate::utils::time::{system_clock, Clock};
 use std::marker::PhantomData;
+use zeroize::Zeroize;
 
 #[cfg(feature = "post-quantum")]
-use pqcrypto_kyber::{keypair as kyber_keypair, encapsulate};
-#[cfg(feature = "post-quantum")]
-use pqcrypto_dilithium::{keypair as dilithium_keypair, sign};
-#[cfg(feature = "post-quantum")]
-use crate::crypto::pq_x3dh::PQX3DHBundle;
+use crate::crypto::pq_x3dh::{self, PQX3DHBundle, PQX3DHInitiation, PqIdentityKeys, PQ_ROOT_KEY_LEN};
+
 
+/// Статистика расшифровки сессии для диагностики (не сохраняется)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecryptStats {
+    /// Время последней успешной расшифровки (секунды с UNIX epoch)
+    pub last_success_at: Option<i64>,
+    /// Ошибок расшифровки подряд после последнего успеха
+    pub consecutive_failures: u32,
+}
 
 pub struct ClientCrypto<P: CryptoProvider> {
     identity_key: P::KemPrivateKey,
     signed_prekey: P::KemPrivateKey,
     signing_key: P::SignaturePrivateKey,
+    verifying_key: P::SignaturePublicKey,
     sessions: std::collections::HashMap<String, DoubleRatchetSession<P>>,
-
-    #[cfg(feature = "post-quantum")]
-    kyber_secret: pqcrypto_kyber::SecretKey,
+    /// Активная сессия для каждого контакта (contact_id -> session_id)
+    contact_sessions: std::collections::HashMap<String, String>,
+    /// Лимит пропущенных сообщений для новых сессий
+    max_skipped_messages: u32,
+    /// Выравнивание длины сообщений для новых сессий
+    padding: PaddingMode,
+    /// Шифровать заголовки в новых исходящих сессиях
+    header_encryption: bool,
+    /// Статистика расшифровки по session_id
+    decrypt_stats: std::collections::HashMap<String, DecryptStats>,
+    /// Время последнего использования сессии по session_id (не сохраняется)
+    last_used: std::collections::HashMap<String, i64>,
+    /// Время создания (или восстановления) сессии по session_id (не сохраняется)
+    created_at: std::collections::HashMap<String, i64>,
+    /// Источник времени (подменяется в тестах)
+    clock: Clock,
+
+    /// Пост-квантовые ключи (только у клиента из `new_with_pqc`)
     #[cfg(feature = "post-quantum")]
-    kyber_prekey_secret: pqcrypto_kyber::SecretKey,
-    #[cfg(feature = "post-quantum")]
-    dilithium_secret: pqcrypto_dilithium::SecretKey,
-    
+    pq_keys: Option<PqIdentityKeys>,
+
     _phantom: PhantomData<P>,
 }
 
+impl<P: CryptoProvider> Drop for ClientCrypto<P> {
+    fn drop(&mut self) {
+        // Сессии обнуляют свои ключи сами (Drop для DoubleRatchetSession)
+        self.identity_key.zeroize();
+        self.signed_prekey.zeroize();
+        self.signing_key.zeroize();
+    }
+}
+
 impl<P: CryptoProvider> Default for ClientCrypto<P> {
     fn default() -> Self {
         Self::new().unwrap()
@@ -35,18 +70,85 @@ impl<P: CryptoProvider> Default for ClientCrypto<P> {
 }
 
 impl<P: CryptoProvider> ClientCrypto<P> {
-    pub fn new() -> Result<Self, String> {
+    pub fn new() -> Result<Self, CryptoStringError> {
         let (identity_key, _) = P::generate_kem_keys().map_err(|e| e.to_string())?;
         let (signed_prekey, _) = P::generate_kem_keys().map_err(|e| e.to_string())?;
-        let (signing_key, _) = P::generate_signature_keys().map_err(|e| e.to_string())?;
+        let (signing_key, verifying_key) = P::generate_signature_keys().map_err(|e| e.to_string())?;
+
+        Ok(Self::from_keys(identity_key, signed_prekey, signing_key, verifying_key))
+    }
 
-        Ok(Self {
+    /// Создать клиент на существующих долговременных ключах (например, из KeyManager)
+    pub fn from_keys(
+        identity_key: P::KemPrivateKey,
+        signed_prekey: P::KemPrivateKey,
+        signing_key: P::SignaturePrivateKey,
+        verifying_key: P::SignaturePublicKey,
+    ) -> Self {
+        Self {
             identity_key,
             signed_prekey,
             signing_key,
+            verifying_key,
             sessions: std::collections::HashMap::new(),
+            contact_sessions: std::collections::HashMap::new(),
+            max_skipped_messages: DEFAULT_MAX_SKIPPED_MESSAGES,
+            padding: PaddingMode::default(),
+            header_encryption: false,
+            decrypt_stats: std::collections::HashMap::new(),
+            last_used: std::collections::HashMap::new(),
+            created_at: std::collections::HashMap::new(),
+            clock: system_clock(),
+            #[cfg(feature = "post-quantum")]
+            pq_keys: None,
             _phantom: PhantomData,
-        })
+        }
+    }
+
+    /// Использовать другой источник времени
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Заменить signed prekey после ротации (новые сессии получателя используют его)
+    pub fn set_signed_prekey(&mut self, signed_prekey: P::KemPrivateKey) {
+        self.signed_prekey.zeroize();
+        self.signed_prekey = signed_prekey;
+    }
+
+    /// Заменить долговременные ключи после смены identity.
+    /// Существующие сессии не трогаются - их завершает вызывающий
+    pub fn set_identity_keys(
+        &mut self,
+        identity_key: P::KemPrivateKey,
+        signed_prekey: P::KemPrivateKey,
+        signing_key: P::SignaturePrivateKey,
+        verifying_key: P::SignaturePublicKey,
+    ) {
+        self.identity_key.zeroize();
+        self.identity_key = identity_key;
+        self.set_signed_prekey(signed_prekey);
+        self.signing_key.zeroize();
+        self.signing_key = signing_key;
+        self.verifying_key = verifying_key;
+    }
+
+    /// Задать лимит пропущенных сообщений для новых сессий
+    pub fn set_max_skipped_messages(&mut self, limit: u32) {
+        self.max_skipped_messages = limit;
+    }
+
+    /// Задать выравнивание длины сообщений для новых сессий.
+    /// Собеседник должен использовать тот же режим
+    pub fn set_padding(&mut self, padding: PaddingMode) {
+        self.padding = padding;
+    }
+
+    /// Шифровать заголовки в новых исходящих сессиях. Получатель включает
+    /// режим сам, увидев зашифрованный заголовок первого сообщения
+    pub fn set_header_encryption(&mut self, enabled: bool) {
+        self.header_encryption = enabled;
     }
 
     /// Регистрация - возвращаем публичные ключи клиента
@@ -54,9 +156,6 @@ impl<P: CryptoProvider> ClientCrypto<P> {
         let identity_public = P::from_private_key_to_public_key(&self.identity_key).unwrap();
         let signed_prekey_public = P::from_private_key_to_public_key(&self.signed_prekey).unwrap();
 
-        // Generate signature public key from signature private key
-        let (_, verifying_key_generated) = P::generate_signature_keys().unwrap();
-
         // Подписываем signed prekey
         let signature = P::sign(&self.signing_key, signed_prekey_public.as_ref()).unwrap();
 
@@ -64,19 +163,39 @@ impl<P: CryptoProvider> ClientCrypto<P> {
             identity_public: identity_public.as_ref().to_vec(),
             signed_prekey_public: signed_prekey_public.as_ref().to_vec(),
             signature,
-            verifying_key: verifying_key_generated.as_ref().to_vec(),
+            verifying_key: self.verifying_key.as_ref().to_vec(),
             suite_id: P::suite_id(),
         }
     }
 
+    /// Suite собеседника должен совпадать с нашим: X3DH между разными suite невозможен
+    fn check_remote_suite(remote: SuiteID) -> Result<(), CryptoError> {
+        if remote != P::suite_id() {
+            return Err(CryptoError::SuiteMismatch { local: P::suite_id(), remote });
+        }
+        Ok(())
+    }
+
     /// Инициализация сессии - используем X3DH + Double Ratchet
     pub fn init_session(
         &mut self,
         contact_id: &str,
         remote_bundle: &PublicKeyBundle,
-    ) -> Result<String, String> {
+    ) -> Result<String, CryptoStringError> {
+        self.init_session_with_transcript(contact_id, remote_bundle, None)
+    }
+
+    /// Инициализация сессии с подтверждением транскрипта согласования suite:
+    /// первые сообщения несут его MAC, ответ собеседника обязан нести такой же
+    pub fn init_session_with_transcript(
+        &mut self,
+        contact_id: &str,
+        remote_bundle: &PublicKeyBundle,
+        transcript: Option<&HandshakeTranscript>,
+    ) -> Result<String, CryptoStringError> {
         eprintln!("[ClientCrypto] init_session called for contact: {}", contact_id);
         eprintln!("[ClientCrypto] suite_id: {}", remote_bundle.suite_id);
+        Self::check_remote_suite(remote_bundle.suite_id)?;
 
         // Convert Vec<u8> from bundle to generic types
         eprintln!("[ClientCrypto] Converting bytes to keys...");
@@ -86,29 +205,50 @@ impl<P: CryptoProvider> ClientCrypto<P> {
         eprintln!("[ClientCrypto] remote_signed_prekey_public converted");
         let remote_verifying_key = Self::bytes_to_signature_public_key(&remote_bundle.verifying_key)?;
         eprintln!("[ClientCrypto] remote_verifying_key converted");
+        let remote_one_time_prekey = match (
+            &remote_bundle.one_time_prekey_public,
+            remote_bundle.one_time_prekey_id,
+        ) {
+            (Some(bytes), Some(_)) => Some(Self::bytes_to_kem_public_key(bytes)?),
+            (None, None) => None,
+            _ => return Err("One-time prekey must come with its id".into()),
+        };
 
         // 1. X3DH handshake
         eprintln!("[ClientCrypto] Starting X3DH handshake...");
-        let root_key = X3DH::<P>::perform_x3dh(
+        let x3dh = X3DH::<P>::perform_x3dh(
             &self.identity_key,
-            &self.signed_prekey,
             &remote_identity_public,
             &remote_signed_prekey_public,
             &remote_bundle.signature,
             &remote_verifying_key,
+            remote_one_time_prekey.as_ref(),
             remote_bundle.suite_id,
         )?;
         eprintln!("[ClientCrypto] X3DH handshake completed successfully");
 
         // 2. Создание Double Ratchet сессии
         eprintln!("[ClientCrypto] Creating Double Ratchet session...");
-        let session = DoubleRatchetSession::<P>::new_x3dh_session(
+        let mut session = DoubleRatchetSession::<P>::new_x3dh_session(
             remote_bundle.suite_id,
-            &root_key,
+            &x3dh.root_key,
             &remote_identity_public,
             &self.identity_key,
             contact_id.to_string(),
-        )?;
+        )?
+        .with_x3dh_header(
+            x3dh.ephemeral_public.clone(),
+            remote_bundle.signed_prekey_id,
+            remote_bundle.one_time_prekey_id,
+        )
+        .with_max_skipped_messages(self.max_skipped_messages)
+        .with_padding(self.padding);
+        if let Some(transcript) = transcript {
+            session = session.with_transcript_tag(transcript.tag::<P>(&x3dh.root_key)?);
+        }
+        if self.header_encryption {
+            session = session.with_header_encryption(&x3dh.root_key)?;
+        }
         eprintln!("[ClientCrypto] Double Ratchet session created successfully");
 
         eprintln!("[ClientCrypto] Generating session ID...");
@@ -116,58 +256,131 @@ impl<P: CryptoProvider> ClientCrypto<P> {
         eprintln!("[ClientCrypto] Session ID: {}", session_id);
 
         eprintln!("[ClientCrypto] Storing session...");
-        self.sessions.insert(session_id.clone(), session);
+        self.store_session(&session_id, session);
         eprintln!("[ClientCrypto] Session stored successfully");
 
         Ok(session_id)
     }
 
+    /// Клиент с пост-квантовыми ключами (Kyber-768 + Dilithium3) поверх ключей suite
     #[cfg(feature = "post-quantum")]
-    pub fn new_with_pqc() -> Result<Self, String> {
-        // Классические ключи
-        let identity_key = x25519_dalek::StaticSecret::random_from_rng(rand::rngs::OsRng);
-        let signed_prekey = x25519_dalek::StaticSecret::random_from_rng(rand::rngs::OsRng);
-        let signing_key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
-        
-        // Пост-квантовые ключи
-        let (_, kyber_sk) = kyber_keypair().map_err(|e| e.to_string())?;
-        let (_, kyber_prekey_sk) = kyber_keypair().map_err(|e| e.to_string())?;
-        let (_, dilithium_sk) = dilithium_keypair().map_err(|e| e.to_string())?;
-        
-        Ok(Self {
-            identity_key,
-            signed_prekey,
-            signing_key,
-            sessions: std::collections::HashMap::new(),
-            storage: None,
-            kyber_secret: kyber_sk,
-            kyber_prekey_secret: kyber_prekey_sk,
-            dilithium_secret: dilithium_sk,
-            _phantom: PhantomData,
-        })
+    pub fn new_with_pqc() -> Result<Self, CryptoStringError> {
+        let mut client = Self::new()?;
+        client.pq_keys = Some(PqIdentityKeys::generate());
+        Ok(client)
+    }
+
+    #[cfg(feature = "post-quantum")]
+    fn pq_keys(&self) -> Result<&PqIdentityKeys, CryptoStringError> {
+        self.pq_keys
+            .as_ref()
+            .ok_or_else(|| "Client has no post-quantum keys (use new_with_pqc)".into())
+    }
+
+    /// Гибридный bundle для регистрации: классический bundle и подписанные PQ ключи
+    #[cfg(feature = "post-quantum")]
+    pub fn get_pq_registration_bundle(&self) -> Result<PQX3DHBundle, CryptoStringError> {
+        let bundle = self.get_registration_bundle();
+        let classic = PublicKeyBundle {
+            identity_public: bundle.identity_public,
+            signed_prekey_public: bundle.signed_prekey_public,
+            signature: bundle.signature,
+            verifying_key: bundle.verifying_key,
+            suite_id: bundle.suite_id,
+            one_time_prekey_public: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+        };
+        Ok(self.pq_keys()?.public_bundle::<P>(classic, &self.signing_key)?)
     }
-    
+
+    /// Гибридный X3DH инициатора: классический X3DH suite клиента и Kyber инкапсуляция
+    /// на Kyber identity и prekey собеседника; все секреты объединяются через HKDF
+    /// в 64-байтовый root key. `PQX3DHInitiation` передается собеседнику
     #[cfg(feature = "post-quantum")]
-    pub fn perform_pq_x3dh(&self, remote_bundle: &PQX3DHBundle) -> Result<[u8; 64], String> {
-        // This is a placeholder as per the markdown, and needs more implementation details
-        
-        // 1. Классический X3DH (needs to be implemented correctly)
-        // let classical_secret = X3DH::perform_x3dh(...)
-        unimplemented!("Classical part of PQX3DH is not implemented yet");
-
-        // 2. Пост-квантовый обмен (needs proper key conversion and error handling)
-        // let public_key = pqcrypto_kyber::PublicKey::from_bytes(&remote_bundle.kyber_public_key)?;
-        // let (kyber_ciphertext, kyber_shared) = encapsulate(&public_key)?;
-        // ... and for the prekey ...
-        unimplemented!("Post-quantum part of PQX3DH is not implemented yet");
-        
+    pub fn perform_pq_x3dh(
+        &self,
+        remote_bundle: &PQX3DHBundle,
+    ) -> Result<([u8; PQ_ROOT_KEY_LEN], PQX3DHInitiation), CryptoStringError> {
+        let classic_bundle = &remote_bundle.classic;
+        Self::check_remote_suite(classic_bundle.suite_id)?;
+
+        // 1. Классический X3DH
+        let remote_identity_public = Self::bytes_to_kem_public_key(&classic_bundle.identity_public)?;
+        let remote_signed_prekey_public = Self::bytes_to_kem_public_key(&classic_bundle.signed_prekey_public)?;
+        let remote_verifying_key = Self::bytes_to_signature_public_key(&classic_bundle.verifying_key)?;
+        let remote_one_time_prekey = match (
+            &classic_bundle.one_time_prekey_public,
+            classic_bundle.one_time_prekey_id,
+        ) {
+            (Some(bytes), Some(_)) => Some(Self::bytes_to_kem_public_key(bytes)?),
+            (None, None) => None,
+            _ => return Err("One-time prekey must come with its id".into()),
+        };
+        let classic = X3DH::<P>::perform_x3dh(
+            &self.identity_key,
+            &remote_identity_public,
+            &remote_signed_prekey_public,
+            &classic_bundle.signature,
+            &remote_verifying_key,
+            remote_one_time_prekey.as_ref(),
+            classic_bundle.suite_id,
+        )?;
+
+        // 2. Пост-квантовый обмен (проверяет suite bundle, подпись PQ ключей
+        // классическим ключом и Dilithium подпись Kyber prekey)
+        let (mut identity_shared, mut prekey_shared, identity_ciphertext, prekey_ciphertext) =
+            pq_x3dh::encapsulate::<P>(remote_bundle)?;
+
         // 3. Комбинируем через HKDF
-        // let combined = ...;
-        // let final_key = ...;
-        // Ok(final_key)
+        let root_key = pq_x3dh::combine_secrets::<P>(&classic.root_key, &identity_shared, &prekey_shared);
+        identity_shared.zeroize();
+        prekey_shared.zeroize();
+
+        Ok((
+            root_key?,
+            PQX3DHInitiation {
+                ephemeral_public: classic.ephemeral_public.clone(),
+                one_time_prekey_id: classic_bundle.one_time_prekey_id,
+                kyber_identity_ciphertext: identity_ciphertext,
+                kyber_prekey_ciphertext: prekey_ciphertext,
+            },
+        ))
+    }
+
+    /// Гибридный X3DH получателя: тот же root key из своих ключей и `PQX3DHInitiation`
+    #[cfg(feature = "post-quantum")]
+    pub fn perform_pq_x3dh_responder(
+        &self,
+        remote_identity_public: &[u8],
+        initiation: &PQX3DHInitiation,
+        one_time_prekey: Option<&P::KemPrivateKey>,
+    ) -> Result<[u8; PQ_ROOT_KEY_LEN], CryptoStringError> {
+        let pq_keys = self.pq_keys()?;
+        if let (Some(id), None) = (initiation.one_time_prekey_id, one_time_prekey) {
+            return Err(format!("One-time prekey {} is not available", id).into());
+        }
+
+        let remote_identity_public = Self::bytes_to_kem_public_key(remote_identity_public)?;
+        let remote_ephemeral_public = Self::bytes_to_kem_public_key(&initiation.ephemeral_public)?;
+        let mut classic_secret = X3DH::<P>::perform_x3dh_responder(
+            &self.identity_key,
+            &self.signed_prekey,
+            one_time_prekey,
+            &remote_identity_public,
+            &remote_ephemeral_public,
+        )?;
+
+        let (mut identity_shared, mut prekey_shared) = pq_keys.decapsulate(initiation)?;
+        let root_key = pq_x3dh::combine_secrets::<P>(&classic_secret, &identity_shared, &prekey_shared);
+        classic_secret.zeroize();
+        identity_shared.zeroize();
+        prekey_shared.zeroize();
+
+        Ok(root_key?)
     }
 
-    pub fn init_double_ratchet_session(&mut self, contact_id: &str, remote_bundle: &PublicKeyBundle) -> Result<String, String> {
+    pub fn init_double_ratchet_session(&mut self, contact_id: &str, remote_bundle: &PublicKeyBundle) -> Result<String, CryptoStringError> {
         self.init_session(contact_id, remote_bundle)
     }
 
@@ -177,47 +390,149 @@ impl<P: CryptoProvider> ClientCrypto<P> {
         contact_id: &str,
         remote_bundle: &PublicKeyBundle,
         first_message: &EncryptedRatchetMessage,
-    ) -> Result<String, String> {
+    ) -> Result<String, CryptoStringError> {
+        self.init_receiving_session_with_prekey(contact_id, remote_bundle, first_message, None, None)
+    }
+
+    /// Создать сессию получателя по prekey, id которых указаны в первом сообщении:
+    /// `signed_prekey` - приватный signed prekey (None - текущий), `one_time_prekey` -
+    /// приватный одноразовый prekey (вызывающий удаляет его после успеха)
+    pub fn init_receiving_session_with_prekey(
+        &mut self,
+        contact_id: &str,
+        remote_bundle: &PublicKeyBundle,
+        first_message: &EncryptedRatchetMessage,
+        signed_prekey: Option<&P::KemPrivateKey>,
+        one_time_prekey: Option<&P::KemPrivateKey>,
+    ) -> Result<String, CryptoStringError> {
+        self.init_receiving_session_with_transcript(
+            contact_id,
+            remote_bundle,
+            first_message,
+            signed_prekey,
+            one_time_prekey,
+            None,
+        )
+    }
+
+    /// Создать сессию получателя с подтверждением транскрипта согласования suite
+    /// (наше предложение suite и выбранный suite): первое сообщение собеседника
+    /// должно нести MAC того же транскрипта, иначе расшифровка вернет ошибку
+    pub fn init_receiving_session_with_transcript(
+        &mut self,
+        contact_id: &str,
+        remote_bundle: &PublicKeyBundle,
+        first_message: &EncryptedRatchetMessage,
+        signed_prekey: Option<&P::KemPrivateKey>,
+        one_time_prekey: Option<&P::KemPrivateKey>,
+        transcript: Option<&HandshakeTranscript>,
+    ) -> Result<String, CryptoStringError> {
+        Self::check_remote_suite(remote_bundle.suite_id)?;
+
         // Convert Vec<u8> from bundle to generic types
         let remote_identity_public = Self::bytes_to_kem_public_key(&remote_bundle.identity_public)?;
-        let remote_signed_prekey_public = Self::bytes_to_kem_public_key(&remote_bundle.signed_prekey_public)?;
-        let remote_verifying_key = Self::bytes_to_signature_public_key(&remote_bundle.verifying_key)?;
+        let remote_ephemeral_public = first_message
+            .x3dh_ephemeral_key
+            .as_deref()
+            .ok_or("First message has no X3DH ephemeral key")
+            .and_then(|bytes| Self::bytes_to_kem_public_key(bytes).map_err(|_| "Invalid X3DH ephemeral key"))?;
+        if let (Some(id), None) = (first_message.one_time_prekey_id, one_time_prekey) {
+            return Err(format!("One-time prekey {} is not available", id).into());
+        }
 
         // 1. X3DH handshake
-        let root_key = X3DH::<P>::perform_x3dh(
+        let root_key = X3DH::<P>::perform_x3dh_responder(
             &self.identity_key,
-            &self.signed_prekey,
+            signed_prekey.unwrap_or(&self.signed_prekey),
+            one_time_prekey,
             &remote_identity_public,
-            &remote_signed_prekey_public,
-            &remote_bundle.signature,
-            &remote_verifying_key,
-            remote_bundle.suite_id,
+            &remote_ephemeral_public,
         )?;
 
-        // 2. Создание Double Ratchet сессии для получателя
-        let session = DoubleRatchetSession::<P>::new_receiving_session(
+        // 2. Создание Double Ratchet сессии для получателя. Первые сообщения
+        // инициатора могли задержаться, поэтому сессия создается по любому
+        // сообщению его первой цепочки
+        let mut session = DoubleRatchetSession::<P>::new_receiving_session_from(
             remote_bundle.suite_id,
             &root_key,
             &self.identity_key,
             first_message,
             contact_id.to_string(),
-        )?;
+        )?
+        .with_max_skipped_messages(self.max_skipped_messages)
+        .with_padding(self.padding);
+        if let Some(transcript) = transcript {
+            session = session.with_transcript_tag(transcript.tag::<P>(&root_key)?);
+        }
+        session.set_remote_identity(&remote_bundle.identity_public);
 
         let session_id = utils::uuid::generate_v4();
-        self.sessions.insert(session_id.clone(), session);
+        self.store_session(&session_id, session);
 
         Ok(session_id)
     }
 
-    pub fn encrypt_ratchet_message(&mut self, session_id: &str, plaintext: &[u8]) -> Result<EncryptedRatchetMessage, String> {
+    pub fn encrypt_ratchet_message(&mut self, session_id: &str, plaintext: &[u8]) -> Result<EncryptedRatchetMessage, CryptoStringError> {
+        self.encrypt_ratchet_message_with_aad(session_id, plaintext, &[])
+    }
+
+    /// Зашифровать сообщение с дополнительными данными приложения (AAD)
+    pub fn encrypt_ratchet_message_with_aad(
+        &mut self,
+        session_id: &str,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<EncryptedRatchetMessage, CryptoStringError> {
+        let session = self.sessions
+            .get_mut(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+
+        let encrypted = session.encrypt_with_aad(plaintext, aad)?;
+        self.touch_session(session_id);
+        Ok(encrypted)
+    }
+
+    /// Расшифровать сообщение с дополнительными данными приложения (AAD)
+    pub fn decrypt_ratchet_message_with_aad(
+        &mut self,
+        session_id: &str,
+        encrypted: &EncryptedRatchetMessage,
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoStringError> {
+        let session = self.sessions
+            .get_mut(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+
+        let result = session.decrypt_with_aad(encrypted, aad);
+        self.record_decrypt(session_id, result.is_ok());
+        if result.is_ok() {
+            self.touch_session(session_id);
+        }
+        result.map_err(CryptoStringError::from)
+    }
+
+    /// Включить или выключить привязку timestamp к AAD для сессии
+    pub fn set_timestamp_binding(&mut self, session_id: &str, enabled: bool) -> Result<(), CryptoStringError> {
         let session = self.sessions
             .get_mut(session_id)
             .ok_or_else(|| format!("Session not found: {}", session_id))?;
 
-        session.encrypt(plaintext)
+        session.set_timestamp_binding(enabled);
+        Ok(())
     }
 
-    pub fn decrypt_ratchet_message(&mut self, session_id: &str, encrypted: &EncryptedRatchetMessage) -> Result<Vec<u8>, String> {
+    /// Принудительный DH шаг в сессии (см. `DoubleRatchetSession::force_dh_ratchet`).
+    /// Возвращает новый DH публичный ключ отправки
+    pub fn rotate_dh_immediately(&mut self, session_id: &str) -> Result<Vec<u8>, CryptoStringError> {
+        let session = self.sessions
+            .get_mut(session_id)
+            .ok_or_else(|| format!("Session not found: {}", session_id))?;
+
+        session.force_dh_ratchet()?;
+        Ok(session.dh_public_key().to_vec())
+    }
+
+    pub fn decrypt_ratchet_message(&mut self, session_id: &str, encrypted: &EncryptedRatchetMessage) -> Result<Vec<u8>, CryptoStringError> {
         eprintln!("[ClientCrypto] decrypt_ratchet_message called");
         eprintln!("[ClientCrypto] session_id: {}", session_id);
         eprintln!("[ClientCrypto] encrypted.message_number: {}", encrypted.message_number);
@@ -234,6 +549,10 @@ impl<P: CryptoProvider> ClientCrypto<P> {
 
         eprintln!("[ClientCrypto] Session found, calling session.decrypt...");
         let result = session.decrypt(encrypted);
+        self.record_decrypt(session_id, result.is_ok());
+        if result.is_ok() {
+            self.touch_session(session_id);
+        }
 
         if result.is_ok() {
             eprintln!("[ClientCrypto] ✅ Decryption successful");
@@ -241,34 +560,180 @@ impl<P: CryptoProvider> ClientCrypto<P> {
             eprintln!("[ClientCrypto] ❌ session.decrypt failed: {:?}", result);
         }
 
-        result
+        result.map_err(CryptoStringError::from)
     }
 
-    pub fn export_session(&self, session_id: &str) -> Result<Vec<u8>, String> {
+    pub fn export_session(&self, session_id: &str) -> Result<Vec<u8>, CryptoStringError> {
         let session = self.sessions
             .get(session_id)
             .ok_or_else(|| format!("Session not found: {}", session_id))?;
 
-        let serializable = session.to_serializable();
-        utils::serialization::to_bytes(&serializable)
+        session.to_serializable().to_bytes()
     }
 
-    pub fn restore_session(&mut self, session_data: &[u8]) -> Result<String, String> {
-        let serializable: SerializableSession = utils::serialization::from_bytes(session_data)?;
+    pub fn restore_session(&mut self, session_data: &[u8]) -> Result<String, CryptoStringError> {
+        let serializable = SerializableSession::from_bytes(session_data)?;
         let session = DoubleRatchetSession::<P>::from_serializable(serializable)?;
         let session_id = utils::uuid::generate_v4();
-
-        self.sessions.insert(session_id.clone(), session);
+        self.store_session(&session_id, session);
 
         Ok(session_id)
     }
 
+    /// Экспортировать все сессии (отсортированы по session_id)
+    pub fn export_all_sessions(&self) -> Result<Vec<(String, Vec<u8>)>, CryptoStringError> {
+        let mut session_ids: Vec<&String> = self.sessions.keys().collect();
+        session_ids.sort();
+
+        session_ids
+            .into_iter()
+            .map(|id| Ok((id.clone(), self.export_session(id)?)))
+            .collect()
+    }
+
+    /// Восстановить сессию под заданным session_id (например, из архива)
+    pub fn import_session(&mut self, session_id: &str, session_data: &[u8]) -> Result<(), CryptoStringError> {
+        let serializable = SerializableSession::from_bytes(session_data)?;
+        let session = DoubleRatchetSession::<P>::from_serializable(serializable)?;
+
+        self.store_session(session_id, session);
+
+        Ok(())
+    }
+
+    /// Завершить все сессии с контактом; ключи ratchet обнуляются при удалении.
+    /// Возвращает session_id активной сессии, если она была
+    pub fn end_session(&mut self, contact_id: &str) -> Option<String> {
+        let ended: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.contact_id() == contact_id)
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+        for session_id in &ended {
+            self.remove_session(session_id);
+        }
+        self.contact_sessions.remove(contact_id)
+    }
+
+    /// Подтверждена ли сессия собеседником (см. `DoubleRatchetSession::is_confirmed`)
+    pub fn is_session_confirmed(&self, session_id: &str) -> bool {
+        self.sessions
+            .get(session_id)
+            .is_some_and(|session| session.is_confirmed())
+    }
+
+    /// Удалить неподтвержденные (half-open) сессии, созданные или восстановленные
+    /// более `older_than` секунд назад. Возвращает контакты удаленных сессий
+    pub fn cleanup_unconfirmed(&mut self, older_than: i64) -> Vec<String> {
+        let now = (self.clock)();
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(session_id, session)| {
+                let created_at = self.created_at.get(*session_id).copied().unwrap_or_default();
+                !session.is_confirmed() && now - created_at >= older_than
+            })
+            .map(|(session_id, _)| session_id.clone())
+            .collect();
+
+        let mut contacts = Vec::new();
+        for session_id in &expired {
+            if let Some(session) = self.remove_session(session_id) {
+                let contact_id = session.contact_id().to_string();
+                if self.contact_sessions.get(&contact_id) == Some(session_id) {
+                    self.contact_sessions.remove(&contact_id);
+                }
+                contacts.push(contact_id);
+            }
+        }
+        contacts.sort();
+        contacts
+    }
+
+    /// Получить session_id активной сессии с контактом
+    pub fn session_id_for_contact(&self, contact_id: &str) -> Option<&str> {
+        self.contact_sessions.get(contact_id).map(|id| id.as_str())
+    }
+
+    /// Получить сессию по session_id
+    pub fn session(&self, session_id: &str) -> Option<&DoubleRatchetSession<P>> {
+        self.sessions.get(session_id)
+    }
+
+    /// Количество активных сессий
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Статистика расшифровки сессии (пустая, если расшифровок еще не было)
+    pub fn decrypt_stats(&self, session_id: &str) -> DecryptStats {
+        self.decrypt_stats.get(session_id).copied().unwrap_or_default()
+    }
+
+    /// Контакты с активными сессиями и время последнего использования сессии
+    /// (создание, шифрование или успешная расшифровка), от самых свежих.
+    /// При равном времени порядок по contact_id
+    pub fn sessions_by_recency(&self) -> Vec<(String, i64)> {
+        let mut contacts: Vec<(String, i64)> = self
+            .contact_sessions
+            .iter()
+            .map(|(contact_id, session_id)| {
+                let last_used = self.last_used.get(session_id).copied().unwrap_or_default();
+                (contact_id.clone(), last_used)
+            })
+            .collect();
+        contacts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        contacts
+    }
+
+    /// Сохранить сессию и сделать ее активной для контакта
+    fn store_session(&mut self, session_id: &str, session: DoubleRatchetSession<P>) {
+        self.contact_sessions.insert(session.contact_id().to_string(), session_id.to_string());
+        self.sessions.insert(session_id.to_string(), session);
+        self.created_at.insert(session_id.to_string(), (self.clock)());
+        self.touch_session(session_id);
+    }
+
+    /// Удалить сессию вместе с ее служебными данными (привязку к контакту не трогает)
+    fn remove_session(&mut self, session_id: &str) -> Option<DoubleRatchetSession<P>> {
+        self.last_used.remove(session_id);
+        self.created_at.remove(session_id);
+        self.decrypt_stats.remove(session_id);
+        self.sessions.remove(session_id)
+    }
+
+    fn touch_session(&mut self, session_id: &str) {
+        self.last_used.insert(session_id.to_string(), (self.clock)());
+    }
+
+    fn record_decrypt(&mut self, session_id: &str, success: bool) {
+        let stats = self.decrypt_stats.entry(session_id.to_string()).or_default();
+        if success {
+            stats.last_success_at = Some(utils::time::current_timestamp());
+            stats.consecutive_failures = 0;
+        } else {
+            stats.consecutive_failures += 1;
+        }
+    }
+
     // Helper methods to convert bytes to generic key types
     // ✅ SAFE: No unsafe code, uses CryptoProvider trait methods
-    fn bytes_to_kem_public_key(bytes: &[u8]) -> Result<P::KemPublicKey, String> {
+    fn bytes_to_kem_public_key(bytes: &[u8]) -> Result<P::KemPublicKey, CryptoStringError> {
         eprintln!("[ClientCrypto] bytes_to_kem_public_key called, input length: {}", bytes.len());
         eprintln!("[ClientCrypto] Input bytes (first 10): {:?}", &bytes[..10.min(bytes.len())]);
 
+        // Проверяем длину сразу, а не во время DH
+        let expected = P::kem_public_key_len();
+        if bytes.len() != expected {
+            return Err(CryptoError::InvalidKeyData(format!(
+                "KEM public key must be {} bytes, got {}",
+                expected,
+                bytes.len()
+            ))
+            .into());
+        }
+
         let key_vec = bytes.to_vec();
         let result = P::kem_public_key_from_bytes(key_vec);
 
@@ -278,7 +743,7 @@ impl<P: CryptoProvider> ClientCrypto<P> {
     }
 
     // ✅ SAFE: No unsafe code, uses CryptoProvider trait methods
-    fn bytes_to_signature_public_key(bytes: &[u8]) -> Result<P::SignaturePublicKey, String> {
+    fn bytes_to_signature_public_key(bytes: &[u8]) -> Result<P::SignaturePublicKey, CryptoStringError> {
         eprintln!("[ClientCrypto] bytes_to_signature_public_key called, input length: {}", bytes.len());
         eprintln!("[ClientCrypto] Input bytes (first 10): {:?}", &bytes[..10.min(bytes.len())]);
 
@@ -289,4 +754,222 @@ impl<P: CryptoProvider> ClientCrypto<P> {
         eprintln!("[ClientCrypto] Result bytes (first 10): {:?}", &result.as_ref()[..10.min(result.as_ref().len())]);
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    fn valid_bundle() -> PublicKeyBundle {
+        public_bundle(&ClientCrypto::<ClassicSuiteProvider>::new().unwrap())
+    }
+
+    fn public_bundle(client: &ClientCrypto<ClassicSuiteProvider>) -> PublicKeyBundle {
+        let bundle = client.get_registration_bundle();
+        PublicKeyBundle {
+            identity_public: bundle.identity_public,
+            signed_prekey_public: bundle.signed_prekey_public,
+            signature: bundle.signature,
+            verifying_key: bundle.verifying_key,
+            suite_id: bundle.suite_id,
+            one_time_prekey_public: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+        }
+    }
+
+    #[test]
+    fn test_init_session_rejects_short_identity_key() {
+        let mut client = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bundle = valid_bundle();
+        bundle.identity_public = vec![1u8; 16];
+
+        let err = client.init_session("bob", &bundle).unwrap_err();
+        assert_eq!(err.message(), "Invalid key data: KEM public key must be 32 bytes, got 16");
+        assert_eq!(client.session_count(), 0);
+    }
+
+    #[test]
+    fn test_init_session_rejects_empty_prekey() {
+        let mut client = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bundle = valid_bundle();
+        bundle.signed_prekey_public = Vec::new();
+
+        let err = client.init_session("bob", &bundle).unwrap_err();
+        assert!(err.message().starts_with("Invalid key data"), "{}", err);
+    }
+
+    #[test]
+    fn test_suite_mismatch_rejected() {
+        use crate::crypto::{CLASSIC_SUITE_ID, PQ_HYBRID_SUITE_ID};
+
+        let mut alice = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob_bundle = public_bundle(&bob);
+        assert_eq!(bob_bundle.suite_id, CLASSIC_SUITE_ID);
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let first = alice.encrypt_ratchet_message(&alice_session, b"hi").unwrap();
+
+        bob_bundle.suite_id = PQ_HYBRID_SUITE_ID;
+        let err = alice.init_session("bob", &bob_bundle).unwrap_err();
+        assert_eq!(err.message(), "Suite mismatch: local=1 remote=2");
+
+        let mut alice_bundle = public_bundle(&alice);
+        alice_bundle.suite_id = PQ_HYBRID_SUITE_ID;
+        let err = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap_err();
+        assert_eq!(err.message(), "Suite mismatch: local=1 remote=2");
+        assert_eq!(bob.session_count(), 0);
+
+        alice_bundle.suite_id = CLASSIC_SUITE_ID;
+        let bob_session = bob.init_receiving_session("alice", &alice_bundle, &first).unwrap();
+        assert_eq!(bob.decrypt_ratchet_message(&bob_session, &first).unwrap(), b"hi");
+    }
+
+    #[cfg(feature = "post-quantum")]
+    #[test]
+    fn test_pq_x3dh_handshake_between_clients() {
+        use crate::crypto::{CLASSIC_SUITE_ID, PQ_HYBRID_SUITE_ID};
+
+        let alice = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let bob = ClientCrypto::<ClassicSuiteProvider>::new_with_pqc().unwrap();
+        let bob_bundle = bob.get_pq_registration_bundle().unwrap();
+        assert_eq!(bob_bundle.suite_id, PQ_HYBRID_SUITE_ID);
+
+        let (alice_root, initiation) = alice.perform_pq_x3dh(&bob_bundle).unwrap();
+        let alice_identity = alice.get_registration_bundle().identity_public;
+        let bob_root = bob.perform_pq_x3dh_responder(&alice_identity, &initiation, None).unwrap();
+        assert_eq!(alice_root, bob_root);
+
+        // Подменный Kyber ciphertext дает другой root key
+        let mut tampered = initiation.clone();
+        tampered.kyber_prekey_ciphertext[0] ^= 0xff;
+        assert_ne!(bob.perform_pq_x3dh_responder(&alice_identity, &tampered, None).unwrap(), alice_root);
+
+        // Kyber prekey без действительной подписи Dilithium и чужой suite отклоняются
+        let mut unsigned = bob.get_pq_registration_bundle().unwrap();
+        unsigned.kyber_prekey_public[0] ^= 0xff;
+        assert!(alice.perform_pq_x3dh(&unsigned).is_err());
+        // PQ ключи, не подписанные классическим ключом собеседника, отклоняются:
+        // самоподписанный Dilithium ключ ничего не доказывает
+        let mallory = ClientCrypto::<ClassicSuiteProvider>::new_with_pqc().unwrap();
+        let mut substituted = mallory.get_pq_registration_bundle().unwrap();
+        substituted.classic = bob_bundle.classic.clone();
+        assert!(alice.perform_pq_x3dh(&substituted).is_err());
+        let mut classic_only = bob.get_pq_registration_bundle().unwrap();
+        classic_only.suite_id = CLASSIC_SUITE_ID;
+        assert!(alice.perform_pq_x3dh(&classic_only).is_err());
+
+        // Без PQ ключей клиент не может быть получателем
+        assert!(alice.perform_pq_x3dh_responder(&alice_identity, &initiation, None).is_err());
+    }
+
+    #[test]
+    fn test_transcript_confirmed_by_both_sides() {
+        use crate::crypto::{CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID};
+
+        let mut alice = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let transcript =
+            HandshakeTranscript::new(vec![CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID], CLASSIC_SUITE_ID);
+
+        let alice_session = alice
+            .init_session_with_transcript("bob", &public_bundle(&bob), Some(&transcript))
+            .unwrap();
+        let first = alice.encrypt_ratchet_message(&alice_session, b"hello").unwrap();
+        assert!(first.transcript_tag.is_some());
+
+        let bob_session = bob
+            .init_receiving_session_with_transcript("alice", &public_bundle(&alice), &first, None, None, Some(&transcript))
+            .unwrap();
+        assert_eq!(bob.decrypt_ratchet_message(&bob_session, &first).unwrap(), b"hello");
+
+        // Ответ Боба подтверждает транскрипт со своей стороны
+        let reply = bob.encrypt_ratchet_message(&bob_session, b"hi").unwrap();
+        assert!(reply.transcript_tag.is_some());
+        assert_eq!(alice.decrypt_ratchet_message(&alice_session, &reply).unwrap(), b"hi");
+
+        // Алиса ответила на ответ - теги больше не нужны ни одной стороне
+        let next = alice.encrypt_ratchet_message(&alice_session, b"again").unwrap();
+        assert!(next.transcript_tag.is_none());
+        assert_eq!(bob.decrypt_ratchet_message(&bob_session, &next).unwrap(), b"again");
+        assert!(bob.encrypt_ratchet_message(&bob_session, b"ok").unwrap().transcript_tag.is_none());
+    }
+
+    #[test]
+    fn test_sessions_by_recency() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let now = Arc::new(AtomicI64::new(1_000));
+        let clock_now = now.clone();
+        let mut alice = ClientCrypto::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+        let bob = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut carol = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let dave = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+
+        let to_bob = alice.init_session("bob", &public_bundle(&bob)).unwrap();
+        let to_carol = alice.init_session("carol", &public_bundle(&carol)).unwrap();
+        let to_dave = alice.init_session("dave", &public_bundle(&dave)).unwrap();
+        assert_eq!(
+            alice.sessions_by_recency(),
+            vec![("bob".to_string(), 1_000), ("carol".to_string(), 1_000), ("dave".to_string(), 1_000)]
+        );
+
+        now.store(1_010, Ordering::SeqCst);
+        let first = alice.encrypt_ratchet_message(&to_carol, b"hi carol").unwrap();
+        now.store(1_020, Ordering::SeqCst);
+        alice.encrypt_ratchet_message(&to_bob, b"hi bob").unwrap();
+
+        // Ответ Кэрол поднимает ее сессию наверх
+        let carol_session = carol
+            .init_receiving_session("alice", &public_bundle(&alice), &first)
+            .unwrap();
+        carol.decrypt_ratchet_message(&carol_session, &first).unwrap();
+        let reply = carol.encrypt_ratchet_message(&carol_session, b"hi alice").unwrap();
+        now.store(1_030, Ordering::SeqCst);
+        alice.decrypt_ratchet_message(&to_carol, &reply).unwrap();
+
+        // Неудачная расшифровка не считается использованием
+        now.store(1_040, Ordering::SeqCst);
+        assert!(alice.decrypt_ratchet_message(&to_dave, &reply).is_err());
+
+        assert_eq!(
+            alice.sessions_by_recency(),
+            vec![("carol".to_string(), 1_030), ("bob".to_string(), 1_020), ("dave".to_string(), 1_000)]
+        );
+    }
+
+    #[test]
+    fn test_tampered_offered_suites_detected() {
+        use crate::crypto::{CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID};
+
+        let mut alice = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+
+        // Боб предложил два suite, но до Алисы дошло предложение без более сильного
+        let offered =
+            HandshakeTranscript::new(vec![CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID], CLASSIC_SUITE_ID);
+        let stripped = HandshakeTranscript::new(vec![CLASSIC_SUITE_ID], CLASSIC_SUITE_ID);
+
+        let alice_session = alice
+            .init_session_with_transcript("bob", &public_bundle(&bob), Some(&stripped))
+            .unwrap();
+        let first = alice.encrypt_ratchet_message(&alice_session, b"hello").unwrap();
+
+        let bob_session = bob
+            .init_receiving_session_with_transcript("alice", &public_bundle(&alice), &first, None, None, Some(&offered))
+            .unwrap();
+        let err = bob.decrypt_ratchet_message(&bob_session, &first).unwrap_err();
+        assert_eq!(err.message(), "handshake transcript mismatch");
+
+        // Сообщение с вырезанным тегом тоже отклоняется
+        let mut untagged = first.clone();
+        untagged.transcript_tag = None;
+        let err = bob.decrypt_ratchet_message(&bob_session, &untagged).unwrap_err();
+        assert_eq!(err.message(), "handshake transcript mismatch");
+    }
+
+}
@@ -2,16 +2,23 @@
 
 use crate::error::CryptoError;
 use core::fmt::Debug;
+use core::marker::PhantomData;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+/// Fixed label MACed for X3DH key confirmation.
+pub const KEY_CONFIRMATION_LABEL: &[u8] = b"Construct X3DH key confirmation";
 
 /// Trait that formalizes all cryptographic operations for a specific cipher suite.
 /// This enables crypto-agility by allowing different implementations (e.g., classic, PQ-hybrid).
 pub trait CryptoProvider: Send + Sync + 'static {
-    // Associated types for key representation (using Vec<u8> for flexibility)
+    // Associated types for key representation (using Vec<u8> for flexibility).
+    // Secret key types must be zeroizable so owners can wipe them on drop.
     type KemPublicKey: AsRef<[u8]> + Debug + Clone + 'static;
-    type KemPrivateKey: AsRef<[u8]> + Debug + Clone + 'static;
+    type KemPrivateKey: AsRef<[u8]> + Debug + Clone + Zeroize + 'static;
     type SignaturePublicKey: AsRef<[u8]> + Debug + Clone + 'static;
-    type SignaturePrivateKey: AsRef<[u8]> + Debug + Clone + 'static;
-    type AeadKey: AsRef<[u8]> + Debug + Clone + Default + 'static; // Added Default bound
+    type SignaturePrivateKey: AsRef<[u8]> + Debug + Clone + Zeroize + 'static;
+    type AeadKey: AsRef<[u8]> + Debug + Clone + Default + Zeroize + 'static; // Added Default bound
 
     /// Generates a new KEM key pair.
     fn generate_kem_keys() -> Result<(Self::KemPrivateKey, Self::KemPublicKey), CryptoError>;
@@ -19,6 +26,12 @@ pub trait CryptoProvider: Send + Sync + 'static {
     /// Derives a KEM public key from a KEM private key.
     fn from_private_key_to_public_key(private_key: &Self::KemPrivateKey) -> Result<Self::KemPublicKey, CryptoError>;
 
+    /// Length in bytes of a serialized KEM public key for this suite.
+    fn kem_public_key_len() -> usize;
+
+    /// Length in bytes of an AEAD (and chain) key for this suite.
+    fn aead_key_len() -> usize;
+
     /// Creates a KEM public key from raw bytes
     fn kem_public_key_from_bytes(bytes: Vec<u8>) -> Self::KemPublicKey;
 
@@ -31,6 +44,9 @@ pub trait CryptoProvider: Send + Sync + 'static {
     /// Creates a Signature public key from raw bytes
     fn signature_public_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePublicKey;
 
+    /// Creates a Signature private key from raw bytes
+    fn signature_private_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePrivateKey;
+
     /// Generates a new Signature key pair.
     fn generate_signature_keys() -> Result<(Self::SignaturePrivateKey, Self::SignaturePublicKey), CryptoError>;
 
@@ -38,8 +54,21 @@ pub trait CryptoProvider: Send + Sync + 'static {
     fn sign(private_key: &Self::SignaturePrivateKey, message: &[u8]) -> Result<Vec<u8>, CryptoError>;
 
     /// Verifies a signature with the given public key.
+    #[must_use = "an unchecked signature must not be trusted"]
     fn verify(public_key: &Self::SignaturePublicKey, message: &[u8], signature: &[u8]) -> Result<(), CryptoError>;
 
+    /// Verifies many `(public_key, message, signature)` entries at once.
+    /// On failure returns `CryptoError::BatchVerificationError` with the index of the first bad entry.
+    #[must_use = "unchecked signatures must not be trusted"]
+    fn verify_batch(entries: &[(&Self::SignaturePublicKey, &[u8], &[u8])]) -> Result<(), CryptoError> {
+        for (index, (public_key, message, signature)) in entries.iter().enumerate() {
+            Self::verify(public_key, message, signature).map_err(|e| {
+                CryptoError::BatchVerificationError { index, reason: e.to_string() }
+            })?;
+        }
+        Ok(())
+    }
+
     /// Encapsulates a shared secret using the recipient's KEM public key.
     /// Returns the encapsulated ciphertext and the shared secret.
     fn kem_encapsulate(public_key: &Self::KemPublicKey) -> Result<(Vec<u8>, Vec<u8>), CryptoError>;
@@ -79,6 +108,27 @@ pub trait CryptoProvider: Send + Sync + 'static {
         len: usize,
     ) -> Result<Vec<u8>, CryptoError>;
 
+    /// Computes a MAC (HMAC with the suite hash) of `data` under `key`.
+    fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError>;
+
+    /// Key-confirmation tag for an X3DH root: a MAC of a fixed label under a key derived from the root.
+    /// The initiator sends it with its first messages so the responder can check both derived the same root.
+    fn key_confirmation_tag(root_key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let confirmation_key = Self::hkdf_derive_key(b"", root_key, b"X3DH Key Confirmation Key", 32)?;
+        Self::mac(&confirmation_key, KEY_CONFIRMATION_LABEL)
+    }
+
+    /// Checks a tag produced by `key_confirmation_tag` (constant-time comparison).
+    #[must_use = "a failed key confirmation means the roots differ"]
+    fn verify_key_confirmation(root_key: &[u8], tag: &[u8]) -> Result<(), CryptoError> {
+        let expected = Self::key_confirmation_tag(root_key)?;
+        let diff = expected.iter().zip(tag).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if expected.len() != tag.len() || diff != 0 {
+            return Err(CryptoError::KeyConfirmationFailed);
+        }
+        Ok(())
+    }
+
     /// Derives a root key and a chain key from the current root key and DH output.
     fn kdf_rk(root_key: &Self::AeadKey, dh_output: &[u8]) -> Result<(Self::AeadKey, Self::AeadKey), CryptoError>;
 
@@ -88,6 +138,51 @@ pub trait CryptoProvider: Send + Sync + 'static {
     /// Generates a cryptographically secure random nonce of a specified length.
     fn generate_nonce(len: usize) -> Result<Vec<u8>, CryptoError>;
 
+    /// Fills `dest` from the suite's randomness source. All key and nonce generation goes
+    /// through it; the OS CSPRNG by default, a seeded source in reproducible tests.
+    fn fill_random(dest: &mut [u8]) -> Result<(), CryptoError> {
+        rand::rngs::OsRng
+            .try_fill_bytes(dest)
+            .map_err(|e| CryptoError::RandomnessFailure(e.to_string()))
+    }
+
     /// Returns the SuiteID associated with this CryptoProvider.
     fn suite_id() -> u16;
 }
+
+/// `RngCore` view of a provider's `fill_random`, for APIs that take an RNG.
+pub struct ProviderRng<P: CryptoProvider>(PhantomData<P>);
+
+impl<P: CryptoProvider> ProviderRng<P> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<P: CryptoProvider> Default for ProviderRng<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: CryptoProvider> RngCore for ProviderRng<P> {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if let Err(e) = self.try_fill_bytes(dest) {
+            panic!("randomness source failed: {}", e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        P::fill_random(dest).map_err(rand_core::Error::new)
+    }
+}
+
+impl<P: CryptoProvider> CryptoRng for ProviderRng<P> {}
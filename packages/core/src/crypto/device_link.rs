@@ -0,0 +1,77 @@
+// Привязка нового устройства: передача identity и сессий
+// Основное устройство шифрует архив на одноразовый KEM ключ нового устройства
+
+use crate::crypto::crypto_provider::CryptoProvider;
+use crate::error::CryptoError;
+use crate::utils::serialization;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+const DEVICE_LINK_INFO: &[u8] = b"Device Link Key";
+const DEVICE_LINK_NONCE_LEN: usize = 12;
+
+/// Sealed payload: KEM ciphertext for the new device key, AEAD nonce and ciphertext.
+#[derive(Serialize, Deserialize)]
+struct SealedDeviceLink {
+    kem_ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// AEAD key for the link, bound to the KEM ciphertext.
+fn link_key<P: CryptoProvider>(shared_secret: &[u8], kem_ciphertext: &[u8]) -> Result<P::AeadKey, CryptoError> {
+    let key = P::hkdf_derive_key(kem_ciphertext, shared_secret, DEVICE_LINK_INFO, P::aead_key_len())?;
+    Ok(P::aead_key_from_bytes(key))
+}
+
+/// Encrypts `plaintext` to the new device's KEM public key.
+pub fn seal_for_device<P: CryptoProvider>(
+    device_public: &P::KemPublicKey,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let (kem_ciphertext, mut shared_secret) = P::kem_encapsulate(device_public)?;
+    let key = link_key::<P>(&shared_secret, &kem_ciphertext);
+    shared_secret.zeroize();
+    let key = key?;
+
+    let nonce = P::generate_nonce(DEVICE_LINK_NONCE_LEN)?;
+    let ciphertext = P::aead_encrypt(&key, &nonce, plaintext, Some(&kem_ciphertext))?;
+
+    serialization::to_bytes(&SealedDeviceLink { kem_ciphertext, nonce, ciphertext })
+        .map_err(CryptoError::SerializationError)
+}
+
+/// Decrypts a payload produced by `seal_for_device` with the new device's KEM private key.
+pub fn open_from_device<P: CryptoProvider>(
+    device_private: &P::KemPrivateKey,
+    sealed: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, CryptoError> {
+    let sealed: SealedDeviceLink =
+        serialization::from_bytes(sealed).map_err(CryptoError::DeserializationError)?;
+
+    let mut shared_secret = P::kem_decapsulate(device_private, &sealed.kem_ciphertext)?;
+    let key = link_key::<P>(&shared_secret, &sealed.kem_ciphertext);
+    shared_secret.zeroize();
+    let key = key?;
+
+    P::aead_decrypt(&key, &sealed.nonce, &sealed.ciphertext, Some(&sealed.kem_ciphertext))
+        .map(Zeroizing::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    #[test]
+    fn test_sealed_only_for_linked_device() {
+        let (device_private, device_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let sealed = seal_for_device::<ClassicSuiteProvider>(&device_public, b"identity archive").unwrap();
+
+        let opened = open_from_device::<ClassicSuiteProvider>(&device_private, &sealed).unwrap();
+        assert_eq!(opened.as_slice(), b"identity archive");
+
+        let (other_private, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        assert!(open_from_device::<ClassicSuiteProvider>(&other_private, &sealed).is_err());
+    }
+}
@@ -1,8 +1,155 @@
 use crate::crypto::{CryptoProvider, SuiteID};
+use crate::error::CryptoStringError;
+use crate::utils::time::{system_clock, Clock};
+use zeroize::Zeroize;
 
 /// Constants for DoS protection for skipped messages.
-const MAX_SKIPPED_MESSAGES: u32 = 1000;
+/// Лимит пропущенных ключей по умолчанию (настраивается на сессию)
+pub const DEFAULT_MAX_SKIPPED_MESSAGES: u32 = 1000;
 const MAX_SKIPPED_MESSAGE_AGE_SECONDS: i64 = 7 * 24 * 60 * 60; // 7 days
+const HEADER_NONCE_LEN: usize = 12;
+
+/// Причина ошибки расшифровки сообщения
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecryptError {
+    /// Номер сообщения уже пройден цепочкой, сохраненного ключа нет
+    /// (повтор или сообщение, ключ которого уже удален)
+    #[error("Message predates current chain (no stored key): msgNum={0}")]
+    PredatesChain(u32),
+    /// Сообщение слишком далеко впереди - превышен лимит пропущенных ключей
+    #[error("Message too far ahead (exceeds skip limit {limit}): msgNum={message_number}")]
+    TooFarAhead { message_number: u32, limit: u32 },
+    /// Ключ найден, но AEAD проверка не прошла (подделка или повреждение)
+    #[error("AEAD decryption failed: {0}")]
+    AeadFailed(String),
+    /// Тег транскрипта согласования не совпал или отсутствует (возможен downgrade)
+    #[error("handshake transcript mismatch")]
+    TranscriptMismatch,
+    /// Прочие ошибки (формат ключей, DH шаг, KDF)
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<String> for DecryptError {
+    fn from(err: String) -> Self {
+        DecryptError::Other(err)
+    }
+}
+
+impl From<&str> for DecryptError {
+    fn from(err: &str) -> Self {
+        DecryptError::Other(err.to_string())
+    }
+}
+
+impl From<DecryptError> for String {
+    fn from(err: DecryptError) -> Self {
+        err.to_string()
+    }
+}
+
+impl From<CryptoStringError> for DecryptError {
+    fn from(err: CryptoStringError) -> Self {
+        DecryptError::Other(err.0)
+    }
+}
+
+impl From<DecryptError> for CryptoStringError {
+    fn from(err: DecryptError) -> Self {
+        CryptoStringError(err.to_string())
+    }
+}
+
+/// Длина префикса с исходной длиной открытого текста в выровненном сообщении
+const PADDING_LENGTH_PREFIX: usize = 4;
+
+/// Выравнивание длины открытого текста перед шифрованием: длина шифртекста
+/// выдает только корзину, а не точный размер сообщения.
+/// Обе стороны сессии должны использовать один режим
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum PaddingMode {
+    /// Без выравнивания
+    #[default]
+    None,
+    /// До ближайшей степени двойки
+    PowerOfTwo,
+    /// До длины, кратной размеру блока
+    FixedBlock(usize),
+}
+
+impl PaddingMode {
+    /// Дополнить открытый текст: `u32 BE длина || текст || нули` до границы корзины
+    pub fn pad(&self, plaintext: &[u8]) -> Result<Vec<u8>, CryptoStringError> {
+        if *self == PaddingMode::None {
+            return Ok(plaintext.to_vec());
+        }
+        let declared = u32::try_from(plaintext.len()).map_err(|_| "Plaintext too long for padding")?;
+        let unpadded_len = PADDING_LENGTH_PREFIX + plaintext.len();
+        let padded_len = match *self {
+            PaddingMode::None => unpadded_len,
+            PaddingMode::PowerOfTwo => unpadded_len
+                .checked_next_power_of_two()
+                .ok_or("Plaintext too long for padding")?,
+            PaddingMode::FixedBlock(0) => return Err("Padding block size must be positive".into()),
+            PaddingMode::FixedBlock(block) => {
+                let blocks = (unpadded_len + block - 1) / block;
+                blocks.checked_mul(block).ok_or("Plaintext too long for padding")?
+            }
+        };
+
+        let mut padded = Vec::with_capacity(padded_len);
+        padded.extend_from_slice(&declared.to_be_bytes());
+        padded.extend_from_slice(plaintext);
+        padded.resize(padded_len, 0);
+        Ok(padded)
+    }
+
+    /// Снять выравнивание, вернув исходные байты. Выровненный буфер обнуляется
+    pub fn unpad(&self, mut padded: Vec<u8>) -> Result<Vec<u8>, DecryptError> {
+        if *self == PaddingMode::None {
+            return Ok(padded);
+        }
+        let declared = padded
+            .get(..PADDING_LENGTH_PREFIX)
+            .map(|prefix| u32::from_be_bytes([prefix[0], prefix[1], prefix[2], prefix[3]]) as usize);
+        let result = match declared {
+            Some(len) if len <= padded.len() - PADDING_LENGTH_PREFIX => {
+                Ok(padded[PADDING_LENGTH_PREFIX..PADDING_LENGTH_PREFIX + len].to_vec())
+            }
+            _ => Err(DecryptError::Other("Invalid message padding".to_string())),
+        };
+        padded.zeroize();
+        result
+    }
+}
+
+/// Ключи шифрования заголовков (Signal header encryption).
+/// Ключ заголовка следующей цепочки выводится на предыдущем DH шаге, поэтому
+/// получатель находит новую цепочку, пробуя `next_receiving`, еще до DH шага
+struct HeaderKeys<K> {
+    sending: Option<K>,
+    next_sending: K,
+    receiving: Option<K>,
+    next_receiving: K,
+}
+
+impl<K: Zeroize> HeaderKeys<K> {
+    fn zeroize(&mut self) {
+        for key in [&mut self.sending, &mut self.receiving].into_iter().flatten() {
+            key.zeroize();
+        }
+        self.next_sending.zeroize();
+        self.next_receiving.zeroize();
+    }
+}
+
+/// Результат DH шага приема, подготовленный до расшифровки
+struct DhRatchetStep<K> {
+    root_key: K,
+    receiving_chain: K,
+    /// Ключ заголовка следующей цепочки собеседника (только в режиме header encryption)
+    next_header_key: Option<K>,
+}
 
 pub struct DoubleRatchetSession<P: CryptoProvider> {
     suite_id: SuiteID,
@@ -19,11 +166,47 @@ pub struct DoubleRatchetSession<P: CryptoProvider> {
     remote_dh_public: Option<P::KemPublicKey>,
 
     previous_sending_length: u32,
+    /// Новая DH пара для отправки генерируется лениво - при первом encrypt
+    /// после получения нового DH ключа собеседника
+    send_ratchet_pending: bool,
     skipped_message_keys: std::collections::HashMap<u32, P::AeadKey>,
     skipped_key_timestamps: std::collections::HashMap<u32, u64>,
+    /// Максимум хранимых ключей пропущенных сообщений (защита от DoS)
+    max_skipped_messages: u32,
 
     session_id: String,
     contact_id: String,
+
+    /// Identity ключ собеседника, с которым была установлена сессия
+    remote_identity: Option<Vec<u8>>,
+
+    /// Тег подтверждения ключа X3DH: инициатор прикладывает его к сообщениям,
+    /// пока не получит первый ответ
+    key_confirmation: Option<Vec<u8>>,
+
+    /// Timestamp сообщения входит в AAD (переупорядочивание с подменой времени ломает расшифровку)
+    bind_timestamp: bool,
+
+    /// Ephemeral ключ X3DH и id использованных prekey получателя: инициатор
+    /// прикладывает их к сообщениям, пока не получит первый ответ
+    x3dh_ephemeral: Option<Vec<u8>>,
+    one_time_prekey_id: Option<u32>,
+    signed_prekey_id: Option<u32>,
+
+    /// Наш MAC транскрипта согласования: прикладывается к сообщениям,
+    /// пока собеседник не ответит на них со своей стороны DH шагом
+    transcript_tag: Option<Vec<u8>>,
+    /// MAC транскрипта, который обязан приложить собеседник (до первой проверки)
+    expected_transcript_tag: Option<Vec<u8>>,
+
+    /// Ключи шифрования заголовков; `None` - заголовок передается открыто
+    header_keys: Option<HeaderKeys<P::AeadKey>>,
+
+    /// Выравнивание длины открытого текста (обе стороны задают одинаковый режим)
+    padding: PaddingMode,
+
+    /// Источник времени для возраста ключей пропущенных сообщений (подменяется в тестах)
+    clock: Clock,
 }
 
 impl<P: CryptoProvider> DoubleRatchetSession<P> {
@@ -37,6 +220,126 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
         &self.contact_id
     }
 
+    /// Получить identity ключ собеседника, зафиксированный при установке сессии
+    pub fn remote_identity(&self) -> Option<&[u8]> {
+        self.remote_identity.as_deref()
+    }
+
+    /// Задать лимит пропущенных сообщений
+    pub fn with_max_skipped_messages(mut self, limit: u32) -> Self {
+        self.max_skipped_messages = limit;
+        self
+    }
+
+    /// Использовать другой источник времени
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Режим выравнивания длины сообщений (задается при создании сессии обеими сторонами)
+    pub fn with_padding(mut self, padding: PaddingMode) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Режим выравнивания длины сообщений
+    pub fn padding(&self) -> PaddingMode {
+        self.padding
+    }
+
+    /// Подтверждена ли сессия собеседником. Сессия инициатора остается неподтвержденной
+    /// (half-open), пока не расшифрован первый ответ; сессия получателя создается
+    /// по сообщению собеседника и подтверждена сразу
+    pub fn is_confirmed(&self) -> bool {
+        self.key_confirmation.is_none()
+    }
+
+    /// Заголовок X3DH для первых сообщений инициатора: ephemeral ключ и id
+    /// использованных prekey получателя
+    pub fn with_x3dh_header(
+        mut self,
+        ephemeral_public: Vec<u8>,
+        signed_prekey_id: Option<u32>,
+        one_time_prekey_id: Option<u32>,
+    ) -> Self {
+        self.x3dh_ephemeral = Some(ephemeral_public);
+        self.signed_prekey_id = signed_prekey_id;
+        self.one_time_prekey_id = one_time_prekey_id;
+        self
+    }
+
+    /// MAC транскрипта согласования (`HandshakeTranscript::tag`).
+    /// Обе стороны вычисляют его из своего представления транскрипта;
+    /// сообщение собеседника с другим тегом отклоняется
+    pub fn with_transcript_tag(mut self, tag: Vec<u8>) -> Self {
+        self.expected_transcript_tag = Some(tag.clone());
+        self.transcript_tag = Some(tag);
+        self
+    }
+
+    /// Включить шифрование заголовков (инициатор, до первого сообщения).
+    /// `root_key_bytes` - тот же общий секрет X3DH, что и в `new_x3dh_session`;
+    /// получатель включает режим сам, увидев зашифрованный заголовок первого сообщения
+    pub fn with_header_encryption(mut self, root_key_bytes: &[u8]) -> Result<Self, CryptoStringError> {
+        if self.sending_chain_length > 0 || self.send_ratchet_pending {
+            return Err("Header encryption must be enabled by the initiator before the first message".into());
+        }
+        let (initiator_key, responder_key) = Self::initial_header_keys(root_key_bytes)?;
+        self.header_keys = Some(HeaderKeys {
+            sending: Some(initiator_key),
+            next_sending: Self::next_header_key(&self.root_key)?,
+            receiving: None,
+            next_receiving: responder_key,
+        });
+        Ok(self)
+    }
+
+    /// Шифруются ли заголовки сообщений
+    pub fn header_encryption(&self) -> bool {
+        self.header_keys.is_some()
+    }
+
+    /// Лимит пропущенных сообщений
+    pub fn max_skipped_messages(&self) -> u32 {
+        self.max_skipped_messages
+    }
+
+    /// Включить привязку timestamp сообщения к AAD (обе стороны должны включить)
+    pub fn set_timestamp_binding(&mut self, enabled: bool) {
+        self.bind_timestamp = enabled;
+    }
+
+    /// Привязан ли timestamp сообщения к AAD
+    pub fn timestamp_binding(&self) -> bool {
+        self.bind_timestamp
+    }
+
+    /// Suite, на которой установлена сессия
+    pub fn suite_id(&self) -> SuiteID {
+        self.suite_id
+    }
+
+    /// Длина текущей цепочки отправки
+    pub fn sending_chain_length(&self) -> u32 {
+        self.sending_chain_length
+    }
+
+    /// Длина текущей цепочки приема
+    pub fn receiving_chain_length(&self) -> u32 {
+        self.receiving_chain_length
+    }
+
+    /// Количество сохраненных ключей пропущенных сообщений
+    pub fn skipped_key_count(&self) -> usize {
+        self.skipped_message_keys.len()
+    }
+
+    /// Зафиксировать identity ключ собеседника
+    pub fn set_remote_identity(&mut self, identity_public: &[u8]) {
+        self.remote_identity = Some(identity_public.to_vec());
+    }
+
     /// Инициатор сессии (Alice) - создает сессию для отправки первого сообщения
     pub fn new_x3dh_session(
         suite_id: SuiteID,
@@ -44,7 +347,7 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
         remote_identity_public_kem_pk: &P::KemPublicKey,
         local_identity_private_kem_sk: &P::KemPrivateKey,
         contact_id: String,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, CryptoStringError> {
         // Convert root_key bytes to P::AeadKey
         let root_key_vec = P::hkdf_derive_key(b"", root_key_bytes, b"InitialRootKey", 32)
             .map_err(|e| format!("Failed to derive root key: {}", e))?;
@@ -60,6 +363,8 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
 
         let (root_key, chain_key) = P::kdf_rk(&root_key_val, &dh_output_secret)
             .map_err(|e| format!("KDF_RK failed: {}", e))?;
+        let key_confirmation = P::key_confirmation_tag(root_key_bytes)
+            .map_err(|e| format!("Key confirmation tag failed: {}", e))?;
 
         Ok(Self {
             suite_id,
@@ -72,24 +377,126 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
             dh_ratchet_public: dh_public,
             remote_dh_public: Some(remote_identity_public_kem_pk.clone()),
             previous_sending_length: 0,
+            send_ratchet_pending: false,
             skipped_message_keys: std::collections::HashMap::new(),
             skipped_key_timestamps: std::collections::HashMap::new(),
+            max_skipped_messages: DEFAULT_MAX_SKIPPED_MESSAGES,
             session_id: uuid::Uuid::new_v4().to_string(),
             contact_id,
+            remote_identity: Some(remote_identity_public_kem_pk.as_ref().to_vec()),
+            key_confirmation: Some(key_confirmation),
+            bind_timestamp: false,
+            x3dh_ephemeral: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            expected_transcript_tag: None,
+            header_keys: None,
+            padding: PaddingMode::None,
+            clock: system_clock(),
         })
     }
 
-    /// Получатель (Bob) - создает сессию при получении первого сообщения
+    /// Получатель (Bob) - создает сессию при получении первого сообщения.
+    /// Сообщение должно быть первым в сессии (номер 0, предыдущей цепочки нет);
+    /// для более позднего сообщения - `new_receiving_session_from`
     pub fn new_receiving_session(
         suite_id: SuiteID,
         root_key_bytes: &[u8],
         local_identity_private_kem_sk: &P::KemPrivateKey,
         first_message: &EncryptedRatchetMessage,
         contact_id: String,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, CryptoStringError> {
+        let (session, message_number, previous_chain_length) = Self::receiving_session(
+            suite_id,
+            root_key_bytes,
+            local_identity_private_kem_sk,
+            first_message,
+            contact_id,
+        )?;
+        if message_number != 0 || previous_chain_length != 0 {
+            return Err(format!(
+                "Message {} (previous chain length {}) is not the first message of the session",
+                message_number, previous_chain_length
+            )
+            .into());
+        }
+        Ok(session)
+    }
+
+    /// Получатель (Bob) - создает сессию по любому сообщению первой цепочки инициатора
+    /// (первые сообщения задержались или потерялись). Ключи более ранних сообщений
+    /// сохраняются как пропущенные и расшифруют их, когда они придут.
+    ///
+    /// Сообщение из следующей цепочки инициатора (после его DH шага) не подходит:
+    /// DH ключ первой цепочки, от которого выведен ее root, в нем не передается
+    pub fn new_receiving_session_from(
+        suite_id: SuiteID,
+        root_key_bytes: &[u8],
+        local_identity_private_kem_sk: &P::KemPrivateKey,
+        message: &EncryptedRatchetMessage,
+        contact_id: String,
+    ) -> Result<Self, CryptoStringError> {
+        let (mut session, message_number, previous_chain_length) = Self::receiving_session(
+            suite_id,
+            root_key_bytes,
+            local_identity_private_kem_sk,
+            message,
+            contact_id,
+        )?;
+        if previous_chain_length != 0 {
+            return Err(format!(
+                "Message {} is from a later chain of the initiator (previous chain length {}); \
+                 the session must be established from its first chain",
+                message_number, previous_chain_length
+            )
+            .into());
+        }
+        session.skip_receiving_chain_to(message_number)?;
+        Ok(session)
+    }
+
+    /// Общая часть конструкторов получателя: сессия и номер сообщения / длина
+    /// предыдущей цепочки из его заголовка (расшифрованного, если он зашифрован)
+    fn receiving_session(
+        suite_id: SuiteID,
+        root_key_bytes: &[u8],
+        local_identity_private_kem_sk: &P::KemPrivateKey,
+        first_message: &EncryptedRatchetMessage,
+        contact_id: String,
+    ) -> Result<(Self, u32, u32), CryptoStringError> {
+        // Подтверждение ключа обязательно: сообщение без него могло быть лишено тега по пути
+        let tag = first_message
+            .key_confirmation
+            .as_ref()
+            .ok_or("First message carries no X3DH key confirmation")?;
+        P::verify_key_confirmation(root_key_bytes, tag).map_err(|e| e.to_string())?;
+
+        // Зашифрованный заголовок первого сообщения открывается ключом инициатора из X3DH
+        let (initial_header_keys, remote_dh_public_bytes, message_number, previous_chain_length) =
+            match &first_message.encrypted_header {
+                Some(header) => {
+                    let (initiator_key, responder_key) = Self::initial_header_keys(root_key_bytes)?;
+                    let (dh_public_key, message_number, previous_chain_length) =
+                        Self::open_header(&initiator_key, header)
+                            .ok_or("Failed to decrypt the first message header")?;
+                    (
+                        Some((initiator_key, responder_key)),
+                        dh_public_key,
+                        message_number,
+                        previous_chain_length,
+                    )
+                }
+                None => (
+                    None,
+                    first_message.dh_public_key.clone(),
+                    first_message.message_number,
+                    first_message.previous_chain_length,
+                ),
+            };
+
         // Convert DH public key from message
-        let remote_dh_public_bytes = &first_message.dh_public_key;
-        let remote_dh_public = Self::bytes_to_kem_public_key(remote_dh_public_bytes)?;
+        let remote_dh_public = Self::bytes_to_kem_public_key(&remote_dh_public_bytes)?;
 
         // Convert root_key bytes to P::AeadKey
         let root_key_vec = P::hkdf_derive_key(b"", root_key_bytes, b"InitialRootKey", 32)
@@ -103,69 +510,297 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
             .map_err(|e| format!("KDF_RK failed: {}", e))?;
         root_key_val = new_root_key;
 
-        // Generate new DH pair for sending
+        let header_keys = match initial_header_keys {
+            Some((initiator_key, responder_key)) => Some(HeaderKeys {
+                sending: None,
+                next_sending: responder_key,
+                receiving: Some(initiator_key),
+                next_receiving: Self::next_header_key(&root_key_val)?,
+            }),
+            None => None,
+        };
+
+        // Sending chain выводится сразу на новой DH паре: identity ключ не становится
+        // ratchet ключом и не попадает в сериализованную сессию
         let (dh_private, dh_public) = P::generate_kem_keys()
             .map_err(|e| format!("Failed to generate DH keys: {}", e))?;
 
-        // Perform second ratchet for sending chain
-        let dh_output2 = P::kem_decapsulate(&dh_private, remote_dh_public.as_ref())
-            .map_err(|e| format!("Failed to perform DH: {}", e))?;
-        let (final_root_key, sending_chain) = P::kdf_rk(&root_key_val, &dh_output2)
-            .map_err(|e| format!("KDF_RK failed: {}", e))?;
-
-        Ok(Self {
+        let mut session = Self {
             suite_id,
-            root_key: final_root_key,
-            sending_chain_key: sending_chain,
+            root_key: root_key_val,
+            sending_chain_key: P::AeadKey::default(),
             sending_chain_length: 0,
             receiving_chain_key: receiving_chain,
             receiving_chain_length: 0,
-            dh_ratchet_private: Some(dh_private),
-            dh_ratchet_public: dh_public,
+            dh_ratchet_private: None,
+            dh_ratchet_public: dh_public.clone(),
             remote_dh_public: Some(remote_dh_public),
             previous_sending_length: 0,
+            send_ratchet_pending: true,
             skipped_message_keys: std::collections::HashMap::new(),
             skipped_key_timestamps: std::collections::HashMap::new(),
+            max_skipped_messages: DEFAULT_MAX_SKIPPED_MESSAGES,
             session_id: uuid::Uuid::new_v4().to_string(),
             contact_id,
-        })
+            remote_identity: None,
+            key_confirmation: None,
+            bind_timestamp: false,
+            x3dh_ephemeral: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            expected_transcript_tag: None,
+            header_keys,
+            padding: PaddingMode::None,
+            clock: system_clock(),
+        };
+        session.ratchet_sending_chain_with(dh_private, dh_public)?;
+        Ok((session, message_number, previous_chain_length))
+    }
+
+    /// Принудительный DH шаг: новая DH пара и новая sending chain.
+    ///
+    /// Следующее сообщение шифруется ключами, не выводимыми из предыдущей цепочки.
+    /// Шаг выполняется на последнем известном DH ключе собеседника; если его ответ
+    /// с новым ключом уже в пути, собеседник не сможет расшифровать сообщения новой
+    /// цепочки - вызывать только когда входящих сообщений не ожидается.
+    ///
+    /// Инициатор до первого ответа ratchet ключа собеседника еще не знает, и шаг
+    /// невозможен: он произойдет сам при первой отправке после ответа
+    pub fn force_dh_ratchet(&mut self) -> Result<(), CryptoStringError> {
+        if self.key_confirmation.is_some() {
+            return Err("The peer has not replied yet: the DH ratchet step happens with the first send after its reply".into());
+        }
+        self.ratchet_sending_chain()
+    }
+
+    /// Сгенерировать новую DH пару и вывести из нее sending chain
+    fn ratchet_sending_chain(&mut self) -> Result<(), CryptoStringError> {
+        let (new_dh_private, new_dh_public) = P::generate_kem_keys()
+            .map_err(|e| format!("Failed to generate DH keys: {}", e))?;
+        self.ratchet_sending_chain_with(new_dh_private, new_dh_public)
+    }
+
+    /// Вывести sending chain на заданной DH паре
+    fn ratchet_sending_chain_with(
+        &mut self,
+        new_dh_private: P::KemPrivateKey,
+        new_dh_public: P::KemPublicKey,
+    ) -> Result<(), CryptoStringError> {
+        let remote_dh_public = self
+            .remote_dh_public
+            .as_ref()
+            .ok_or("No remote DH public key")?;
+
+        let dh_send = P::kem_decapsulate(&new_dh_private, remote_dh_public.as_ref())
+            .map_err(|e| format!("DH failed: {}", e))?;
+        let (new_root_key, new_sending_chain) = P::kdf_rk(&self.root_key, &dh_send)
+            .map_err(|e| format!("KDF_RK failed: {}", e))?;
+
+        // Новая цепочка шифрует заголовки ключом, выведенным на предыдущем шаге
+        if let Some(keys) = self.header_keys.as_mut() {
+            let next = Self::next_header_key(&new_root_key)?;
+            let current = std::mem::replace(&mut keys.next_sending, next);
+            if let Some(mut old) = keys.sending.replace(current) {
+                old.zeroize();
+            }
+        }
+
+        self.root_key = new_root_key;
+        self.sending_chain_key = new_sending_chain;
+        self.previous_sending_length = self.sending_chain_length;
+        self.sending_chain_length = 0;
+        self.dh_ratchet_private = Some(new_dh_private);
+        self.dh_ratchet_public = new_dh_public;
+        self.send_ratchet_pending = false;
+
+        Ok(())
     }
 
-    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedRatchetMessage, String> {
+    /// Текущий DH публичный ключ отправки
+    pub fn dh_public_key(&self) -> &[u8] {
+        self.dh_ratchet_public.as_ref()
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<EncryptedRatchetMessage, CryptoStringError> {
+        self.encrypt_with_aad(plaintext, &[])
+    }
+
+    /// Зашифровать с дополнительными данными приложения (AAD).
+    /// Получатель должен передать те же данные в `decrypt_with_aad`
+    pub fn encrypt_with_aad(
+        &mut self,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<EncryptedRatchetMessage, CryptoStringError> {
+        if self.send_ratchet_pending {
+            self.ratchet_sending_chain()?;
+        }
+
+        // Длина ключа задается suite (32 байта для X25519, больше для PQ)
+        let dh_public_key = self.dh_ratchet_public.as_ref().to_vec();
+        let message_number = self.sending_chain_length;
+        let previous_chain_length = self.previous_sending_length;
+
+        let encrypted_header = match &self.header_keys {
+            Some(keys) => {
+                let header_key = keys.sending.as_ref().ok_or("No header key for the sending chain")?;
+                Some(Self::seal_header(header_key, &dh_public_key, message_number, previous_chain_length)?)
+            }
+            None => None,
+        };
+
         let (message_key, next_chain_key) = P::kdf_ck(&self.sending_chain_key)
             .map_err(|e| format!("KDF (CK) failed: {}", e))?;
         self.sending_chain_key = next_chain_key;
-
-        let message_number = self.sending_chain_length;
         self.sending_chain_length += 1;
 
         // Generate nonce - use 12 bytes for ChaCha20Poly1305
         let nonce = P::generate_nonce(12)
             .map_err(|e| format!("Nonce generation failed: {}", e))?;
 
-        let ciphertext = P::aead_encrypt(&message_key, &nonce, plaintext, None)
-            .map_err(|e| format!("Encryption failed: {}", e))?;
+        let associated_data = Self::header_associated_data(
+            &dh_public_key,
+            message_number,
+            previous_chain_length,
+            encrypted_header.as_ref(),
+            aad,
+        );
+        let mut padded = self.padding.pad(plaintext)?;
+        let ciphertext = P::aead_encrypt(&message_key, &nonce, &padded, Some(&associated_data))
+            .map_err(|e| format!("Encryption failed: {}", e));
+        padded.zeroize();
+        let ciphertext = ciphertext?;
 
-        // Convert dh_ratchet_public to [u8; 32]
-        let dh_public_key_vec = self.dh_ratchet_public.as_ref().to_vec();
-        let dh_public_key: [u8; 32] = dh_public_key_vec
-            .try_into()
-            .map_err(|_| "Invalid public key length")?;
+        // В режиме header encryption открытые поля заголовка не заполняются
+        let (dh_public_key, message_number, previous_chain_length) = match encrypted_header {
+            Some(_) => (Vec::new(), 0, 0),
+            None => (dh_public_key, message_number, previous_chain_length),
+        };
 
         Ok(EncryptedRatchetMessage {
             dh_public_key,
             message_number,
             ciphertext,
             nonce,
-            previous_chain_length: self.previous_sending_length,
+            previous_chain_length,
             suite_id: self.suite_id,
+            key_confirmation: self.key_confirmation.clone(),
+            x3dh_ephemeral_key: self.x3dh_ephemeral.clone(),
+            one_time_prekey_id: self.one_time_prekey_id,
+            signed_prekey_id: self.signed_prekey_id,
+            transcript_tag: self.transcript_tag.clone(),
+            encrypted_header,
         })
     }
 
-    pub fn decrypt(&mut self, encrypted: &EncryptedRatchetMessage) -> Result<Vec<u8>, String> {
+    /// Относится ли DH ключ из заголовка к текущей цепочке получения
+    /// (такое сообщение расшифровывается без DH шага)
+    pub fn is_current_receiving_chain(&self, dh_public_key: &[u8]) -> bool {
+        self.remote_dh_public
+            .as_ref()
+            .is_some_and(|key| key.as_ref() == dh_public_key)
+    }
+
+    pub fn decrypt(&mut self, encrypted: &EncryptedRatchetMessage) -> Result<Vec<u8>, DecryptError> {
+        self.decrypt_with_aad(encrypted, &[])
+    }
+
+    /// Расшифровать сообщение, зашифрованное `encrypt_with_aad`
+    pub fn decrypt_with_aad(
+        &mut self,
+        encrypted: &EncryptedRatchetMessage,
+        aad: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
+        // Тег транскрипта не отклоняет сообщение сразу: оно проходит тот же путь
+        // (вывод ключей, AEAD), что и подлинное, и отвергается в конце без изменения
+        // состояния. По времени отказа не видно, какая из проверок не прошла
+        let transcript_ok = match &self.expected_transcript_tag {
+            Some(expected) => encrypted
+                .transcript_tag
+                .as_deref()
+                .is_some_and(|tag| crate::crypto::transcript::tags_match(expected, tag)),
+            None => true,
+        };
+
+        // Дальше сообщение обрабатывается с расшифрованными полями заголовка
+        let opened;
+        let encrypted = match &encrypted.encrypted_header {
+            Some(header) => {
+                opened = self.open_message_header(encrypted, header)?;
+                &opened
+            }
+            None if self.header_keys.is_some() => {
+                return Err("Header encryption is enabled but the message header is not encrypted".into());
+            }
+            None => encrypted,
+        };
+
+        // Новый DH ключ собеседника после наших сообщений - он их получил
+        let peer_replied = !self.is_current_receiving_chain(&encrypted.dh_public_key)
+            && (self.sending_chain_length > 0 || self.previous_sending_length > 0);
+
+        let padding = self.padding;
+        let result = self
+            .ratchet_decrypt(encrypted, aad, transcript_ok)
+            .and_then(|padded| padding.unpad(padded));
+        if result.is_ok() {
+            // Собеседник ответил - он вывел тот же root, подтверждение и заголовок X3DH больше не нужны
+            self.key_confirmation = None;
+            self.x3dh_ephemeral = None;
+            self.one_time_prekey_id = None;
+            self.signed_prekey_id = None;
+            self.expected_transcript_tag = None;
+            if peer_replied {
+                self.transcript_tag = None;
+            }
+        }
+        result
+    }
+
+    /// Расшифровать заголовок ключом текущей или следующей цепочки приема
+    fn open_message_header(
+        &self,
+        encrypted: &EncryptedRatchetMessage,
+        header: &EncryptedHeader,
+    ) -> Result<EncryptedRatchetMessage, DecryptError> {
+        let keys = self
+            .header_keys
+            .as_ref()
+            .ok_or("Header-encrypted message on a session without header keys")?;
+
+        // Пробуются оба ключа, даже если подошел первый: время не выдает, какой из них
+        let candidates = [keys.receiving.as_ref(), Some(&keys.next_receiving)];
+        let opened = candidates
+            .into_iter()
+            .flatten()
+            .map(|key| Self::open_header(key, header))
+            .fold(None, |found, attempt| found.or(attempt));
+        let (dh_public_key, message_number, previous_chain_length) = opened.ok_or_else(|| {
+            DecryptError::AeadFailed("no header key matches the message header".to_string())
+        })?;
+
+        let mut opened = encrypted.clone();
+        opened.dh_public_key = dh_public_key;
+        opened.message_number = message_number;
+        opened.previous_chain_length = previous_chain_length;
+        Ok(opened)
+    }
+
+    /// Расшифровать сообщение; при `transcript_ok == false` вся работа выполняется,
+    /// но результат отклоняется (`TranscriptMismatch`) до изменения состояния
+    fn ratchet_decrypt(
+        &mut self,
+        encrypted: &EncryptedRatchetMessage,
+        aad: &[u8],
+        transcript_ok: bool,
+    ) -> Result<Vec<u8>, DecryptError> {
         eprintln!("[DoubleRatchet] decrypt: msgNum={}, current_recv_chain_len={}, skipped_keys={}",
                   encrypted.message_number, self.receiving_chain_length, self.skipped_message_keys.len());
 
+        // Устаревшие ключи удаляются до поиска и до проверки лимита
+        self.prune_expired_skipped_keys();
+
         // Convert DH public key from message
         let remote_dh_public = Self::bytes_to_kem_public_key(&encrypted.dh_public_key)?;
 
@@ -178,47 +813,120 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
             None => true,
         };
 
-        if needs_ratchet {
+        // Все изменения состояния готовятся локально и применяются только после
+        // успешной AEAD расшифровки: поддельное сообщение не сдвигает цепочку
+        let ratchet = if needs_ratchet {
             eprintln!("[DoubleRatchet] Performing DH ratchet");
-            self.perform_dh_ratchet(&remote_dh_public)?;
-        }
+            Some(self.derive_dh_ratchet(&remote_dh_public)?)
+        } else {
+            None
+        };
 
         // Try to find skipped message key
-        if let Some(key) = self.skipped_message_keys.remove(&encrypted.message_number) {
+        if let Some(key) = self.skipped_message_keys.get(&encrypted.message_number) {
             eprintln!("[DoubleRatchet] Found skipped message key for msgNum={}", encrypted.message_number);
-            return self.decrypt_with_key(&key, encrypted);
+            let plaintext = Self::accept_plaintext(self.decrypt_with_key(key, encrypted, aad), transcript_ok)?;
+            self.skipped_message_keys.remove(&encrypted.message_number);
+            self.skipped_key_timestamps.remove(&encrypted.message_number);
+            if let Some(ratchet) = ratchet {
+                self.commit_dh_ratchet(remote_dh_public, ratchet);
+            }
+            return Ok(plaintext);
+        }
+
+        let (mut chain_key, mut chain_length) = match &ratchet {
+            Some(step) => (step.receiving_chain.clone(), 0),
+            None => (self.receiving_chain_key.clone(), self.receiving_chain_length),
+        };
+
+        if encrypted.message_number < chain_length {
+            return Err(DecryptError::PredatesChain(encrypted.message_number));
+        }
+
+        // DoS protection: не выводим ключи, если лимит будет превышен
+        let to_skip = (encrypted.message_number - chain_length) as usize;
+        if self.skipped_message_keys.len() + to_skip > self.max_skipped_messages as usize {
+            return Err(DecryptError::TooFarAhead {
+                message_number: encrypted.message_number,
+                limit: self.max_skipped_messages,
+            });
         }
 
         // Derive keys until we reach the message number
-        while self.receiving_chain_length <= encrypted.message_number {
-            let (msg_key, next_chain) = P::kdf_ck(&self.receiving_chain_key)
+        let mut skipped = Vec::with_capacity(to_skip);
+        while chain_length < encrypted.message_number {
+            let (msg_key, next_chain) = P::kdf_ck(&chain_key)
                 .map_err(|e| format!("KDF_CK failed: {}", e))?;
+            skipped.push((chain_length, msg_key));
+            chain_key = next_chain;
+            chain_length += 1;
+        }
 
-            if self.receiving_chain_length == encrypted.message_number {
-                self.receiving_chain_key = next_chain;
-                self.receiving_chain_length += 1;
-                return self.decrypt_with_key(&msg_key, encrypted);
-            } else {
-                // Store skipped key
-                self.skipped_message_keys
-                    .insert(self.receiving_chain_length, msg_key);
-                self.receiving_chain_key = next_chain;
-                self.receiving_chain_length += 1;
-
-                // DoS protection
-                if self.skipped_message_keys.len() > MAX_SKIPPED_MESSAGES as usize {
-                    return Err("Too many skipped messages".to_string());
-                }
+        let (msg_key, next_chain) = P::kdf_ck(&chain_key)
+            .map_err(|e| format!("KDF_CK failed: {}", e))?;
+        let plaintext = Self::accept_plaintext(self.decrypt_with_key(&msg_key, encrypted, aad), transcript_ok)?;
+
+        if let Some(ratchet) = ratchet {
+            self.commit_dh_ratchet(remote_dh_public, ratchet);
+        }
+        let stored_at = (self.clock)().max(0) as u64;
+        for (number, key) in skipped {
+            self.skipped_message_keys.insert(number, key);
+            self.skipped_key_timestamps.insert(number, stored_at);
+        }
+        self.receiving_chain_key = next_chain;
+        self.receiving_chain_length = encrypted.message_number + 1;
+
+        Ok(plaintext)
+    }
+
+    /// Сдвинуть receiving chain до сообщения `message_number`, сохранив ключи
+    /// предыдущих сообщений как пропущенные (с тем же лимитом, что и при расшифровке)
+    fn skip_receiving_chain_to(&mut self, message_number: u32) -> Result<(), CryptoStringError> {
+        let to_skip = message_number.saturating_sub(self.receiving_chain_length) as usize;
+        if self.skipped_message_keys.len() + to_skip > self.max_skipped_messages as usize {
+            return Err(DecryptError::TooFarAhead {
+                message_number,
+                limit: self.max_skipped_messages,
             }
+            .into());
         }
 
-        Err("Message key not found".to_string())
+        let stored_at = (self.clock)().max(0) as u64;
+        while self.receiving_chain_length < message_number {
+            let (msg_key, next_chain) = P::kdf_ck(&self.receiving_chain_key)
+                .map_err(|e| format!("KDF_CK failed: {}", e))?;
+            self.skipped_message_keys.insert(self.receiving_chain_length, msg_key);
+            self.skipped_key_timestamps.insert(self.receiving_chain_length, stored_at);
+            self.receiving_chain_key = next_chain;
+            self.receiving_chain_length += 1;
+        }
+        Ok(())
     }
 
-    fn perform_dh_ratchet(&mut self, new_remote_dh: &P::KemPublicKey) -> Result<(), String> {
-        self.previous_sending_length = self.sending_chain_length;
+    /// Удалить ключи пропущенных сообщений старше `MAX_SKIPPED_MESSAGE_AGE_SECONDS`
+    fn prune_expired_skipped_keys(&mut self) {
+        let now = (self.clock)();
+        let expired: Vec<u32> = self
+            .skipped_key_timestamps
+            .iter()
+            .filter(|(_, stored_at)| now.saturating_sub(**stored_at as i64) > MAX_SKIPPED_MESSAGE_AGE_SECONDS)
+            .map(|(number, _)| *number)
+            .collect();
+
+        for number in expired {
+            self.skipped_key_timestamps.remove(&number);
+            if let Some(mut key) = self.skipped_message_keys.remove(&number) {
+                key.zeroize();
+            }
+        }
+    }
 
-        // 1. Get new receiving chain key using old DH private and new remote DH
+    /// Вывести новый root key и receiving chain для DH ключа собеседника, не меняя состояние
+    fn derive_dh_ratchet(
+        &self,
+        new_remote_dh: &P::KemPublicKey,
+    ) -> Result<DhRatchetStep<P::AeadKey>, CryptoStringError> {
         let dh_private = self
             .dh_ratchet_private
             .as_ref()
@@ -226,52 +934,143 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
         let dh_receive = P::kem_decapsulate(dh_private, new_remote_dh.as_ref())
             .map_err(|e| format!("DH failed: {}", e))?;
 
-        let (new_root_key, new_receiving_chain) = P::kdf_rk(&self.root_key, &dh_receive)
+        let (root_key, receiving_chain) = P::kdf_rk(&self.root_key, &dh_receive)
             .map_err(|e| format!("KDF_RK failed: {}", e))?;
-        self.root_key = new_root_key;
-        self.receiving_chain_key = new_receiving_chain;
-        self.receiving_chain_length = 0;
-
-        // 2. Generate new DH pair for sending
-        let (new_dh_private, new_dh_public) = P::generate_kem_keys()
-            .map_err(|e| format!("Failed to generate DH keys: {}", e))?;
+        let next_header_key = match self.header_keys {
+            Some(_) => Some(Self::next_header_key(&root_key)?),
+            None => None,
+        };
 
-        // 3. Get sending chain key using new DH private and new remote DH
-        let dh_send = P::kem_decapsulate(&new_dh_private, new_remote_dh.as_ref())
-            .map_err(|e| format!("DH failed: {}", e))?;
+        Ok(DhRatchetStep {
+            root_key,
+            receiving_chain,
+            next_header_key,
+        })
+    }
 
-        let (new_root_key2, new_sending_chain) = P::kdf_rk(&self.root_key, &dh_send)
-            .map_err(|e| format!("KDF_RK failed: {}", e))?;
-        self.root_key = new_root_key2;
-        self.sending_chain_key = new_sending_chain;
-        self.sending_chain_length = 0;
+    /// Применить DH шаг, подготовленный `derive_dh_ratchet`
+    fn commit_dh_ratchet(&mut self, new_remote_dh: P::KemPublicKey, step: DhRatchetStep<P::AeadKey>) {
+        self.root_key = step.root_key;
+        self.receiving_chain_key = step.receiving_chain;
+        self.receiving_chain_length = 0;
 
-        // 4. Update state
-        self.dh_ratchet_private = Some(new_dh_private);
-        self.dh_ratchet_public = new_dh_public;
-        self.remote_dh_public = Some(new_remote_dh.clone());
+        if let (Some(keys), Some(next)) = (self.header_keys.as_mut(), step.next_header_key) {
+            let current = std::mem::replace(&mut keys.next_receiving, next);
+            if let Some(mut old) = keys.receiving.replace(current) {
+                old.zeroize();
+            }
+        }
 
-        Ok(())
+        // Sending chain на новой DH паре выводится при следующем encrypt
+        self.remote_dh_public = Some(new_remote_dh);
+        self.send_ratchet_pending = true;
     }
 
     fn decrypt_with_key(
         &self,
         message_key: &P::AeadKey,
         encrypted: &EncryptedRatchetMessage,
-    ) -> Result<Vec<u8>, String> {
+        aad: &[u8],
+    ) -> Result<Vec<u8>, DecryptError> {
         eprintln!("[DoubleRatchet] decrypt_with_key: msgNum={}, nonce_len={}, ciphertext_len={}",
                   encrypted.message_number, encrypted.nonce.len(), encrypted.ciphertext.len());
 
-        let result = P::aead_decrypt(message_key, &encrypted.nonce, &encrypted.ciphertext, None)
-            .map_err(|e| format!("Decryption failed: {}", e));
+        let associated_data = Self::header_associated_data(
+            &encrypted.dh_public_key,
+            encrypted.message_number,
+            encrypted.previous_chain_length,
+            encrypted.encrypted_header.as_ref(),
+            aad,
+        );
+        // Без логирования исхода: отдельная ветка на успех и отказ заметна по времени
+        P::aead_decrypt(message_key, &encrypted.nonce, &encrypted.ciphertext, Some(&associated_data))
+            .map_err(|e| DecryptError::AeadFailed(e.to_string()))
+    }
 
-        if result.is_ok() {
-            eprintln!("[DoubleRatchet] ✅ Decryption successful");
-        } else {
-            eprintln!("[DoubleRatchet] ❌ Decryption failed");
+    /// Итог расшифровки, когда обе проверки (AEAD и транскрипт) уже выполнены.
+    /// Несовпадение транскрипта важнее ошибки AEAD; расшифрованный текст
+    /// отвергнутого сообщения обнуляется
+    fn accept_plaintext(
+        decrypted: Result<Vec<u8>, DecryptError>,
+        transcript_ok: bool,
+    ) -> Result<Vec<u8>, DecryptError> {
+        match decrypted {
+            Ok(plaintext) if transcript_ok => Ok(plaintext),
+            Ok(mut plaintext) => {
+                plaintext.zeroize();
+                Err(DecryptError::TranscriptMismatch)
+            }
+            Err(_) if !transcript_ok => Err(DecryptError::TranscriptMismatch),
+            Err(e) => Err(e),
         }
+    }
 
-        result
+    /// AAD сообщения: заголовок (DH ключ, номер, длина предыдущей цепочки, big-endian),
+    /// зашифрованный заголовок (если есть) и данные приложения. Подмена полей заголовка ломает AEAD тег
+    fn header_associated_data(
+        dh_public_key: &[u8],
+        message_number: u32,
+        previous_chain_length: u32,
+        encrypted_header: Option<&EncryptedHeader>,
+        aad: &[u8],
+    ) -> Vec<u8> {
+        let mut out = Vec::with_capacity(dh_public_key.len() + 8 + aad.len());
+        out.extend_from_slice(dh_public_key);
+        out.extend_from_slice(&message_number.to_be_bytes());
+        out.extend_from_slice(&previous_chain_length.to_be_bytes());
+        if let Some(header) = encrypted_header {
+            out.extend_from_slice(&header.nonce);
+            out.extend_from_slice(&header.ciphertext);
+        }
+        out.extend_from_slice(aad);
+        out
+    }
+
+    /// Начальные ключи заголовков из общего секрета X3DH: (инициатора, получателя)
+    fn initial_header_keys(root_key_bytes: &[u8]) -> Result<(P::AeadKey, P::AeadKey), CryptoStringError> {
+        let derive = |info: &[u8]| {
+            P::hkdf_derive_key(b"", root_key_bytes, info, P::aead_key_len())
+                .map(P::aead_key_from_bytes)
+                .map_err(|e| format!("Failed to derive header key: {}", e))
+        };
+        Ok((derive(b"InitiatorHeaderKey")?, derive(b"ResponderHeaderKey")?))
+    }
+
+    /// Ключ заголовка следующей цепочки из root key после DH шага
+    fn next_header_key(root_key: &P::AeadKey) -> Result<P::AeadKey, CryptoStringError> {
+        P::hkdf_derive_key(b"", root_key.as_ref(), b"NextHeaderKey", P::aead_key_len())
+            .map(P::aead_key_from_bytes)
+            .map_err(|e| format!("Failed to derive header key: {}", e).into())
+    }
+
+    /// Зашифровать заголовок: номер и длина предыдущей цепочки (big-endian), затем DH ключ
+    fn seal_header(
+        header_key: &P::AeadKey,
+        dh_public_key: &[u8],
+        message_number: u32,
+        previous_chain_length: u32,
+    ) -> Result<EncryptedHeader, CryptoStringError> {
+        let mut plaintext = Vec::with_capacity(8 + dh_public_key.len());
+        plaintext.extend_from_slice(&message_number.to_be_bytes());
+        plaintext.extend_from_slice(&previous_chain_length.to_be_bytes());
+        plaintext.extend_from_slice(dh_public_key);
+
+        let nonce = P::generate_nonce(HEADER_NONCE_LEN)
+            .map_err(|e| format!("Nonce generation failed: {}", e))?;
+        let ciphertext = P::aead_encrypt(header_key, &nonce, &plaintext, None)
+            .map_err(|e| format!("Header encryption failed: {}", e))?;
+        Ok(EncryptedHeader { nonce, ciphertext })
+    }
+
+    /// Расшифровать заголовок; `None`, если ключ не подходит
+    fn open_header(header_key: &P::AeadKey, header: &EncryptedHeader) -> Option<(Vec<u8>, u32, u32)> {
+        let plaintext = P::aead_decrypt(header_key, &header.nonce, &header.ciphertext, None).ok()?;
+        if plaintext.len() < 8 {
+            return None;
+        }
+        let message_number = u32::from_be_bytes(plaintext[0..4].try_into().ok()?);
+        let previous_chain_length = u32::from_be_bytes(plaintext[4..8].try_into().ok()?);
+        Some((plaintext[8..].to_vec(), message_number, previous_chain_length))
     }
 
     pub fn to_serializable(&self) -> SerializableSession {
@@ -289,19 +1088,61 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
             dh_ratchet_public: self.dh_ratchet_public.as_ref().to_vec(),
             remote_dh_public: self.remote_dh_public.as_ref().map(|k| k.as_ref().to_vec()),
             previous_sending_length: self.previous_sending_length,
+            send_ratchet_pending: self.send_ratchet_pending,
             skipped_message_keys: self
                 .skipped_message_keys
                 .iter()
                 .map(|(k, v)| (*k, v.as_ref().to_vec()))
                 .collect(),
             skipped_key_timestamps: self.skipped_key_timestamps.clone(),
+            max_skipped_messages: self.max_skipped_messages,
             session_id: self.session_id.clone(),
             contact_id: self.contact_id.clone(),
+            remote_identity: self.remote_identity.clone(),
+            key_confirmation: self.key_confirmation.clone(),
+            bind_timestamp: self.bind_timestamp,
+            x3dh_ephemeral: self.x3dh_ephemeral.clone(),
+            one_time_prekey_id: self.one_time_prekey_id,
+            signed_prekey_id: self.signed_prekey_id,
+            transcript_tag: self.transcript_tag.clone(),
+            expected_transcript_tag: self.expected_transcript_tag.clone(),
+            header_keys: self.header_keys.as_ref().map(|keys| SerializableHeaderKeys {
+                sending: keys.sending.as_ref().map(|k| k.as_ref().to_vec()),
+                next_sending: keys.next_sending.as_ref().to_vec(),
+                receiving: keys.receiving.as_ref().map(|k| k.as_ref().to_vec()),
+                next_receiving: keys.next_receiving.as_ref().to_vec(),
+            }),
+            padding: self.padding,
         }
     }
 
-    pub fn from_serializable(data: SerializableSession) -> Result<Self, String> {
-        Ok(Self {
+    pub fn from_serializable(data: SerializableSession) -> Result<Self, CryptoStringError> {
+        Self::from_serializable_with_report(data).map(|(session, _)| session)
+    }
+
+    /// Восстановить сессию, отбросив поврежденные ключи пропущенных сообщений.
+    /// Возвращает сессию и количество отброшенных ключей
+    pub fn from_serializable_with_report(mut data: SerializableSession) -> Result<(Self, usize), CryptoStringError> {
+        let key_len = P::aead_key_len();
+        let before = data.skipped_message_keys.len();
+        data.skipped_message_keys.retain(|number, key| {
+            if key.len() == key_len {
+                return true;
+            }
+            tracing::warn!(
+                message_number = *number,
+                len = key.len(),
+                expected = key_len,
+                "dropping corrupted skipped message key"
+            );
+            false
+        });
+        let dropped = before - data.skipped_message_keys.len();
+        let skipped_keys = &data.skipped_message_keys;
+        data.skipped_key_timestamps
+            .retain(|number, _| skipped_keys.contains_key(number));
+
+        let session = Self {
             suite_id: data.suite_id,
             root_key: Self::bytes_to_aead_key(&data.root_key)?,
             sending_chain_key: Self::bytes_to_aead_key(&data.sending_chain_key)?,
@@ -318,52 +1159,200 @@ impl<P: CryptoProvider> DoubleRatchetSession<P> {
                 .map(|bytes| Self::bytes_to_kem_public_key(&bytes))
                 .transpose()?,
             previous_sending_length: data.previous_sending_length,
+            send_ratchet_pending: data.send_ratchet_pending,
             skipped_message_keys: data
                 .skipped_message_keys
                 .into_iter()
                 .map(|(k, v)| Self::bytes_to_aead_key(&v).map(|key| (k, key)))
                 .collect::<Result<_, _>>()?,
             skipped_key_timestamps: data.skipped_key_timestamps,
+            max_skipped_messages: data.max_skipped_messages,
             session_id: data.session_id,
             contact_id: data.contact_id,
-        })
+            remote_identity: data.remote_identity,
+            key_confirmation: data.key_confirmation,
+            bind_timestamp: data.bind_timestamp,
+            x3dh_ephemeral: data.x3dh_ephemeral,
+            one_time_prekey_id: data.one_time_prekey_id,
+            signed_prekey_id: data.signed_prekey_id,
+            transcript_tag: data.transcript_tag,
+            expected_transcript_tag: data.expected_transcript_tag,
+            header_keys: data
+                .header_keys
+                .map(|keys| -> Result<_, CryptoStringError> {
+                    let optional = |bytes: Option<Vec<u8>>| bytes.map(|b| Self::bytes_to_aead_key(&b)).transpose();
+                    Ok(HeaderKeys {
+                        sending: optional(keys.sending)?,
+                        next_sending: Self::bytes_to_aead_key(&keys.next_sending)?,
+                        receiving: optional(keys.receiving)?,
+                        next_receiving: Self::bytes_to_aead_key(&keys.next_receiving)?,
+                    })
+                })
+                .transpose()?,
+            padding: data.padding,
+            clock: system_clock(),
+        };
+
+        Ok((session, dropped))
     }
 
     // Helper functions to convert between bytes and keys
-    fn bytes_to_aead_key(bytes: &[u8]) -> Result<P::AeadKey, String> {
+    fn bytes_to_aead_key(bytes: &[u8]) -> Result<P::AeadKey, CryptoStringError> {
         // ✅ Use the proper from_bytes method
         Ok(P::aead_key_from_bytes(bytes.to_vec()))
     }
 
-    fn bytes_to_kem_public_key(bytes: &[u8]) -> Result<P::KemPublicKey, String> {
-        // ✅ Use the proper from_bytes method
+    fn bytes_to_kem_public_key(bytes: &[u8]) -> Result<P::KemPublicKey, CryptoStringError> {
+        // Ключ чужого suite отклоняется сразу, а не во время DH
+        let expected = P::kem_public_key_len();
+        if bytes.len() != expected {
+            return Err(format!("DH public key must be {} bytes, got {}", expected, bytes.len()).into());
+        }
         Ok(P::kem_public_key_from_bytes(bytes.to_vec()))
     }
 
-    fn bytes_to_kem_private_key(bytes: &[u8]) -> Result<P::KemPrivateKey, String> {
+    fn bytes_to_kem_private_key(bytes: &[u8]) -> Result<P::KemPrivateKey, CryptoStringError> {
         // ✅ Use the proper from_bytes method
         Ok(P::kem_private_key_from_bytes(bytes.to_vec()))
     }
 }
 
-// Drop is automatic for Vec<u8> - no manual cleanup needed
-// Removed Drop implementation to prevent double-free with UniFFI
-// impl<P: CryptoProvider> Drop for DoubleRatchetSession<P> {
-//     fn drop(&mut self) {
-//         self.dh_ratchet_private.take();
-//     }
-// }
+// Только обнуление буферов на месте: память освобождается обычным Drop полей
+impl<P: CryptoProvider> Drop for DoubleRatchetSession<P> {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+        self.sending_chain_key.zeroize();
+        self.receiving_chain_key.zeroize();
+        if let Some(private_key) = self.dh_ratchet_private.as_mut() {
+            private_key.zeroize();
+        }
+        for key in self.skipped_message_keys.values_mut() {
+            key.zeroize();
+        }
+        self.skipped_message_keys.clear();
+        if let Some(tag) = self.key_confirmation.as_mut() {
+            tag.zeroize();
+        }
+        if let Some(keys) = self.header_keys.as_mut() {
+            keys.zeroize();
+        }
+    }
+}
+
+/// Заголовок сообщения, зашифрованный ключом заголовков цепочки
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct EncryptedHeader {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EncryptedRatchetMessage {
-    pub dh_public_key: [u8; 32],
+    /// DH ratchet ключ отправителя, длина `P::kem_public_key_len()` его suite
+    pub dh_public_key: Vec<u8>,
     pub message_number: u32,
     pub ciphertext: Vec<u8>,
     pub nonce: Vec<u8>,
     pub previous_chain_length: u32,
     pub suite_id: u16,
+    /// Подтверждение ключа X3DH (только в сообщениях инициатора до первого ответа)
+    #[serde(default)]
+    pub key_confirmation: Option<Vec<u8>>,
+    /// Ephemeral ключ X3DH инициатора (только до первого ответа)
+    #[serde(default)]
+    pub x3dh_ephemeral_key: Option<Vec<u8>>,
+    /// Id одноразового prekey получателя, использованного в X3DH
+    #[serde(default)]
+    pub one_time_prekey_id: Option<u32>,
+    /// Id signed prekey получателя, использованного в X3DH: сообщение могло
+    /// быть создано по bundle, выданному до ротации prekey
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
+    /// MAC транскрипта согласования suite (только в первых сообщениях каждой стороны)
+    #[serde(default)]
+    pub transcript_tag: Option<Vec<u8>>,
+    /// Зашифрованный заголовок (режим header encryption). Если задан, открытые
+    /// `dh_public_key`, `message_number` и `previous_chain_length` пустые
+    #[serde(default)]
+    pub encrypted_header: Option<EncryptedHeader>,
 }
 
+impl EncryptedRatchetMessage {
+    /// Зашифрован ли заголовок сообщения
+    pub fn is_header_encrypted(&self) -> bool {
+        self.encrypted_header.is_some()
+    }
+
+    /// Канонический бинарный формат для передачи по сети: `CRWM || версия || MessagePack`.
+    /// Единая точка кодирования для UniFFI, WASM и AppState
+    pub fn to_wire_bytes(&self) -> Result<Vec<u8>, CryptoStringError> {
+        let body = rmp_serde::to_vec_named(self).map_err(|e| format!("Failed to encode message: {}", e))?;
+        let mut bytes = Vec::with_capacity(WIRE_FORMAT_MAGIC.len() + 1 + body.len());
+        bytes.extend_from_slice(WIRE_FORMAT_MAGIC);
+        bytes.push(WIRE_FORMAT_VERSION);
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    /// Разобрать сообщение из канонического бинарного формата. Сообщение без
+    /// префикса - bincode исходного формата (версия 0) от старого клиента
+    pub fn from_wire_bytes(bytes: &[u8]) -> Result<Self, CryptoStringError> {
+        let versioned_error = match bytes.strip_prefix(WIRE_FORMAT_MAGIC).and_then(|rest| rest.split_first()) {
+            Some((&WIRE_FORMAT_VERSION, body)) => match rmp_serde::from_slice(body) {
+                Ok(message) => return Ok(message),
+                Err(e) => Some(format!("Failed to decode message: {}", e)),
+            },
+            Some((version, _)) => Some(format!("Unsupported message format version: {}", version)),
+            None => None,
+        };
+
+        // Версия 0 начинается со случайного DH ключа и может совпасть с префиксом
+        match crate::utils::serialization::from_bytes::<EncryptedRatchetMessageV0>(bytes) {
+            Ok(legacy) => Ok(legacy.into()),
+            Err(e) => Err(versioned_error.unwrap_or(e).into()),
+        }
+    }
+}
+
+/// Префикс версионированного формата сообщения
+const WIRE_FORMAT_MAGIC: &[u8; 4] = b"CRWM";
+/// Версия 1: MessagePack с именованными полями. Новое поле с `#[serde(default)]`
+/// читается и из сообщений, в которых его нет
+const WIRE_FORMAT_VERSION: u8 = 1;
+
+/// Сообщение в исходном формате (bincode, версия 0) - только для приема от старых клиентов
+#[derive(serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct EncryptedRatchetMessageV0 {
+    dh_public_key: [u8; 32],
+    message_number: u32,
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    previous_chain_length: u32,
+    suite_id: u16,
+}
+
+impl From<EncryptedRatchetMessageV0> for EncryptedRatchetMessage {
+    fn from(v0: EncryptedRatchetMessageV0) -> Self {
+        Self {
+            dh_public_key: v0.dh_public_key.to_vec(),
+            message_number: v0.message_number,
+            ciphertext: v0.ciphertext,
+            nonce: v0.nonce,
+            previous_chain_length: v0.previous_chain_length,
+            suite_id: v0.suite_id,
+            key_confirmation: None,
+            x3dh_ephemeral_key: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            encrypted_header: None,
+        }
+    }
+}
+
+/// Состояние сессии для хранения. Кодируется только через `to_bytes`/`from_bytes`;
+/// новые поля добавляются с `#[serde(default)]`
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct SerializableSession {
     suite_id: u16,
@@ -376,8 +1365,1144 @@ pub struct SerializableSession {
     dh_ratchet_public: Vec<u8>,
     remote_dh_public: Option<Vec<u8>>,
     previous_sending_length: u32,
+    #[serde(default)]
+    send_ratchet_pending: bool,
     skipped_message_keys: std::collections::HashMap<u32, Vec<u8>>,
     skipped_key_timestamps: std::collections::HashMap<u32, u64>,
+    #[serde(default = "default_max_skipped_messages")]
+    max_skipped_messages: u32,
     session_id: String,
     contact_id: String,
+    #[serde(default)]
+    remote_identity: Option<Vec<u8>>,
+    #[serde(default)]
+    key_confirmation: Option<Vec<u8>>,
+    #[serde(default)]
+    bind_timestamp: bool,
+    #[serde(default)]
+    x3dh_ephemeral: Option<Vec<u8>>,
+    #[serde(default)]
+    one_time_prekey_id: Option<u32>,
+    #[serde(default)]
+    signed_prekey_id: Option<u32>,
+    #[serde(default)]
+    transcript_tag: Option<Vec<u8>>,
+    #[serde(default)]
+    expected_transcript_tag: Option<Vec<u8>>,
+    #[serde(default)]
+    header_keys: Option<SerializableHeaderKeys>,
+    #[serde(default)]
+    padding: PaddingMode,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializableHeaderKeys {
+    sending: Option<Vec<u8>>,
+    next_sending: Vec<u8>,
+    receiving: Option<Vec<u8>>,
+    next_receiving: Vec<u8>,
+}
+
+fn default_max_skipped_messages() -> u32 {
+    DEFAULT_MAX_SKIPPED_MESSAGES
+}
+
+/// Префикс версионированного формата сохраненной сессии
+const SESSION_FORMAT_MAGIC: &[u8; 4] = b"CRSS";
+/// Версия 1: MessagePack с именованными полями. Поля читаются по именам,
+/// поэтому новое поле с `#[serde(default)]` не ломает уже сохраненные сессии
+const SESSION_FORMAT_VERSION: u8 = 1;
+
+impl SerializableSession {
+    /// Закодировать для хранения: `CRSS || версия || MessagePack`
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CryptoStringError> {
+        let body = rmp_serde::to_vec_named(self).map_err(|e| format!("Failed to serialize session: {}", e))?;
+        let mut bytes = Vec::with_capacity(SESSION_FORMAT_MAGIC.len() + 1 + body.len());
+        bytes.extend_from_slice(SESSION_FORMAT_MAGIC);
+        bytes.push(SESSION_FORMAT_VERSION);
+        bytes.extend_from_slice(&body);
+        Ok(bytes)
+    }
+
+    /// Разобрать сохраненную сессию. Запись без префикса - bincode исходного
+    /// формата (версия 0); она явно переводится в текущую структуру
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoStringError> {
+        let Some(versioned) = bytes.strip_prefix(SESSION_FORMAT_MAGIC) else {
+            let legacy: SerializableSessionV0 = crate::utils::serialization::from_bytes(bytes)?;
+            return Ok(legacy.into());
+        };
+        match versioned.split_first() {
+            Some((&SESSION_FORMAT_VERSION, body)) => {
+                Ok(rmp_serde::from_slice(body).map_err(|e| format!("Failed to deserialize session: {}", e))?)
+            }
+            Some((version, _)) => Err(format!("Unsupported session format version: {}", version).into()),
+            None => Err("Session data is truncated".into()),
+        }
+    }
+}
+
+/// Сессия в исходном формате (bincode, версия 0) - только для миграции
+#[derive(serde::Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct SerializableSessionV0 {
+    suite_id: u16,
+    root_key: Vec<u8>,
+    sending_chain_key: Vec<u8>,
+    sending_chain_length: u32,
+    receiving_chain_key: Vec<u8>,
+    receiving_chain_length: u32,
+    dh_ratchet_private: Option<Vec<u8>>,
+    dh_ratchet_public: Vec<u8>,
+    remote_dh_public: Option<Vec<u8>>,
+    previous_sending_length: u32,
+    skipped_message_keys: std::collections::HashMap<u32, Vec<u8>>,
+    skipped_key_timestamps: std::collections::HashMap<u32, u64>,
+    session_id: String,
+    contact_id: String,
+}
+
+impl From<SerializableSessionV0> for SerializableSession {
+    /// Сессия версии 0 уже вывела sending chain и не знает о последующих
+    /// возможностях: они остаются выключенными
+    fn from(v0: SerializableSessionV0) -> Self {
+        Self {
+            suite_id: v0.suite_id,
+            root_key: v0.root_key,
+            sending_chain_key: v0.sending_chain_key,
+            sending_chain_length: v0.sending_chain_length,
+            receiving_chain_key: v0.receiving_chain_key,
+            receiving_chain_length: v0.receiving_chain_length,
+            dh_ratchet_private: v0.dh_ratchet_private,
+            dh_ratchet_public: v0.dh_ratchet_public,
+            remote_dh_public: v0.remote_dh_public,
+            previous_sending_length: v0.previous_sending_length,
+            send_ratchet_pending: false,
+            skipped_message_keys: v0.skipped_message_keys,
+            skipped_key_timestamps: v0.skipped_key_timestamps,
+            max_skipped_messages: DEFAULT_MAX_SKIPPED_MESSAGES,
+            session_id: v0.session_id,
+            contact_id: v0.contact_id,
+            remote_identity: None,
+            key_confirmation: None,
+            bind_timestamp: false,
+            x3dh_ephemeral: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            expected_transcript_tag: None,
+            header_keys: None,
+            padding: PaddingMode::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+    use crate::error::CryptoError;
+
+    type Session = DoubleRatchetSession<ClassicSuiteProvider>;
+
+    /// Alice отправила первое сообщение, Боб его расшифровал
+    fn established_pair() -> (Session, Session) {
+        let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let root_key = [5u8; 32];
+
+        let mut alice = Session::new_x3dh_session(
+            1,
+            &root_key,
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap();
+        let first = alice.encrypt(b"hello").unwrap();
+
+        let mut bob =
+            Session::new_receiving_session(1, &root_key, &bob_identity, &first, "alice".to_string())
+                .unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hello");
+
+        (alice, bob)
+    }
+
+    /// Пара сессий с шифрованием заголовков, Боб уже получил первое сообщение
+    fn header_encrypted_pair() -> (Session, Session) {
+        let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let root_key = [6u8; 32];
+
+        let mut alice = Session::new_x3dh_session(
+            1,
+            &root_key,
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap()
+        .with_header_encryption(&root_key)
+        .unwrap();
+        let first = alice.encrypt(b"hello").unwrap();
+
+        let mut bob =
+            Session::new_receiving_session(1, &root_key, &bob_identity, &first, "alice".to_string())
+                .unwrap();
+        assert!(bob.header_encryption());
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hello");
+
+        (alice, bob)
+    }
+
+    #[test]
+    fn test_header_encryption_hides_header_fields() {
+        let (mut alice, mut bob) = header_encrypted_pair();
+
+        let skipped = alice.encrypt(b"skipped").unwrap();
+        let msg = alice.encrypt(b"third").unwrap();
+        for sent in [&skipped, &msg] {
+            assert!(sent.is_header_encrypted());
+            let wire = sent.to_wire_bytes().unwrap();
+            let received = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+            assert_eq!(received.message_number, 0);
+            assert_eq!(received.previous_chain_length, 0);
+            assert!(received.dh_public_key.is_empty());
+            assert!(!wire
+                .windows(alice.dh_public_key().len())
+                .any(|window| window == alice.dh_public_key()));
+        }
+
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"third");
+        assert_eq!(bob.receiving_chain_length(), 3);
+        assert_eq!(bob.decrypt(&skipped).unwrap(), b"skipped");
+
+        // Заголовок защищен AEAD: подмена не сдвигает цепочку
+        let next = alice.encrypt(b"next").unwrap();
+        let mut tampered = next.clone();
+        tampered.encrypted_header.as_mut().unwrap().ciphertext[0] ^= 0xff;
+        assert!(matches!(bob.decrypt(&tampered), Err(DecryptError::AeadFailed(_))));
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_receiving_session_requires_first_message() {
+        let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let root_key = [7u8; 32];
+        let mut alice = Session::new_x3dh_session(
+            1,
+            &root_key,
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap();
+        let messages: Vec<_> = (0..3u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+
+        // Настоящее первое сообщение принимают оба конструктора
+        for session in [
+            Session::new_receiving_session(1, &root_key, &bob_identity, &messages[0], "alice".to_string()),
+            Session::new_receiving_session_from(1, &root_key, &bob_identity, &messages[0], "alice".to_string()),
+        ] {
+            let mut bob = session.unwrap();
+            assert_eq!(bob.skipped_key_count(), 0);
+            assert_eq!(bob.decrypt(&messages[0]).unwrap(), vec![0]);
+        }
+
+        // Третье сообщение - не первое: строгий конструктор отказывает,
+        // new_receiving_session_from сдвигает цепочку и сохраняет ключи 0 и 1
+        let strict =
+            Session::new_receiving_session(1, &root_key, &bob_identity, &messages[2], "alice".to_string());
+        assert!(strict.err().unwrap().0.contains("not the first message"));
+
+        let mut bob =
+            Session::new_receiving_session_from(1, &root_key, &bob_identity, &messages[2], "alice".to_string())
+                .unwrap();
+        assert_eq!(bob.receiving_chain_length(), 2);
+        assert_eq!(bob.skipped_key_count(), 2);
+        assert_eq!(bob.decrypt(&messages[2]).unwrap(), vec![2]);
+        assert_eq!(bob.decrypt(&messages[0]).unwrap(), vec![0]);
+        assert_eq!(bob.decrypt(&messages[1]).unwrap(), vec![1]);
+
+        // Сообщение из следующей цепочки инициатора не принимает ни один конструктор
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        let later_chain = alice.encrypt(b"later").unwrap();
+        assert_eq!(later_chain.previous_chain_length, 3);
+        assert!(Session::new_receiving_session(1, &root_key, &bob_identity, &later_chain, "alice".to_string()).is_err());
+        assert!(
+            Session::new_receiving_session_from(1, &root_key, &bob_identity, &later_chain, "alice".to_string())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_header_encryption_across_dh_ratchet() {
+        let (mut alice, mut bob) = header_encrypted_pair();
+
+        // Ответ Боба на новой DH паре - Алиса находит цепочку по следующему ключу заголовков
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+        let second = bob.encrypt(b"second reply").unwrap();
+        assert_eq!(alice.decrypt(&second).unwrap(), b"second reply");
+
+        // Принудительный шаг и восстановление из сериализации сохраняют режим
+        alice.force_dh_ratchet().unwrap();
+        let rotated = alice.encrypt(b"rotated").unwrap();
+        let bytes = bob.to_serializable().to_bytes().unwrap();
+        let mut bob = Session::from_serializable(SerializableSession::from_bytes(&bytes).unwrap()).unwrap();
+        assert!(bob.header_encryption());
+        assert_eq!(bob.decrypt(&rotated).unwrap(), b"rotated");
+
+        // Сообщение с открытым заголовком в сессии с шифрованием заголовков отклоняется
+        let (mut plain_alice, _) = established_pair();
+        let plain = plain_alice.encrypt(b"plain").unwrap();
+        assert!(matches!(bob.decrypt(&plain), Err(DecryptError::Other(_))));
+    }
+
+    #[test]
+    fn test_force_dh_ratchet_before_peer_replies() {
+        let (mut alice, mut bob) = established_pair();
+        let old_public = alice.dh_public_key().to_vec();
+
+        // Ratchet ключа Боба Алиса еще не знает: шаг отклоняется, цепочка прежняя
+        assert!(alice.force_dh_ratchet().is_err());
+        let msg = alice.encrypt(b"still first chain").unwrap();
+        assert_eq!(msg.dh_public_key, old_public);
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"still first chain");
+
+        // После ответа первая же отправка идет на новой DH паре
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        let next = alice.encrypt(b"next").unwrap();
+        assert_ne!(next.dh_public_key, old_public);
+        assert_eq!(next.message_number, 0);
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_responder_ratchet_key_is_not_identity() {
+        let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let root_key = [8u8; 32];
+        let mut alice =
+            Session::new_x3dh_session(1, &root_key, &bob_identity_public, &alice_identity, "bob".to_string())
+                .unwrap();
+        let first = alice.encrypt(b"hello").unwrap();
+        let bob =
+            Session::new_receiving_session(1, &root_key, &bob_identity, &first, "alice".to_string()).unwrap();
+
+        // Сериализованная сессия ответчика не содержит приватный identity ключ
+        assert_ne!(bob.dh_public_key(), bob_identity_public.as_slice());
+        let bytes = bob.to_serializable().to_bytes().unwrap();
+        let identity_private: &[u8] = bob_identity.as_ref();
+        assert!(!bytes.windows(identity_private.len()).any(|window| window == identity_private));
+    }
+
+    #[test]
+    fn test_force_dh_ratchet_repeatedly() {
+        let (mut alice, mut bob) = established_pair();
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+
+        let mut seen = vec![reply.dh_public_key.clone()];
+        for i in 0..3u8 {
+            alice.force_dh_ratchet().unwrap();
+            let msg = alice.encrypt(&[i]).unwrap();
+            assert!(!seen.contains(&msg.dh_public_key));
+            seen.push(msg.dh_public_key.clone());
+            assert_eq!(bob.decrypt(&msg).unwrap(), vec![i]);
+        }
+
+        // Состояние переживает сериализацию
+        let mut bob = Session::from_serializable(bob.to_serializable()).unwrap();
+        let reply = bob.encrypt(b"still here").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"still here");
+    }
+
+    #[test]
+    fn test_legacy_session_format_migrates() {
+        let (mut alice, bob) = established_pair();
+        let in_flight = alice.encrypt(b"sent before upgrade").unwrap();
+
+        // Сессия, сохраненная исходным форматом (bincode без префикса версии)
+        let data = bob.to_serializable();
+        let legacy = SerializableSessionV0 {
+            suite_id: data.suite_id,
+            root_key: data.root_key.clone(),
+            sending_chain_key: data.sending_chain_key.clone(),
+            sending_chain_length: data.sending_chain_length,
+            receiving_chain_key: data.receiving_chain_key.clone(),
+            receiving_chain_length: data.receiving_chain_length,
+            dh_ratchet_private: data.dh_ratchet_private.clone(),
+            dh_ratchet_public: data.dh_ratchet_public.clone(),
+            remote_dh_public: data.remote_dh_public.clone(),
+            previous_sending_length: data.previous_sending_length,
+            skipped_message_keys: data.skipped_message_keys.clone(),
+            skipped_key_timestamps: data.skipped_key_timestamps.clone(),
+            session_id: data.session_id.clone(),
+            contact_id: data.contact_id.clone(),
+        };
+        let bytes = crate::utils::serialization::to_bytes(&legacy).unwrap();
+
+        let mut restored = Session::from_serializable(SerializableSession::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(restored.session_id(), bob.session_id());
+        assert_eq!(restored.max_skipped_messages(), DEFAULT_MAX_SKIPPED_MESSAGES);
+        assert_eq!(restored.decrypt(&in_flight).unwrap(), b"sent before upgrade");
+        let reply = restored.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+
+        // Текущий формат помечен версией; неизвестная версия отклоняется явно
+        let mut current = bob.to_serializable().to_bytes().unwrap();
+        assert!(current.starts_with(SESSION_FORMAT_MAGIC));
+        current[SESSION_FORMAT_MAGIC.len()] = SESSION_FORMAT_VERSION + 1;
+        let err = SerializableSession::from_bytes(&current).err().unwrap();
+        assert!(err.0.contains("Unsupported session format version"), "{}", err.0);
+    }
+
+    #[test]
+    fn test_serialized_session_roundtrip_decrypts() {
+        let (mut alice, bob) = established_pair();
+        let in_flight = alice.encrypt(b"sent before save").unwrap();
+
+        // Ключи восстанавливаются через CryptoProvider, а не побайтовым копированием
+        let bytes = bob.to_serializable().to_bytes().unwrap();
+        let data = SerializableSession::from_bytes(&bytes).unwrap();
+        let mut bob = Session::from_serializable(data).unwrap();
+
+        assert_eq!(bob.decrypt(&in_flight).unwrap(), b"sent before save");
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn test_tampered_message_does_not_advance_chain() {
+        let (mut alice, mut bob) = established_pair();
+        let tamper = |msg: &EncryptedRatchetMessage| {
+            let mut tampered = msg.clone();
+            tampered.ciphertext[0] ^= 0xff;
+            tampered
+        };
+
+        // Сообщение в текущей цепочке, перед ним одно пропущенное
+        let skipped = alice.encrypt(b"skipped").unwrap();
+        let msg = alice.encrypt(b"genuine").unwrap();
+        assert!(matches!(
+            bob.decrypt(&tamper(&msg)),
+            Err(DecryptError::AeadFailed(_))
+        ));
+        assert_eq!(bob.receiving_chain_length(), 1);
+        assert_eq!(bob.skipped_key_count(), 0);
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"genuine");
+
+        // Поддельное сообщение с ранее пропущенным ключом не сжигает ключ
+        assert!(bob.decrypt(&tamper(&skipped)).is_err());
+        assert_eq!(bob.decrypt(&skipped).unwrap(), b"skipped");
+
+        // Поддельное сообщение новой DH цепочки не выполняет DH шаг
+        let reply = bob.encrypt(b"reply").unwrap();
+        assert!(alice.decrypt(&tamper(&reply)).is_err());
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"reply");
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+    }
+
+    /// Best-effort проверка по времени: поддельное сообщение (испорченный AEAD тег
+    /// или чужой тег транскрипта) проходит тот же путь вывода ключей, что и подлинное.
+    /// Сравниваются медианы с широким порогом - тест ловит только явный ранний выход,
+    /// например отказ до вывода ключей цепочки
+    #[test]
+    fn test_decrypt_failure_timing_matches_success() {
+        use std::time::{Duration, Instant};
+        const SAMPLES: usize = 41;
+
+        let (mut alice, bob) = established_pair();
+        // Сообщение далеко впереди: вывод ключей цепочки заметен на фоне AEAD
+        for _ in 0..200 {
+            alice.encrypt(b"skipped").unwrap();
+        }
+        let genuine = alice.encrypt(b"genuine").unwrap();
+        let mut tampered = genuine.clone();
+        *tampered.ciphertext.last_mut().unwrap() ^= 0x01;
+
+        let median = |message: &EncryptedRatchetMessage, expected_tag: Option<Vec<u8>>| {
+            let mut samples: Vec<Duration> = (0..SAMPLES)
+                .map(|_| {
+                    let mut session = Session::from_serializable(bob.to_serializable()).unwrap();
+                    session.expected_transcript_tag = expected_tag.clone();
+                    let started = Instant::now();
+                    let result = session.decrypt(message);
+                    let elapsed = started.elapsed();
+                    assert_eq!(result.is_ok(), expected_tag.is_none() && message.ciphertext == genuine.ciphertext);
+                    elapsed
+                })
+                .collect();
+            samples.sort();
+            samples[SAMPLES / 2]
+        };
+
+        median(&genuine, None);
+        let success = median(&genuine, None);
+        let forged_tag = median(&tampered, None);
+        let wrong_transcript = median(&genuine, Some(vec![7u8; 32]));
+        for (name, failure) in [("AEAD tag", forged_tag), ("transcript", wrong_transcript)] {
+            assert!(
+                failure * 4 >= success && success * 4 >= failure,
+                "{} failure took {:?}, success {:?}",
+                name,
+                failure,
+                success
+            );
+        }
+    }
+
+    #[test]
+    fn test_skipped_keys_expire_by_age() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let (mut alice, bob) = established_pair();
+        let now = Arc::new(AtomicI64::new(1_000));
+        let clock_now = now.clone();
+        let mut bob = bob.with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+
+        let skipped = alice.encrypt(b"skipped").unwrap();
+        let msg = alice.encrypt(b"later").unwrap();
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"later");
+        assert_eq!(bob.skipped_key_count(), 1);
+
+        // Через 7 дней ключ еще хранится
+        now.store(1_000 + MAX_SKIPPED_MESSAGE_AGE_SECONDS, Ordering::SeqCst);
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+        assert_eq!(bob.skipped_key_count(), 1);
+
+        // Окно истекло - ключ удален, старое сообщение больше не расшифровать
+        now.store(1_000 + MAX_SKIPPED_MESSAGE_AGE_SECONDS + 1, Ordering::SeqCst);
+        assert_eq!(
+            bob.decrypt(&skipped),
+            Err(DecryptError::PredatesChain(skipped.message_number))
+        );
+        assert_eq!(bob.skipped_key_count(), 0);
+    }
+
+    #[test]
+    fn test_header_fields_authenticated() {
+        let (mut alice, mut bob) = established_pair();
+        let msg = alice.encrypt(b"genuine").unwrap();
+
+        let mut renumbered = msg.clone();
+        renumbered.message_number ^= 0b10;
+        assert!(matches!(
+            bob.decrypt(&renumbered),
+            Err(DecryptError::AeadFailed(_))
+        ));
+
+        // Длина предыдущей цепочки не участвует в выводе ключа - ее защищает только AAD
+        let mut relengthed = msg.clone();
+        relengthed.previous_chain_length ^= 1;
+        assert!(matches!(
+            bob.decrypt(&relengthed),
+            Err(DecryptError::AeadFailed(_))
+        ));
+
+        assert_eq!(bob.receiving_chain_length(), 1);
+        assert_eq!(bob.decrypt(&msg).unwrap(), b"genuine");
+    }
+
+    #[test]
+    fn test_restored_session_keeps_chain_keys() {
+        let (mut alice, mut bob) = established_pair();
+        alice.encrypt(b"before save").unwrap();
+
+        let saved = alice.to_serializable();
+        let mut restored = Session::from_serializable(alice.to_serializable()).unwrap();
+        let roundtrip = restored.to_serializable();
+
+        assert_ne!(saved.root_key, vec![0u8; 32]);
+        assert_eq!(roundtrip.root_key, saved.root_key);
+        assert_eq!(roundtrip.sending_chain_key, saved.sending_chain_key);
+        assert_eq!(roundtrip.receiving_chain_key, saved.receiving_chain_key);
+
+        // Исходная и восстановленная сессии шифруют одним ключом
+        let original = alice.encrypt(b"next").unwrap();
+        let from_restored = restored.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&from_restored).unwrap(), b"next");
+        assert_eq!(original.message_number, from_restored.message_number);
+    }
+
+    #[test]
+    fn test_per_session_skipped_message_limit() {
+        let deliver_fifth = |limit: u32| {
+            let (mut alice, bob) = established_pair();
+            let mut bob = bob.with_max_skipped_messages(limit);
+            let mut last = None;
+            for i in 1..=4u8 {
+                last = Some(alice.encrypt(&[i]).unwrap());
+            }
+            // Пропущены сообщения 1..=3
+            bob.decrypt(&last.unwrap()).map(|_| bob)
+        };
+
+        assert!(deliver_fifth(2).is_err());
+        let bob = deliver_fifth(5).unwrap();
+        assert_eq!(bob.max_skipped_messages(), 5);
+
+        // Лимит сохраняется при сериализации
+        let restored = Session::from_serializable(bob.to_serializable()).unwrap();
+        assert_eq!(restored.max_skipped_messages(), 5);
+
+        // Сохраненные сессии без этого поля получают лимит по умолчанию
+        let (_, fresh) = established_pair();
+        let mut legacy = serde_json::to_value(fresh.with_max_skipped_messages(5).to_serializable()).unwrap();
+        legacy.as_object_mut().unwrap().remove("max_skipped_messages");
+        let mut bytes = SESSION_FORMAT_MAGIC.to_vec();
+        bytes.push(SESSION_FORMAT_VERSION);
+        bytes.extend(rmp_serde::to_vec_named(&legacy).unwrap());
+        let restored = Session::from_serializable(SerializableSession::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(restored.max_skipped_messages(), DEFAULT_MAX_SKIPPED_MESSAGES);
+    }
+
+    #[test]
+    fn test_decrypt_error_variants() {
+        let (mut alice, bob) = established_pair();
+        let mut bob = bob.with_max_skipped_messages(2);
+
+        // Повтор уже расшифрованного сообщения
+        let first = alice.encrypt(b"one").unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"one");
+        assert_eq!(
+            bob.decrypt(&first),
+            Err(DecryptError::PredatesChain(first.message_number))
+        );
+
+        // Сообщение за пределами лимита пропусков
+        let ahead: Vec<_> = (0..4).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        assert_eq!(
+            bob.decrypt(&ahead[3]),
+            Err(DecryptError::TooFarAhead {
+                message_number: ahead[3].message_number,
+                limit: 2,
+            })
+        );
+        // Отклоненное сообщение не сдвигает цепочку
+        assert_eq!(bob.decrypt(&ahead[0]).unwrap(), vec![0]);
+
+        // Поврежденный шифротекст
+        let mut tampered = ahead[1].clone();
+        tampered.ciphertext[0] ^= 0xff;
+        assert!(matches!(
+            bob.decrypt(&tampered),
+            Err(DecryptError::AeadFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_drops_corrupted_skipped_keys() {
+        let (mut alice, mut bob) = established_pair();
+        let delayed: Vec<_> = (1..=3u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        let latest = alice.encrypt(b"latest").unwrap();
+        assert_eq!(bob.decrypt(&latest).unwrap(), b"latest");
+
+        let mut data = bob.to_serializable();
+        assert_eq!(data.skipped_message_keys.len(), 3);
+        data.skipped_message_keys
+            .insert(delayed[0].message_number, vec![0u8; 5]);
+
+        let (mut bob, dropped) = Session::from_serializable_with_report(data).unwrap();
+        assert_eq!(dropped, 1);
+
+        // Остальные пропущенные сообщения и новые сообщения расшифровываются
+        assert_eq!(bob.decrypt(&delayed[1]).unwrap(), vec![2]);
+        assert_eq!(bob.decrypt(&delayed[2]).unwrap(), vec![3]);
+        assert_eq!(
+            bob.decrypt(&delayed[0]),
+            Err(DecryptError::PredatesChain(delayed[0].message_number))
+        );
+        let next = alice.encrypt(b"next").unwrap();
+        assert_eq!(bob.decrypt(&next).unwrap(), b"next");
+    }
+
+    #[test]
+    fn test_wire_format_versions() {
+        let (mut alice, _) = established_pair();
+        let message = alice.encrypt(b"versioned").unwrap();
+
+        let wire = message.to_wire_bytes().unwrap();
+        assert!(wire.starts_with(WIRE_FORMAT_MAGIC));
+        let decoded = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        assert_eq!(decoded.dh_public_key, message.dh_public_key);
+        assert_eq!(decoded.ciphertext, message.ciphertext);
+        assert_eq!(decoded.key_confirmation, message.key_confirmation);
+
+        // Сообщение версии 1 без полей, добавленных позже, читается с их значениями по умолчанию
+        let mut older = serde_json::to_value(&message).unwrap();
+        older.as_object_mut().unwrap().remove("transcript_tag");
+        older.as_object_mut().unwrap().remove("encrypted_header");
+        let mut wire = WIRE_FORMAT_MAGIC.to_vec();
+        wire.push(WIRE_FORMAT_VERSION);
+        wire.extend(rmp_serde::to_vec_named(&older).unwrap());
+        let decoded = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        assert!(decoded.transcript_tag.is_none() && decoded.encrypted_header.is_none());
+        assert_eq!(decoded.nonce, message.nonce);
+
+        // Исходный формат (bincode, DH ключ фиксированной длины) от старого клиента
+        let legacy = EncryptedRatchetMessageV0 {
+            dh_public_key: message.dh_public_key.clone().try_into().unwrap(),
+            message_number: 4,
+            ciphertext: vec![1, 2, 3],
+            nonce: vec![7; 12],
+            previous_chain_length: 2,
+            suite_id: 1,
+        };
+        let wire = crate::utils::serialization::to_bytes(&legacy).unwrap();
+        let decoded = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        assert_eq!(decoded.dh_public_key, message.dh_public_key);
+        assert_eq!((decoded.message_number, decoded.previous_chain_length), (4, 2));
+        assert!(decoded.key_confirmation.is_none());
+
+        // Неизвестная версия отклоняется
+        let mut wire = message.to_wire_bytes().unwrap();
+        wire[WIRE_FORMAT_MAGIC.len()] = WIRE_FORMAT_VERSION + 1;
+        let err = EncryptedRatchetMessage::from_wire_bytes(&wire).err().unwrap();
+        assert!(err.0.contains("Unsupported message format version"), "{}", err.0);
+    }
+
+    #[test]
+    fn test_key_confirmation() {
+        let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+
+        let mut alice = Session::new_x3dh_session(
+            1,
+            &[5u8; 32],
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap();
+        let first = alice.encrypt(b"hello").unwrap();
+        assert!(first.key_confirmation.is_some());
+
+        // Тот же root - сессия принимается
+        let mut bob =
+            Session::new_receiving_session(1, &[5u8; 32], &bob_identity, &first, "alice".to_string())
+                .unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hello");
+
+        // Другой root - ошибка до создания сессии
+        let err = Session::new_receiving_session(
+            1,
+            &[6u8; 32],
+            &bob_identity,
+            &first,
+            "alice".to_string(),
+        )
+        .err()
+        .unwrap();
+        assert!(err.message().contains("key confirmation failed"), "{}", err);
+
+        // Сообщение, с которого сняли тег, тоже не принимается
+        let mut stripped = first.clone();
+        stripped.key_confirmation = None;
+        let err = Session::new_receiving_session(1, &[5u8; 32], &bob_identity, &stripped, "alice".to_string())
+            .err()
+            .unwrap();
+        assert!(err.message().contains("no X3DH key confirmation"), "{}", err);
+
+        // После ответа собеседника тег больше не прикладывается
+        let reply = bob.encrypt(b"hi").unwrap();
+        assert!(reply.key_confirmation.is_none());
+        alice.decrypt(&reply).unwrap();
+        assert!(alice.encrypt(b"again").unwrap().key_confirmation.is_none());
+    }
+
+    std::thread_local! {
+        static WIPED_KEYS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    /// Ключ, который считает свои обнуления (только непустые буферы)
+    #[derive(Debug, Clone, Default)]
+    struct RecordingKey(Vec<u8>);
+
+    impl AsRef<[u8]> for RecordingKey {
+        fn as_ref(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    impl Zeroize for RecordingKey {
+        fn zeroize(&mut self) {
+            if !self.0.is_empty() {
+                WIPED_KEYS.with(|count| count.set(count.get() + 1));
+            }
+            self.0.zeroize();
+        }
+    }
+
+    /// Классический набор с секретными ключами типа `RecordingKey`
+    struct RecordingSuite;
+
+    fn classic(key: &RecordingKey) -> crate::crypto::classic_suite::SecretBytes {
+        key.0.clone().into()
+    }
+
+    fn recording(key: crate::crypto::classic_suite::SecretBytes) -> RecordingKey {
+        RecordingKey(key.to_vec())
+    }
+
+    impl CryptoProvider for RecordingSuite {
+        type KemPublicKey = Vec<u8>;
+        type KemPrivateKey = RecordingKey;
+        type SignaturePublicKey = Vec<u8>;
+        type SignaturePrivateKey = RecordingKey;
+        type AeadKey = RecordingKey;
+
+        fn generate_kem_keys() -> Result<(RecordingKey, Vec<u8>), CryptoError> {
+            let (private_key, public_key) = ClassicSuiteProvider::generate_kem_keys()?;
+            Ok((recording(private_key), public_key))
+        }
+
+        fn from_private_key_to_public_key(private_key: &RecordingKey) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::from_private_key_to_public_key(&classic(private_key))
+        }
+
+        fn kem_public_key_len() -> usize {
+            ClassicSuiteProvider::kem_public_key_len()
+        }
+
+        fn aead_key_len() -> usize {
+            ClassicSuiteProvider::aead_key_len()
+        }
+
+        fn kem_public_key_from_bytes(bytes: Vec<u8>) -> Vec<u8> {
+            bytes
+        }
+
+        fn kem_private_key_from_bytes(bytes: Vec<u8>) -> RecordingKey {
+            RecordingKey(bytes)
+        }
+
+        fn aead_key_from_bytes(bytes: Vec<u8>) -> RecordingKey {
+            RecordingKey(bytes)
+        }
+
+        fn signature_public_key_from_bytes(bytes: Vec<u8>) -> Vec<u8> {
+            bytes
+        }
+
+        fn signature_private_key_from_bytes(bytes: Vec<u8>) -> RecordingKey {
+            RecordingKey(bytes)
+        }
+
+        fn generate_signature_keys() -> Result<(RecordingKey, Vec<u8>), CryptoError> {
+            let (private_key, public_key) = ClassicSuiteProvider::generate_signature_keys()?;
+            Ok((recording(private_key), public_key))
+        }
+
+        fn sign(private_key: &RecordingKey, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::sign(&classic(private_key), message)
+        }
+
+        fn verify(public_key: &Vec<u8>, message: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+            ClassicSuiteProvider::verify(public_key, message, signature)
+        }
+
+        fn kem_encapsulate(public_key: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+            ClassicSuiteProvider::kem_encapsulate(public_key)
+        }
+
+        fn kem_decapsulate(private_key: &RecordingKey, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::kem_decapsulate(&classic(private_key), ciphertext)
+        }
+
+        fn aead_encrypt(
+            key: &RecordingKey,
+            nonce: &[u8],
+            plaintext: &[u8],
+            associated_data: Option<&[u8]>,
+        ) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::aead_encrypt(&classic(key), nonce, plaintext, associated_data)
+        }
+
+        fn aead_decrypt(
+            key: &RecordingKey,
+            nonce: &[u8],
+            ciphertext: &[u8],
+            associated_data: Option<&[u8]>,
+        ) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::aead_decrypt(&classic(key), nonce, ciphertext, associated_data)
+        }
+
+        fn hkdf_derive_key(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::hkdf_derive_key(salt, ikm, info, len)
+        }
+
+        fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::mac(key, data)
+        }
+
+        fn kdf_rk(root_key: &RecordingKey, dh_output: &[u8]) -> Result<(RecordingKey, RecordingKey), CryptoError> {
+            let (root, chain) = ClassicSuiteProvider::kdf_rk(&classic(root_key), dh_output)?;
+            Ok((recording(root), recording(chain)))
+        }
+
+        fn kdf_ck(chain_key: &RecordingKey) -> Result<(RecordingKey, RecordingKey), CryptoError> {
+            let (message_key, next_chain) = ClassicSuiteProvider::kdf_ck(&classic(chain_key))?;
+            Ok((recording(message_key), recording(next_chain)))
+        }
+
+        fn generate_nonce(len: usize) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::generate_nonce(len)
+        }
+
+        fn suite_id() -> u16 {
+            ClassicSuiteProvider::suite_id()
+        }
+    }
+
+    #[test]
+    fn test_drop_zeroizes_session_keys() {
+        type RecordingSession = DoubleRatchetSession<RecordingSuite>;
+
+        let (alice_identity, _) = RecordingSuite::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = RecordingSuite::generate_kem_keys().unwrap();
+        let mut alice = RecordingSession::new_x3dh_session(
+            1,
+            &[5u8; 32],
+            &bob_identity_public,
+            &alice_identity,
+            "bob".to_string(),
+        )
+        .unwrap();
+
+        // Боб получает только третье сообщение - два ключа остаются пропущенными
+        let messages: Vec<_> = (0..3u8).map(|i| alice.encrypt(&[i]).unwrap()).collect();
+        let mut bob = RecordingSession::new_receiving_session(
+            1,
+            &[5u8; 32],
+            &bob_identity,
+            &messages[0],
+            "alice".to_string(),
+        )
+        .unwrap();
+        assert_eq!(bob.decrypt(&messages[2]).unwrap(), vec![2]);
+        let skipped = bob.skipped_key_count();
+        assert_eq!(skipped, 2);
+
+        let before = WIPED_KEYS.with(|count| count.get());
+        drop(bob);
+        let wiped = WIPED_KEYS.with(|count| count.get()) - before;
+
+        // root + receiving chain + DH private + пропущенные ключи
+        assert!(wiped >= 3 + skipped, "wiped {} keys", wiped);
+    }
+
+    /// Классический набор с 48-байтовыми публичными ключами, как у suite
+    /// с более длинным DH/KEM ключом: X25519 ключ плюс фиксированный суффикс
+    struct WideKeySuite;
+
+    const WIDE_KEY_SUFFIX: [u8; 16] = [0xA5; 16];
+
+    type ClassicKey = crate::crypto::classic_suite::SecretBytes;
+
+    fn widen(mut public_key: Vec<u8>) -> Vec<u8> {
+        public_key.extend_from_slice(&WIDE_KEY_SUFFIX);
+        public_key
+    }
+
+    fn narrow(public_key: &[u8]) -> Result<&[u8], CryptoError> {
+        public_key
+            .strip_suffix(&WIDE_KEY_SUFFIX[..])
+            .ok_or_else(|| CryptoError::InvalidKeyData("missing wide key suffix".to_string()))
+    }
+
+    impl CryptoProvider for WideKeySuite {
+        type KemPublicKey = Vec<u8>;
+        type KemPrivateKey = ClassicKey;
+        type SignaturePublicKey = Vec<u8>;
+        type SignaturePrivateKey = ClassicKey;
+        type AeadKey = ClassicKey;
+
+        fn generate_kem_keys() -> Result<(ClassicKey, Vec<u8>), CryptoError> {
+            let (private_key, public_key) = ClassicSuiteProvider::generate_kem_keys()?;
+            Ok((private_key, widen(public_key)))
+        }
+
+        fn from_private_key_to_public_key(private_key: &ClassicKey) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::from_private_key_to_public_key(private_key).map(widen)
+        }
+
+        fn kem_public_key_len() -> usize {
+            ClassicSuiteProvider::kem_public_key_len() + WIDE_KEY_SUFFIX.len()
+        }
+
+        fn aead_key_len() -> usize {
+            ClassicSuiteProvider::aead_key_len()
+        }
+
+        fn kem_public_key_from_bytes(bytes: Vec<u8>) -> Vec<u8> {
+            bytes
+        }
+
+        fn kem_private_key_from_bytes(bytes: Vec<u8>) -> ClassicKey {
+            ClassicSuiteProvider::kem_private_key_from_bytes(bytes)
+        }
+
+        fn aead_key_from_bytes(bytes: Vec<u8>) -> ClassicKey {
+            ClassicSuiteProvider::aead_key_from_bytes(bytes)
+        }
+
+        fn signature_public_key_from_bytes(bytes: Vec<u8>) -> Vec<u8> {
+            bytes
+        }
+
+        fn signature_private_key_from_bytes(bytes: Vec<u8>) -> ClassicKey {
+            ClassicSuiteProvider::signature_private_key_from_bytes(bytes)
+        }
+
+        fn generate_signature_keys() -> Result<(ClassicKey, Vec<u8>), CryptoError> {
+            ClassicSuiteProvider::generate_signature_keys()
+        }
+
+        fn sign(private_key: &ClassicKey, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::sign(private_key, message)
+        }
+
+        fn verify(public_key: &Vec<u8>, message: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+            ClassicSuiteProvider::verify(public_key, message, signature)
+        }
+
+        fn kem_encapsulate(public_key: &Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+            ClassicSuiteProvider::kem_encapsulate(&narrow(public_key)?.to_vec())
+        }
+
+        // Ratchet передает сюда публичный ключ собеседника
+        fn kem_decapsulate(private_key: &ClassicKey, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::kem_decapsulate(private_key, narrow(ciphertext)?)
+        }
+
+        fn aead_encrypt(
+            key: &ClassicKey,
+            nonce: &[u8],
+            plaintext: &[u8],
+            associated_data: Option<&[u8]>,
+        ) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::aead_encrypt(key, nonce, plaintext, associated_data)
+        }
+
+        fn aead_decrypt(
+            key: &ClassicKey,
+            nonce: &[u8],
+            ciphertext: &[u8],
+            associated_data: Option<&[u8]>,
+        ) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::aead_decrypt(key, nonce, ciphertext, associated_data)
+        }
+
+        fn hkdf_derive_key(salt: &[u8], ikm: &[u8], info: &[u8], len: usize) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::hkdf_derive_key(salt, ikm, info, len)
+        }
+
+        fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::mac(key, data)
+        }
+
+        fn kdf_rk(root_key: &ClassicKey, dh_output: &[u8]) -> Result<(ClassicKey, ClassicKey), CryptoError> {
+            ClassicSuiteProvider::kdf_rk(root_key, dh_output)
+        }
+
+        fn kdf_ck(chain_key: &ClassicKey) -> Result<(ClassicKey, ClassicKey), CryptoError> {
+            ClassicSuiteProvider::kdf_ck(chain_key)
+        }
+
+        fn generate_nonce(len: usize) -> Result<Vec<u8>, CryptoError> {
+            ClassicSuiteProvider::generate_nonce(len)
+        }
+
+        fn suite_id() -> u16 {
+            ClassicSuiteProvider::suite_id()
+        }
+    }
+
+    #[test]
+    fn test_non_32_byte_dh_public_key() {
+        type WideSession = DoubleRatchetSession<WideKeySuite>;
+
+        let (alice_identity, _) = WideKeySuite::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = WideKeySuite::generate_kem_keys().unwrap();
+        let mut alice =
+            WideSession::new_x3dh_session(1, &[5u8; 32], &bob_identity_public, &alice_identity, "bob".to_string())
+                .unwrap();
+
+        let first = alice.encrypt(b"hello").unwrap();
+        assert_eq!(first.dh_public_key.len(), 48);
+
+        // Ключ переживает канонический бинарный формат без обрезки
+        let first = EncryptedRatchetMessage::from_wire_bytes(&first.to_wire_bytes().unwrap()).unwrap();
+        let mut bob =
+            WideSession::new_receiving_session(1, &[5u8; 32], &bob_identity, &first, "alice".to_string()).unwrap();
+        assert_eq!(bob.decrypt(&first).unwrap(), b"hello");
+
+        let reply = bob.encrypt(b"hi").unwrap();
+        assert_eq!(reply.dh_public_key.len(), 48);
+        assert_eq!(alice.decrypt(&reply).unwrap(), b"hi");
+
+        // 32-байтовый ключ чужого suite отклоняется до DH
+        let mut foreign = alice.encrypt(b"again").unwrap();
+        foreign.dh_public_key.truncate(32);
+        let err = bob.decrypt(&foreign).unwrap_err();
+        assert!(err.to_string().contains("DH public key must be 48 bytes, got 32"), "{}", err);
+    }
+
+    #[test]
+    fn test_padding_hides_plaintext_length() {
+        for mode in [PaddingMode::PowerOfTwo, PaddingMode::FixedBlock(256)] {
+            let (alice, bob) = established_pair();
+            let mut alice = alice.with_padding(mode);
+            let mut bob = bob.with_padding(mode);
+
+            let short = alice.encrypt(b"hi there").unwrap();
+            let longer = alice.encrypt(b"see you at 7").unwrap();
+            assert_eq!(short.ciphertext.len(), longer.ciphertext.len(), "{:?}", mode);
+
+            assert_eq!(bob.decrypt(&short).unwrap(), b"hi there");
+            assert_eq!(bob.decrypt(&longer).unwrap(), b"see you at 7");
+
+            // Режим сохраняется вместе с сессией
+            let restored = Session::from_serializable(bob.to_serializable()).unwrap();
+            assert_eq!(restored.padding(), mode);
+        }
+    }
+
+    #[test]
+    fn test_padding_roundtrip_edge_cases() {
+        let cases: [(PaddingMode, usize, usize); 7] = [
+            (PaddingMode::PowerOfTwo, 0, 4),
+            (PaddingMode::PowerOfTwo, 60, 64),
+            (PaddingMode::PowerOfTwo, 61, 128),
+            (PaddingMode::FixedBlock(256), 0, 256),
+            (PaddingMode::FixedBlock(256), 252, 256),
+            (PaddingMode::FixedBlock(256), 253, 512),
+            (PaddingMode::None, 5, 5),
+        ];
+        for (mode, len, padded_len) in cases {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let padded = mode.pad(&plaintext).unwrap();
+            assert_eq!(padded.len(), padded_len, "{:?} len={}", mode, len);
+            assert_eq!(mode.unpad(padded).unwrap(), plaintext, "{:?} len={}", mode, len);
+        }
+
+        assert!(PaddingMode::FixedBlock(0).pad(b"data").is_err());
+
+        // Объявленная длина больше буфера и слишком короткий буфер отклоняются
+        let mut corrupted = PaddingMode::FixedBlock(16).pad(b"data").unwrap();
+        corrupted[..4].copy_from_slice(&100u32.to_be_bytes());
+        assert!(PaddingMode::FixedBlock(16).unpad(corrupted).is_err());
+        assert!(PaddingMode::PowerOfTwo.unpad(vec![0, 0]).is_err());
+    }
 }
@@ -3,10 +3,32 @@
 
 use crate::utils::error::{ConstructError, Result};
 use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use x25519_dalek::{PublicKey, StaticSecret};
 use crate::crypto::CryptoProvider;
+use crate::utils::time::{system_clock, Clock};
 use std::marker::PhantomData;
+use zeroize::Zeroize;
+
+/// Префикс данных, которые старый signing ключ подписывает при смене identity
+const IDENTITY_ROTATION_LABEL: &[u8] = b"construct-identity-rotation:";
+
+/// Данные подписи преемственности при смене identity: весь публичный материал
+/// нового bundle. Подписываются старым signing ключом и проверяются его verifying ключом
+pub fn identity_rotation_signed_data(bundle: &crate::crypto::RegistrationBundle) -> Vec<u8> {
+    let mut data = IDENTITY_ROTATION_LABEL.to_vec();
+    for field in [
+        &bundle.identity_public,
+        &bundle.signed_prekey_public,
+        &bundle.signature,
+        &bundle.verifying_key,
+    ] {
+        data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        data.extend_from_slice(field);
+    }
+    data.extend_from_slice(&bundle.suite_id.to_be_bytes());
+    data
+}
 
 /// Пара ключей X25519
 #[derive(Clone)]
@@ -50,6 +72,15 @@ impl Ed25519KeyPair {
     }
 }
 
+/// Одноразовый prekey для публикации на сервере
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedOneTimePrekey<K> {
+    pub key_id: u32,
+    pub public_key: K,
+    /// Подпись публичного ключа signing ключом
+    pub signature: Vec<u8>,
+}
+
 /// Хранилище prekey с метаданными
 #[derive(Clone)]
 pub struct PrekeyStore<P: CryptoProvider> {
@@ -59,6 +90,24 @@ pub struct PrekeyStore<P: CryptoProvider> {
     pub key_id: u32,
 }
 
+impl<P: CryptoProvider> Drop for PrekeyStore<P> {
+    fn drop(&mut self) {
+        self.key_pair.0.zeroize();
+    }
+}
+
+/// Сколько хранятся старые signed prekey после ротации (30 дней)
+pub const DEFAULT_PREKEY_MAX_AGE_SECONDS: i64 = 30 * 24 * 3600;
+
+/// Как часто ротируется signed prekey (7 дней)
+pub const DEFAULT_PREKEY_ROTATION_SECONDS: i64 = 7 * 24 * 3600;
+
+/// Запас одноразовых prekey пополняется, когда их остается меньше
+pub const MIN_ONE_TIME_PREKEYS: usize = 20;
+
+/// До какого количества пополняется запас одноразовых prekey
+pub const TARGET_ONE_TIME_PREKEYS: usize = 100;
+
 /// Менеджер криптографических ключей
 pub struct KeyManager<P: CryptoProvider> {
     /// Identity ключ (долговременный)
@@ -67,6 +116,9 @@ pub struct KeyManager<P: CryptoProvider> {
     /// Signing ключ для подписей
     signing_key: Option<(P::SignaturePrivateKey, P::SignaturePublicKey)>,
 
+    /// Verifying ключ до последней смены identity (для проверки перехода)
+    previous_verifying_key: Option<P::SignaturePublicKey>,
+
     /// Текущий signed prekey
     current_signed_prekey: Option<PrekeyStore<P>>,
 
@@ -76,6 +128,15 @@ pub struct KeyManager<P: CryptoProvider> {
     /// Счетчик для key_id
     next_prekey_id: u32,
 
+    /// Одноразовые prekey для X3DH (DH4), удаляются после использования
+    one_time_prekeys: BTreeMap<u32, (P::KemPrivateKey, P::KemPublicKey)>,
+
+    /// Счетчик для id одноразовых prekey
+    next_one_time_prekey_id: u32,
+
+    /// Источник времени для возраста prekey
+    clock: Clock,
+
     _phantom: PhantomData<P>,
 }
 
@@ -85,13 +146,50 @@ impl<P: CryptoProvider> KeyManager<P> {
         Self {
             identity_key: None,
             signing_key: None,
+            previous_verifying_key: None,
             current_signed_prekey: None,
             old_prekeys: HashMap::new(),
             next_prekey_id: 1,
+            one_time_prekeys: BTreeMap::new(),
+            next_one_time_prekey_id: 1,
+            clock: system_clock(),
             _phantom: PhantomData,
         }
     }
 
+    /// Восстановить менеджер из существующих ключей (например, переданных
+    /// с основного устройства при привязке). Подпись prekey проверяется
+    pub fn from_keys(
+        identity_secret: P::KemPrivateKey,
+        signing_key: (P::SignaturePrivateKey, P::SignaturePublicKey),
+        signed_prekey: P::KemPrivateKey,
+        signed_prekey_signature: Vec<u8>,
+        signed_prekey_id: u32,
+    ) -> Result<Self> {
+        let crypto_err = |e: crate::error::CryptoError| ConstructError::CryptoError(e.to_string());
+        let identity_public = P::from_private_key_to_public_key(&identity_secret).map_err(crypto_err)?;
+        let prekey_public = P::from_private_key_to_public_key(&signed_prekey).map_err(crypto_err)?;
+        P::verify(&signing_key.1, prekey_public.as_ref(), &signed_prekey_signature).map_err(crypto_err)?;
+
+        let mut manager = Self::new();
+        manager.identity_key = Some((identity_secret, identity_public));
+        manager.signing_key = Some(signing_key);
+        manager.current_signed_prekey = Some(PrekeyStore {
+            key_pair: (signed_prekey, prekey_public),
+            signature: signed_prekey_signature,
+            created_at: (manager.clock)(),
+            key_id: signed_prekey_id,
+        });
+        manager.next_prekey_id = signed_prekey_id.saturating_add(1);
+        Ok(manager)
+    }
+
+    /// Использовать другой источник времени
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// Инициализировать с новыми ключами
     pub fn initialize(&mut self) -> Result<()> {
         self.identity_key = Some(P::generate_kem_keys().map_err(|e| ConstructError::CryptoError(e.to_string()))?);
@@ -108,6 +206,11 @@ impl<P: CryptoProvider> KeyManager<P> {
             .ok_or_else(|| ConstructError::CryptoError("Identity key not initialized".to_string()))
     }
 
+    /// Отпечаток identity ключа (60 цифр) для сверки вне приложения
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(crate::crypto::identity_fingerprint(self.identity_public_key()?.as_ref()))
+    }
+
     /// Получить identity secret key
     pub fn identity_secret_key(&self) -> Result<&P::KemPrivateKey> {
         self.identity_key
@@ -124,6 +227,45 @@ impl<P: CryptoProvider> KeyManager<P> {
             .ok_or_else(|| ConstructError::CryptoError("Signing key not initialized".to_string()))
     }
 
+    /// Verifying ключ, действовавший до последней `rotate_identity_key`
+    pub fn previous_verifying_key(&self) -> Option<&P::SignaturePublicKey> {
+        self.previous_verifying_key.as_ref()
+    }
+
+    /// Сменить identity и signing ключи (вместе с signed prekey)
+    ///
+    /// Новый регистрационный bundle подписывается старым signing ключом
+    /// (`identity_rotation_signed_data`) - контакты проверяют подпись ключом,
+    /// который уже знают. Старый verifying ключ сохраняется в `previous_verifying_key`.
+    /// Prekey (старые signed и одноразовые) привязаны к прежнему identity и удаляются.
+    /// Возвращает подпись преемственности
+    pub fn rotate_identity_key(&mut self) -> Result<Vec<u8>> {
+        let crypto_err = |e: crate::error::CryptoError| ConstructError::CryptoError(e.to_string());
+        let identity_key = P::generate_kem_keys().map_err(crypto_err)?;
+        let signing_key = P::generate_signature_keys().map_err(crypto_err)?;
+        let Some((mut old_signing_key, old_verifying_key)) = self.signing_key.replace(signing_key) else {
+            if let Some((mut private_key, _)) = self.signing_key.take() {
+                private_key.zeroize();
+            }
+            return Err(ConstructError::CryptoError("Signing key not initialized".to_string()));
+        };
+        if let Some((mut private_key, _)) = self.identity_key.replace(identity_key) {
+            private_key.zeroize();
+        }
+        self.current_signed_prekey = None;
+        self.old_prekeys.clear();
+        for (mut private_key, _) in std::mem::take(&mut self.one_time_prekeys).into_values() {
+            private_key.zeroize();
+        }
+        self.rotate_signed_prekey()?;
+
+        let signed_data = identity_rotation_signed_data(&self.export_registration_bundle()?);
+        let signature = P::sign(&old_signing_key, &signed_data).map_err(crypto_err);
+        old_signing_key.zeroize();
+        self.previous_verifying_key = Some(old_verifying_key);
+        signature
+    }
+
     /// Получить текущий signed prekey
     pub fn current_signed_prekey(&self) -> Result<&PrekeyStore<P>> {
         self.current_signed_prekey
@@ -147,7 +289,7 @@ impl<P: CryptoProvider> KeyManager<P> {
         let prekey_store = PrekeyStore {
             key_pair,
             signature,
-            created_at: crate::utils::time::current_timestamp(),
+            created_at: (self.clock)(),
             key_id,
         };
 
@@ -159,11 +301,19 @@ impl<P: CryptoProvider> KeyManager<P> {
         self.current_signed_prekey = Some(prekey_store);
 
         // Очищаем старые prekeys (старше 30 дней)
-        self.cleanup_old_prekeys(30 * 24 * 3600);
+        self.purge_expired_prekeys(DEFAULT_PREKEY_MAX_AGE_SECONDS);
 
         Ok(())
     }
 
+    /// Пора ли ротировать signed prekey: текущему не меньше `max_age_seconds`.
+    /// Без signed prekey (ключи не инициализированы) ротировать нечего
+    pub fn should_rotate_prekey(&self, max_age_seconds: i64) -> bool {
+        self.current_signed_prekey
+            .as_ref()
+            .is_some_and(|prekey| (self.clock)() - prekey.created_at >= max_age_seconds)
+    }
+
     /// Получить prekey по ID
     pub fn get_prekey(&self, key_id: u32) -> Option<&PrekeyStore<P>> {
         if let Some(current) = &self.current_signed_prekey {
@@ -174,11 +324,133 @@ impl<P: CryptoProvider> KeyManager<P> {
         self.old_prekeys.get(&key_id)
     }
 
-    /// Очистка старых prekeys
-    fn cleanup_old_prekeys(&mut self, max_age_seconds: i64) {
-        let now = crate::utils::time::current_timestamp();
+    /// Восстановить signed prekey, замененный ротацией (после перезапуска).
+    /// Подпись проверяется, как и в `from_keys`
+    pub fn restore_old_prekey(
+        &mut self,
+        key_id: u32,
+        private_key: P::KemPrivateKey,
+        signature: Vec<u8>,
+        created_at: i64,
+    ) -> Result<()> {
+        let crypto_err = |e: crate::error::CryptoError| ConstructError::CryptoError(e.to_string());
+        let public_key = P::from_private_key_to_public_key(&private_key).map_err(crypto_err)?;
+        P::verify(self.verifying_key()?, public_key.as_ref(), &signature).map_err(crypto_err)?;
+
+        self.next_prekey_id = self.next_prekey_id.max(key_id.saturating_add(1));
+        self.old_prekeys.insert(
+            key_id,
+            PrekeyStore {
+                key_pair: (private_key, public_key),
+                signature,
+                created_at,
+                key_id,
+            },
+        );
+        Ok(())
+    }
+
+    /// Удалить старые prekeys, созданные более `max_age_seconds` назад
+    /// (текущий prekey не удаляется). Возвращает количество удаленных
+    pub fn purge_expired_prekeys(&mut self, max_age_seconds: i64) -> usize {
+        let now = (self.clock)();
+        let before = self.old_prekeys.len();
         self.old_prekeys
             .retain(|_, prekey| now - prekey.created_at < max_age_seconds);
+        before - self.old_prekeys.len()
+    }
+
+    /// Id сохраненных старых prekeys (по возрастанию)
+    pub fn old_prekey_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.old_prekeys.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Время создания prekey (текущего или старого)
+    pub fn prekey_created_at(&self, key_id: u32) -> Option<i64> {
+        self.get_prekey(key_id).map(|prekey| prekey.created_at)
+    }
+
+    /// Сгенерировать `count` одноразовых prekey; возвращает их id, публичные ключи
+    /// и подписи для публикации на сервере
+    pub fn generate_one_time_prekeys(
+        &mut self,
+        count: usize,
+    ) -> Result<Vec<SignedOneTimePrekey<P::KemPublicKey>>> {
+        let key_pairs = (0..count)
+            .map(|_| P::generate_kem_keys().map_err(|e| ConstructError::CryptoError(e.to_string())))
+            .collect::<Result<Vec<_>>>()?;
+        // Подписываем до сохранения: при ошибке не остается неподписанных prekey
+        let publics: Vec<&[u8]> = key_pairs.iter().map(|(_, public_key)| public_key.as_ref()).collect();
+        let signatures = self.sign_many(&publics)?;
+
+        let mut generated = Vec::with_capacity(count);
+        for (key_pair, signature) in key_pairs.into_iter().zip(signatures) {
+            let key_id = self.next_one_time_prekey_id;
+            self.next_one_time_prekey_id += 1;
+
+            generated.push(SignedOneTimePrekey {
+                key_id,
+                public_key: key_pair.1.clone(),
+                signature,
+            });
+            self.one_time_prekeys.insert(key_id, key_pair);
+        }
+        Ok(generated)
+    }
+
+    /// Публичные ключи всех неиспользованных одноразовых prekey
+    pub fn one_time_prekey_publics(&self) -> Vec<(u32, P::KemPublicKey)> {
+        self.one_time_prekeys
+            .iter()
+            .map(|(id, (_, public_key))| (*id, public_key.clone()))
+            .collect()
+    }
+
+    /// Приватный одноразовый prekey по id (без удаления)
+    pub fn one_time_prekey(&self, key_id: u32) -> Option<&P::KemPrivateKey> {
+        self.one_time_prekeys.get(&key_id).map(|(private_key, _)| private_key)
+    }
+
+    /// Удалить одноразовый prekey после успешного X3DH - повторно он не используется
+    pub fn consume_one_time_prekey(&mut self, key_id: u32) -> bool {
+        match self.one_time_prekeys.remove(&key_id) {
+            Some((mut private_key, _)) => {
+                private_key.zeroize();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Количество неиспользованных одноразовых prekey
+    pub fn one_time_prekeys_count(&self) -> usize {
+        self.one_time_prekeys.len()
+    }
+
+    /// Id, который получит следующий одноразовый prekey
+    pub fn next_one_time_prekey_id(&self) -> u32 {
+        self.next_one_time_prekey_id
+    }
+
+    /// Восстановить одноразовые prekey из хранилища после перезапуска.
+    /// Публичные ключи вычисляются из приватных; выданные ранее id не переиспользуются
+    pub fn restore_one_time_prekeys(
+        &mut self,
+        prekeys: Vec<(u32, P::KemPrivateKey)>,
+        next_id: u32,
+    ) -> Result<()> {
+        for (key_id, private_key) in prekeys {
+            let public_key = P::from_private_key_to_public_key(&private_key)
+                .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+            self.next_one_time_prekey_id = self.next_one_time_prekey_id.max(key_id.saturating_add(1));
+            if let Some((mut replaced, _)) = self.one_time_prekeys.insert(key_id, (private_key, public_key)) {
+                replaced.zeroize();
+            }
+        }
+        self.next_one_time_prekey_id = self.next_one_time_prekey_id.max(next_id);
+        Ok(())
     }
 
     /// Экспорт регистрационного bundle
@@ -202,12 +474,18 @@ impl<P: CryptoProvider> KeyManager<P> {
         let verifying_key = self.verifying_key()?.as_ref().to_vec();
         let prekey = self.current_signed_prekey()?;
 
+        // Первый из оставшихся одноразовых prekey (сервер раздает их по одному)
+        let one_time_prekey = self.one_time_prekeys.iter().next();
+
         Ok(crate::crypto::PublicKeyBundle {
             identity_public,
             signed_prekey_public: prekey.key_pair.1.as_ref().to_vec(),
             signature: prekey.signature.clone(),
             verifying_key,
             suite_id: P::suite_id(),
+            one_time_prekey_public: one_time_prekey.map(|(_, (_, public_key))| public_key.as_ref().to_vec()),
+            one_time_prekey_id: one_time_prekey.map(|(id, _)| *id),
+            signed_prekey_id: Some(prekey.key_id),
         })
     }
 
@@ -220,6 +498,20 @@ impl<P: CryptoProvider> KeyManager<P> {
         P::sign(signing_key, data).map_err(|e| ConstructError::CryptoError(e.to_string()))
     }
 
+    /// Подписать несколько сообщений одним ключом (пополнение одноразовых prekey)
+    pub fn sign_many(&self, messages: &[&[u8]]) -> Result<Vec<Vec<u8>>> {
+        let (signing_key, _) = self.signing_key.as_ref().ok_or_else(|| {
+            ConstructError::CryptoError("Signing key not initialized".to_string())
+        })?;
+
+        messages
+            .iter()
+            .map(|message| {
+                P::sign(signing_key, message).map_err(|e| ConstructError::CryptoError(e.to_string()))
+            })
+            .collect()
+    }
+
     /// Количество сохраненных старых prekeys
     pub fn old_prekeys_count(&self) -> usize {
         self.old_prekeys.len()
@@ -234,8 +526,159 @@ impl<P: CryptoProvider> KeyManager<P> {
     }
 }
 
+impl<P: CryptoProvider> Drop for KeyManager<P> {
+    fn drop(&mut self) {
+        // Prekey (текущий и старые) обнуляются в Drop для PrekeyStore
+        if let Some((private_key, _)) = self.identity_key.as_mut() {
+            private_key.zeroize();
+        }
+        if let Some((private_key, _)) = self.signing_key.as_mut() {
+            private_key.zeroize();
+        }
+        for (private_key, _) in self.one_time_prekeys.values_mut() {
+            private_key.zeroize();
+        }
+    }
+}
+
 impl<P: CryptoProvider> Default for KeyManager<P> {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    #[test]
+    fn test_purge_expired_prekeys() {
+        let mut manager = KeyManager::<ClassicSuiteProvider>::new();
+        manager.initialize().unwrap();
+        for _ in 0..3 {
+            manager.rotate_signed_prekey().unwrap();
+        }
+        assert_eq!(manager.old_prekey_ids(), vec![1, 2, 3]);
+
+        // Состарить prekeys 1 и 2 на 10 и 5 дней
+        let now = crate::utils::time::current_timestamp();
+        for (key_id, age_days) in [(1, 10), (2, 5)] {
+            manager.old_prekeys.get_mut(&key_id).unwrap().created_at = now - age_days * 24 * 3600;
+        }
+
+        assert_eq!(manager.purge_expired_prekeys(7 * 24 * 3600), 1);
+        assert_eq!(manager.old_prekey_ids(), vec![2, 3]);
+        assert!(manager.prekey_created_at(1).is_none());
+        assert_eq!(manager.prekey_created_at(2), Some(now - 5 * 24 * 3600));
+
+        // Текущий prekey не удаляется даже при нулевом сроке
+        assert_eq!(manager.purge_expired_prekeys(0), 2);
+        assert!(manager.old_prekey_ids().is_empty());
+        assert!(manager.prekey_created_at(4).is_some());
+    }
+
+    #[test]
+    fn test_should_rotate_prekey() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let now = Arc::new(AtomicI64::new(1_000));
+        let clock_now = now.clone();
+        let mut manager = KeyManager::<ClassicSuiteProvider>::new()
+            .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+        assert!(!manager.should_rotate_prekey(0));
+        manager.initialize().unwrap();
+        assert_eq!(manager.prekey_created_at(1), Some(1_000));
+
+        assert!(!manager.should_rotate_prekey(100));
+        now.store(1_100, Ordering::SeqCst);
+        assert!(manager.should_rotate_prekey(100));
+
+        manager.rotate_signed_prekey().unwrap();
+        assert!(!manager.should_rotate_prekey(100));
+        assert_eq!(manager.prekey_created_at(2), Some(1_100));
+    }
+
+    #[test]
+    fn test_sign_many() {
+        let mut manager = KeyManager::<ClassicSuiteProvider>::new();
+        assert!(manager.sign_many(&[b"one"]).is_err());
+        manager.initialize().unwrap();
+
+        let messages: [&[u8]; 3] = [b"one", b"two", b""];
+        let signatures = manager.sign_many(&messages).unwrap();
+        assert_eq!(signatures.len(), messages.len());
+        let verifying_key = &manager.verifying_key().unwrap().clone();
+        for (message, signature) in messages.iter().zip(&signatures) {
+            ClassicSuiteProvider::verify(verifying_key, message, signature).unwrap();
+        }
+        assert!(ClassicSuiteProvider::verify(verifying_key, b"one", &signatures[1]).is_err());
+        assert!(manager.sign_many(&[]).unwrap().is_empty());
+
+        // Одноразовые prekey подписываются пакетом
+        let prekeys = manager.generate_one_time_prekeys(4).unwrap();
+        for prekey in &prekeys {
+            ClassicSuiteProvider::verify(verifying_key, prekey.public_key.as_ref(), &prekey.signature)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_restore_one_time_prekeys() {
+        let mut manager = KeyManager::<ClassicSuiteProvider>::new();
+        manager.initialize().unwrap();
+        manager.generate_one_time_prekeys(3).unwrap();
+        manager.consume_one_time_prekey(3);
+        let saved: Vec<_> = manager
+            .one_time_prekey_publics()
+            .into_iter()
+            .map(|(id, _)| (id, manager.one_time_prekey(id).unwrap().clone()))
+            .collect();
+
+        let mut restored = KeyManager::<ClassicSuiteProvider>::new();
+        restored.initialize().unwrap();
+        restored
+            .restore_one_time_prekeys(saved, manager.next_one_time_prekey_id())
+            .unwrap();
+        assert_eq!(restored.one_time_prekey_publics(), manager.one_time_prekey_publics());
+        // Израсходованный id 3 не выдается повторно
+        let next = restored.generate_one_time_prekeys(1).unwrap();
+        assert_eq!(next[0].key_id, 4);
+    }
+
+    #[test]
+    fn test_rotate_identity_key() {
+        let mut manager = KeyManager::<ClassicSuiteProvider>::new();
+        assert!(manager.rotate_identity_key().is_err());
+        manager.initialize().unwrap();
+        manager.generate_one_time_prekeys(2).unwrap();
+        manager.rotate_signed_prekey().unwrap();
+        let old_bundle = manager.export_registration_bundle().unwrap();
+        let old_verifying_key = manager.verifying_key().unwrap().clone();
+
+        let signature = manager.rotate_identity_key().unwrap();
+        let new_bundle = manager.export_registration_bundle().unwrap();
+        assert_ne!(new_bundle.identity_public, old_bundle.identity_public);
+        assert_ne!(new_bundle.verifying_key, old_bundle.verifying_key);
+        assert_eq!(manager.previous_verifying_key(), Some(&old_verifying_key));
+        // Prekey прежнего identity не переживают смену
+        assert_eq!(manager.one_time_prekeys_count(), 0);
+        assert!(manager.old_prekey_ids().is_empty());
+
+        // Новый bundle подписан старым ключом, но не новым
+        let signed_data = identity_rotation_signed_data(&new_bundle);
+        ClassicSuiteProvider::verify(&old_verifying_key, &signed_data, &signature).unwrap();
+        let new_verifying_key = manager.verifying_key().unwrap();
+        assert!(ClassicSuiteProvider::verify(new_verifying_key, &signed_data, &signature).is_err());
+        // Новый signed prekey подписан новым ключом
+        ClassicSuiteProvider::verify(new_verifying_key, &new_bundle.signed_prekey_public, &new_bundle.signature)
+            .unwrap();
+        assert!(ClassicSuiteProvider::verify(
+            &old_verifying_key,
+            &identity_rotation_signed_data(&old_bundle),
+            &signature
+        )
+        .is_err());
+    }
+}
@@ -17,7 +17,7 @@ use zeroize::{Zeroize, Zeroizing};
 
 /// Параметры PBKDF2
 const PBKDF2_ITERATIONS: u32 = 100_000; // Рекомендуемое значение OWASP
-const SALT_LENGTH: usize = 32; // 256 бит
+pub const SALT_LENGTH: usize = 32; // 256 бит
 const KEY_LENGTH: usize = 32; // 256 бит для AES-256
 const NONCE_LENGTH: usize = 12; // 96 бит для GCM
 
@@ -44,6 +44,19 @@ impl PrivateKeys {
         }
     }
 
+    /// Создать из срезов байтов (ключи должны быть по 32 байта)
+    pub fn from_slices(
+        identity_secret: &[u8],
+        signing_key: &[u8],
+        signed_prekey_secret: &[u8],
+    ) -> Result<Self> {
+        Ok(Self::new(
+            to_array_32(identity_secret)?,
+            to_array_32(signing_key)?,
+            to_array_32(signed_prekey_secret)?,
+        ))
+    }
+
     /// Конвертировать в StaticSecret и SigningKey
     pub fn to_keys(&self) -> Result<(StaticSecret, SigningKey, StaticSecret)> {
         let identity = StaticSecret::from(self.identity_secret);
@@ -131,6 +144,11 @@ pub fn encrypt_private_keys(
         prekey_signature,
         salt: salt.to_vec(),
         created_at: current_timestamp(),
+        verifying_key: Vec::new(),
+        signed_prekey_id: 0,
+        one_time_prekeys: Vec::new(),
+        next_one_time_prekey_id: 0,
+        old_signed_prekeys: Vec::new(),
     })
 }
 
@@ -161,6 +179,53 @@ pub fn decrypt_private_keys(
     Ok(PrivateKeys::new(identity_secret, signing_key, prekey_secret))
 }
 
+/// Зашифровать данные мастер-ключом (nonce || ciphertext)
+pub fn encrypt_with_master_key(master_key: &[u8; KEY_LENGTH], data: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new(master_key.into());
+    encrypt_data(&cipher, data)
+}
+
+/// Расшифровать данные, зашифрованные `encrypt_with_master_key`
+pub fn decrypt_with_master_key(
+    master_key: &[u8; KEY_LENGTH],
+    data: &[u8],
+) -> Result<Zeroizing<Vec<u8>>> {
+    let cipher = Aes256Gcm::new(master_key.into());
+    decrypt_data(&cipher, data)
+}
+
+/// Зашифровать произвольные данные паролем
+///
+/// Формат результата: salt (32 байта) || nonce (12 байт) || ciphertext
+pub fn encrypt_with_password(password: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let salt = generate_salt();
+    let master_key = derive_master_key(password, &salt)?;
+    let cipher = Aes256Gcm::new((&*master_key).into());
+
+    let encrypted = encrypt_data(&cipher, data)?;
+
+    let mut result = Vec::with_capacity(SALT_LENGTH + encrypted.len());
+    result.extend_from_slice(&salt);
+    result.extend_from_slice(&encrypted);
+
+    Ok(result)
+}
+
+/// Расшифровать данные, зашифрованные `encrypt_with_password`
+pub fn decrypt_with_password(password: &str, blob: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    if blob.len() < SALT_LENGTH + NONCE_LENGTH {
+        return Err(ConstructError::CryptoError(
+            "Invalid encrypted blob: too short".to_string(),
+        ));
+    }
+
+    let (salt, encrypted) = blob.split_at(SALT_LENGTH);
+    let master_key = derive_master_key(password, salt)?;
+    let cipher = Aes256Gcm::new((&*master_key).into());
+
+    decrypt_data(&cipher, encrypted)
+}
+
 /// Зашифровать данные с использованием AES-256-GCM
 fn encrypt_data(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>> {
     // Генерируем случайный nonce
@@ -347,6 +412,18 @@ mod tests {
         assert_eq!(salt2.len(), SALT_LENGTH);
     }
 
+    #[test]
+    fn test_encrypt_with_password_roundtrip() {
+        let blob = encrypt_with_password("backup_password_1", b"archive").unwrap();
+        assert_eq!(blob.len(), SALT_LENGTH + NONCE_LENGTH + b"archive".len() + 16);
+
+        let decrypted = decrypt_with_password("backup_password_1", &blob).unwrap();
+        assert_eq!(decrypted.as_slice(), b"archive");
+
+        assert!(decrypt_with_password("wrong_password_2", &blob).is_err());
+        assert!(decrypt_with_password("backup_password_1", &blob[..10]).is_err());
+    }
+
     #[test]
     fn test_encrypt_data_includes_nonce() {
         let master_key = [0u8; KEY_LENGTH];
@@ -7,6 +7,9 @@ pub mod x3dh;
 pub mod keys;
 pub mod session;
 pub mod master_key;
+pub mod transcript;
+pub mod device_link;
+pub mod sender_keys;
 pub mod crypto_provider; // Added
 pub mod classic_suite; // Added
 
@@ -15,15 +18,157 @@ pub mod classic_suite; // Added
 pub mod pq_x3dh;
 #[cfg(feature = "post-quantum")]
 pub mod pq_double_ratchet;
+#[cfg(feature = "post-quantum")]
+pub mod pq_hybrid_suite;
 
 pub use client::ClientCrypto;
-pub use double_ratchet::{DoubleRatchetSession, EncryptedRatchetMessage, SerializableSession};
+pub use double_ratchet::{DoubleRatchetSession, EncryptedRatchetMessage, PaddingMode, SerializableSession};
 pub use x3dh::{PublicKeyBundle, RegistrationBundle, X3DH};
 pub use crypto_provider::CryptoProvider;
+pub use transcript::HandshakeTranscript;
+#[cfg(feature = "post-quantum")]
+pub use pq_hybrid_suite::PQHybridSuiteProvider;
 
 pub type SuiteID = u16;
 
 /// Suite ID for the classic suite as per API_V3_SPEC.md
 pub const CLASSIC_SUITE_ID: SuiteID = 1;
-/// Suite ID for Post-Quantum hybrid suite (reserved)
+/// Suite ID for the Post-Quantum hybrid suite (`PQHybridSuiteProvider`, `post-quantum` feature)
 pub const PQ_HYBRID_SUITE_ID: SuiteID = 2;
+/// Suite ID for the classic suite with HKDF-SHA512
+pub const CLASSIC_SHA512_SUITE_ID: SuiteID = 3;
+
+/// Raw byte lengths of the public parts of a registration bundle for a suite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuiteKeyLengths {
+    /// Identity and signed prekey public keys
+    pub kem_public_key: usize,
+    pub signature: usize,
+    pub verifying_key: usize,
+}
+
+/// X25519 / Ed25519
+const CLASSIC_KEY_LENGTHS: SuiteKeyLengths = SuiteKeyLengths {
+    kem_public_key: 32,
+    signature: 64,
+    verifying_key: 32,
+};
+
+/// X25519 + Kyber-768 / Ed25519 + Dilithium3 (classic and PQ parts concatenated).
+/// PQ sizes come from the linked pqcrypto implementation, not from the spec tables.
+#[cfg(feature = "post-quantum")]
+fn pq_hybrid_key_lengths() -> SuiteKeyLengths {
+    use pqcrypto_dilithium::dilithium3;
+    use pqcrypto_kyber::kyber768;
+
+    SuiteKeyLengths {
+        kem_public_key: CLASSIC_KEY_LENGTHS.kem_public_key + kyber768::public_key_bytes(),
+        signature: CLASSIC_KEY_LENGTHS.signature + dilithium3::signature_bytes(),
+        verifying_key: CLASSIC_KEY_LENGTHS.verifying_key + dilithium3::public_key_bytes(),
+    }
+}
+
+/// Whether this build includes the post-quantum hybrid suite (`post-quantum` feature).
+pub fn build_supports_pq() -> bool {
+    cfg!(feature = "post-quantum")
+}
+
+/// Rejects the PQ hybrid suite in builds without it, so such a bundle never falls through to the classic path.
+pub fn ensure_suite_available(suite_id: SuiteID) -> Result<(), crate::error::CryptoError> {
+    if suite_id == PQ_HYBRID_SUITE_ID && !build_supports_pq() {
+        return Err(crate::error::CryptoError::PostQuantumUnsupported);
+    }
+    Ok(())
+}
+
+/// Expected bundle key lengths for `suite_id`, `None` if the suite is not supported by this build.
+pub fn suite_key_lengths(suite_id: SuiteID) -> Option<SuiteKeyLengths> {
+    match suite_id {
+        CLASSIC_SUITE_ID | CLASSIC_SHA512_SUITE_ID => Some(CLASSIC_KEY_LENGTHS),
+        #[cfg(feature = "post-quantum")]
+        PQ_HYBRID_SUITE_ID => Some(pq_hybrid_key_lengths()),
+        _ => None,
+    }
+}
+
+/// SHA-512 iterations per fingerprint, as in Signal's numeric fingerprints.
+pub const FINGERPRINT_ITERATIONS: usize = 5200;
+/// Fingerprint format version mixed into the first hash round.
+const FINGERPRINT_VERSION: u16 = 0;
+
+/// Iterated SHA-512 over an identity public key: `H = SHA512(H || key)`.
+fn fingerprint_hash(identity_public: &[u8]) -> [u8; 64] {
+    use sha2::{Digest, Sha512};
+
+    let mut hash: [u8; 64] = Sha512::new()
+        .chain_update(FINGERPRINT_VERSION.to_be_bytes())
+        .chain_update(identity_public)
+        .finalize()
+        .into();
+    for _ in 0..FINGERPRINT_ITERATIONS {
+        hash = Sha512::new()
+            .chain_update(hash)
+            .chain_update(identity_public)
+            .finalize()
+            .into();
+    }
+    hash
+}
+
+/// Renders `chunks` 5-byte groups of `hash` as 5-digit decimal blocks.
+fn fingerprint_digits(hash: &[u8], chunks: usize) -> String {
+    hash.chunks_exact(5)
+        .take(chunks)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+/// Stable 60-digit fingerprint of a single identity public key.
+pub fn identity_fingerprint(identity_public: &[u8]) -> String {
+    fingerprint_digits(&fingerprint_hash(identity_public), 12)
+}
+
+/// Symmetric 60-digit safety number for two identities.
+///
+/// Each side contributes 30 digits; the halves are sorted so both parties
+/// display the same number regardless of who computes it.
+pub fn combined_safety_number(local_identity: &[u8], remote_identity: &[u8]) -> String {
+    let local = fingerprint_digits(&fingerprint_hash(local_identity), 6);
+    let remote = fingerprint_digits(&fingerprint_hash(remote_identity), 6);
+
+    if local <= remote {
+        local + &remote
+    } else {
+        remote + &local
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let alice = [1u8; 32];
+        let bob = [2u8; 32];
+
+        let number = combined_safety_number(&alice, &bob);
+        assert_eq!(number, combined_safety_number(&bob, &alice));
+        assert_eq!(number.len(), 60);
+        assert!(number.chars().all(|c| c.is_ascii_digit()));
+
+        // Other pair gives another number
+        assert_ne!(number, combined_safety_number(&alice, &[3u8; 32]));
+    }
+
+    #[test]
+    fn test_identity_fingerprint_is_stable() {
+        let fingerprint = identity_fingerprint(&[7u8; 32]);
+        assert_eq!(fingerprint.len(), 60);
+        assert_eq!(fingerprint, identity_fingerprint(&[7u8; 32]));
+        assert_ne!(fingerprint, identity_fingerprint(&[8u8; 32]));
+    }
+}
@@ -0,0 +1,320 @@
+//! Post-quantum hybrid suite: X25519 + Kyber-768 KEM and Ed25519 + Dilithium3 signatures.
+//!
+//! Every public key, private key, ciphertext and signature is the classic part followed by
+//! the PQ part (lengths in `suite_key_lengths(PQ_HYBRID_SUITE_ID)`). AEAD, HKDF and the ratchet KDFs are
+//! those of the classic suite; only `suite_id()` tells the two apart on the wire.
+//!
+//! X3DH and the Double Ratchet agree on secrets by "decapsulating" the peer's public key,
+//! which is a Diffie-Hellman. Kyber has no such operation: only the X25519 halves would
+//! agree, and a session labelled post-quantum would have no PQ protection. So
+//! `kem_decapsulate` refuses a hybrid public key, and X3DH and the ratchet fail on this
+//! provider. PQ sessions are established by the hybrid X3DH of a classic client with PQ
+//! keys (`ClientCrypto::new_with_pqc`, `ClientCrypto::perform_pq_x3dh`).
+#![cfg(feature = "post-quantum")]
+
+use crate::crypto::classic_suite::{ClassicSuiteProvider, SecretBytes};
+use crate::crypto::{CryptoProvider, PQ_HYBRID_SUITE_ID};
+use crate::error::CryptoError;
+use pqcrypto_dilithium::dilithium3;
+use pqcrypto_kyber::kyber768;
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SecretKey as _, SharedSecret as _};
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
+use zeroize::Zeroize;
+
+type Classic = ClassicSuiteProvider;
+
+const X25519_KEY_LEN: usize = 32;
+const ED25519_KEY_LEN: usize = 32;
+const ED25519_SIGNATURE_LEN: usize = 64;
+/// Offset of the public key inside a Kyber-768 secret key (after the 3 * 384-byte IND-CPA key).
+const KYBER_SECRET_PUBLIC_OFFSET: usize = 3 * 384;
+
+/// Hybrid `CryptoProvider` for `PQ_HYBRID_SUITE_ID`.
+pub struct PQHybridSuiteProvider;
+
+/// Splits a hybrid value into its classic and PQ parts, checking the total length.
+fn split<'a>(bytes: &'a [u8], classic_len: usize, pq_len: usize, what: &str) -> Result<(&'a [u8], &'a [u8]), CryptoError> {
+    if bytes.len() != classic_len + pq_len {
+        return Err(CryptoError::InvalidInputError(format!(
+            "Invalid hybrid {} length: expected {}, got {}",
+            what,
+            classic_len + pq_len,
+            bytes.len()
+        )));
+    }
+    Ok(bytes.split_at(classic_len))
+}
+
+fn concat(classic: &[u8], pq: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(classic.len() + pq.len());
+    out.extend_from_slice(classic);
+    out.extend_from_slice(pq);
+    out
+}
+
+fn split_kem_private(private_key: &SecretBytes) -> Result<(SecretBytes, kyber768::SecretKey), CryptoError> {
+    let (classic, kyber) = split(private_key, X25519_KEY_LEN, kyber768::secret_key_bytes(), "KEM private key")?;
+    let kyber = kyber768::SecretKey::from_bytes(kyber)
+        .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Kyber secret key: {}", e)))?;
+    Ok((SecretBytes::from(classic.to_vec()), kyber))
+}
+
+impl CryptoProvider for PQHybridSuiteProvider {
+    type KemPublicKey = Vec<u8>;
+    type KemPrivateKey = SecretBytes;
+    type SignaturePublicKey = Vec<u8>;
+    type SignaturePrivateKey = SecretBytes;
+    type AeadKey = SecretBytes;
+
+    fn generate_kem_keys() -> Result<(Self::KemPrivateKey, Self::KemPublicKey), CryptoError> {
+        let (classic_private, classic_public) = Classic::generate_kem_keys()?;
+        let (kyber_public, kyber_secret) = kyber768::keypair();
+        Ok((
+            SecretBytes::from(concat(&classic_private, kyber_secret.as_bytes())),
+            concat(&classic_public, kyber_public.as_bytes()),
+        ))
+    }
+
+    fn from_private_key_to_public_key(
+        private_key: &Self::KemPrivateKey,
+    ) -> Result<Self::KemPublicKey, CryptoError> {
+        let (classic, kyber) = split(private_key, X25519_KEY_LEN, kyber768::secret_key_bytes(), "KEM private key")?;
+        let classic_public = Classic::from_private_key_to_public_key(&SecretBytes::from(classic.to_vec()))?;
+        let kyber_public = &kyber[KYBER_SECRET_PUBLIC_OFFSET..KYBER_SECRET_PUBLIC_OFFSET + kyber768::public_key_bytes()];
+        Ok(concat(&classic_public, kyber_public))
+    }
+
+    fn kem_public_key_len() -> usize {
+        X25519_KEY_LEN + kyber768::public_key_bytes()
+    }
+
+    fn aead_key_len() -> usize {
+        Classic::aead_key_len()
+    }
+
+    fn kem_public_key_from_bytes(bytes: Vec<u8>) -> Self::KemPublicKey {
+        bytes
+    }
+
+    fn kem_private_key_from_bytes(bytes: Vec<u8>) -> Self::KemPrivateKey {
+        SecretBytes::from(bytes)
+    }
+
+    fn aead_key_from_bytes(bytes: Vec<u8>) -> Self::AeadKey {
+        SecretBytes::from(bytes)
+    }
+
+    fn signature_public_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePublicKey {
+        bytes
+    }
+
+    fn signature_private_key_from_bytes(bytes: Vec<u8>) -> Self::SignaturePrivateKey {
+        SecretBytes::from(bytes)
+    }
+
+    fn generate_signature_keys(
+    ) -> Result<(Self::SignaturePrivateKey, Self::SignaturePublicKey), CryptoError> {
+        let (classic_private, classic_public) = Classic::generate_signature_keys()?;
+        let (dilithium_public, dilithium_secret) = dilithium3::keypair();
+        Ok((
+            SecretBytes::from(concat(&classic_private, dilithium_secret.as_bytes())),
+            concat(&classic_public, dilithium_public.as_bytes()),
+        ))
+    }
+
+    fn sign(private_key: &Self::SignaturePrivateKey, message: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (classic, dilithium) =
+            split(private_key, ED25519_KEY_LEN, dilithium3::secret_key_bytes(), "signing key")?;
+        let classic_signature = Classic::sign(&SecretBytes::from(classic.to_vec()), message)?;
+        let dilithium = dilithium3::SecretKey::from_bytes(dilithium)
+            .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Dilithium secret key: {}", e)))?;
+        let dilithium_signature = dilithium3::detached_sign(message, &dilithium);
+        Ok(concat(&classic_signature, dilithium_signature.as_bytes()))
+    }
+
+    /// Both signatures must be valid.
+    fn verify(
+        public_key: &Self::SignaturePublicKey,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), CryptoError> {
+        let (classic_key, dilithium_key) =
+            split(public_key, ED25519_KEY_LEN, dilithium3::public_key_bytes(), "verifying key")?;
+        let (classic_signature, dilithium_signature) =
+            split(signature, ED25519_SIGNATURE_LEN, dilithium3::signature_bytes(), "signature")?;
+
+        Classic::verify(&classic_key.to_vec(), message, classic_signature)?;
+
+        let dilithium_key = dilithium3::PublicKey::from_bytes(dilithium_key)
+            .map_err(|e| CryptoError::InvalidInputError(format!("Invalid Dilithium verifying key: {}", e)))?;
+        let dilithium_signature = dilithium3::DetachedSignature::from_bytes(dilithium_signature)
+            .map_err(|e| CryptoError::InvalidInputError(format!("Invalid Dilithium signature: {}", e)))?;
+        dilithium3::verify_detached_signature(&dilithium_signature, message, &dilithium_key)
+            .map_err(|e| CryptoError::SignatureVerificationError(e.to_string()))
+    }
+
+    /// Ciphertext is the X25519 ephemeral key followed by the Kyber ciphertext;
+    /// the shared secret is both shared secrets concatenated.
+    fn kem_encapsulate(
+        public_key: &Self::KemPublicKey,
+    ) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        let (classic, kyber) = split(public_key, X25519_KEY_LEN, kyber768::public_key_bytes(), "KEM public key")?;
+        let (classic_ciphertext, mut classic_shared) = Classic::kem_encapsulate(&classic.to_vec())?;
+        let kyber = kyber768::PublicKey::from_bytes(kyber)
+            .map_err(|e| CryptoError::InvalidInputError(format!("Invalid Kyber public key: {}", e)))?;
+        let (kyber_shared, kyber_ciphertext) = kyber768::encapsulate(&kyber);
+
+        let shared = concat(&classic_shared, kyber_shared.as_bytes());
+        classic_shared.zeroize();
+        Ok((concat(&classic_ciphertext, kyber_ciphertext.as_bytes()), shared))
+    }
+
+    /// Accepts only a hybrid ciphertext from `kem_encapsulate`. A peer's hybrid public key
+    /// (the DH-style agreement of X3DH and the ratchet) is refused, see module docs.
+    fn kem_decapsulate(
+        private_key: &Self::KemPrivateKey,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        if ciphertext.len() == Self::kem_public_key_len() {
+            return Err(CryptoError::KemDecapsulationError(
+                "DH-style agreement on hybrid keys would skip Kyber; use the hybrid X3DH (perform_pq_x3dh)"
+                    .to_string(),
+            ));
+        }
+        let (classic_private, kyber_private) = split_kem_private(private_key)?;
+
+        let (classic_ciphertext, kyber_ciphertext) =
+            split(ciphertext, X25519_KEY_LEN, kyber768::ciphertext_bytes(), "KEM ciphertext")?;
+        let mut classic_shared = Classic::kem_decapsulate(&classic_private, classic_ciphertext)?;
+        let kyber_ciphertext = kyber768::Ciphertext::from_bytes(kyber_ciphertext)
+            .map_err(|e| CryptoError::InvalidInputError(format!("Invalid Kyber ciphertext: {}", e)))?;
+        let kyber_shared = kyber768::decapsulate(&kyber_ciphertext, &kyber_private);
+
+        let shared = concat(&classic_shared, kyber_shared.as_bytes());
+        classic_shared.zeroize();
+        Ok(shared)
+    }
+
+    fn aead_encrypt(
+        key: &Self::AeadKey,
+        nonce: &[u8],
+        plaintext: &[u8],
+        associated_data: Option<&[u8]>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        Classic::aead_encrypt(key, nonce, plaintext, associated_data)
+    }
+
+    fn aead_decrypt(
+        key: &Self::AeadKey,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: Option<&[u8]>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        Classic::aead_decrypt(key, nonce, ciphertext, associated_data)
+    }
+
+    fn hkdf_derive_key(
+        salt: &[u8],
+        ikm: &[u8],
+        info: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, CryptoError> {
+        Classic::hkdf_derive_key(salt, ikm, info, len)
+    }
+
+    fn mac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        Classic::mac(key, data)
+    }
+
+    fn kdf_rk(
+        root_key: &Self::AeadKey,
+        dh_output: &[u8],
+    ) -> Result<(Self::AeadKey, Self::AeadKey), CryptoError> {
+        Classic::kdf_rk(root_key, dh_output)
+    }
+
+    fn kdf_ck(chain_key: &Self::AeadKey) -> Result<(Self::AeadKey, Self::AeadKey), CryptoError> {
+        Classic::kdf_ck(chain_key)
+    }
+
+    fn generate_nonce(len: usize) -> Result<Vec<u8>, CryptoError> {
+        Classic::generate_nonce(len)
+    }
+
+    fn fill_random(dest: &mut [u8]) -> Result<(), CryptoError> {
+        Classic::fill_random(dest)
+    }
+
+    fn suite_id() -> u16 {
+        PQ_HYBRID_SUITE_ID
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::x3dh::{PublicKeyBundle, X3DH};
+    use crate::crypto::{suite_key_lengths, ClientCrypto};
+
+    type Hybrid = PQHybridSuiteProvider;
+
+    #[test]
+    fn test_hybrid_registration_bundle_lengths() {
+        let bundle = X3DH::<Hybrid>::generate_registration_bundle().unwrap();
+        let lengths = suite_key_lengths(PQ_HYBRID_SUITE_ID).unwrap();
+        assert_eq!(bundle.suite_id, PQ_HYBRID_SUITE_ID);
+        assert_eq!(bundle.identity_public.len(), lengths.kem_public_key);
+        assert_eq!(bundle.signed_prekey_public.len(), lengths.kem_public_key);
+        assert_eq!(bundle.signature.len(), lengths.signature);
+        assert_eq!(bundle.verifying_key.len(), lengths.verifying_key);
+        assert!(Hybrid::verify(&bundle.verifying_key, &bundle.signed_prekey_public, &bundle.signature).is_ok());
+
+        // Подпись принимается только если верны обе половины
+        let mut forged = bundle.signature.clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 0xff;
+        assert!(Hybrid::verify(&bundle.verifying_key, &bundle.signed_prekey_public, &forged).is_err());
+    }
+
+    #[test]
+    fn test_hybrid_kem_roundtrip() {
+        let (private_key, public_key) = Hybrid::generate_kem_keys().unwrap();
+        assert_eq!(Hybrid::from_private_key_to_public_key(&private_key).unwrap(), public_key);
+
+        let (ciphertext, shared) = Hybrid::kem_encapsulate(&public_key).unwrap();
+        assert_eq!(shared.len(), 32 + kyber768::shared_secret_bytes());
+        assert_eq!(Hybrid::kem_decapsulate(&private_key, &ciphertext).unwrap(), shared);
+    }
+
+    #[test]
+    fn test_hybrid_refuses_dh_style_sessions() {
+        let mut alice = ClientCrypto::<Hybrid>::new().unwrap();
+        let mut bob = ClientCrypto::<Hybrid>::new().unwrap();
+        let public_bundle = |client: &ClientCrypto<Hybrid>| {
+            let bundle = client.get_registration_bundle();
+            PublicKeyBundle {
+                identity_public: bundle.identity_public,
+                signed_prekey_public: bundle.signed_prekey_public,
+                signature: bundle.signature,
+                verifying_key: bundle.verifying_key,
+                suite_id: bundle.suite_id,
+                one_time_prekey_public: None,
+                one_time_prekey_id: None,
+                signed_prekey_id: None,
+            }
+        };
+
+        // X3DH на гибридных ключах защищала бы только X25519 половины - отказ,
+        // а не сессия с PQ меткой без PQ защиты
+        let err = alice.init_session("bob", &public_bundle(&bob)).unwrap_err();
+        assert!(err.to_string().contains("perform_pq_x3dh"), "{}", err);
+        assert!(bob.init_session("alice", &public_bundle(&alice)).is_err());
+
+        let (private_key, _) = Hybrid::generate_kem_keys().unwrap();
+        let (_, peer_public) = Hybrid::generate_kem_keys().unwrap();
+        assert!(matches!(
+            Hybrid::kem_decapsulate(&private_key, &peer_public),
+            Err(CryptoError::KemDecapsulationError(_))
+        ));
+    }
+}
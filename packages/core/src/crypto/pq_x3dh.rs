@@ -1,23 +1,201 @@
 //! Post-quantum extensions for the X3DH protocol.
+//!
+//! The hybrid handshake runs the classic X3DH of the client's suite and, on top of it,
+//! two Kyber-768 encapsulations: against the responder's Kyber identity key and
+//! against its Kyber prekey. All shared secrets are combined through HKDF into a
+//! 64-byte root key, so the session stays secure while either part holds.
 #![cfg(feature = "post-quantum")]
 
-use pqc_kyber::KyberKeypair;
-use x25519_dalek::{StaticSecret, PublicKey};
+use crate::crypto::x3dh::PublicKeyBundle;
+use crate::crypto::{CryptoProvider, SuiteID, PQ_HYBRID_SUITE_ID};
+use crate::error::CryptoError;
+use pqcrypto_dilithium::dilithium3;
+use pqcrypto_kyber::kyber768;
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SharedSecret as _};
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _};
+use zeroize::Zeroize;
 
-// TODO: These should be properly defined with actual key sizes from the pqc crates.
-// Using Vec<u8> for now as per the documentation.
-// Kyber-768 public key is 1184 bytes.
-// Dilithium3 signature is 2701 bytes.
+/// Length of the hybrid root key.
+pub const PQ_ROOT_KEY_LEN: usize = 64;
+const PQ_X3DH_INFO: &[u8] = b"Construct-PQX3DH";
+const PQ_KEYS_BINDING_LABEL: &[u8] = b"Construct-PQX3DH-Keys";
 
-/// A post-quantum X3DH bundle, containing both classical and PQC keys.
+/// Data the classic signing key signs to vouch for the PQ keys of a bundle:
+/// a domain label, the suite and every PQ public key, each length-prefixed.
+/// Without it the Dilithium key would only vouch for itself.
+pub fn pq_keys_signed_data(
+    suite_id: SuiteID,
+    kyber_public_key: &[u8],
+    kyber_prekey_public: &[u8],
+    dilithium_verifying_key: &[u8],
+) -> Vec<u8> {
+    let mut data = PQ_KEYS_BINDING_LABEL.to_vec();
+    data.extend_from_slice(&suite_id.to_be_bytes());
+    for field in [kyber_public_key, kyber_prekey_public, dilithium_verifying_key] {
+        data.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        data.extend_from_slice(field);
+    }
+    data
+}
+
+/// Long-term post-quantum keys of a client: Kyber identity and prekey, Dilithium signing key.
+pub struct PqIdentityKeys {
+    kyber_public: kyber768::PublicKey,
+    kyber_secret: kyber768::SecretKey,
+    kyber_prekey_public: kyber768::PublicKey,
+    kyber_prekey_secret: kyber768::SecretKey,
+    dilithium_public: dilithium3::PublicKey,
+    dilithium_secret: dilithium3::SecretKey,
+}
+
+impl PqIdentityKeys {
+    pub fn generate() -> Self {
+        let (kyber_public, kyber_secret) = kyber768::keypair();
+        let (kyber_prekey_public, kyber_prekey_secret) = kyber768::keypair();
+        let (dilithium_public, dilithium_secret) = dilithium3::keypair();
+        Self {
+            kyber_public,
+            kyber_secret,
+            kyber_prekey_public,
+            kyber_prekey_secret,
+            dilithium_public,
+            dilithium_secret,
+        }
+    }
+
+    /// Public PQ half of a registration bundle. The Kyber prekey is signed with
+    /// Dilithium, and all PQ public keys are signed with the classic signing key
+    /// whose verifying key is in `classic`.
+    pub fn public_bundle<P: CryptoProvider>(
+        &self,
+        classic: PublicKeyBundle,
+        classic_signing_key: &P::SignaturePrivateKey,
+    ) -> Result<PQX3DHBundle, CryptoError> {
+        let pq_signature = dilithium3::detached_sign(self.kyber_prekey_public.as_bytes(), &self.dilithium_secret);
+        let signed_data = pq_keys_signed_data(
+            PQ_HYBRID_SUITE_ID,
+            self.kyber_public.as_bytes(),
+            self.kyber_prekey_public.as_bytes(),
+            self.dilithium_public.as_bytes(),
+        );
+        Ok(PQX3DHBundle {
+            suite_id: PQ_HYBRID_SUITE_ID,
+            classic,
+            kyber_public_key: self.kyber_public.as_bytes().to_vec(),
+            kyber_prekey_public: self.kyber_prekey_public.as_bytes().to_vec(),
+            pq_signature: pq_signature.as_bytes().to_vec(),
+            dilithium_verifying_key: self.dilithium_public.as_bytes().to_vec(),
+            pq_keys_signature: P::sign(classic_signing_key, &signed_data)?,
+        })
+    }
+
+    /// Responder side: recover both Kyber shared secrets from the initiation.
+    pub fn decapsulate(&self, initiation: &PQX3DHInitiation) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+        let identity_ciphertext = kyber768::Ciphertext::from_bytes(&initiation.kyber_identity_ciphertext)
+            .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Kyber identity ciphertext: {}", e)))?;
+        let prekey_ciphertext = kyber768::Ciphertext::from_bytes(&initiation.kyber_prekey_ciphertext)
+            .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Kyber prekey ciphertext: {}", e)))?;
+
+        let identity_shared = kyber768::decapsulate(&identity_ciphertext, &self.kyber_secret);
+        let prekey_shared = kyber768::decapsulate(&prekey_ciphertext, &self.kyber_prekey_secret);
+        Ok((identity_shared.as_bytes().to_vec(), prekey_shared.as_bytes().to_vec()))
+    }
+}
+
+/// A post-quantum X3DH bundle: the classic bundle of the client's suite plus PQC keys.
 pub struct PQX3DHBundle {
-    // Классические ключи (для обратной совместимости)
-    pub identity_public: [u8; 32],           // X25519
-    pub signed_prekey_public: [u8; 32],      // X25519
-    pub signature: [u8; 64],                 // Ed25519
-    
+    /// Always `PQ_HYBRID_SUITE_ID`; other suites are rejected by the handshake.
+    pub suite_id: SuiteID,
+    /// Classic keys (X3DH of the client's suite)
+    pub classic: PublicKeyBundle,
+
     // Пост-квантовые ключи
     pub kyber_public_key: Vec<u8>,           // Kyber-768 (1184 байт)
     pub kyber_prekey_public: Vec<u8>,        // Kyber для prekey
-    pub pq_signature: Vec<u8>,               // Dilithium подпись
+    pub pq_signature: Vec<u8>,               // Dilithium подпись Kyber prekey
+    pub dilithium_verifying_key: Vec<u8>,    // Dilithium3 (1952 байт)
+    /// Classic signing key signature over `pq_keys_signed_data`
+    pub pq_keys_signature: Vec<u8>,
+}
+
+/// What the initiator sends so the responder can derive the same root key.
+#[derive(Debug, Clone)]
+pub struct PQX3DHInitiation {
+    /// Classic X3DH ephemeral key
+    pub ephemeral_public: Vec<u8>,
+    pub one_time_prekey_id: Option<u32>,
+    pub kyber_identity_ciphertext: Vec<u8>,
+    pub kyber_prekey_ciphertext: Vec<u8>,
+}
+
+/// Initiator side of the PQ part: check that the classic signing key of the bundle
+/// vouches for its PQ keys and that Dilithium signed the Kyber prekey, then
+/// encapsulate against the Kyber identity key and prekey.
+/// Returns `(identity_shared, prekey_shared, identity_ciphertext, prekey_ciphertext)`.
+#[allow(clippy::type_complexity)]
+pub fn encapsulate<P: CryptoProvider>(
+    bundle: &PQX3DHBundle,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>, Vec<u8>), CryptoError> {
+    if bundle.suite_id != PQ_HYBRID_SUITE_ID {
+        return Err(CryptoError::SuiteMismatch {
+            local: PQ_HYBRID_SUITE_ID,
+            remote: bundle.suite_id,
+        });
+    }
+
+    let signed_data = pq_keys_signed_data(
+        bundle.suite_id,
+        &bundle.kyber_public_key,
+        &bundle.kyber_prekey_public,
+        &bundle.dilithium_verifying_key,
+    );
+    P::verify(
+        &P::signature_public_key_from_bytes(bundle.classic.verifying_key.clone()),
+        &signed_data,
+        &bundle.pq_keys_signature,
+    )
+    .map_err(|_| CryptoError::SignatureVerificationError("PQ keys are not signed by the identity key".to_string()))?;
+
+    let verifying_key = dilithium3::PublicKey::from_bytes(&bundle.dilithium_verifying_key)
+        .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Dilithium verifying key: {}", e)))?;
+    let signature = dilithium3::DetachedSignature::from_bytes(&bundle.pq_signature)
+        .map_err(|e| CryptoError::SignatureVerificationError(format!("Invalid Dilithium signature: {}", e)))?;
+    dilithium3::verify_detached_signature(&signature, &bundle.kyber_prekey_public, &verifying_key)
+        .map_err(|_| CryptoError::SignatureVerificationError("Kyber prekey signature is invalid".to_string()))?;
+
+    let identity_public = kyber768::PublicKey::from_bytes(&bundle.kyber_public_key)
+        .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Kyber identity key: {}", e)))?;
+    let prekey_public = kyber768::PublicKey::from_bytes(&bundle.kyber_prekey_public)
+        .map_err(|e| CryptoError::InvalidKeyData(format!("Invalid Kyber prekey: {}", e)))?;
+
+    let (identity_shared, identity_ciphertext) = kyber768::encapsulate(&identity_public);
+    let (prekey_shared, prekey_ciphertext) = kyber768::encapsulate(&prekey_public);
+    Ok((
+        identity_shared.as_bytes().to_vec(),
+        prekey_shared.as_bytes().to_vec(),
+        identity_ciphertext.as_bytes().to_vec(),
+        prekey_ciphertext.as_bytes().to_vec(),
+    ))
+}
+
+/// Combine the classic X3DH secret with both Kyber secrets:
+/// `HKDF(salt = 0xFF * 32, ikm = classic || kyber_identity || kyber_prekey)`.
+pub fn combine_secrets<P: CryptoProvider>(
+    classic_secret: &[u8],
+    kyber_identity_shared: &[u8],
+    kyber_prekey_shared: &[u8],
+) -> Result<[u8; PQ_ROOT_KEY_LEN], CryptoError> {
+    let mut ikm = Vec::with_capacity(classic_secret.len() + kyber_identity_shared.len() + kyber_prekey_shared.len());
+    ikm.extend_from_slice(classic_secret);
+    ikm.extend_from_slice(kyber_identity_shared);
+    ikm.extend_from_slice(kyber_prekey_shared);
+
+    let derived = P::hkdf_derive_key(&[0xFF; 32], &ikm, PQ_X3DH_INFO, PQ_ROOT_KEY_LEN);
+    ikm.zeroize();
+    let mut derived = derived?;
+
+    let mut root_key = [0u8; PQ_ROOT_KEY_LEN];
+    root_key.copy_from_slice(&derived);
+    derived.zeroize();
+    Ok(root_key)
 }
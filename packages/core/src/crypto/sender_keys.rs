@@ -0,0 +1,383 @@
+// Групповые сообщения: схема sender keys (Signal)
+// Каждый участник шифрует свои сообщения в группу одной цепочкой ключей и подписывает их.
+// Цепочку и ключ подписи он рассылает участникам попарно через Double Ratchet
+
+use crate::crypto::CryptoProvider;
+use crate::error::CryptoError;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use zeroize::Zeroize;
+
+/// Сколько ключей пропущенных сообщений одного отправителя хранится (защита от DoS)
+pub const MAX_SENDER_KEY_SKIP: u32 = 1000;
+const SENDER_KEY_NONCE_LEN: usize = 12;
+const SENDER_KEY_SIGNATURE_LABEL: &[u8] = b"construct-sender-key:";
+
+/// Цепочка отправителя для участников группы. Передается только внутри
+/// попарной Double Ratchet сессии: `chain_key` позволяет читать сообщения,
+/// начиная с `iteration`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderKeyDistributionMessage {
+    pub group_id: String,
+    pub iteration: u32,
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub chain_key: Vec<u8>,
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub signing_key: Vec<u8>,
+}
+
+/// Сообщение в группу: один шифртекст для всех участников
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderKeyMessage {
+    pub group_id: String,
+    pub iteration: u32,
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub ciphertext: Vec<u8>,
+    /// Подпись ключом отправителя из `SenderKeyDistributionMessage`
+    #[serde(with = "crate::utils::b64::bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl SenderKeyMessage {
+    /// Подписываемые данные: метка, длина и id группы, номер, nonce и шифртекст
+    fn signed_data(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            SENDER_KEY_SIGNATURE_LABEL.len() + 8 + self.group_id.len() + self.nonce.len() + self.ciphertext.len(),
+        );
+        out.extend_from_slice(SENDER_KEY_SIGNATURE_LABEL);
+        out.extend_from_slice(&(self.group_id.len() as u32).to_be_bytes());
+        out.extend_from_slice(self.group_id.as_bytes());
+        out.extend_from_slice(&self.iteration.to_be_bytes());
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+}
+
+/// AAD сообщения в группу: id группы и номер сообщения в цепочке
+fn sender_key_associated_data(group_id: &str, iteration: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(group_id.len() + 4);
+    out.extend_from_slice(group_id.as_bytes());
+    out.extend_from_slice(&iteration.to_be_bytes());
+    out
+}
+
+/// Цепочка одного отправителя в одной группе.
+/// Своя цепочка хранит ключ подписи, чужие - только публичный ключ
+pub struct SenderKeyState<P: CryptoProvider> {
+    group_id: String,
+    chain_key: P::AeadKey,
+    iteration: u32,
+    signing_public: P::SignaturePublicKey,
+    signing_private: Option<P::SignaturePrivateKey>,
+    skipped_keys: HashMap<u32, P::AeadKey>,
+}
+
+impl<P: CryptoProvider> SenderKeyState<P> {
+    /// Новая собственная цепочка со случайным chain key и ключом подписи
+    pub fn generate(group_id: impl Into<String>) -> Result<Self, CryptoError> {
+        let mut chain_key = vec![0u8; P::aead_key_len()];
+        P::fill_random(&mut chain_key)?;
+        let (signing_private, signing_public) = P::generate_signature_keys()?;
+
+        Ok(Self {
+            group_id: group_id.into(),
+            chain_key: P::aead_key_from_bytes(chain_key),
+            iteration: 0,
+            signing_public,
+            signing_private: Some(signing_private),
+            skipped_keys: HashMap::new(),
+        })
+    }
+
+    /// Цепочка отправителя из полученного `SenderKeyDistributionMessage`
+    pub fn from_distribution(message: &SenderKeyDistributionMessage) -> Result<Self, CryptoError> {
+        if message.chain_key.len() != P::aead_key_len() {
+            return Err(CryptoError::InvalidKeyData(format!(
+                "Sender chain key must be {} bytes, got {}",
+                P::aead_key_len(),
+                message.chain_key.len()
+            )));
+        }
+
+        Ok(Self {
+            group_id: message.group_id.clone(),
+            chain_key: P::aead_key_from_bytes(message.chain_key.clone()),
+            iteration: message.iteration,
+            signing_public: P::signature_public_key_from_bytes(message.signing_key.clone()),
+            signing_private: None,
+            skipped_keys: HashMap::new(),
+        })
+    }
+
+    /// Сообщение для рассылки цепочки участникам (с текущего номера)
+    pub fn create_sender_key_distribution_message(&self) -> SenderKeyDistributionMessage {
+        SenderKeyDistributionMessage {
+            group_id: self.group_id.clone(),
+            iteration: self.iteration,
+            chain_key: self.chain_key.as_ref().to_vec(),
+            signing_key: self.signing_public.as_ref().to_vec(),
+        }
+    }
+
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Номер следующего сообщения цепочки
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    /// Зашифровать и подписать сообщение (только своя цепочка)
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<SenderKeyMessage, CryptoError> {
+        let signing_private = self.signing_private.as_ref().ok_or_else(|| {
+            CryptoError::InvalidInputError("Cannot encrypt with a received sender key".to_string())
+        })?;
+
+        let (message_key, next_chain_key) = P::kdf_ck(&self.chain_key)?;
+        let iteration = self.iteration;
+        let nonce = P::generate_nonce(SENDER_KEY_NONCE_LEN)?;
+        let associated_data = sender_key_associated_data(&self.group_id, iteration);
+        let ciphertext = P::aead_encrypt(&message_key, &nonce, plaintext, Some(&associated_data))?;
+
+        let mut message = SenderKeyMessage {
+            group_id: self.group_id.clone(),
+            iteration,
+            nonce,
+            ciphertext,
+            signature: Vec::new(),
+        };
+        message.signature = P::sign(signing_private, &message.signed_data())?;
+
+        self.chain_key = next_chain_key;
+        self.iteration += 1;
+        Ok(message)
+    }
+
+    /// Проверить подпись и расшифровать сообщение отправителя.
+    /// Состояние меняется только после успешной расшифровки
+    pub fn decrypt(&mut self, message: &SenderKeyMessage) -> Result<Vec<u8>, CryptoError> {
+        if message.group_id != self.group_id {
+            return Err(CryptoError::InvalidInputError(format!(
+                "Sender key message for group {} does not belong to group {}",
+                message.group_id, self.group_id
+            )));
+        }
+        P::verify(&self.signing_public, &message.signed_data(), &message.signature)?;
+
+        let associated_data = sender_key_associated_data(&self.group_id, message.iteration);
+        if message.iteration < self.iteration {
+            let key = self.skipped_keys.get(&message.iteration).ok_or_else(|| {
+                CryptoError::AeadDecryptionError(format!(
+                    "No sender key for message {} (chain is at {})",
+                    message.iteration, self.iteration
+                ))
+            })?;
+            let plaintext = P::aead_decrypt(key, &message.nonce, &message.ciphertext, Some(&associated_data))?;
+            if let Some(mut key) = self.skipped_keys.remove(&message.iteration) {
+                key.zeroize();
+            }
+            return Ok(plaintext);
+        }
+
+        let to_skip = message.iteration - self.iteration;
+        if self.skipped_keys.len() + to_skip as usize > MAX_SENDER_KEY_SKIP as usize {
+            return Err(CryptoError::InvalidInputError(format!(
+                "Sender key message too far ahead: {} (chain is at {})",
+                message.iteration, self.iteration
+            )));
+        }
+
+        let mut chain_key = self.chain_key.clone();
+        let mut skipped = Vec::with_capacity(to_skip as usize);
+        for iteration in self.iteration..message.iteration {
+            let (message_key, next_chain_key) = P::kdf_ck(&chain_key)?;
+            skipped.push((iteration, message_key));
+            chain_key = next_chain_key;
+        }
+        let (message_key, next_chain_key) = P::kdf_ck(&chain_key)?;
+        let plaintext = P::aead_decrypt(&message_key, &message.nonce, &message.ciphertext, Some(&associated_data))?;
+
+        self.skipped_keys.extend(skipped);
+        self.chain_key = next_chain_key;
+        self.iteration = message.iteration + 1;
+        Ok(plaintext)
+    }
+}
+
+impl<P: CryptoProvider> Drop for SenderKeyState<P> {
+    fn drop(&mut self) {
+        self.chain_key.zeroize();
+        if let Some(private_key) = self.signing_private.as_mut() {
+            private_key.zeroize();
+        }
+        for key in self.skipped_keys.values_mut() {
+            key.zeroize();
+        }
+    }
+}
+
+/// Групповая сессия: своя цепочка, цепочки остальных участников
+/// и участники, которым своя цепочка уже разослана
+pub struct GroupSession<P: CryptoProvider> {
+    group_id: String,
+    members: BTreeSet<String>,
+    own: SenderKeyState<P>,
+    distributed_to: HashSet<String>,
+    senders: HashMap<String, SenderKeyState<P>>,
+}
+
+impl<P: CryptoProvider> GroupSession<P> {
+    /// Создать группу со своей новой цепочкой
+    pub fn new(group_id: impl Into<String>, members: impl IntoIterator<Item = String>) -> Result<Self, CryptoError> {
+        let group_id = group_id.into();
+        Ok(Self {
+            own: SenderKeyState::generate(group_id.clone())?,
+            group_id,
+            members: members.into_iter().collect(),
+            distributed_to: HashSet::new(),
+            senders: HashMap::new(),
+        })
+    }
+
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Участники группы (без себя), по алфавиту
+    pub fn members(&self) -> Vec<String> {
+        self.members.iter().cloned().collect()
+    }
+
+    pub fn is_member(&self, member_id: &str) -> bool {
+        self.members.contains(member_id)
+    }
+
+    /// Добавить участника; своя цепочка будет разослана ему перед следующим сообщением
+    pub fn add_member(&mut self, member_id: impl Into<String>) {
+        self.members.insert(member_id.into());
+    }
+
+    /// Удалить участника. Своя цепочка заменяется новой и рассылается заново,
+    /// чтобы удаленный участник не читал следующие сообщения
+    pub fn remove_member(&mut self, member_id: &str) -> Result<(), CryptoError> {
+        if !self.members.remove(member_id) {
+            return Ok(());
+        }
+        self.senders.remove(member_id);
+        self.own = SenderKeyState::generate(self.group_id.clone())?;
+        self.distributed_to.clear();
+        Ok(())
+    }
+
+    /// Сообщение со своей цепочкой для рассылки участникам
+    pub fn create_sender_key_distribution_message(&self) -> SenderKeyDistributionMessage {
+        self.own.create_sender_key_distribution_message()
+    }
+
+    /// Участники, которым своя цепочка еще не разослана
+    pub fn pending_distribution(&self) -> Vec<String> {
+        self.members
+            .iter()
+            .filter(|member| !self.distributed_to.contains(*member))
+            .cloned()
+            .collect()
+    }
+
+    /// Отметить, что участник получил свою цепочку
+    pub fn mark_distributed(&mut self, member_id: &str) {
+        self.distributed_to.insert(member_id.to_string());
+    }
+
+    /// Принять цепочку участника (заменяет предыдущую)
+    pub fn process_distribution(
+        &mut self,
+        sender_id: &str,
+        message: &SenderKeyDistributionMessage,
+    ) -> Result<(), CryptoError> {
+        if message.group_id != self.group_id || !self.members.contains(sender_id) {
+            return Err(CryptoError::InvalidInputError(format!(
+                "{} is not a member of group {}",
+                sender_id, message.group_id
+            )));
+        }
+        self.senders
+            .insert(sender_id.to_string(), SenderKeyState::from_distribution(message)?);
+        Ok(())
+    }
+
+    /// Есть ли цепочка участника
+    pub fn has_sender_key(&self, sender_id: &str) -> bool {
+        self.senders.contains_key(sender_id)
+    }
+
+    /// Зашифровать сообщение в группу своей цепочкой
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<SenderKeyMessage, CryptoError> {
+        self.own.encrypt(plaintext)
+    }
+
+    /// Расшифровать сообщение участника его цепочкой
+    pub fn decrypt(&mut self, sender_id: &str, message: &SenderKeyMessage) -> Result<Vec<u8>, CryptoError> {
+        let state = self.senders.get_mut(sender_id).ok_or_else(|| {
+            CryptoError::InvalidInputError(format!(
+                "No sender key from {} in group {}",
+                sender_id, self.group_id
+            ))
+        })?;
+        state.decrypt(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    type Group = GroupSession<ClassicSuiteProvider>;
+
+    #[test]
+    fn test_sender_key_out_of_order_and_forgery() {
+        let mut alice = Group::new("g1", ["bob".to_string()]).unwrap();
+        let mut bob = Group::new("g1", ["alice".to_string()]).unwrap();
+        bob.process_distribution("alice", &alice.create_sender_key_distribution_message())
+            .unwrap();
+
+        let first = alice.encrypt(b"one").unwrap();
+        let second = alice.encrypt(b"two").unwrap();
+        assert_eq!(bob.decrypt("alice", &second).unwrap(), b"two");
+        assert_eq!(bob.decrypt("alice", &first).unwrap(), b"one");
+        // Ключ пропущенного сообщения одноразовый
+        assert!(bob.decrypt("alice", &first).is_err());
+
+        // Подмена шифртекста ломает подпись
+        let mut forged = alice.encrypt(b"three").unwrap();
+        forged.ciphertext[0] ^= 0xff;
+        assert!(matches!(
+            bob.decrypt("alice", &forged),
+            Err(CryptoError::SignatureVerificationError(_))
+        ));
+
+        // Цепочку рассылает только участник группы
+        let mallory = Group::new("g1", Vec::new()).unwrap();
+        assert!(bob
+            .process_distribution("mallory", &mallory.create_sender_key_distribution_message())
+            .is_err());
+    }
+
+    #[test]
+    fn test_removed_member_gets_new_chain() {
+        let mut alice = Group::new("g1", ["bob".to_string(), "carol".to_string()]).unwrap();
+        alice.mark_distributed("bob");
+        alice.mark_distributed("carol");
+        let old = alice.create_sender_key_distribution_message();
+
+        alice.remove_member("carol").unwrap();
+        assert_ne!(alice.create_sender_key_distribution_message().chain_key, old.chain_key);
+        assert_eq!(alice.pending_distribution(), vec!["bob".to_string()]);
+    }
+}
@@ -1,12 +1,42 @@
 // Управление сессиями
 // Хранение и управление Double Ratchet сессиями для разных контактов
 
-use crate::crypto::double_ratchet::{DoubleRatchetSession, SerializableSession};
+use crate::crypto::double_ratchet::{DoubleRatchetSession, EncryptedRatchetMessage, SerializableSession};
+use crate::crypto::sender_keys::GroupSession;
 use crate::utils::error::{ConstructError, Result};
+use crate::utils::time::{system_clock, Clock};
 use std::collections::HashMap;
 use crate::crypto::CryptoProvider;
 use std::marker::PhantomData;
 
+/// Сколько секунд замененная сессия остается доступной для расшифровки сообщений в пути
+pub const DEFAULT_REKEY_GRACE_PERIOD: i64 = 300;
+
+/// Состояние установки сессии
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Сессия создана, но ответа от собеседника еще не было (half-open)
+    Established,
+    /// Состоялся хотя бы один успешный обмен сообщениями
+    Confirmed,
+}
+
+/// Результат массового импорта сессий
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// contact_id восстановленных сессий
+    pub imported: Vec<String>,
+    /// contact_id и причина для сессий, которые не удалось восстановить
+    pub failed: Vec<(String, String)>,
+}
+
+impl ImportReport {
+    /// Все сессии восстановлены
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
 /// Метаданные сессии
 pub struct SessionMetadata {
     pub session_id: String,
@@ -14,24 +44,40 @@ pub struct SessionMetadata {
     pub created_at: i64,
     pub last_used: i64,
     pub message_count: u64,
+    pub state: SessionState,
 }
 
 impl SessionMetadata {
     pub fn new(session_id: String, contact_id: String) -> Self {
-        let now = crate::utils::time::current_timestamp();
+        Self::new_at(session_id, contact_id, crate::utils::time::current_timestamp())
+    }
+
+    /// Создать метаданные с заданным временем создания
+    pub fn new_at(session_id: String, contact_id: String, now: i64) -> Self {
         Self {
             session_id,
             contact_id,
             created_at: now,
             last_used: now,
             message_count: 0,
+            state: SessionState::Established,
         }
     }
 
     pub fn update_last_used(&mut self) {
-        self.last_used = crate::utils::time::current_timestamp();
+        self.update_last_used_at(crate::utils::time::current_timestamp());
+    }
+
+    /// Обновить время использования заданным значением
+    pub fn update_last_used_at(&mut self, now: i64) {
+        self.last_used = now;
         self.message_count += 1;
     }
+
+    /// Подтверждена ли сессия ответом собеседника
+    pub fn is_confirmed(&self) -> bool {
+        self.state == SessionState::Confirmed
+    }
 }
 
 /// Хранилище сессий
@@ -40,14 +86,32 @@ pub struct SessionStore<P: CryptoProvider> {
     pub metadata: SessionMetadata,
 }
 
+/// Сессия, замененная при rekey
+struct RetiredSession<P: CryptoProvider> {
+    session: DoubleRatchetSession<P>,
+    retired_at: i64,
+}
+
 /// Менеджер Double Ratchet сессий
 pub struct SessionManager<P: CryptoProvider> {
     /// Активные сессии, индексированные по contact_id
     sessions: HashMap<String, SessionStore<P>>,
 
+    /// Замененные сессии, еще принимающие сообщения старой цепочки
+    retired: HashMap<String, RetiredSession<P>>,
+
+    /// Групповые сессии (sender keys), индексированные по group_id
+    groups: HashMap<String, GroupSession<P>>,
+
+    /// Сколько секунд замененная сессия остается доступной
+    grace_period: i64,
+
     /// Максимальное количество сохраненных сессий
     max_sessions: usize,
 
+    /// Источник времени (подменяется в тестах)
+    clock: Clock,
+
     _phantom: PhantomData<P>,
 }
 
@@ -56,7 +120,11 @@ impl<P: CryptoProvider> SessionManager<P> {
     pub fn new() -> Self {
         Self {
             sessions: HashMap::new(),
+            retired: HashMap::new(),
+            groups: HashMap::new(),
+            grace_period: DEFAULT_REKEY_GRACE_PERIOD,
             max_sessions: 100,
+            clock: system_clock(),
             _phantom: PhantomData,
         }
     }
@@ -65,20 +133,30 @@ impl<P: CryptoProvider> SessionManager<P> {
     pub fn with_capacity(max_sessions: usize) -> Self {
         Self {
             sessions: HashMap::new(),
+            retired: HashMap::new(),
+            groups: HashMap::new(),
+            grace_period: DEFAULT_REKEY_GRACE_PERIOD,
             max_sessions,
+            clock: system_clock(),
             _phantom: PhantomData,
         }
     }
 
+    /// Использовать другой источник времени
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// Добавить новую сессию
     pub fn add_session(&mut self, contact_id: String, session: DoubleRatchetSession<P>) -> Result<()> {
-        // Проверяем лимит сессий
-        if self.sessions.len() >= self.max_sessions {
+        // Проверяем лимит сессий (замена сессии контакта места не занимает)
+        if !self.sessions.contains_key(&contact_id) && self.sessions.len() >= self.max_sessions {
             self.cleanup_old_sessions()?;
         }
 
         let session_id = session.session_id().to_string();
-        let metadata = SessionMetadata::new(session_id, contact_id.clone());
+        let metadata = SessionMetadata::new_at(session_id, contact_id.clone(), (self.clock)());
 
         self.sessions.insert(
             contact_id,
@@ -91,6 +169,77 @@ impl<P: CryptoProvider> SessionManager<P> {
         Ok(())
     }
 
+    /// Задать период, в течение которого замененная сессия принимает сообщения
+    pub fn with_grace_period(mut self, seconds: i64) -> Self {
+        self.grace_period = seconds;
+        self
+    }
+
+    /// Атомарно заменить сессию контакта (rekey)
+    ///
+    /// Новая сессия сразу становится активной, а старая еще `grace_period` секунд
+    /// расшифровывает сообщения своей цепочки, отправленные до rekey.
+    pub fn replace_session(&mut self, contact_id: String, session: DoubleRatchetSession<P>) -> Result<()> {
+        if !self.sessions.contains_key(&contact_id) {
+            return self.add_session(contact_id, session);
+        }
+
+        let now = (self.clock)();
+        let metadata = SessionMetadata::new_at(session.session_id().to_string(), contact_id.clone(), now);
+        if let Some(old) = self.sessions.insert(contact_id.clone(), SessionStore { session, metadata }) {
+            self.retired.insert(
+                contact_id,
+                RetiredSession {
+                    session: old.session,
+                    retired_at: now,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Есть ли у контакта замененная сессия, еще принимающая сообщения
+    pub fn has_retired_session(&self, contact_id: &str) -> bool {
+        let now = (self.clock)();
+        self.retired
+            .get(contact_id)
+            .is_some_and(|retired| now - retired.retired_at < self.grace_period)
+    }
+
+    /// Расшифровать входящее сообщение контакта
+    ///
+    /// Сообщения старой цепочки, отправленные до rekey, расшифровываются
+    /// замененной сессией, пока не истек grace period.
+    pub fn decrypt_message(&mut self, contact_id: &str, encrypted: &EncryptedRatchetMessage) -> Result<Vec<u8>> {
+        self.purge_retired();
+
+        let for_retired = self.retired.get(contact_id).is_some_and(|retired| {
+            retired.session.is_current_receiving_chain(&encrypted.dh_public_key)
+        }) && !self.get_session(contact_id).is_some_and(|session| {
+            session.is_current_receiving_chain(&encrypted.dh_public_key)
+        });
+
+        let session = if for_retired {
+            self.retired.get_mut(contact_id).map(|retired| &mut retired.session)
+        } else {
+            self.get_session_mut(contact_id)
+        }
+        .ok_or_else(|| ConstructError::SessionError(format!("Session not found: {}", contact_id)))?;
+
+        session
+            .decrypt(encrypted)
+            .map_err(|e| ConstructError::CryptoError(e.into()))
+    }
+
+    /// Удалить замененные сессии с истекшим grace period
+    fn purge_retired(&mut self) {
+        let now = (self.clock)();
+        let grace_period = self.grace_period;
+        self.retired
+            .retain(|_, retired| now - retired.retired_at < grace_period);
+    }
+
     /// Получить сессию по contact_id
     pub fn get_session(&self, contact_id: &str) -> Option<&DoubleRatchetSession<P>> {
         self.sessions.get(contact_id).map(|store| &store.session)
@@ -98,12 +247,23 @@ impl<P: CryptoProvider> SessionManager<P> {
 
     /// Получить изменяемую сессию по contact_id
     pub fn get_session_mut(&mut self, contact_id: &str) -> Option<&mut DoubleRatchetSession<P>> {
+        let now = (self.clock)();
         self.sessions.get_mut(contact_id).map(|store| {
-            store.metadata.update_last_used();
+            store.metadata.update_last_used_at(now);
             &mut store.session
         })
     }
 
+    /// Отметить сессию подтвержденной (первый успешный обмен сообщениями)
+    pub fn confirm_session(&mut self, contact_id: &str) -> Result<()> {
+        let store = self
+            .sessions
+            .get_mut(contact_id)
+            .ok_or_else(|| ConstructError::SessionError(format!("Session not found: {}", contact_id)))?;
+        store.metadata.state = SessionState::Confirmed;
+        Ok(())
+    }
+
     /// Проверить наличие сессии
     pub fn has_session(&self, contact_id: &str) -> bool {
         self.sessions.contains_key(contact_id)
@@ -111,6 +271,7 @@ impl<P: CryptoProvider> SessionManager<P> {
 
     /// Удалить сессию
     pub fn remove_session(&mut self, contact_id: &str) -> Option<DoubleRatchetSession<P>> {
+        self.retired.remove(contact_id);
         self.sessions.remove(contact_id).map(|store| store.session)
     }
 
@@ -129,16 +290,29 @@ impl<P: CryptoProvider> SessionManager<P> {
         self.sessions.len()
     }
 
-    /// Очистка старых неиспользуемых сессий
+    /// Освободить место под новую сессию, вытеснив столько сессий, сколько нужно
+    /// для соблюдения лимита. Первыми вытесняются давно не использованные; при равном
+    /// `last_used` - более старые по `created_at`, затем с меньшим `message_count`.
+    /// Последний критерий - contact_id, чтобы выбор был детерминированным
     fn cleanup_old_sessions(&mut self) -> Result<()> {
-        // Находим самую старую неиспользуемую сессию
-        let oldest = self
+        let excess = (self.sessions.len() + 1).saturating_sub(self.max_sessions);
+        if excess == 0 {
+            return Ok(());
+        }
+
+        let mut candidates: Vec<(&String, &SessionMetadata)> = self
             .sessions
             .iter()
-            .min_by_key(|(_, store)| store.metadata.last_used)
-            .map(|(contact_id, _)| contact_id.clone());
-
-        if let Some(contact_id) = oldest {
+            .map(|(contact_id, store)| (contact_id, &store.metadata))
+            .collect();
+        candidates.sort_by_key(|(contact_id, m)| (m.last_used, m.created_at, m.message_count, *contact_id));
+
+        let evicted: Vec<String> = candidates
+            .into_iter()
+            .take(excess)
+            .map(|(contact_id, _)| contact_id.clone())
+            .collect();
+        for contact_id in evicted {
             self.sessions.remove(&contact_id);
         }
 
@@ -147,50 +321,84 @@ impl<P: CryptoProvider> SessionManager<P> {
 
     /// Очистка всех сессий старше определенного времени
     pub fn cleanup_sessions_older_than(&mut self, max_age_seconds: i64) {
-        let now = crate::utils::time::current_timestamp();
+        let now = (self.clock)();
         self.sessions
             .retain(|_, store| now - store.metadata.last_used < max_age_seconds);
     }
 
+    /// Удалить неподтвержденные (half-open) сессии, созданные более `older_than` секунд назад.
+    /// Возвращает количество удаленных сессий
+    pub fn cleanup_unconfirmed(&mut self, older_than: i64) -> usize {
+        let now = (self.clock)();
+        let before = self.sessions.len();
+        self.sessions.retain(|_, store| {
+            store.metadata.is_confirmed() || now - store.metadata.created_at < older_than
+        });
+        before - self.sessions.len()
+    }
+
     /// Сериализовать сессию для сохранения
     pub fn serialize_session(&self, contact_id: &str) -> Result<Vec<u8>> {
         let session = self
             .get_session(contact_id)
             .ok_or_else(|| ConstructError::SessionError(format!("Session not found: {}", contact_id)))?;
 
-        let serializable = session.to_serializable();
-        bincode::serialize(&serializable)
-            .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize session: {}", e)))
+        session
+            .to_serializable()
+            .to_bytes()
+            .map_err(|e| ConstructError::SerializationError(e.to_string()))
+    }
+
+    /// Декодировать сохраненную сессию.
+    /// Не обращается к менеджеру, поэтому может выполняться параллельно
+    pub fn decode_session(data: &[u8]) -> Result<DoubleRatchetSession<P>> {
+        let serializable = SerializableSession::from_bytes(data)
+            .map_err(|e| ConstructError::SerializationError(e.to_string()))?;
+
+        DoubleRatchetSession::<P>::from_serializable(serializable)
+            .map_err(|e| ConstructError::CryptoError(format!("Failed to restore session: {}", e)))
     }
 
     /// Десериализовать и восстановить сессию
     pub fn deserialize_session(&mut self, contact_id: String, data: &[u8]) -> Result<()> {
-        let serializable: SerializableSession = bincode::deserialize(data)
-            .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize session: {}", e)))?;
+        let session = Self::decode_session(data)?;
+        self.add_session(contact_id, session)
+    }
 
-        let session = DoubleRatchetSession::<P>::from_serializable(serializable)
-            .map_err(|e| ConstructError::CryptoError(format!("Failed to restore session: {}", e)))?;
-        self.add_session(contact_id, session)?;
+    /// Восстановить все сессии, которые удается декодировать.
+    /// Поврежденная сессия не прерывает импорт остальных и попадает в отчет
+    pub fn deserialize_all(&mut self, sessions: Vec<(String, Vec<u8>)>) -> ImportReport {
+        // 1. Декодирование (CPU-bound, независимо для каждой сессии)
+        let decoded: Vec<_> = sessions
+            .into_iter()
+            .map(|(contact_id, data)| (contact_id, Self::decode_session(&data)))
+            .collect();
+
+        // 2. Регистрация в менеджере
+        let mut report = ImportReport::default();
+        for (contact_id, session) in decoded {
+            match session.and_then(|session| self.add_session(contact_id.clone(), session)) {
+                Ok(()) => report.imported.push(contact_id),
+                Err(e) => report.failed.push((contact_id, e.to_string())),
+            }
+        }
 
-        Ok(())
+        report
     }
 
-    /// Экспорт всех сессий в формат для сохранения
-    pub fn export_all_sessions(&self) -> Result<HashMap<String, Vec<u8>>> {
-        let mut exported = HashMap::new();
-
-        for (contact_id, store) in &self.sessions {
-            let serializable = store.session.to_serializable();
-            let data = bincode::serialize(&serializable)
-                .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize session: {}", e)))?;
-            exported.insert(contact_id.clone(), data);
-        }
+    /// Экспорт всех сессий (отсортированы по contact_id - архивы воспроизводимы)
+    pub fn export_all_sessions(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut contact_ids: Vec<&String> = self.sessions.keys().collect();
+        contact_ids.sort();
 
-        Ok(exported)
+        contact_ids
+            .into_iter()
+            .map(|contact_id| Ok((contact_id.clone(), self.serialize_session(contact_id)?)))
+            .collect()
     }
 
     /// Импорт всех сессий
-    pub fn import_all_sessions(&mut self, sessions: HashMap<String, Vec<u8>>) -> Result<()> {
+    pub fn import_all_sessions(&mut self, sessions: Vec<(String, Vec<u8>)>) -> Result<()> {
         for (contact_id, data) in sessions {
             self.deserialize_session(contact_id, &data)?;
         }
@@ -198,9 +406,36 @@ impl<P: CryptoProvider> SessionManager<P> {
         Ok(())
     }
 
+    /// Добавить групповую сессию (заменяет сессию с тем же group_id)
+    pub fn add_group_session(&mut self, group: GroupSession<P>) {
+        self.groups.insert(group.group_id().to_string(), group);
+    }
+
+    pub fn get_group_session(&self, group_id: &str) -> Option<&GroupSession<P>> {
+        self.groups.get(group_id)
+    }
+
+    pub fn get_group_session_mut(&mut self, group_id: &str) -> Option<&mut GroupSession<P>> {
+        self.groups.get_mut(group_id)
+    }
+
+    /// Удалить групповую сессию
+    pub fn remove_group_session(&mut self, group_id: &str) -> Option<GroupSession<P>> {
+        self.groups.remove(group_id)
+    }
+
+    /// id групповых сессий по алфавиту
+    pub fn group_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.groups.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
     /// Очистить все сессии
     pub fn clear_all(&mut self) {
         self.sessions.clear();
+        self.retired.clear();
+        self.groups.clear();
     }
 }
 
@@ -216,24 +451,25 @@ mod tests {
     use crate::crypto::classic_suite::ClassicSuiteProvider;
     use x25519_dalek::{PublicKey, StaticSecret};
 
-    #[test]
-    fn test_session_manager_add_get() {
-        let mut manager = SessionManager::<ClassicSuiteProvider>::new();
-
+    /// Новая исходящая сессия с контактом на случайном identity ключе
+    fn new_session(contact_id: &str) -> DoubleRatchetSession<ClassicSuiteProvider> {
         let identity_secret = StaticSecret::random_from_rng(rand::rngs::OsRng);
         let identity_public = PublicKey::from(&identity_secret);
-        let root_key = [0u8; 32];
-
-        let session = DoubleRatchetSession::<ClassicSuiteProvider>::new_x3dh_session(
+        DoubleRatchetSession::<ClassicSuiteProvider>::new_x3dh_session(
             1,
-            &root_key,
+            &[0u8; 32],
             &identity_public.to_bytes().to_vec(),
-            &identity_secret.to_bytes().to_vec(),
-            "contact1".to_string(),
+            &identity_secret.to_bytes().to_vec().into(),
+            contact_id.to_string(),
         )
-        .unwrap();
+        .unwrap()
+    }
+
+    #[test]
+    fn test_session_manager_add_get() {
+        let mut manager = SessionManager::<ClassicSuiteProvider>::new();
 
-        manager.add_session("contact1".to_string(), session).unwrap();
+        manager.add_session("contact1".to_string(), new_session("contact1")).unwrap();
 
         assert!(manager.has_session("contact1"));
         assert_eq!(manager.session_count(), 1);
@@ -243,20 +479,7 @@ mod tests {
     fn test_session_manager_remove() {
         let mut manager = SessionManager::<ClassicSuiteProvider>::new();
 
-        let identity_secret = StaticSecret::random_from_rng(rand::rngs::OsRng);
-        let identity_public = PublicKey::from(&identity_secret);
-        let root_key = [0u8; 32];
-
-        let session = DoubleRatchetSession::<ClassicSuiteProvider>::new_x3dh_session(
-            1,
-            &root_key,
-            &identity_public.to_bytes().to_vec(),
-            &identity_secret.to_bytes().to_vec(),
-            "contact1".to_string(),
-        )
-        .unwrap();
-
-        manager.add_session("contact1".to_string(), session).unwrap();
+        manager.add_session("contact1".to_string(), new_session("contact1")).unwrap();
         assert!(manager.has_session("contact1"));
 
         manager.remove_session("contact1");
@@ -267,23 +490,193 @@ mod tests {
     fn test_session_manager_metadata() {
         let mut manager = SessionManager::<ClassicSuiteProvider>::new();
 
-        let identity_secret = StaticSecret::random_from_rng(rand::rngs::OsRng);
-        let identity_public = PublicKey::from(&identity_secret);
-        let root_key = [0u8; 32];
-
-        let session = DoubleRatchetSession::<ClassicSuiteProvider>::new_x3dh_session(
-            1,
-            &root_key,
-            &identity_public.to_bytes().to_vec(),
-            &identity_secret.to_bytes().to_vec(),
-            "contact1".to_string(),
-        )
-        .unwrap();
-
-        manager.add_session("contact1".to_string(), session).unwrap();
+        manager.add_session("contact1".to_string(), new_session("contact1")).unwrap();
 
         let metadata = manager.get_metadata("contact1").unwrap();
         assert_eq!(metadata.contact_id, "contact1");
         assert_eq!(metadata.message_count, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cleanup_unconfirmed_sessions() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let now = Arc::new(AtomicI64::new(1_000));
+        let clock_now = now.clone();
+        let mut manager = SessionManager::<ClassicSuiteProvider>::new()
+            .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+
+        for contact_id in ["half_open", "confirmed"] {
+            manager.add_session(contact_id.to_string(), new_session(contact_id)).unwrap();
+        }
+        manager.confirm_session("confirmed").unwrap();
+        assert!(manager.get_metadata("confirmed").unwrap().is_confirmed());
+        assert_eq!(
+            manager.get_metadata("half_open").unwrap().state,
+            SessionState::Established
+        );
+
+        // Таймаут еще не истек
+        now.store(1_000 + 299, Ordering::SeqCst);
+        assert_eq!(manager.cleanup_unconfirmed(300), 0);
+        assert_eq!(manager.session_count(), 2);
+
+        // Таймаут истек - удаляется только неподтвержденная сессия
+        now.store(1_000 + 300, Ordering::SeqCst);
+        assert_eq!(manager.cleanup_unconfirmed(300), 1);
+        assert!(!manager.has_session("half_open"));
+        assert!(manager.has_session("confirmed"));
+
+        assert!(manager.confirm_session("half_open").is_err());
+    }
+
+    #[test]
+    fn test_eviction_breaks_last_used_ties() {
+        use std::sync::Arc;
+
+        let mut manager = SessionManager::<ClassicSuiteProvider>::with_capacity(3)
+            .with_clock(Arc::new(|| 1_000));
+        // Все сессии использованы в один момент, как после массового восстановления
+        for contact_id in ["c1", "c2", "c3"] {
+            manager.add_session(contact_id.to_string(), new_session(contact_id)).unwrap();
+        }
+        for (contact_id, message_count) in [("c1", 5), ("c2", 1), ("c3", 2)] {
+            manager.sessions.get_mut(contact_id).unwrap().metadata.message_count = message_count;
+        }
+        manager.sessions.get_mut("c3").unwrap().metadata.created_at = 900;
+
+        // Раньше всех создана c3
+        manager.add_session("c4".to_string(), new_session("c4")).unwrap();
+        assert!(!manager.has_session("c3"));
+
+        // Равные created_at - вытесняется сессия с меньшим числом сообщений
+        manager.add_session("c5".to_string(), new_session("c5")).unwrap();
+        assert!(!manager.has_session("c4"));
+        assert_eq!(manager.session_count(), 3);
+
+        // Полностью равные метаданные - по contact_id
+        manager.sessions.get_mut("c5").unwrap().metadata.message_count = 1;
+        manager.add_session("c6".to_string(), new_session("c6")).unwrap();
+        assert!(!manager.has_session("c2"));
+        assert!(manager.has_session("c1") && manager.has_session("c5") && manager.has_session("c6"));
+
+        // Замена сессии контакта ничего не вытесняет
+        manager.add_session("c1".to_string(), new_session("c1")).unwrap();
+        assert_eq!(manager.session_count(), 3);
+
+        // Сверх лимита вытесняется сразу несколько сессий
+        for contact_id in ["x1", "x2"] {
+            let metadata = SessionMetadata::new_at(contact_id.to_string(), contact_id.to_string(), 500);
+            manager
+                .sessions
+                .insert(contact_id.to_string(), SessionStore { session: new_session(contact_id), metadata });
+        }
+        manager.add_session("c7".to_string(), new_session("c7")).unwrap();
+        assert_eq!(manager.session_count(), 3);
+        assert!(!manager.has_session("x1") && !manager.has_session("x2"));
+        // Замененная c1 начала счет сообщений заново
+        assert!(!manager.has_session("c1"));
+        assert!(manager.has_session("c5") && manager.has_session("c6") && manager.has_session("c7"));
+    }
+
+    #[test]
+    fn test_deserialize_all_skips_corrupt_sessions() {
+        let mut source = SessionManager::<ClassicSuiteProvider>::new();
+        for contact_id in ["alice", "bob"] {
+            source.add_session(contact_id.to_string(), new_session(contact_id)).unwrap();
+        }
+
+        let alice = source.serialize_session("alice").unwrap();
+        let bob = source.serialize_session("bob").unwrap();
+        let mut truncated = bob.clone();
+        truncated.truncate(bob.len() / 2);
+
+        let mut manager = SessionManager::<ClassicSuiteProvider>::new();
+        let report = manager.deserialize_all(vec![
+            ("alice".to_string(), alice),
+            ("garbage".to_string(), vec![0xff; 7]),
+            ("bob".to_string(), bob),
+            ("truncated".to_string(), truncated),
+        ]);
+
+        assert_eq!(report.imported, vec!["alice".to_string(), "bob".to_string()]);
+        assert!(!report.is_complete());
+        let failed: Vec<_> = report.failed.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(failed, vec!["garbage", "truncated"]);
+
+        assert!(manager.has_session("alice"));
+        assert!(manager.has_session("bob"));
+        assert_eq!(manager.session_count(), 2);
+    }
+
+    #[test]
+    fn test_export_all_sessions_is_deterministic() {
+        let mut manager = SessionManager::<ClassicSuiteProvider>::new();
+        for contact_id in ["carol", "alice", "dave", "bob"] {
+            manager.add_session(contact_id.to_string(), new_session(contact_id)).unwrap();
+        }
+
+        let first = manager.export_all_sessions().unwrap();
+        let second = manager.export_all_sessions().unwrap();
+        assert_eq!(bincode::serialize(&first).unwrap(), bincode::serialize(&second).unwrap());
+
+        let ids: Vec<_> = first.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["alice", "bob", "carol", "dave"]);
+
+        // Импорт в новый менеджер дает тот же экспорт
+        let mut restored = SessionManager::<ClassicSuiteProvider>::new();
+        restored.import_all_sessions(first.clone()).unwrap();
+        assert_eq!(restored.export_all_sessions().unwrap(), first);
+    }
+
+    #[test]
+    fn test_replace_session_keeps_old_chain_during_grace() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        type Session = DoubleRatchetSession<ClassicSuiteProvider>;
+
+        /// Новая пара сессий (X3DH) между Alice и Бобом; Боб уже получил первое сообщение
+        fn handshake(root_key: [u8; 32], first_text: &[u8]) -> (Session, Session, EncryptedRatchetMessage) {
+            let (alice_identity, _) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+            let (bob_identity, bob_identity_public) = ClassicSuiteProvider::generate_kem_keys().unwrap();
+            let mut alice =
+                Session::new_x3dh_session(1, &root_key, &bob_identity_public, &alice_identity, "bob".to_string())
+                    .unwrap();
+            let first = alice.encrypt(first_text).unwrap();
+            let bob = Session::new_receiving_session(1, &root_key, &bob_identity, &first, "alice".to_string())
+                .unwrap();
+            (alice, bob, first)
+        }
+
+        let now = Arc::new(AtomicI64::new(1_000));
+        let clock_now = now.clone();
+        let mut bob = SessionManager::<ClassicSuiteProvider>::new()
+            .with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)))
+            .with_grace_period(60);
+
+        let (mut alice_old, bob_old, first) = handshake([1u8; 32], b"old 1");
+        bob.add_session("alice".to_string(), bob_old).unwrap();
+        assert_eq!(bob.decrypt_message("alice", &first).unwrap(), b"old 1");
+
+        // Сообщения старой сессии еще в пути, когда происходит rekey
+        let in_flight = alice_old.encrypt(b"old 2").unwrap();
+        let late = alice_old.encrypt(b"old 3").unwrap();
+
+        let (mut alice_new, bob_new, new_first) = handshake([2u8; 32], b"new 1");
+        bob.replace_session("alice".to_string(), bob_new).unwrap();
+        assert!(bob.has_retired_session("alice"));
+        assert_eq!(bob.session_count(), 1);
+
+        assert_eq!(bob.decrypt_message("alice", &new_first).unwrap(), b"new 1");
+        assert_eq!(bob.decrypt_message("alice", &in_flight).unwrap(), b"old 2");
+        let next = alice_new.encrypt(b"new 2").unwrap();
+        assert_eq!(bob.decrypt_message("alice", &next).unwrap(), b"new 2");
+
+        // После grace period старая цепочка больше не принимается
+        now.store(1_000 + 60, Ordering::SeqCst);
+        assert!(!bob.has_retired_session("alice"));
+        assert!(bob.decrypt_message("alice", &late).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+// Транскрипт согласования suite: защита от downgrade
+// Обе стороны подтверждают MAC над параметрами согласования в первых сообщениях сессии
+
+use crate::crypto::crypto_provider::CryptoProvider;
+use crate::crypto::SuiteID;
+use crate::error::CryptoError;
+
+/// Version of the handshake transcript format.
+pub const HANDSHAKE_PROTOCOL_VERSION: u16 = 1;
+
+const TRANSCRIPT_LABEL: &[u8] = b"construct-handshake-transcript:";
+
+/// Negotiated parameters as seen by one side: the suites offered by the responder
+/// (in preference order), the suite chosen for the session and the protocol version.
+///
+/// An attacker who strips a stronger suite from the offer changes the initiator's
+/// view of the transcript, so the tags computed by both sides no longer match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeTranscript {
+    pub protocol_version: u16,
+    pub offered_suites: Vec<SuiteID>,
+    pub chosen_suite: SuiteID,
+}
+
+impl HandshakeTranscript {
+    pub fn new(offered_suites: Vec<SuiteID>, chosen_suite: SuiteID) -> Self {
+        Self {
+            protocol_version: HANDSHAKE_PROTOCOL_VERSION,
+            offered_suites,
+            chosen_suite,
+        }
+    }
+
+    /// Canonical encoding: label, version, suite count, offered suites, chosen suite (big-endian).
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(TRANSCRIPT_LABEL.len() + 8 + self.offered_suites.len() * 2);
+        out.extend_from_slice(TRANSCRIPT_LABEL);
+        out.extend_from_slice(&self.protocol_version.to_be_bytes());
+        out.extend_from_slice(&(self.offered_suites.len() as u32).to_be_bytes());
+        for suite in &self.offered_suites {
+            out.extend_from_slice(&suite.to_be_bytes());
+        }
+        out.extend_from_slice(&self.chosen_suite.to_be_bytes());
+        out
+    }
+
+    /// MAC of the transcript under a key derived from the X3DH root.
+    pub fn tag<P: CryptoProvider>(&self, root_key: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let transcript_key = P::hkdf_derive_key(b"", root_key, b"Handshake Transcript Key", 32)?;
+        P::mac(&transcript_key, &self.encode())
+    }
+
+    /// Checks a tag produced by the peer for its view of the transcript.
+    #[must_use = "a transcript mismatch means the suite negotiation was tampered with"]
+    pub fn verify<P: CryptoProvider>(&self, root_key: &[u8], tag: &[u8]) -> Result<(), CryptoError> {
+        if !tags_match(&self.tag::<P>(root_key)?, tag) {
+            return Err(CryptoError::TranscriptMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Constant-time tag comparison.
+pub(crate) fn tags_match(expected: &[u8], received: &[u8]) -> bool {
+    let diff = expected
+        .iter()
+        .zip(received)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    expected.len() == received.len() && diff == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+    use crate::crypto::{CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID, PQ_HYBRID_SUITE_ID};
+
+    #[test]
+    fn test_stripped_suite_detected() {
+        let root_key = [9u8; 32];
+        let offered = HandshakeTranscript::new(
+            vec![PQ_HYBRID_SUITE_ID, CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID],
+            CLASSIC_SUITE_ID,
+        );
+        let tag = offered.tag::<ClassicSuiteProvider>(&root_key).unwrap();
+        assert!(offered.verify::<ClassicSuiteProvider>(&root_key, &tag).is_ok());
+
+        // Сильный suite вырезан из предложения по пути к инициатору
+        let stripped = HandshakeTranscript::new(vec![CLASSIC_SUITE_ID], CLASSIC_SUITE_ID);
+        assert!(matches!(
+            stripped.verify::<ClassicSuiteProvider>(&root_key, &tag),
+            Err(CryptoError::TranscriptMismatch)
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 use crate::crypto::{CryptoProvider, SuiteID};
+use crate::error::CryptoStringError;
 use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PublicKeyBundle {
@@ -8,6 +10,14 @@ pub struct PublicKeyBundle {
     pub signature: Vec<u8>,
     pub verifying_key: Vec<u8>,
     pub suite_id: SuiteID,
+    /// Одноразовый prekey (DH4 в X3DH), если у получателя остались
+    #[serde(default)]
+    pub one_time_prekey_public: Option<Vec<u8>>,
+    #[serde(default)]
+    pub one_time_prekey_id: Option<u32>,
+    /// Id signed prekey (None - bundle старого формата)
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -19,34 +29,39 @@ pub struct RegistrationBundle {
     pub suite_id: SuiteID,
 }
 
+/// Результат X3DH инициатора: root key и ephemeral ключ для первого сообщения
+pub struct X3DHInitiation {
+    pub root_key: Vec<u8>,
+    pub ephemeral_public: Vec<u8>,
+}
+
+impl Drop for X3DHInitiation {
+    fn drop(&mut self) {
+        self.root_key.zeroize();
+    }
+}
+
 /// Чистая реализация X3DH протокола без состояния (generic по CryptoProvider)
 pub struct X3DH<P: CryptoProvider> {
     _phantom: std::marker::PhantomData<P>,
 }
 
 impl<P: CryptoProvider> X3DH<P> {
-    /// Выполняет X3DH обмен и возвращает root key
-    /// Упрощенная версия без ephemeral ключа
+    /// X3DH со стороны инициатора: проверяет подпись signed prekey, генерирует
+    /// ephemeral ключ и выводит root key из DH1..DH4:
+    /// DH1 = IK_a·SPK_b, DH2 = EK_a·IK_b, DH3 = EK_a·SPK_b, DH4 = EK_a·OPK_b (если есть OPK)
     pub fn perform_x3dh(
         identity_private: &P::KemPrivateKey,
-        _signed_prekey_private: &P::KemPrivateKey,
         remote_identity_public: &P::KemPublicKey,
         remote_signed_prekey_public: &P::KemPublicKey,
         remote_signature: &[u8],
         remote_verifying_key: &P::SignaturePublicKey,
+        remote_one_time_prekey_public: Option<&P::KemPublicKey>,
         _remote_suite_id: SuiteID,
-    ) -> Result<Vec<u8>, String> {
+    ) -> Result<X3DHInitiation, CryptoStringError> {
         eprintln!("[X3DH] perform_x3dh called");
-        eprintln!("[X3DH] remote_signature length: {}", remote_signature.len());
-        eprintln!("[X3DH] remote_signed_prekey_public length: {}", remote_signed_prekey_public.as_ref().len());
-        eprintln!("[X3DH] remote_verifying_key length: {}", remote_verifying_key.as_ref().len());
 
         // 1. Верификация подписи
-        eprintln!("[X3DH] Step 1: Starting signature verification...");
-        eprintln!("[X3DH] Data to verify (first 10 bytes): {:?}", &remote_signed_prekey_public.as_ref()[..10.min(remote_signed_prekey_public.as_ref().len())]);
-        eprintln!("[X3DH] Signature to verify (first 10 bytes): {:?}", &remote_signature[..10.min(remote_signature.len())]);
-        eprintln!("[X3DH] Verifying key (first 10 bytes): {:?}", &remote_verifying_key.as_ref()[..10.min(remote_verifying_key.as_ref().len())]);
-
         P::verify(
             remote_verifying_key,
             remote_signed_prekey_public.as_ref(),
@@ -56,39 +71,79 @@ impl<P: CryptoProvider> X3DH<P> {
             eprintln!("[X3DH] ERROR: Signature verification failed: {}", e);
             format!("Signature verification failed: {}", e)
         })?;
-        eprintln!("[X3DH] Step 1: Signature verified successfully");
-
-        // 2. KEM decapsulation для получения shared secret
-        // Для X25519 это будет DH, для PQ это будет KEM decapsulation
-        eprintln!("[X3DH] Step 2: Starting KEM decapsulation...");
-        eprintln!("[X3DH] remote_identity_public length: {}", remote_identity_public.as_ref().len());
-        let shared_secret = P::kem_decapsulate(identity_private, remote_identity_public.as_ref())
-            .map_err(|e| {
-                eprintln!("[X3DH] ERROR: KEM decapsulation failed: {}", e);
-                format!("KEM decapsulation failed: {}", e)
-            })?;
-        eprintln!("[X3DH] Step 2: KEM decapsulation completed, shared_secret length: {}", shared_secret.len());
-
-        // 3. Вывод root key через HKDF
-        eprintln!("[X3DH] Step 3: Starting HKDF derivation...");
+
+        // 2. Ephemeral ключ только для этого handshake
+        let (ephemeral_private, ephemeral_public) = P::generate_kem_keys()
+            .map_err(|e| format!("Failed to generate ephemeral key: {}", e))?;
+
+        // 3. DH1..DH4
+        let mut dh_outputs = vec![
+            Self::dh(identity_private, remote_signed_prekey_public)?,
+            Self::dh(&ephemeral_private, remote_identity_public)?,
+            Self::dh(&ephemeral_private, remote_signed_prekey_public)?,
+        ];
+        if let Some(one_time_prekey) = remote_one_time_prekey_public {
+            dh_outputs.push(Self::dh(&ephemeral_private, one_time_prekey)?);
+        }
+
+        let root_key = Self::derive_root_key(dh_outputs)?;
+
+        Ok(X3DHInitiation {
+            root_key,
+            ephemeral_public: ephemeral_public.as_ref().to_vec(),
+        })
+    }
+
+    /// X3DH со стороны получателя: те же DH1..DH4 из своих приватных ключей
+    /// и ephemeral ключа инициатора из первого сообщения
+    pub fn perform_x3dh_responder(
+        identity_private: &P::KemPrivateKey,
+        signed_prekey_private: &P::KemPrivateKey,
+        one_time_prekey_private: Option<&P::KemPrivateKey>,
+        remote_identity_public: &P::KemPublicKey,
+        remote_ephemeral_public: &P::KemPublicKey,
+    ) -> Result<Vec<u8>, CryptoStringError> {
+        let mut dh_outputs = vec![
+            Self::dh(signed_prekey_private, remote_identity_public)?,
+            Self::dh(identity_private, remote_ephemeral_public)?,
+            Self::dh(signed_prekey_private, remote_ephemeral_public)?,
+        ];
+        if let Some(one_time_prekey) = one_time_prekey_private {
+            dh_outputs.push(Self::dh(one_time_prekey, remote_ephemeral_public)?);
+        }
+
+        Self::derive_root_key(dh_outputs)
+    }
+
+    /// Для X25519 decapsulate - это DH(private, public)
+    fn dh(
+        private_key: &P::KemPrivateKey,
+        public_key: &P::KemPublicKey,
+    ) -> Result<Vec<u8>, CryptoStringError> {
+        P::kem_decapsulate(private_key, public_key.as_ref())
+            .map_err(|e| format!("KEM decapsulation failed: {}", e).into())
+    }
+
+    /// Root key = HKDF(DH1 || DH2 || DH3 [|| DH4]); промежуточные секреты обнуляются
+    fn derive_root_key(dh_outputs: Vec<Vec<u8>>) -> Result<Vec<u8>, CryptoStringError> {
+        let mut key_material = Zeroizing::new(Vec::new());
+        for mut output in dh_outputs {
+            key_material.extend_from_slice(&output);
+            output.zeroize();
+        }
+
         let root_key = P::hkdf_derive_key(
             b"", // no salt
-            &shared_secret,
+            &key_material,
             b"X3DH Root Key",
             32, // 32 bytes root key
         )
-        .map_err(|e| {
-            eprintln!("[X3DH] ERROR: HKDF derivation failed: {}", e);
-            format!("HKDF derivation failed: {}", e)
-        })?;
-        eprintln!("[X3DH] Step 3: HKDF derivation completed, root_key length: {}", root_key.len());
-
-        eprintln!("[X3DH] perform_x3dh completed successfully");
+        .map_err(|e| format!("HKDF derivation failed: {}", e))?;
         Ok(root_key)
     }
 
     /// Генерирует bundle для регистрации
-    pub fn generate_registration_bundle() -> Result<RegistrationBundle, String> {
+    pub fn generate_registration_bundle() -> Result<RegistrationBundle, CryptoStringError> {
         eprintln!("[X3DH] generate_registration_bundle called");
 
         // Генерируем ключи через CryptoProvider
@@ -121,3 +176,73 @@ impl<P: CryptoProvider> X3DH<P> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    type Suite = ClassicSuiteProvider;
+
+    #[test]
+    fn test_initiator_and_responder_derive_same_root() {
+        let (alice_identity, alice_identity_public) = Suite::generate_kem_keys().unwrap();
+        let (bob_identity, bob_identity_public) = Suite::generate_kem_keys().unwrap();
+        let (bob_signed_prekey, bob_signed_prekey_public) = Suite::generate_kem_keys().unwrap();
+        let (bob_one_time, bob_one_time_public) = Suite::generate_kem_keys().unwrap();
+        let (signing_key, verifying_key) = Suite::generate_signature_keys().unwrap();
+        let signature = Suite::sign(&signing_key, &bob_signed_prekey_public).unwrap();
+
+        for with_one_time in [false, true] {
+            let initiation = X3DH::<Suite>::perform_x3dh(
+                &alice_identity,
+                &bob_identity_public,
+                &bob_signed_prekey_public,
+                &signature,
+                &verifying_key,
+                with_one_time.then_some(&bob_one_time_public),
+                Suite::suite_id(),
+            )
+            .unwrap();
+            let ephemeral = Suite::kem_public_key_from_bytes(initiation.ephemeral_public.clone());
+
+            let root = X3DH::<Suite>::perform_x3dh_responder(
+                &bob_identity,
+                &bob_signed_prekey,
+                with_one_time.then_some(&bob_one_time),
+                &alice_identity_public,
+                &ephemeral,
+            )
+            .unwrap();
+            assert_eq!(root, initiation.root_key);
+
+            // Без DH4 (или с ним, если инициатор его не делал) root другой
+            let mismatched = X3DH::<Suite>::perform_x3dh_responder(
+                &bob_identity,
+                &bob_signed_prekey,
+                (!with_one_time).then_some(&bob_one_time),
+                &alice_identity_public,
+                &ephemeral,
+            )
+            .unwrap();
+            assert_ne!(mismatched, initiation.root_key);
+        }
+
+        // Каждый handshake - новый ephemeral ключ и новый root
+        let initiate = || {
+            X3DH::<Suite>::perform_x3dh(
+                &alice_identity,
+                &bob_identity_public,
+                &bob_signed_prekey_public,
+                &signature,
+                &verifying_key,
+                None,
+                Suite::suite_id(),
+            )
+            .unwrap()
+        };
+        let (first, second) = (initiate(), initiate());
+        assert_ne!(first.ephemeral_public, second.ephemeral_public);
+        assert_ne!(first.root_key, second.root_key);
+    }
+}
@@ -8,6 +8,8 @@ pub enum CryptoError {
     SigningError(String),
     #[error("Signature verification failed: {0}")]
     SignatureVerificationError(String),
+    #[error("Signature verification failed at batch index {index}: {reason}")]
+    BatchVerificationError { index: usize, reason: String },
     #[error("KEM encapsulation failed: {0}")]
     KemEncapsulationError(String),
     #[error("KEM decapsulation failed: {0}")]
@@ -20,12 +22,24 @@ pub enum CryptoError {
     KeyDerivationError(String),
     #[error("Nonce generation failed: {0}")]
     NonceGenerationError(String),
+    #[error("Randomness source failure: {0}")]
+    RandomnessFailure(String),
     #[error("Invalid input: {0}")]
     InvalidInputError(String),
+    #[error("Invalid key data: {0}")]
+    InvalidKeyData(String),
     #[error("Serialization error: {0}")]
     SerializationError(String),
     #[error("Deserialization error: {0}")]
     DeserializationError(String),
+    #[error("key confirmation failed")]
+    KeyConfirmationFailed,
+    #[error("handshake transcript mismatch")]
+    TranscriptMismatch,
+    #[error("Suite mismatch: local={local} remote={remote}")]
+    SuiteMismatch { local: u16, remote: u16 },
+    #[error("post-quantum suite not supported in this build")]
+    PostQuantumUnsupported,
     #[error("Other crypto error: {0}")]
     Other(String),
 }
@@ -44,6 +58,73 @@ impl From<ed25519_dalek::SignatureError> for CryptoError {
 
 impl From<rand::Error> for CryptoError {
     fn from(err: rand::Error) -> Self {
-        CryptoError::KeyGenerationError(err.to_string()) // General RNG error
+        CryptoError::RandomnessFailure(err.to_string())
+    }
+}
+
+/// String error of the crypto layer (sessions, `ClientCrypto`, X3DH).
+/// Transitional type until those APIs return `CryptoError`: it keeps the
+/// message but works with `?` and `std::error::Error`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{0}")]
+pub struct CryptoStringError(pub String);
+
+impl CryptoStringError {
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for CryptoStringError {
+    fn from(message: String) -> Self {
+        CryptoStringError(message)
+    }
+}
+
+impl From<&str> for CryptoStringError {
+    fn from(message: &str) -> Self {
+        CryptoStringError(message.to_string())
+    }
+}
+
+impl From<CryptoError> for CryptoStringError {
+    fn from(err: CryptoError) -> Self {
+        CryptoStringError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+    use crate::crypto::ClientCrypto;
+    use crate::utils::error::{ConstructError, Result};
+
+    fn export_missing(client: &ClientCrypto<ClassicSuiteProvider>) -> Result<Vec<u8>> {
+        Ok(client.export_session("missing")?)
+    }
+
+    #[test]
+    fn test_crypto_string_error_propagates_message() {
+        let client = ClientCrypto::<ClassicSuiteProvider>::new().unwrap();
+        let err = client.export_session("missing").unwrap_err();
+        assert_eq!(err.message(), "Session not found: missing");
+
+        // Работает как std::error::Error
+        let boxed: Box<dyn std::error::Error> = Box::new(err.clone());
+        assert_eq!(boxed.to_string(), "Session not found: missing");
+        assert!(boxed.source().is_none());
+
+        // `?` переводит ошибку в ConstructError без потери сообщения
+        match export_missing(&client) {
+            Err(ConstructError::CryptoError(message)) => assert_eq!(message, err.message()),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        let from_enum = CryptoStringError::from(CryptoError::KeyConfirmationFailed);
+        assert_eq!(
+            ConstructError::from(from_enum).to_string(),
+            "Cryptography error: key confirmation failed"
+        );
     }
 }
@@ -10,7 +10,7 @@ pub mod uniffi_bindings;
 
 // Re-export UniFFI types at crate root so scaffolding can find them
 #[cfg(not(target_arch = "wasm32"))]
-pub use uniffi_bindings::{ClassicCryptoCore, CryptoError, EncryptedMessageComponents, RegistrationBundleJson, create_crypto_core};
+pub use uniffi_bindings::{ClassicCryptoCore, CryptoError, EncryptedMessageComponents, RegistrationBundleJson, combined_safety_number, create_crypto_core};
 
 // Include UniFFI scaffolding generated from construct_core.udl
 #[cfg(not(target_arch = "wasm32"))]
@@ -66,5 +66,7 @@ pub use wasm::bindings::{
     encrypt_message,
     decrypt_message,
     destroy_client,
+    on_inbound_message,
+    take_inbound,
 };
 
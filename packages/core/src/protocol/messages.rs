@@ -1,6 +1,10 @@
 // Типы сообщений протокола
 // Соответствуют спецификации WebSocket API
 
+use crate::crypto::double_ratchet::EncryptedRatchetMessage;
+use crate::crypto::sender_keys::{SenderKeyDistributionMessage, SenderKeyMessage};
+use crate::utils::error::{ConstructError, Result};
+use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
 
 /// Основной тип сообщения для чата (Double Ratchet совместимый)
@@ -13,19 +17,191 @@ pub struct ChatMessage {
     pub from: String,
     /// UUID получателя
     pub to: String,
-    /// X25519 ephemeral public key (32 bytes)
-    #[serde(with = "serde_bytes")]
-    pub ephemeral_public_key: Vec<u8>,
+    /// Текущий DH ratchet public key отправителя (X25519, 32 bytes).
+    /// Это не эфемерный ключ X3DH: ключ меняется на каждом DH шаге и одинаков
+    /// для всех сообщений одной цепочки. Имя на проводе сохранено для совместимости с сервером
+    #[serde(rename = "ephemeralPublicKey", with = "serde_bytes")]
+    pub ratchet_dh_public: Vec<u8>,
     /// Номер сообщения в цепочке
     pub message_number: u32,
     /// Зашифрованное содержимое (ChaCha20-Poly1305)
     pub content: String, // Base64 encoded
     /// Unix timestamp в секундах
     pub timestamp: u64,
+    /// Назначенный сервером номер в беседе (монотонно растет); отсутствует в исходящих
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl ChatMessage {
+    /// Упаковать зашифрованное Double Ratchet сообщение.
+    /// `content` - Base64 от `EncryptedRatchetMessage::to_wire_bytes`; DH ключ и номер
+    /// дублируются в открытых полях для запросов повторной отправки. При шифровании
+    /// заголовков открытые поля пустые (ключ пустой, номер 0) и ничего не раскрывают
+    pub fn from_encrypted(from: &str, to: &str, encrypted: &EncryptedRatchetMessage) -> Result<Self> {
+        Self::from_encrypted_at(from, to, encrypted, crate::utils::time::now())
+    }
+
+    /// Упаковать сообщение с заданным `timestamp` (если он привязан к AAD при шифровании)
+    pub fn from_encrypted_at(
+        from: &str,
+        to: &str,
+        encrypted: &EncryptedRatchetMessage,
+        timestamp: u64,
+    ) -> Result<Self> {
+        let bytes = encrypted
+            .to_wire_bytes()
+            .map_err(|e| ConstructError::SerializationError(e.to_string()))?;
+
+        Ok(Self {
+            id: crate::utils::uuid::generate_v4(),
+            from: from.to_string(),
+            to: to.to_string(),
+            ratchet_dh_public: encrypted.dh_public_key.clone(),
+            message_number: encrypted.message_number,
+            content: general_purpose::STANDARD.encode(bytes),
+            timestamp,
+            seq: None,
+        })
+    }
+
+    /// Распаковать `EncryptedRatchetMessage` из `content`.
+    /// Открытые поля заголовка должны совпадать с упакованными
+    /// (см. `validation::verify_chat_message_integrity`)
+    pub fn to_encrypted(&self) -> Result<EncryptedRatchetMessage> {
+        crate::protocol::validation::verify_chat_message_integrity(self)
+    }
+}
+
+/// Содержимое сообщения до шифрования Double Ratchet.
+/// Сериализуется в JSON; всё внутри (включая цитату) остается E2E-зашифрованным
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum MessageBody {
+    Text {
+        text: String,
+        /// ID цитируемого сообщения
+        #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<String>,
+    },
+    /// Служебное: отправитель завершил сессию (экстренная смена ключей).
+    /// Идет через старую сессию, поэтому подделать его без ее ключей нельзя
+    SessionReset,
+    /// Служебное: цепочка sender key отправителя для группы (`crypto::sender_keys`).
+    /// Передается только попарно, chain key не должен покидать Double Ratchet
+    SenderKeyDistribution { distribution: SenderKeyDistributionMessage },
+    /// Служебное: отправитель просит прислать цепочку для группы
+    /// (вступил позже или потерял состояние)
+    #[serde(rename_all = "camelCase")]
+    SenderKeyRequest { group_id: String },
+}
+
+impl MessageBody {
+    /// Обычное текстовое сообщение
+    pub fn new_text(text: impl Into<String>) -> Self {
+        MessageBody::Text {
+            text: text.into(),
+            reply_to: None,
+        }
+    }
+
+    /// Ответ на сообщение `reply_to`
+    pub fn reply(text: impl Into<String>, reply_to: impl Into<String>) -> Self {
+        MessageBody::Text {
+            text: text.into(),
+            reply_to: Some(reply_to.into()),
+        }
+    }
+
+    /// Текст сообщения
+    pub fn as_text(&self) -> &str {
+        match self {
+            MessageBody::Text { text, .. } => text,
+            _ => "",
+        }
+    }
+
+    /// ID цитируемого сообщения
+    pub fn reply_to(&self) -> Option<&str> {
+        match self {
+            MessageBody::Text { reply_to, .. } => reply_to.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Сериализовать для шифрования
+    pub fn to_plaintext(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(|e| {
+            ConstructError::SerializationError(format!("Failed to serialize message body: {}", e))
+        })
+    }
+
+    /// Разобрать расшифрованный plaintext.
+    /// Старые клиенты шифруют просто текст - он становится `Text` без цитаты
+    pub fn from_plaintext(plaintext: &[u8]) -> Result<Self> {
+        if let Ok(body) = serde_json::from_slice(plaintext) {
+            return Ok(body);
+        }
+
+        let text = String::from_utf8(plaintext.to_vec())
+            .map_err(|e| ConstructError::SerializationError(format!("Invalid UTF-8: {}", e)))?;
+        Ok(MessageBody::new_text(text))
+    }
+}
+
+/// Служебные сообщения между клиентами, которые публикуются и сверяются вне чата
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ProtocolMessage {
+    /// Хеш bundle пользователя, который видел отправитель (`KeyBundle::content_hash`).
+    /// Разные хеши для одного identity у разных клиентов - признак подмены ключей сервером
+    #[serde(rename_all = "camelCase")]
+    KeyDigest { user_id: String, bundle_hash: String },
+    /// Новое устройство просит привязать его к аккаунту: одноразовый KEM ключ,
+    /// на который основное устройство шифрует identity и сессии
+    #[serde(rename_all = "camelCase")]
+    DeviceLinkRequest {
+        #[serde(with = "crate::utils::b64::bytes")]
+        new_device_pubkey: Vec<u8>,
+    },
+    /// Ответ основного устройства: identity ключи, контакты, сообщения и сессии,
+    /// зашифрованные на ключ из `DeviceLinkRequest`
+    #[serde(rename_all = "camelCase")]
+    DeviceLinkResponse {
+        #[serde(with = "crate::utils::b64::bytes")]
+        encrypted_identity_bundle: Vec<u8>,
+    },
+    /// Собеседник прочитал сообщения. `conversation_id` - user id читателя,
+    /// то есть беседа, в которой эти сообщения хранятся у получателя квитанции
+    #[serde(rename_all = "camelCase")]
+    ReadReceipt {
+        message_ids: Vec<String>,
+        conversation_id: String,
+        timestamp: i64,
+    },
+    /// Собеседник набирает (или перестал набирать) текст. Эфемерное: не сохраняется,
+    /// `conversation_id` - user id набирающего
+    #[serde(rename_all = "camelCase")]
+    Typing { conversation_id: String, is_typing: bool },
+    /// Сообщение в группу: один шифртекст sender key, который доставляется
+    /// каждому участнику `message.group_id`
+    #[serde(rename_all = "camelCase")]
+    GroupMessage { sender_id: String, message: SenderKeyMessage },
+    /// Отправитель сменил identity ключ. `signature_over_old` - base64 подпись
+    /// `keys::identity_rotation_signed_data(new_bundle)` старым signing ключом:
+    /// доказательство, что смену выполнил владелец прежнего ключа
+    #[serde(rename_all = "camelCase")]
+    IdentityRotation {
+        new_bundle: RegistrationBundle,
+        signature_over_old: String,
+    },
+    /// Новый signed prekey после ротации: регистрационный bundle с ним.
+    /// Уходит на сервер в `ClientMessage::RotatePrekey`
+    UpdatePrekey(RegistrationBundle),
 }
 
 /// Регистрационный bundle с публичными ключами
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RegistrationBundle {
     /// Base64 X25519 identity public key (44 chars)
@@ -38,6 +214,9 @@ pub struct RegistrationBundle {
     pub verifying_key: String,
     /// Suite ID (crypto suite identifier)
     pub suite_id: String,
+    /// Id signed prekey: сервер отдает его в `PublicKeyBundleData`
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
 }
 
 /// Публичная информация о пользователе
@@ -62,6 +241,19 @@ pub struct PublicKeyBundleData {
     pub signature: String,
     /// Base64 verifying key
     pub verifying_key: String,
+    // Optional поля всегда сериализуются: MessagePack кодирует структуры массивом,
+    // и пропуск поля сдвинул бы следующие. `default` - для старых серверов без них
+    /// Suite ID (отсутствует у старых серверов - считается классическим)
+    #[serde(default)]
+    pub suite_id: Option<String>,
+    /// Base64 одноразовый prekey (сервер выдает каждый не более одного раза)
+    #[serde(default)]
+    pub one_time_prekey_public: Option<String>,
+    #[serde(default)]
+    pub one_time_prekey_id: Option<u32>,
+    /// Id signed prekey (отсутствует у старых серверов)
+    #[serde(default)]
+    pub signed_prekey_id: Option<u32>,
 }
 
 /// Успешная регистрация (ответ сервера)
@@ -84,6 +276,30 @@ pub struct LoginSuccessData {
     pub expires: i64,
 }
 
+/// Ответ сервера на Register (успех или отказ)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisterResponseData {
+    /// UUID, назначенный сервером (только при успехе). Сериализуется и как `None`:
+    /// поле не последнее, а MessagePack кодирует структуру массивом
+    #[serde(default)]
+    pub user_id: Option<String>,
+    pub success: bool,
+    /// Причина отказа (только при неудаче)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Ответ сервера на Login (успех или отказ)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginResponseData {
+    pub success: bool,
+    /// Причина отказа (только при неудаче)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 /// Подтверждение получения сообщения
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -150,10 +366,29 @@ pub struct GetPublicKeyData {
 #[serde(rename_all = "camelCase")]
 pub struct RotatePrekeyData {
     pub user_id: String,
-    /// Base64-encoded MessagePack of SignedPrekeyUpdate
+    /// Base64-encoded MessagePack of `ProtocolMessage::UpdatePrekey`
     pub update: String,
 }
 
+/// Пополнение одноразовых prekey на сервере
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadOneTimePrekeysData {
+    pub user_id: String,
+    pub prekeys: Vec<OneTimePrekeyData>,
+}
+
+/// Одноразовый prekey для публикации на сервере
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OneTimePrekeyData {
+    pub key_id: u32,
+    /// Base64 публичный ключ
+    pub public_key: String,
+    /// Base64 подпись публичного ключа signing ключом
+    pub signature: String,
+}
+
 /// Данные для выхода
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -161,6 +396,41 @@ pub struct LogoutData {
     pub session_token: String,
 }
 
+/// Загрузка зашифрованного бэкапа на сервер
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupUploadData {
+    /// Архив, зашифрованный на клиенте паролем пользователя
+    #[serde(with = "serde_bytes")]
+    pub encrypted_blob: Vec<u8>,
+    /// Версия формата архива
+    pub version: u32,
+}
+
+/// Запрос сохраненного бэкапа у сервера
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupDownloadRequestData {
+    /// Максимальная версия формата архива, которую понимает клиент
+    pub max_version: u32,
+}
+
+/// Запрос повторной отправки потерянных сообщений.
+/// Сервер пересылает его собеседнику как `ServerMessage::RequestResend`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestResendData {
+    /// UUID запрашивающего (получателя потерянных сообщений)
+    pub from: String,
+    /// UUID отправителя потерянных сообщений
+    pub to: String,
+    /// DH ключ цепочки отправителя, к которой относятся номера
+    /// (номера сообщений повторяются в каждой цепочке)
+    pub ratchet_dh_public: Vec<u8>,
+    /// Номера сообщений в цепочке Double Ratchet
+    pub message_numbers: Vec<u32>,
+}
+
 /// Типы сообщений WebSocket протокола (клиент -> сервер)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload", rename_all = "camelCase")]
@@ -172,7 +442,11 @@ pub enum ClientMessage {
     GetPublicKey(GetPublicKeyData),
     SendMessage(ChatMessage),
     RotatePrekey(RotatePrekeyData),
+    UploadOneTimePrekeys(UploadOneTimePrekeysData),
     Logout(LogoutData),
+    BackupUpload(BackupUploadData),
+    BackupDownloadRequest(BackupDownloadRequestData),
+    RequestResend(RequestResendData),
 }
 
 // ============================================================================
@@ -194,12 +468,25 @@ pub struct SearchResultsData {
     pub users: Vec<PublicUserInfo>,
 }
 
+/// Зашифрованный бэкап, возвращенный сервером
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupDownloadResponseData {
+    /// Архив, зашифрованный на клиенте паролем пользователя
+    #[serde(with = "serde_bytes")]
+    pub encrypted_blob: Vec<u8>,
+    /// Версия формата архива
+    pub version: u32,
+}
+
 /// Типы сообщений от сервера (сервер -> клиент)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload", rename_all = "camelCase")]
 pub enum ServerMessage {
     RegisterSuccess(RegisterSuccessData),
     LoginSuccess(LoginSuccessData),
+    RegisterResponse(RegisterResponseData),
+    LoginResponse(LoginResponseData),
     ConnectSuccess(ConnectSuccessData),
     SessionExpired,
     SearchResults(SearchResultsData),
@@ -209,4 +496,104 @@ pub enum ServerMessage {
     KeyRotationSuccess,
     Error(ErrorData),
     LogoutSuccess,
+    BackupDownloadResponse(BackupDownloadResponseData),
+    RequestResend(RequestResendData),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_encrypted() -> EncryptedRatchetMessage {
+        EncryptedRatchetMessage {
+            dh_public_key: vec![3u8; 32],
+            message_number: 7,
+            ciphertext: vec![1, 2, 3, 4],
+            nonce: vec![9u8; 12],
+            previous_chain_length: 2,
+            suite_id: 1,
+            key_confirmation: None,
+            x3dh_ephemeral_key: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            encrypted_header: None,
+        }
+    }
+
+    #[test]
+    fn test_chat_message_encrypted_roundtrip() {
+        let encrypted = sample_encrypted();
+        let msg = ChatMessage::from_encrypted("alice", "bob", &encrypted).unwrap();
+        assert_eq!(msg.from, "alice");
+        assert_eq!(msg.to, "bob");
+        assert_eq!(msg.ratchet_dh_public, encrypted.dh_public_key);
+        assert_eq!(msg.message_number, 7);
+
+        let decoded = msg.to_encrypted().unwrap();
+        assert_eq!(decoded.dh_public_key, encrypted.dh_public_key);
+        assert_eq!(decoded.message_number, encrypted.message_number);
+        assert_eq!(decoded.ciphertext, encrypted.ciphertext);
+        assert_eq!(decoded.nonce, encrypted.nonce);
+        assert_eq!(decoded.previous_chain_length, encrypted.previous_chain_length);
+        assert_eq!(decoded.suite_id, encrypted.suite_id);
+    }
+
+    #[test]
+    fn test_chat_message_rejects_bad_content() {
+        let mut msg = ChatMessage::from_encrypted("alice", "bob", &sample_encrypted()).unwrap();
+
+        // Открытый заголовок расходится с зашифрованным содержимым
+        msg.message_number += 1;
+        assert!(matches!(msg.to_encrypted(), Err(ConstructError::ValidationError(_))));
+
+        msg.content = "not base64!".to_string();
+        assert!(matches!(msg.to_encrypted(), Err(ConstructError::SerializationError(_))));
+    }
+
+    #[test]
+    fn test_ratchet_dh_public_follows_sender_ratchet() {
+        use crate::api::crypto::CryptoCore;
+        use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+        let mut alice = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let alice_bundle = alice.export_registration_bundle().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        let alice_session = alice.init_session("bob", &bob_bundle).unwrap();
+        let sender_dh = |core: &CryptoCore<ClassicSuiteProvider>, session: &str| {
+            core.client().session(session).unwrap().dh_public_key().to_vec()
+        };
+        let send = |core: &mut CryptoCore<ClassicSuiteProvider>, session: &str, text: &str| {
+            let encrypted = core.encrypt_message(session, text).unwrap();
+            ChatMessage::from_encrypted("x", "y", &encrypted).unwrap()
+        };
+
+        // Первое сообщение несет DH ключ ratchet, а не X3DH ephemeral
+        let first = send(&mut alice, &alice_session, "one");
+        assert_eq!(first.ratchet_dh_public, sender_dh(&alice, &alice_session));
+        // Сообщения одной цепочки несут один и тот же ключ
+        let second = send(&mut alice, &alice_session, "two");
+        assert_eq!(second.ratchet_dh_public, first.ratchet_dh_public);
+
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &first.to_encrypted().unwrap())
+            .unwrap();
+        bob.decrypt_message(&bob_session, &first.to_encrypted().unwrap())
+            .unwrap();
+        let reply = send(&mut bob, &bob_session, "reply");
+        alice
+            .decrypt_message(&alice_session, &reply.to_encrypted().unwrap())
+            .unwrap();
+
+        // После DH шага - новый ключ
+        let third = send(&mut alice, &alice_session, "three");
+        assert_ne!(third.ratchet_dh_public, first.ratchet_dh_public);
+        assert_eq!(third.ratchet_dh_public, sender_dh(&alice, &alice_session));
+
+        // Имя поля на проводе не изменилось
+        let json = serde_json::to_value(&third).unwrap();
+        assert!(json.get("ephemeralPublicKey").is_some());
+    }
 }
@@ -1,34 +1,157 @@
 // WebSocket транспорт
-// Обертка над браузерным WebSocket API для WASM
+// Браузерный WebSocket API для WASM, tokio-tungstenite для desktop (feature `desktop`)
 
+use crate::protocol::messages::ClientMessage;
 use crate::utils::error::{ConstructError, Result};
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(any(target_arch = "wasm32", feature = "desktop"))]
 use crate::protocol::{
-    messages::{ClientMessage, ServerMessage},
-    wire::{pack_client_message, unpack_server_message},
+    messages::ServerMessage,
+    wire::{decode_server_frame, pack_client_message, pack_message_json, InboundFrame, WireCodec},
 };
 #[cfg(target_arch = "wasm32")]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::JsCast;
 #[cfg(target_arch = "wasm32")]
 use web_sys::{CloseEvent, ErrorEvent, MessageEvent, WebSocket};
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+use futures_util::{SinkExt, StreamExt};
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+use tokio_tungstenite::tungstenite::Message as WsMessage;
 
 /// Состояние WebSocket соединения
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionState {
     Connecting,
     Connected,
+    /// Соединение неожиданно закрылось, идут повторные попытки
+    Reconnecting,
     Disconnecting,
     Disconnected,
 }
 
+/// Политика автоматического переподключения: exponential backoff с jitter
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Переподключаться ли после неожиданного закрытия
+    pub enabled: bool,
+    /// Задержка перед первой попыткой (мс)
+    pub base_delay_ms: u32,
+    /// Верхняя граница задержки (мс)
+    pub max_delay_ms: u32,
+    /// Случайный разброс задержки: 0.0 - без разброса, 0.2 - ±20%
+    pub jitter: f64,
+    /// Максимум попыток подряд (0 = бесконечно)
+    pub max_attempts: u32,
+}
+
+impl ReconnectPolicy {
+    /// Политика без переподключения
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Можно ли сделать попытку с номером `attempt` (с нуля)
+    pub fn can_retry(&self, attempt: u32) -> bool {
+        self.enabled && (self.max_attempts == 0 || attempt < self.max_attempts)
+    }
+
+    /// Задержка перед попыткой `attempt` без разброса: base * 2^attempt, не больше max
+    pub fn backoff_delay_ms(&self, attempt: u32) -> u32 {
+        let delay = u64::from(self.base_delay_ms) << attempt.min(32);
+        delay.min(u64::from(self.max_delay_ms)) as u32
+    }
+
+    /// Задержка перед попыткой `attempt` со случайным разбросом
+    pub fn delay_ms(&self, attempt: u32) -> u32 {
+        self.jittered(self.backoff_delay_ms(attempt), rand::random::<f64>())
+    }
+
+    /// Применить разброс к задержке; `sample` из [0, 1)
+    fn jittered(&self, delay_ms: u32, sample: f64) -> u32 {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (2.0 * sample - 1.0);
+        (f64::from(delay_ms) * factor)
+            .round()
+            .min(f64::from(self.max_delay_ms)) as u32
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            base_delay_ms: 1000,
+            max_delay_ms: 30000,
+            jitter: 0.2,
+            max_attempts: 0,
+        }
+    }
+}
+
+/// Общий интерфейс транспорта до сервера
+/// Позволяет AppState отправлять сообщения независимо от реализации (WebSocket, mock в тестах)
+pub trait Transport {
+    /// Отправить сообщение серверу
+    fn send(&self, message: &ClientMessage) -> Result<()>;
+
+    /// Проверить, подключен ли транспорт
+    fn is_connected(&self) -> bool;
+
+    /// Текущее состояние соединения
+    fn state(&self) -> ConnectionState {
+        if self.is_connected() {
+            ConnectionState::Connected
+        } else {
+            ConnectionState::Disconnected
+        }
+    }
+
+    /// Настроить автоматическое переподключение (транспорт без него игнорирует политику)
+    fn set_reconnect_policy(&mut self, _policy: ReconnectPolicy) {}
+}
+
 /// WebSocket транспорт для WASM
+///
+/// Callbacks хранятся в транспорте и навешиваются на каждый новый сокет,
+/// поэтому после автоматического переподключения продолжают работать
 #[cfg(target_arch = "wasm32")]
 pub struct WebSocketTransport {
+    connection: Rc<RefCell<WasmConnection>>,
+    /// Разбирать текстовые фреймы как JSON (режим совместимости)
+    json_fallback: bool,
+    /// Кодек исходящих фреймов (JSON - только для отладки)
+    codec: WireCodec,
+}
+
+/// Текущий сокет, политика переподключения и callbacks пользователя
+#[cfg(target_arch = "wasm32")]
+struct WasmConnection {
+    url: String,
     ws: Option<WebSocket>,
     state: ConnectionState,
+    policy: ReconnectPolicy,
+    /// Номер следующей попытки переподключения
+    attempt: u32,
+    /// Закрытие запрошено пользователем - не переподключаться
+    closing: bool,
+    /// Разбирать текстовые фреймы как JSON
+    json_fallback: bool,
+    on_open: Option<Rc<dyn Fn()>>,
+    on_message: Option<Rc<dyn Fn(ServerMessage)>>,
+    on_error: Option<Rc<dyn Fn(String)>>,
+    on_close: Option<Rc<dyn Fn(u16, String)>>,
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -36,35 +159,52 @@ impl WebSocketTransport {
     /// Создать новый WebSocket транспорт
     pub fn new() -> Self {
         Self {
-            ws: None,
-            state: ConnectionState::Disconnected,
+            connection: Rc::new(RefCell::new(WasmConnection {
+                url: String::new(),
+                ws: None,
+                state: ConnectionState::Disconnected,
+                policy: ReconnectPolicy::default(),
+                attempt: 0,
+                closing: false,
+                json_fallback: false,
+                on_open: None,
+                on_message: None,
+                on_error: None,
+                on_close: None,
+            })),
+            json_fallback: false,
+            codec: WireCodec::MessagePack,
         }
     }
 
     /// Подключиться к серверу
     pub fn connect(&mut self, url: &str) -> Result<()> {
-        if self.state == ConnectionState::Connected {
+        if matches!(
+            self.state(),
+            ConnectionState::Connected | ConnectionState::Reconnecting
+        ) {
             return Err(ConstructError::NetworkError(
                 "Already connected".to_string(),
             ));
         }
 
-        let ws = WebSocket::new(url).map_err(|e| {
-            ConstructError::NetworkError(format!("Failed to create WebSocket: {:?}", e))
-        })?;
-
-        // Установить binary тип для MessagePack
-        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+        let ws = open_socket(url)?;
+        attach_handlers(&self.connection, &ws);
 
-        self.ws = Some(ws);
-        self.state = ConnectionState::Connecting;
+        let mut connection = self.connection.borrow_mut();
+        connection.url = url.to_string();
+        connection.ws = Some(ws);
+        connection.state = ConnectionState::Connecting;
+        connection.attempt = 0;
+        connection.closing = false;
 
         Ok(())
     }
 
     /// Отправить сообщение
     pub fn send(&self, message: &ClientMessage) -> Result<()> {
-        let ws = self
+        let connection = self.connection.borrow();
+        let ws = connection
             .ws
             .as_ref()
             .ok_or_else(|| ConstructError::NetworkError("WebSocket not initialized".to_string()))?;
@@ -74,58 +214,74 @@ impl WebSocketTransport {
             return Err(ConstructError::NetworkError("Not connected".to_string()));
         }
 
-        // Сериализовать в MessagePack
-        let packed = pack_client_message(message)?;
+        let sent = match self.codec {
+            // Сериализовать в MessagePack и отправить как ArrayBuffer
+            WireCodec::MessagePack => ws.send_with_u8_array(&pack_client_message(message)?),
+            // Отладочный режим: текстовый JSON фрейм
+            WireCodec::Json => ws.send_with_str(&pack_message_json(message)?),
+        };
 
-        // Отправить как ArrayBuffer
-        ws.send_with_u8_array(&packed).map_err(|e| {
+        sent.map_err(|e| {
             ConstructError::NetworkError(format!("Failed to send message: {:?}", e))
         })?;
 
         Ok(())
     }
 
-    /// Закрыть соединение
+    /// Закрыть соединение (без переподключения)
     pub fn close(&mut self) -> Result<()> {
-        if let Some(ws) = &self.ws {
+        let mut connection = self.connection.borrow_mut();
+        connection.closing = true;
+        if connection.state == ConnectionState::Reconnecting {
+            // Сокет уже закрыт, отложенная попытка увидит флаг closing
+            connection.state = ConnectionState::Disconnected;
+        } else if let Some(ws) = &connection.ws {
             ws.close()
                 .map_err(|e| ConstructError::NetworkError(format!("Failed to close: {:?}", e)))?;
-            self.state = ConnectionState::Disconnecting;
+            connection.state = ConnectionState::Disconnecting;
         }
         Ok(())
     }
 
     /// Получить текущее состояние соединения
     pub fn state(&self) -> ConnectionState {
-        self.state
+        self.connection.borrow().state
     }
 
     /// Проверить, подключен ли транспорт
     pub fn is_connected(&self) -> bool {
-        self.ws
+        self.connection
+            .borrow()
+            .ws
             .as_ref()
             .map(|ws| ws.ready_state() == 1)
             .unwrap_or(false)
     }
 
-    /// Установить callback для onopen
+    /// Настроить автоматическое переподключение
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.connection.borrow_mut().policy = policy;
+    }
+
+    /// Установить callback для onopen (вызывается и после переподключения)
     pub fn set_on_open<F>(&self, callback: F) -> Result<()>
     where
         F: Fn() + 'static,
     {
-        let ws = self
-            .ws
-            .as_ref()
-            .ok_or_else(|| ConstructError::NetworkError("WebSocket not initialized".to_string()))?;
-
-        let closure = Closure::wrap(Box::new(move |_event: JsValue| {
-            callback();
-        }) as Box<dyn Fn(JsValue)>);
+        self.connection.borrow_mut().on_open = Some(Rc::new(callback));
+        Ok(())
+    }
 
-        ws.set_onopen(Some(closure.as_ref().unchecked_ref()));
-        closure.forget();
+    /// Включить/выключить разбор текстовых фреймов как JSON
+    pub fn set_json_fallback(&mut self, enabled: bool) {
+        self.json_fallback = enabled;
+        self.connection.borrow_mut().json_fallback = self.json_fallback || self.codec == WireCodec::Json;
+    }
 
-        Ok(())
+    /// Выбрать кодек фреймов (JSON удобно читать в devtools)
+    pub fn set_codec(&mut self, codec: WireCodec) {
+        self.codec = codec;
+        self.connection.borrow_mut().json_fallback = self.json_fallback || self.codec == WireCodec::Json;
     }
 
     /// Установить callback для onmessage (принимает ServerMessage от сервера)
@@ -133,32 +289,7 @@ impl WebSocketTransport {
     where
         F: Fn(ServerMessage) + 'static,
     {
-        let ws = self
-            .ws
-            .as_ref()
-            .ok_or_else(|| ConstructError::NetworkError("WebSocket not initialized".to_string()))?;
-
-        let closure = Closure::wrap(Box::new(move |event: MessageEvent| {
-            if let Ok(array_buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
-                let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-                let data = uint8_array.to_vec();
-
-                match unpack_server_message(&data) {
-                    Ok(msg) => callback(msg),
-                    Err(e) => {
-                        #[cfg(feature = "wasm")]
-                        crate::wasm::console::log(&format!(
-                            "Failed to unpack server message: {:?}",
-                            e
-                        ));
-                    }
-                }
-            }
-        }) as Box<dyn Fn(MessageEvent)>);
-
-        ws.set_onmessage(Some(closure.as_ref().unchecked_ref()));
-        closure.forget();
-
+        self.connection.borrow_mut().on_message = Some(Rc::new(callback));
         Ok(())
     }
 
@@ -167,53 +298,476 @@ impl WebSocketTransport {
     where
         F: Fn(String) + 'static,
     {
-        let ws = self
-            .ws
-            .as_ref()
-            .ok_or_else(|| ConstructError::NetworkError("WebSocket not initialized".to_string()))?;
-
-        let closure = Closure::wrap(Box::new(move |_event: ErrorEvent| {
-            callback("WebSocket error occurred".to_string());
-        }) as Box<dyn Fn(ErrorEvent)>);
-
-        ws.set_onerror(Some(closure.as_ref().unchecked_ref()));
-        closure.forget();
-
+        self.connection.borrow_mut().on_error = Some(Rc::new(callback));
         Ok(())
     }
 
-    /// Установить callback для onclose
+    /// Установить callback для onclose (вызывается при каждом закрытии сокета)
     pub fn set_on_close<F>(&self, callback: F) -> Result<()>
     where
         F: Fn(u16, String) + 'static,
     {
-        let ws = self
-            .ws
+        self.connection.borrow_mut().on_close = Some(Rc::new(callback));
+        Ok(())
+    }
+}
+
+/// Открыть сокет с binary типом для MessagePack
+#[cfg(target_arch = "wasm32")]
+fn open_socket(url: &str) -> Result<WebSocket> {
+    let ws = WebSocket::new(url).map_err(|e| {
+        ConstructError::NetworkError(format!("Failed to create WebSocket: {:?}", e))
+    })?;
+    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+    Ok(ws)
+}
+
+/// Навесить на сокет обработчики, которые вызывают текущие callbacks транспорта.
+/// Обработчики держат слабую ссылку: после удаления транспорта они ничего не делают
+#[cfg(target_arch = "wasm32")]
+fn attach_handlers(connection: &Rc<RefCell<WasmConnection>>, ws: &WebSocket) {
+    let weak = Rc::downgrade(connection);
+    let on_open = Closure::wrap(Box::new(move |_event: JsValue| {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let callback = {
+            let mut connection = connection.borrow_mut();
+            connection.state = ConnectionState::Connected;
+            connection.attempt = 0;
+            connection.on_open.clone()
+        };
+        if let Some(callback) = callback {
+            callback();
+        }
+    }) as Box<dyn Fn(JsValue)>);
+    ws.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+    on_open.forget();
+
+    let weak = Rc::downgrade(connection);
+    let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let (json_fallback, callback) = {
+            let connection = connection.borrow();
+            (connection.json_fallback, connection.on_message.clone())
+        };
+        let Some(callback) = callback else {
+            return;
+        };
+
+        let data = event.data();
+        let result = if let Ok(array_buffer) = data.clone().dyn_into::<js_sys::ArrayBuffer>() {
+            let bytes = js_sys::Uint8Array::new(&array_buffer).to_vec();
+            decode_server_frame(InboundFrame::Binary(&bytes), json_fallback)
+        } else if let Some(text) = data.as_string() {
+            decode_server_frame(InboundFrame::Text(&text), json_fallback)
+        } else {
+            Err(ConstructError::NetworkError(
+                "Unsupported WebSocket frame type".to_string(),
+            ))
+        };
+
+        match result {
+            Ok(msg) => callback(msg),
+            Err(e) => {
+                #[cfg(feature = "wasm")]
+                crate::wasm::console::log(&format!(
+                    "Failed to unpack server message: {:?}",
+                    e
+                ));
+            }
+        }
+    }) as Box<dyn Fn(MessageEvent)>);
+    ws.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    on_message.forget();
+
+    let weak = Rc::downgrade(connection);
+    let on_error = Closure::wrap(Box::new(move |_event: ErrorEvent| {
+        let callback = weak
+            .upgrade()
+            .and_then(|connection| connection.borrow().on_error.clone());
+        if let Some(callback) = callback {
+            callback("WebSocket error occurred".to_string());
+        }
+    }) as Box<dyn Fn(ErrorEvent)>);
+    ws.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+    on_error.forget();
+
+    let weak = Rc::downgrade(connection);
+    let on_close = Closure::wrap(Box::new(move |event: CloseEvent| {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let (callback, delay_ms) = {
+            let mut connection = connection.borrow_mut();
+            let attempt = connection.attempt;
+            let delay_ms = if !connection.closing && connection.policy.can_retry(attempt) {
+                connection.attempt += 1;
+                connection.state = ConnectionState::Reconnecting;
+                Some(connection.policy.delay_ms(attempt))
+            } else {
+                connection.state = ConnectionState::Disconnected;
+                None
+            };
+            (connection.on_close.clone(), delay_ms)
+        };
+
+        if let Some(callback) = callback {
+            callback(event.code(), event.reason());
+        }
+        if let Some(delay_ms) = delay_ms {
+            schedule_reconnect(&connection, delay_ms);
+        }
+    }) as Box<dyn Fn(CloseEvent)>);
+    ws.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+    on_close.forget();
+}
+
+/// Открыть новый сокет через `delay_ms`, если закрытие не запрошено пользователем
+#[cfg(target_arch = "wasm32")]
+fn schedule_reconnect(connection: &Rc<RefCell<WasmConnection>>, delay_ms: u32) {
+    let weak = Rc::downgrade(connection);
+    let reconnect = Closure::once_into_js(move || {
+        let Some(connection) = weak.upgrade() else {
+            return;
+        };
+        let url = {
+            let connection = connection.borrow();
+            if connection.closing {
+                return;
+            }
+            connection.url.clone()
+        };
+
+        match open_socket(&url) {
+            Ok(ws) => {
+                attach_handlers(&connection, &ws);
+                connection.borrow_mut().ws = Some(ws);
+            }
+            // Некорректный URL не исправится повторной попыткой
+            Err(_) => connection.borrow_mut().state = ConnectionState::Disconnected,
+        }
+    });
+
+    if let Some(window) = web_sys::window() {
+        let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            reconnect.unchecked_ref(),
+            delay_ms.min(i32::MAX as u32) as i32,
+        );
+    }
+}
+
+/// WebSocket транспорт для desktop сборок (tokio-tungstenite)
+///
+/// Соединение обслуживает собственный tokio runtime. Входящие сообщения
+/// забираются через `recv_timeout`/`try_recv`; очередь входящих переживает
+/// переподключение, так что получатель продолжает читать ее как прежде.
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+pub struct WebSocketTransport {
+    runtime: Option<tokio::runtime::Runtime>,
+    outgoing: Option<tokio::sync::mpsc::UnboundedSender<WsMessage>>,
+    incoming: Option<std::sync::mpsc::Receiver<Result<ServerMessage>>>,
+    state: Arc<Mutex<ConnectionState>>,
+    /// Закрытие запрошено пользователем - не переподключаться
+    closing: Arc<AtomicBool>,
+    policy: Arc<Mutex<ReconnectPolicy>>,
+    /// Разбирать текстовые фреймы как JSON (режим совместимости)
+    json_fallback: bool,
+    /// Кодек исходящих фреймов (JSON - только для отладки)
+    codec: WireCodec,
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+type WsStream = tokio_tungstenite::WebSocketStream<
+    tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+>;
+
+/// Чем закончилась работа с одним сокетом
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+enum SocketEnd {
+    /// Закрыто пользователем или транспорт удален
+    Closed,
+    /// Сокет оборвался (текст ошибки, если она была)
+    Dropped(Option<String>),
+}
+
+/// Все, что нужно фоновой задаче соединения
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+struct ConnectionTask {
+    url: String,
+    outgoing: tokio::sync::mpsc::UnboundedReceiver<WsMessage>,
+    incoming: std::sync::mpsc::Sender<Result<ServerMessage>>,
+    state: Arc<Mutex<ConnectionState>>,
+    closing: Arc<AtomicBool>,
+    policy: Arc<Mutex<ReconnectPolicy>>,
+    json_fallback: bool,
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+impl WebSocketTransport {
+    /// Создать новый WebSocket транспорт
+    pub fn new() -> Self {
+        Self {
+            runtime: None,
+            outgoing: None,
+            incoming: None,
+            state: Arc::new(Mutex::new(ConnectionState::Disconnected)),
+            closing: Arc::new(AtomicBool::new(false)),
+            policy: Arc::new(Mutex::new(ReconnectPolicy::default())),
+            json_fallback: false,
+            codec: WireCodec::MessagePack,
+        }
+    }
+
+    /// Подключиться к серверу (блокирует до завершения handshake)
+    pub fn connect(&mut self, url: &str) -> Result<()> {
+        if matches!(
+            self.state(),
+            ConnectionState::Connected | ConnectionState::Reconnecting
+        ) {
+            return Err(ConstructError::NetworkError(
+                "Already connected".to_string(),
+            ));
+        }
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|e| ConstructError::NetworkError(format!("Failed to start runtime: {}", e)))?;
+
+        let state = Arc::new(Mutex::new(ConnectionState::Connecting));
+        self.state = state.clone();
+        let connected = runtime.block_on(tokio_tungstenite::connect_async(url));
+        let (ws, _response) = connected.map_err(|e| {
+            set_state(&state, ConnectionState::Disconnected);
+            ConstructError::NetworkError(format!("Failed to connect WebSocket: {}", e))
+        })?;
+        set_state(&state, ConnectionState::Connected);
+
+        let (outgoing, outgoing_rx) = tokio::sync::mpsc::unbounded_channel::<WsMessage>();
+        let (incoming_tx, incoming) = std::sync::mpsc::channel();
+        let closing = Arc::new(AtomicBool::new(false));
+        runtime.spawn(run_connection(
+            ConnectionTask {
+                url: url.to_string(),
+                outgoing: outgoing_rx,
+                incoming: incoming_tx,
+                state,
+                closing: closing.clone(),
+                policy: self.policy.clone(),
+                json_fallback: self.json_fallback || self.codec == WireCodec::Json,
+            },
+            ws,
+        ));
+
+        self.runtime = Some(runtime);
+        self.outgoing = Some(outgoing);
+        self.incoming = Some(incoming);
+        self.closing = closing;
+        Ok(())
+    }
+
+    /// Отправить сообщение (во время переподключения - ошибка)
+    pub fn send(&self, message: &ClientMessage) -> Result<()> {
+        let outgoing = self
+            .outgoing
+            .as_ref()
+            .filter(|_| self.is_connected())
+            .ok_or_else(|| ConstructError::NetworkError("Not connected".to_string()))?;
+
+        let frame = match self.codec {
+            WireCodec::MessagePack => WsMessage::Binary(pack_client_message(message)?),
+            WireCodec::Json => WsMessage::Text(pack_message_json(message)?),
+        };
+        outgoing
+            .send(frame)
+            .map_err(|_| ConstructError::NetworkError("Failed to send message: connection closed".to_string()))
+    }
+
+    /// Дождаться следующего сообщения сервера (None - истек таймаут)
+    pub fn recv_timeout(&self, timeout: std::time::Duration) -> Result<Option<ServerMessage>> {
+        let incoming = self
+            .incoming
             .as_ref()
             .ok_or_else(|| ConstructError::NetworkError("WebSocket not initialized".to_string()))?;
 
-        let closure = Closure::wrap(Box::new(move |event: CloseEvent| {
-            let code = event.code();
-            let reason = event.reason();
-            callback(code, reason);
-        }) as Box<dyn Fn(CloseEvent)>);
+        match incoming.recv_timeout(timeout) {
+            Ok(message) => message.map(Some),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                Err(ConstructError::NetworkError("Connection closed".to_string()))
+            }
+        }
+    }
 
-        ws.set_onclose(Some(closure.as_ref().unchecked_ref()));
-        closure.forget();
+    /// Забрать уже полученное сообщение сервера без ожидания
+    pub fn try_recv(&self) -> Result<Option<ServerMessage>> {
+        self.recv_timeout(std::time::Duration::ZERO)
+    }
 
+    /// Закрыть соединение (без переподключения)
+    pub fn close(&mut self) -> Result<()> {
+        self.closing.store(true, Ordering::SeqCst);
+        if let Some(outgoing) = self.outgoing.take() {
+            let _ = outgoing.send(WsMessage::Close(None));
+            set_state(&self.state, ConnectionState::Disconnecting);
+        }
         Ok(())
     }
+
+    /// Получить текущее состояние соединения
+    pub fn state(&self) -> ConnectionState {
+        get_state(&self.state)
+    }
+
+    /// Проверить, подключен ли транспорт
+    pub fn is_connected(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+
+    /// Настроить автоматическое переподключение (действует и на текущее соединение)
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        if let Ok(mut current) = self.policy.lock() {
+            *current = policy;
+        }
+    }
+
+    /// Включить разбор текстовых фреймов как JSON (действует при следующем connect)
+    pub fn set_json_fallback(&mut self, enabled: bool) {
+        self.json_fallback = enabled;
+    }
+
+    /// Выбрать кодек исходящих фреймов
+    pub fn set_codec(&mut self, codec: WireCodec) {
+        self.codec = codec;
+    }
 }
 
-/// Заглушка для не-WASM платформ
-#[cfg(not(target_arch = "wasm32"))]
-use crate::protocol::messages::ClientMessage;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+fn get_state(state: &Mutex<ConnectionState>) -> ConnectionState {
+    state
+        .lock()
+        .map(|state| *state)
+        .unwrap_or(ConnectionState::Disconnected)
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+fn set_state(state: &Mutex<ConnectionState>, value: ConnectionState) {
+    if let Ok(mut state) = state.lock() {
+        *state = value;
+    }
+}
+
+/// Фоновая задача соединения: обслуживает сокет, а при обрыве
+/// переподключается по политике с той же очередью исходящих и входящих
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+async fn run_connection(mut task: ConnectionTask, mut ws: WsStream) {
+    loop {
+        let error = match serve_socket(&mut task, ws).await {
+            SocketEnd::Closed => break,
+            SocketEnd::Dropped(error) => error,
+        };
+
+        match reconnect(&task).await {
+            Some(next) => {
+                ws = next;
+                set_state(&task.state, ConnectionState::Connected);
+            }
+            None => {
+                if let Some(error) = error {
+                    let _ = task.incoming.send(Err(ConstructError::NetworkError(error)));
+                }
+                break;
+            }
+        }
+    }
+    set_state(&task.state, ConnectionState::Disconnected);
+}
+
+/// Пересылать фреймы через один сокет, пока он жив
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+async fn serve_socket(task: &mut ConnectionTask, ws: WsStream) -> SocketEnd {
+    let (mut sink, mut stream) = ws.split();
+    loop {
+        tokio::select! {
+            frame = task.outgoing.recv() => {
+                let Some(frame) = frame else {
+                    let _ = sink.close().await;
+                    return SocketEnd::Closed;
+                };
+                let closing = matches!(frame, WsMessage::Close(_));
+                if let Err(e) = sink.send(frame).await {
+                    return SocketEnd::Dropped(Some(format!("WebSocket error: {}", e)));
+                }
+                if closing {
+                    let _ = sink.close().await;
+                    return SocketEnd::Closed;
+                }
+            }
+            frame = stream.next() => {
+                let decoded = match frame {
+                    Some(Ok(WsMessage::Binary(bytes))) => decode_server_frame(InboundFrame::Binary(&bytes), task.json_fallback),
+                    Some(Ok(WsMessage::Text(text))) => decode_server_frame(InboundFrame::Text(&text), task.json_fallback),
+                    Some(Ok(WsMessage::Close(_))) | None => return SocketEnd::Dropped(None),
+                    // Ping/Pong обрабатывает tungstenite
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return SocketEnd::Dropped(Some(format!("WebSocket error: {}", e))),
+                };
+                if task.incoming.send(decoded).is_err() {
+                    return SocketEnd::Closed;
+                }
+            }
+        }
+    }
+}
+
+/// Повторять подключение с backoff, пока политика разрешает (None - сдались)
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+async fn reconnect(task: &ConnectionTask) -> Option<WsStream> {
+    let mut attempt = 0;
+    loop {
+        let policy = task.policy.lock().ok()?.clone();
+        if task.closing.load(Ordering::SeqCst) || !policy.can_retry(attempt) {
+            return None;
+        }
+        set_state(&task.state, ConnectionState::Reconnecting);
+
+        let delay = std::time::Duration::from_millis(u64::from(policy.delay_ms(attempt)));
+        tokio::time::sleep(delay).await;
+        if task.closing.load(Ordering::SeqCst) {
+            return None;
+        }
+        if let Ok((ws, _response)) = tokio_tungstenite::connect_async(task.url.as_str()).await {
+            return Some(ws);
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "desktop"))]
+impl Drop for WebSocketTransport {
+    fn drop(&mut self) {
+        let _ = self.close();
+        // Runtime нельзя ронять внутри async контекста - останавливаем в фоне
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Заглушка для не-WASM платформ без feature `desktop`
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "desktop")))]
 pub struct WebSocketTransport {
     state: ConnectionState,
 }
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "desktop")))]
 impl WebSocketTransport {
     pub fn new() -> Self {
         Self {
@@ -223,19 +777,19 @@ impl WebSocketTransport {
 
     pub fn connect(&mut self, _url: &str) -> Result<()> {
         Err(ConstructError::NetworkError(
-            "WebSocket transport only available in WASM target".to_string(),
+            "WebSocket transport requires the WASM target or the desktop feature".to_string(),
         ))
     }
 
     pub fn send(&self, _message: &ClientMessage) -> Result<()> {
         Err(ConstructError::NetworkError(
-            "WebSocket transport only available in WASM target".to_string(),
+            "WebSocket transport requires the WASM target or the desktop feature".to_string(),
         ))
     }
 
     pub fn close(&mut self) -> Result<()> {
         Err(ConstructError::NetworkError(
-            "WebSocket transport only available in WASM target".to_string(),
+            "WebSocket transport requires the WASM target or the desktop feature".to_string(),
         ))
     }
 
@@ -246,6 +800,8 @@ impl WebSocketTransport {
     pub fn is_connected(&self) -> bool {
         false
     }
+
+    pub fn set_reconnect_policy(&mut self, _policy: ReconnectPolicy) {}
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -254,3 +810,191 @@ impl Default for WebSocketTransport {
         Self::new()
     }
 }
+
+impl Transport for WebSocketTransport {
+    fn send(&self, message: &ClientMessage) -> Result<()> {
+        WebSocketTransport::send(self, message)
+    }
+
+    fn is_connected(&self) -> bool {
+        WebSocketTransport::is_connected(self)
+    }
+
+    fn state(&self) -> ConnectionState {
+        WebSocketTransport::state(self)
+    }
+
+    fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        WebSocketTransport::set_reconnect_policy(self, policy)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "desktop")]
+    use crate::protocol::messages::RequestResendData;
+    #[cfg(feature = "desktop")]
+    use std::time::Duration;
+
+    #[test]
+    fn test_reconnect_policy_backoff() {
+        let policy = ReconnectPolicy {
+            enabled: true,
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+            jitter: 0.0,
+            max_attempts: 5,
+        };
+        let delays: Vec<u32> = (0..6).map(|attempt| policy.backoff_delay_ms(attempt)).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff_delay_ms(u32::MAX), 1000);
+        assert_eq!(policy.delay_ms(2), 400);
+        assert!(policy.can_retry(4));
+        assert!(!policy.can_retry(5));
+        assert!(!ReconnectPolicy::disabled().can_retry(0));
+
+        // Разброс ±50%, но не выше max_delay_ms
+        let jittered = ReconnectPolicy { jitter: 0.5, ..policy };
+        assert_eq!(jittered.jittered(400, 0.0), 200);
+        assert_eq!(jittered.jittered(400, 0.5), 400);
+        assert_eq!(jittered.jittered(800, 0.99), 1000);
+        for attempt in 0..6 {
+            let base = jittered.backoff_delay_ms(attempt);
+            let delay = jittered.delay_ms(attempt);
+            assert!(delay >= base / 2 && delay <= (base * 3 / 2).min(1000));
+        }
+    }
+
+    /// Локальный echo сервер на одно соединение: возвращает клиенту каждый фрейм.
+    /// С `drop_after` сервер "падает" после стольких echo - рвет сокет без close фрейма
+    #[cfg(feature = "desktop")]
+    fn spawn_echo_server(addr: &str, drop_after: Option<usize>) -> (String, std::thread::JoinHandle<()>) {
+        let addr = addr.to_string();
+        let (addr_tx, addr_rx) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+                addr_tx.send(listener.local_addr().unwrap()).unwrap();
+
+                let (socket, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(socket).await.unwrap();
+                let mut echoed = 0;
+                while let Some(Ok(frame)) = ws.next().await {
+                    if frame.is_close() {
+                        break;
+                    }
+                    if frame.is_binary() || frame.is_text() {
+                        ws.send(frame).await.unwrap();
+                        echoed += 1;
+                    }
+                    if drop_after == Some(echoed) {
+                        break;
+                    }
+                }
+            });
+        });
+        (format!("ws://{}", addr_rx.recv().unwrap()), server)
+    }
+
+    #[cfg(feature = "desktop")]
+    fn resend_request(message_numbers: Vec<u32>) -> ClientMessage {
+        ClientMessage::RequestResend(RequestResendData {
+            from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            ratchet_dh_public: vec![0u8; 32],
+            message_numbers,
+        })
+    }
+
+    #[cfg(feature = "desktop")]
+    fn wait_for_state(transport: &WebSocketTransport, state: ConnectionState) -> bool {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while std::time::Instant::now() < deadline {
+            if transport.state() == state {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    #[cfg(feature = "desktop")]
+    fn test_native_transport_echo_round_trip() {
+        let (url, server) = spawn_echo_server("127.0.0.1:0", None);
+        let mut transport = WebSocketTransport::new();
+        transport.connect(&url).unwrap();
+        assert_eq!(transport.state(), ConnectionState::Connected);
+        assert!(transport.connect(&url).is_err());
+
+        // RequestResend одинаков в обоих направлениях: echo приходит как ServerMessage
+        transport.send(&resend_request(vec![4, 5])).unwrap();
+        match transport.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Some(ServerMessage::RequestResend(echoed)) => {
+                assert_eq!(echoed.from, "550e8400-e29b-41d4-a716-446655440001");
+                assert_eq!(echoed.message_numbers, [4, 5]);
+            }
+            other => panic!("expected RequestResend echo, got {:?}", other),
+        }
+        assert!(transport.try_recv().unwrap().is_none());
+
+        transport.close().unwrap();
+        assert!(!transport.is_connected());
+        assert!(transport.send(&resend_request(vec![6])).is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "desktop")]
+    fn test_native_transport_reconnects_after_server_restart() {
+        let (url, server) = spawn_echo_server("127.0.0.1:0", Some(1));
+        let mut transport = WebSocketTransport::new();
+        transport.set_reconnect_policy(ReconnectPolicy {
+            base_delay_ms: 20,
+            max_delay_ms: 100,
+            ..ReconnectPolicy::default()
+        });
+        transport.connect(&url).unwrap();
+
+        let echoed = |transport: &WebSocketTransport| match transport.recv_timeout(Duration::from_secs(5)) {
+            Ok(Some(ServerMessage::RequestResend(echoed))) => echoed.message_numbers,
+            other => panic!("expected RequestResend echo, got {:?}", other),
+        };
+        transport.send(&resend_request(vec![1])).unwrap();
+        assert_eq!(echoed(&transport), [1]);
+
+        // Сервер падает посреди сессии: клиент переходит к повторным попыткам
+        server.join().unwrap();
+        assert!(wait_for_state(&transport, ConnectionState::Reconnecting));
+        assert!(transport.send(&resend_request(vec![2])).is_err());
+
+        // Сервер возвращается на тот же адрес - клиент подключается сам,
+        // а очередь входящих продолжает работать
+        let (_, server) = spawn_echo_server(url.trim_start_matches("ws://"), None);
+        assert!(wait_for_state(&transport, ConnectionState::Connected));
+        transport.send(&resend_request(vec![3])).unwrap();
+        assert_eq!(echoed(&transport), [3]);
+
+        transport.close().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "desktop")]
+    fn test_native_transport_without_reconnect_disconnects() {
+        let (url, server) = spawn_echo_server("127.0.0.1:0", Some(1));
+        let mut transport = WebSocketTransport::new();
+        transport.set_reconnect_policy(ReconnectPolicy::disabled());
+        transport.connect(&url).unwrap();
+
+        transport.send(&resend_request(vec![1])).unwrap();
+        assert!(transport.recv_timeout(Duration::from_secs(5)).unwrap().is_some());
+        server.join().unwrap();
+        assert!(wait_for_state(&transport, ConnectionState::Disconnected));
+    }
+}
@@ -1,9 +1,62 @@
 // Валидация входящих данных
 
-use crate::protocol::messages::{ChatMessage, ClientMessage, RegistrationBundle};
+use crate::protocol::messages::{
+    ChatMessage, ClientMessage, ProtocolMessage, RegisterResponseData, RegistrationBundle,
+    RequestResendData, ServerMessage, UploadOneTimePrekeysData,
+};
+use crate::crypto::double_ratchet::EncryptedRatchetMessage;
+use crate::crypto::{ensure_suite_available, suite_key_lengths, SuiteID};
+use crate::storage::models::ARCHIVE_VERSION;
 use crate::utils::error::{ConstructError, Result};
 use base64::{engine::general_purpose, Engine as _};
 
+/// Максимальный размер зашифрованного бэкапа (64 MiB)
+pub const MAX_BACKUP_BLOB_SIZE: usize = 64 * 1024 * 1024;
+
+/// Максимальное количество сообщений в одном запросе повторной отправки
+pub const MAX_RESEND_REQUEST: usize = 100;
+
+/// Максимальный размер зашифрованного содержимого сообщения (Base64, 256 KiB)
+pub const MAX_MESSAGE_CONTENT_SIZE: usize = 256 * 1024;
+
+/// Размер X25519 DH ratchet public key (наименьший среди suite)
+pub const RATCHET_DH_PUBLIC_SIZE: usize = 32;
+
+/// Максимальный размер DH ratchet public key (с запасом для PQ hybrid suite)
+pub const MAX_RATCHET_DH_PUBLIC_SIZE: usize = 2048;
+
+/// Максимальное количество одноразовых prekey в одной загрузке
+pub const MAX_ONE_TIME_PREKEYS_UPLOAD: usize = 100;
+
+/// Максимальное количество сообщений в одной квитанции о прочтении
+pub const MAX_READ_RECEIPT_MESSAGES: usize = 100;
+
+/// Максимальное количество пользователей в результатах поиска
+pub const MAX_SEARCH_RESULTS: usize = 100;
+
+/// Максимальный размер одноразового KEM ключа в `DeviceLinkRequest` (любой suite)
+pub const MAX_DEVICE_LINK_KEY_SIZE: usize = MAX_RATCHET_DH_PUBLIC_SIZE;
+
+/// Максимальный размер `DeviceLinkResponse`: внутри тот же архив состояния, что и в бэкапе
+pub const MAX_DEVICE_LINK_RESPONSE_SIZE: usize = MAX_BACKUP_BLOB_SIZE;
+
+/// Максимальный размер nonce в сообщении sender key
+pub const MAX_SENDER_KEY_NONCE_SIZE: usize = 32;
+
+/// Максимальный размер подписи (с запасом для PQ подписей)
+pub const MAX_SIGNATURE_SIZE: usize = 8 * 1024;
+
+/// Проверка размера поля до какой-либо обработки содержимого
+fn validate_field_size(field: &str, len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(ConstructError::ValidationError(format!(
+            "{} too large: {} (max {})",
+            field, len, max
+        )));
+    }
+    Ok(())
+}
+
 /// Валидация Base64 строки
 pub fn validate_base64(encoded: &str) -> Result<()> {
     if general_purpose::STANDARD.decode(encoded).is_err() {
@@ -55,99 +108,386 @@ pub fn validate_uuid(uuid: &str) -> Result<()> {
     Ok(())
 }
 
-/// Валидация ChatMessage
-pub fn validate_chat_message(msg: &ChatMessage) -> Result<()> {
-    // Проверка UUID
-    validate_uuid(&msg.id)?;
-    validate_uuid(&msg.from)?;
-    validate_uuid(&msg.to)?;
+/// Проверка размеров полей ChatMessage
+/// Применяется и к входящим сообщениям, которые могли долго ждать доставки
+pub fn validate_chat_message_size(msg: &ChatMessage) -> Result<()> {
+    validate_ratchet_dh_public(msg)?;
+    validate_field_size("Message content", msg.content.len(), MAX_MESSAGE_CONTENT_SIZE)
+}
 
-    // Проверка ephemeral key (должен быть 32 байта для X25519)
-    if msg.ephemeral_public_key.len() != 32 {
-        return Err(ConstructError::ValidationError(
-            "Ephemeral public key must be 32 bytes".to_string(),
-        ));
+/// Проверка DH ratchet ключа: длина зависит от suite, точное значение
+/// проверяет сессия при расшифровке. Пустой ключ допустим, только если
+/// заголовок в `content` зашифрован
+fn validate_ratchet_dh_public(msg: &ChatMessage) -> Result<()> {
+    if msg.ratchet_dh_public.is_empty() && has_encrypted_header(&msg.content) {
+        return Ok(());
     }
+    validate_ratchet_dh_public_len(msg.ratchet_dh_public.len())
+}
 
-    // Проверка зашифрованного содержимого
-    if msg.content.is_empty() {
+/// Содержит ли `content` сообщение с зашифрованным заголовком
+fn has_encrypted_header(content: &str) -> bool {
+    general_purpose::STANDARD
+        .decode(content)
+        .ok()
+        .and_then(|bytes| EncryptedRatchetMessage::from_wire_bytes(&bytes).ok())
+        .is_some_and(|encrypted| encrypted.is_header_encrypted())
+}
+
+/// Допустимая длина DH ratchet ключа для любого suite
+pub fn validate_ratchet_dh_public_len(len: usize) -> Result<()> {
+    if !(RATCHET_DH_PUBLIC_SIZE..=MAX_RATCHET_DH_PUBLIC_SIZE).contains(&len) {
+        return Err(ConstructError::ValidationError(format!(
+            "Ratchet DH public key must be {}-{} bytes",
+            RATCHET_DH_PUBLIC_SIZE, MAX_RATCHET_DH_PUBLIC_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Проверка зашифрованного содержимого: непустой Base64
+fn validate_message_content(content: &str) -> Result<()> {
+    if content.is_empty() {
         return Err(ConstructError::ValidationError(
             "Message content cannot be empty".to_string(),
         ));
     }
+    validate_base64(content)
+}
 
-    validate_base64(&msg.content)?;
-
-    // Проверка timestamp (не должен быть в будущем или слишком старым)
+/// Проверка timestamp (не должен быть в будущем или слишком старым)
+fn validate_message_timestamp(timestamp: u64) -> Result<()> {
     let now = crate::utils::time::now();
-    if msg.timestamp > now + 300 {
+    if timestamp > now + 300 {
         // 5 минут в будущем
         return Err(ConstructError::ValidationError(
             "Message timestamp is too far in the future".to_string(),
         ));
     }
-    if msg.timestamp < now.saturating_sub(3600) {
+    if timestamp < now.saturating_sub(3600) {
         // 1 час в прошлом
         return Err(ConstructError::ValidationError(
             "Message timestamp is too old".to_string(),
         ));
     }
+    Ok(())
+}
 
+/// Сообщение самому себе (from == to) допустимо только в режиме "заметки для себя"
+pub fn validate_not_self_message(from: &str, to: &str, allow_note_to_self: bool) -> Result<()> {
+    if from == to && !allow_note_to_self {
+        return Err(ConstructError::ValidationError(
+            "Sender and recipient must differ".to_string(),
+        ));
+    }
     Ok(())
 }
 
-/// Валидация RegistrationBundle
-pub fn validate_registration_bundle(bundle: &RegistrationBundle) -> Result<()> {
-    // Base64 X25519 public key должен быть 44 символа
-    if bundle.identity_public.len() != 44 {
+/// Валидация ChatMessage
+pub fn validate_chat_message(msg: &ChatMessage) -> Result<()> {
+    validate_chat_message_with(msg, false)
+}
+
+/// Валидация ChatMessage; `allow_note_to_self` разрешает сообщения самому себе
+pub fn validate_chat_message_with(msg: &ChatMessage, allow_note_to_self: bool) -> Result<()> {
+    // Проверка UUID
+    validate_uuid(&msg.id)?;
+    validate_uuid(&msg.from)?;
+    validate_uuid(&msg.to)?;
+    validate_not_self_message(&msg.from, &msg.to, allow_note_to_self)?;
+
+    validate_chat_message_size(msg)?;
+    validate_message_content(&msg.content)?;
+    validate_message_timestamp(msg.timestamp)
+}
+
+/// Сверка открытого заголовка ChatMessage с `EncryptedRatchetMessage` из `content`
+///
+/// Сервер маршрутизирует по открытым полям, а расшифровка идет по упакованным;
+/// расхождение означает подмену по пути или ошибку отправителя. У сообщения с
+/// зашифрованным заголовком упакованные поля пустые, поэтому и открытые обязаны
+/// быть пустыми. Возвращает распакованное сообщение
+pub fn verify_chat_message_integrity(msg: &ChatMessage) -> Result<EncryptedRatchetMessage> {
+    let bytes = general_purpose::STANDARD
+        .decode(&msg.content)
+        .map_err(|e| ConstructError::SerializationError(format!("Invalid base64 content: {}", e)))?;
+    let encrypted = EncryptedRatchetMessage::from_wire_bytes(&bytes)
+        .map_err(|e| ConstructError::SerializationError(e.to_string()))?;
+
+    if encrypted.message_number != msg.message_number {
+        return Err(ConstructError::ValidationError(format!(
+            "Message number {} does not match encrypted content ({})",
+            msg.message_number, encrypted.message_number
+        )));
+    }
+    if encrypted.dh_public_key[..] != msg.ratchet_dh_public[..] {
         return Err(ConstructError::ValidationError(
-            "Identity public key must be 44 characters (32 bytes base64)".to_string(),
+            "Ratchet DH public key does not match encrypted content".to_string(),
         ));
     }
+    Ok(encrypted)
+}
+
+/// Все нарушения в ChatMessage (см. `validate_client_message_all`)
+fn chat_message_errors(msg: &ChatMessage, allow_note_to_self: bool) -> Vec<ConstructError> {
+    collect_errors([
+        in_field("id", validate_uuid(&msg.id)),
+        in_field("from", validate_uuid(&msg.from)),
+        in_field("to", validate_uuid(&msg.to)),
+        validate_not_self_message(&msg.from, &msg.to, allow_note_to_self),
+        validate_ratchet_dh_public(msg),
+        validate_field_size("Message content", msg.content.len(), MAX_MESSAGE_CONTENT_SIZE),
+        validate_message_content(&msg.content),
+        validate_message_timestamp(msg.timestamp),
+    ])
+}
+
+/// Ошибки независимых проверок (пустой список - все прошли)
+fn collect_errors<const N: usize>(checks: [Result<()>; N]) -> Vec<ConstructError> {
+    checks.into_iter().filter_map(|check| check.err()).collect()
+}
+
+/// Добавить к ошибке валидации имя поля (одинаковые проверки разных полей различимы)
+fn in_field(field: &str, result: Result<()>) -> Result<()> {
+    result.map_err(|e| match e {
+        ConstructError::ValidationError(message) => {
+            ConstructError::ValidationError(format!("{}: {}", field, message))
+        }
+        other => other,
+    })
+}
+
+/// Валидация RegistrationBundle
+///
+/// Ожидаемые длины ключей зависят от suite бандла
+/// (например, 44 символа для X25519 ключа классического suite).
+///
+/// Результат проверки нельзя потерять - это предупреждение `unused_must_use`:
+///
+/// ```compile_fail
+/// #![deny(unused_must_use)]
+/// use construct_core::protocol::messages::RegistrationBundle;
+/// use construct_core::protocol::validation::validate_registration_bundle;
+///
+/// fn register(bundle: &RegistrationBundle) {
+///     validate_registration_bundle(bundle);
+/// }
+/// ```
+#[must_use = "an invalid registration bundle must be rejected"]
+pub fn validate_registration_bundle(bundle: &RegistrationBundle) -> Result<()> {
+    let suite_id = bundle.suite_id.parse::<SuiteID>().ok();
+    if let Some(suite_id) = suite_id {
+        ensure_suite_available(suite_id)
+            .map_err(|e| ConstructError::ValidationError(e.to_string()))?;
+    }
+    let lengths = suite_id.and_then(suite_key_lengths).ok_or_else(|| {
+        ConstructError::ValidationError(format!("Unsupported suite id: {}", bundle.suite_id))
+    })?;
+
+    validate_base64_field_len("Identity public key", &bundle.identity_public, lengths.kem_public_key)?;
+    validate_base64_field_len(
+        "Signed prekey public",
+        &bundle.signed_prekey_public,
+        lengths.kem_public_key,
+    )?;
+    validate_base64_field_len("Signature", &bundle.signature, lengths.signature)?;
+    validate_base64_field_len("Verifying key", &bundle.verifying_key, lengths.verifying_key)
+}
+
+/// Проверить, что base64 поле кодирует ровно `bytes` байт (с padding)
+fn validate_base64_field_len(field: &str, value: &str, bytes: usize) -> Result<()> {
+    let chars = (bytes + 2) / 3 * 4;
+    if value.len() != chars {
+        return Err(ConstructError::ValidationError(format!(
+            "{} must be {} characters ({} bytes base64)",
+            field, chars, bytes
+        )));
+    }
+    Ok(())
+}
 
-    if bundle.signed_prekey_public.len() != 44 {
+/// Валидация зашифрованного бэкапа (размер и версия формата)
+pub fn validate_backup_blob(encrypted_blob: &[u8], version: u32) -> Result<()> {
+    if encrypted_blob.is_empty() {
         return Err(ConstructError::ValidationError(
-            "Signed prekey public must be 44 characters (32 bytes base64)".to_string(),
+            "Backup blob cannot be empty".to_string(),
         ));
     }
 
-    // Ed25519 signature должна быть 88 символов (64 bytes base64)
-    if bundle.signature.len() != 88 {
+    validate_field_size("Backup blob", encrypted_blob.len(), MAX_BACKUP_BLOB_SIZE)?;
+
+    if version == 0 || version > ARCHIVE_VERSION {
+        return Err(ConstructError::ValidationError(format!(
+            "Unsupported backup version: {}",
+            version
+        )));
+    }
+
+    Ok(())
+}
+
+/// Валидация запроса повторной отправки
+pub fn validate_resend_request(data: &RequestResendData) -> Result<()> {
+    validate_uuid(&data.from)?;
+    validate_uuid(&data.to)?;
+    validate_ratchet_dh_public_len(data.ratchet_dh_public.len())?;
+    validate_resend_numbers(data)
+}
+
+fn validate_resend_numbers(data: &RequestResendData) -> Result<()> {
+    if data.message_numbers.is_empty() {
         return Err(ConstructError::ValidationError(
-            "Signature must be 88 characters (64 bytes base64)".to_string(),
+            "Resend request must contain at least one message number".to_string(),
         ));
     }
 
-    // Ed25519 verifying key должен быть 44 символа
-    if bundle.verifying_key.len() != 44 {
+    validate_field_size(
+        "Resend request",
+        data.message_numbers.len(),
+        MAX_RESEND_REQUEST,
+    )
+}
+
+/// Валидация загрузки одноразовых prekey: непустой пакет с уникальными id
+/// и декодируемыми ключами и подписями
+pub fn validate_one_time_prekeys_upload(data: &UploadOneTimePrekeysData) -> Result<()> {
+    if data.prekeys.is_empty() {
         return Err(ConstructError::ValidationError(
-            "Verifying key must be 44 characters (32 bytes base64)".to_string(),
+            "One-time prekey upload must contain at least one prekey".to_string(),
         ));
     }
+    validate_field_size(
+        "One-time prekey upload",
+        data.prekeys.len(),
+        MAX_ONE_TIME_PREKEYS_UPLOAD,
+    )?;
 
+    let mut seen = std::collections::HashSet::new();
+    for prekey in &data.prekeys {
+        if !seen.insert(prekey.key_id) {
+            return Err(ConstructError::ValidationError(format!(
+                "Duplicate one-time prekey id {}",
+                prekey.key_id
+            )));
+        }
+        let decode = |field: &str, value: &str| {
+            general_purpose::STANDARD.decode(value).map_err(|_| {
+                ConstructError::ValidationError(format!("Invalid Base64 in one-time prekey {}", field))
+            })
+        };
+        let public_key = decode("publicKey", &prekey.public_key)?;
+        if public_key.is_empty() {
+            return Err(ConstructError::ValidationError(
+                "One-time prekey public key cannot be empty".to_string(),
+            ));
+        }
+        validate_field_size("One-time prekey", public_key.len(), MAX_RATCHET_DH_PUBLIC_SIZE)?;
+        let signature = decode("signature", &prekey.signature)?;
+        validate_field_size("One-time prekey signature", signature.len(), MAX_SIGNATURE_SIZE)?;
+    }
     Ok(())
 }
 
-/// Валидация ClientMessage (клиент → сервер)
-pub fn validate_client_message(msg: &ClientMessage) -> Result<()> {
+/// Валидация сообщений между клиентами
+pub fn validate_protocol_message(msg: &ProtocolMessage) -> Result<()> {
     match msg {
-        ClientMessage::Register(data) => {
-            validate_username(&data.username)?;
-            if data.password.len() < 8 {
+        ProtocolMessage::ReadReceipt {
+            message_ids,
+            conversation_id,
+            ..
+        } => {
+            in_field("conversationId", validate_uuid(conversation_id))?;
+            if message_ids.is_empty() {
                 return Err(ConstructError::ValidationError(
-                    "Password too short (min 8 chars)".to_string(),
+                    "Read receipt must reference at least one message".to_string(),
                 ));
             }
-            // Декодируем и валидируем бандл
-            let msgpack_bytes = general_purpose::STANDARD
-                .decode(&data.public_key)
-                .map_err(|_| {
-                    ConstructError::ValidationError("Invalid Base64 in public_key".to_string())
-                })?;
-            let bundle: RegistrationBundle = rmp_serde::from_slice(&msgpack_bytes).map_err(
-                |_| ConstructError::ValidationError("Invalid MessagePack in public_key".to_string()),
+            validate_field_size("Read receipt", message_ids.len(), MAX_READ_RECEIPT_MESSAGES)?;
+            for message_id in message_ids {
+                in_field("messageIds", validate_uuid(message_id))?;
+            }
+        }
+        ProtocolMessage::Typing { conversation_id, .. } => {
+            in_field("conversationId", validate_uuid(conversation_id))?;
+        }
+        ProtocolMessage::GroupMessage { sender_id, message } => {
+            in_field("senderId", validate_uuid(sender_id))?;
+            in_field("groupId", validate_uuid(&message.group_id))?;
+            validate_field_size("Group message", message.ciphertext.len(), MAX_MESSAGE_CONTENT_SIZE)?;
+            validate_field_size("Group message nonce", message.nonce.len(), MAX_SENDER_KEY_NONCE_SIZE)?;
+            validate_field_size("Group message signature", message.signature.len(), MAX_SIGNATURE_SIZE)?;
+        }
+        ProtocolMessage::DeviceLinkRequest { new_device_pubkey } => {
+            validate_field_size("Device link key", new_device_pubkey.len(), MAX_DEVICE_LINK_KEY_SIZE)?;
+        }
+        ProtocolMessage::DeviceLinkResponse { encrypted_identity_bundle } => {
+            validate_field_size(
+                "Device link response",
+                encrypted_identity_bundle.len(),
+                MAX_DEVICE_LINK_RESPONSE_SIZE,
             )?;
-            validate_registration_bundle(&bundle)?;
+        }
+        ProtocolMessage::IdentityRotation { new_bundle, signature_over_old } => {
+            in_field("newBundle", validate_registration_bundle(new_bundle))?;
+            // Старый ключ того же suite, что и новый bundle (suite уже проверен выше)
+            let lengths = new_bundle
+                .suite_id
+                .parse::<SuiteID>()
+                .ok()
+                .and_then(suite_key_lengths)
+                .ok_or_else(|| {
+                    ConstructError::ValidationError(format!("Unsupported suite id: {}", new_bundle.suite_id))
+                })?;
+            validate_base64_field_len("Signature over old key", signature_over_old, lengths.signature)?;
+        }
+        ProtocolMessage::UpdatePrekey(bundle) => validate_registration_bundle(bundle)?,
+        // Без Vec полей; хеш сверяется при обработке. Новые варианты с Vec полями
+        // должны получить здесь свою проверку размера - поэтому без `_`
+        ProtocolMessage::KeyDigest { .. } => {}
+    }
+
+    Ok(())
+}
+
+/// Валидация результата Register/Login: при успехе нет ошибки, при отказе есть причина
+fn validate_auth_result(success: bool, error: &Option<String>) -> Result<()> {
+    match (success, error) {
+        (true, Some(_)) => Err(ConstructError::ValidationError(
+            "Successful auth response must not carry an error".to_string(),
+        )),
+        (false, None) => Err(ConstructError::ValidationError(
+            "Failed auth response must carry an error".to_string(),
+        )),
+        (false, Some(error)) if error.is_empty() => Err(ConstructError::ValidationError(
+            "Auth error cannot be empty".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Валидация ответа сервера на Register
+pub fn validate_register_response(data: &RegisterResponseData) -> Result<()> {
+    validate_auth_result(data.success, &data.error)?;
+
+    match (&data.user_id, data.success) {
+        (Some(user_id), true) => validate_uuid(user_id),
+        (None, true) => Err(ConstructError::ValidationError(
+            "Successful registration must assign a user_id".to_string(),
+        )),
+        (Some(_), false) => Err(ConstructError::ValidationError(
+            "Failed registration must not assign a user_id".to_string(),
+        )),
+        (None, false) => Ok(()),
+    }
+}
+
+/// Валидация ClientMessage (клиент → сервер)
+pub fn validate_client_message(msg: &ClientMessage) -> Result<()> {
+    match msg {
+        ClientMessage::Register(data) => {
+            validate_username(&data.username)?;
+            validate_register_password(&data.password)?;
+            validate_register_public_key(&data.public_key)?;
         }
         ClientMessage::Login(data) => {
             validate_username(&data.username)?;
@@ -177,6 +517,15 @@ pub fn validate_client_message(msg: &ClientMessage) -> Result<()> {
                 ));
             }
         }
+        ClientMessage::BackupUpload(data) => {
+            validate_backup_blob(&data.encrypted_blob, data.version)?;
+        }
+        ClientMessage::RequestResend(data) => {
+            validate_resend_request(data)?;
+        }
+        ClientMessage::UploadOneTimePrekeys(data) => {
+            validate_one_time_prekeys_upload(data)?;
+        }
         // Logout, RotatePrekey не требуют специальной валидации на этом уровне
         _ => {}
     }
@@ -184,6 +533,76 @@ pub fn validate_client_message(msg: &ClientMessage) -> Result<()> {
     Ok(())
 }
 
+/// Валидация ClientMessage без остановки на первой ошибке (dry-run для UI и тестов).
+/// Возвращает все нарушения; пустой список - сообщение корректно.
+/// На горячем пути используется `validate_client_message`
+pub fn validate_client_message_all(msg: &ClientMessage) -> Vec<ConstructError> {
+    match msg {
+        ClientMessage::Register(data) => collect_errors([
+            validate_username(&data.username),
+            validate_register_password(&data.password),
+            validate_register_public_key(&data.public_key),
+        ]),
+        ClientMessage::SendMessage(chat_msg) => chat_message_errors(chat_msg, false),
+        ClientMessage::RequestResend(data) => collect_errors([
+            in_field("from", validate_uuid(&data.from)),
+            in_field("to", validate_uuid(&data.to)),
+            validate_ratchet_dh_public_len(data.ratchet_dh_public.len()),
+            validate_resend_numbers(data),
+        ]),
+        // В остальных сообщениях проверка одна - достаточно короткой версии
+        _ => validate_client_message(msg).err().into_iter().collect(),
+    }
+}
+
+fn validate_register_password(password: &str) -> Result<()> {
+    if password.len() < 8 {
+        return Err(ConstructError::ValidationError(
+            "Password too short (min 8 chars)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Декодировать (Base64 + MessagePack) и проверить бандл из Register
+fn validate_register_public_key(public_key: &str) -> Result<()> {
+    let msgpack_bytes = general_purpose::STANDARD
+        .decode(public_key)
+        .map_err(|_| ConstructError::ValidationError("Invalid Base64 in public_key".to_string()))?;
+    let bundle: RegistrationBundle = rmp_serde::from_slice(&msgpack_bytes).map_err(|_| {
+        ConstructError::ValidationError("Invalid MessagePack in public_key".to_string())
+    })?;
+    validate_registration_bundle(&bundle)
+}
+
+/// Валидация ServerMessage (сервер → клиент)
+pub fn validate_server_message(msg: &ServerMessage) -> Result<()> {
+    match msg {
+        ServerMessage::Message(chat_msg) => {
+            validate_chat_message_size(chat_msg)?;
+        }
+        ServerMessage::SearchResults(data) => {
+            validate_field_size("Search results", data.users.len(), MAX_SEARCH_RESULTS)?;
+        }
+        ServerMessage::BackupDownloadResponse(data) => {
+            validate_backup_blob(&data.encrypted_blob, data.version)?;
+        }
+        ServerMessage::RequestResend(data) => {
+            validate_resend_request(data)?;
+        }
+        ServerMessage::RegisterResponse(data) => {
+            validate_register_response(data)?;
+        }
+        ServerMessage::LoginResponse(data) => {
+            validate_auth_result(data.success, &data.error)?;
+        }
+        // Остальные сообщения проверяются при обработке
+        _ => {}
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -209,17 +628,546 @@ mod tests {
             id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
             from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
             to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
-            ephemeral_public_key: vec![0u8; 32],
+            ratchet_dh_public: vec![0u8; 32],
             message_number: 1,
-            content: "encrypted_content".to_string(),
+            content: "ZW5jcnlwdGVkX2NvbnRlbnQ=".to_string(),
             timestamp: crate::utils::time::current_timestamp() as u64,
+            seq: None,
         };
 
         assert!(validate_chat_message(&msg).is_ok());
 
         // Тест с неверным ephemeral key
         let mut bad_msg = msg.clone();
-        bad_msg.ephemeral_public_key = vec![0u8; 16]; // Неверная длина
+        bad_msg.ratchet_dh_public = vec![0u8; 16]; // Неверная длина
         assert!(validate_chat_message(&bad_msg).is_err());
     }
+
+    #[test]
+    fn test_verify_chat_message_integrity() {
+        let encrypted = EncryptedRatchetMessage {
+            dh_public_key: vec![5u8; 32],
+            message_number: 7,
+            ciphertext: vec![1, 2, 3],
+            nonce: vec![0u8; 12],
+            previous_chain_length: 0,
+            suite_id: 1,
+            key_confirmation: None,
+            x3dh_ephemeral_key: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            encrypted_header: None,
+        };
+        let msg = ChatMessage::from_encrypted(
+            "550e8400-e29b-41d4-a716-446655440001",
+            "550e8400-e29b-41d4-a716-446655440002",
+            &encrypted,
+        )
+        .unwrap();
+        assert_eq!(verify_chat_message_integrity(&msg).unwrap().message_number, 7);
+
+        let mut renumbered = msg.clone();
+        renumbered.message_number = 8;
+        assert!(matches!(
+            verify_chat_message_integrity(&renumbered),
+            Err(ConstructError::ValidationError(message))
+                if message == "Message number 8 does not match encrypted content (7)"
+        ));
+
+        let mut rekeyed = msg.clone();
+        rekeyed.ratchet_dh_public = vec![6u8; 32];
+        assert!(verify_chat_message_integrity(&rekeyed).is_err());
+
+        // Открытый заголовок проходит обычную валидацию, но не содержимое
+        let mut garbage = msg;
+        garbage.content = "ZW5jcnlwdGVkX2NvbnRlbnQ=".to_string();
+        assert!(validate_chat_message(&garbage).is_ok());
+        assert!(matches!(
+            verify_chat_message_integrity(&garbage),
+            Err(ConstructError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_client_message_reports_all_errors() {
+        let valid = ChatMessage {
+            id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+            from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            ratchet_dh_public: vec![0u8; 32],
+            message_number: 1,
+            content: "ZW5jcnlwdGVkX2NvbnRlbnQ=".to_string(),
+            timestamp: crate::utils::time::now(),
+            seq: None,
+        };
+        assert!(validate_client_message_all(&ClientMessage::SendMessage(valid.clone())).is_empty());
+
+        let broken = ClientMessage::SendMessage(ChatMessage {
+            id: "bad".to_string(),
+            from: "alice".to_string(),
+            ratchet_dh_public: vec![0u8; 31],
+            content: String::new(),
+            timestamp: crate::utils::time::now() + 3600,
+            ..valid
+        });
+        let messages: Vec<String> = validate_client_message_all(&broken)
+            .into_iter()
+            .map(|e| match e {
+                ConstructError::ValidationError(message) => message,
+                other => panic!("unexpected error: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "id: Invalid UUID format: incorrect length",
+                "from: Invalid UUID format: incorrect length",
+                "Ratchet DH public key must be 32-2048 bytes",
+                "Message content cannot be empty",
+                "Message timestamp is too far in the future",
+            ]
+        );
+
+        // Короткая версия останавливается на первой ошибке
+        assert!(matches!(
+            validate_client_message(&broken),
+            Err(ConstructError::ValidationError(message)) if message == "Invalid UUID format: incorrect length"
+        ));
+
+        let register = ClientMessage::Register(crate::protocol::messages::RegisterData {
+            username: "x".to_string(),
+            password: "short".to_string(),
+            public_key: "!!!".to_string(),
+        });
+        assert_eq!(validate_client_message_all(&register).len(), 3);
+    }
+
+    #[test]
+    fn test_validate_read_receipt() {
+        let receipt = ProtocolMessage::ReadReceipt {
+            message_ids: vec![
+                "550e8400-e29b-41d4-a716-446655440000".to_string(),
+                "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            ],
+            conversation_id: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            timestamp: crate::utils::time::current_timestamp(),
+        };
+        assert!(validate_protocol_message(&receipt).is_ok());
+
+        let ProtocolMessage::ReadReceipt { message_ids, conversation_id, timestamp } = receipt else {
+            unreachable!()
+        };
+        let with = |message_ids: Vec<String>, conversation_id: &str| ProtocolMessage::ReadReceipt {
+            message_ids,
+            conversation_id: conversation_id.to_string(),
+            timestamp,
+        };
+
+        let mut bad_id = message_ids.clone();
+        bad_id.push("not-a-uuid".to_string());
+        assert!(matches!(
+            validate_protocol_message(&with(bad_id, &conversation_id)),
+            Err(ConstructError::ValidationError(message))
+                if message == "messageIds: Invalid UUID format: incorrect length"
+        ));
+        assert!(validate_protocol_message(&with(message_ids.clone(), "bob")).is_err());
+        assert!(validate_protocol_message(&with(Vec::new(), &conversation_id)).is_err());
+        let too_many = vec![message_ids[0].clone(); MAX_READ_RECEIPT_MESSAGES + 1];
+        assert!(validate_protocol_message(&with(too_many, &conversation_id)).is_err());
+
+        let typing = |conversation_id: &str| ProtocolMessage::Typing {
+            conversation_id: conversation_id.to_string(),
+            is_typing: true,
+        };
+        assert!(validate_protocol_message(&typing(&conversation_id)).is_ok());
+        assert!(validate_protocol_message(&typing("bob")).is_err());
+    }
+
+    #[test]
+    fn test_validate_backup_blob() {
+        use crate::protocol::messages::{BackupDownloadResponseData, BackupUploadData};
+
+        let upload = ClientMessage::BackupUpload(BackupUploadData {
+            encrypted_blob: vec![1u8; 128],
+            version: ARCHIVE_VERSION,
+        });
+        assert!(validate_client_message(&upload).is_ok());
+
+        // Пустой блоб
+        assert!(validate_backup_blob(&[], ARCHIVE_VERSION).is_err());
+        // Неизвестная версия
+        assert!(validate_backup_blob(&[1u8; 16], ARCHIVE_VERSION + 1).is_err());
+        assert!(validate_backup_blob(&[1u8; 16], 0).is_err());
+        // Слишком большой блоб
+        let response = ServerMessage::BackupDownloadResponse(BackupDownloadResponseData {
+            encrypted_blob: vec![0u8; MAX_BACKUP_BLOB_SIZE + 1],
+            version: ARCHIVE_VERSION,
+        });
+        assert!(validate_server_message(&response).is_err());
+    }
+
+    #[test]
+    fn test_validate_one_time_prekeys_upload() {
+        use crate::protocol::messages::OneTimePrekeyData;
+
+        let prekey = |key_id| OneTimePrekeyData {
+            key_id,
+            public_key: general_purpose::STANDARD.encode([1u8; 32]),
+            signature: general_purpose::STANDARD.encode([2u8; 64]),
+        };
+        let mut data = UploadOneTimePrekeysData {
+            user_id: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            prekeys: vec![prekey(1), prekey(2)],
+        };
+        assert!(validate_client_message(&ClientMessage::UploadOneTimePrekeys(data.clone())).is_ok());
+
+        // Повторный id
+        data.prekeys.push(prekey(2));
+        assert!(validate_one_time_prekeys_upload(&data).is_err());
+
+        // Пустой пакет и слишком большой пакет
+        data.prekeys.clear();
+        assert!(validate_one_time_prekeys_upload(&data).is_err());
+        data.prekeys = (0..=MAX_ONE_TIME_PREKEYS_UPLOAD as u32).map(prekey).collect();
+        assert!(validate_one_time_prekeys_upload(&data).is_err());
+
+        // Ключ не в Base64
+        data.prekeys = vec![prekey(1)];
+        data.prekeys[0].public_key = "not base64!".to_string();
+        assert!(validate_one_time_prekeys_upload(&data).is_err());
+    }
+
+    #[test]
+    fn test_validate_resend_request() {
+        let mut data = RequestResendData {
+            from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            ratchet_dh_public: vec![0u8; RATCHET_DH_PUBLIC_SIZE],
+            message_numbers: vec![0, 3],
+        };
+        assert!(validate_client_message(&ClientMessage::RequestResend(data.clone())).is_ok());
+
+        // Слишком много номеров за один запрос
+        data.message_numbers = (0..=MAX_RESEND_REQUEST as u32).collect();
+        assert!(validate_server_message(&ServerMessage::RequestResend(data.clone())).is_err());
+
+        // Пустой запрос
+        data.message_numbers.clear();
+        assert!(validate_resend_request(&data).is_err());
+
+        // Неверная длина DH ключа цепочки
+        data.message_numbers = vec![1];
+        data.ratchet_dh_public.truncate(16);
+        assert!(validate_resend_request(&data).is_err());
+        data.ratchet_dh_public = vec![0u8; RATCHET_DH_PUBLIC_SIZE];
+        assert!(validate_resend_request(&data).is_ok());
+
+        // Некорректный UUID
+        data.to = "bob".to_string();
+        assert!(validate_resend_request(&data).is_err());
+    }
+
+    #[test]
+    fn test_vec_field_size_limits() {
+        use crate::protocol::messages::{BackupUploadData, PublicUserInfo, SearchResultsData};
+
+        // ChatMessage.content
+        let mut msg = ChatMessage {
+            id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+            from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            ratchet_dh_public: vec![0u8; RATCHET_DH_PUBLIC_SIZE],
+            message_number: 0,
+            content: "A".repeat(MAX_MESSAGE_CONTENT_SIZE),
+            timestamp: 0,
+            seq: None,
+        };
+        assert!(validate_server_message(&ServerMessage::Message(msg.clone())).is_ok());
+        msg.content.push('A');
+        let err = validate_server_message(&ServerMessage::Message(msg.clone())).unwrap_err();
+        assert!(err.to_string().contains("Message content too large"));
+        assert!(validate_client_message(&ClientMessage::SendMessage(msg.clone())).is_err());
+
+        // ChatMessage.ratchet_dh_public
+        msg.content = "AQID".to_string();
+        msg.ratchet_dh_public = vec![0u8; RATCHET_DH_PUBLIC_SIZE + 16];
+        assert!(validate_server_message(&ServerMessage::Message(msg.clone())).is_ok());
+        msg.ratchet_dh_public = vec![0u8; MAX_RATCHET_DH_PUBLIC_SIZE + 1];
+        assert!(validate_server_message(&ServerMessage::Message(msg)).is_err());
+
+        // SearchResults.users
+        let user = PublicUserInfo {
+            id: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            username: "bob".to_string(),
+        };
+        let mut results = SearchResultsData {
+            users: vec![user.clone(); MAX_SEARCH_RESULTS],
+        };
+        assert!(validate_server_message(&ServerMessage::SearchResults(results.clone())).is_ok());
+        results.users.push(user);
+        assert!(validate_server_message(&ServerMessage::SearchResults(results)).is_err());
+
+        // BackupUpload.encrypted_blob
+        let mut upload = BackupUploadData {
+            encrypted_blob: vec![0u8; MAX_BACKUP_BLOB_SIZE],
+            version: ARCHIVE_VERSION,
+        };
+        assert!(validate_client_message(&ClientMessage::BackupUpload(upload.clone())).is_ok());
+        upload.encrypted_blob.push(0);
+        assert!(validate_client_message(&ClientMessage::BackupUpload(upload)).is_err());
+
+        // RequestResend.message_numbers
+        let mut resend = RequestResendData {
+            from: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            to: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+            ratchet_dh_public: vec![0u8; RATCHET_DH_PUBLIC_SIZE],
+            message_numbers: vec![0; MAX_RESEND_REQUEST],
+        };
+        assert!(validate_client_message(&ClientMessage::RequestResend(resend.clone())).is_ok());
+        resend.message_numbers.push(0);
+        assert!(validate_client_message(&ClientMessage::RequestResend(resend)).is_err());
+    }
+
+    #[test]
+    fn test_protocol_message_size_limits() {
+        use crate::crypto::sender_keys::SenderKeyMessage;
+
+        let at_and_over = |max: usize, message: &dyn Fn(usize) -> ProtocolMessage| {
+            assert!(validate_protocol_message(&message(max)).is_ok());
+            let err = validate_protocol_message(&message(max + 1)).unwrap_err();
+            assert!(err.to_string().contains("too large"), "{}", err);
+        };
+
+        // ReadReceipt.message_ids
+        at_and_over(MAX_READ_RECEIPT_MESSAGES, &|len| ProtocolMessage::ReadReceipt {
+            message_ids: vec!["550e8400-e29b-41d4-a716-446655440000".to_string(); len],
+            conversation_id: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            timestamp: 0,
+        });
+
+        // DeviceLinkRequest.new_device_pubkey
+        at_and_over(MAX_DEVICE_LINK_KEY_SIZE, &|len| ProtocolMessage::DeviceLinkRequest {
+            new_device_pubkey: vec![0u8; len],
+        });
+
+        // DeviceLinkResponse.encrypted_identity_bundle
+        at_and_over(MAX_DEVICE_LINK_RESPONSE_SIZE, &|len| ProtocolMessage::DeviceLinkResponse {
+            encrypted_identity_bundle: vec![0u8; len],
+        });
+
+        // GroupMessage: ciphertext, nonce и signature
+        let group_message = |ciphertext: usize, nonce: usize, signature: usize| ProtocolMessage::GroupMessage {
+            sender_id: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            message: SenderKeyMessage {
+                group_id: "550e8400-e29b-41d4-a716-446655440002".to_string(),
+                iteration: 0,
+                nonce: vec![0u8; nonce],
+                ciphertext: vec![0u8; ciphertext],
+                signature: vec![0u8; signature],
+            },
+        };
+        at_and_over(MAX_MESSAGE_CONTENT_SIZE, &|len| group_message(len, 12, 64));
+        at_and_over(MAX_SENDER_KEY_NONCE_SIZE, &|len| group_message(16, len, 64));
+        at_and_over(MAX_SIGNATURE_SIZE, &|len| group_message(16, 12, len));
+    }
+
+    #[test]
+    fn test_validate_auth_responses() {
+        use crate::protocol::messages::LoginResponseData;
+
+        let ok = RegisterResponseData {
+            user_id: Some("550e8400-e29b-41d4-a716-446655440000".to_string()),
+            success: true,
+            error: None,
+        };
+        assert!(validate_server_message(&ServerMessage::RegisterResponse(ok.clone())).is_ok());
+
+        // Успех с некорректным UUID
+        let mut bad = ok.clone();
+        bad.user_id = Some("user-1".to_string());
+        assert!(validate_register_response(&bad).is_err());
+
+        // Отказ не может назначать user_id и должен содержать причину
+        let mut failed = ok;
+        failed.success = false;
+        failed.error = Some("Username taken".to_string());
+        assert!(validate_register_response(&failed).is_err());
+        failed.user_id = None;
+        assert!(validate_register_response(&failed).is_ok());
+        failed.error = None;
+        assert!(validate_register_response(&failed).is_err());
+
+        let login = |success, error: Option<&str>| {
+            validate_server_message(&ServerMessage::LoginResponse(LoginResponseData {
+                success,
+                error: error.map(str::to_string),
+            }))
+        };
+        assert!(login(true, None).is_ok());
+        assert!(login(false, Some("Invalid credentials")).is_ok());
+        assert!(login(true, Some("Invalid credentials")).is_err());
+        assert!(login(false, Some("")).is_err());
+    }
+
+    #[test]
+    fn test_validate_self_message() {
+        let self_id = "550e8400-e29b-41d4-a716-446655440001";
+        let msg = ChatMessage {
+            id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
+            from: self_id.to_string(),
+            to: self_id.to_string(),
+            ratchet_dh_public: vec![0u8; RATCHET_DH_PUBLIC_SIZE],
+            message_number: 0,
+            content: "AQID".to_string(),
+            timestamp: crate::utils::time::now(),
+            seq: None,
+        };
+
+        let err = validate_chat_message(&msg).unwrap_err();
+        assert!(err.to_string().contains("Sender and recipient must differ"));
+        assert!(validate_client_message(&ClientMessage::SendMessage(msg.clone())).is_err());
+
+        assert!(validate_chat_message_with(&msg, true).is_ok());
+    }
+
+    fn bundle_with_lengths(suite_id: SuiteID, lengths: crate::crypto::SuiteKeyLengths) -> RegistrationBundle {
+        let b64 = |len: usize| general_purpose::STANDARD.encode(vec![7u8; len]);
+        RegistrationBundle {
+            identity_public: b64(lengths.kem_public_key),
+            signed_prekey_public: b64(lengths.kem_public_key),
+            signature: b64(lengths.signature),
+            verifying_key: b64(lengths.verifying_key),
+            suite_id: suite_id.to_string(),
+            signed_prekey_id: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_classic_registration_bundle() {
+        use crate::crypto::{CLASSIC_SHA512_SUITE_ID, CLASSIC_SUITE_ID, PQ_HYBRID_SUITE_ID};
+
+        let lengths = suite_key_lengths(CLASSIC_SUITE_ID).unwrap();
+        let bundle = bundle_with_lengths(CLASSIC_SUITE_ID, lengths);
+        assert_eq!(bundle.identity_public.len(), 44);
+        assert_eq!(bundle.signature.len(), 88);
+        assert!(validate_registration_bundle(&bundle).is_ok());
+        assert!(validate_registration_bundle(&bundle_with_lengths(CLASSIC_SHA512_SUITE_ID, lengths)).is_ok());
+
+        let mut short = bundle.clone();
+        short.signature = general_purpose::STANDARD.encode([7u8; 32]);
+        let err = validate_registration_bundle(&short).unwrap_err();
+        assert!(err.to_string().contains("Signature must be 88 characters (64 bytes base64)"));
+
+        // Классический бандл под PQ suite не проходит
+        let mut mislabeled = bundle.clone();
+        mislabeled.suite_id = PQ_HYBRID_SUITE_ID.to_string();
+        let err = validate_registration_bundle(&mislabeled).unwrap_err();
+        if !crate::crypto::build_supports_pq() {
+            assert!(err
+                .to_string()
+                .contains("post-quantum suite not supported in this build"));
+        }
+
+        let mut unknown = bundle;
+        unknown.suite_id = "42".to_string();
+        let err = validate_registration_bundle(&unknown).unwrap_err();
+        assert!(err.to_string().contains("Unsupported suite id: 42"));
+    }
+
+    #[test]
+    #[cfg(feature = "post-quantum")]
+    fn test_validate_pq_registration_bundle() {
+        use crate::crypto::{CLASSIC_SUITE_ID, PQ_HYBRID_SUITE_ID};
+
+        let lengths = suite_key_lengths(PQ_HYBRID_SUITE_ID).unwrap();
+        let bundle = bundle_with_lengths(PQ_HYBRID_SUITE_ID, lengths);
+        assert!(validate_registration_bundle(&bundle).is_ok());
+
+        // Настоящий гибридный bundle (размеры из pqcrypto) проходит проверку
+        let keys = crate::crypto::X3DH::<crate::crypto::PQHybridSuiteProvider>::generate_registration_bundle().unwrap();
+        let genuine = RegistrationBundle {
+            identity_public: general_purpose::STANDARD.encode(&keys.identity_public),
+            signed_prekey_public: general_purpose::STANDARD.encode(&keys.signed_prekey_public),
+            signature: general_purpose::STANDARD.encode(&keys.signature),
+            verifying_key: general_purpose::STANDARD.encode(&keys.verifying_key),
+            suite_id: PQ_HYBRID_SUITE_ID.to_string(),
+            signed_prekey_id: None,
+        };
+        assert!(validate_registration_bundle(&genuine).is_ok());
+
+        // PQ бандл под классическим suite не проходит
+        let mut mislabeled = bundle;
+        mislabeled.suite_id = CLASSIC_SUITE_ID.to_string();
+        assert!(validate_registration_bundle(&mislabeled).is_err());
+    }
+
+    /// Случайные ChatMessage из корректных и испорченных полей: валидация принимает
+    /// сообщение ровно тогда, когда корректно каждое поле, и не паникует на мусоре
+    #[test]
+    fn test_random_chat_messages_rejected_when_invalid() {
+        use rand::{seq::SliceRandom, Rng, SeedableRng};
+
+        let good_ids = [
+            "550e8400-e29b-41d4-a716-446655440000",
+            "550e8400-e29b-41d4-a716-446655440001",
+        ];
+        let bad_ids = ["", "bob", "550e8400-e29b-41d4-a716-44665544000", "550e8400e29b41d4a716446655440000----"];
+        let good_contents = ["AQID", "AAAAAAAAAAAAAAAA"];
+        let bad_contents = ["", "not base64!", "AQI"];
+        let dh_lens = [0, 31, 32, 48, 1216, MAX_RATCHET_DH_PUBLIC_SIZE, MAX_RATCHET_DH_PUBLIC_SIZE + 1];
+        // Смещения timestamp от текущего времени далеко от границ окна
+        let offsets: [i64; 5] = [-7200, -1800, 0, 120, 1200];
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0xc0ffee);
+        let now = crate::utils::time::now() as i64;
+        for _ in 0..2000 {
+            let mut pick_id = || {
+                if rng.gen_bool(0.8) {
+                    (*good_ids.choose(&mut rng).unwrap(), true)
+                } else {
+                    (*bad_ids.choose(&mut rng).unwrap(), false)
+                }
+            };
+            let (id, id_ok) = pick_id();
+            let (from, from_ok) = pick_id();
+            let (to, to_ok) = pick_id();
+            let (content, content_ok) = if rng.gen_bool(0.8) {
+                (*good_contents.choose(&mut rng).unwrap(), true)
+            } else {
+                (*bad_contents.choose(&mut rng).unwrap(), false)
+            };
+            let dh_len = *dh_lens.choose(&mut rng).unwrap();
+            let offset = *offsets.choose(&mut rng).unwrap();
+
+            let msg = ChatMessage {
+                id: id.to_string(),
+                from: from.to_string(),
+                to: to.to_string(),
+                ratchet_dh_public: vec![7u8; dh_len],
+                message_number: rng.gen(),
+                content: content.to_string(),
+                timestamp: (now + offset) as u64,
+                seq: None,
+            };
+            let dh_ok = (RATCHET_DH_PUBLIC_SIZE..=MAX_RATCHET_DH_PUBLIC_SIZE).contains(&dh_len);
+            let expected = id_ok
+                && from_ok
+                && to_ok
+                && from != to
+                && dh_ok
+                && content_ok
+                && (-3600..=300).contains(&offset);
+
+            let client_msg = ClientMessage::SendMessage(msg.clone());
+            assert_eq!(validate_client_message(&client_msg).is_ok(), expected, "{:?}", msg);
+            assert_eq!(validate_client_message_all(&client_msg).is_empty(), expected, "{:?}", msg);
+            assert_eq!(
+                validate_server_message(&ServerMessage::Message(msg.clone())).is_ok(),
+                dh_ok,
+                "{:?}",
+                msg
+            );
+        }
+    }
 }
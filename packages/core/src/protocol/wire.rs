@@ -1,10 +1,21 @@
 // Wire format (MessagePack сериализация)
 // Используется для передачи сообщений через WebSocket
+// JSON кодек - только для отладки (читается в devtools)
 
-use crate::protocol::messages::{ClientMessage, ServerMessage};
+use crate::protocol::messages::{ClientMessage, ProtocolMessage, ServerMessage};
 use crate::utils::error::{ConstructError, Result};
 use rmp_serde::{Deserializer, Serializer};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Кодек WebSocket фреймов
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireCodec {
+    /// Бинарные MessagePack фреймы (production)
+    #[default]
+    MessagePack,
+    /// Текстовые JSON фреймы (отладка)
+    Json,
+}
 
 /// Упаковать ClientMessage в MessagePack формат (клиент -> сервер)
 pub fn pack_client_message(message: &ClientMessage) -> Result<Vec<u8>> {
@@ -24,6 +35,53 @@ pub fn unpack_server_message(data: &[u8]) -> Result<ServerMessage> {
         .map_err(|e| ConstructError::SerializationError(format!("MessagePack unpack error: {}", e)))
 }
 
+/// Упаковать ProtocolMessage в MessagePack (клиент -> клиент)
+pub fn pack_protocol_message(message: &ProtocolMessage) -> Result<Vec<u8>> {
+    pack_raw(message)
+}
+
+/// Распаковать ProtocolMessage из MessagePack и проверить его поля
+pub fn unpack_protocol_message(data: &[u8]) -> Result<ProtocolMessage> {
+    let message: ProtocolMessage = unpack_raw(data)?;
+    crate::protocol::validation::validate_protocol_message(&message)?;
+    Ok(message)
+}
+
+/// Упаковать сообщение в JSON (отладочный кодек)
+pub fn pack_message_json<T: Serialize>(message: &T) -> Result<String> {
+    serde_json::to_string(message)
+        .map_err(|e| ConstructError::SerializationError(format!("JSON pack error: {}", e)))
+}
+
+/// Распаковать сообщение из JSON (отладочный кодек)
+pub fn unpack_message_json<T: DeserializeOwned>(data: &str) -> Result<T> {
+    serde_json::from_str(data)
+        .map_err(|e| ConstructError::SerializationError(format!("JSON unpack error: {}", e)))
+}
+
+/// Входящий WebSocket фрейм
+#[derive(Debug, Clone, Copy)]
+pub enum InboundFrame<'a> {
+    Binary(&'a [u8]),
+    Text(&'a str),
+}
+
+/// Декодировать входящий фрейм от сервера
+///
+/// Протокол использует только бинарные MessagePack фреймы. Текстовый фрейм
+/// считается ошибкой сервера; разобрать его как JSON можно только в режиме
+/// совместимости (`json_fallback`).
+pub fn decode_server_frame(frame: InboundFrame<'_>, json_fallback: bool) -> Result<ServerMessage> {
+    match frame {
+        InboundFrame::Binary(data) => unpack_server_message(data),
+        InboundFrame::Text(text) if json_fallback => unpack_message_json(text),
+        InboundFrame::Text(text) => Err(ConstructError::NetworkError(format!(
+            "Unexpected text frame ({} bytes): server must send binary MessagePack frames",
+            text.len()
+        ))),
+    }
+}
+
 /// Упаковать произвольные данные в MessagePack
 pub fn pack_raw<T: Serialize>(data: &T) -> Result<Vec<u8>> {
     let mut buffer = Vec::new();
@@ -56,4 +114,306 @@ mod tests {
         let packed = pack_client_message(&msg).unwrap();
         assert!(!packed.is_empty());
     }
+
+    #[test]
+    fn test_decode_text_frame() {
+        let msg = ServerMessage::LogoutSuccess;
+        let json = serde_json::to_string(&msg).unwrap();
+
+        // По умолчанию текстовый фрейм отклоняется
+        let err = decode_server_frame(InboundFrame::Text(&json), false).unwrap_err();
+        assert!(matches!(err, ConstructError::NetworkError(_)));
+
+        // В режиме совместимости разбирается как JSON
+        let decoded = decode_server_frame(InboundFrame::Text(&json), true).unwrap();
+        assert!(matches!(decoded, ServerMessage::LogoutSuccess));
+
+        // Мусор в режиме совместимости - ошибка сериализации, а не паника
+        let err = decode_server_frame(InboundFrame::Text("not json"), true).unwrap_err();
+        assert!(matches!(err, ConstructError::SerializationError(_)));
+    }
+
+    fn sample_client_messages() -> Vec<ClientMessage> {
+        use crate::protocol::messages::*;
+
+        vec![
+            ClientMessage::Register(RegisterData {
+                username: "alice".to_string(),
+                password: "password1".to_string(),
+                public_key: "a2V5".to_string(),
+            }),
+            ClientMessage::Login(LoginData {
+                username: "alice".to_string(),
+                password: "password1".to_string(),
+            }),
+            ClientMessage::Connect(ConnectData {
+                session_token: "token".to_string(),
+            }),
+            ClientMessage::SearchUsers(SearchUsersData {
+                query: "bo".to_string(),
+            }),
+            ClientMessage::GetPublicKey(GetPublicKeyData {
+                user_id: "bob".to_string(),
+            }),
+            ClientMessage::SendMessage(ChatMessage {
+                id: "m1".to_string(),
+                from: "alice".to_string(),
+                to: "bob".to_string(),
+                ratchet_dh_public: vec![7u8; 32],
+                message_number: 3,
+                content: "AQID".to_string(),
+                timestamp: 1_700_000_000,
+                seq: None,
+            }),
+            ClientMessage::RotatePrekey(RotatePrekeyData {
+                user_id: "alice".to_string(),
+                update: "dXBk".to_string(),
+            }),
+            ClientMessage::UploadOneTimePrekeys(UploadOneTimePrekeysData {
+                user_id: "alice".to_string(),
+                prekeys: vec![OneTimePrekeyData {
+                    key_id: 1,
+                    public_key: "cHVi".to_string(),
+                    signature: "c2ln".to_string(),
+                }],
+            }),
+            ClientMessage::Logout(LogoutData {
+                session_token: "token".to_string(),
+            }),
+            ClientMessage::BackupUpload(BackupUploadData {
+                encrypted_blob: vec![1, 2, 3],
+                version: 1,
+            }),
+            ClientMessage::BackupDownloadRequest(BackupDownloadRequestData { max_version: 1 }),
+            ClientMessage::RequestResend(RequestResendData {
+                from: "alice".to_string(),
+                to: "bob".to_string(),
+                ratchet_dh_public: vec![7; 32],
+                message_numbers: vec![1, 2],
+            }),
+        ]
+    }
+
+    fn sample_server_messages() -> Vec<ServerMessage> {
+        use crate::protocol::messages::*;
+
+        vec![
+            ServerMessage::RegisterSuccess(RegisterSuccessData {
+                user_id: "alice".to_string(),
+                username: "alice".to_string(),
+                session_token: "token".to_string(),
+                expires: 10,
+            }),
+            ServerMessage::LoginSuccess(LoginSuccessData {
+                user_id: "alice".to_string(),
+                username: "alice".to_string(),
+                session_token: "token".to_string(),
+                expires: 10,
+            }),
+            ServerMessage::ConnectSuccess(ConnectSuccessData {
+                user_id: "alice".to_string(),
+                username: "alice".to_string(),
+            }),
+            ServerMessage::SessionExpired,
+            ServerMessage::SearchResults(SearchResultsData {
+                users: vec![PublicUserInfo {
+                    id: "bob".to_string(),
+                    username: "bob".to_string(),
+                }],
+            }),
+            ServerMessage::PublicKeyBundle(PublicKeyBundleData {
+                user_id: "bob".to_string(),
+                identity_public: "aWQ=".to_string(),
+                signed_prekey_public: "c3Br".to_string(),
+                signature: "c2ln".to_string(),
+                verifying_key: "dms=".to_string(),
+                suite_id: Some("1".to_string()),
+                one_time_prekey_public: None,
+                one_time_prekey_id: None,
+                signed_prekey_id: None,
+            }),
+            // Suite не указан, одноразовый prekey есть - поля не должны сдвигаться
+            ServerMessage::PublicKeyBundle(PublicKeyBundleData {
+                user_id: "bob".to_string(),
+                identity_public: "aWQ=".to_string(),
+                signed_prekey_public: "c3Br".to_string(),
+                signature: "c2ln".to_string(),
+                verifying_key: "dms=".to_string(),
+                suite_id: None,
+                one_time_prekey_public: Some("b3Rw".to_string()),
+                one_time_prekey_id: Some(7),
+                signed_prekey_id: Some(3),
+            }),
+            ServerMessage::Message(ChatMessage {
+                id: "m1".to_string(),
+                from: "bob".to_string(),
+                to: "alice".to_string(),
+                ratchet_dh_public: vec![9u8; 32],
+                message_number: 0,
+                content: "AQID".to_string(),
+                timestamp: 1_700_000_000,
+                seq: Some(17),
+            }),
+            ServerMessage::Ack(AckData {
+                message_id: "m1".to_string(),
+                status: "delivered".to_string(),
+            }),
+            ServerMessage::KeyRotationSuccess,
+            ServerMessage::Error(ErrorData {
+                code: "E1".to_string(),
+                message: "boom".to_string(),
+            }),
+            ServerMessage::LogoutSuccess,
+            ServerMessage::RegisterResponse(RegisterResponseData {
+                user_id: None,
+                success: false,
+                error: Some("username taken".to_string()),
+            }),
+            ServerMessage::BackupDownloadResponse(BackupDownloadResponseData {
+                encrypted_blob: vec![4, 5, 6],
+                version: 1,
+            }),
+            ServerMessage::RequestResend(RequestResendData {
+                from: "bob".to_string(),
+                to: "alice".to_string(),
+                ratchet_dh_public: vec![8; 32],
+                message_numbers: vec![0],
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_json_codec_roundtrip_all_variants() {
+        for msg in sample_client_messages() {
+            let json = pack_message_json(&msg).unwrap();
+            let decoded: ClientMessage = unpack_message_json(&json).unwrap();
+            assert_eq!(pack_message_json(&decoded).unwrap(), json);
+        }
+
+        for msg in sample_server_messages() {
+            let json = pack_message_json(&msg).unwrap();
+            let decoded: ServerMessage = unpack_message_json(&json).unwrap();
+            assert_eq!(pack_message_json(&decoded).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_json_and_messagepack_are_equivalent() {
+        for msg in sample_client_messages() {
+            let json = pack_message_json(&msg).unwrap();
+
+            let packed = pack_client_message(&msg).unwrap();
+            let from_msgpack: ClientMessage = unpack_raw(&packed).unwrap();
+            let from_json: ClientMessage = unpack_message_json(&json).unwrap();
+
+            assert_eq!(
+                pack_message_json(&from_msgpack).unwrap(),
+                pack_message_json(&from_json).unwrap()
+            );
+        }
+    }
+
+    /// Структуры кодируются массивами: пропущенное `None` поле сдвинуло бы следующие
+    #[test]
+    fn test_optional_fields_keep_positions() {
+        for msg in sample_server_messages() {
+            if !matches!(msg, ServerMessage::PublicKeyBundle(_) | ServerMessage::RegisterResponse(_)) {
+                continue;
+            }
+            let json = pack_message_json(&msg).unwrap();
+            let from_msgpack = unpack_server_message(&pack_raw(&msg).unwrap()).unwrap();
+            assert_eq!(pack_message_json(&from_msgpack).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_protocol_message_roundtrip() {
+        let typing = ProtocolMessage::Typing {
+            conversation_id: "550e8400-e29b-41d4-a716-446655440001".to_string(),
+            is_typing: true,
+        };
+        let packed = pack_protocol_message(&typing).unwrap();
+        assert_eq!(unpack_protocol_message(&packed).unwrap(), typing);
+
+        // Распаковка валидирует поля
+        let invalid = ProtocolMessage::Typing {
+            conversation_id: "bob".to_string(),
+            is_typing: false,
+        };
+        let packed = pack_protocol_message(&invalid).unwrap();
+        assert!(matches!(
+            unpack_protocol_message(&packed),
+            Err(ConstructError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_binary_frame() {
+        use crate::protocol::messages::ErrorData;
+
+        let msg = ServerMessage::Error(ErrorData {
+            code: "E1".to_string(),
+            message: "boom".to_string(),
+        });
+        let packed = pack_raw(&msg).unwrap();
+        let decoded = decode_server_frame(InboundFrame::Binary(&packed), false).unwrap();
+        assert!(matches!(decoded, ServerMessage::Error(data) if data.code == "E1"));
+    }
+
+    /// Случайная порча фрейма: бит, обрезка, вставка или "опасный" байт длины
+    fn mutate(frame: &[u8], rng: &mut impl rand::Rng) -> Vec<u8> {
+        let mut out = frame.to_vec();
+        for _ in 0..rng.gen_range(1..4) {
+            let pos = rng.gen_range(0..=out.len());
+            match rng.gen_range(0..4) {
+                0 if pos < out.len() => out[pos] ^= 1 << rng.gen_range(0..8),
+                1 => out.truncate(pos),
+                2 => out.insert(pos, rng.gen()),
+                _ if pos < out.len() => {
+                    // Маркеры bin32/str32/array32/map32 с огромной длиной
+                    out[pos] = [0xc6, 0xdb, 0xdd, 0xdf, 0xff][rng.gen_range(0..5)];
+                }
+                _ => out.push(0xff),
+            }
+        }
+        out
+    }
+
+    /// Fuzz-прогон без внешних инструментов: случайные байты и испорченные фреймы
+    /// из корпуса известных сообщений. Разбор и валидация возвращают ошибку, а не паникуют.
+    /// Долгий прогон - `cargo fuzz` в `packages/core/fuzz` (см. docs/TESTING.md)
+    #[test]
+    fn test_unpack_mutated_frames_never_panics() {
+        use crate::protocol::validation::{validate_client_message, validate_server_message};
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x5eed);
+        // Сервер шлет структуры картами (с именами полей)
+        let mut corpus: Vec<Vec<u8>> = sample_server_messages()
+            .iter()
+            .map(|msg| rmp_serde::to_vec_named(msg).unwrap())
+            .collect();
+        corpus.extend(sample_client_messages().iter().map(|msg| pack_client_message(msg).unwrap()));
+
+        for round in 0..5000 {
+            let frame = if round % 5 == 0 {
+                let len = rng.gen_range(0..256);
+                (0..len).map(|_| rng.gen()).collect()
+            } else {
+                mutate(&corpus[rng.gen_range(0..corpus.len())], &mut rng)
+            };
+
+            if let Ok(msg) = unpack_server_message(&frame) {
+                let _ = validate_server_message(&msg);
+            }
+            if let Ok(msg) = unpack_raw::<ClientMessage>(&frame) {
+                let _ = validate_client_message(&msg);
+            }
+        }
+
+        // Сам корпус разбирается без ошибок
+        for frame in &corpus[..sample_server_messages().len()] {
+            unpack_server_message(frame).unwrap();
+        }
+    }
 }
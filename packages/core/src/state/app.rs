@@ -1,24 +1,46 @@
-use crate::api::contacts::{Contact, ContactManager};
-use crate::api::crypto::CryptoCore;
+use crate::api::contacts::{Contact, ContactManager, PublicKeyBundle};
+use crate::api::crypto::{CryptoCore, KeyBundle};
 use crate::storage::models::*;
 use crate::utils::error::{ConstructError, Result};
+use crate::utils::ids::{ContactId, SessionId};
 use crate::utils::time::current_timestamp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-#[cfg(target_arch = "wasm32")]
-use crate::storage::indexeddb::IndexedDbStorage;
+use crate::storage::{DefaultStorage, Storage};
 
 #[cfg(not(target_arch = "wasm32"))]
-use crate::storage::memory::MemoryStorage;
+use crate::storage::complete_now;
 
-use crate::protocol::messages::ChatMessage;
+use crate::protocol::messages::{
+    AckData, BackupDownloadRequestData, BackupDownloadResponseData, BackupUploadData, ChatMessage,
+    ClientMessage, GetPublicKeyData, LoginResponseData, MessageBody, ProtocolMessage, PublicKeyBundleData,
+    RegisterResponseData, RequestResendData, ServerMessage,
+};
 use crate::state::conversations::ConversationsManager;
+use crate::state::events::AppEvent;
+use crate::state::notifications::{
+    DefaultNotificationPolicy, NotificationContext, NotificationDecision, NotificationPolicy,
+};
+use crate::state::plaintext_cache::PlaintextCache;
+use crate::crypto::device_link;
+use crate::crypto::keys::{
+    KeyManager, DEFAULT_PREKEY_MAX_AGE_SECONDS, DEFAULT_PREKEY_ROTATION_SECONDS, MIN_ONE_TIME_PREKEYS,
+    TARGET_ONE_TIME_PREKEYS,
+};
+use crate::crypto::sender_keys::{GroupSession, SenderKeyDistributionMessage};
+use crate::crypto::session::ImportReport;
 use crate::crypto::CryptoProvider;
+use std::cell::RefCell;
 use std::marker::PhantomData;
+use zeroize::{Zeroize, Zeroizing};
 
+use crate::protocol::transport::{ConnectionState as TransportState, ReconnectPolicy};
 #[cfg(target_arch = "wasm32")]
 use crate::protocol::transport::WebSocketTransport;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::protocol::transport::Transport;
+
 
 
 /// Состояние подключения к серверу
@@ -76,55 +98,43 @@ impl Default for UiState {
 }
 
 /// Состояние автоматического переподключения
+///
+/// Сам backoff выполняет транспорт по той же `ReconnectPolicy`; здесь политика
+/// хранится для новых транспортов и ведется счетчик попыток для UI
 #[derive(Debug, Clone)]
 pub struct ReconnectState {
     /// Количество попыток переподключения
     attempts: u32,
-    /// Максимальное количество попыток (0 = бесконечно)
-    max_attempts: u32,
-    /// Текущая задержка в миллисекундах
-    current_delay_ms: u32,
-    /// Начальная задержка в миллисекундах
-    initial_delay_ms: u32,
-    /// Максимальная задержка в миллисекундах
-    max_delay_ms: u32,
-    /// Включено ли автоматическое переподключение
-    enabled: bool,
+    /// Задержки, jitter и лимит попыток
+    policy: ReconnectPolicy,
 }
 
 impl ReconnectState {
     /// Создать новое состояние переподключения
     pub fn new() -> Self {
-        Self {
-            attempts: 0,
-            max_attempts: 0,        // Бесконечные попытки
-            current_delay_ms: 1000, // Начинаем с 1 секунды
-            initial_delay_ms: 1000,
-            max_delay_ms: 30000, // Максимум 30 секунд
-            enabled: true,
-        }
+        Self::with_policy(ReconnectPolicy::default())
     }
 
-    /// Вычислить следующую задержку с exponential backoff
-    pub fn next_delay(&mut self) -> u32 {
-        let delay = self.current_delay_ms;
+    /// Создать состояние с заданной политикой
+    pub fn with_policy(policy: ReconnectPolicy) -> Self {
+        Self { attempts: 0, policy }
+    }
 
-        // Exponential backoff: удваиваем задержку
-        self.current_delay_ms = (self.current_delay_ms * 2).min(self.max_delay_ms);
+    /// Вычислить следующую задержку (exponential backoff с jitter)
+    pub fn next_delay(&mut self) -> u32 {
+        let delay = self.policy.delay_ms(self.attempts);
         self.attempts += 1;
-
         delay
     }
 
     /// Сбросить счётчик попыток
     pub fn reset(&mut self) {
         self.attempts = 0;
-        self.current_delay_ms = self.initial_delay_ms;
     }
 
     /// Проверить, можно ли продолжать попытки
     pub fn can_retry(&self) -> bool {
-        self.enabled && (self.max_attempts == 0 || self.attempts < self.max_attempts)
+        self.policy.can_retry(self.attempts)
     }
 
     /// Получить количество попыток
@@ -134,7 +144,12 @@ impl ReconnectState {
 
     /// Включить/выключить автоматическое переподключение
     pub fn set_enabled(&mut self, enabled: bool) {
-        self.enabled = enabled;
+        self.policy.enabled = enabled;
+    }
+
+    /// Текущая политика переподключения
+    pub fn policy(&self) -> &ReconnectPolicy {
+        &self.policy
     }
 }
 
@@ -144,28 +159,135 @@ impl Default for ReconnectState {
     }
 }
 
+/// Прогресс ленивого восстановления сессий (см. `AppState::begin_restore`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreProgress {
+    /// Контактов, чьи сессии нужно восстановить
+    pub total: usize,
+    /// Обработано контактов (включая контакты без сохраненной сессии)
+    pub done: usize,
+    /// Сессий, которые не удалось восстановить
+    pub failed: usize,
+}
+
+impl RestoreProgress {
+    pub fn is_complete(&self) -> bool {
+        self.done + self.failed >= self.total
+    }
+}
+
+/// Результат периодического обслуживания (см. `AppState::run_maintenance`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Удалено устаревших signed prekey
+    pub purged_prekeys: usize,
+}
+
+/// Диагностика беседы: состояние ratchet-сессии без ключевого материала
+/// (см. `AppState::diagnose_conversation`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationDiagnosis {
+    pub contact_id: String,
+    /// Есть ли активная сессия с контактом
+    pub session_present: bool,
+    /// Сессия ожидает ленивого восстановления из хранилища
+    pub restore_pending: bool,
+    pub suite_id: Option<u16>,
+    pub sending_chain_length: u32,
+    pub receiving_chain_length: u32,
+    /// Сохраненных ключей пропущенных сообщений
+    pub skipped_key_count: usize,
+    /// Время последней успешной расшифровки (секунды с UNIX epoch)
+    pub last_successful_decrypt: Option<i64>,
+    /// Ошибок расшифровки подряд после последнего успеха
+    pub consecutive_failures: u32,
+}
+
+/// Цитата, на которую отвечает сообщение
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplyContext {
+    /// Цитируемое сообщение найдено
+    Quoted {
+        message_id: String,
+        from: String,
+        timestamp: i64,
+        /// Текст, если есть локальная копия содержимого
+        text: Option<String>,
+    },
+    /// Цитируемое сообщение удалено или не было получено
+    Missing { message_id: String },
+}
+
+/// Вставить сообщение в отсортированный кеш беседы
+///
+/// Порядок - по timestamp, при равных timestamp - по id. Позиция ищется
+/// бинарным поиском, поэтому вставка не пересортировывает весь кеш.
+fn insert_sorted(cache: &mut Vec<StoredMessage>, msg: StoredMessage) {
+    // Сообщение с тем же id заменяется, как в хранилище
+    cache.retain(|m| m.id != msg.id);
+    let index = cache.partition_point(|m| (m.timestamp, &m.id) <= (msg.timestamp, &msg.id));
+    cache.insert(index, msg);
+}
+
+/// Хеш bundle контакта и identity ключ, при котором он наблюдался
+struct ObservedKeyDigest {
+    identity_public: Vec<u8>,
+    bundle_hash: String,
+}
+
+/// Долговременные ключи, передаваемые на привязываемое устройство
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DeviceLinkKeys {
+    identity_secret: Vec<u8>,
+    signing_secret: Vec<u8>,
+    verifying_key: Vec<u8>,
+    signed_prekey_secret: Vec<u8>,
+    signed_prekey_signature: Vec<u8>,
+    signed_prekey_id: u32,
+}
+
+impl Drop for DeviceLinkKeys {
+    fn drop(&mut self) {
+        self.identity_secret.zeroize();
+        self.signing_secret.zeroize();
+        self.signed_prekey_secret.zeroize();
+    }
+}
+
+/// Содержимое `ProtocolMessage::DeviceLinkResponse` до шифрования
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DeviceLinkPayload {
+    keys: DeviceLinkKeys,
+    archive: StateArchive,
+}
+
+/// Через сколько секунд гаснет индикатор набора текста без повторного `Typing`
+pub const TYPING_TIMEOUT_SECONDS: i64 = 5;
+
 /// Главное состояние всего приложения
-pub struct AppState<P: CryptoProvider> {
+pub struct AppState<P: CryptoProvider, S: Storage = DefaultStorage> {
     // === Идентификация пользователя ===
     user_id: Option<String>,
     username: Option<String>,
 
+    /// Мастер-ключ (PBKDF2 от пароля), доступен после разблокировки
+    master_key: Option<Zeroizing<[u8; 32]>>,
+
     // === Менеджеры ===
     crypto_manager: CryptoCore<P>,
     contact_manager: ContactManager,
     conversations_manager: ConversationsManager,
 
     // === Хранилище ===
-    #[cfg(target_arch = "wasm32")]
-    storage: IndexedDbStorage,
-
-    #[cfg(not(target_arch = "wasm32"))]
-    storage: MemoryStorage,
+    storage: S,
 
     // === Сетевое соединение ===
     #[cfg(target_arch = "wasm32")]
     transport: Option<WebSocketTransport>,
 
+    #[cfg(not(target_arch = "wasm32"))]
+    transport: Option<Box<dyn Transport>>,
+
     // === Состояние соединения ===
     connection_state: ConnectionState,
     server_url: Option<String>,
@@ -174,10 +296,58 @@ pub struct AppState<P: CryptoProvider> {
     // === Кеш сообщений (в памяти) ===
     message_cache: HashMap<String, Vec<StoredMessage>>,
 
+    // === Расшифрованные локальные копии для повторного показа ===
+    plaintext_cache: RefCell<PlaintextCache>,
+
+    // === Примененные Ack (message_id -> статус), повторы игнорируются.
+    // Только для сохраненных сообщений; удаляются вместе с сообщениями ===
+    acked_messages: HashMap<String, MessageStatus>,
+
+    // === Последний серверный seq по беседам (только в памяти) ===
+    last_seq: HashMap<String, u64>,
+
+    // === Наблюдавшиеся хеши bundle по контактам (key transparency) ===
+    observed_key_digests: HashMap<String, ObservedKeyDigest>,
+
+    // === Identity ключи, о смене на которые уже предупредили (до подтверждения) ===
+    reported_key_mismatches: HashMap<String, Vec<u8>>,
+
+    // === Бэкап, полученный от сервера и ожидающий пароля ===
+    pending_backup: Option<BackupDownloadResponseData>,
+
+    // === Одноразовый ключ привязки (на новом устройстве до ответа основного) ===
+    pending_device_link: Option<P::KemPrivateKey>,
+
     // === Состояние UI ===
     active_conversation: Option<String>,
     ui_state: UiState,
 
+    // === Очередь событий для UI ===
+    events: Vec<AppEvent>,
+    notification_policy: Box<dyn NotificationPolicy>,
+
+    /// Строгий режим: не отправлять сообщения неподтвержденным контактам
+    require_verified_before_send: bool,
+
+    /// Разрешены ли сообщения самому себе ("заметки для себя")
+    note_to_self_enabled: bool,
+
+    /// Шифрование заголовков в сессиях, которые открываем мы
+    header_encryption: bool,
+
+    // === Ленивое восстановление сессий ===
+    pending_restore: HashSet<String>,
+    restore_progress: RestoreProgress,
+
+    // === Контакты, чьи сессии сброшены `rekey_all` и ждут свежий bundle ===
+    pending_rekey: HashSet<String>,
+
+    // === Очередь исходящих без соединения (id сообщений в порядке отправки) ===
+    outbound_queue: VecDeque<String>,
+
+    // === Кто сейчас набирает текст: беседа -> когда индикатор истекает (не сохраняется) ===
+    typing_until: HashMap<String, i64>,
+
     _phantom: PhantomData<P>,
 }
 
@@ -185,35 +355,22 @@ impl<P: CryptoProvider> AppState<P> {
     /// Создать новое состояние приложения
     #[cfg(target_arch = "wasm32")]
     pub async fn new() -> Result<Self> {
-        let mut storage = IndexedDbStorage::new();
+        let mut storage = DefaultStorage::new();
         storage.init().await?;
 
-        let crypto_manager = CryptoCore::<P>::new()?;
-        let contact_manager = ContactManager::new();
-        let conversations_manager = ConversationsManager::new();
-
-        Ok(Self {
-            user_id: None,
-            username: None,
-            crypto_manager,
-            contact_manager,
-            conversations_manager,
-            storage,
-            transport: None,
-            connection_state: ConnectionState::Disconnected,
-            server_url: None,
-            reconnect_state: ReconnectState::new(),
-            message_cache: HashMap::new(),
-            active_conversation: None,
-            ui_state: UiState::new(),
-            _phantom: PhantomData,
-        })
+        Self::with_storage(storage)
     }
 
     /// Создать новое состояние приложения (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
     pub fn new(_db_name: &str) -> Result<Self> {
-        let storage = MemoryStorage::new();
+        Self::with_storage(DefaultStorage::new())
+    }
+}
+
+impl<P: CryptoProvider, S: Storage> AppState<P, S> {
+    /// Создать состояние приложения поверх заданного хранилища
+    pub fn with_storage(storage: S) -> Result<Self> {
         let crypto_manager = CryptoCore::<P>::new()?;
         let contact_manager = ContactManager::new();
         let conversations_manager = ConversationsManager::new();
@@ -221,16 +378,35 @@ impl<P: CryptoProvider> AppState<P> {
         Ok(Self {
             user_id: None,
             username: None,
+            master_key: None,
             crypto_manager,
             contact_manager,
             conversations_manager,
             storage,
+            transport: None,
             connection_state: ConnectionState::Disconnected,
             server_url: None,
             reconnect_state: ReconnectState::new(),
             message_cache: HashMap::new(),
+            plaintext_cache: RefCell::new(PlaintextCache::new()),
+            acked_messages: HashMap::new(),
+            last_seq: HashMap::new(),
+            observed_key_digests: HashMap::new(),
+            reported_key_mismatches: HashMap::new(),
+            pending_backup: None,
+            pending_device_link: None,
             active_conversation: None,
             ui_state: UiState::new(),
+            events: Vec::new(),
+            notification_policy: Box::new(DefaultNotificationPolicy),
+            require_verified_before_send: false,
+            note_to_self_enabled: false,
+            header_encryption: false,
+            pending_restore: HashSet::new(),
+            pending_rekey: HashSet::new(),
+            outbound_queue: VecDeque::new(),
+            typing_until: HashMap::new(),
+            restore_progress: RestoreProgress::default(),
             _phantom: PhantomData,
         })
     }
@@ -256,17 +432,6 @@ impl<P: CryptoProvider> AppState<P> {
         Ok(())
     }
 
-    /// Завершить регистрацию после получения UUID от сервера
-    #[cfg(target_arch = "wasm32")]
-    pub async fn finalize_registration(
-        &mut self,
-        server_user_id: String,
-        _session_token: String,
-        password: String,
-    ) -> Result<()> {
-        unimplemented!()
-    }
-
     /// Инициализировать нового пользователя (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
     pub fn initialize_user(&mut self, username: String, password: String) -> Result<()> {
@@ -284,6 +449,17 @@ impl<P: CryptoProvider> AppState<P> {
         Ok(())
     }
 
+    /// Завершить регистрацию после получения UUID от сервера
+    #[cfg(target_arch = "wasm32")]
+    pub async fn finalize_registration(
+        &mut self,
+        server_user_id: String,
+        _session_token: String,
+        password: String,
+    ) -> Result<()> {
+        self.save_registration(server_user_id, &password).await
+    }
+
     /// Завершить регистрацию (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
     pub fn finalize_registration(
@@ -292,57 +468,399 @@ impl<P: CryptoProvider> AppState<P> {
         _session_token: String,
         password: String,
     ) -> Result<()> {
-        unimplemented!()
+        complete_now(self.save_registration(server_user_id, &password))
+    }
+
+    async fn save_registration(&mut self, server_user_id: String, password: &str) -> Result<()> {
+        let (stored_keys, master_key) = self.seal_private_keys(&server_user_id, password)?;
+        self.storage.save_private_keys(stored_keys).await?;
+        let metadata = self.build_metadata(&server_user_id);
+        self.storage.save_metadata(metadata).await?;
+        self.storage.set_at_rest_key(&master_key)?;
+
+        self.user_id = Some(server_user_id);
+        self.master_key = Some(master_key);
+
+        Ok(())
+    }
+
+    /// Зашифровать приватные ключи мастер-ключом, полученным из пароля
+    fn seal_private_keys(
+        &self,
+        user_id: &str,
+        password: &str,
+    ) -> Result<(StoredPrivateKeys, Zeroizing<[u8; 32]>)> {
+        use crate::crypto::master_key;
+
+        master_key::validate_password(password)?;
+
+        let salt = master_key::generate_salt();
+        let key = master_key::derive_master_key(password, &salt)?;
+        let stored = self.encrypt_private_keys(user_id, &key, salt)?;
+
+        Ok((stored, key))
+    }
+
+    /// Зашифровать текущие приватные ключи мастер-ключом (соль хранится рядом)
+    fn encrypt_private_keys(
+        &self,
+        user_id: &str,
+        key: &[u8; 32],
+        salt: [u8; crate::crypto::master_key::SALT_LENGTH],
+    ) -> Result<StoredPrivateKeys> {
+        use crate::crypto::master_key::{self, PrivateKeys};
+
+        let key_manager = self.crypto_manager.key_manager();
+        let prekey = key_manager.current_signed_prekey()?;
+        let keys = PrivateKeys::from_slices(
+            key_manager.identity_secret_key()?.as_ref(),
+            key_manager.signing_secret_key()?.as_ref(),
+            prekey.key_pair.0.as_ref(),
+        )?;
+
+        let mut stored =
+            master_key::encrypt_private_keys(&keys, key, salt, user_id.to_string(), prekey.signature.clone())?;
+        stored.verifying_key = key_manager.verifying_key()?.as_ref().to_vec();
+        stored.signed_prekey_id = prekey.key_id;
+        stored.next_one_time_prekey_id = key_manager.next_one_time_prekey_id();
+        for key_id in key_manager.old_prekey_ids() {
+            let Some(old) = key_manager.get_prekey(key_id) else {
+                continue;
+            };
+            stored.old_signed_prekeys.push(StoredSignedPrekey {
+                key_id,
+                encrypted_private: master_key::encrypt_with_master_key(key, old.key_pair.0.as_ref())?,
+                signature: old.signature.clone(),
+                created_at: old.created_at,
+            });
+        }
+        for (key_id, _) in key_manager.one_time_prekey_publics() {
+            let Some(private_key) = key_manager.one_time_prekey(key_id) else {
+                continue;
+            };
+            stored.one_time_prekeys.push(StoredOneTimePrekey {
+                key_id,
+                encrypted_private: master_key::encrypt_with_master_key(key, private_key.as_ref())?,
+            });
+        }
+        Ok(stored)
+    }
+
+    /// Заменить CryptoCore на построенный по `key_manager`, сохранив настройки сессий
+    fn install_key_manager(&mut self, key_manager: KeyManager<P>) -> Result<()> {
+        self.crypto_manager = CryptoCore::from_key_manager(key_manager)?;
+        self.crypto_manager.set_header_encryption(self.header_encryption);
+        Ok(())
+    }
+
+    /// Установить сохраненные долговременные и одноразовые ключи вместо созданных
+    /// при запуске. Записи старых версий без verifying ключа оставляют ключи как есть
+    fn install_stored_keys(&mut self, stored: &StoredPrivateKeys, key: &[u8; 32]) -> Result<()> {
+        use crate::crypto::master_key;
+
+        if stored.verifying_key.is_empty() {
+            return Ok(());
+        }
+        let keys = master_key::decrypt_private_keys(stored, key)?;
+        let mut key_manager = KeyManager::<P>::from_keys(
+            P::kem_private_key_from_bytes(keys.identity_secret.to_vec()),
+            (
+                P::signature_private_key_from_bytes(keys.signing_key.to_vec()),
+                P::signature_public_key_from_bytes(stored.verifying_key.clone()),
+            ),
+            P::kem_private_key_from_bytes(keys.signed_prekey_secret.to_vec()),
+            stored.prekey_signature.clone(),
+            stored.signed_prekey_id,
+        )?;
+        let one_time_prekeys = stored
+            .one_time_prekeys
+            .iter()
+            .map(|prekey| {
+                let private_key = master_key::decrypt_with_master_key(key, &prekey.encrypted_private)?;
+                Ok((prekey.key_id, P::kem_private_key_from_bytes(private_key.to_vec())))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        key_manager.restore_one_time_prekeys(one_time_prekeys, stored.next_one_time_prekey_id)?;
+        for old in &stored.old_signed_prekeys {
+            let private_key = master_key::decrypt_with_master_key(key, &old.encrypted_private)?;
+            key_manager.restore_old_prekey(
+                old.key_id,
+                P::kem_private_key_from_bytes(private_key.to_vec()),
+                old.signature.clone(),
+                old.created_at,
+            )?;
+        }
+
+        self.install_key_manager(key_manager)?;
+        Ok(())
+    }
+
+    fn build_metadata(&self, user_id: &str) -> StoredAppMetadata {
+        StoredAppMetadata {
+            user_id: user_id.to_string(),
+            username: self.username.clone().unwrap_or_default(),
+            last_sync: current_timestamp(),
+            settings: Vec::new(),
+        }
+    }
+
+    /// Получить мастер-ключ из пароля, проверив его на сохраненных ключах
+    fn derive_checked_master_key(
+        stored: &StoredPrivateKeys,
+        password: &str,
+    ) -> Result<Zeroizing<[u8; 32]>> {
+        use crate::crypto::master_key;
+
+        let key = master_key::derive_master_key(password, &stored.salt)?;
+        // Неверный пароль не расшифрует ключи
+        master_key::decrypt_private_keys(stored, &key).map_err(|_| {
+            ConstructError::CryptoError("Invalid password".to_string())
+        })?;
+
+        Ok(key)
+    }
+
+    /// Зашифровать plaintext для локального хранения (StoredMessage::local_content)
+    pub fn seal_local_content(&self, plaintext: &str) -> Result<Vec<u8>> {
+        self.seal_local_body(&MessageBody::new_text(plaintext))
+    }
+
+    /// Зашифровать содержимое сообщения для локального хранения
+    pub fn seal_local_body(&self, body: &MessageBody) -> Result<Vec<u8>> {
+        let key = self.require_master_key()?;
+        crate::crypto::master_key::encrypt_with_master_key(key, &body.to_plaintext()?)
+    }
+
+    /// Расшифровать локальную копию содержимого (None - копии нет).
+    /// Повторные запросы того же сообщения обслуживаются из LRU-кеша
+    fn open_local_body(&self, msg: &StoredMessage) -> Result<Option<MessageBody>> {
+        let Some(sealed) = &msg.local_content else {
+            return Ok(None);
+        };
+
+        let key = self.require_master_key()?;
+        if let Some(plaintext) = self.plaintext_cache.borrow_mut().get(&msg.id) {
+            return MessageBody::from_plaintext(plaintext).map(Some);
+        }
+
+        let plaintext = crate::crypto::master_key::decrypt_with_master_key(key, sealed)?;
+        let body = MessageBody::from_plaintext(&plaintext)?;
+        self.plaintext_cache
+            .borrow_mut()
+            .insert(&msg.id, plaintext.to_vec());
+        Ok(Some(body))
+    }
+
+    /// Заблокировать: забыть мастер-ключ и расшифрованные копии сообщений
+    pub fn lock(&mut self) {
+        self.master_key = None;
+        self.plaintext_cache.borrow_mut().clear();
+        self.storage.clear_at_rest_key();
+    }
+
+    fn require_master_key(&self) -> Result<&[u8; 32]> {
+        self.master_key
+            .as_deref()
+            .ok_or_else(|| ConstructError::CryptoError("Master key is locked".to_string()))
+    }
+
+    /// Разблокирован ли мастер-ключ
+    pub fn is_unlocked(&self) -> bool {
+        self.master_key.is_some()
     }
 
     /// Загрузить существующего пользователя
     #[cfg(target_arch = "wasm32")]
     pub async fn load_user(&mut self, user_id: String, password: String) -> Result<()> {
-        unimplemented!()
+        self.load_user_async(user_id, &password).await
     }
 
     /// Загрузить существующего пользователя (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
     pub fn load_user(&mut self, user_id: String, password: String) -> Result<()> {
-        unimplemented!()
+        complete_now(self.load_user_async(user_id, &password))
     }
 
-    // === Управление контактами ===
+    async fn load_user_async(&mut self, user_id: String, password: &str) -> Result<()> {
+        let master_key = self.unlock_user(&user_id, password).await?;
 
-    /// Добавить контакт
+        let sessions = self.storage.load_all_sessions().await?;
+        self.restore_sessions(sessions);
+
+        self.user_id = Some(user_id);
+        self.master_key = Some(master_key);
+        Ok(())
+    }
+
+    /// Загрузить пользователя без сессий: метаданные и контакты доступны сразу,
+    /// сессия контакта восстанавливается при первом обращении к нему
+    /// или в фоне через `restore_remaining`
     #[cfg(target_arch = "wasm32")]
-    pub async fn add_contact(&mut self, contact_id: String, username: String) -> Result<()> {
-        // 1. Добавить в ContactManager
-        let contact = crate::api::contacts::create_contact(contact_id.clone(), username.clone());
-        self.contact_manager.add_contact(contact)?;
+    pub async fn begin_restore(&mut self, user_id: String, password: String) -> Result<()> {
+        self.begin_restore_async(user_id, &password).await
+    }
 
-        // 2. Сохранить в storage
-        let stored = StoredContact {
-            id: contact_id,
-            username,
-            public_key_bundle: None,
-            added_at: current_timestamp(),
-            last_message_at: None,
+    /// Загрузить пользователя без сессий (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn begin_restore(&mut self, user_id: String, password: String) -> Result<()> {
+        complete_now(self.begin_restore_async(user_id, &password))
+    }
+
+    async fn begin_restore_async(&mut self, user_id: String, password: &str) -> Result<()> {
+        let master_key = self.unlock_user(&user_id, password).await?;
+        self.schedule_restore();
+
+        self.user_id = Some(user_id);
+        self.master_key = Some(master_key);
+        Ok(())
+    }
+
+    /// Восстановить сессии всех контактов, которые еще не были затронуты
+    #[cfg(target_arch = "wasm32")]
+    pub async fn restore_remaining(&mut self) -> Result<RestoreProgress> {
+        self.restore_remaining_async().await
+    }
+
+    /// Восстановить сессии всех оставшихся контактов (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn restore_remaining(&mut self) -> Result<RestoreProgress> {
+        complete_now(self.restore_remaining_async())
+    }
+
+    async fn restore_remaining_async(&mut self) -> Result<RestoreProgress> {
+        let mut pending: Vec<String> = self.pending_restore.iter().cloned().collect();
+        pending.sort();
+        for contact_id in pending {
+            self.ensure_session_restored(&contact_id).await?;
+        }
+        Ok(self.restore_progress)
+    }
+
+    /// Прогресс ленивого восстановления сессий
+    pub fn restore_progress(&self) -> RestoreProgress {
+        self.restore_progress
+    }
+
+    /// Проверить пароль, загрузить метаданные и контакты. Возвращает мастер-ключ
+    async fn unlock_user(&mut self, user_id: &str, password: &str) -> Result<Zeroizing<[u8; 32]>> {
+        let stored_keys = self
+            .storage
+            .load_private_keys(user_id)
+            .await?
+            .ok_or_else(|| ConstructError::NotFound(format!("User not found: {}", user_id)))?;
+        let master_key = Self::derive_checked_master_key(&stored_keys, password)?;
+        self.install_stored_keys(&stored_keys, &master_key)?;
+        self.storage.set_at_rest_key(&master_key)?;
+
+        if let Some(metadata) = self.storage.load_metadata(user_id).await? {
+            self.username = Some(metadata.username);
+        }
+        for stored in self.storage.load_all_contacts().await? {
+            if !self.contact_manager.has_contact(&stored.id) {
+                self.contact_manager.add_contact(stored.into())?;
+            }
+        }
+
+        Ok(master_key)
+    }
+
+    /// Отметить сессии всех известных контактов как ожидающие восстановления
+    fn schedule_restore(&mut self) {
+        self.pending_restore = self
+            .contact_manager
+            .get_all_contacts()
+            .into_iter()
+            .map(|c| c.id.clone())
+            .filter(|id| self.crypto_manager.client().session_id_for_contact(id).is_none())
+            .collect();
+        self.restore_progress = RestoreProgress {
+            total: self.pending_restore.len(),
+            ..RestoreProgress::default()
         };
-        self.storage.save_contact(stored).await?;
+    }
+
+    /// Восстановить сессию контакта при первом обращении к нему
+    async fn ensure_session_restored(&mut self, contact_id: &str) -> Result<()> {
+        if !self.pending_restore.contains(contact_id) {
+            return Ok(());
+        }
 
+        let stored = self.storage.load_session_for_contact(contact_id).await?;
+        self.finish_contact_restore(contact_id, stored);
         Ok(())
     }
 
+    fn finish_contact_restore(&mut self, contact_id: &str, stored: Option<StoredSession>) {
+        self.pending_restore.remove(contact_id);
+
+        let failed = stored.is_some_and(|s| !self.restore_sessions(vec![s]).is_complete());
+        if failed {
+            self.restore_progress.failed += 1;
+        } else {
+            self.restore_progress.done += 1;
+        }
+    }
+
+    /// Восстановить сохраненные сессии; поврежденные пропускаются
+    /// с событием `AppEvent::SessionRestoreFailed`
+    fn restore_sessions(&mut self, sessions: Vec<StoredSession>) -> ImportReport {
+        // Сессии восстанавливаются в ClientCrypto - шифрование и расшифровка работают с ним
+        let mut report = ImportReport::default();
+        for stored in sessions {
+            match self
+                .crypto_manager
+                .client_mut()
+                .import_session(&stored.session_id, &stored.session_data)
+            {
+                Ok(()) => report.imported.push(stored.contact_id),
+                Err(e) => report.failed.push((stored.contact_id, e.to_string())),
+            }
+        }
+
+        for (contact_id, reason) in &report.failed {
+            self.events.push(AppEvent::SessionRestoreFailed {
+                contact_id: contact_id.clone(),
+                reason: reason.clone(),
+            });
+        }
+
+        report
+    }
+
+    // === Управление контактами ===
+
+    /// Добавить контакт
+    #[cfg(target_arch = "wasm32")]
+    pub async fn add_contact(&mut self, contact_id: String, username: String) -> Result<()> {
+        self.add_contact_async(contact_id, username).await
+    }
+
     /// Добавить контакт (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
     pub fn add_contact(&mut self, contact_id: String, username: String) -> Result<()> {
+        complete_now(self.add_contact_async(contact_id, username))
+    }
+
+    async fn add_contact_async(&mut self, contact_id: String, username: String) -> Result<()> {
+        // 1. Добавить в ContactManager
         let contact = crate::api::contacts::create_contact(contact_id.clone(), username.clone());
         self.contact_manager.add_contact(contact)?;
 
+        // 2. Сохранить в storage
         let stored = StoredContact {
             id: contact_id,
             username,
             public_key_bundle: None,
             added_at: current_timestamp(),
             last_message_at: None,
+            verified: false,
+            notes: None,
+            metadata: HashMap::new(),
+            identity_key_history: Vec::new(),
+            muted: false,
+            blocked: false,
         };
-        self.storage.save_contact(stored)?;
+        self.storage.save_contact(stored).await?;
 
         Ok(())
     }
@@ -352,389 +870,5424 @@ impl<P: CryptoProvider> AppState<P> {
         self.contact_manager.get_all_contacts()
     }
 
-    // === Работа с сообщениями ===
+    /// Ждет ли смена identity ключа контакта сверки safety number
+    pub fn has_pending_key_change(&self, contact_id: &str) -> bool {
+        self.contact_manager.has_pending_identity_change(contact_id)
+    }
 
-    /// Отправить сообщение
+    /// Обновить ключевой bundle контакта и проверить смену identity ключа
     #[cfg(target_arch = "wasm32")]
-    pub async fn send_message(
+    pub async fn update_contact_bundle(
         &mut self,
-        to_contact_id: &str,
-        session_id: &str,
-        plaintext: &str,
-    ) -> Result<String> {
-        unimplemented!()
+        contact_id: &str,
+        bundle: PublicKeyBundle,
+    ) -> Result<bool> {
+        self.update_contact_bundle_async(contact_id, bundle).await
     }
 
-    /// Отправить сообщение (non-WASM версия)
+    /// Обновить ключевой bundle контакта и проверить смену identity ключа (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn send_message(
+    pub fn update_contact_bundle(&mut self, contact_id: &str, bundle: PublicKeyBundle) -> Result<bool> {
+        complete_now(self.update_contact_bundle_async(contact_id, bundle))
+    }
+
+    async fn update_contact_bundle_async(
         &mut self,
-        to_contact_id: &str,
-        _session_id: &str,
-        plaintext: &str,
-    ) -> Result<String> {
-        unimplemented!()
+        contact_id: &str,
+        bundle: PublicKeyBundle,
+    ) -> Result<bool> {
+        self.contact_manager.update_contact_keys(contact_id, bundle)?;
+        let consistent = self.check_identity_consistency(contact_id)?;
+        self.record_key_digest(contact_id)?;
+        self.persist_contact(contact_id).await?;
+
+        Ok(consistent)
     }
 
-    /// Обработать входящее сообщение
+    /// Принять смену identity ключа контакта (переустановка, новое устройство)
+    ///
+    /// Текущий ключ из bundle отмечается принятым в журнале ключей контакта,
+    /// ожидающие `AppEvent::IdentityKeyMismatch` по контакту снимаются. Следующая
+    /// смена ключа снова вызовет предупреждение. Подтверждение личности (`verified`)
+    /// не восстанавливается - для него нужна повторная сверка.
     #[cfg(target_arch = "wasm32")]
-    pub async fn receive_message(&mut self, chat_msg: ChatMessage, session_id: &str) -> Result<()> {
-        unimplemented!()
+    pub async fn acknowledge_key_change(&mut self, contact_id: &str) -> Result<()> {
+        self.acknowledge_key_change_async(contact_id).await
     }
 
-    /// Обработать входящее сообщение (non-WASM заглушка)
+    /// Принять смену identity ключа контакта (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn receive_message(&mut self, _chat_msg: ChatMessage, _session_id: &str) -> Result<()> {
-        Ok(())
+    pub fn acknowledge_key_change(&mut self, contact_id: &str) -> Result<()> {
+        complete_now(self.acknowledge_key_change_async(contact_id))
     }
 
-    /// Обновить кеш сообщений
-    #[cfg(target_arch = "wasm32")]
-    async fn update_message_cache(
-        &mut self,
-        conversation_id: &str,
-        msg: StoredMessage,
-    ) -> Result<()> {
-        unimplemented!()
+    async fn acknowledge_key_change_async(&mut self, contact_id: &str) -> Result<()> {
+        self.contact_manager
+            .accept_identity_key(contact_id, current_timestamp())?;
+        self.events.retain(|event| {
+            !matches!(event, AppEvent::IdentityKeyMismatch { contact_id: id, .. } if id == contact_id)
+        });
+        self.reported_key_mismatches.remove(contact_id);
+        self.persist_contact(contact_id).await
     }
 
-    /// Загрузить беседу
+    /// Установить или удалить заметку о контакте
     #[cfg(target_arch = "wasm32")]
-    pub async fn load_conversation(&mut self, contact_id: &str) -> Result<Vec<StoredMessage>> {
-        unimplemented!()
+    pub async fn set_contact_note(&mut self, contact_id: &str, note: Option<String>) -> Result<()> {
+        self.contact_manager.set_note(contact_id, note)?;
+        self.persist_contact(contact_id).await
     }
 
-    /// Загрузить беседу (non-WASM версия)
+    /// Установить или удалить заметку о контакте (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn load_conversation(&mut self, contact_id: &str) -> Result<Vec<StoredMessage>> {
-        unimplemented!()
+    pub fn set_contact_note(&mut self, contact_id: &str, note: Option<String>) -> Result<()> {
+        self.contact_manager.set_note(contact_id, note)?;
+        complete_now(self.persist_contact(contact_id))
     }
 
-    /// Установить активную беседу
-    pub fn set_active_conversation(&mut self, contact_id: Option<String>) {
-        self.active_conversation = contact_id;
+    /// Отключить/включить уведомления от контакта
+    #[cfg(target_arch = "wasm32")]
+    pub async fn set_contact_muted(&mut self, contact_id: &str, muted: bool) -> Result<()> {
+        self.contact_manager.set_muted(contact_id, muted)?;
+        self.persist_contact(contact_id).await
     }
 
-    /// Получить активную беседу
-    pub fn get_active_conversation(&self) -> Option<&str> {
-        self.active_conversation.as_deref()
+    /// Отключить/включить уведомления от контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_contact_muted(&mut self, contact_id: &str, muted: bool) -> Result<()> {
+        self.contact_manager.set_muted(contact_id, muted)?;
+        complete_now(self.persist_contact(contact_id))
     }
 
-    // === Управление соединением ===
-
-    /// Подключиться к серверу WebSocket
+    /// Заблокировать/разблокировать контакт: входящие от него отбрасываются
     #[cfg(target_arch = "wasm32")]
-    pub fn connect(&mut self, server_url: &str) -> Result<()> {
-        if self.connection_state == ConnectionState::Connected {
-            return Err(ConstructError::NetworkError(
-                "Already connected".to_string(),
-            ));
-        }
-
-        self.connection_state = ConnectionState::Connecting;
+    pub async fn set_contact_blocked(&mut self, contact_id: &str, blocked: bool) -> Result<()> {
+        self.contact_manager.set_blocked(contact_id, blocked)?;
+        self.persist_contact(contact_id).await
+    }
 
-        let mut transport = WebSocketTransport::new();
-        transport.connect(server_url)?;
+    /// Заблокировать/разблокировать контакт (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_contact_blocked(&mut self, contact_id: &str, blocked: bool) -> Result<()> {
+        self.contact_manager.set_blocked(contact_id, blocked)?;
+        complete_now(self.persist_contact(contact_id))
+    }
 
-        // Настроить базовые callbacks
-        self.setup_transport_callbacks(&mut transport)?;
+    /// Установить или удалить (`None`) поле метаданных контакта
+    #[cfg(target_arch = "wasm32")]
+    pub async fn set_contact_metadata(
+        &mut self,
+        contact_id: &str,
+        key: &str,
+        value: Option<String>,
+    ) -> Result<()> {
+        self.contact_manager.set_metadata(contact_id, key, value)?;
+        self.persist_contact(contact_id).await
+    }
 
-        self.transport = Some(transport);
-        self.connection_state = ConnectionState::Connected;
+    /// Установить или удалить поле метаданных контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_contact_metadata(
+        &mut self,
+        contact_id: &str,
+        key: &str,
+        value: Option<String>,
+    ) -> Result<()> {
+        self.contact_manager.set_metadata(contact_id, key, value)?;
+        complete_now(self.persist_contact(contact_id))
+    }
 
+    /// Сохранить карточку контакта в хранилище
+    async fn persist_contact(&mut self, contact_id: &str) -> Result<()> {
+        if let Some(contact) = self.contact_manager.get_contact(contact_id) {
+            self.storage.save_contact(contact.into()).await?;
+        }
         Ok(())
     }
 
-    /// Настроить WebSocket callbacks (базовая версия без Arc)
-    /// Эта версия используется внутри AppState, где мы не имеем доступа к Arc
-    #[cfg(target_arch = "wasm32")]
-    fn setup_transport_callbacks(&self, transport: &mut WebSocketTransport) -> Result<()> {
-        use crate::wasm::console;
+    /// Identity ключ из bundle контакта (если bundle известен)
+    fn contact_identity_key(&self, contact_id: &str) -> Result<Option<Vec<u8>>> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        self.contact_manager
+            .get_contact(contact_id)
+            .and_then(|c| c.public_key_bundle.as_ref())
+            .map(|bundle| {
+                general_purpose::STANDARD
+                    .decode(&bundle.identity_public)
+                    .map_err(|e| {
+                        ConstructError::ValidationError(format!("Invalid identity key: {}", e))
+                    })
+            })
+            .transpose()
+    }
 
-        // Callback для успешного подключения
-        transport.set_on_open(|| {
-            console::log("✅ WebSocket connected successfully");
-        })?;
+    /// Сравнить identity ключ, зафиксированный в сессии, с текущим bundle контакта
+    ///
+    /// При расхождении контакт помечается неподтвержденным и в очередь
+    /// добавляется `AppEvent::IdentityKeyMismatch` - один раз на каждый новый ключ
+    /// контакта, пока смену не примут. Непринятая смена ключа остается расхождением
+    /// и для сессии, установленной уже на новом ключе. Возвращает `false` при расхождении.
+    pub fn check_identity_consistency(&mut self, contact_id: &str) -> Result<bool> {
+        let Some(bundle_identity) = self.contact_identity_key(contact_id)? else {
+            return Ok(true);
+        };
+        let pending_change = self.contact_manager.has_pending_identity_change(contact_id);
+
+        let client = self.crypto_manager.client();
+        let (session_id, session_identity) = match client
+            .session_id_for_contact(contact_id)
+            .and_then(|id| client.session(id).map(|s| (id, s)))
+            .and_then(|(id, s)| s.remote_identity().map(|key| (id.to_string(), key.to_vec())))
+        {
+            Some(found) => found,
+            None => return Ok(!pending_change),
+        };
 
-        // Базовый callback для входящих сообщений
-        transport.set_on_message(|msg| {
-            console::log(&format!("📩 Received message: {:?}", msg));
-        })?;
+        // Смену ключа пользователь уже принял (новое устройство собеседника)
+        if (session_identity == bundle_identity && !pending_change)
+            || self.contact_manager.is_identity_key_accepted(contact_id)
+        {
+            self.reported_key_mismatches.remove(contact_id);
+            return Ok(true);
+        }
 
-        // Callback для ошибок
-        transport.set_on_error(|err| {
-            console::log(&format!("❌ WebSocket error: {}", err));
-        })?;
+        self.contact_manager.set_verified(contact_id, false)?;
+        // О том же ключе уже предупредили - каждое входящее сообщение не дублирует событие
+        if self.reported_key_mismatches.get(contact_id) == Some(&bundle_identity) {
+            return Ok(false);
+        }
+        self.reported_key_mismatches
+            .insert(contact_id.to_string(), bundle_identity);
+        self.events.push(AppEvent::IdentityKeyMismatch {
+            contact_id: contact_id.to_string(),
+            session_id,
+        });
+
+        Ok(false)
+    }
 
-        // Callback для закрытия соединения
-        transport.set_on_close(|code, reason| {
-            console::log(&format!("🔌 WebSocket closed: {} - {}", code, reason));
-        })?;
+    /// Хеш bundle контакта в нашем представлении - для публикации другим клиентам
+    pub fn key_digest(&self, contact_id: &str) -> Result<Option<ProtocolMessage>> {
+        Ok(self.cached_key_bundle(contact_id)?.map(|bundle| ProtocolMessage::KeyDigest {
+            user_id: contact_id.to_string(),
+            bundle_hash: bundle.content_hash(),
+        }))
+    }
+
+    /// Запомнить хеш bundle, полученного нами с сервера (ротация prekey его законно меняет)
+    fn record_key_digest(&mut self, contact_id: &str) -> Result<()> {
+        let (Some(identity_public), Some(bundle)) = (
+            self.contact_identity_key(contact_id)?,
+            self.cached_key_bundle(contact_id)?,
+        ) else {
+            return Ok(());
+        };
 
+        self.observed_key_digests.insert(
+            contact_id.to_string(),
+            ObservedKeyDigest {
+                identity_public,
+                bundle_hash: bundle.content_hash(),
+            },
+        );
         Ok(())
     }
 
-    /// Настроить WebSocket callbacks с доступом к Arc<Mutex<AppState>>
-    /// Эта версия вызывается из WASM bindings и имеет полный доступ к AppState
+    /// Сверить хеш bundle, опубликованный другим клиентом, с наблюдавшимся нами
+    ///
+    /// Другой хеш при том же identity ключе добавляет `AppEvent::KeyDigestMismatch`
+    /// и возвращает `false`; наблюдавшийся хеш при этом не заменяется.
+    pub fn observe_key_digest(&mut self, message: &ProtocolMessage) -> Result<bool> {
+        let ProtocolMessage::KeyDigest { user_id, bundle_hash } = message else {
+            return Err(ConstructError::ValidationError("Expected KeyDigest message".to_string()));
+        };
+        let Some(identity_public) = self.contact_identity_key(user_id)? else {
+            return Ok(true);
+        };
+
+        match self.observed_key_digests.get(user_id) {
+            Some(seen) if seen.identity_public == identity_public => {
+                if seen.bundle_hash == *bundle_hash {
+                    return Ok(true);
+                }
+                self.events.push(AppEvent::KeyDigestMismatch {
+                    contact_id: user_id.clone(),
+                    expected: seen.bundle_hash.clone(),
+                    received: bundle_hash.clone(),
+                });
+                Ok(false)
+            }
+            // Первое наблюдение для этого identity ключа
+            _ => {
+                self.observed_key_digests.insert(
+                    user_id.clone(),
+                    ObservedKeyDigest {
+                        identity_public,
+                        bundle_hash: bundle_hash.clone(),
+                    },
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    // === Работа с сообщениями ===
+
+    /// Отправить сообщение
     #[cfg(target_arch = "wasm32")]
-    pub fn setup_transport_callbacks_with_arc(
-        transport: &mut WebSocketTransport,
-        app_state_arc: std::sync::Arc<std::sync::Mutex<AppState<P>>>,
-    ) -> Result<()> {
-        unimplemented!()
+    pub async fn send_message(
+        &mut self,
+        to_contact_id: &ContactId,
+        session_id: &SessionId,
+        plaintext: &str,
+    ) -> Result<String> {
+        self.send_message_async(to_contact_id, session_id, plaintext).await
     }
 
-    /// Подключиться к серверу (non-WASM заглушка)
+    /// Отправить сообщение (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn connect(&mut self, _server_url: &str) -> Result<()> {
-        Err(ConstructError::NetworkError(
-            "WebSocket only available in WASM".to_string(),
-        ))
+    pub fn send_message(
+        &mut self,
+        to_contact_id: &ContactId,
+        session_id: &SessionId,
+        plaintext: &str,
+    ) -> Result<String> {
+        complete_now(self.send_message_async(to_contact_id, session_id, plaintext))
     }
 
-    /// Отключиться от сервера
-    #[cfg(target_arch = "wasm32")]
-    pub fn disconnect(&mut self) -> Result<()> {
-        if let Some(transport) = &mut self.transport {
-            transport.close()?;
+    async fn send_message_async(
+        &mut self,
+        to_contact_id: &ContactId,
+        session_id: &SessionId,
+        plaintext: &str,
+    ) -> Result<String> {
+        // Без соединения (или пока очередь не разобрана) сообщение ждет в очереди,
+        // чтобы номера ratchet сообщений шли в порядке отправки
+        if !self.outbound_queue.is_empty() || !self.transport_connected() {
+            let message_id = self.enqueue_outgoing(to_contact_id, plaintext).await?;
+            self.flush_outbound_queue_async().await?;
+            return Ok(message_id);
         }
 
-        self.transport = None;
-        self.connection_state = ConnectionState::Disconnected;
+        let (chat_msg, stored) = self.prepare_outgoing(to_contact_id, session_id, plaintext)?;
+        self.storage.save_message(stored.clone()).await?;
+        self.conversations_manager.add_message(to_contact_id.as_str(), stored.clone());
+        self.update_message_cache(to_contact_id.as_str(), stored);
 
-        Ok(())
+        let message_id = chat_msg.id.clone();
+        self.send_to_server(&ClientMessage::SendMessage(chat_msg))?;
+        Ok(message_id)
     }
 
-    /// Отключиться от сервера (non-WASM заглушка)
+    /// Отправить сообщение контакту, при необходимости создав сессию (X3DH)
+    ///
+    /// Если сессии нет, используется переданный `bundle` или bundle из карточки контакта.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn send_message_auto(
+        &mut self,
+        contact_id: &str,
+        bundle: Option<&KeyBundle>,
+        plaintext: &str,
+    ) -> Result<String> {
+        self.send_message_auto_async(contact_id, bundle, plaintext).await
+    }
+
+    /// Отправить сообщение контакту, при необходимости создав сессию (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn disconnect(&mut self) -> Result<()> {
-        self.connection_state = ConnectionState::Disconnected;
-        Ok(())
+    pub fn send_message_auto(
+        &mut self,
+        contact_id: &str,
+        bundle: Option<&KeyBundle>,
+        plaintext: &str,
+    ) -> Result<String> {
+        complete_now(self.send_message_auto_async(contact_id, bundle, plaintext))
     }
 
-    /// Установить WebSocket транспорт
-    /// Используется из WASM bindings после настройки callbacks
-    #[cfg(target_arch = "wasm32")]
-    pub fn set_transport(&mut self, transport: WebSocketTransport) {
-        self.transport = Some(transport);
-        self.connection_state = ConnectionState::Connecting;
+    async fn send_message_auto_async(
+        &mut self,
+        contact_id: &str,
+        bundle: Option<&KeyBundle>,
+        plaintext: &str,
+    ) -> Result<String> {
+        self.ensure_session_restored(contact_id).await?;
+        let session_id = SessionId::new(self.resolve_sending_session(contact_id, bundle)?)?;
+        self.send_message_async(&ContactId::new(contact_id)?, &session_id, plaintext)
+            .await
     }
 
-    /// Установить состояние соединения
-    pub fn set_connection_state(&mut self, state: ConnectionState) {
-        self.connection_state = state;
+    /// Найти активную сессию с контактом или установить новую по bundle
+    fn resolve_sending_session(
+        &mut self,
+        contact_id: &str,
+        bundle: Option<&KeyBundle>,
+    ) -> Result<String> {
+        if let Some(session_id) = self.crypto_manager.client().session_id_for_contact(contact_id) {
+            return Ok(session_id.to_string());
+        }
+
+        let bundle = match bundle {
+            Some(bundle) => bundle.clone(),
+            None => self.cached_key_bundle(contact_id)?.ok_or_else(|| {
+                ConstructError::SessionError(format!(
+                    "No session and no key bundle for contact: {}",
+                    contact_id
+                ))
+            })?,
+        };
+        // Новый identity ключ контакта не сверен - сессию на нем не строим
+        if self.contact_manager.has_pending_identity_change(contact_id) {
+            return Err(ConstructError::SessionError(format!(
+                "Identity key change for contact {} is not verified",
+                contact_id
+            )));
+        }
+
+        self.crypto_manager.get_or_init_sending_session(contact_id, &bundle)
     }
 
-    /// Получить состояние соединения
-    pub fn connection_state(&self) -> ConnectionState {
-        self.connection_state
+    /// Bundle, сохраненный в карточке контакта
+    fn cached_key_bundle(&self, contact_id: &str) -> Result<Option<KeyBundle>> {
+        let Some(bundle) = self
+            .contact_manager
+            .get_contact(contact_id)
+            .and_then(|c| c.public_key_bundle.as_ref())
+        else {
+            return Ok(None);
+        };
+
+        let data = PublicKeyBundleData {
+            user_id: contact_id.to_string(),
+            identity_public: bundle.identity_public.clone(),
+            signed_prekey_public: bundle.signed_prekey_public.clone(),
+            signature: bundle.signature.clone(),
+            verifying_key: bundle.verifying_key.clone(),
+            suite_id: None,
+            one_time_prekey_public: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: bundle.signed_prekey_id,
+        };
+        KeyBundle::try_from(&data).map(Some)
     }
 
-    /// Проверить, подключен ли к серверу
-    pub fn is_connected(&self) -> bool {
-        self.connection_state == ConnectionState::Connected
+    /// Включить/выключить строгий режим отправки только подтвержденным контактам
+    pub fn set_require_verified_before_send(&mut self, enabled: bool) {
+        self.require_verified_before_send = enabled;
     }
 
-    /// Установить URL сервера
-    pub fn set_server_url(&mut self, url: String) {
-        self.server_url = Some(url);
+    pub fn require_verified_before_send(&self) -> bool {
+        self.require_verified_before_send
     }
 
-    /// Получить URL сервера
-    pub fn get_server_url(&self) -> Option<&str> {
-        self.server_url.as_deref()
+    /// Шифровать заголовки сообщений в новых исходящих сессиях: DH ключ и
+    /// номер сообщения не видны серверу. Получатель включает режим сам
+    pub fn set_header_encryption(&mut self, enabled: bool) {
+        self.header_encryption = enabled;
+        self.crypto_manager.set_header_encryption(enabled);
     }
 
-    /// Получить состояние переподключения
-    pub fn reconnect_state(&self) -> &ReconnectState {
-        &self.reconnect_state
+    /// Разрешить сообщения самому себе; они попадают в беседу с собственным user_id
+    pub fn set_note_to_self_enabled(&mut self, enabled: bool) {
+        self.note_to_self_enabled = enabled;
     }
 
-    /// Получить мутабельное состояние переподключения
-    pub fn reconnect_state_mut(&mut self) -> &mut ReconnectState {
-        &mut self.reconnect_state
+    /// В строгом режиме отправка разрешена только подтвержденным контактам
+    fn check_send_allowed(&self, contact_id: &str) -> Result<()> {
+        if !self.require_verified_before_send {
+            return Ok(());
+        }
+
+        let verified = self
+            .contact_manager
+            .get_contact(contact_id)
+            .is_some_and(|c| c.verified);
+        if !verified {
+            return Err(ConstructError::ValidationError(format!(
+                "Contact {} is not verified. Verify the safety number before sending",
+                contact_id
+            )));
+        }
+
+        Ok(())
     }
 
-    /// Запланировать автоматическое переподключение
-    #[cfg(target_arch = "wasm32")]
-    pub fn schedule_reconnect(app_state_arc: std::sync::Arc<std::sync::Mutex<AppState<P>>>) {
-        unimplemented!()
+    /// Проверить, что контакту можно писать, и вернуть user_id отправителя
+    fn outgoing_sender(&self, to_contact_id: &str) -> Result<String> {
+        self.check_send_allowed(to_contact_id)?;
+
+        let user_id = self.require_user_id()?.to_string();
+        crate::protocol::validation::validate_not_self_message(
+            &user_id,
+            to_contact_id,
+            self.note_to_self_enabled,
+        )?;
+        Ok(user_id)
     }
 
-    /// Попытка переподключения
-    #[cfg(target_arch = "wasm32")]
-    async fn attempt_reconnect(
-        app_state_arc: std::sync::Arc<std::sync::Mutex<AppState<P>>>,
-        server_url: &str,
-    ) -> Result<()> {
-        unimplemented!()
+    /// Зашифровать сообщение и подготовить его локальную копию
+    fn prepare_outgoing(
+        &mut self,
+        to_contact_id: &ContactId,
+        session_id: &SessionId,
+        plaintext: &str,
+    ) -> Result<(ChatMessage, StoredMessage)> {
+        let to_contact_id = to_contact_id.as_str();
+        let session_id = session_id.as_str();
+        let user_id = self.outgoing_sender(to_contact_id)?;
+        let body = MessageBody::new_text(plaintext);
+        let timestamp = crate::utils::time::now();
+        let encrypted = self
+            .crypto_manager
+            .encrypt_body_at(session_id, &body, timestamp)?;
+        let chat_msg = ChatMessage::from_encrypted_at(&user_id, to_contact_id, &encrypted, timestamp)?;
+
+        let stored = StoredMessage {
+            id: chat_msg.id.clone(),
+            conversation_id: to_contact_id.to_string(),
+            from: user_id,
+            to: to_contact_id.to_string(),
+            encrypted_content: chat_msg.content.clone(),
+            timestamp: chat_msg.timestamp as i64,
+            status: MessageStatus::Sent,
+            local_content: Some(self.seal_local_body(&body)?),
+            ratchet_header: Some(StoredRatchetHeader {
+                ratchet_dh_public: chat_msg.ratchet_dh_public.clone(),
+                message_number: chat_msg.message_number,
+            }),
+        };
+
+        Ok((chat_msg, stored))
     }
 
-    // === Геттеры для UI ===
+    /// Обработать входящее сообщение
+    #[cfg(target_arch = "wasm32")]
+    pub async fn receive_message(&mut self, chat_msg: ChatMessage, session_id: &SessionId) -> Result<()> {
+        self.receive_message_async(chat_msg, session_id).await
+    }
 
-    pub fn get_user_id(&self) -> Option<&str> {
-        self.user_id.as_deref()
+    /// Обработать входящее сообщение (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn receive_message(&mut self, chat_msg: ChatMessage, session_id: &SessionId) -> Result<()> {
+        complete_now(self.receive_message_async(chat_msg, session_id))
     }
 
-    pub fn get_username(&self) -> Option<&str> {
-        self.username.as_deref()
+    async fn receive_message_async(&mut self, chat_msg: ChatMessage, session_id: &SessionId) -> Result<()> {
+        // Подмененный заголовок отвергается до того, как сообщение учтет seq
+        let encrypted = crate::protocol::validation::verify_chat_message_integrity(&chat_msg)?;
+        let decision = self.notification_decision(&chat_msg.from);
+        // Повторная доставка (переотправка, синхронизация) уже сохраненного
+        // сообщения ничего не меняет: ключ сообщения израсходован, seq учтен
+        if let Some(existing) = self.storage.load_message(&chat_msg.id).await? {
+            if existing.from != chat_msg.from {
+                return Err(ConstructError::ValidationError(format!(
+                    "Message id {} is already used by another sender",
+                    chat_msg.id
+                )));
+            }
+            return Ok(());
+        }
+        // Беседу очистили: старые сообщения из синхронизации истории не возвращаются
+        if self.is_cleared(&chat_msg.from, chat_msg.timestamp as i64).await? {
+            return Ok(());
+        }
+        self.ensure_session_restored(&chat_msg.from).await?;
+        self.check_identity_consistency(&chat_msg.from)?;
+        self.check_sequence(&chat_msg)?;
+
+        let body = self
+            .crypto_manager
+            .decrypt_body_at(session_id.as_str(), &encrypted, chat_msg.timestamp)?;
+        // seq учитывается только для сообщения, которое удалось расшифровать:
+        // поддельный seq не должен блокировать настоящие сообщения
+        self.commit_sequence(&chat_msg);
+        if let Some(key_id) = encrypted.one_time_prekey_id {
+            self.forget_one_time_prekey(key_id).await?;
+        }
+        // Сообщение заблокированного контакта расшифровывается и отбрасывается:
+        // ratchet продвигается, и после разблокировки сессия продолжает работать
+        if decision == NotificationDecision::Drop {
+            return Ok(());
+        }
+        match body {
+            MessageBody::SessionReset => return self.handle_peer_reset(&chat_msg.from).await,
+            MessageBody::SenderKeyDistribution { distribution } => {
+                return self.handle_sender_key_distribution(&chat_msg.from, &distribution);
+            }
+            MessageBody::SenderKeyRequest { group_id } => {
+                return self.handle_sender_key_request(&chat_msg.from, &group_id).await;
+            }
+            MessageBody::Text { .. } => {}
+        }
+
+        let stored = StoredMessage {
+            id: chat_msg.id.clone(),
+            conversation_id: chat_msg.from.clone(),
+            from: chat_msg.from.clone(),
+            to: chat_msg.to,
+            encrypted_content: chat_msg.content,
+            timestamp: chat_msg.timestamp as i64,
+            status: MessageStatus::Delivered,
+            local_content: Some(self.seal_local_body(&body)?),
+            ratchet_header: Some(StoredRatchetHeader {
+                ratchet_dh_public: chat_msg.ratchet_dh_public,
+                message_number: chat_msg.message_number,
+            }),
+        };
+        self.storage.save_message(stored.clone()).await?;
+        self.conversations_manager.add_message(&chat_msg.from, stored.clone());
+        self.update_message_cache(&chat_msg.from, stored);
+
+        if matches!(decision, NotificationDecision::Notify | NotificationDecision::Silent) {
+            self.conversations_manager
+                .get_or_create(&chat_msg.from)
+                .increment_unread();
+        }
+        if decision == NotificationDecision::Notify {
+            self.events.push(AppEvent::MessageReceived {
+                conversation_id: chat_msg.from,
+                message_id: chat_msg.id,
+            });
+        }
+
+        Ok(())
     }
 
-    pub fn ui_state(&self) -> &UiState {
-        &self.ui_state
+    /// Решение политики уведомлений для сообщения от `contact_id`
+    fn notification_decision(&self, contact_id: &str) -> NotificationDecision {
+        self.notification_policy.decide(&NotificationContext {
+            contact_id,
+            contact: self.contact_manager.get_contact(contact_id),
+            active_conversation: self.active_conversation.as_deref() == Some(contact_id),
+        })
     }
 
-    pub fn ui_state_mut(&mut self) -> &mut UiState {
-        &mut self.ui_state
+    /// Заменить политику уведомлений (по умолчанию `DefaultNotificationPolicy`)
+    pub fn set_notification_policy(&mut self, policy: Box<dyn NotificationPolicy>) {
+        self.notification_policy = policy;
     }
 
-    pub fn crypto_manager(&self) -> &CryptoCore<P> {
-        &self.crypto_manager
+    /// Проверить серверный `seq` входящего сообщения до расшифровки.
+    /// Повтор или откат номера - ошибка. Номер не запоминается (см. `commit_sequence`)
+    fn check_sequence(&self, chat_msg: &ChatMessage) -> Result<()> {
+        let (Some(seq), Some(&last)) = (chat_msg.seq, self.last_seq.get(&chat_msg.from)) else {
+            return Ok(());
+        };
+
+        if seq <= last {
+            return Err(ConstructError::ValidationError(format!(
+                "Non-monotonic seq {} after {} from {}",
+                seq, last, chat_msg.from
+            )));
+        }
+        Ok(())
     }
 
-    pub fn crypto_manager_mut(&mut self) -> &mut CryptoCore<P> {
-        &mut self.crypto_manager
+    /// Запомнить `seq` расшифрованного сообщения.
+    /// Пропуск номеров - событие `AppEvent::SequenceGap`.
+    /// Первое сообщение беседы после запуска задает точку отсчета
+    fn commit_sequence(&mut self, chat_msg: &ChatMessage) {
+        let Some(seq) = chat_msg.seq else {
+            return;
+        };
+
+        if let Some(&last) = self.last_seq.get(&chat_msg.from) {
+            if seq > last + 1 {
+                self.events.push(AppEvent::SequenceGap {
+                    conversation_id: chat_msg.from.clone(),
+                    expected: last + 1,
+                    received: seq,
+                });
+            }
+        }
+
+        self.last_seq.insert(chat_msg.from.clone(), seq);
     }
 
-    pub fn conversations_manager(&self) -> &ConversationsManager {
-        &self.conversations_manager
+    /// Обновить кеш сообщений
+    fn update_message_cache(&mut self, conversation_id: &str, msg: StoredMessage) {
+        let cache = self
+            .message_cache
+            .entry(conversation_id.to_string())
+            .or_default();
+        insert_sorted(cache, msg);
     }
 
-    pub fn conversations_manager_mut(&mut self) -> &mut ConversationsManager {
-        &mut self.conversations_manager
+    /// Лента последних сообщений по всем беседам, от новых к старым
+    #[cfg(target_arch = "wasm32")]
+    pub async fn recent_activity(&self, limit: usize) -> Result<Vec<StoredMessage>> {
+        self.storage.load_recent(limit).await
     }
 
-    // === Очистка ===
+    /// Лента последних сообщений по всем беседам (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recent_activity(&self, limit: usize) -> Result<Vec<StoredMessage>> {
+        complete_now(self.storage.load_recent(limit))
+    }
 
-    /// Очистить все данные
+    /// Количество сообщений в беседе с контактом
     #[cfg(target_arch = "wasm32")]
-    pub async fn clear_all_data(&mut self) -> Result<()> {
-        // Очистить кеши
-        self.message_cache.clear();
-        self.conversations_manager.clear_all();
-        self.contact_manager.clear_all();
+    pub async fn message_count(&self, contact_id: &str) -> Result<usize> {
+        self.storage.count_messages(contact_id).await
+    }
 
-        // Сбросить состояние
-        self.user_id = None;
-        self.username = None;
-        self.active_conversation = None;
-        self.connection_state = ConnectionState::Disconnected;
+    /// Количество сообщений в беседе с контактом (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn message_count(&self, contact_id: &str) -> Result<usize> {
+        complete_now(self.storage.count_messages(contact_id))
+    }
 
-        // TODO: Очистить IndexedDB полностью
+    /// Загрузить беседу
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_conversation(&mut self, contact_id: &str) -> Result<Vec<StoredMessage>> {
+        unimplemented!()
+    }
 
-        Ok(())
+    /// Загрузить беседу (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_conversation(&mut self, contact_id: &str) -> Result<Vec<StoredMessage>> {
+        unimplemented!()
     }
 
-    /// Очистить все данные (non-WASM версия)
+    /// Цитата для сообщения-ответа (None - сообщение не является ответом)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn reply_context(&self, message_id: &str) -> Result<Option<ReplyContext>> {
+        self.reply_context_async(message_id).await
+    }
+
+    /// Цитата для сообщения-ответа (non-WASM версия)
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn clear_all_data(&mut self) -> Result<()> {
-        self.message_cache.clear();
-        self.conversations_manager.clear_all();
-        self.contact_manager.clear_all();
-        self.storage.clear_all()?;
+    pub fn reply_context(&self, message_id: &str) -> Result<Option<ReplyContext>> {
+        complete_now(self.reply_context_async(message_id))
+    }
 
-        self.user_id = None;
-        self.username = None;
-        self.active_conversation = None;
-        self.connection_state = ConnectionState::Disconnected;
+    async fn reply_context_async(&self, message_id: &str) -> Result<Option<ReplyContext>> {
+        let msg = self.storage.load_message(message_id).await?.ok_or_else(|| {
+            ConstructError::NotFound(format!("Message not found: {}", message_id))
+        })?;
+        let Some(reply_to) = self.reply_to_of(&msg)? else {
+            return Ok(None);
+        };
 
-        Ok(())
+        let original = self.storage.load_message(&reply_to).await?;
+        self.quote(reply_to, original).map(Some)
     }
 
-    // === Регистрация на сервере ===
+    fn reply_to_of(&self, msg: &StoredMessage) -> Result<Option<String>> {
+        Ok(self
+            .open_local_body(msg)?
+            .and_then(|body| body.reply_to().map(str::to_string)))
+    }
 
-    /// Зарегистрировать пользователя на сервере
-    /// Отправляет сообщение Register с username, password и registration bundle
+    fn quote(&self, reply_to: String, original: Option<StoredMessage>) -> Result<ReplyContext> {
+        let Some(original) = original else {
+            return Ok(ReplyContext::Missing {
+                message_id: reply_to,
+            });
+        };
+
+        let text = self
+            .open_local_body(&original)?
+            .map(|body| body.as_text().to_string());
+
+        Ok(ReplyContext::Quoted {
+            message_id: original.id,
+            from: original.from,
+            timestamp: original.timestamp,
+            text,
+        })
+    }
+
+    /// Диагностика беседы для поддержки ("почему не расшифровывается").
+    /// Ключи и содержимое сообщений не раскрываются
+    pub fn diagnose_conversation(&self, contact_id: &str) -> ConversationDiagnosis {
+        let client = self.crypto_manager.client();
+        let session_id = client.session_id_for_contact(contact_id);
+        let session = session_id.and_then(|id| client.session(id));
+        let stats = session_id
+            .map(|id| client.decrypt_stats(id))
+            .unwrap_or_default();
+
+        ConversationDiagnosis {
+            contact_id: contact_id.to_string(),
+            session_present: session.is_some(),
+            restore_pending: self.pending_restore.contains(contact_id),
+            suite_id: session.map(|s| s.suite_id()),
+            sending_chain_length: session.map_or(0, |s| s.sending_chain_length()),
+            receiving_chain_length: session.map_or(0, |s| s.receiving_chain_length()),
+            skipped_key_count: session.map_or(0, |s| s.skipped_key_count()),
+            last_successful_decrypt: stats.last_success_at,
+            consecutive_failures: stats.consecutive_failures,
+        }
+    }
+
+    /// Установить активную беседу
+    pub fn set_active_conversation(&mut self, contact_id: Option<String>) {
+        self.active_conversation = contact_id;
+    }
+
+    /// Получить активную беседу
+    pub fn get_active_conversation(&self) -> Option<&str> {
+        self.active_conversation.as_deref()
+    }
+
+    // === Очистка беседы ===
+
+    /// Очистить беседу с контактом: удалить ее сообщения (и ждущие отправки)
+    ///
+    /// Запоминается отметка `cleared_at` - время очистки. Сообщения беседы с
+    /// `timestamp <= cleared_at`, пришедшие позже (повторная доставка,
+    /// синхронизация истории с сервера), отбрасываются; более новые принимаются.
     #[cfg(target_arch = "wasm32")]
-    pub fn register_on_server(&self, password: String) -> Result<()> {
-        use crate::protocol::messages::{ClientMessage, RegisterData};
+    pub async fn clear_conversation(&mut self, contact_id: &str) -> Result<usize> {
+        self.clear_conversation_async(&ContactId::new(contact_id)?).await
+    }
+
+    /// Очистить беседу с контактом (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn clear_conversation(&mut self, contact_id: &str) -> Result<usize> {
+        complete_now(self.clear_conversation_async(&ContactId::new(contact_id)?))
+    }
+
+    async fn clear_conversation_async(&mut self, contact_id: &ContactId) -> Result<usize> {
+        let contact_id = contact_id.as_str();
+        let cleared_at = (crate::utils::time::now() as i64)
+            .max(self.cleared_at_async(contact_id).await?.unwrap_or(i64::MIN));
+        self.storage
+            .save_clear_marker(StoredClearMarker {
+                conversation_id: contact_id.to_string(),
+                cleared_at,
+            })
+            .await?;
+
+        let messages = self
+            .storage
+            .load_messages_for_conversation(contact_id, usize::MAX, 0)
+            .await?;
+        let mut removed = 0;
+        for msg in messages.iter().filter(|msg| msg.timestamp <= cleared_at) {
+            self.storage.delete_message(&msg.id).await?;
+            self.acked_messages.remove(&msg.id);
+            removed += 1;
+        }
+
+        if let Some(conversation) = self.conversations_manager.get_mut(contact_id) {
+            conversation.messages.retain(|msg| msg.timestamp > cleared_at);
+            if conversation.messages.is_empty() {
+                conversation.clear_messages();
+            }
+        }
+        if let Some(cache) = self.message_cache.get_mut(contact_id) {
+            cache.retain(|msg| msg.timestamp > cleared_at);
+        }
+        Ok(removed)
+    }
+
+    /// Время последней очистки беседы (None - беседу не очищали)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn cleared_at(&self, contact_id: &str) -> Result<Option<i64>> {
+        self.cleared_at_async(contact_id).await
+    }
+
+    /// Время последней очистки беседы (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn cleared_at(&self, contact_id: &str) -> Result<Option<i64>> {
+        complete_now(self.cleared_at_async(contact_id))
+    }
+
+    async fn cleared_at_async(&self, contact_id: &str) -> Result<Option<i64>> {
+        Ok(self
+            .storage
+            .load_clear_marker(contact_id)
+            .await?
+            .map(|marker| marker.cleared_at))
+    }
+
+    /// Сообщение с `timestamp` попадает под очистку беседы
+    async fn is_cleared(&self, contact_id: &str, timestamp: i64) -> Result<bool> {
+        Ok(self
+            .cleared_at_async(contact_id)
+            .await?
+            .is_some_and(|cleared_at| timestamp <= cleared_at))
+    }
+
+    // === Черновики ===
+
+    /// Сохранить черновик для контакта (текст шифруется мастер-ключом).
+    /// Пустой текст удаляет черновик
+    #[cfg(target_arch = "wasm32")]
+    pub async fn save_draft(&mut self, contact_id: &str, text: &str) -> Result<()> {
+        self.save_draft_async(&ContactId::new(contact_id)?, text).await
+    }
+
+    /// Сохранить черновик для контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save_draft(&mut self, contact_id: &str, text: &str) -> Result<()> {
+        complete_now(self.save_draft_async(&ContactId::new(contact_id)?, text))
+    }
+
+    async fn save_draft_async(&mut self, contact_id: &ContactId, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return self.storage.delete_draft(contact_id.as_str()).await;
+        }
+
+        let key = self.require_master_key()?;
+        let sealed_text = crate::crypto::master_key::encrypt_with_master_key(key, text.as_bytes())?;
+        self.storage
+            .save_draft(StoredDraft {
+                contact_id: contact_id.to_string(),
+                sealed_text,
+                updated_at: current_timestamp(),
+            })
+            .await
+    }
+
+    /// Загрузить черновик для контакта (None - черновика нет)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_draft(&self, contact_id: &str) -> Result<Option<String>> {
+        self.load_draft_async(&ContactId::new(contact_id)?).await
+    }
+
+    /// Загрузить черновик для контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_draft(&self, contact_id: &str) -> Result<Option<String>> {
+        complete_now(self.load_draft_async(&ContactId::new(contact_id)?))
+    }
+
+    async fn load_draft_async(&self, contact_id: &ContactId) -> Result<Option<String>> {
+        let Some(draft) = self.storage.load_draft(contact_id.as_str()).await? else {
+            return Ok(None);
+        };
+
+        let key = self.require_master_key()?;
+        let plaintext = crate::crypto::master_key::decrypt_with_master_key(key, &draft.sealed_text)?;
+        std::str::from_utf8(&plaintext)
+            .map(|text| Some(text.to_string()))
+            .map_err(|_| ConstructError::SerializationError("Draft is not valid UTF-8".to_string()))
+    }
+
+    /// Удалить черновик для контакта
+    #[cfg(target_arch = "wasm32")]
+    pub async fn clear_draft(&mut self, contact_id: &str) -> Result<()> {
+        self.storage.delete_draft(ContactId::new(contact_id)?.as_str()).await
+    }
+
+    /// Удалить черновик для контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn clear_draft(&mut self, contact_id: &str) -> Result<()> {
+        complete_now(self.storage.delete_draft(ContactId::new(contact_id)?.as_str()))
+    }
+
+    // === Очередь исходящих ===
+
+    /// Сохранить сообщение со статусом `Pending` и поставить в очередь.
+    /// Шифруется оно только при отправке, сессией, активной на тот момент
+    async fn enqueue_outgoing(&mut self, to_contact_id: &ContactId, plaintext: &str) -> Result<String> {
+        let to_contact_id = to_contact_id.as_str();
+        let user_id = self.outgoing_sender(to_contact_id)?;
+        let body = MessageBody::new_text(plaintext);
+
+        let stored = StoredMessage {
+            id: crate::utils::uuid::generate_v4(),
+            conversation_id: to_contact_id.to_string(),
+            from: user_id,
+            to: to_contact_id.to_string(),
+            encrypted_content: String::new(),
+            timestamp: crate::utils::time::now() as i64,
+            status: MessageStatus::Pending,
+            local_content: Some(self.seal_local_body(&body)?),
+            ratchet_header: None,
+        };
+        self.storage.save_message(stored.clone()).await?;
+        self.conversations_manager.add_message(to_contact_id, stored.clone());
+        self.update_message_cache(to_contact_id, stored.clone());
+
+        self.outbound_queue.push_back(stored.id.clone());
+        Ok(stored.id)
+    }
+
+    /// Отправить сообщения из очереди (вызывается после восстановления соединения)
+    ///
+    /// Сообщения уходят в порядке постановки в очередь. Если сообщение не удалось
+    /// зашифровать (например, нет сессии), оно и следующие сообщения этому контакту
+    /// остаются в очереди до следующего вызова. Возвращает количество отправленных.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn flush_outbound_queue(&mut self) -> Result<usize> {
+        self.flush_outbound_queue_async().await
+    }
+
+    /// Отправить сообщения из очереди (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn flush_outbound_queue(&mut self) -> Result<usize> {
+        complete_now(self.flush_outbound_queue_async())
+    }
+
+    async fn flush_outbound_queue_async(&mut self) -> Result<usize> {
+        let mut remaining = VecDeque::new();
+        let mut blocked: HashSet<String> = HashSet::new();
+        let mut sent = 0;
+
+        while let Some(message_id) = self.outbound_queue.pop_front() {
+            if !self.transport_connected() {
+                remaining.push_back(message_id);
+                continue;
+            }
+            // Сообщение могли удалить, пока оно ждало отправки
+            let Some(mut stored) = self.storage.load_message(&message_id).await? else {
+                continue;
+            };
+            if blocked.contains(&stored.to) {
+                remaining.push_back(message_id);
+                continue;
+            }
+
+            let chat_msg = match self.encrypt_queued(&stored).await {
+                Ok(chat_msg) => chat_msg,
+                Err(_) => {
+                    blocked.insert(stored.to.clone());
+                    remaining.push_back(message_id);
+                    continue;
+                }
+            };
+            if self.send_to_server(&ClientMessage::SendMessage(chat_msg.clone())).is_err() {
+                blocked.insert(stored.to.clone());
+                remaining.push_back(message_id);
+                continue;
+            }
+
+            stored.encrypted_content = chat_msg.content;
+            stored.timestamp = chat_msg.timestamp as i64;
+            stored.status = MessageStatus::Sent;
+            stored.ratchet_header = Some(StoredRatchetHeader {
+                ratchet_dh_public: chat_msg.ratchet_dh_public,
+                message_number: chat_msg.message_number,
+            });
+            self.storage.save_message(stored.clone()).await?;
+            self.replace_cached_message(stored);
+            sent += 1;
+        }
+
+        self.outbound_queue = remaining;
+        Ok(sent)
+    }
+
+    /// Зашифровать сообщение из очереди текущей сессией с контактом.
+    /// `timestamp` берется на момент отправки: получатель отвергает слишком старые
+    async fn encrypt_queued(&mut self, stored: &StoredMessage) -> Result<ChatMessage> {
+        self.ensure_session_restored(&stored.to).await?;
+        let session_id = self.resolve_sending_session(&stored.to, None)?;
+        let body = self
+            .open_local_body(stored)?
+            .ok_or_else(|| ConstructError::NotFound(format!("Queued message body: {}", stored.id)))?;
+
+        let timestamp = crate::utils::time::now();
+        let encrypted = self
+            .crypto_manager
+            .encrypt_body_at(&session_id, &body, timestamp)?;
+        let mut chat_msg = ChatMessage::from_encrypted_at(&stored.from, &stored.to, &encrypted, timestamp)?;
+        chat_msg.id = stored.id.clone();
+        Ok(chat_msg)
+    }
+
+    /// Заменить сообщение в беседе и кеше (после отправки из очереди)
+    fn replace_cached_message(&mut self, stored: StoredMessage) {
+        let conversation_id = stored.conversation_id.clone();
+        if let Some(conversation) = self.conversations_manager.get_mut(&conversation_id) {
+            conversation.messages.retain(|m| m.id != stored.id);
+            conversation.add_message(stored.clone());
+        }
+        if let Some(cache) = self.message_cache.get_mut(&conversation_id) {
+            cache.retain(|m| m.id != stored.id);
+        }
+        self.update_message_cache(&conversation_id, stored);
+    }
+
+    /// Количество сообщений, ждущих отправки
+    pub fn pending_message_count(&self) -> usize {
+        self.outbound_queue.len()
+    }
+
+    /// Есть ли транспорт с открытым соединением
+    fn transport_connected(&self) -> bool {
+        self.transport
+            .as_ref()
+            .is_some_and(|transport| transport.is_connected())
+    }
+
+    // === Управление соединением ===
+
+    /// Подключиться к серверу WebSocket
+    #[cfg(target_arch = "wasm32")]
+    pub fn connect(&mut self, server_url: &str) -> Result<()> {
+        if self.connection_state == ConnectionState::Connected {
+            return Err(ConstructError::NetworkError(
+                "Already connected".to_string(),
+            ));
+        }
+
+        self.connection_state = ConnectionState::Connecting;
+
+        let mut transport = WebSocketTransport::new();
+        transport.set_reconnect_policy(self.reconnect_state.policy().clone());
+        transport.connect(server_url)?;
+
+        // Настроить базовые callbacks
+        self.setup_transport_callbacks(&mut transport)?;
+
+        self.transport = Some(transport);
+        self.connection_state = ConnectionState::Connected;
+
+        Ok(())
+    }
+
+    /// Настроить WebSocket callbacks (базовая версия без Arc)
+    /// Эта версия используется внутри AppState, где мы не имеем доступа к Arc
+    #[cfg(target_arch = "wasm32")]
+    fn setup_transport_callbacks(&self, transport: &mut WebSocketTransport) -> Result<()> {
+        use crate::wasm::console;
+
+        // Callback для успешного подключения
+        transport.set_on_open(|| {
+            console::log("✅ WebSocket connected successfully");
+        })?;
+
+        // Базовый callback для входящих сообщений
+        transport.set_on_message(|msg| {
+            console::log(&format!("📩 Received message: {:?}", msg));
+        })?;
+
+        // Callback для ошибок
+        transport.set_on_error(|err| {
+            console::log(&format!("❌ WebSocket error: {}", err));
+        })?;
+
+        // Callback для закрытия соединения
+        transport.set_on_close(|code, reason| {
+            console::log(&format!("🔌 WebSocket closed: {} - {}", code, reason));
+        })?;
+
+        Ok(())
+    }
+
+    /// Настроить WebSocket callbacks с доступом к Arc<Mutex<AppState>>
+    /// Эта версия вызывается из WASM bindings и имеет полный доступ к AppState
+    #[cfg(target_arch = "wasm32")]
+    pub fn setup_transport_callbacks_with_arc(
+        transport: &mut WebSocketTransport,
+        app_state_arc: std::sync::Arc<std::sync::Mutex<AppState<P>>>,
+    ) -> Result<()> {
+        unimplemented!()
+    }
+
+    /// Подключиться к серверу (non-WASM заглушка)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect(&mut self, _server_url: &str) -> Result<()> {
+        Err(ConstructError::NetworkError(
+            "WebSocket only available in WASM".to_string(),
+        ))
+    }
+
+    /// Отключиться от сервера
+    #[cfg(target_arch = "wasm32")]
+    pub fn disconnect(&mut self) -> Result<()> {
+        if let Some(transport) = &mut self.transport {
+            transport.close()?;
+        }
+
+        self.transport = None;
+        self.connection_state = ConnectionState::Disconnected;
+
+        Ok(())
+    }
+
+    /// Отключиться от сервера (non-WASM заглушка)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn disconnect(&mut self) -> Result<()> {
+        self.connection_state = ConnectionState::Disconnected;
+        Ok(())
+    }
+
+    /// Установить WebSocket транспорт
+    /// Используется из WASM bindings после настройки callbacks
+    #[cfg(target_arch = "wasm32")]
+    pub fn set_transport(&mut self, mut transport: WebSocketTransport) {
+        transport.set_reconnect_policy(self.reconnect_state.policy().clone());
+        self.transport = Some(transport);
+        self.connection_state = ConnectionState::Connecting;
+    }
+
+    /// Установить транспорт (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_transport(&mut self, mut transport: Box<dyn Transport>) {
+        transport.set_reconnect_policy(self.reconnect_state.policy().clone());
+        self.transport = Some(transport);
+        self.connection_state = ConnectionState::Connected;
+    }
+
+    /// Отправить сообщение серверу через текущий транспорт
+    fn send_to_server(&self, message: &ClientMessage) -> Result<()> {
+        let transport = self.transport.as_ref().ok_or_else(|| {
+            ConstructError::NetworkError("Not connected to server. Call connect first.".to_string())
+        })?;
+
+        transport.send(message)
+    }
+
+    /// Установить состояние соединения
+    pub fn set_connection_state(&mut self, state: ConnectionState) {
+        self.connection_state = state;
+    }
+
+    /// Получить состояние соединения
+    /// (Reconnecting, пока транспорт восстанавливает оборванное соединение)
+    pub fn connection_state(&self) -> ConnectionState {
+        let reconnecting = self
+            .transport
+            .as_ref()
+            .is_some_and(|transport| transport.state() == TransportState::Reconnecting);
+        if reconnecting && self.connection_state == ConnectionState::Connected {
+            return ConnectionState::Reconnecting;
+        }
+        self.connection_state
+    }
+
+    /// Проверить, подключен ли к серверу
+    pub fn is_connected(&self) -> bool {
+        self.connection_state() == ConnectionState::Connected
+    }
+
+    /// Установить URL сервера
+    pub fn set_server_url(&mut self, url: String) {
+        self.server_url = Some(url);
+    }
+
+    /// Получить URL сервера
+    pub fn get_server_url(&self) -> Option<&str> {
+        self.server_url.as_deref()
+    }
+
+    /// Получить состояние переподключения
+    pub fn reconnect_state(&self) -> &ReconnectState {
+        &self.reconnect_state
+    }
+
+    /// Получить мутабельное состояние переподключения
+    pub fn reconnect_state_mut(&mut self) -> &mut ReconnectState {
+        &mut self.reconnect_state
+    }
+
+    /// Настроить автоматическое переподключение (или выключить его:
+    /// `ReconnectPolicy::disabled()`). Применяется к текущему и будущим транспортам
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        if let Some(transport) = &mut self.transport {
+            transport.set_reconnect_policy(policy.clone());
+        }
+        self.reconnect_state = ReconnectState::with_policy(policy);
+    }
+
+    // === Экспорт беседы ===
+
+    /// Экспортировать одну беседу в JSON (массив сообщений)
+    ///
+    /// При `decrypt == false` content содержит сохраненный ciphertext,
+    /// при `decrypt == true` - расшифрованный текст (пароль должен открыть мастер-ключ).
+    #[cfg(target_arch = "wasm32")]
+    pub async fn export_conversation(
+        &self,
+        contact_id: &str,
+        decrypt: bool,
+        password: &str,
+    ) -> Result<String> {
+        self.export_conversation_async(contact_id, decrypt, password).await
+    }
+
+    /// Экспортировать одну беседу в JSON (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_conversation(
+        &self,
+        contact_id: &str,
+        decrypt: bool,
+        password: &str,
+    ) -> Result<String> {
+        complete_now(self.export_conversation_async(contact_id, decrypt, password))
+    }
+
+    async fn export_conversation_async(
+        &self,
+        contact_id: &str,
+        decrypt: bool,
+        password: &str,
+    ) -> Result<String> {
+        let master_key = if decrypt {
+            let user_id = self.require_user_id()?;
+            let stored = self.storage.load_private_keys(user_id).await?.ok_or_else(|| {
+                ConstructError::NotFound(format!("Private keys not found for {}", user_id))
+            })?;
+            Some(Self::derive_checked_master_key(&stored, password)?)
+        } else {
+            None
+        };
+
+        let messages = self
+            .storage
+            .load_messages_for_conversation(contact_id, usize::MAX, 0)
+            .await?;
+
+        self.render_export(messages, master_key.as_deref())
+    }
+
+    fn require_user_id(&self) -> Result<&str> {
+        self.user_id
+            .as_deref()
+            .ok_or_else(|| ConstructError::ValidationError("User not registered".to_string()))
+    }
+
+    fn render_export(
+        &self,
+        messages: Vec<StoredMessage>,
+        master_key: Option<&[u8; 32]>,
+    ) -> Result<String> {
+        use crate::crypto::master_key as mk;
+
+        let exported = messages
+            .into_iter()
+            .map(|msg| {
+                let content = match master_key {
+                    Some(key) => match &msg.local_content {
+                        Some(sealed) => {
+                            let plaintext = mk::decrypt_with_master_key(key, sealed)?;
+                            Some(MessageBody::from_plaintext(&plaintext)?.as_text().to_string())
+                        }
+                        None => None,
+                    },
+                    None => Some(msg.encrypted_content.clone()),
+                };
+
+                let direction = if self.user_id.as_deref() == Some(msg.from.as_str()) {
+                    MessageDirection::Sent
+                } else {
+                    MessageDirection::Received
+                };
+
+                Ok(ExportedMessage {
+                    id: msg.id,
+                    from: msg.from,
+                    to: msg.to,
+                    timestamp: msg.timestamp,
+                    direction,
+                    status: msg.status,
+                    content,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        serde_json::to_string_pretty(&exported).map_err(|e| {
+            ConstructError::SerializationError(format!("Failed to export conversation: {}", e))
+        })
+    }
+
+    // === Бэкапы ===
+
+    /// Собрать архив состояния из сохраненных контактов и сообщений
+    fn build_archive(
+        &self,
+        contacts: Vec<StoredContact>,
+        messages: Vec<StoredMessage>,
+    ) -> Result<StateArchive> {
+        let sessions = self
+            .crypto_manager
+            .client()
+            .export_all_sessions()
+            .map_err(ConstructError::from)?;
+
+        Ok(StateArchive {
+            version: ARCHIVE_VERSION,
+            user_id: self.user_id.clone(),
+            username: self.username.clone(),
+            contacts,
+            messages,
+            sessions,
+            created_at: current_timestamp(),
+            private_keys: None,
+        })
+    }
+
+    /// Зашифровать архив паролем пользователя
+    fn seal_archive(archive: &StateArchive, password: &str) -> Result<Vec<u8>> {
+        use crate::crypto::master_key;
+
+        let bytes = crate::utils::serialization::to_bytes(archive)
+            .map_err(ConstructError::SerializationError)?;
+        master_key::encrypt_with_password(password, &bytes)
+    }
+
+    /// Расшифровать архив и проверить его версию
+    fn open_archive(blob: &[u8], password: &str) -> Result<StateArchive> {
+        use crate::crypto::master_key;
+
+        let bytes = master_key::decrypt_with_password(password, blob)?;
+        let archive: StateArchive = match crate::utils::serialization::from_bytes(&bytes) {
+            Ok(archive) => archive,
+            Err(e) => match crate::utils::serialization::from_bytes::<StateArchiveV1>(&bytes) {
+                Ok(legacy) if legacy.version == 1 => legacy.into(),
+                _ => return Err(ConstructError::SerializationError(e)),
+            },
+        };
+
+        if archive.version == 0 || archive.version > ARCHIVE_VERSION {
+            return Err(ConstructError::ValidationError(format!(
+                "Unsupported archive version: {}",
+                archive.version
+            )));
+        }
+
+        Ok(archive)
+    }
+
+    /// Применить архив к состоянию в памяти (контакты, беседы, сессии)
+    fn apply_archive_in_memory(&mut self, archive: &StateArchive) -> Result<()> {
+        if self.user_id.is_none() {
+            self.user_id = archive.user_id.clone();
+        }
+        if self.username.is_none() {
+            self.username = archive.username.clone();
+        }
+
+        for stored in &archive.contacts {
+            if !self.contact_manager.has_contact(&stored.id) {
+                self.contact_manager.add_contact(stored.clone().into())?;
+            }
+        }
+
+        for msg in &archive.messages {
+            self.conversations_manager
+                .add_message(&msg.conversation_id, msg.clone());
+        }
+
+        for (session_id, session_data) in &archive.sessions {
+            self.crypto_manager
+                .client_mut()
+                .import_session(session_id, session_data)
+                .map_err(ConstructError::from)?;
+        }
+
+        Ok(())
+    }
+
+    /// Экспортировать зашифрованный архив: контакты, сообщения и приватные ключи,
+    /// зашифрованные мастер-ключом из того же пароля. Сессии не входят в архив
+    #[cfg(target_arch = "wasm32")]
+    pub async fn export_archive(&self, password: &str) -> Result<Vec<u8>> {
+        self.export_archive_async(password).await
+    }
+
+    /// Экспортировать зашифрованный архив (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_archive(&self, password: &str) -> Result<Vec<u8>> {
+        complete_now(self.export_archive_async(password))
+    }
+
+    async fn export_archive_async(&self, password: &str) -> Result<Vec<u8>> {
+        let mut archive = self.load_archive().await?;
+        // Снимок сессий устареет к моменту восстановления и откатил бы цепочки;
+        // после восстановления сессии создаются заново через X3DH
+        archive.sessions.clear();
+        let (private_keys, _) = self.seal_private_keys(self.require_user_id()?, password)?;
+        archive.private_keys = Some(private_keys);
+        Self::seal_archive(&archive, password)
+    }
+
+    /// Собрать архив из хранилища и текущих сессий
+    async fn load_archive(&self) -> Result<StateArchive> {
+        let contacts = self.storage.load_all_contacts().await?;
+        let messages = self.storage.load_all_messages().await?;
+        self.build_archive(contacts, messages)
+    }
+
+    /// Импортировать зашифрованный архив
+    #[cfg(target_arch = "wasm32")]
+    pub async fn import_archive(&mut self, password: &str, blob: &[u8]) -> Result<()> {
+        self.import_archive_async(password, blob).await
+    }
+
+    /// Импортировать зашифрованный архив (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn import_archive(&mut self, password: &str, blob: &[u8]) -> Result<()> {
+        complete_now(self.import_archive_async(password, blob))
+    }
+
+    async fn import_archive_async(&mut self, password: &str, blob: &[u8]) -> Result<()> {
+        let mut archive = Self::open_archive(blob, password)?;
+        // Сессии из бэкапов версии 1 устарели: новые создаются через X3DH при первой отправке
+        archive.sessions.clear();
+
+        // Ключи разблокированного пользователя архив не подменяет
+        let restored_keys = match archive.private_keys.take() {
+            Some(stored) if self.master_key.is_none() => {
+                let master_key = Self::derive_checked_master_key(&stored, password)?;
+                self.install_stored_keys(&stored, &master_key)?;
+                Some((stored, master_key))
+            }
+            _ => None,
+        };
+        self.apply_archive_in_memory(&archive)?;
+
+        if let Some((stored, master_key)) = restored_keys {
+            let metadata = self.build_metadata(&stored.user_id);
+            self.storage.save_private_keys(stored).await?;
+            self.storage.save_metadata(metadata).await?;
+            self.storage.set_at_rest_key(&master_key)?;
+            self.master_key = Some(master_key);
+        }
+        self.save_archive(archive).await
+    }
+
+    /// Сохранить контакты, сообщения и сессии архива в хранилище
+    async fn save_archive(&mut self, archive: StateArchive) -> Result<()> {
+        for contact in archive.contacts {
+            self.storage.save_contact(contact).await?;
+        }
+        for msg in archive.messages {
+            self.storage.save_message(msg).await?;
+        }
+        // Сессии уже импортированы в память (`apply_archive_in_memory`)
+        let now = current_timestamp();
+        for (session_id, session_data) in archive.sessions {
+            let Some(contact_id) = self
+                .crypto_manager
+                .client()
+                .session(&session_id)
+                .map(|session| session.contact_id().to_string())
+            else {
+                continue;
+            };
+            self.storage
+                .save_session(StoredSession {
+                    session_id,
+                    contact_id,
+                    session_data,
+                    last_used: now,
+                    created_at: now,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    // === Привязка устройств ===
+
+    /// Новое устройство: создать одноразовый ключ привязки и запрос для основного
+    /// устройства (передается по доверенному каналу, например через QR-код)
+    pub fn begin_device_link(&mut self) -> Result<ProtocolMessage> {
+        if self.user_id.is_some() {
+            return Err(ConstructError::ValidationError(
+                "Device is already registered".to_string(),
+            ));
+        }
+
+        let (private_key, public_key) =
+            P::generate_kem_keys().map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+        if let Some(mut previous) = self.pending_device_link.replace(private_key) {
+            previous.zeroize();
+        }
+
+        Ok(ProtocolMessage::DeviceLinkRequest {
+            new_device_pubkey: public_key.as_ref().to_vec(),
+        })
+    }
+
+    /// Основное устройство: зашифровать identity, контакты, сообщения и сессии
+    /// на ключ из `DeviceLinkRequest`
+    #[cfg(target_arch = "wasm32")]
+    pub async fn respond_device_link(&self, request: &ProtocolMessage) -> Result<ProtocolMessage> {
+        let archive = self.load_archive().await?;
+        self.seal_device_link(request, archive)
+    }
+
+    /// Основное устройство: ответить на запрос привязки (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn respond_device_link(&self, request: &ProtocolMessage) -> Result<ProtocolMessage> {
+        let archive = complete_now(self.load_archive())?;
+        self.seal_device_link(request, archive)
+    }
+
+    /// Новое устройство: расшифровать ответ, установить ключи и состояние основного
+    /// устройства и защитить их локальным паролем
+    #[cfg(target_arch = "wasm32")]
+    pub async fn complete_device_link(&mut self, response: &ProtocolMessage, password: &str) -> Result<()> {
+        self.complete_device_link_async(response, password).await
+    }
+
+    /// Новое устройство: завершить привязку (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn complete_device_link(&mut self, response: &ProtocolMessage, password: &str) -> Result<()> {
+        complete_now(self.complete_device_link_async(response, password))
+    }
+
+    async fn complete_device_link_async(&mut self, response: &ProtocolMessage, password: &str) -> Result<()> {
+        let (user_id, archive) = self.open_device_link(response, password)?;
+
+        let (stored_keys, master_key) = self.seal_private_keys(&user_id, password)?;
+        self.storage.save_private_keys(stored_keys).await?;
+        let metadata = self.build_metadata(&user_id);
+        self.storage.save_metadata(metadata).await?;
+        self.storage.set_at_rest_key(&master_key)?;
+        self.save_archive(archive).await?;
+
+        self.master_key = Some(master_key);
+        Ok(())
+    }
+
+    fn seal_device_link(&self, request: &ProtocolMessage, archive: StateArchive) -> Result<ProtocolMessage> {
+        crate::protocol::validation::validate_protocol_message(request)?;
+        let ProtocolMessage::DeviceLinkRequest { new_device_pubkey } = request else {
+            return Err(ConstructError::ValidationError(
+                "Expected DeviceLinkRequest message".to_string(),
+            ));
+        };
+        // Ключи отдает только разблокированное зарегистрированное устройство
+        self.require_master_key()?;
+        self.require_user_id()?;
+        if new_device_pubkey.len() != P::kem_public_key_len() {
+            return Err(ConstructError::ValidationError(format!(
+                "Device link key must be {} bytes, got {}",
+                P::kem_public_key_len(),
+                new_device_pubkey.len()
+            )));
+        }
+
+        let key_manager = self.crypto_manager.key_manager();
+        let prekey = key_manager.current_signed_prekey()?;
+        let payload = DeviceLinkPayload {
+            keys: DeviceLinkKeys {
+                identity_secret: key_manager.identity_secret_key()?.as_ref().to_vec(),
+                signing_secret: key_manager.signing_secret_key()?.as_ref().to_vec(),
+                verifying_key: key_manager.verifying_key()?.as_ref().to_vec(),
+                signed_prekey_secret: prekey.key_pair.0.as_ref().to_vec(),
+                signed_prekey_signature: prekey.signature.clone(),
+                signed_prekey_id: prekey.key_id,
+            },
+            archive,
+        };
+        let bytes = Zeroizing::new(
+            crate::utils::serialization::to_bytes(&payload)
+                .map_err(ConstructError::SerializationError)?,
+        );
+
+        let device_public = P::kem_public_key_from_bytes(new_device_pubkey.clone());
+        let sealed = device_link::seal_for_device::<P>(&device_public, &bytes)
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+
+        Ok(ProtocolMessage::DeviceLinkResponse {
+            encrypted_identity_bundle: sealed,
+        })
+    }
+
+    /// Расшифровать ответ на одноразовый ключ привязки и применить его в памяти.
+    /// Возвращает user_id и архив для сохранения в хранилище
+    fn open_device_link(&mut self, response: &ProtocolMessage, password: &str) -> Result<(String, StateArchive)> {
+        crate::protocol::validation::validate_protocol_message(response)?;
+        let ProtocolMessage::DeviceLinkResponse { encrypted_identity_bundle } = response else {
+            return Err(ConstructError::ValidationError(
+                "Expected DeviceLinkResponse message".to_string(),
+            ));
+        };
+        crate::crypto::master_key::validate_password(password)?;
+        let mut device_key = self.pending_device_link.take().ok_or_else(|| {
+            ConstructError::ValidationError("No device link in progress".to_string())
+        })?;
+
+        let opened = device_link::open_from_device::<P>(&device_key, encrypted_identity_bundle);
+        device_key.zeroize();
+        let bytes = opened.map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+        let DeviceLinkPayload { keys, archive } = crate::utils::serialization::from_bytes(&bytes)
+            .map_err(ConstructError::SerializationError)?;
+        let user_id = archive.user_id.clone().ok_or_else(|| {
+            ConstructError::ValidationError("Device link archive has no user id".to_string())
+        })?;
+
+        let key_manager = KeyManager::<P>::from_keys(
+            P::kem_private_key_from_bytes(keys.identity_secret.clone()),
+            (
+                P::signature_private_key_from_bytes(keys.signing_secret.clone()),
+                P::signature_public_key_from_bytes(keys.verifying_key.clone()),
+            ),
+            P::kem_private_key_from_bytes(keys.signed_prekey_secret.clone()),
+            keys.signed_prekey_signature.clone(),
+            keys.signed_prekey_id,
+        )?;
+        self.install_key_manager(key_manager)?;
+        self.apply_archive_in_memory(&archive)?;
+
+        Ok((user_id, archive))
+    }
+
+    /// Зашифровать состояние и загрузить бэкап на сервер
+    #[cfg(target_arch = "wasm32")]
+    pub async fn upload_backup(&self, password: &str) -> Result<()> {
+        let encrypted_blob = self.export_archive_async(password).await?;
+        self.send_backup_upload(encrypted_blob)
+    }
+
+    /// Зашифровать состояние и загрузить бэкап на сервер (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn upload_backup(&self, password: &str) -> Result<()> {
+        let encrypted_blob = complete_now(self.export_archive_async(password))?;
+        self.send_backup_upload(encrypted_blob)
+    }
+
+    fn send_backup_upload(&self, encrypted_blob: Vec<u8>) -> Result<()> {
+        let message = ClientMessage::BackupUpload(BackupUploadData {
+            encrypted_blob,
+            version: ARCHIVE_VERSION,
+        });
+        crate::protocol::validation::validate_client_message(&message)?;
+
+        self.send_to_server(&message)
+    }
+
+    /// Запросить сохраненный бэкап у сервера
+    /// Ответ приходит как ServerMessage::BackupDownloadResponse
+    pub fn request_backup_download(&self) -> Result<()> {
+        self.send_to_server(&ClientMessage::BackupDownloadRequest(
+            BackupDownloadRequestData {
+                max_version: ARCHIVE_VERSION,
+            },
+        ))
+    }
+
+    /// Есть ли полученный от сервера бэкап, ожидающий восстановления
+    pub fn has_pending_backup(&self) -> bool {
+        self.pending_backup.is_some()
+    }
+
+    /// Восстановить состояние из бэкапа, полученного от сервера
+    #[cfg(target_arch = "wasm32")]
+    pub async fn restore_from_server_backup(&mut self, password: &str) -> Result<()> {
+        self.restore_from_server_backup_async(password).await
+    }
+
+    /// Восстановить состояние из бэкапа, полученного от сервера (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn restore_from_server_backup(&mut self, password: &str) -> Result<()> {
+        complete_now(self.restore_from_server_backup_async(password))
+    }
+
+    async fn restore_from_server_backup_async(&mut self, password: &str) -> Result<()> {
+        let backup = self.pending_backup.take().ok_or_else(|| {
+            ConstructError::NotFound("No backup received from server".to_string())
+        })?;
+
+        if let Err(e) = self.import_archive_async(password, &backup.encrypted_blob).await {
+            // Оставляем бэкап, чтобы можно было повторить с другим паролем
+            self.pending_backup = Some(backup);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    // === Экстренная смена ключей ===
+
+    /// Сменить ключи всех бесед разом (при подозрении на компрометацию)
+    ///
+    /// Signed prekey ротируется, каждому собеседнику через старую сессию уходит
+    /// `MessageBody::SessionReset`, после чего все сессии и их сохраненные копии
+    /// удаляются. Для каждого контакта запрашивается свежий bundle; новая сессия
+    /// создается, когда он придет (`ServerMessage::PublicKeyBundle`) или при первой
+    /// отправке. Возвращает количество сброшенных сессий.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn rekey_all(&mut self) -> Result<usize> {
+        self.rekey_all_async().await
+    }
+
+    /// Сменить ключи всех бесед разом (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rekey_all(&mut self) -> Result<usize> {
+        complete_now(self.rekey_all_async())
+    }
+
+    async fn rekey_all_async(&mut self) -> Result<usize> {
+        let user_id = self.require_user_id()?.to_string();
+        // Без соединения собеседники не узнают о сбросе и продолжат писать в старые сессии
+        if !self.transport_connected() {
+            return Err(ConstructError::NetworkError(
+                "Rekey requires a server connection".to_string(),
+            ));
+        }
+        self.restore_remaining_async().await?;
+
+        let mut notices = Vec::new();
+        for (contact_id, _) in self.crypto_manager.client().sessions_by_recency() {
+            let Some(session_id) = self.crypto_manager.client().session_id_for_contact(&contact_id) else {
+                continue;
+            };
+            let session_id = session_id.to_string();
+            let timestamp = crate::utils::time::now();
+            let encrypted = self
+                .crypto_manager
+                .encrypt_body_at(&session_id, &MessageBody::SessionReset, timestamp)?;
+            notices.push(ChatMessage::from_encrypted_at(
+                &user_id,
+                &contact_id,
+                &encrypted,
+                timestamp,
+            )?);
+        }
+
+        let contacts = self.crypto_manager.rekey_all()?;
+        self.persist_private_keys(&user_id).await?;
+        self.delete_stored_sessions(|_| true).await?;
+
+        for notice in notices {
+            self.send_to_server(&ClientMessage::SendMessage(notice))?;
+        }
+        for contact_id in &contacts {
+            self.send_to_server(&ClientMessage::GetPublicKey(GetPublicKeyData {
+                user_id: contact_id.clone(),
+            }))?;
+        }
+
+        let reset = contacts.len();
+        self.pending_rekey.extend(contacts);
+        Ok(reset)
+    }
+
+    /// Контакты, ожидающие свежий bundle после `rekey_all`
+    pub fn pending_rekey(&self) -> Vec<String> {
+        let mut contacts: Vec<String> = self.pending_rekey.iter().cloned().collect();
+        contacts.sort();
+        contacts
+    }
+
+    /// Перешифровать приватные ключи после ротации prekey (мастер-ключ и соль прежние)
+    async fn persist_private_keys(&mut self, user_id: &str) -> Result<()> {
+        let stored = self
+            .storage
+            .load_private_keys(user_id)
+            .await?
+            .ok_or_else(|| ConstructError::NotFound(format!("User not found: {}", user_id)))?;
+        let salt = stored
+            .salt
+            .as_slice()
+            .try_into()
+            .map_err(|_| ConstructError::CryptoError("Invalid stored salt".to_string()))?;
+        let resealed = self.encrypt_private_keys(user_id, self.require_master_key()?, salt)?;
+        self.storage.save_private_keys(resealed).await
+    }
+
+    /// Убрать из хранилища одноразовый prekey, израсходованный при создании сессии:
+    /// после перезапуска он не должен снова принять первое сообщение
+    async fn forget_one_time_prekey(&mut self, key_id: u32) -> Result<()> {
+        let Some(user_id) = self.user_id.clone() else {
+            return Ok(());
+        };
+        if self.crypto_manager.key_manager().one_time_prekey(key_id).is_some() {
+            return Ok(());
+        }
+        let Some(stored) = self.storage.load_private_keys(&user_id).await? else {
+            return Ok(());
+        };
+        if stored.one_time_prekeys.iter().any(|prekey| prekey.key_id == key_id) {
+            self.persist_private_keys(&user_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Удалить сохраненные сессии контактов, подходящих под `matches`
+    async fn delete_stored_sessions(&mut self, matches: impl Fn(&str) -> bool) -> Result<()> {
+        for stored in self.storage.load_all_sessions().await? {
+            if matches(&stored.contact_id) {
+                self.storage.delete_session(&stored.session_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Собеседник сбросил сессию: удалить ее у себя. Следующее сообщение
+    /// в любую сторону начнет новую сессию через X3DH
+    async fn handle_peer_reset(&mut self, contact_id: &str) -> Result<()> {
+        self.crypto_manager.end_session(contact_id);
+        self.delete_stored_sessions(|id| id == contact_id).await?;
+        self.events.push(AppEvent::SessionReset {
+            contact_id: contact_id.to_string(),
+        });
+        Ok(())
+    }
+
+    // === Bundle контакта от сервера ===
+
+    /// Обработать bundle контакта из ответа сервера на `ClientMessage::GetPublicKey`
+    ///
+    /// Bundle проверяется (suite, длины ключей, подпись prekey) и сохраняется в
+    /// карточку контакта. Если контакту ждут отправки сообщения из очереди или его
+    /// сессию сбросил `rekey_all`, по bundle создается сессия и очередь отправляется.
+    /// При смене identity ключа сессия не создается - нужно решение пользователя.
+    /// Возвращает количество отправленных из очереди сообщений.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn on_key_bundle_response(&mut self, data: PublicKeyBundleData) -> Result<usize> {
+        self.on_key_bundle_response_async(data).await
+    }
+
+    /// Обработать bundle контакта из ответа сервера (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn on_key_bundle_response(&mut self, data: PublicKeyBundleData) -> Result<usize> {
+        complete_now(self.on_key_bundle_response_async(data))
+    }
+
+    async fn on_key_bundle_response_async(&mut self, data: PublicKeyBundleData) -> Result<usize> {
+        let contact_id = ContactId::new(&data.user_id)?.as_str().to_string();
+        let bundle = KeyBundle::try_from(&data)?;
+        bundle.verify::<P>()?;
+
+        // Сессия могла существовать и без карточки контакта - тогда обновлять нечего
+        let consistent = if self.contact_manager.get_contact(&contact_id).is_some() {
+            self.update_contact_bundle_async(
+                &contact_id,
+                PublicKeyBundle {
+                    identity_public: data.identity_public,
+                    signed_prekey_public: data.signed_prekey_public,
+                    signature: data.signature,
+                    verifying_key: data.verifying_key,
+                    signed_prekey_id: data.signed_prekey_id,
+                },
+            )
+            .await?
+        } else {
+            true
+        };
+
+        let rekeyed = self.pending_rekey.remove(&contact_id);
+        let queued = self.has_queued_for(&contact_id).await?;
+        if !consistent || !(rekeyed || queued) {
+            return Ok(0);
+        }
+        self.crypto_manager
+            .get_or_init_sending_session(&contact_id, &bundle)?;
+        if !queued {
+            return Ok(0);
+        }
+        self.flush_outbound_queue_async().await
+    }
+
+    /// Ждут ли в очереди сообщения контакту
+    async fn has_queued_for(&self, contact_id: &str) -> Result<bool> {
+        for message_id in &self.outbound_queue {
+            if let Some(stored) = self.storage.load_message(message_id).await? {
+                if stored.to == contact_id {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // === Смена identity ключа ===
+
+    /// Сменить собственный identity ключ
+    ///
+    /// Генерируются новые identity, signing ключ и signed prekey; новый bundle
+    /// подписывается старым signing ключом. Все сессии и их сохраненные копии
+    /// удаляются, одноразовые prekey нужно опубликовать заново. Возвращает
+    /// `ProtocolMessage::IdentityRotation` для рассылки контактам.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn rotate_identity(&mut self) -> Result<ProtocolMessage> {
+        self.rotate_identity_async().await
+    }
+
+    /// Сменить собственный identity ключ (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn rotate_identity(&mut self) -> Result<ProtocolMessage> {
+        complete_now(self.rotate_identity_async())
+    }
+
+    async fn rotate_identity_async(&mut self) -> Result<ProtocolMessage> {
+        use base64::{engine::general_purpose, Engine as _};
+        use crate::protocol::messages::RegistrationBundle;
+
+        let user_id = self.require_user_id()?.to_string();
+        let signature_over_old = self.crypto_manager.rotate_identity()?;
+        self.persist_private_keys(&user_id).await?;
+        self.delete_stored_sessions(|_| true).await?;
+
+        let bundle = self.crypto_manager.export_registration_bundle_b64()?;
+        let message = ProtocolMessage::IdentityRotation {
+            new_bundle: RegistrationBundle {
+                identity_public: bundle.identity_public,
+                signed_prekey_public: bundle.signed_prekey_public,
+                signature: bundle.signature,
+                verifying_key: bundle.verifying_key,
+                suite_id: bundle.suite_id,
+                signed_prekey_id: Some(bundle.signed_prekey_id),
+            },
+            signature_over_old: general_purpose::STANDARD.encode(signature_over_old),
+        };
+        crate::protocol::validation::validate_protocol_message(&message)?;
+        Ok(message)
+    }
+
+    /// Применить `ProtocolMessage::IdentityRotation` контакта
+    ///
+    /// Подпись нового bundle проверяется verifying ключом из сохраненной карточки
+    /// контакта. Подпись старым ключом - только подсказка: украденный ключ дает
+    /// такую же. Поэтому при успехе карточка получает новый bundle, но смена
+    /// ключа ждет сверки safety number (`AppEvent::IdentityKeyMismatch`,
+    /// `acknowledge_key_change`), а сессия с контактом сбрасывается
+    /// (`AppEvent::SessionReset`). До подтверждения новая сессия не создается.
+    /// Поддельная подпись отклоняется, карточка и сессия не меняются.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn handle_identity_rotation(&mut self, contact_id: &str, message: &ProtocolMessage) -> Result<()> {
+        self.handle_identity_rotation_async(contact_id, message).await
+    }
+
+    /// Применить `ProtocolMessage::IdentityRotation` контакта (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn handle_identity_rotation(&mut self, contact_id: &str, message: &ProtocolMessage) -> Result<()> {
+        complete_now(self.handle_identity_rotation_async(contact_id, message))
+    }
+
+    async fn handle_identity_rotation_async(&mut self, contact_id: &str, message: &ProtocolMessage) -> Result<()> {
+        use base64::{engine::general_purpose, Engine as _};
+
+        crate::protocol::validation::validate_protocol_message(message)?;
+        let ProtocolMessage::IdentityRotation { new_bundle, signature_over_old } = message else {
+            return Err(ConstructError::ValidationError("Expected IdentityRotation message".to_string()));
+        };
+        let old_verifying_key = self
+            .contact_manager
+            .get_contact(contact_id)
+            .and_then(|c| c.public_key_bundle.as_ref())
+            .map(|bundle| bundle.verifying_key.clone())
+            .ok_or_else(|| ConstructError::NotFound(format!("No key bundle for contact: {}", contact_id)))?;
+        let decode = |field: &str, value: &str| {
+            general_purpose::STANDARD
+                .decode(value)
+                .map_err(|e| ConstructError::ValidationError(format!("Invalid {}: {}", field, e)))
+        };
+        let old_verifying_key = decode("verifying key", &old_verifying_key)?;
+        let signature = decode("rotation signature", signature_over_old)?;
+
+        let bundle = KeyBundle::try_from(&PublicKeyBundleData {
+            user_id: contact_id.to_string(),
+            identity_public: new_bundle.identity_public.clone(),
+            signed_prekey_public: new_bundle.signed_prekey_public.clone(),
+            signature: new_bundle.signature.clone(),
+            verifying_key: new_bundle.verifying_key.clone(),
+            suite_id: Some(new_bundle.suite_id.clone()),
+            one_time_prekey_public: None,
+            one_time_prekey_id: None,
+            signed_prekey_id: new_bundle.signed_prekey_id,
+        })?;
+        bundle.verify::<P>()?;
+
+        let signed_data = crate::crypto::keys::identity_rotation_signed_data(&crate::crypto::RegistrationBundle {
+            identity_public: bundle.identity_public,
+            signed_prekey_public: bundle.signed_prekey_public,
+            signature: bundle.signature,
+            verifying_key: bundle.verifying_key,
+            suite_id: bundle.suite_id,
+        });
+        P::verify(&P::signature_public_key_from_bytes(old_verifying_key), &signed_data, &signature)
+            .map_err(|_| ConstructError::CryptoError("Identity rotation signature is invalid".to_string()))?;
+
+        self.update_contact_bundle_async(
+            contact_id,
+            PublicKeyBundle {
+                identity_public: new_bundle.identity_public.clone(),
+                signed_prekey_public: new_bundle.signed_prekey_public.clone(),
+                signature: new_bundle.signature.clone(),
+                verifying_key: new_bundle.verifying_key.clone(),
+                signed_prekey_id: new_bundle.signed_prekey_id,
+            },
+        )
+        .await?;
+        self.handle_peer_reset(contact_id).await
+    }
+
+    // === Повторная отправка потерянных сообщений ===
+
+    /// Запросить у собеседника повторную отправку сообщений с указанными номерами
+    /// из его цепочки с DH ключом `ratchet_dh_public`
+    pub fn request_resend(
+        &self,
+        contact_id: &str,
+        ratchet_dh_public: Vec<u8>,
+        message_numbers: Vec<u32>,
+    ) -> Result<()> {
+        let request = ClientMessage::RequestResend(RequestResendData {
+            from: self.require_user_id()?.to_string(),
+            to: contact_id.to_string(),
+            ratchet_dh_public,
+            message_numbers,
+        });
+        crate::protocol::validation::validate_client_message(&request)?;
+
+        self.send_to_server(&request)
+    }
+
+    /// Переотправить запрошенные сообщения без изменений (тот же ciphertext).
+    /// Переотправляются только сообщения, отправленные текущим пользователем запрашивающему
+    fn resend_stored(&self, request: &RequestResendData, stored: Vec<StoredMessage>) -> Result<usize> {
+        let user_id = self.require_user_id()?;
+        if request.to != user_id {
+            return Err(ConstructError::ValidationError(
+                "Resend request is addressed to another user".to_string(),
+            ));
+        }
+
+        let mut resent = 0;
+        for msg in stored {
+            if msg.from != user_id || msg.to != request.from {
+                continue;
+            }
+            let Some(header) = msg.ratchet_header else {
+                continue;
+            };
+            // Номера сообщений повторяются в каждой цепочке - сверяется и ее DH ключ
+            if header.ratchet_dh_public != request.ratchet_dh_public
+                || !request.message_numbers.contains(&header.message_number)
+            {
+                continue;
+            }
+
+            self.send_to_server(&ClientMessage::SendMessage(ChatMessage {
+                id: msg.id,
+                from: msg.from,
+                to: msg.to,
+                ratchet_dh_public: header.ratchet_dh_public,
+                message_number: header.message_number,
+                content: msg.encrypted_content,
+                timestamp: msg.timestamp as u64,
+                seq: None,
+            }))?;
+            resent += 1;
+        }
+
+        Ok(resent)
+    }
+
+    /// Обработать запрос повторной отправки от собеседника
+    async fn handle_resend_request(&self, request: &RequestResendData) -> Result<usize> {
+        let stored = self
+            .storage
+            .load_messages_for_conversation(&request.from, usize::MAX, 0)
+            .await?;
+        self.resend_stored(request, stored)
+    }
+
+    // === Обработка сообщений сервера ===
+
+    /// Обработать сообщение от сервера
+    #[cfg(target_arch = "wasm32")]
+    pub async fn handle_server_message(&mut self, message: ServerMessage) -> Result<()> {
+        self.handle_server_message_async(message).await
+    }
+
+    /// Обработать сообщение от сервера (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn handle_server_message(&mut self, message: ServerMessage) -> Result<()> {
+        complete_now(self.handle_server_message_async(message))
+    }
+
+    async fn handle_server_message_async(&mut self, message: ServerMessage) -> Result<()> {
+        crate::protocol::validation::validate_server_message(&message)?;
+
+        match message {
+            ServerMessage::BackupDownloadResponse(data) => {
+                self.pending_backup = Some(data);
+            }
+            ServerMessage::RequestResend(data) => {
+                self.handle_resend_request(&data).await?;
+            }
+            ServerMessage::RegisterResponse(data) => self.apply_register_response(data),
+            ServerMessage::LoginResponse(data) => self.apply_login_response(data),
+            ServerMessage::Ack(data) => {
+                self.handle_ack(&data).await?;
+            }
+            ServerMessage::PublicKeyBundle(data) => {
+                self.on_key_bundle_response_async(data).await?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Обработать Ack сервера. Повторный Ack (ретрансляция) не меняет состояние
+    /// и не порождает повторного события. Возвращает, был ли Ack применен
+    async fn handle_ack(&mut self, data: &AckData) -> Result<bool> {
+        let Some(status) = self.new_ack_status(data) else {
+            return Ok(false);
+        };
+        let stored = self
+            .storage
+            .update_message_status(&data.message_id, status)
+            .await?;
+        // Ack запоминается только после записи статуса: иначе при ошибке хранилища
+        // повтор Ack был бы отброшен, а Ack неизвестных сообщений копились бы в памяти
+        if stored.is_some() {
+            self.acked_messages.insert(data.message_id.clone(), status);
+        }
+        self.apply_ack_status(&data.message_id, status, stored);
+        Ok(true)
+    }
+
+    /// Статус из Ack; `None`, если сообщение уже подтверждено этим или более поздним статусом
+    fn new_ack_status(&self, data: &AckData) -> Option<MessageStatus> {
+        fn rank(status: MessageStatus) -> u8 {
+            match status {
+                MessageStatus::Read => 3,
+                MessageStatus::Delivered => 2,
+                _ => 1,
+            }
+        }
+
+        let status = match data.status.as_str() {
+            "read" => MessageStatus::Read,
+            "delivered" => MessageStatus::Delivered,
+            // "queued" и прочие - сервер принял сообщение
+            _ => MessageStatus::Sent,
+        };
+
+        if let Some(previous) = self.acked_messages.get(&data.message_id) {
+            if rank(*previous) >= rank(status) {
+                return None;
+            }
+        }
+        Some(status)
+    }
+
+    fn apply_ack_status(
+        &mut self,
+        message_id: &str,
+        status: MessageStatus,
+        stored: Option<StoredMessage>,
+    ) {
+        if let Some(stored) = stored {
+            if let Some(cache) = self.message_cache.get_mut(&stored.conversation_id) {
+                for msg in cache.iter_mut().filter(|m| m.id == message_id) {
+                    msg.status = status;
+                }
+            }
+            if let Some(conversation) = self.conversations_manager.get_mut(&stored.conversation_id) {
+                conversation.update_message_status(message_id, status);
+            }
+        }
+
+        self.events.push(AppEvent::MessageStatusChanged {
+            message_id: message_id.to_string(),
+            status,
+        });
+    }
+
+    /// Применить ответ сервера на Register: при успехе запомнить назначенный user_id.
+    /// Ключи сохраняются позже в `finalize_registration`, которому нужен пароль
+    fn apply_register_response(&mut self, data: RegisterResponseData) {
+        match data.user_id {
+            Some(user_id) if data.success => {
+                self.user_id = Some(user_id);
+                self.ui_state.clear_error();
+            }
+            _ => self.ui_state.set_error(data.error.unwrap_or_default()),
+        }
+    }
+
+    /// Применить ответ сервера на Login
+    fn apply_login_response(&mut self, data: LoginResponseData) {
+        if data.success {
+            self.ui_state.clear_error();
+        } else {
+            self.ui_state.set_error(data.error.unwrap_or_default());
+        }
+    }
+
+    // === Квитанции о прочтении ===
+
+    /// Квитанция для собеседника: мы прочитали его сообщения `message_ids`
+    pub fn read_receipt(&self, message_ids: Vec<String>) -> Result<ProtocolMessage> {
+        let receipt = ProtocolMessage::ReadReceipt {
+            message_ids,
+            conversation_id: self.require_user_id()?.to_string(),
+            timestamp: current_timestamp(),
+        };
+        crate::protocol::validation::validate_protocol_message(&receipt)?;
+        Ok(receipt)
+    }
+
+    /// Применить квитанцию о прочтении: наши сообщения собеседнику переходят в `Read`
+    ///
+    /// Учитываются только исходящие сообщения в беседе `conversation_id`,
+    /// остальные id (чужие, неизвестные, уже прочитанные) пропускаются.
+    /// Возвращает количество обновленных сообщений.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn handle_read_receipt(&mut self, receipt: &ProtocolMessage) -> Result<usize> {
+        self.handle_read_receipt_async(receipt).await
+    }
+
+    /// Применить квитанцию о прочтении (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn handle_read_receipt(&mut self, receipt: &ProtocolMessage) -> Result<usize> {
+        complete_now(self.handle_read_receipt_async(receipt))
+    }
+
+    async fn handle_read_receipt_async(&mut self, receipt: &ProtocolMessage) -> Result<usize> {
+        crate::protocol::validation::validate_protocol_message(receipt)?;
+        let ProtocolMessage::ReadReceipt { message_ids, conversation_id, .. } = receipt else {
+            return Err(ConstructError::ValidationError("Expected ReadReceipt message".to_string()));
+        };
+
+        let mut updated = 0;
+        for message_id in message_ids {
+            let outgoing = self
+                .storage
+                .load_message(message_id)
+                .await?
+                .is_some_and(|msg| msg.conversation_id == *conversation_id && msg.to == *conversation_id);
+            if !outgoing {
+                continue;
+            }
+
+            let ack = AckData {
+                message_id: message_id.clone(),
+                status: "read".to_string(),
+            };
+            if self.handle_ack(&ack).await? {
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    // === Индикатор набора текста ===
+
+    /// Сообщение для собеседника: мы набираем текст (или перестали)
+    pub fn typing_indicator(&self, is_typing: bool) -> Result<ProtocolMessage> {
+        let message = ProtocolMessage::Typing {
+            conversation_id: self.require_user_id()?.to_string(),
+            is_typing,
+        };
+        crate::protocol::validation::validate_protocol_message(&message)?;
+        Ok(message)
+    }
+
+    /// Применить `ProtocolMessage::Typing` от собеседника. Индикатор гаснет сам
+    /// через `TYPING_TIMEOUT_SECONDS`, если отправитель не повторит его раньше
+    pub fn handle_typing(&mut self, message: &ProtocolMessage) -> Result<()> {
+        self.handle_typing_at(message, current_timestamp())
+    }
+
+    fn handle_typing_at(&mut self, message: &ProtocolMessage, now: i64) -> Result<()> {
+        crate::protocol::validation::validate_protocol_message(message)?;
+        let ProtocolMessage::Typing { conversation_id, is_typing } = message else {
+            return Err(ConstructError::ValidationError("Expected Typing message".to_string()));
+        };
+        if self.notification_decision(conversation_id) == NotificationDecision::Drop {
+            return Ok(());
+        }
+
+        if *is_typing {
+            self.typing_until
+                .insert(conversation_id.clone(), now + TYPING_TIMEOUT_SECONDS);
+        } else {
+            self.typing_until.remove(conversation_id);
+        }
+        self.conversations_manager
+            .get_or_create(conversation_id)
+            .set_typing(*is_typing);
+        Ok(())
+    }
+
+    /// Набирает ли собеседник текст прямо сейчас
+    pub fn is_typing(&self, contact_id: &str) -> bool {
+        self.is_typing_at(contact_id, current_timestamp())
+    }
+
+    fn is_typing_at(&self, contact_id: &str, now: i64) -> bool {
+        self.typing_until
+            .get(contact_id)
+            .is_some_and(|until| now < *until)
+    }
+
+    /// Погасить истекшие индикаторы (в том числе флаг `is_typing` бесед)
+    pub fn expire_typing(&mut self) {
+        self.expire_typing_at(current_timestamp());
+    }
+
+    fn expire_typing_at(&mut self, now: i64) {
+        let expired: Vec<String> = self
+            .typing_until
+            .iter()
+            .filter(|(_, until)| now >= **until)
+            .map(|(conversation_id, _)| conversation_id.clone())
+            .collect();
+        for conversation_id in expired {
+            self.typing_until.remove(&conversation_id);
+            if let Some(conversation) = self.conversations_manager.get_mut(&conversation_id) {
+                conversation.set_typing(false);
+            }
+        }
+    }
+
+    // === Групповые сообщения (sender keys) ===
+
+    /// Создать группу `group_id` с участниками `members` (user id).
+    /// Каждый участник создает группу у себя с тем же id; своя цепочка
+    /// рассылается участникам перед первым сообщением
+    pub fn create_group(&mut self, group_id: &str, members: Vec<String>) -> Result<()> {
+        crate::protocol::validation::validate_uuid(group_id)?;
+        let user_id = self.require_user_id()?.to_string();
+        let group = GroupSession::new(group_id, members.into_iter().filter(|member| *member != user_id))
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+        self.crypto_manager.session_manager_mut().add_group_session(group);
+        Ok(())
+    }
+
+    /// Добавить участника; он получит нашу цепочку перед следующим сообщением
+    pub fn add_group_member(&mut self, group_id: &str, member_id: &str) -> Result<()> {
+        self.group_mut(group_id)?.add_member(member_id);
+        Ok(())
+    }
+
+    /// Удалить участника; наша цепочка заменяется и рассылается остальным заново
+    pub fn remove_group_member(&mut self, group_id: &str, member_id: &str) -> Result<()> {
+        self.group_mut(group_id)?
+            .remove_member(member_id)
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))
+    }
+
+    /// Участники группы (без себя)
+    pub fn group_members(&self, group_id: &str) -> Result<Vec<String>> {
+        self.crypto_manager
+            .session_manager()
+            .get_group_session(group_id)
+            .map(|group| group.members())
+            .ok_or_else(|| ConstructError::NotFound(format!("Group not found: {}", group_id)))
+    }
+
+    fn group_mut(&mut self, group_id: &str) -> Result<&mut GroupSession<P>> {
+        self.crypto_manager
+            .session_manager_mut()
+            .get_group_session_mut(group_id)
+            .ok_or_else(|| ConstructError::NotFound(format!("Group not found: {}", group_id)))
+    }
+
+    /// Отправить сообщение в группу
+    ///
+    /// Участникам, у которых еще нет нашей цепочки, она уходит попарно через их
+    /// Double Ratchet сессии. Возвращает `ProtocolMessage::GroupMessage` - один
+    /// шифртекст, который доставляется каждому участнику группы.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn send_group_message(&mut self, group_id: &str, plaintext: &str) -> Result<ProtocolMessage> {
+        self.send_group_message_async(group_id, plaintext).await
+    }
+
+    /// Отправить сообщение в группу (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn send_group_message(&mut self, group_id: &str, plaintext: &str) -> Result<ProtocolMessage> {
+        complete_now(self.send_group_message_async(group_id, plaintext))
+    }
+
+    async fn send_group_message_async(&mut self, group_id: &str, plaintext: &str) -> Result<ProtocolMessage> {
+        let user_id = self.require_user_id()?.to_string();
+        let pending = self.group_mut(group_id)?.pending_distribution();
+        if !pending.is_empty() && !self.transport_connected() {
+            return Err(ConstructError::NetworkError(
+                "Sender key distribution requires a server connection".to_string(),
+            ));
+        }
+        for member_id in pending {
+            self.send_sender_key(&member_id, group_id).await?;
+        }
+
+        let body = MessageBody::new_text(plaintext);
+        let message = self
+            .group_mut(group_id)?
+            .encrypt(&body.to_plaintext()?)
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+
+        let stored = StoredMessage {
+            id: crate::utils::uuid::generate_v4(),
+            conversation_id: group_id.to_string(),
+            from: user_id.clone(),
+            to: group_id.to_string(),
+            encrypted_content: crate::utils::b64::encode(&message.ciphertext),
+            timestamp: current_timestamp(),
+            status: MessageStatus::Sent,
+            local_content: Some(self.seal_local_body(&body)?),
+            ratchet_header: None,
+        };
+        self.storage.save_message(stored.clone()).await?;
+        self.conversations_manager.add_message(group_id, stored.clone());
+        self.update_message_cache(group_id, stored);
+
+        Ok(ProtocolMessage::GroupMessage {
+            sender_id: user_id,
+            message,
+        })
+    }
+
+    /// Обработать `ProtocolMessage::GroupMessage` участника
+    ///
+    /// Возвращает id сохраненного сообщения. Если цепочки отправителя нет
+    /// (например, мы вступили позже), у него попарно запрашивается свежая
+    /// цепочка, а сообщение пропускается - `Ok(None)`: старые сообщения
+    /// новой цепочкой не расшифровать.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn receive_group_message(&mut self, message: &ProtocolMessage) -> Result<Option<String>> {
+        self.receive_group_message_async(message).await
+    }
+
+    /// Обработать сообщение в группу (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn receive_group_message(&mut self, message: &ProtocolMessage) -> Result<Option<String>> {
+        complete_now(self.receive_group_message_async(message))
+    }
+
+    async fn receive_group_message_async(&mut self, message: &ProtocolMessage) -> Result<Option<String>> {
+        crate::protocol::validation::validate_protocol_message(message)?;
+        let ProtocolMessage::GroupMessage { sender_id, message } = message else {
+            return Err(ConstructError::ValidationError("Expected GroupMessage".to_string()));
+        };
+        let group_id = message.group_id.as_str();
+        let user_id = self.require_user_id()?.to_string();
+
+        let decision = self.notification_policy.decide(&NotificationContext {
+            contact_id: sender_id,
+            contact: self.contact_manager.get_contact(sender_id),
+            active_conversation: self.active_conversation.as_deref() == Some(group_id),
+        });
+        if decision == NotificationDecision::Drop {
+            return Ok(None);
+        }
+
+        let group = self.group_mut(group_id)?;
+        if !group.is_member(sender_id) {
+            return Err(ConstructError::ValidationError(format!(
+                "{} is not a member of group {}",
+                sender_id, group_id
+            )));
+        }
+        if !group.has_sender_key(sender_id) {
+            let request = MessageBody::SenderKeyRequest {
+                group_id: group_id.to_string(),
+            };
+            self.send_control_body(sender_id, &request).await?;
+            return Ok(None);
+        }
+        let plaintext = group
+            .decrypt(sender_id, message)
+            .map_err(|e| ConstructError::CryptoError(e.to_string()))?;
+        let body = MessageBody::from_plaintext(&plaintext)?;
+
+        let stored = StoredMessage {
+            id: crate::utils::uuid::generate_v4(),
+            conversation_id: group_id.to_string(),
+            from: sender_id.clone(),
+            to: user_id,
+            encrypted_content: crate::utils::b64::encode(&message.ciphertext),
+            timestamp: current_timestamp(),
+            status: MessageStatus::Delivered,
+            local_content: Some(self.seal_local_body(&body)?),
+            ratchet_header: None,
+        };
+        let message_id = stored.id.clone();
+        self.storage.save_message(stored.clone()).await?;
+        self.conversations_manager.add_message(group_id, stored.clone());
+        self.update_message_cache(group_id, stored);
+
+        if matches!(decision, NotificationDecision::Notify | NotificationDecision::Silent) {
+            self.conversations_manager.get_or_create(group_id).increment_unread();
+        }
+        if decision == NotificationDecision::Notify {
+            self.events.push(AppEvent::MessageReceived {
+                conversation_id: group_id.to_string(),
+                message_id: message_id.clone(),
+            });
+        }
+        Ok(Some(message_id))
+    }
+
+    /// Разослать участнику нашу текущую цепочку группы
+    async fn send_sender_key(&mut self, member_id: &str, group_id: &str) -> Result<()> {
+        let distribution = self.group_mut(group_id)?.create_sender_key_distribution_message();
+        self.send_control_body(member_id, &MessageBody::SenderKeyDistribution { distribution })
+            .await?;
+        self.group_mut(group_id)?.mark_distributed(member_id);
+        Ok(())
+    }
+
+    /// Отправить служебное тело сообщения через попарную сессию с контактом
+    async fn send_control_body(&mut self, contact_id: &str, body: &MessageBody) -> Result<()> {
+        let user_id = self.require_user_id()?.to_string();
+        self.ensure_session_restored(contact_id).await?;
+        let session_id = self.resolve_sending_session(contact_id, None)?;
+        let timestamp = crate::utils::time::now();
+        let encrypted = self.crypto_manager.encrypt_body_at(&session_id, body, timestamp)?;
+        let chat_msg = ChatMessage::from_encrypted_at(&user_id, contact_id, &encrypted, timestamp)?;
+        self.send_to_server(&ClientMessage::SendMessage(chat_msg))
+    }
+
+    /// Принять цепочку участника, присланную попарно
+    fn handle_sender_key_distribution(
+        &mut self,
+        sender_id: &str,
+        distribution: &SenderKeyDistributionMessage,
+    ) -> Result<()> {
+        self.group_mut(&distribution.group_id)?
+            .process_distribution(sender_id, distribution)
+            .map_err(|e| ConstructError::ValidationError(e.to_string()))
+    }
+
+    /// Участник просит нашу цепочку (вступил позже или потерял состояние)
+    async fn handle_sender_key_request(&mut self, sender_id: &str, group_id: &str) -> Result<()> {
+        if !self.group_mut(group_id)?.is_member(sender_id) {
+            return Err(ConstructError::ValidationError(format!(
+                "{} is not a member of group {}",
+                sender_id, group_id
+            )));
+        }
+        self.send_sender_key(sender_id, group_id).await
+    }
+
+    // === Геттеры для UI ===
+
+    pub fn get_user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    pub fn get_username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+
+    pub fn ui_state(&self) -> &UiState {
+        &self.ui_state
+    }
+
+    pub fn ui_state_mut(&mut self) -> &mut UiState {
+        &mut self.ui_state
+    }
+
+    /// Забрать накопленные события
+    pub fn take_events(&mut self) -> Vec<AppEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn crypto_manager(&self) -> &CryptoCore<P> {
+        &self.crypto_manager
+    }
+
+    pub fn crypto_manager_mut(&mut self) -> &mut CryptoCore<P> {
+        &mut self.crypto_manager
+    }
+
+    pub fn conversations_manager(&self) -> &ConversationsManager {
+        &self.conversations_manager
+    }
+
+    pub fn conversations_manager_mut(&mut self) -> &mut ConversationsManager {
+        &mut self.conversations_manager
+    }
+
+    // === Обслуживание ===
+
+    /// Периодическое обслуживание: удаляет signed prekey старше срока хранения,
+    /// даже если ротация давно не выполнялась
+    pub fn run_maintenance(&mut self) -> MaintenanceReport {
+        let purged_prekeys = self
+            .crypto_manager
+            .key_manager_mut()
+            .purge_expired_prekeys(DEFAULT_PREKEY_MAX_AGE_SECONDS);
+
+        MaintenanceReport { purged_prekeys }
+    }
+
+    /// Ротировать signed prekey, если ему не меньше `DEFAULT_PREKEY_ROTATION_SECONDS`
+    ///
+    /// Новый регистрационный bundle уходит на сервер (`ProtocolMessage::UpdatePrekey`
+    /// в `ClientMessage::RotatePrekey`); старый prekey остается в истории для X3DH
+    /// по уже выданным bundle. Без соединения ротация откладывается, чтобы сервер
+    /// не остался со старым prekey. Возвращает, была ли ротация.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn maybe_rotate_prekey(&mut self) -> Result<bool> {
+        self.maybe_rotate_prekey_async().await
+    }
+
+    /// Ротировать signed prekey, если пора (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn maybe_rotate_prekey(&mut self) -> Result<bool> {
+        complete_now(self.maybe_rotate_prekey_async())
+    }
+
+    async fn maybe_rotate_prekey_async(&mut self) -> Result<bool> {
+        use base64::{engine::general_purpose, Engine as _};
+        use crate::protocol::messages::{RegistrationBundle, RotatePrekeyData};
+
+        if !self
+            .crypto_manager
+            .key_manager()
+            .should_rotate_prekey(DEFAULT_PREKEY_ROTATION_SECONDS)
+        {
+            return Ok(false);
+        }
+        let user_id = self.require_user_id()?.to_string();
+        if !self.transport_connected() {
+            return Ok(false);
+        }
+
+        self.crypto_manager.rotate_prekey()?;
+        self.persist_private_keys(&user_id).await?;
+
+        let bundle = self.crypto_manager.export_registration_bundle_b64()?;
+        let update = ProtocolMessage::UpdatePrekey(RegistrationBundle {
+            identity_public: bundle.identity_public,
+            signed_prekey_public: bundle.signed_prekey_public,
+            signature: bundle.signature,
+            verifying_key: bundle.verifying_key,
+            suite_id: bundle.suite_id,
+            signed_prekey_id: Some(bundle.signed_prekey_id),
+        });
+        crate::protocol::validation::validate_protocol_message(&update)?;
+        let update = rmp_serde::to_vec_named(&update)
+            .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize prekey update: {}", e)))?;
+        self.send_to_server(&ClientMessage::RotatePrekey(RotatePrekeyData {
+            user_id,
+            update: general_purpose::STANDARD.encode(update),
+        }))?;
+        Ok(true)
+    }
+
+    /// Пополнить запас одноразовых prekey на сервере, если их осталось меньше
+    /// `MIN_ONE_TIME_PREKEYS`. Новые приватные ключи сохраняются (зашифрованными
+    /// мастер-ключом) до отправки публичных. Без соединения пополнение откладывается.
+    /// Возвращает количество опубликованных prekey
+    #[cfg(target_arch = "wasm32")]
+    pub async fn maybe_replenish_one_time_prekeys(&mut self) -> Result<usize> {
+        self.maybe_replenish_one_time_prekeys_async().await
+    }
+
+    /// Пополнить запас одноразовых prekey, если пора (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn maybe_replenish_one_time_prekeys(&mut self) -> Result<usize> {
+        complete_now(self.maybe_replenish_one_time_prekeys_async())
+    }
+
+    async fn maybe_replenish_one_time_prekeys_async(&mut self) -> Result<usize> {
+        use base64::{engine::general_purpose, Engine as _};
+        use crate::protocol::messages::{OneTimePrekeyData, UploadOneTimePrekeysData};
+
+        let available = self.crypto_manager.key_manager().one_time_prekeys_count();
+        if available >= MIN_ONE_TIME_PREKEYS {
+            return Ok(0);
+        }
+        let user_id = self.require_user_id()?.to_string();
+        if !self.transport_connected() {
+            return Ok(0);
+        }
+
+        let generated = self
+            .crypto_manager
+            .generate_one_time_prekeys(TARGET_ONE_TIME_PREKEYS - available)?;
+        self.persist_private_keys(&user_id).await?;
+
+        let message = ClientMessage::UploadOneTimePrekeys(UploadOneTimePrekeysData {
+            user_id,
+            prekeys: generated
+                .iter()
+                .map(|prekey| OneTimePrekeyData {
+                    key_id: prekey.key_id,
+                    public_key: general_purpose::STANDARD.encode(&prekey.public_key),
+                    signature: general_purpose::STANDARD.encode(&prekey.signature),
+                })
+                .collect(),
+        });
+        crate::protocol::validation::validate_client_message(&message)?;
+        self.send_to_server(&message)?;
+        Ok(generated.len())
+    }
+
+    // === Очистка ===
+
+    /// Очистить все данные
+    #[cfg(target_arch = "wasm32")]
+    pub async fn clear_all_data(&mut self) -> Result<()> {
+        self.clear_all_data_async().await
+    }
+
+    /// Очистить все данные (non-WASM версия)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn clear_all_data(&mut self) -> Result<()> {
+        complete_now(self.clear_all_data_async())
+    }
+
+    async fn clear_all_data_async(&mut self) -> Result<()> {
+        // Очистить кеши
+        self.message_cache.clear();
+        self.plaintext_cache.borrow_mut().clear();
+        self.acked_messages.clear();
+        self.last_seq.clear();
+        self.observed_key_digests.clear();
+        self.reported_key_mismatches.clear();
+        self.pending_rekey.clear();
+        self.outbound_queue.clear();
+        self.typing_until.clear();
+        self.conversations_manager.clear_all();
+        self.contact_manager.clear_all();
+        self.storage.clear_all().await?;
+
+        // Сбросить состояние
+        self.user_id = None;
+        self.username = None;
+        self.master_key = None;
+        self.storage.clear_at_rest_key();
+        self.active_conversation = None;
+        self.connection_state = ConnectionState::Disconnected;
+
+        Ok(())
+    }
+
+    // === Регистрация на сервере ===
+
+    /// Зарегистрировать пользователя на сервере
+    /// Отправляет сообщение Register с username, password и registration bundle
+    #[cfg(target_arch = "wasm32")]
+    pub fn register_on_server(&self, password: String) -> Result<()> {
+        use crate::protocol::messages::{ClientMessage, RegisterData};
+
+        // 1. Проверить, что пользователь инициализирован
+        let username = self.username.as_ref()
+            .ok_or_else(|| ConstructError::InvalidInput(
+                "User not initialized. Call initialize_user first.".to_string()
+            ))?;
+
+        // 2. Проверить, что есть transport
+        let transport = self.transport.as_ref()
+            .ok_or_else(|| ConstructError::NetworkError(
+                "Not connected to server. Call connect first.".to_string()
+            ))?;
+
+        // 3. Получить registration bundle в base64
+        let bundle = self.crypto_manager.export_registration_bundle_b64()?;
+
+        // 4. Сериализовать bundle в JSON для public_key поля
+        let public_key = serde_json::to_string(&bundle)
+            .map_err(|e| ConstructError::SerializationError(
+                format!("Failed to serialize registration bundle: {}", e)
+            ))?;
+
+        // 5. Создать RegisterData
+        let register_data = RegisterData {
+            username: username.clone(),
+            display_name: username.clone(), // Используем username как display_name
+            password,
+            public_key,
+        };
+
+        // 6. Отправить через transport
+        let message = ClientMessage::Register(register_data);
+        transport.send(&message)?;
+
+        Ok(())
+    }
+
+    /// Зарегистрировать пользователя на сервере (non-WASM заглушка)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn register_on_server(&self, _password: String) -> Result<()> {
+        Err(ConstructError::NetworkError(
+            "Registration only available in WASM".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::classic_suite::ClassicSuiteProvider;
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_app_state_creation() {
+        let state = AppState::<ClassicSuiteProvider>::new("test_db");
+        assert!(state.is_ok());
+
+        let state = state.unwrap();
+        assert!(state.get_user_id().is_none());
+        assert_eq!(state.connection_state(), ConnectionState::Disconnected);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_app_state_initialize_user() {
+        let mut state = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        state
+            .initialize_user("alice".to_string(), "testpass123".to_string())
+            .unwrap();
+
+        assert_eq!(state.get_username(), Some("alice"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_app_state_contacts() {
+        let mut state = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        state
+            .initialize_user("alice".to_string(), "testpass123".to_string())
+            .unwrap();
+
+        state
+            .add_contact("contact1".to_string(), "bob".to_string())
+            .unwrap();
+
+        let contacts = state.get_contacts();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].username, "bob");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[derive(Clone, Default)]
+    struct MockTransport {
+        sent: std::rc::Rc<std::cell::RefCell<Vec<ClientMessage>>>,
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    impl Transport for MockTransport {
+        fn send(&self, message: &ClientMessage) -> Result<()> {
+            self.sent.borrow_mut().push(message.clone());
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            true
+        }
+    }
+
+    /// Транспорт, который можно "уронить" в переподключение
+    #[cfg(not(target_arch = "wasm32"))]
+    #[derive(Clone, Default)]
+    struct FlakyTransport {
+        reconnecting: std::rc::Rc<std::cell::Cell<bool>>,
+        policy: std::rc::Rc<RefCell<Option<ReconnectPolicy>>>,
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    impl Transport for FlakyTransport {
+        fn send(&self, _message: &ClientMessage) -> Result<()> {
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            !self.reconnecting.get()
+        }
+
+        fn state(&self) -> TransportState {
+            if self.reconnecting.get() {
+                TransportState::Reconnecting
+            } else {
+                TransportState::Connected
+            }
+        }
+
+        fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+            *self.policy.borrow_mut() = Some(policy);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn registered_state(user_id: &str, password: &str) -> AppState<ClassicSuiteProvider> {
+        let mut state = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        state
+            .initialize_user("alice".to_string(), password.to_string())
+            .unwrap();
+        state
+            .finalize_registration(user_id.to_string(), "token".to_string(), password.to_string())
+            .unwrap();
+        state
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_export_conversation() {
+        let mut state = registered_state("alice_id", "testpass123");
+        assert!(state.is_unlocked());
+
+        for (id, from, to, timestamp, text) in [
+            ("m2", "bob_id", "alice_id", 200, "hi alice"),
+            ("m1", "alice_id", "bob_id", 100, "hi bob"),
+        ] {
+            let local_content = Some(state.seal_local_content(text).unwrap());
+            state
+                .storage
+                .save_message(StoredMessage {
+                    id: id.to_string(),
+                    conversation_id: "bob_id".to_string(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    encrypted_content: "AQID".to_string(),
+                    timestamp,
+                    status: MessageStatus::Delivered,
+                    local_content,
+                    ratchet_header: None,
+                })
+                .unwrap();
+        }
+
+        // Ciphertext
+        let json = state.export_conversation("bob_id", false, "").unwrap();
+        let exported: Vec<ExportedMessage> = serde_json::from_str(&json).unwrap();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0].id, "m1");
+        assert_eq!(exported[0].direction, MessageDirection::Sent);
+        assert_eq!(exported[1].direction, MessageDirection::Received);
+        assert_eq!(exported[1].timestamp, 200);
+        assert_eq!(exported[0].content.as_deref(), Some("AQID"));
+
+        // Plaintext
+        let json = state.export_conversation("bob_id", true, "testpass123").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[0]["content"], "hi bob");
+        assert_eq!(value[0]["direction"], "sent");
+        assert_eq!(value[1]["content"], "hi alice");
+        assert_eq!(value[1]["direction"], "received");
+
+        // Неверный пароль
+        assert!(state.export_conversation("bob_id", true, "wrongpass123").is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn contact_bundle(bundle: &crate::api::crypto::KeyBundle) -> PublicKeyBundle {
+        use base64::{engine::general_purpose, Engine as _};
+
+        PublicKeyBundle {
+            identity_public: general_purpose::STANDARD.encode(&bundle.identity_public),
+            signed_prekey_public: general_purpose::STANDARD.encode(&bundle.signed_prekey_public),
+            signature: general_purpose::STANDARD.encode(&bundle.signature),
+            verifying_key: general_purpose::STANDARD.encode(&bundle.verifying_key),
+            signed_prekey_id: bundle.signed_prekey_id,
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_identity_key_mismatch_detected() {
+        let mut alice = AppState::<ClassicSuiteProvider>::new("alice_db").unwrap();
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let session_id = alice
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob_bundle)
+            .unwrap();
+
+        // Тот же ключ - расхождения нет
+        assert!(alice
+            .update_contact_bundle("bob_id", contact_bundle(&bob_bundle))
+            .unwrap());
+        alice.contact_manager.set_verified("bob_id", true).unwrap();
+        assert!(alice.take_events().is_empty());
+
+        // Боб переустановил приложение - новый identity ключ, старая сессия жива
+        let bob_reinstalled = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let new_bundle = bob_reinstalled.export_registration_bundle().unwrap();
+        assert!(!alice
+            .update_contact_bundle("bob_id", contact_bundle(&new_bundle))
+            .unwrap());
+
+        assert_eq!(
+            alice.take_events(),
+            vec![AppEvent::IdentityKeyMismatch {
+                contact_id: "bob_id".to_string(),
+                session_id,
+            }]
+        );
+        assert!(!alice.contact_manager.get_contact("bob_id").unwrap().verified);
+        assert!(!alice.storage.load_contact("bob_id").unwrap().unwrap().verified);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_acknowledge_key_change() {
+        let mut alice = AppState::<ClassicSuiteProvider>::new("alice_db").unwrap();
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        alice
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob_bundle)
+            .unwrap();
+        alice
+            .update_contact_bundle("bob_id", contact_bundle(&bob_bundle))
+            .unwrap();
+
+        // Смена ключа не принимается при каждой проверке, но предупреждение о ней одно
+        let reinstalled = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let new_bundle = contact_bundle(&reinstalled.export_registration_bundle().unwrap());
+        assert!(!alice.update_contact_bundle("bob_id", new_bundle.clone()).unwrap());
+        assert!(!alice.check_identity_consistency("bob_id").unwrap());
+        assert_eq!(alice.take_events().len(), 1);
+
+        assert!(!alice.check_identity_consistency("bob_id").unwrap());
+        assert!(alice.take_events().is_empty());
+        alice.acknowledge_key_change("bob_id").unwrap();
+        assert!(alice.take_events().is_empty());
+        assert!(alice.check_identity_consistency("bob_id").unwrap());
+        assert!(alice.update_contact_bundle("bob_id", new_bundle.clone()).unwrap());
+        assert!(alice.take_events().is_empty());
+
+        // Журнал ключей сохраняется вместе с контактом
+        let history = alice.storage.load_contact("bob_id").unwrap().unwrap().identity_key_history;
+        assert_eq!(history.len(), 2);
+        assert!(history[0].accepted_at.is_none());
+        assert_eq!(history[1].identity_public, new_bundle.identity_public);
+        assert!(history[1].accepted_at.is_some());
+
+        // Следующая смена ключа снова требует подтверждения
+        let third = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        assert!(!alice
+            .update_contact_bundle("bob_id", contact_bundle(&third.export_registration_bundle().unwrap()))
+            .unwrap());
+        assert!(matches!(
+            alice.take_events().as_slice(),
+            [AppEvent::IdentityKeyMismatch { contact_id, .. }] if contact_id == "bob_id"
+        ));
+        assert_eq!(alice.contact_manager.get_contact("bob_id").unwrap().identity_key_history.len(), 3);
+
+        // Без bundle принимать нечего
+        alice
+            .add_contact("carol_id".to_string(), "carol".to_string())
+            .unwrap();
+        assert!(alice.acknowledge_key_change("carol_id").is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_identity_key_mismatch_reported_once_per_key() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+        let (mut bob, bob_session, alice_session) = connected_peer(&mut alice, "bob_id");
+        alice.take_events();
+
+        let reinstalled = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let new_bundle = contact_bundle(&reinstalled.export_registration_bundle().unwrap());
+        assert!(!alice.update_contact_bundle("bob_id", new_bundle).unwrap());
+        let mismatches = |events: Vec<AppEvent>| {
+            events
+                .into_iter()
+                .filter(|event| matches!(event, AppEvent::IdentityKeyMismatch { .. }))
+                .count()
+        };
+        assert_eq!(mismatches(alice.take_events()), 1);
+
+        // Сообщения по старой сессии не повторяют предупреждение о том же ключе
+        for _ in 0..3 {
+            let msg = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+            alice.receive_message(msg, &alice_session).unwrap();
+        }
+        assert_eq!(mismatches(alice.take_events()), 0);
+
+        // Другой новый ключ - новое предупреждение
+        let third = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        alice
+            .update_contact_bundle("bob_id", contact_bundle(&third.export_registration_bundle().unwrap()))
+            .unwrap();
+        assert_eq!(mismatches(alice.take_events()), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_key_digest_mismatch_warns() {
+        let mut alice = AppState::<ClassicSuiteProvider>::new("alice_db").unwrap();
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        alice
+            .update_contact_bundle("bob_id", contact_bundle(&bob_bundle))
+            .unwrap();
+
+        // Опубликованный хеш совпадает с bundle, полученным с сервера
+        let digest = alice.key_digest("bob_id").unwrap().unwrap();
+        assert_eq!(
+            digest,
+            ProtocolMessage::KeyDigest {
+                user_id: "bob_id".to_string(),
+                bundle_hash: bob_bundle.content_hash(),
+            }
+        );
+        assert!(alice.observe_key_digest(&digest).unwrap());
+        assert!(alice.take_events().is_empty());
+
+        // Другой клиент видит для того же identity ключа иной bundle
+        let tampered = ProtocolMessage::KeyDigest {
+            user_id: "bob_id".to_string(),
+            bundle_hash: "00".repeat(32),
+        };
+        assert!(!alice.observe_key_digest(&tampered).unwrap());
+        assert_eq!(
+            alice.take_events(),
+            vec![AppEvent::KeyDigestMismatch {
+                contact_id: "bob_id".to_string(),
+                expected: bob_bundle.content_hash(),
+                received: "00".repeat(32),
+            }]
+        );
+
+        // Наблюдавшийся хеш не заменен подмененным
+        assert!(alice.observe_key_digest(&digest).unwrap());
+        assert!(alice.take_events().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_backup_roundtrip_through_server() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+        alice
+            .storage
+            .save_message(StoredMessage {
+                id: "msg1".to_string(),
+                conversation_id: "bob_id".to_string(),
+                from: "alice".to_string(),
+                to: "bob_id".to_string(),
+                encrypted_content: "AQID".to_string(),
+                timestamp: 100,
+                status: MessageStatus::Sent,
+                local_content: None,
+                ratchet_header: None,
+            })
+            .unwrap();
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        alice
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob_bundle)
+            .unwrap();
+        let fingerprint = alice.crypto_manager.fingerprint().unwrap();
+
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        alice.upload_backup("backup_pass1").unwrap();
+
+        // Сервер сохраняет блоб как есть
+        let uploaded = match transport.sent.borrow().last() {
+            Some(ClientMessage::BackupUpload(data)) => data.clone(),
+            other => panic!("expected BackupUpload, got {:?}", other),
+        };
+        assert_eq!(uploaded.version, ARCHIVE_VERSION);
+
+        // Новое устройство запрашивает бэкап
+        let mut restored = AppState::<ClassicSuiteProvider>::new("restored_db").unwrap();
+        let restored_transport = MockTransport::default();
+        restored.set_transport(Box::new(restored_transport.clone()));
+        restored.request_backup_download().unwrap();
+        assert!(matches!(
+            restored_transport.sent.borrow().last(),
+            Some(ClientMessage::BackupDownloadRequest(_))
+        ));
+
+        restored
+            .handle_server_message(ServerMessage::BackupDownloadResponse(
+                BackupDownloadResponseData {
+                    encrypted_blob: uploaded.encrypted_blob,
+                    version: uploaded.version,
+                },
+            ))
+            .unwrap();
+        assert!(restored.has_pending_backup());
+
+        // Неверный пароль не теряет бэкап
+        assert!(restored.restore_from_server_backup("wrong_pass1").is_err());
+        assert!(restored.has_pending_backup());
+
+        restored.restore_from_server_backup("backup_pass1").unwrap();
+        assert!(!restored.has_pending_backup());
+
+        assert_eq!(restored.get_username(), Some("alice"));
+        assert_eq!(restored.get_contacts().len(), 1);
+        assert_eq!(
+            restored
+                .conversations_manager()
+                .get("bob_id")
+                .map(|c| c.message_count()),
+            Some(1)
+        );
+
+        // Identity восстановлена из архива и сохранена под паролем бэкапа
+        assert!(restored.is_unlocked());
+        assert_eq!(restored.get_user_id(), Some("alice_id"));
+        assert_eq!(restored.crypto_manager.fingerprint().unwrap(), fingerprint);
+
+        // Снимок сессии не восстанавливается - новая сессия строится через X3DH
+        assert!(!restored.crypto_manager.has_session("bob_id"));
+        restored
+            .send_message_auto("bob_id", Some(&bob_bundle), "hello after restore")
+            .unwrap();
+        let first = match restored_transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(chat_msg)) => chat_msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        assert!(first.x3dh_ephemeral_key.is_some());
+        let alice_bundle = restored.crypto_manager.export_registration_bundle().unwrap();
+        let bob_session = bob.init_receiving_session("alice_id", &alice_bundle, &first).unwrap();
+        assert_eq!(bob.decrypt_body(&bob_session, &first).unwrap().as_text(), "hello after restore");
+
+        // После перезапуска ключи загружаются из хранилища
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("restored_db").unwrap();
+        restarted.storage = std::mem::take(&mut restored.storage);
+        restarted
+            .load_user("alice_id".to_string(), "backup_pass1".to_string())
+            .unwrap();
+        assert_eq!(restarted.crypto_manager.fingerprint().unwrap(), fingerprint);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_import_archive_version_1() {
+        let contact: StoredContact = (&crate::api::contacts::create_contact(
+            "bob_id".to_string(),
+            "bob".to_string(),
+        ))
+            .into();
+        let legacy = StateArchiveV1 {
+            version: 1,
+            user_id: Some("alice_id".to_string()),
+            username: Some("alice".to_string()),
+            contacts: vec![contact],
+            messages: Vec::new(),
+            sessions: vec![("stale".to_string(), vec![1, 2, 3])],
+            created_at: 100,
+        };
+        let blob = crate::crypto::master_key::encrypt_with_password(
+            "backup_pass1",
+            &crate::utils::serialization::to_bytes(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        // Старый архив без ключей: контакты восстанавливаются, устаревшие сессии нет
+        let mut restored = AppState::<ClassicSuiteProvider>::new("restored_db").unwrap();
+        restored.import_archive("backup_pass1", &blob).unwrap();
+        assert_eq!(restored.get_contacts().len(), 1);
+        assert_eq!(restored.crypto_manager.client().session_count(), 0);
+        assert!(restored.take_events().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_request_resend_reemits_stored_messages() {
+        const ALICE: &str = "550e8400-e29b-41d4-a716-446655440001";
+        const BOB: &str = "550e8400-e29b-41d4-a716-446655440002";
+        const CAROL: &str = "550e8400-e29b-41d4-a716-446655440003";
+
+        let mut alice = registered_state(ALICE, "testpass123");
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+
+        // Три исходящих сообщения Бобу в первой цепочке и одно в следующей,
+        // одно входящее от Боба и одно исходящее Кэрол
+        let (chain, next_chain) = (1u8, 9u8);
+        for (id, from, to, dh, number, content) in [
+            ("m0", ALICE, BOB, chain, 0, "AAAA"),
+            ("m1", ALICE, BOB, chain, 1, "AQEB"),
+            ("m2", ALICE, BOB, chain, 2, "AgIC"),
+            ("n0", ALICE, BOB, next_chain, 0, "CQkJ"),
+            ("r2", BOB, ALICE, chain, 2, "BwcH"),
+            ("c2", ALICE, CAROL, chain, 2, "CAgI"),
+        ] {
+            let conversation_id = if from == ALICE { to } else { from };
+            alice
+                .storage
+                .save_message(StoredMessage {
+                    id: id.to_string(),
+                    conversation_id: conversation_id.to_string(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    encrypted_content: content.to_string(),
+                    timestamp: 100 + number as i64,
+                    status: MessageStatus::Sent,
+                    local_content: None,
+                    ratchet_header: Some(StoredRatchetHeader {
+                        ratchet_dh_public: vec![dh; 32],
+                        message_number: number,
+                    }),
+                })
+                .unwrap();
+        }
+
+        // Номер 0 есть в обеих цепочках - переотправляется только из запрошенной
+        alice
+            .handle_server_message(ServerMessage::RequestResend(RequestResendData {
+                from: BOB.to_string(),
+                to: ALICE.to_string(),
+                ratchet_dh_public: vec![chain; 32],
+                message_numbers: vec![0, 2, 7],
+            }))
+            .unwrap();
+
+        let sent = transport.sent.borrow();
+        let resent: Vec<&ChatMessage> = sent
+            .iter()
+            .map(|m| match m {
+                ClientMessage::SendMessage(chat) => chat,
+                other => panic!("expected SendMessage, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(resent.len(), 2);
+        assert_eq!(resent[0].id, "m0");
+        assert_eq!(resent[0].content, "AAAA");
+        assert_eq!(resent[1].id, "m2");
+        assert_eq!(resent[1].content, "AgIC");
+        assert_eq!(resent[1].message_number, 2);
+        assert_eq!(resent[1].ratchet_dh_public, vec![chain; 32]);
+        assert_eq!(resent[1].to, BOB);
+        drop(sent);
+
+        alice
+            .handle_server_message(ServerMessage::RequestResend(RequestResendData {
+                from: BOB.to_string(),
+                to: ALICE.to_string(),
+                ratchet_dh_public: vec![next_chain; 32],
+                message_numbers: vec![0],
+            }))
+            .unwrap();
+        assert!(matches!(
+            transport.sent.borrow().last(),
+            Some(ClientMessage::SendMessage(chat)) if chat.id == "n0"
+        ));
+        assert_eq!(transport.sent.borrow().len(), 3);
+
+        // Запрос превышает лимит - ничего не отправляется
+        let too_many = ServerMessage::RequestResend(RequestResendData {
+            from: BOB.to_string(),
+            to: ALICE.to_string(),
+            ratchet_dh_public: vec![chain; 32],
+            message_numbers: (0..=crate::protocol::validation::MAX_RESEND_REQUEST as u32).collect(),
+        });
+        assert!(alice.handle_server_message(too_many).is_err());
+        assert_eq!(transport.sent.borrow().len(), 3);
+
+        // Собственный запрос уходит на сервер
+        alice.request_resend(BOB, vec![chain; 32], vec![5]).unwrap();
+        assert!(matches!(
+            transport.sent.borrow().last(),
+            Some(ClientMessage::RequestResend(data))
+                if data.from == ALICE && data.ratchet_dh_public == vec![chain; 32] && data.message_numbers == vec![5]
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_reply_context() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let mut bob = registered_state("bob_id", "testpass123");
+
+        let bob_bundle = bob.crypto_manager().export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager().export_registration_bundle().unwrap();
+        let alice_session = alice
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob_bundle)
+            .unwrap();
+
+        // Боб пишет первым (сообщение уже у обоих в истории), Алиса отвечает цитатой
+        for state in [&mut alice, &mut bob] {
+            let local_content = Some(state.seal_local_content("lunch?").unwrap());
+            state
+                .storage
+                .save_message(StoredMessage {
+                    id: "original".to_string(),
+                    conversation_id: "bob_id".to_string(),
+                    from: "bob_id".to_string(),
+                    to: "alice_id".to_string(),
+                    encrypted_content: "AQID".to_string(),
+                    timestamp: 100,
+                    status: MessageStatus::Delivered,
+                    local_content,
+                    ratchet_header: None,
+                })
+                .unwrap();
+        }
+
+        let encrypted = alice
+            .crypto_manager_mut()
+            .encrypt_body(&alice_session, &MessageBody::reply("sure", "original"))
+            .unwrap();
+
+        let bob_session = bob
+            .crypto_manager_mut()
+            .init_receiving_session("alice_id", &alice_bundle, &encrypted)
+            .unwrap();
+        let body = bob
+            .crypto_manager_mut()
+            .decrypt_body(&bob_session, &encrypted)
+            .unwrap();
+        assert_eq!(body.reply_to(), Some("original"));
+
+        let local_content = Some(bob.seal_local_body(&body).unwrap());
+        bob.storage
+            .save_message(StoredMessage {
+                id: "reply".to_string(),
+                conversation_id: "alice_id".to_string(),
+                from: "alice_id".to_string(),
+                to: "bob_id".to_string(),
+                encrypted_content: "AQID".to_string(),
+                timestamp: 200,
+                status: MessageStatus::Delivered,
+                local_content,
+                ratchet_header: None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            bob.reply_context("reply").unwrap(),
+            Some(ReplyContext::Quoted {
+                message_id: "original".to_string(),
+                from: "bob_id".to_string(),
+                timestamp: 100,
+                text: Some("lunch?".to_string()),
+            })
+        );
+        // Обычное сообщение - не ответ
+        assert_eq!(bob.reply_context("original").unwrap(), None);
+
+        // Оригинал удален - ответ все равно отображается
+        bob.storage.delete_message("original").unwrap();
+        assert_eq!(
+            bob.reply_context("reply").unwrap(),
+            Some(ReplyContext::Missing {
+                message_id: "original".to_string(),
+            })
+        );
+        assert!(matches!(
+            bob.reply_context("unknown"),
+            Err(ConstructError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_plaintext_cache_avoids_redecryption() {
+        let mut state = registered_state("alice_id", "testpass123");
+        let local_content = Some(state.seal_local_content("lunch?").unwrap());
+        let msg = StoredMessage {
+            id: "m1".to_string(),
+            conversation_id: "bob_id".to_string(),
+            from: "bob_id".to_string(),
+            to: "alice_id".to_string(),
+            encrypted_content: "AQID".to_string(),
+            timestamp: 100,
+            status: MessageStatus::Delivered,
+            local_content,
+            ratchet_header: None,
+        };
+
+        for _ in 0..3 {
+            let body = state.open_local_body(&msg).unwrap().unwrap();
+            assert_eq!(body.as_text(), "lunch?");
+        }
+
+        // Повторный запрос не расшифровывает: даже испорченная копия отдается из кеша
+        let mut corrupted = msg.clone();
+        corrupted.local_content = Some(vec![0u8; 64]);
+        assert_eq!(
+            state.open_local_body(&corrupted).unwrap().unwrap().as_text(),
+            "lunch?"
+        );
+
+        // Блокировка очищает кеш и требует мастер-ключ заново
+        state.lock();
+        assert!(state.plaintext_cache.borrow().is_empty());
+        assert!(state.open_local_body(&msg).is_err());
+
+        state
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        assert!(state.open_local_body(&corrupted).is_err());
+        state.open_local_body(&msg).unwrap().unwrap();
+
+        state.clear_all_data().unwrap();
+        assert!(state.plaintext_cache.borrow().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_duplicate_ack_is_ignored() {
+        use crate::protocol::messages::AckData;
+
+        let mut state = registered_state("alice_id", "testpass123");
+        let msg = StoredMessage {
+            id: "m1".to_string(),
+            conversation_id: "bob_id".to_string(),
+            from: "alice_id".to_string(),
+            to: "bob_id".to_string(),
+            encrypted_content: "AQID".to_string(),
+            timestamp: 100,
+            status: MessageStatus::Sent,
+            local_content: None,
+            ratchet_header: None,
+        };
+        state.storage.save_message(msg.clone()).unwrap();
+        state.update_message_cache("bob_id", msg);
+
+        let ack = |status: &str| {
+            ServerMessage::Ack(AckData {
+                message_id: "m1".to_string(),
+                status: status.to_string(),
+            })
+        };
+        state.handle_server_message(ack("delivered")).unwrap();
+        state.handle_server_message(ack("delivered")).unwrap();
+        // Запоздавший "queued" не откатывает статус
+        state.handle_server_message(ack("queued")).unwrap();
+
+        assert_eq!(
+            state.take_events(),
+            vec![AppEvent::MessageStatusChanged {
+                message_id: "m1".to_string(),
+                status: MessageStatus::Delivered,
+            }]
+        );
+        let stored = state.storage.load_message("m1").unwrap().unwrap();
+        assert_eq!(stored.status, MessageStatus::Delivered);
+        assert_eq!(state.message_cache["bob_id"][0].status, MessageStatus::Delivered);
+
+        state.handle_server_message(ack("read")).unwrap();
+        assert_eq!(state.take_events().len(), 1);
+
+        // Удаленные сообщения не остаются в списке подтвержденных
+        state.clear_conversation("bob_id").unwrap();
+        assert!(state.acked_messages.is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_ack_recorded_only_for_stored_messages() {
+        use crate::protocol::messages::AckData;
+
+        let mut state = registered_state("alice_id", "testpass123");
+        let ack = ServerMessage::Ack(AckData {
+            message_id: "m1".to_string(),
+            status: "delivered".to_string(),
+        });
+
+        // Ack пришел раньше, чем сообщение записано: статус не сохранен и не запоминается
+        state.handle_server_message(ack.clone()).unwrap();
+        assert!(state.acked_messages.is_empty());
+        state.take_events();
+
+        state
+            .storage
+            .save_message(StoredMessage {
+                id: "m1".to_string(),
+                conversation_id: "bob_id".to_string(),
+                from: "alice_id".to_string(),
+                to: "bob_id".to_string(),
+                encrypted_content: "AQID".to_string(),
+                timestamp: 100,
+                status: MessageStatus::Sent,
+                local_content: None,
+                ratchet_header: None,
+            })
+            .unwrap();
+
+        // Повтор того же Ack применяется
+        state.handle_server_message(ack).unwrap();
+        assert_eq!(
+            state.storage.load_message("m1").unwrap().unwrap().status,
+            MessageStatus::Delivered
+        );
+        assert_eq!(state.acked_messages.len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_diagnose_conversation() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let mut bob = registered_state("bob_id", "testpass123");
+
+        let empty = bob.diagnose_conversation("alice_id");
+        assert!(!empty.session_present);
+        assert_eq!(empty.suite_id, None);
+        assert_eq!(empty.last_successful_decrypt, None);
+
+        let bob_bundle = bob.crypto_manager().export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager().export_registration_bundle().unwrap();
+        let alice_session = alice
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob_bundle)
+            .unwrap();
+        let messages: Vec<_> = (0..3)
+            .map(|i| {
+                alice
+                    .crypto_manager_mut()
+                    .encrypt_message(&alice_session, &format!("msg {}", i))
+                    .unwrap()
+            })
+            .collect();
+
+        let bob_session = bob
+            .crypto_manager_mut()
+            .init_receiving_session("alice_id", &alice_bundle, &messages[0])
+            .unwrap();
+        // Сообщение 1 пропущено - его ключ остается сохраненным
+        bob.crypto_manager_mut()
+            .decrypt_message(&bob_session, &messages[0])
+            .unwrap();
+        bob.crypto_manager_mut()
+            .decrypt_message(&bob_session, &messages[2])
+            .unwrap();
+
+        let diagnosis = bob.diagnose_conversation("alice_id");
+        assert!(diagnosis.session_present);
+        assert!(!diagnosis.restore_pending);
+        assert_eq!(diagnosis.suite_id, Some(ClassicSuiteProvider::suite_id()));
+        assert_eq!(diagnosis.receiving_chain_length, 3);
+        assert_eq!(diagnosis.skipped_key_count, 1);
+        assert!(diagnosis.last_successful_decrypt.is_some());
+        assert_eq!(diagnosis.consecutive_failures, 0);
+
+        let mut corrupted = alice
+            .crypto_manager_mut()
+            .encrypt_message(&alice_session, "corrupted")
+            .unwrap();
+        corrupted.ciphertext[0] ^= 0xff;
+        for _ in 0..2 {
+            assert!(bob
+                .crypto_manager_mut()
+                .decrypt_message(&bob_session, &corrupted)
+                .is_err());
+        }
+        assert_eq!(bob.diagnose_conversation("alice_id").consecutive_failures, 2);
+
+        let next = alice
+            .crypto_manager_mut()
+            .encrypt_message(&alice_session, "next")
+            .unwrap();
+        bob.crypto_manager_mut()
+            .decrypt_message(&bob_session, &next)
+            .unwrap();
+        assert_eq!(bob.diagnose_conversation("alice_id").consecutive_failures, 0);
+
+        let sender = alice.diagnose_conversation("bob_id");
+        assert_eq!(sender.sending_chain_length, 5);
+    }
+
+    /// Зашифрованное сообщение собеседника для Алисы с серверным `seq`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn encrypted_chat(
+        peer: &mut CryptoCore<ClassicSuiteProvider>,
+        peer_session: &str,
+        peer_id: &str,
+        seq: Option<u64>,
+    ) -> ChatMessage {
+        let timestamp = crate::utils::time::now();
+        let encrypted = peer
+            .encrypt_body_at(peer_session, &MessageBody::new_text("hi"), timestamp)
+            .unwrap();
+        let mut msg = ChatMessage::from_encrypted_at(peer_id, "alice_id", &encrypted, timestamp).unwrap();
+        msg.seq = seq;
+        msg
+    }
+
+    /// Собеседник с сессией к Алисе; Алиса уже приняла его первое сообщение (без `seq`)
+    #[cfg(not(target_arch = "wasm32"))]
+    fn connected_peer(
+        alice: &mut AppState<ClassicSuiteProvider>,
+        peer_id: &str,
+    ) -> (CryptoCore<ClassicSuiteProvider>, String, SessionId) {
+        let mut peer = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let peer_bundle = peer.export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        let peer_session = peer.init_session("alice_id", &alice_bundle).unwrap();
+
+        let first = encrypted_chat(&mut peer, &peer_session, peer_id, None);
+        let alice_session = alice
+            .crypto_manager
+            .init_receiving_session(peer_id, &peer_bundle, &first.to_encrypted().unwrap())
+            .unwrap();
+        let alice_session = SessionId::new(alice_session).unwrap();
+        alice.receive_message(first, &alice_session).unwrap();
+        (peer, peer_session, alice_session)
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_rekey_all_resets_every_session() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mut alice = registered_state("alice_id", "testpass123");
+        let sent = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        alice.set_transport(Box::new(MockTransport { sent: sent.clone() }));
+
+        let (mut bob, bob_session, alice_bob_session) = connected_peer(&mut alice, "bob_id");
+        let (_carol, _, _) = connected_peer(&mut alice, "carol_id");
+        let in_flight = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+        let prekey_before = alice.crypto_manager.export_registration_bundle().unwrap();
+
+        assert_eq!(alice.rekey_all().unwrap(), 2);
+        assert_eq!(alice.pending_rekey(), vec!["bob_id", "carol_id"]);
+        assert!(!alice.crypto_manager.has_session("bob_id"));
+        assert!(!alice.crypto_manager.has_session("carol_id"));
+        assert_ne!(
+            alice.crypto_manager.export_registration_bundle().unwrap().signed_prekey_public,
+            prekey_before.signed_prekey_public
+        );
+
+        // Сброс уходит через старую сессию и читается собеседником
+        let sent_messages = sent.borrow().clone();
+        let reset = sent_messages
+            .iter()
+            .find_map(|message| match message {
+                ClientMessage::SendMessage(msg) if msg.to == "bob_id" => Some(msg.clone()),
+                _ => None,
+            })
+            .unwrap();
+        let body = bob
+            .decrypt_body_at(&bob_session, &reset.to_encrypted().unwrap(), reset.timestamp)
+            .unwrap();
+        assert_eq!(body, MessageBody::SessionReset);
+        let requested: Vec<_> = sent_messages
+            .iter()
+            .filter_map(|message| match message {
+                ClientMessage::GetPublicKey(data) => Some(data.user_id.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(requested, vec!["bob_id", "carol_id"]);
+
+        // Старые шифртексты больше не расшифровываются
+        assert!(alice.receive_message(in_flight, &alice_bob_session).is_err());
+
+        // Свежий bundle восстанавливает сессию
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        alice
+            .handle_server_message(ServerMessage::PublicKeyBundle(PublicKeyBundleData {
+                user_id: "bob_id".to_string(),
+                identity_public: general_purpose::STANDARD.encode(&bob_bundle.identity_public),
+                signed_prekey_public: general_purpose::STANDARD.encode(&bob_bundle.signed_prekey_public),
+                signature: general_purpose::STANDARD.encode(&bob_bundle.signature),
+                verifying_key: general_purpose::STANDARD.encode(&bob_bundle.verifying_key),
+                suite_id: None,
+                one_time_prekey_public: None,
+                one_time_prekey_id: None,
+                signed_prekey_id: None,
+            }))
+            .unwrap();
+        assert!(alice.crypto_manager.has_session("bob_id"));
+        assert_eq!(alice.pending_rekey(), vec!["carol_id"]);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_key_bundle_response_completes_pending_send() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+
+        // Первое сообщение без сессии и bundle ждет в очереди
+        let bob_id = ContactId::new("bob_id").unwrap();
+        let message_id = alice
+            .send_message(&bob_id, &SessionId::new("no_session").unwrap(), "hi bob")
+            .unwrap();
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        assert_eq!(alice.flush_outbound_queue().unwrap(), 0);
+        assert_eq!(alice.pending_message_count(), 1);
+
+        // Bundle с чужой подписью отклоняется и ничего не меняет
+        let mut forged = bob_bundle.to_bundle_data("bob_id");
+        forged.signature = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap()
+            .to_bundle_data("bob_id")
+            .signature;
+        assert!(alice.on_key_bundle_response(forged).is_err());
+        assert!(!alice.crypto_manager.has_session("bob_id"));
+        assert_eq!(alice.pending_message_count(), 1);
+
+        let sent = alice
+            .on_key_bundle_response(bob_bundle.to_bundle_data("bob_id"))
+            .unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(alice.pending_message_count(), 0);
+        let stored_bundle = alice.contact_manager.get_contact("bob_id").unwrap().public_key_bundle.clone();
+        assert_eq!(
+            stored_bundle.map(|b| b.identity_public),
+            Some(contact_bundle(&bob_bundle).identity_public)
+        );
+        assert_eq!(
+            alice.storage.load_message(&message_id).unwrap().unwrap().status,
+            MessageStatus::Sent
+        );
+
+        let chat_msg = transport
+            .sent
+            .borrow()
+            .iter()
+            .find_map(|message| match message {
+                ClientMessage::SendMessage(msg) => Some(msg.clone()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(chat_msg.id, message_id);
+        let encrypted = chat_msg.to_encrypted().unwrap();
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &encrypted)
+            .unwrap();
+        let body = bob
+            .decrypt_body_at(&bob_session, &encrypted, chat_msg.timestamp)
+            .unwrap();
+        assert!(matches!(body, MessageBody::Text { text, .. } if text == "hi bob"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_rekey_all_requires_connection() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let (_bob, _, _) = connected_peer(&mut alice, "bob_id");
+
+        assert!(matches!(alice.rekey_all(), Err(ConstructError::NetworkError(_))));
+        assert!(alice.crypto_manager.has_session("bob_id"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_peer_session_reset_drops_session() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let (mut bob, bob_session, alice_session) = connected_peer(&mut alice, "bob_id");
+        alice.take_events();
+
+        let timestamp = crate::utils::time::now();
+        let encrypted = bob
+            .encrypt_body_at(&bob_session, &MessageBody::SessionReset, timestamp)
+            .unwrap();
+        let reset = ChatMessage::from_encrypted_at("bob_id", "alice_id", &encrypted, timestamp).unwrap();
+        alice.receive_message(reset, &alice_session).unwrap();
+
+        assert!(!alice.crypto_manager.has_session("bob_id"));
+        assert_eq!(
+            alice.take_events(),
+            vec![AppEvent::SessionReset {
+                contact_id: "bob_id".to_string()
+            }]
+        );
+        // Управляющее сообщение не попадает в переписку
+        assert_eq!(alice.conversations_manager().get("bob_id").unwrap().message_count(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_header_encryption_end_to_end() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_header_encryption(true);
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::builder()
+            .header_encryption(true)
+            .build()
+            .unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+
+        alice.send_message_auto("bob_id", Some(&bob_bundle), "one").unwrap();
+        alice.send_message_auto("bob_id", None, "two").unwrap();
+        let sent: Vec<ChatMessage> = transport
+            .sent
+            .borrow()
+            .iter()
+            .filter_map(|message| match message {
+                ClientMessage::SendMessage(msg) => Some(msg.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sent.len(), 2);
+
+        // Сервер не видит ни DH ключа, ни номера сообщения в цепочке
+        for msg in &sent {
+            assert!(msg.ratchet_dh_public.is_empty());
+            assert_eq!(msg.message_number, 0);
+            crate::protocol::validation::validate_server_message(&ServerMessage::Message(msg.clone())).unwrap();
+            assert!(msg.to_encrypted().unwrap().is_header_encrypted());
+        }
+
+        let first = sent[0].to_encrypted().unwrap();
+        let bob_session = bob.init_receiving_session("alice_id", &alice_bundle, &first).unwrap();
+        for (msg, text) in sent.iter().zip(["one", "two"]) {
+            let body = bob
+                .decrypt_body_at(&bob_session, &msg.to_encrypted().unwrap(), msg.timestamp)
+                .unwrap();
+            assert_eq!(body.as_text(), text);
+        }
+
+        // Ответ с зашифрованным заголовком проходит проверки и расшифровывается
+        let reply = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+        assert!(reply.ratchet_dh_public.is_empty());
+        crate::protocol::validation::validate_server_message(&ServerMessage::Message(reply.clone())).unwrap();
+        let alice_session = alice.crypto_manager.client().session_id_for_contact("bob_id").unwrap().to_string();
+        alice
+            .receive_message(reply.clone(), &SessionId::new(alice_session).unwrap())
+            .unwrap();
+        assert!(alice.storage.load_message(&reply.id).unwrap().is_some());
+
+        // Открытые поля рядом с зашифрованным заголовком - подмена
+        let mut leaked = reply;
+        leaked.message_number = 1;
+        assert!(leaked.to_encrypted().is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_identity_rotation() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let mut bob = registered_state("bob_id", "testpass456");
+        let bob_bundle = bob.crypto_manager.export_registration_bundle().unwrap();
+        alice.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+        alice.update_contact_bundle("bob_id", contact_bundle(&bob_bundle)).unwrap();
+        alice.crypto_manager.init_session("bob_id", &bob_bundle).unwrap();
+        alice.take_events();
+
+        let rotation = bob.rotate_identity().unwrap();
+        let ProtocolMessage::IdentityRotation { new_bundle, .. } = &rotation else {
+            panic!("expected IdentityRotation");
+        };
+        let rotated = bob.crypto_manager.export_registration_bundle().unwrap();
+        assert_ne!(rotated.identity_public, bob_bundle.identity_public);
+
+        // Подпись новым ключом вместо старого - подделка
+        let signed_data = crate::crypto::keys::identity_rotation_signed_data(
+            &bob.crypto_manager.key_manager().export_registration_bundle().unwrap(),
+        );
+        let forged = ProtocolMessage::IdentityRotation {
+            new_bundle: new_bundle.clone(),
+            signature_over_old: {
+                use base64::{engine::general_purpose, Engine as _};
+                general_purpose::STANDARD.encode(bob.crypto_manager.sign_data(&signed_data).unwrap())
+            },
+        };
+        assert!(matches!(
+            alice.handle_identity_rotation("bob_id", &forged),
+            Err(ConstructError::CryptoError(_))
+        ));
+        assert_eq!(alice.contact_identity_key("bob_id").unwrap(), Some(bob_bundle.identity_public.clone()));
+        assert!(alice.crypto_manager.has_session("bob_id"));
+        assert!(alice.take_events().is_empty());
+
+        // Подпись старым ключом принимается, но смена ключа ждет сверки пользователем
+        let old_session = alice.crypto_manager.client().session_id_for_contact("bob_id").unwrap().to_string();
+        alice.handle_identity_rotation("bob_id", &rotation).unwrap();
+        assert_eq!(alice.contact_identity_key("bob_id").unwrap(), Some(rotated.identity_public.clone()));
+        assert!(!alice.crypto_manager.has_session("bob_id"));
+        assert!(alice.has_pending_key_change("bob_id"));
+        assert_eq!(
+            alice.take_events(),
+            vec![
+                AppEvent::IdentityKeyMismatch {
+                    contact_id: "bob_id".to_string(),
+                    session_id: old_session,
+                },
+                AppEvent::SessionReset {
+                    contact_id: "bob_id".to_string()
+                },
+            ]
+        );
+
+        // Без подтверждения сообщение на новый ключ не уходит
+        let err = alice.send_message_auto("bob_id", None, "hello").unwrap_err();
+        assert!(err.to_string().contains("is not verified"), "{}", err);
+        assert!(!alice.crypto_manager.has_session("bob_id"));
+
+        // После сверки safety number сессия строится на новом bundle,
+        // повтор старого перехода отклоняется
+        alice.acknowledge_key_change("bob_id").unwrap();
+        assert!(!alice.has_pending_key_change("bob_id"));
+        alice.crypto_manager.init_session("bob_id", &rotated).unwrap();
+        assert!(alice.handle_identity_rotation("bob_id", &rotation).is_err());
+        assert!(alice.crypto_manager.has_session("bob_id"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_outbound_queue_flushed_in_order() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let (mut bob, bob_session, alice_session) = connected_peer(&mut alice, "bob_id");
+        let bob_id = ContactId::new("bob_id").unwrap();
+
+        // Соединения нет: сообщения ждут в очереди со статусом Pending
+        let first = alice.send_message(&bob_id, &alice_session, "one").unwrap();
+        let second = alice.send_message(&bob_id, &alice_session, "two").unwrap();
+        let carol_id = ContactId::new("carol_id").unwrap();
+        let carol_session = SessionId::new("carol_session").unwrap();
+        alice.send_message(&carol_id, &carol_session, "no session yet").unwrap();
+        assert_eq!(alice.pending_message_count(), 3);
+        assert_eq!(
+            alice.storage.load_message(&first).unwrap().unwrap().status,
+            MessageStatus::Pending
+        );
+
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        assert_eq!(alice.flush_outbound_queue().unwrap(), 2);
+        // Сообщение без сессии остается в очереди
+        assert_eq!(alice.pending_message_count(), 1);
+
+        let sent: Vec<ChatMessage> = transport
+            .sent
+            .borrow()
+            .iter()
+            .filter_map(|message| match message {
+                ClientMessage::SendMessage(msg) => Some(msg.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sent.iter().map(|msg| msg.id.as_str()).collect::<Vec<_>>(),
+            vec![first.as_str(), second.as_str()]
+        );
+        assert_eq!(sent[1].message_number, sent[0].message_number + 1);
+        for (msg, text) in sent.iter().zip(["one", "two"]) {
+            let body = bob
+                .decrypt_body_at(&bob_session, &msg.to_encrypted().unwrap(), msg.timestamp)
+                .unwrap();
+            assert_eq!(body.as_text(), text);
+        }
+
+        let stored = alice.storage.load_message(&second).unwrap().unwrap();
+        assert_eq!(stored.status, MessageStatus::Sent);
+        assert_eq!(stored.encrypted_content, sent[1].content);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_read_receipt_marks_messages_read() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_transport(Box::new(MockTransport::default()));
+        let bob_id = "550e8400-e29b-41d4-a716-446655440001";
+        let carol_id = "550e8400-e29b-41d4-a716-446655440002";
+        let bob_bundle = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap();
+
+        let first = alice.send_message_auto(bob_id, Some(&bob_bundle), "one").unwrap();
+        let second = alice.send_message_auto(bob_id, None, "two").unwrap();
+        let unread = alice.send_message_auto(bob_id, None, "three").unwrap();
+        alice.take_events();
+
+        // Квитанция из чужой беседы не трогает сообщения Бобу
+        let forged = ProtocolMessage::ReadReceipt {
+            message_ids: vec![first.clone()],
+            conversation_id: carol_id.to_string(),
+            timestamp: current_timestamp(),
+        };
+        assert_eq!(alice.handle_read_receipt(&forged).unwrap(), 0);
+
+        let receipt = ProtocolMessage::ReadReceipt {
+            message_ids: vec![first.clone(), second.clone()],
+            conversation_id: bob_id.to_string(),
+            timestamp: current_timestamp(),
+        };
+        assert_eq!(alice.handle_read_receipt(&receipt).unwrap(), 2);
+        // Повтор квитанции ничего не меняет
+        assert_eq!(alice.handle_read_receipt(&receipt).unwrap(), 0);
+
+        for (message_id, status) in [
+            (&first, MessageStatus::Read),
+            (&second, MessageStatus::Read),
+            (&unread, MessageStatus::Sent),
+        ] {
+            assert_eq!(alice.storage.load_message(message_id).unwrap().unwrap().status, status);
+        }
+        let conversation = alice.conversations_manager().get(bob_id).unwrap();
+        assert!(conversation
+            .messages
+            .iter()
+            .filter(|msg| msg.id != unread)
+            .all(|msg| msg.status == MessageStatus::Read));
+        assert_eq!(alice.take_events().len(), 2);
+
+        let bad = ProtocolMessage::ReadReceipt {
+            message_ids: vec!["m1".to_string()],
+            conversation_id: bob_id.to_string(),
+            timestamp: current_timestamp(),
+        };
+        assert!(matches!(
+            alice.handle_read_receipt(&bad),
+            Err(ConstructError::ValidationError(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_notification_policy() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+        let (mut bob, bob_session, session) = connected_peer(&mut alice, "bob_id");
+        let mut receive = |alice: &mut AppState<ClassicSuiteProvider>| {
+            let msg = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+            let message_id = msg.id.clone();
+            alice.receive_message(msg, &session).unwrap();
+            message_id
+        };
+        let unread = |alice: &AppState<ClassicSuiteProvider>| {
+            alice.conversations_manager().get("bob_id").unwrap().unread_count
+        };
+        let stored = |alice: &AppState<ClassicSuiteProvider>| {
+            alice.conversations_manager().get("bob_id").unwrap().message_count()
+        };
+        alice.take_events();
+        let (unread_before, stored_before) = (unread(&alice), stored(&alice));
+
+        // Обычный случай: сохранить, счетчик, уведомление
+        let message_id = receive(&mut alice);
+        assert_eq!(unread(&alice), unread_before + 1);
+        assert_eq!(
+            alice.take_events(),
+            vec![AppEvent::MessageReceived {
+                conversation_id: "bob_id".to_string(),
+                message_id,
+            }]
+        );
+
+        // Открытая беседа: без счетчика и уведомления
+        alice.set_active_conversation(Some("bob_id".to_string()));
+        receive(&mut alice);
+        assert_eq!(unread(&alice), unread_before + 1);
+        assert!(alice.take_events().is_empty());
+        alice.set_active_conversation(None);
+
+        // Без звука: счетчик растет, уведомления нет
+        alice.set_contact_muted("bob_id", true).unwrap();
+        receive(&mut alice);
+        assert_eq!(unread(&alice), unread_before + 2);
+        assert!(alice.take_events().is_empty());
+        assert!(alice.storage.load_contact("bob_id").unwrap().unwrap().muted);
+
+        // Заблокирован: сообщение отброшено целиком
+        alice.set_contact_blocked("bob_id", true).unwrap();
+        receive(&mut alice);
+        assert_eq!(stored(&alice), stored_before + 3);
+        assert_eq!(unread(&alice), unread_before + 2);
+        assert!(alice.take_events().is_empty());
+
+        // Своя политика заменяет встроенную
+        struct NotifyAll;
+        impl NotificationPolicy for NotifyAll {
+            fn decide(&self, _context: &NotificationContext<'_>) -> NotificationDecision {
+                NotificationDecision::Notify
+            }
+        }
+        alice.set_notification_policy(Box::new(NotifyAll));
+        receive(&mut alice);
+        assert_eq!(stored(&alice), stored_before + 4);
+        assert_eq!(alice.take_events().len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_blocked_messages_keep_session_in_sync() {
+        use crate::crypto::double_ratchet::DEFAULT_MAX_SKIPPED_MESSAGES;
+
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+        let (mut bob, bob_session, session) = connected_peer(&mut alice, "bob_id");
+        alice.take_events();
+        let stored_before = alice.message_count("bob_id").unwrap();
+
+        // Больше сообщений, чем сессия может пропустить без расшифровки
+        alice.set_contact_blocked("bob_id", true).unwrap();
+        for _ in 0..DEFAULT_MAX_SKIPPED_MESSAGES + 5 {
+            let msg = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+            alice.receive_message(msg, &session).unwrap();
+        }
+        assert_eq!(alice.message_count("bob_id").unwrap(), stored_before);
+        assert!(alice.take_events().is_empty());
+
+        alice.set_contact_blocked("bob_id", false).unwrap();
+        let msg = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+        alice.receive_message(msg, &session).unwrap();
+        assert_eq!(alice.message_count("bob_id").unwrap(), stored_before + 1);
+        assert_eq!(alice.take_events().len(), 1);
+    }
+
+    /// Доставить получателю попарные сообщения из `sent`, адресованные ему.
+    /// Первое сообщение от нового собеседника устанавливает сессию по его bundle
+    #[cfg(not(target_arch = "wasm32"))]
+    fn deliver_pairwise(
+        to: &mut AppState<ClassicSuiteProvider>,
+        sent: &MockTransport,
+        from: &AppState<ClassicSuiteProvider>,
+    ) -> usize {
+        let to_id = to.require_user_id().unwrap().to_string();
+        let from_id = from.require_user_id().unwrap().to_string();
+        let from_bundle = from.crypto_manager.export_registration_bundle().unwrap();
+
+        let mut delivered = 0;
+        let messages: Vec<ClientMessage> = sent.sent.borrow_mut().drain(..).collect();
+        for message in messages {
+            let ClientMessage::SendMessage(chat_msg) = message else {
+                continue;
+            };
+            if chat_msg.to != to_id {
+                sent.sent.borrow_mut().push(ClientMessage::SendMessage(chat_msg));
+                continue;
+            }
+            let session_id = match to.crypto_manager.client().session_id_for_contact(&from_id) {
+                Some(session_id) => session_id.to_string(),
+                None => to
+                    .crypto_manager
+                    .init_receiving_session(&from_id, &from_bundle, &chat_msg.to_encrypted().unwrap())
+                    .unwrap(),
+            };
+            to.receive_message(chat_msg, &SessionId::new(session_id).unwrap()).unwrap();
+            delivered += 1;
+        }
+        delivered
+    }
 
-        // 1. Проверить, что пользователь инициализирован
-        let username = self.username.as_ref()
-            .ok_or_else(|| ConstructError::InvalidInput(
-                "User not initialized. Call initialize_user first.".to_string()
-            ))?;
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_group_message_with_late_member() {
+        let alice_id = "550e8400-e29b-41d4-a716-446655440001";
+        let bob_id = "550e8400-e29b-41d4-a716-446655440002";
+        let carol_id = "550e8400-e29b-41d4-a716-446655440003";
+        let group_id = "550e8400-e29b-41d4-a716-4466554400aa";
+
+        let mut states = Vec::new();
+        let mut transports = Vec::new();
+        for user_id in [alice_id, bob_id, carol_id] {
+            let mut state = registered_state(user_id, "testpass123");
+            let transport = MockTransport::default();
+            state.set_transport(Box::new(transport.clone()));
+            states.push(state);
+            transports.push(transport);
+        }
+        let mut carol = states.pop().unwrap();
+        let mut bob = states.pop().unwrap();
+        let mut alice = states.pop().unwrap();
+        let (alice_sent, carol_sent) = (&transports[0], &transports[2]);
+        let text_of = |state: &AppState<ClassicSuiteProvider>, message_id: &str| {
+            let stored = state.storage.load_message(message_id).unwrap().unwrap();
+            state.open_local_body(&stored).unwrap().unwrap().as_text().to_string()
+        };
 
-        // 2. Проверить, что есть transport
-        let transport = self.transport.as_ref()
-            .ok_or_else(|| ConstructError::NetworkError(
-                "Not connected to server. Call connect first.".to_string()
-            ))?;
+        let bob_bundle = bob.crypto_manager.export_registration_bundle().unwrap();
+        alice.crypto_manager.init_session(bob_id, &bob_bundle).unwrap();
+        alice.create_group(group_id, vec![bob_id.to_string()]).unwrap();
+        bob.create_group(group_id, vec![alice_id.to_string()]).unwrap();
+
+        // Цепочка Алисы уходит Бобу попарно, само сообщение - один шифртекст
+        let before = alice.send_group_message(group_id, "before carol").unwrap();
+        assert_eq!(deliver_pairwise(&mut bob, alice_sent, &alice), 1);
+        let received = bob.receive_group_message(&before).unwrap().unwrap();
+        assert_eq!(text_of(&bob, &received), "before carol");
+
+        // Кэрол вступает позже: ключа Алисы у нее нет, она просит свежую цепочку
+        alice.add_group_member(group_id, carol_id).unwrap();
+        bob.add_group_member(group_id, carol_id).unwrap();
+        carol
+            .create_group(group_id, vec![alice_id.to_string(), bob_id.to_string()])
+            .unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        carol.crypto_manager.init_session(alice_id, &alice_bundle).unwrap();
+        assert_eq!(carol.receive_group_message(&before).unwrap(), None);
+        assert_eq!(deliver_pairwise(&mut alice, carol_sent, &carol), 1);
+        assert_eq!(deliver_pairwise(&mut carol, alice_sent, &alice), 1);
+
+        // Следующее сообщение читают оба, повторной рассылки цепочки нет
+        let after = alice.send_group_message(group_id, "hello both").unwrap();
+        assert!(alice_sent.sent.borrow().is_empty());
+        for member in [&mut bob, &mut carol] {
+            let received = member.receive_group_message(&after).unwrap().unwrap();
+            assert_eq!(text_of(member, &received), "hello both");
+        }
 
-        // 3. Получить registration bundle в base64
-        let bundle = self.crypto_manager.export_registration_bundle_b64()?;
+        // Сообщения до вступления новой цепочкой не расшифровать
+        assert!(carol.receive_group_message(&before).is_err());
+        assert_eq!(alice.conversations_manager().get(group_id).unwrap().message_count(), 2);
+    }
 
-        // 4. Сериализовать bundle в JSON для public_key поля
-        let public_key = serde_json::to_string(&bundle)
-            .map_err(|e| ConstructError::SerializationError(
-                format!("Failed to serialize registration bundle: {}", e)
-            ))?;
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_typing_indicator_expires() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let bob_id = "550e8400-e29b-41d4-a716-446655440001";
+        let typing = |is_typing| ProtocolMessage::Typing {
+            conversation_id: bob_id.to_string(),
+            is_typing,
+        };
+        let conversation_typing = |alice: &AppState<ClassicSuiteProvider>| {
+            alice.conversations_manager().get(bob_id).unwrap().is_typing
+        };
 
-        // 5. Создать RegisterData
-        let register_data = RegisterData {
-            username: username.clone(),
-            display_name: username.clone(), // Используем username как display_name
-            password,
-            public_key,
+        alice.handle_typing_at(&typing(true), 1000).unwrap();
+        assert!(alice.is_typing_at(bob_id, 1000));
+        assert!(alice.is_typing_at(bob_id, 1000 + TYPING_TIMEOUT_SECONDS - 1));
+        assert!(conversation_typing(&alice));
+
+        // Повтор продлевает индикатор
+        alice.handle_typing_at(&typing(true), 1003).unwrap();
+        alice.expire_typing_at(1000 + TYPING_TIMEOUT_SECONDS);
+        assert!(alice.is_typing_at(bob_id, 1000 + TYPING_TIMEOUT_SECONDS));
+        assert!(conversation_typing(&alice));
+
+        alice.expire_typing_at(1003 + TYPING_TIMEOUT_SECONDS);
+        assert!(!alice.is_typing_at(bob_id, 1003 + TYPING_TIMEOUT_SECONDS));
+        assert!(!conversation_typing(&alice));
+
+        // Явный сброс и эфемерность
+        alice.handle_typing_at(&typing(true), 2000).unwrap();
+        alice.handle_typing_at(&typing(false), 2001).unwrap();
+        assert!(!alice.is_typing_at(bob_id, 2001));
+        assert!(alice.storage.load_all_contacts().unwrap().is_empty());
+        assert_eq!(alice.message_count(bob_id).unwrap(), 0);
+
+
+        let bob = registered_state(bob_id, "testpass456");
+        assert_eq!(bob.typing_indicator(true).unwrap(), typing(true));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_sequence_gap_detection() {
+        let mut state = registered_state("alice_id", "testpass123");
+        let (mut bob, bob_session, session) = connected_peer(&mut state, "bob_id");
+        let (mut carol, carol_session, carol_alice_session) = connected_peer(&mut state, "carol_id");
+        // Уведомления о каждом сообщении здесь не важны
+        let take_gap_events = |state: &mut AppState<ClassicSuiteProvider>| -> Vec<AppEvent> {
+            state
+                .take_events()
+                .into_iter()
+                .filter(|event| !matches!(event, AppEvent::MessageReceived { .. }))
+                .collect()
         };
+        let mut incoming = |seq: Option<u64>| encrypted_chat(&mut bob, &bob_session, "bob_id", seq);
 
-        // 6. Отправить через transport
-        let message = ClientMessage::Register(register_data);
-        transport.send(&message)?;
+        for seq in [5, 6, 7] {
+            state.receive_message(incoming(Some(seq)), &session).unwrap();
+        }
+        // Сообщения без seq не влияют на нумерацию
+        state.receive_message(incoming(None), &session).unwrap();
+        assert!(take_gap_events(&mut state).is_empty());
+
+        state.receive_message(incoming(Some(10)), &session).unwrap();
+        assert_eq!(
+            take_gap_events(&mut state),
+            vec![AppEvent::SequenceGap {
+                conversation_id: "bob_id".to_string(),
+                expected: 8,
+                received: 10,
+            }]
+        );
+
+        // Нумерация у каждой беседы своя
+        let from_carol = encrypted_chat(&mut carol, &carol_session, "carol_id", Some(1));
+        state.receive_message(from_carol, &carol_alice_session).unwrap();
+        assert!(take_gap_events(&mut state).is_empty());
+
+        // Нерасшифрованное сообщение не сдвигает seq и не дает события пропуска
+        let forged = encrypted_chat(&mut carol, &carol_session, "bob_id", Some(11));
+        assert!(state.receive_message(forged, &session).is_err());
+        assert!(take_gap_events(&mut state).is_empty());
+        state.receive_message(incoming(Some(11)), &session).unwrap();
+        assert!(take_gap_events(&mut state).is_empty());
+
+        assert!(state.receive_message(incoming(Some(10)), &session).is_err());
+        assert!(state.receive_message(incoming(Some(3)), &session).is_err());
+    }
 
-        Ok(())
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_redelivered_message_is_idempotent() {
+        let mut state = registered_state("alice_id", "testpass123");
+        let (mut bob, bob_session, session) = connected_peer(&mut state, "bob_id");
+        state.take_events();
+
+        let msg = encrypted_chat(&mut bob, &bob_session, "bob_id", Some(1));
+        state.receive_message(msg.clone(), &session).unwrap();
+        let unread = state.conversations_manager.get_or_create("bob_id").unread_count;
+        assert_eq!(state.take_events().len(), 1);
+
+        // Тот же id второй раз: без ошибки, одна запись, без нового уведомления
+        state.receive_message(msg.clone(), &session).unwrap();
+        assert!(state.take_events().is_empty());
+        assert_eq!(state.message_count("bob_id").unwrap(), 2);
+        assert_eq!(state.message_cache["bob_id"].len(), 2);
+        assert_eq!(state.conversations_manager.get_or_create("bob_id").unread_count, unread);
+
+        // Чужой отправитель не может занять существующий id
+        let (mut carol, carol_session, carol_alice_session) = connected_peer(&mut state, "carol_id");
+        let mut from_carol = encrypted_chat(&mut carol, &carol_session, "carol_id", None);
+        from_carol.id = msg.id.clone();
+        let err = state.receive_message(from_carol, &carol_alice_session).unwrap_err();
+        assert!(err.to_string().contains("already used by another sender"), "{}", err);
+        assert_eq!(state.storage.load_message(&msg.id).unwrap().unwrap().from, "bob_id");
     }
 
-    /// Зарегистрировать пользователя на сервере (non-WASM заглушка)
+    #[test]
     #[cfg(not(target_arch = "wasm32"))]
-    pub fn register_on_server(&self, _password: String) -> Result<()> {
-        Err(ConstructError::NetworkError(
-            "Registration only available in WASM".to_string(),
-        ))
+    fn test_maybe_rotate_prekey_on_schedule() {
+        use base64::{engine::general_purpose, Engine as _};
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let mut state = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        state.set_transport(Box::new(transport.clone()));
+        let now = Arc::new(AtomicI64::new(current_timestamp()));
+        let clock_now = now.clone();
+        let key_manager = std::mem::take(state.crypto_manager.key_manager_mut());
+        *state.crypto_manager.key_manager_mut() =
+            key_manager.with_clock(Arc::new(move || clock_now.load(Ordering::SeqCst)));
+        let old_prekey = state.crypto_manager.key_manager().current_signed_prekey().unwrap().key_id;
+
+        assert!(!state.maybe_rotate_prekey().unwrap());
+        assert!(transport.sent.borrow().is_empty());
+
+        now.fetch_add(DEFAULT_PREKEY_ROTATION_SECONDS + 1, Ordering::SeqCst);
+        assert!(state.maybe_rotate_prekey().unwrap());
+        assert!(!state.maybe_rotate_prekey().unwrap());
+
+        let key_manager = state.crypto_manager.key_manager();
+        assert_ne!(key_manager.current_signed_prekey().unwrap().key_id, old_prekey);
+        assert_eq!(key_manager.old_prekey_ids(), vec![old_prekey]);
+
+        // Сервер получил ровно один bundle, и в нем новый prekey
+        let sent = transport.sent.borrow();
+        assert_eq!(sent.len(), 1);
+        let ClientMessage::RotatePrekey(data) = &sent[0] else {
+            panic!("expected RotatePrekey, got {:?}", sent[0]);
+        };
+        assert_eq!(data.user_id, "alice_id");
+        let update: ProtocolMessage =
+            rmp_serde::from_slice(&general_purpose::STANDARD.decode(&data.update).unwrap()).unwrap();
+        let ProtocolMessage::UpdatePrekey(bundle) = update else {
+            panic!("expected UpdatePrekey");
+        };
+        let current = state.crypto_manager.export_registration_bundle().unwrap();
+        assert_eq!(
+            general_purpose::STANDARD.decode(&bundle.signed_prekey_public).unwrap(),
+            current.signed_prekey_public
+        );
+        drop(sent);
+
+        // Старый prekey переживает перезапуск: по ранее выданным bundle еще пишут
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        restarted.storage = std::mem::take(&mut state.storage);
+        restarted
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        let key_manager = restarted.crypto_manager.key_manager();
+        assert_eq!(key_manager.old_prekey_ids(), vec![old_prekey]);
+        assert_eq!(key_manager.current_signed_prekey().unwrap().key_id, current.signed_prekey_id.unwrap());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::crypto::classic_suite::ClassicSuiteProvider;
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_one_time_prekeys_published_and_survive_restart() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let mut alice = registered_state("alice_id", "testpass123");
+        // Без соединения пополнение откладывается
+        assert_eq!(alice.maybe_replenish_one_time_prekeys().unwrap(), 0);
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        assert_eq!(alice.maybe_replenish_one_time_prekeys().unwrap(), TARGET_ONE_TIME_PREKEYS);
+        assert_eq!(alice.maybe_replenish_one_time_prekeys().unwrap(), 0);
+
+        // Сервер получил публичные ключи с подписями
+        {
+            let sent = transport.sent.borrow();
+            assert_eq!(sent.len(), 1);
+            let ClientMessage::UploadOneTimePrekeys(data) = &sent[0] else {
+                panic!("expected UploadOneTimePrekeys, got {:?}", sent[0]);
+            };
+            assert_eq!(data.user_id, "alice_id");
+            assert_eq!(data.prekeys.len(), TARGET_ONE_TIME_PREKEYS);
+            let verifying_key = alice.crypto_manager.key_manager().verifying_key().unwrap();
+            for prekey in &data.prekeys {
+                ClassicSuiteProvider::verify(
+                    verifying_key,
+                    &general_purpose::STANDARD.decode(&prekey.public_key).unwrap(),
+                    &general_purpose::STANDARD.decode(&prekey.signature).unwrap(),
+                )
+                .unwrap();
+            }
+        }
+        let fingerprint = alice.crypto_manager.fingerprint().unwrap();
+        let alice_bundle = alice.crypto_manager.export_public_bundle().unwrap();
+        assert_eq!(alice_bundle.one_time_prekey_id, Some(1));
+
+        // Перезапуск: identity и одноразовые prekey восстановлены из хранилища
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        restarted.storage = std::mem::take(&mut alice.storage);
+        restarted
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        assert_eq!(restarted.crypto_manager.fingerprint().unwrap(), fingerprint);
+        assert_eq!(
+            restarted.crypto_manager.key_manager().one_time_prekeys_count(),
+            TARGET_ONE_TIME_PREKEYS
+        );
+
+        // Боб начинает сессию по bundle, выданному до перезапуска
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let bob_session = bob.init_session("alice_id", &alice_bundle).unwrap();
+        let first = encrypted_chat(&mut bob, &bob_session, "bob_id", None);
+        let session = restarted
+            .crypto_manager
+            .init_receiving_session("bob_id", &bob_bundle, &first.to_encrypted().unwrap())
+            .unwrap();
+        restarted
+            .receive_message(first, &SessionId::new(session).unwrap())
+            .unwrap();
+
+        // Израсходованный prekey не возвращается после следующего перезапуска
+        let mut reloaded = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        reloaded.storage = std::mem::take(&mut restarted.storage);
+        reloaded
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        let key_manager = reloaded.crypto_manager.key_manager();
+        assert!(key_manager.one_time_prekey(1).is_none());
+        assert_eq!(key_manager.one_time_prekeys_count(), TARGET_ONE_TIME_PREKEYS - 1);
+    }
 
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
-    fn test_app_state_creation() {
-        let state = AppState::<ClassicSuiteProvider>::new("test_db");
-        assert!(state.is_ok());
+    fn test_cleared_conversation_ignores_synced_history() {
+        let mut state = registered_state("alice_id", "testpass123");
+        let (mut bob, bob_session, session) = connected_peer(&mut state, "bob_id");
+        let chat_at = |bob: &mut CryptoCore<ClassicSuiteProvider>, timestamp: u64| {
+            let encrypted = bob
+                .encrypt_body_at(&bob_session, &MessageBody::new_text("hi"), timestamp)
+                .unwrap();
+            ChatMessage::from_encrypted_at("bob_id", "alice_id", &encrypted, timestamp).unwrap()
+        };
+        let now = crate::utils::time::now();
+        let older = chat_at(&mut bob, now - 60);
+        state.take_events();
+
+        assert_eq!(state.clear_conversation("bob_id").unwrap(), 1);
+        let cleared_at = state.cleared_at("bob_id").unwrap().unwrap();
+        assert!(cleared_at >= now as i64);
+        assert_eq!(state.message_count("bob_id").unwrap(), 0);
+        assert_eq!(state.conversations_manager.get("bob_id").unwrap().message_count(), 0);
+        assert!(state.cleared_at("carol_id").unwrap().is_none());
+
+        // Синхронизация истории приносит сообщение старше очистки - оно не возвращается
+        state.receive_message(older, &session).unwrap();
+        assert_eq!(state.message_count("bob_id").unwrap(), 0);
+        assert!(state.take_events().is_empty());
+
+        let newer = chat_at(&mut bob, cleared_at as u64 + 60);
+        state.receive_message(newer.clone(), &session).unwrap();
+        assert_eq!(state.message_count("bob_id").unwrap(), 1);
+        assert_eq!(state.take_events().len(), 1);
+
+        // Отметка сохранена в хранилище и не сдвигается назад
+        assert_eq!(state.storage.load_clear_marker("bob_id").unwrap().unwrap().cleared_at, cleared_at);
+        assert_eq!(state.clear_conversation("bob_id").unwrap(), 0);
+        assert_eq!(state.cleared_at("bob_id").unwrap(), Some(cleared_at));
+        let remaining = state.storage.load_messages_for_conversation("bob_id", 10, 0).unwrap();
+        assert_eq!(remaining.into_iter().map(|m| m.id).collect::<Vec<_>>(), vec![newer.id]);
+    }
 
-        let state = state.unwrap();
-        assert!(state.get_user_id().is_none());
-        assert_eq!(state.connection_state(), ConnectionState::Disconnected);
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_two_app_states_round_trip() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let mut bob = registered_state("bob_id", "testpass456");
+        let alice_transport = MockTransport::default();
+        let bob_transport = MockTransport::default();
+        alice.set_transport(Box::new(alice_transport.clone()));
+        bob.set_transport(Box::new(bob_transport.clone()));
+
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        let bob_bundle = bob.crypto_manager.export_registration_bundle().unwrap();
+        let last_sent = |transport: &MockTransport| match transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(chat_msg)) => chat_msg.clone(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        let text_of = |msg: &StoredMessage, state: &AppState<ClassicSuiteProvider>| {
+            match state.open_local_body(msg).unwrap().unwrap() {
+                MessageBody::Text { text, .. } => text,
+                other => panic!("expected text body, got {:?}", other),
+            }
+        };
+
+        // Алиса пишет первой: X3DH сессия по bundle Боба
+        let message_id = alice
+            .send_message_auto("bob_id", Some(&bob_bundle), "hi bob")
+            .unwrap();
+        let to_bob = last_sent(&alice_transport);
+        let bob_session = bob
+            .crypto_manager
+            .init_receiving_session("alice_id", &alice_bundle, &to_bob.to_encrypted().unwrap())
+            .unwrap();
+        let bob_session = SessionId::new(bob_session).unwrap();
+        bob.receive_message(to_bob, &bob_session).unwrap();
+
+        let received = bob.storage.load_message(&message_id).unwrap().unwrap();
+        assert_eq!(received.status, MessageStatus::Delivered);
+        assert_eq!(text_of(&received, &bob), "hi bob");
+        assert_eq!(bob.conversations_manager().get("alice_id").unwrap().unread_count, 1);
+
+        // Ответ Боба в открытой беседе не увеличивает счетчик непрочитанных
+        alice.set_active_conversation(Some("bob_id".to_string()));
+        let reply_id = bob
+            .send_message(&ContactId::new("alice_id").unwrap(), &bob_session, "hi alice")
+            .unwrap();
+        let alice_session = alice
+            .crypto_manager
+            .client()
+            .session_id_for_contact("bob_id")
+            .unwrap()
+            .to_string();
+        alice
+            .receive_message(last_sent(&bob_transport), &SessionId::new(alice_session).unwrap())
+            .unwrap();
+
+        let reply = alice.storage.load_message(&reply_id).unwrap().unwrap();
+        assert_eq!(text_of(&reply, &alice), "hi alice");
+        assert_eq!(alice.conversations_manager().get("bob_id").unwrap().unread_count, 0);
+        assert_eq!(alice.message_count("bob_id").unwrap(), 2);
     }
 
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
-    fn test_app_state_initialize_user() {
-        let mut state = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+    fn test_recent_activity_across_conversations() {
+        let mut state = registered_state("alice_id", "testpass123");
+        let stored = |id: &str, conversation: &str, timestamp: i64| StoredMessage {
+            id: id.to_string(),
+            conversation_id: conversation.to_string(),
+            from: conversation.to_string(),
+            to: "alice_id".to_string(),
+            encrypted_content: "AQID".to_string(),
+            timestamp,
+            status: MessageStatus::Delivered,
+            local_content: None,
+            ratchet_header: None,
+        };
+
+        for msg in [
+            stored("b1", "bob_id", 10),
+            stored("c1", "carol_id", 20),
+            stored("b2", "bob_id", 30),
+            stored("d1", "dave_id", 25),
+            stored("c2", "carol_id", 5),
+        ] {
+            state.storage.save_message(msg).unwrap();
+        }
+
+        let ids = |messages: Vec<StoredMessage>| {
+            messages.into_iter().map(|m| m.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(state.recent_activity(3).unwrap()), ["b2", "d1", "c1"]);
+        assert_eq!(
+            ids(state.recent_activity(10).unwrap()),
+            ["b2", "d1", "c1", "b1", "c2"]
+        );
+        assert!(state.recent_activity(0).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_load_user_skips_corrupt_sessions() {
+        let mut state = registered_state("alice_id", "testpass123");
+        state.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let session_id = state
+            .crypto_manager_mut()
+            .init_session("bob_id", &bob.export_registration_bundle().unwrap())
+            .unwrap();
+        let session = state.crypto_manager().client().session(&session_id).unwrap();
+        let session_data = session.to_serializable().to_bytes().unwrap();
+
+        for (contact_id, session_data) in [("bob_id", session_data), ("carol_id", vec![1, 2, 3])] {
+            state
+                .storage
+                .save_session(StoredSession {
+                    session_id: format!("{}_session", contact_id),
+                    contact_id: contact_id.to_string(),
+                    session_data,
+                    last_used: 0,
+                    created_at: 0,
+                })
+                .unwrap();
+        }
+
+        assert!(state
+            .load_user("alice_id".to_string(), "wrongpass123".to_string())
+            .is_err());
+
         state
-            .initialize_user("alice".to_string(), "testpass123".to_string())
+            .load_user("alice_id".to_string(), "testpass123".to_string())
             .unwrap();
+        assert!(state.is_unlocked());
+        assert!(state.crypto_manager().client().session_id_for_contact("bob_id").is_some());
+        assert!(state.crypto_manager().client().session_id_for_contact("carol_id").is_none());
+        assert!(matches!(
+            state.take_events().as_slice(),
+            [AppEvent::SessionRestoreFailed { contact_id, .. }] if contact_id == "carol_id"
+        ));
+    }
 
-        assert_eq!(state.get_username(), Some("alice"));
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_load_user_restores_working_sessions() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        alice.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        alice
+            .send_message_auto("bob_id", Some(&bob_bundle), "before restart")
+            .unwrap();
+
+        let first = match transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(msg)) => msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &first)
+            .unwrap();
+        bob.decrypt_body(&bob_session, &first).unwrap();
+
+        let client = alice.crypto_manager.client();
+        let session_id = client.session_id_for_contact("bob_id").unwrap().to_string();
+        alice
+            .storage
+            .save_session(StoredSession {
+                session_id: session_id.clone(),
+                contact_id: "bob_id".to_string(),
+                session_data: client.export_session(&session_id).unwrap(),
+                last_used: 1,
+                created_at: 0,
+            })
+            .unwrap();
+
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        restarted.storage = std::mem::take(&mut alice.storage);
+        restarted.set_transport(Box::new(transport.clone()));
+        restarted
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        assert_eq!(
+            restarted.crypto_manager.client().session_id_for_contact("bob_id"),
+            Some(session_id.as_str())
+        );
+
+        // Восстановленная сессия шифрует без нового bundle ...
+        restarted
+            .send_message_auto("bob_id", None, "after restart")
+            .unwrap();
+        let second = match transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(msg)) => msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        match bob.decrypt_body(&bob_session, &second).unwrap() {
+            MessageBody::Text { text, .. } => assert_eq!(text, "after restart"),
+            other => panic!("expected text body, got {:?}", other),
+        }
+
+        // ... и расшифровывает ответ собеседника
+        let reply = bob
+            .encrypt_body(&bob_session, &MessageBody::new_text("reply"))
+            .unwrap();
+        match restarted
+            .crypto_manager
+            .decrypt_body(&session_id, &reply)
+            .unwrap()
+        {
+            MessageBody::Text { text, .. } => assert_eq!(text, "reply"),
+            other => panic!("expected text body, got {:?}", other),
+        }
     }
 
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
-    fn test_app_state_contacts() {
+    fn test_reconnect_policy_applied_to_transport() {
         let mut state = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        let policy = ReconnectPolicy {
+            base_delay_ms: 250,
+            max_attempts: 3,
+            ..ReconnectPolicy::default()
+        };
+        state.set_reconnect_policy(policy.clone());
+
+        let transport = FlakyTransport::default();
+        state.set_transport(Box::new(transport.clone()));
+        assert_eq!(transport.policy.borrow().as_ref(), Some(&policy));
+        assert_eq!(state.connection_state(), ConnectionState::Connected);
+
+        // Обрыв: транспорт переподключается, AppState сообщает об этом UI
+        transport.reconnecting.set(true);
+        assert_eq!(state.connection_state(), ConnectionState::Reconnecting);
+        assert!(!state.is_connected());
+        transport.reconnecting.set(false);
+        assert!(state.is_connected());
+
+        state.set_reconnect_policy(ReconnectPolicy::disabled());
+        assert!(!transport.policy.borrow().as_ref().unwrap().enabled);
+        assert!(!state.reconnect_state().can_retry());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_drafts_survive_restart_and_clear() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        assert_eq!(alice.load_draft("bob_id").unwrap(), None);
+
+        alice.save_draft("bob_id", "see you at").unwrap();
+        alice.save_draft("bob_id", "see you at noon").unwrap();
+        alice.save_draft("carol_id", "draft for carol").unwrap();
+        assert!(alice.save_draft("bad id", "x").is_err());
+
+        // Текст хранится только в зашифрованном виде
+        let stored = alice.storage.load_draft("bob_id").unwrap().unwrap();
+        assert!(!stored
+            .sealed_text
+            .windows(b"noon".len())
+            .any(|w| w == b"noon"));
+
+        // Перезапуск: новое состояние поверх того же хранилища
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        restarted.storage = std::mem::take(&mut alice.storage);
+        assert!(restarted.load_draft("bob_id").is_err());
+        restarted
+            .load_user("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+        assert_eq!(
+            restarted.load_draft("bob_id").unwrap().as_deref(),
+            Some("see you at noon")
+        );
+
+        restarted.clear_draft("bob_id").unwrap();
+        assert_eq!(restarted.load_draft("bob_id").unwrap(), None);
+        restarted.save_draft("carol_id", "").unwrap();
+        assert_eq!(restarted.load_draft("carol_id").unwrap(), None);
+
+        restarted.save_draft("bob_id", "unsent").unwrap();
+        restarted.clear_all_data().unwrap();
+        assert!(restarted.storage.load_draft("bob_id").unwrap().is_none());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_contact_notes_and_metadata_persist() {
+        let mut state = registered_state("alice_id", "testpass123");
+        state.add_contact("bob_id".to_string(), "bob".to_string()).unwrap();
+
         state
-            .initialize_user("alice".to_string(), "testpass123".to_string())
+            .set_contact_note("bob_id", Some("met at the conference".to_string()))
+            .unwrap();
+        state
+            .set_contact_metadata("bob_id", "nickname", Some("Bobby".to_string()))
+            .unwrap();
+        state
+            .set_contact_metadata("bob_id", "tag", Some("work".to_string()))
             .unwrap();
+        state.set_contact_metadata("bob_id", "tag", None).unwrap();
 
+        // Слишком длинное значение отклоняется и не сохраняется
+        assert!(state
+            .set_contact_metadata("bob_id", "bio", Some("x".repeat(2000)))
+            .is_err());
+        assert!(state.set_contact_note("unknown", Some("x".to_string())).is_err());
+
+        // Перезагрузка из хранилища
+        state.contact_manager.clear_all();
         state
-            .add_contact("contact1".to_string(), "bob".to_string())
+            .load_user("alice_id".to_string(), "testpass123".to_string())
             .unwrap();
 
-        let contacts = state.get_contacts();
-        assert_eq!(contacts.len(), 1);
-        assert_eq!(contacts[0].username, "bob");
+        let bob = state.contact_manager.get_contact("bob_id").unwrap();
+        assert_eq!(bob.notes.as_deref(), Some("met at the conference"));
+        assert_eq!(bob.metadata.len(), 1);
+        assert_eq!(bob.metadata.get("nickname").map(String::as_str), Some("Bobby"));
+
+        // Поля попадают в экспорт контактов
+        let exported = state.contact_manager.export_contacts().unwrap();
+        let mut imported = ContactManager::new();
+        imported.import_contacts(&exported).unwrap();
+        let bob = imported.get_contact("bob_id").unwrap();
+        assert_eq!(bob.notes.as_deref(), Some("met at the conference"));
+        assert_eq!(bob.metadata.get("nickname").map(String::as_str), Some("Bobby"));
+    }
+
+    #[test]
+    fn test_register_response_sets_user_id() {
+        use crate::protocol::messages::{LoginResponseData, RegisterResponseData};
+
+        let mut state = AppState::<ClassicSuiteProvider>::new("register_response_db").unwrap();
+
+        // Отказ: user_id не назначен, причина видна в UI
+        state
+            .handle_server_message(ServerMessage::RegisterResponse(RegisterResponseData {
+                user_id: None,
+                success: false,
+                error: Some("Username taken".to_string()),
+            }))
+            .unwrap();
+        assert_eq!(state.get_user_id(), None);
+        assert_eq!(state.ui_state().error_message.as_deref(), Some("Username taken"));
+
+        // Противоречивый ответ отклоняется и не меняет состояние
+        let inconsistent = ServerMessage::RegisterResponse(RegisterResponseData {
+            user_id: None,
+            success: true,
+            error: None,
+        });
+        assert!(state.handle_server_message(inconsistent).is_err());
+        assert_eq!(state.get_user_id(), None);
+
+        // Успех: сохраняем назначенный сервером UUID
+        let user_id = "550e8400-e29b-41d4-a716-446655440000";
+        state
+            .handle_server_message(ServerMessage::RegisterResponse(RegisterResponseData {
+                user_id: Some(user_id.to_string()),
+                success: true,
+                error: None,
+            }))
+            .unwrap();
+        assert_eq!(state.get_user_id(), Some(user_id));
+        assert_eq!(state.ui_state().error_message, None);
+
+        state
+            .handle_server_message(ServerMessage::LoginResponse(LoginResponseData {
+                success: false,
+                error: Some("Invalid credentials".to_string()),
+            }))
+            .unwrap();
+        assert_eq!(
+            state.ui_state().error_message.as_deref(),
+            Some("Invalid credentials")
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_send_message_auto_creates_session() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+
+        // Без сессии и без bundle отправить нельзя
+        assert!(alice.send_message_auto("bob_id", None, "hi").is_err());
+        assert!(transport.sent.borrow().is_empty());
+
+        let first_id = alice
+            .send_message_auto("bob_id", Some(&bob_bundle), "hi bob")
+            .unwrap();
+        assert!(alice.crypto_manager.has_session("bob_id"));
+        assert_eq!(alice.crypto_manager.client().session_count(), 1);
+
+        // Повторная отправка использует ту же сессию
+        alice.send_message_auto("bob_id", None, "again").unwrap();
+        assert_eq!(alice.crypto_manager.client().session_count(), 1);
+
+        let sent: Vec<ChatMessage> = transport
+            .sent
+            .borrow()
+            .iter()
+            .map(|msg| match msg {
+                ClientMessage::SendMessage(chat_msg) => chat_msg.clone(),
+                other => panic!("expected SendMessage, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].id, first_id);
+        assert!(alice.storage.load_message(&first_id).unwrap().is_some());
+
+        let first = sent[0].to_encrypted().unwrap();
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &first)
+            .unwrap();
+        let texts: Vec<String> = sent
+            .iter()
+            .map(|msg| {
+                let body = bob
+                    .decrypt_body(&bob_session, &msg.to_encrypted().unwrap())
+                    .unwrap();
+                match body {
+                    MessageBody::Text { text, .. } => text,
+                    other => panic!("expected text body, got {:?}", other),
+                }
+            })
+            .collect();
+        assert_eq!(texts, vec!["hi bob", "again"]);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_device_link_continues_conversation() {
+        let mut primary = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        primary.set_transport(Box::new(transport.clone()));
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let alice_bundle = primary.crypto_manager.export_registration_bundle().unwrap();
+        primary
+            .send_message_auto("bob_id", Some(&bob_bundle), "hi bob")
+            .unwrap();
+        let first = match &transport.sent.borrow()[0] {
+            ClientMessage::SendMessage(chat_msg) => chat_msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &first)
+            .unwrap();
+        bob.decrypt_body(&bob_session, &first).unwrap();
+
+        // Новое устройство получает ключи и сессии основного
+        let mut linked = AppState::<ClassicSuiteProvider>::new("linked_db").unwrap();
+        let request = linked.begin_device_link().unwrap();
+        let response = primary.respond_device_link(&request).unwrap();
+        linked.complete_device_link(&response, "devicepass1").unwrap();
+
+        assert_eq!(linked.get_user_id(), Some("alice_id"));
+        assert!(linked.is_unlocked());
+        assert_eq!(
+            linked.crypto_manager.fingerprint().unwrap(),
+            primary.crypto_manager.fingerprint().unwrap()
+        );
+        assert_eq!(linked.message_count("bob_id").unwrap(), 1);
+        // Сессия из архива привязки сохранена и переживет перезапуск
+        assert!(linked.storage.load_session_for_contact("bob_id").unwrap().is_some());
+
+        // Ответ Боба расшифровывается на привязанном устройстве
+        let reply = bob
+            .encrypt_body(&bob_session, &MessageBody::new_text("hi alice"))
+            .unwrap();
+        let session_id = linked
+            .crypto_manager
+            .client()
+            .session_id_for_contact("bob_id")
+            .unwrap()
+            .to_string();
+        match linked.crypto_manager.decrypt_body(&session_id, &reply).unwrap() {
+            MessageBody::Text { text, .. } => assert_eq!(text, "hi alice"),
+            other => panic!("expected text body, got {:?}", other),
+        }
+
+        // Одноразовый ключ израсходован, повтор ответа не принимается
+        assert!(linked.complete_device_link(&response, "devicepass1").is_err());
+        // Зарегистрированное устройство не начинает привязку
+        assert!(primary.begin_device_link().is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_send_message_auto_uses_cached_bundle() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_transport(Box::new(MockTransport::default()));
+
+        let bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let data = bob
+            .export_registration_bundle()
+            .unwrap()
+            .to_bundle_data("bob_id");
+
+        alice
+            .add_contact("bob_id".to_string(), "bob".to_string())
+            .unwrap();
+        alice
+            .update_contact_bundle(
+                "bob_id",
+                PublicKeyBundle {
+                    identity_public: data.identity_public,
+                    signed_prekey_public: data.signed_prekey_public,
+                    signature: data.signature,
+                    verifying_key: data.verifying_key,
+                    signed_prekey_id: data.signed_prekey_id,
+                },
+            )
+            .unwrap();
+
+        alice.send_message_auto("bob_id", None, "hi").unwrap();
+        assert!(alice.crypto_manager.has_session("bob_id"));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_send_message_stores_ciphertext() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_transport(Box::new(MockTransport::default()));
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let session_id = alice
+            .crypto_manager
+            .get_or_init_sending_session("bob_id", &bob_bundle)
+            .unwrap();
+
+        let plaintext = "top secret plaintext";
+        let message_id = alice
+            .send_message(
+                &ContactId::new("bob_id").unwrap(),
+                &SessionId::new(session_id).unwrap(),
+                plaintext,
+            )
+            .unwrap();
+
+        // В хранилище попадает шифротекст сессии, а не base64 открытого текста
+        let stored = alice.storage.load_message(&message_id).unwrap().unwrap();
+        assert_eq!(stored.status, MessageStatus::Sent);
+        assert_ne!(stored.encrypted_content, crate::utils::b64::encode(plaintext.as_bytes()));
+        let wire = crate::utils::b64::decode(&stored.encrypted_content).unwrap();
+        assert!(!wire.windows(plaintext.len()).any(|w| w == plaintext.as_bytes()));
+
+        let encrypted = crate::crypto::double_ratchet::EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &encrypted)
+            .unwrap();
+        match bob.decrypt_body(&bob_session, &encrypted).unwrap() {
+            MessageBody::Text { text, .. } => assert_eq!(text, plaintext),
+            other => panic!("expected text body, got {:?}", other),
+        }
+    }
+
+    fn cached_message(id: &str, timestamp: i64) -> StoredMessage {
+        StoredMessage {
+            id: id.to_string(),
+            conversation_id: "bob_id".to_string(),
+            from: "bob_id".to_string(),
+            to: "alice_id".to_string(),
+            encrypted_content: "AQID".to_string(),
+            timestamp,
+            status: MessageStatus::Delivered,
+            local_content: None,
+            ratchet_header: None,
+        }
+    }
+
+    #[test]
+    fn test_insert_sorted_out_of_order() {
+        let mut cache = Vec::new();
+        for (id, timestamp) in [("c", 300), ("a", 100), ("e", 200), ("b", 200), ("d", 50)] {
+            insert_sorted(&mut cache, cached_message(id, timestamp));
+        }
+
+        let order: Vec<(&str, i64)> = cache.iter().map(|m| (m.id.as_str(), m.timestamp)).collect();
+        assert_eq!(order, vec![("d", 50), ("a", 100), ("b", 200), ("e", 200), ("c", 300)]);
+    }
+
+    #[test]
+    fn test_insert_sorted_large_conversation() {
+        // Псевдослучайный порядок прихода сообщений большой беседы
+        let count = 10_000i64;
+        let mut cache = Vec::new();
+        for i in 0..count {
+            let timestamp = (i * 7919) % count / 2;
+            insert_sorted(&mut cache, cached_message(&format!("{:05}", i), timestamp));
+        }
+
+        assert_eq!(cache.len(), count as usize);
+        assert!(cache
+            .windows(2)
+            .all(|w| (w[0].timestamp, &w[0].id) <= (w[1].timestamp, &w[1].id)));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_require_verified_before_send() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        assert!(!alice.require_verified_before_send());
+
+        for (contact_id, username) in [("bob_id", "bob"), ("carol_id", "carol")] {
+            alice
+                .add_contact(contact_id.to_string(), username.to_string())
+                .unwrap();
+        }
+        alice.contact_manager.set_verified("bob_id", true).unwrap();
+
+        let bob_bundle = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap();
+        let carol_bundle = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap();
+
+        // По умолчанию отправка разрешена всем
+        alice.send_message_auto("bob_id", Some(&bob_bundle), "hi bob").unwrap();
+        alice.send_message_auto("carol_id", Some(&carol_bundle), "hi carol").unwrap();
+        assert_eq!(transport.sent.borrow().len(), 2);
+
+        alice.set_require_verified_before_send(true);
+        alice.send_message_auto("bob_id", None, "still fine").unwrap();
+
+        let err = alice.send_message_auto("carol_id", None, "blocked").unwrap_err();
+        assert!(matches!(err, ConstructError::ValidationError(_)));
+        assert!(alice.send_message_auto("unknown_id", None, "blocked").is_err());
+        assert_eq!(transport.sent.borrow().len(), 3);
+        let carol_messages = alice
+            .storage
+            .load_messages_for_conversation("carol_id", usize::MAX, 0)
+            .unwrap();
+        assert_eq!(carol_messages.len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_message_count() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_transport(Box::new(MockTransport::default()));
+        let bob_bundle = CryptoCore::<ClassicSuiteProvider>::new()
+            .unwrap()
+            .export_registration_bundle()
+            .unwrap();
+        assert_eq!(alice.message_count("bob_id").unwrap(), 0);
+
+        let first = alice
+            .send_message_auto("bob_id", Some(&bob_bundle), "one")
+            .unwrap();
+        alice.send_message_auto("bob_id", None, "two").unwrap();
+        assert_eq!(alice.message_count("bob_id").unwrap(), 2);
+        assert_eq!(alice.message_count("carol_id").unwrap(), 0);
+
+        alice.storage.delete_message(&first).unwrap();
+        assert_eq!(alice.message_count("bob_id").unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_note_to_self() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        alice.set_transport(Box::new(MockTransport::default()));
+        let own_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+
+        let err = alice
+            .send_message_auto("alice_id", Some(&own_bundle), "note")
+            .unwrap_err();
+        assert!(matches!(err, ConstructError::ValidationError(_)));
+        assert_eq!(alice.message_count("alice_id").unwrap(), 0);
+
+        alice.set_note_to_self_enabled(true);
+        alice
+            .send_message_auto("alice_id", Some(&own_bundle), "note")
+            .unwrap();
+        assert_eq!(alice.message_count("alice_id").unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_lazy_session_restore() {
+        let mut alice = registered_state("alice_id", "testpass123");
+        let transport = MockTransport::default();
+        alice.set_transport(Box::new(transport.clone()));
+        for (contact_id, username) in [("bob_id", "bob"), ("carol_id", "carol")] {
+            alice
+                .add_contact(contact_id.to_string(), username.to_string())
+                .unwrap();
+        }
+
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = bob.export_registration_bundle().unwrap();
+        let alice_bundle = alice.crypto_manager.export_registration_bundle().unwrap();
+        alice
+            .send_message_auto("bob_id", Some(&bob_bundle), "before restart")
+            .unwrap();
+
+        let first = match transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(msg)) => msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        let bob_session = bob
+            .init_receiving_session("alice_id", &alice_bundle, &first)
+            .unwrap();
+        bob.decrypt_body(&bob_session, &first).unwrap();
+
+        // Сохраняем сессию и "перезапускаем" приложение на том же хранилище
+        let client = alice.crypto_manager.client();
+        let session_id = client.session_id_for_contact("bob_id").unwrap().to_string();
+        alice
+            .storage
+            .save_session(StoredSession {
+                session_id: session_id.clone(),
+                contact_id: "bob_id".to_string(),
+                session_data: client.export_session(&session_id).unwrap(),
+                last_used: 1,
+                created_at: 0,
+            })
+            .unwrap();
+        alice
+            .storage
+            .save_session(StoredSession {
+                session_id: "carol_session".to_string(),
+                contact_id: "carol_id".to_string(),
+                session_data: vec![1, 2, 3],
+                last_used: 1,
+                created_at: 0,
+            })
+            .unwrap();
+
+        let mut restarted = AppState::<ClassicSuiteProvider>::new("test_db").unwrap();
+        restarted.storage = std::mem::take(&mut alice.storage);
+        restarted.set_transport(Box::new(transport.clone()));
+        restarted
+            .begin_restore("alice_id".to_string(), "testpass123".to_string())
+            .unwrap();
+
+        assert_eq!(restarted.get_contacts().len(), 2);
+        assert_eq!(restarted.crypto_manager.client().session_count(), 0);
+        assert_eq!(
+            restarted.restore_progress(),
+            RestoreProgress { total: 2, done: 0, failed: 0 }
+        );
+
+        // Первое обращение к контакту восстанавливает только его сессию
+        restarted
+            .send_message_auto("bob_id", None, "after restart")
+            .unwrap();
+        assert_eq!(
+            restarted.crypto_manager.client().session_id_for_contact("bob_id"),
+            Some(session_id.as_str())
+        );
+        assert_eq!(restarted.restore_progress().done, 1);
+        assert!(!restarted.restore_progress().is_complete());
+
+        let second = match transport.sent.borrow().last() {
+            Some(ClientMessage::SendMessage(msg)) => msg.to_encrypted().unwrap(),
+            other => panic!("expected SendMessage, got {:?}", other),
+        };
+        match bob.decrypt_body(&bob_session, &second).unwrap() {
+            MessageBody::Text { text, .. } => assert_eq!(text, "after restart"),
+            other => panic!("expected text body, got {:?}", other),
+        }
+
+        let progress = restarted.restore_remaining().unwrap();
+        assert!(progress.is_complete());
+        assert_eq!(progress, RestoreProgress { total: 2, done: 1, failed: 1 });
+        assert!(restarted.crypto_manager.client().session_id_for_contact("carol_id").is_none());
+        assert!(restarted.take_events().iter().any(|e| matches!(
+            e,
+            AppEvent::SessionRestoreFailed { contact_id, .. } if contact_id == "carol_id"
+        )));
     }
-}
\ No newline at end of file
+}
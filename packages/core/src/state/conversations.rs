@@ -24,8 +24,9 @@ impl ConversationState {
         }
     }
 
-    /// Добавить сообщение в беседу
+    /// Добавить сообщение в беседу; сообщение с тем же id заменяется
     pub fn add_message(&mut self, msg: StoredMessage) {
+        self.messages.retain(|m| m.id != msg.id);
         self.messages.push(msg);
         // Сортировка по timestamp для поддержания порядка
         self.messages.sort_by_key(|m| m.timestamp);
@@ -174,11 +175,21 @@ mod tests {
             encrypted_content: "AQID".to_string(),
             timestamp: 100,
             status: MessageStatus::Sent,
+            local_content: None,
+            ratchet_header: None,
         };
 
-        conv.add_message(msg1);
+        conv.add_message(msg1.clone());
         assert_eq!(conv.message_count(), 1);
         assert_eq!(conv.get_last_message().unwrap().id, "msg1");
+
+        // Повторное добавление того же id обновляет запись
+        conv.add_message(StoredMessage {
+            status: MessageStatus::Delivered,
+            ..msg1
+        });
+        assert_eq!(conv.message_count(), 1);
+        assert_eq!(conv.get_last_message().unwrap().status, MessageStatus::Delivered);
     }
 
     #[test]
@@ -193,6 +204,8 @@ mod tests {
             encrypted_content: "AQID".to_string(),
             timestamp: 100,
             status: MessageStatus::Sent,
+            local_content: None,
+            ratchet_header: None,
         };
 
         manager.add_message("contact1", msg1);
@@ -213,6 +226,8 @@ mod tests {
             encrypted_content: "BAUG".to_string(),
             timestamp: 100,
             status: MessageStatus::Delivered,
+            local_content: None,
+            ratchet_header: None,
         };
 
         manager.add_message("contact1", msg1);
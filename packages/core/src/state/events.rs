@@ -0,0 +1,45 @@
+// События приложения для UI
+// AppState складывает события в очередь, UI периодически забирает их через take_events()
+
+use crate::storage::models::MessageStatus;
+use serde::{Deserialize, Serialize};
+
+/// Событие, о котором нужно уведомить UI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppEvent {
+    /// Identity ключ контакта не совпадает с ключом, на котором установлена сессия
+    IdentityKeyMismatch {
+        contact_id: String,
+        session_id: String,
+    },
+    /// Сохраненную сессию не удалось восстановить при загрузке пользователя
+    SessionRestoreFailed { contact_id: String, reason: String },
+    /// В серверной нумерации беседы пропущены номера: сообщения
+    /// `expected..received` не дошли
+    SequenceGap {
+        conversation_id: String,
+        expected: u64,
+        received: u64,
+    },
+    /// Статус исходящего сообщения изменился по Ack сервера
+    MessageStatusChanged {
+        message_id: String,
+        status: MessageStatus,
+    },
+    /// Опубликованный хеш bundle контакта отличается от наблюдавшегося ранее
+    /// при том же identity ключе - возможна подмена ключей сервером
+    KeyDigestMismatch {
+        contact_id: String,
+        expected: String,
+        received: String,
+    },
+    /// Собеседник сбросил сессию (экстренная смена ключей); новая сессия
+    /// будет установлена при следующем сообщении
+    SessionReset { contact_id: String },
+    /// Новое входящее сообщение, о котором стоит уведомить пользователя
+    /// (см. `NotificationPolicy`)
+    MessageReceived {
+        conversation_id: String,
+        message_id: String,
+    },
+}
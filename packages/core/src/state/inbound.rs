@@ -0,0 +1,107 @@
+// Очередь входящих расшифрованных сообщений
+// Заполняется обработчиком входящих сообщений, UI забирает ее целиком (poll)
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Максимальный размер очереди по умолчанию
+pub const DEFAULT_INBOUND_CAPACITY: usize = 500;
+
+/// Расшифрованное входящее сообщение
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InboundMessage {
+    pub message_id: String,
+    pub from: String,
+    pub session_id: String,
+    pub text: String,
+    pub timestamp: i64,
+}
+
+/// Ограниченная очередь входящих сообщений.
+/// При переполнении вытесняются самые старые сообщения
+#[derive(Debug)]
+pub struct InboundQueue {
+    messages: VecDeque<InboundMessage>,
+    capacity: usize,
+    dropped: u64,
+}
+
+impl InboundQueue {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_INBOUND_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            capacity: capacity.max(1),
+            dropped: 0,
+        }
+    }
+
+    /// Добавить сообщение
+    pub fn push(&mut self, msg: InboundMessage) {
+        if self.messages.len() >= self.capacity {
+            self.messages.pop_front();
+            self.dropped += 1;
+        }
+        self.messages.push_back(msg);
+    }
+
+    /// Забрать все накопленные сообщения (в порядке поступления)
+    pub fn drain(&mut self) -> Vec<InboundMessage> {
+        self.messages.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Сколько сообщений вытеснено из-за переполнения
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl Default for InboundQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inbound(n: usize) -> InboundMessage {
+        InboundMessage {
+            message_id: format!("m{}", n),
+            from: "bob".to_string(),
+            session_id: "s1".to_string(),
+            text: format!("text {}", n),
+            timestamp: n as i64,
+        }
+    }
+
+    #[test]
+    fn test_inbound_queue_is_bounded() {
+        let mut queue = InboundQueue::with_capacity(3);
+        for n in 0..5 {
+            queue.push(inbound(n));
+        }
+
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.dropped(), 2);
+
+        let drained = queue.drain();
+        let ids: Vec<_> = drained.iter().map(|m| m.message_id.as_str()).collect();
+        assert_eq!(ids, vec!["m2", "m3", "m4"]);
+        assert!(queue.is_empty());
+        assert!(queue.drain().is_empty());
+    }
+}
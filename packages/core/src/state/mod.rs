@@ -3,3 +3,7 @@
 pub mod app;
 pub mod contacts;
 pub mod conversations;
+pub mod events;
+pub mod inbound;
+pub mod notifications;
+pub mod plaintext_cache;
@@ -0,0 +1,77 @@
+// Политика уведомлений о входящих сообщениях
+// AppState спрашивает политику перед обработкой сообщения; приложение может подставить свою
+
+use crate::api::contacts::Contact;
+
+/// Что сделать с входящим сообщением
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationDecision {
+    /// Не сохранять и не показывать (заблокированный контакт)
+    Drop,
+    /// Сохранить и учесть в непрочитанных, но без уведомления (контакт без звука)
+    Silent,
+    /// Сохранить без счетчика непрочитанных и уведомления (беседа открыта)
+    NoBadge,
+    /// Сохранить, учесть в непрочитанных и уведомить UI (`AppEvent::MessageReceived`)
+    Notify,
+}
+
+/// Данные, по которым принимается решение
+#[derive(Debug, Clone, Copy)]
+pub struct NotificationContext<'a> {
+    pub contact_id: &'a str,
+    /// Карточка отправителя (`None`, если он не в контактах)
+    pub contact: Option<&'a Contact>,
+    /// Открыта ли сейчас беседа с отправителем
+    pub active_conversation: bool,
+}
+
+/// Политика уведомлений (см. `AppState::set_notification_policy`)
+pub trait NotificationPolicy {
+    fn decide(&self, context: &NotificationContext<'_>) -> NotificationDecision;
+}
+
+/// Политика по умолчанию: блокировка важнее всего; в открытой беседе
+/// счетчик не растет и для контакта без звука
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultNotificationPolicy;
+
+impl NotificationPolicy for DefaultNotificationPolicy {
+    fn decide(&self, context: &NotificationContext<'_>) -> NotificationDecision {
+        match context.contact {
+            Some(contact) if contact.blocked => NotificationDecision::Drop,
+            _ if context.active_conversation => NotificationDecision::NoBadge,
+            Some(contact) if contact.muted => NotificationDecision::Silent,
+            _ => NotificationDecision::Notify,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::contacts::create_contact;
+
+    #[test]
+    fn test_default_policy_precedence() {
+        let decide = |contact: Option<&Contact>, active_conversation| {
+            DefaultNotificationPolicy.decide(&NotificationContext {
+                contact_id: "bob_id",
+                contact,
+                active_conversation,
+            })
+        };
+
+        let mut bob = create_contact("bob_id".to_string(), "bob".to_string());
+        assert_eq!(decide(None, false), NotificationDecision::Notify);
+        assert_eq!(decide(Some(&bob), false), NotificationDecision::Notify);
+        assert_eq!(decide(Some(&bob), true), NotificationDecision::NoBadge);
+
+        bob.muted = true;
+        assert_eq!(decide(Some(&bob), false), NotificationDecision::Silent);
+        assert_eq!(decide(Some(&bob), true), NotificationDecision::NoBadge);
+
+        bob.blocked = true;
+        assert_eq!(decide(Some(&bob), true), NotificationDecision::Drop);
+    }
+}
@@ -0,0 +1,114 @@
+// LRU-кеш расшифрованного содержимого сообщений для повторного показа бесед
+// Значения обнуляются (zeroize) при вытеснении и очистке
+
+use std::collections::{HashMap, VecDeque};
+use zeroize::Zeroizing;
+
+/// Размер кеша по умолчанию (количество сообщений)
+pub const DEFAULT_PLAINTEXT_CACHE_CAPACITY: usize = 1000;
+
+/// Ограниченный LRU-кеш `message_id -> plaintext`.
+/// При переполнении вытесняется давно не использованная запись
+#[derive(Debug)]
+pub struct PlaintextCache {
+    entries: HashMap<String, Zeroizing<Vec<u8>>>,
+    /// Порядок использования: в начале - самые старые
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl PlaintextCache {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_PLAINTEXT_CACHE_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Получить plaintext и отметить запись как недавно использованную
+    pub fn get(&mut self, message_id: &str) -> Option<&[u8]> {
+        if !self.entries.contains_key(message_id) {
+            return None;
+        }
+        self.touch(message_id);
+        self.entries.get(message_id).map(|p| p.as_slice())
+    }
+
+    /// Сохранить plaintext, вытеснив самую старую запись при переполнении
+    pub fn insert(&mut self, message_id: &str, plaintext: Vec<u8>) {
+        if self
+            .entries
+            .insert(message_id.to_string(), Zeroizing::new(plaintext))
+            .is_some()
+        {
+            self.touch(message_id);
+            return;
+        }
+
+        self.order.push_back(message_id.to_string());
+        while self.entries.len() > self.capacity {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            // Zeroizing обнуляет plaintext при удалении
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Удалить все записи (при блокировке и очистке данных)
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn touch(&mut self, message_id: &str) {
+        if let Some(pos) = self.order.iter().position(|id| id == message_id) {
+            if let Some(id) = self.order.remove(pos) {
+                self.order.push_back(id);
+            }
+        }
+    }
+}
+
+impl Default for PlaintextCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = PlaintextCache::with_capacity(2);
+        cache.insert("m1", b"one".to_vec());
+        cache.insert("m2", b"two".to_vec());
+
+        // m1 использован последним - вытесняется m2
+        assert_eq!(cache.get("m1"), Some(&b"one"[..]));
+        cache.insert("m3", b"three".to_vec());
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("m2").is_none());
+        assert_eq!(cache.get("m1"), Some(&b"one"[..]));
+        assert_eq!(cache.get("m3"), Some(&b"three"[..]));
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
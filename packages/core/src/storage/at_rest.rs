@@ -0,0 +1,141 @@
+// Шифрование данных в хранилище (at-rest)
+// Ключ выводится из мастер-ключа через HKDF и не совпадает ни с мастер-ключом,
+// ни с транспортными ключами Double Ratchet
+
+use crate::crypto::master_key::{decrypt_with_master_key, encrypt_with_master_key};
+use crate::storage::models::{StoredMessage, StoredSession};
+use crate::utils::error::{ConstructError, Result};
+use base64::{engine::general_purpose, Engine as _};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+/// HKDF info для ключа хранилища
+const AT_REST_KEY_INFO: &[u8] = b"construct-at-rest-key-v1";
+
+/// Префикс зашифрованных бинарных полей (отличает их от старых незашифрованных записей)
+const SEALED_BYTES_MAGIC: &[u8] = b"CRS1";
+
+/// Префикс зашифрованных текстовых полей
+const SEALED_TEXT_PREFIX: &str = "rest1:";
+
+/// Шифр для записей хранилища
+pub struct AtRestCipher {
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl AtRestCipher {
+    /// Вывести ключ хранилища из мастер-ключа
+    pub fn from_master_key(master_key: &[u8; 32]) -> Result<Self> {
+        let hkdf = Hkdf::<Sha256>::new(None, master_key);
+        let mut key = Zeroizing::new([0u8; 32]);
+        hkdf.expand(AT_REST_KEY_INFO, key.as_mut())
+            .map_err(|e| ConstructError::CryptoError(format!("At-rest key derivation failed: {}", e)))?;
+
+        Ok(Self { key })
+    }
+
+    /// Зашифровать бинарные данные (magic || nonce || ciphertext)
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut sealed = SEALED_BYTES_MAGIC.to_vec();
+        sealed.extend(encrypt_with_master_key(&self.key, data)?);
+        Ok(sealed)
+    }
+
+    /// Расшифровать данные, зашифрованные `seal`.
+    /// Записи без префикса (сохраненные до включения шифрования) возвращаются как есть
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match data.strip_prefix(SEALED_BYTES_MAGIC) {
+            Some(sealed) => Ok(decrypt_with_master_key(&self.key, sealed)?.to_vec()),
+            None => Ok(data.to_vec()),
+        }
+    }
+
+    /// Зашифровать текстовое поле
+    pub fn seal_text(&self, text: &str) -> Result<String> {
+        let sealed = encrypt_with_master_key(&self.key, text.as_bytes())?;
+        Ok(format!(
+            "{}{}",
+            SEALED_TEXT_PREFIX,
+            general_purpose::STANDARD.encode(sealed)
+        ))
+    }
+
+    /// Расшифровать текстовое поле, зашифрованное `seal_text`
+    pub fn open_text(&self, text: &str) -> Result<String> {
+        let Some(encoded) = text.strip_prefix(SEALED_TEXT_PREFIX) else {
+            return Ok(text.to_string());
+        };
+
+        let sealed = general_purpose::STANDARD.decode(encoded).map_err(|e| {
+            ConstructError::StorageError(format!("Corrupted at-rest record: {}", e))
+        })?;
+        let plaintext = decrypt_with_master_key(&self.key, &sealed)?;
+
+        String::from_utf8(plaintext.to_vec()).map_err(|e| {
+            ConstructError::StorageError(format!("Corrupted at-rest record: {}", e))
+        })
+    }
+
+    /// Зашифровать содержимое сообщения (id, беседа и timestamp остаются открытыми для индексов)
+    pub fn seal_message(&self, mut msg: StoredMessage) -> Result<StoredMessage> {
+        msg.encrypted_content = self.seal_text(&msg.encrypted_content)?;
+        Ok(msg)
+    }
+
+    /// Расшифровать содержимое сообщения
+    pub fn open_message(&self, mut msg: StoredMessage) -> Result<StoredMessage> {
+        msg.encrypted_content = self.open_text(&msg.encrypted_content)?;
+        Ok(msg)
+    }
+
+    /// Зашифровать состояние сессии
+    pub fn seal_session(&self, mut session: StoredSession) -> Result<StoredSession> {
+        session.session_data = self.seal(&session.session_data)?;
+        Ok(session)
+    }
+
+    /// Расшифровать состояние сессии
+    pub fn open_session(&self, mut session: StoredSession) -> Result<StoredSession> {
+        session.session_data = self.open(&session.session_data)?;
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_at_rest_key_differs_from_master_key() {
+        let master_key = [7u8; 32];
+        let cipher = AtRestCipher::from_master_key(&master_key).unwrap();
+        assert_ne!(*cipher.key, master_key);
+
+        // Данные, зашифрованные мастер-ключом напрямую, не открываются ключом хранилища
+        let sealed = encrypt_with_master_key(&master_key, b"secret").unwrap();
+        let mut prefixed = SEALED_BYTES_MAGIC.to_vec();
+        prefixed.extend(sealed);
+        assert!(cipher.open(&prefixed).is_err());
+    }
+
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let cipher = AtRestCipher::from_master_key(&[1u8; 32]).unwrap();
+
+        let sealed = cipher.seal_text("AQID").unwrap();
+        assert!(!sealed.contains("AQID"));
+        assert_eq!(cipher.open_text(&sealed).unwrap(), "AQID");
+
+        let sealed = cipher.seal(b"session state").unwrap();
+        assert_eq!(cipher.open(&sealed).unwrap(), b"session state");
+
+        // Старые незашифрованные записи читаются как есть
+        assert_eq!(cipher.open_text("AQID").unwrap(), "AQID");
+        assert_eq!(cipher.open(b"legacy").unwrap(), b"legacy");
+
+        // Другой мастер-ключ не открывает запись
+        let other = AtRestCipher::from_master_key(&[2u8; 32]).unwrap();
+        assert!(other.open(&sealed).is_err());
+    }
+}
@@ -1,7 +1,11 @@
 // IndexedDB хранилище для WASM
 
+use crate::storage::at_rest::AtRestCipher;
 use crate::storage::models::*;
+use crate::storage::Storage;
 use crate::utils::error::{ConstructError, Result};
+use std::future::Future;
+use std::pin::Pin;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
@@ -15,6 +19,8 @@ use web_sys::{IdbDatabase, IdbRequest, IdbTransactionMode};
 pub struct IndexedDbStorage {
     #[cfg(target_arch = "wasm32")]
     db: Option<IdbDatabase>,
+    /// Шифрование содержимого сообщений и сессий (включается после разблокировки)
+    at_rest: Option<AtRestCipher>,
 }
 
 impl IndexedDbStorage {
@@ -22,72 +28,63 @@ impl IndexedDbStorage {
         Self {
             #[cfg(target_arch = "wasm32")]
             db: None,
+            at_rest: None,
         }
     }
 
-    /// Инициализировать базу данных
-    #[cfg(target_arch = "wasm32")]
-    pub async fn init(&mut self) -> Result<()> {
-        let window = web_sys::window()
-            .ok_or_else(|| ConstructError::StorageError("No window object".to_string()))?;
-
-        let idb = window
-            .indexed_db()
-            .map_err(|e| ConstructError::StorageError(format!("IndexedDB not available: {:?}", e)))?
-            .ok_or_else(|| ConstructError::StorageError("IndexedDB not supported".to_string()))?;
-
-        // Открыть или создать БД
-        let open_request = idb
-            .open_with_u32("construct_messenger", 1)
-            .map_err(|e| ConstructError::StorageError(format!("Failed to open DB: {:?}", e)))?;
-        
-        let onupgradeneeded = Closure::wrap(Box::new(move |event: web_sys::IdbVersionChangeEvent| {
-            let target = event.target().expect("Event should have target");
-            let request: IdbRequest = target.dyn_into().expect("Target should be IdbRequest");
-            let db: IdbDatabase = request.result().unwrap().dyn_into().unwrap();
-
-            // Создать object stores (версия БД контролирует создание)
-            // Если версия увеличена, создаем все stores заново
-            let params = web_sys::IdbObjectStoreParameters::new();
-            params.set_key_path(&JsValue::from_str("user_id"));
-            let _ = db.create_object_store_with_optional_parameters("private_keys", &params);
-
-            let params = web_sys::IdbObjectStoreParameters::new();
-            params.set_key_path(&JsValue::from_str("session_id"));
-            if let Ok(store) = db.create_object_store_with_optional_parameters("sessions", &params) {
-                // Индекс по contact_id
-                let _ = store.create_index_with_str("contact_id", "contact_id");
-            }
+    /// Включить шифрование записей ключом, выведенным из мастер-ключа
+    pub fn set_at_rest_key(&mut self, master_key: &[u8; 32]) -> Result<()> {
+        self.at_rest = Some(AtRestCipher::from_master_key(master_key)?);
+        Ok(())
+    }
 
-            let params = web_sys::IdbObjectStoreParameters::new();
-            params.set_key_path(&JsValue::from_str("id"));
-            let _ = db.create_object_store_with_optional_parameters("contacts", &params);
+    /// Забыть ключ хранилища (выход пользователя)
+    pub fn clear_at_rest_key(&mut self) {
+        self.at_rest = None;
+    }
 
-            let params = web_sys::IdbObjectStoreParameters::new();
-            params.set_key_path(&JsValue::from_str("id"));
-            if let Ok(store) = db.create_object_store_with_optional_parameters("messages", &params) {
-                // Индексы для поиска
-                let _ = store.create_index_with_str("conversation_id", "conversation_id");
-                let _ = store.create_index_with_str("timestamp", "timestamp");
-            }
+    /// Включено ли шифрование записей
+    pub fn is_encrypted_at_rest(&self) -> bool {
+        self.at_rest.is_some()
+    }
 
-            let params = web_sys::IdbObjectStoreParameters::new();
-            params.set_key_path(&JsValue::from_str("user_id"));
-            let _ = db.create_object_store_with_optional_parameters("metadata", &params);
-        }) as Box<dyn FnMut(_)>);
+    #[cfg(target_arch = "wasm32")]
+    fn seal_message(&self, msg: StoredMessage) -> Result<StoredMessage> {
+        match &self.at_rest {
+            Some(cipher) => cipher.seal_message(msg),
+            None => Ok(msg),
+        }
+    }
 
-        open_request.set_onupgradeneeded(Some(onupgradeneeded.as_ref().unchecked_ref()));
-        onupgradeneeded.forget();
+    #[cfg(target_arch = "wasm32")]
+    fn open_message(&self, msg: StoredMessage) -> Result<StoredMessage> {
+        match &self.at_rest {
+            Some(cipher) => cipher.open_message(msg),
+            None => Ok(msg),
+        }
+    }
 
-        // Дождаться открытия БД
-        let db_promise = idb_open_request_to_promise(&open_request);
-        let db_value = JsFuture::from(db_promise).await
-            .map_err(|e| ConstructError::StorageError(format!("Failed to open database: {:?}", e)))?;
+    #[cfg(target_arch = "wasm32")]
+    fn seal_session(&self, session: StoredSession) -> Result<StoredSession> {
+        match &self.at_rest {
+            Some(cipher) => cipher.seal_session(session),
+            None => Ok(session),
+        }
+    }
 
-        let db: IdbDatabase = db_value.dyn_into()
-            .map_err(|_| ConstructError::StorageError("Invalid database object".to_string()))?;
+    #[cfg(target_arch = "wasm32")]
+    fn open_session(&self, session: StoredSession) -> Result<StoredSession> {
+        match &self.at_rest {
+            Some(cipher) => cipher.open_session(session),
+            None => Ok(session),
+        }
+    }
 
-        self.db = Some(db);
+    /// Инициализировать базу данных: открыть ее, при необходимости создав
+    /// или обновив схему
+    #[cfg(target_arch = "wasm32")]
+    pub async fn init(&mut self) -> Result<()> {
+        self.db = Some(open_database().await?);
         Ok(())
     }
 
@@ -101,8 +98,9 @@ impl IndexedDbStorage {
 
     #[cfg(target_arch = "wasm32")]
     fn get_db(&self) -> Result<&IdbDatabase> {
-        self.db.as_ref()
-            .ok_or_else(|| ConstructError::StorageError("Database not initialized".to_string()))
+        self.db.as_ref().ok_or_else(|| {
+            ConstructError::StorageError("Database not initialized (call init() first)".to_string())
+        })
     }
 
     #[cfg(target_arch = "wasm32")]
@@ -243,6 +241,7 @@ impl IndexedDbStorage {
 
     #[cfg(target_arch = "wasm32")]
     pub async fn save_session(&self, session: StoredSession) -> Result<()> {
+        let session = self.seal_session(session)?;
         let value = serde_wasm_bindgen::to_value(&session)
             .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize session: {:?}", e)))?;
 
@@ -263,7 +262,7 @@ impl IndexedDbStorage {
             Some(v) => {
                 let session: StoredSession = serde_wasm_bindgen::from_value(v)
                     .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize session: {:?}", e)))?;
-                Ok(Some(session))
+                Ok(Some(self.open_session(session)?))
             }
             None => Ok(None)
         }
@@ -274,6 +273,52 @@ impl IndexedDbStorage {
         Ok(None)
     }
 
+    /// Последняя использованная сессия с контактом (поиск по индексу contact_id)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_session_for_contact(&self, contact_id: &str) -> Result<Option<StoredSession>> {
+        let db = self.get_db()?;
+
+        let transaction = db
+            .transaction_with_str("sessions")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to create transaction: {:?}", e)))?;
+
+        let store = transaction
+            .object_store("sessions")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get store: {:?}", e)))?;
+
+        let index = store
+            .index("contact_id")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get index: {:?}", e)))?;
+
+        let key = JsValue::from_str(contact_id);
+        let request = index
+            .get_all_with_key(&key)
+            .map_err(|e| ConstructError::StorageError(format!("Failed to query index: {:?}", e)))?;
+
+        let promise = idb_request_to_promise(&request);
+        let result = JsFuture::from(promise).await
+            .map_err(|e| ConstructError::StorageError(format!("Query operation failed: {:?}", e)))?;
+
+        let array: js_sys::Array = result.dyn_into()
+            .map_err(|_| ConstructError::StorageError("Invalid array result".to_string()))?;
+
+        let sessions: Vec<StoredSession> = array.iter()
+            .map(|v| serde_wasm_bindgen::from_value(v))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize sessions: {:?}", e)))?;
+
+        sessions
+            .into_iter()
+            .max_by_key(|s| s.last_used)
+            .map(|s| self.open_session(s))
+            .transpose()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_session_for_contact(&self, _contact_id: &str) -> Result<Option<StoredSession>> {
+        Ok(None)
+    }
+
     #[cfg(target_arch = "wasm32")]
     pub async fn load_all_sessions(&self) -> Result<Vec<StoredSession>> {
         let values = self.get_all_values("sessions").await?;
@@ -282,7 +327,7 @@ impl IndexedDbStorage {
         for value in values {
             let session: StoredSession = serde_wasm_bindgen::from_value(value)
                 .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize session: {:?}", e)))?;
-            sessions.push(session);
+            sessions.push(self.open_session(session)?);
         }
 
         Ok(sessions)
@@ -342,6 +387,7 @@ impl IndexedDbStorage {
 
     #[cfg(target_arch = "wasm32")]
     pub async fn save_message(&self, msg: StoredMessage) -> Result<()> {
+        let msg = self.seal_message(msg)?;
         let value = serde_wasm_bindgen::to_value(&msg)
             .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize message: {:?}", e)))?;
 
@@ -387,13 +433,17 @@ impl IndexedDbStorage {
         let array: js_sys::Array = result.dyn_into()
             .map_err(|_| ConstructError::StorageError("Invalid array result".to_string()))?;
 
-        let mut messages: Vec<StoredMessage> = array.iter()
+        let messages: Vec<StoredMessage> = array.iter()
             .map(|v| serde_wasm_bindgen::from_value(v))
             .collect::<std::result::Result<Vec<_>, _>>()
             .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize messages: {:?}", e)))?;
+        let mut messages = messages
+            .into_iter()
+            .map(|msg| self.open_message(msg))
+            .collect::<Result<Vec<_>>>()?;
 
-        // Сортировать по timestamp
-        messages.sort_by_key(|m| m.timestamp);
+        // Сортировать по timestamp, при равных - по id
+        messages.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
 
         // Применить offset и limit
         let messages: Vec<StoredMessage> = messages
@@ -415,6 +465,166 @@ impl IndexedDbStorage {
         Ok(Vec::new())
     }
 
+    /// Самые новые `limit` сообщений по всем беседам, от новых к старым.
+    /// Порядок берется из глобального индекса `timestamp`
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_recent(&self, limit: usize) -> Result<Vec<StoredMessage>> {
+        let db = self.get_db()?;
+
+        let transaction = db
+            .transaction_with_str("messages")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to create transaction: {:?}", e)))?;
+
+        let store = transaction
+            .object_store("messages")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get store: {:?}", e)))?;
+
+        let index = store
+            .index("timestamp")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get index: {:?}", e)))?;
+
+        let request = index
+            .get_all()
+            .map_err(|e| ConstructError::StorageError(format!("Failed to query index: {:?}", e)))?;
+
+        let promise = idb_request_to_promise(&request);
+        let result = JsFuture::from(promise).await
+            .map_err(|e| ConstructError::StorageError(format!("Query operation failed: {:?}", e)))?;
+
+        let array: js_sys::Array = result.dyn_into()
+            .map_err(|_| ConstructError::StorageError("Invalid array result".to_string()))?;
+
+        // Индекс отсортирован по возрастанию timestamp - берем хвост
+        let skip = (array.length() as usize).saturating_sub(limit);
+        let messages: Vec<StoredMessage> = array.iter()
+            .skip(skip)
+            .map(|v| serde_wasm_bindgen::from_value(v))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize messages: {:?}", e)))?;
+
+        messages
+            .into_iter()
+            .rev()
+            .map(|msg| self.open_message(msg))
+            .collect()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_recent(&self, _limit: usize) -> Result<Vec<StoredMessage>> {
+        Ok(Vec::new())
+    }
+
+    /// Количество сообщений в беседе (подсчет по индексу, без загрузки сообщений)
+    #[cfg(target_arch = "wasm32")]
+    pub async fn count_messages(&self, conversation_id: &str) -> Result<usize> {
+        let db = self.get_db()?;
+
+        let transaction = db
+            .transaction_with_str("messages")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to create transaction: {:?}", e)))?;
+
+        let store = transaction
+            .object_store("messages")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get store: {:?}", e)))?;
+
+        let index = store
+            .index("conversation_id")
+            .map_err(|e| ConstructError::StorageError(format!("Failed to get index: {:?}", e)))?;
+
+        let key = JsValue::from_str(conversation_id);
+        let request = index
+            .count_with_key(&key)
+            .map_err(|e| ConstructError::StorageError(format!("Failed to count index: {:?}", e)))?;
+
+        let promise = idb_request_to_promise(&request);
+        let result = JsFuture::from(promise).await
+            .map_err(|e| ConstructError::StorageError(format!("Count operation failed: {:?}", e)))?;
+
+        result
+            .as_f64()
+            .map(|count| count as usize)
+            .ok_or_else(|| ConstructError::StorageError("Invalid count result".to_string()))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn count_messages(&self, _conversation_id: &str) -> Result<usize> {
+        Ok(0)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_message(&self, message_id: &str) -> Result<Option<StoredMessage>> {
+        let key = JsValue::from_str(message_id);
+        let value = self.get_value("messages", &key).await?;
+
+        match value {
+            Some(v) => {
+                let msg: StoredMessage = serde_wasm_bindgen::from_value(v)
+                    .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize message: {:?}", e)))?;
+                Ok(Some(self.open_message(msg)?))
+            }
+            None => Ok(None)
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_message(&self, _message_id: &str) -> Result<Option<StoredMessage>> {
+        Ok(None)
+    }
+
+    /// Обновить статус сообщения; `None`, если сообщения нет
+    #[cfg(target_arch = "wasm32")]
+    pub async fn update_message_status(
+        &self,
+        message_id: &str,
+        status: MessageStatus,
+    ) -> Result<Option<StoredMessage>> {
+        let Some(mut msg) = self.load_message(message_id).await? else {
+            return Ok(None);
+        };
+        msg.status = status;
+        self.save_message(msg.clone()).await?;
+        Ok(Some(msg))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn update_message_status(
+        &self,
+        _message_id: &str,
+        _status: MessageStatus,
+    ) -> Result<Option<StoredMessage>> {
+        Ok(None)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn delete_message(&self, message_id: &str) -> Result<()> {
+        let key = JsValue::from_str(message_id);
+        self.delete_value("messages", &key).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn delete_message(&self, _message_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_all_messages(&self) -> Result<Vec<StoredMessage>> {
+        let values = self.get_all_values("messages").await?;
+
+        let mut messages = Vec::new();
+        for value in values {
+            let msg: StoredMessage = serde_wasm_bindgen::from_value(value)
+                .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize message: {:?}", e)))?;
+            messages.push(self.open_message(msg)?);
+        }
+
+        Ok(messages)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_all_messages(&self) -> Result<Vec<StoredMessage>> {
+        Ok(Vec::new())
+    }
+
     // === Метаданные ===
 
     #[cfg(target_arch = "wasm32")]
@@ -449,6 +659,371 @@ impl IndexedDbStorage {
     pub async fn load_metadata(&self, _user_id: &str) -> Result<Option<StoredAppMetadata>> {
         Ok(None)
     }
+
+    // === Черновики ===
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn save_draft(&self, draft: StoredDraft) -> Result<()> {
+        let value = serde_wasm_bindgen::to_value(&draft)
+            .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize draft: {:?}", e)))?;
+
+        self.put_value("drafts", &value).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn save_draft(&self, _draft: StoredDraft) -> Result<()> {
+        Err(ConstructError::StorageError("IndexedDB only available in WASM".to_string()))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_draft(&self, contact_id: &str) -> Result<Option<StoredDraft>> {
+        let key = JsValue::from_str(contact_id);
+        let value = self.get_value("drafts", &key).await?;
+
+        match value {
+            Some(v) => {
+                let draft: StoredDraft = serde_wasm_bindgen::from_value(v)
+                    .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize draft: {:?}", e)))?;
+                Ok(Some(draft))
+            }
+            None => Ok(None)
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_draft(&self, _contact_id: &str) -> Result<Option<StoredDraft>> {
+        Ok(None)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn delete_draft(&self, contact_id: &str) -> Result<()> {
+        let key = JsValue::from_str(contact_id);
+        self.delete_value("drafts", &key).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn delete_draft(&self, _contact_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    // === Отметки об очистке бесед ===
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn save_clear_marker(&self, marker: StoredClearMarker) -> Result<()> {
+        let value = serde_wasm_bindgen::to_value(&marker)
+            .map_err(|e| ConstructError::SerializationError(format!("Failed to serialize clear marker: {:?}", e)))?;
+
+        self.put_value("clear_markers", &value).await
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn save_clear_marker(&self, _marker: StoredClearMarker) -> Result<()> {
+        Err(ConstructError::StorageError("IndexedDB only available in WASM".to_string()))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub async fn load_clear_marker(&self, conversation_id: &str) -> Result<Option<StoredClearMarker>> {
+        let key = JsValue::from_str(conversation_id);
+        let value = self.get_value("clear_markers", &key).await?;
+
+        match value {
+            Some(v) => {
+                let marker: StoredClearMarker = serde_wasm_bindgen::from_value(v)
+                    .map_err(|e| ConstructError::SerializationError(format!("Failed to deserialize clear marker: {:?}", e)))?;
+                Ok(Some(marker))
+            }
+            None => Ok(None)
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn load_clear_marker(&self, _conversation_id: &str) -> Result<Option<StoredClearMarker>> {
+        Ok(None)
+    }
+
+    // === Утилиты ===
+
+    /// Очистить все object stores
+    #[cfg(target_arch = "wasm32")]
+    pub async fn clear_all(&self) -> Result<()> {
+        for (store_name, _, _) in OBJECT_STORES {
+            let db = self.get_db()?;
+
+            let transaction = db
+                .transaction_with_str_and_mode(store_name, IdbTransactionMode::Readwrite)
+                .map_err(|e| ConstructError::StorageError(format!("Failed to create transaction: {:?}", e)))?;
+
+            let store = transaction
+                .object_store(store_name)
+                .map_err(|e| ConstructError::StorageError(format!("Failed to get store: {:?}", e)))?;
+
+            let request = store
+                .clear()
+                .map_err(|e| ConstructError::StorageError(format!("Failed to clear {}: {:?}", store_name, e)))?;
+
+            let promise = idb_request_to_promise(&request);
+            JsFuture::from(promise).await
+                .map_err(|e| ConstructError::StorageError(format!("Clear operation failed: {:?}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn clear_all(&self) -> Result<()> {
+        Err(ConstructError::StorageError("IndexedDB only available in WASM".to_string()))
+    }
+}
+
+/// Ответ асинхронного хранилища
+type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + 'a>>;
+
+impl Storage for IndexedDbStorage {
+    type Reply<'a, T> = StorageFuture<'a, T> where T: 'a;
+
+    fn set_at_rest_key(&mut self, master_key: &[u8; 32]) -> Result<()> {
+        IndexedDbStorage::set_at_rest_key(self, master_key)
+    }
+
+    fn clear_at_rest_key(&mut self) {
+        IndexedDbStorage::clear_at_rest_key(self)
+    }
+
+    fn save_private_keys(&mut self, keys: StoredPrivateKeys) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_private_keys(self, keys))
+    }
+
+    fn load_private_keys<'a>(
+        &'a self,
+        user_id: &'a str,
+    ) -> Self::Reply<'a, Option<StoredPrivateKeys>> {
+        Box::pin(IndexedDbStorage::load_private_keys(self, user_id))
+    }
+
+    fn save_session(&mut self, session: StoredSession) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_session(self, session))
+    }
+
+    fn load_session_for_contact<'a>(
+        &'a self,
+        contact_id: &'a str,
+    ) -> Self::Reply<'a, Option<StoredSession>> {
+        Box::pin(IndexedDbStorage::load_session_for_contact(self, contact_id))
+    }
+
+    fn load_all_sessions(&self) -> Self::Reply<'_, Vec<StoredSession>> {
+        Box::pin(IndexedDbStorage::load_all_sessions(self))
+    }
+
+    fn delete_session<'a>(&'a mut self, session_id: &'a str) -> Self::Reply<'a, ()> {
+        Box::pin(IndexedDbStorage::delete_session(self, session_id))
+    }
+
+    fn save_contact(&mut self, contact: StoredContact) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_contact(self, contact))
+    }
+
+    fn load_all_contacts(&self) -> Self::Reply<'_, Vec<StoredContact>> {
+        Box::pin(IndexedDbStorage::load_all_contacts(self))
+    }
+
+    fn save_message(&mut self, msg: StoredMessage) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_message(self, msg))
+    }
+
+    fn load_message<'a>(&'a self, message_id: &'a str) -> Self::Reply<'a, Option<StoredMessage>> {
+        Box::pin(IndexedDbStorage::load_message(self, message_id))
+    }
+
+    fn load_messages_for_conversation<'a>(
+        &'a self,
+        conversation_id: &'a str,
+        limit: usize,
+        offset: usize,
+    ) -> Self::Reply<'a, Vec<StoredMessage>> {
+        Box::pin(IndexedDbStorage::load_messages_for_conversation(self, conversation_id, limit, offset))
+    }
+
+    fn load_recent(&self, limit: usize) -> Self::Reply<'_, Vec<StoredMessage>> {
+        Box::pin(IndexedDbStorage::load_recent(self, limit))
+    }
+
+    fn count_messages<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, usize> {
+        Box::pin(IndexedDbStorage::count_messages(self, conversation_id))
+    }
+
+    fn load_all_messages(&self) -> Self::Reply<'_, Vec<StoredMessage>> {
+        Box::pin(IndexedDbStorage::load_all_messages(self))
+    }
+
+    fn update_message_status<'a>(
+        &'a mut self,
+        message_id: &'a str,
+        status: MessageStatus,
+    ) -> Self::Reply<'a, Option<StoredMessage>> {
+        Box::pin(IndexedDbStorage::update_message_status(self, message_id, status))
+    }
+
+    fn delete_message<'a>(&'a mut self, message_id: &'a str) -> Self::Reply<'a, ()> {
+        Box::pin(IndexedDbStorage::delete_message(self, message_id))
+    }
+
+    fn save_metadata(&mut self, metadata: StoredAppMetadata) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_metadata(self, metadata))
+    }
+
+    fn load_metadata<'a>(
+        &'a self,
+        user_id: &'a str,
+    ) -> Self::Reply<'a, Option<StoredAppMetadata>> {
+        Box::pin(IndexedDbStorage::load_metadata(self, user_id))
+    }
+
+    fn save_draft(&mut self, draft: StoredDraft) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_draft(self, draft))
+    }
+
+    fn load_draft<'a>(&'a self, contact_id: &'a str) -> Self::Reply<'a, Option<StoredDraft>> {
+        Box::pin(IndexedDbStorage::load_draft(self, contact_id))
+    }
+
+    fn delete_draft<'a>(&'a mut self, contact_id: &'a str) -> Self::Reply<'a, ()> {
+        Box::pin(IndexedDbStorage::delete_draft(self, contact_id))
+    }
+
+    fn save_clear_marker(&mut self, marker: StoredClearMarker) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::save_clear_marker(self, marker))
+    }
+
+    fn load_clear_marker<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, Option<StoredClearMarker>> {
+        Box::pin(IndexedDbStorage::load_clear_marker(self, conversation_id))
+    }
+
+    fn clear_all(&mut self) -> Self::Reply<'_, ()> {
+        Box::pin(IndexedDbStorage::clear_all(self))
+    }
+}
+
+/// Имя базы и версия схемы (увеличивается при добавлении stores или индексов)
+#[cfg(target_arch = "wasm32")]
+const DB_NAME: &str = "construct_messenger";
+#[cfg(target_arch = "wasm32")]
+const DB_VERSION: u32 = 3;
+
+/// Object stores: имя, key path и индексы
+#[cfg(target_arch = "wasm32")]
+const OBJECT_STORES: &[(&str, &str, &[&str])] = &[
+    ("private_keys", "user_id", &[]),
+    ("sessions", "session_id", &["contact_id"]),
+    ("contacts", "id", &[]),
+    ("messages", "id", &["conversation_id", "timestamp"]),
+    ("metadata", "user_id", &[]),
+    ("drafts", "contact_id", &[]),
+    ("clear_markers", "conversation_id", &[]),
+];
+
+/// Описание ошибки IndexedDB (имя и текст DOMException, если есть)
+#[cfg(target_arch = "wasm32")]
+fn describe_js_error(error: &JsValue) -> String {
+    match error.dyn_ref::<web_sys::DomException>() {
+        Some(e) => format!("{}: {}", e.name(), e.message()),
+        None => format!("{:?}", error),
+    }
+}
+
+/// Открыть базу, создав или обновив схему в обработчике upgradeneeded
+#[cfg(target_arch = "wasm32")]
+async fn open_database() -> Result<IdbDatabase> {
+    let window = web_sys::window()
+        .ok_or_else(|| ConstructError::StorageError("No window object".to_string()))?;
+
+    let idb = window
+        .indexed_db()
+        .map_err(|e| ConstructError::StorageError(format!("IndexedDB not available: {}", describe_js_error(&e))))?
+        .ok_or_else(|| ConstructError::StorageError("IndexedDB not supported".to_string()))?;
+
+    let open_request = idb
+        .open_with_u32(DB_NAME, DB_VERSION)
+        .map_err(|e| ConstructError::StorageError(format!("Failed to open DB: {}", describe_js_error(&e))))?;
+
+    let onupgradeneeded = Closure::wrap(Box::new(move |event: web_sys::IdbVersionChangeEvent| {
+        let request: Option<web_sys::IdbOpenDbRequest> =
+            event.target().and_then(|target| target.dyn_into().ok());
+        let result = request
+            .as_ref()
+            .ok_or_else(|| JsValue::from_str("upgradeneeded event has no request"))
+            .and_then(upgrade_schema);
+
+        if let Err(e) = result {
+            web_sys::console::error_1(
+                &format!("IndexedDB schema upgrade failed: {}", describe_js_error(&e)).into(),
+            );
+            // Прерванный upgrade завершает открытие БД ошибкой
+            if let Some(transaction) = request.and_then(|r| r.transaction()) {
+                let _ = transaction.abort();
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+
+    // Другая вкладка держит старую версию открытой - upgrade ждет ее закрытия
+    let onblocked = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        web_sys::console::warn_1(
+            &"IndexedDB upgrade is blocked by another open tab of the app".into(),
+        );
+    }) as Box<dyn FnMut(_)>);
+
+    open_request.set_onupgradeneeded(Some(onupgradeneeded.as_ref().unchecked_ref()));
+    open_request.set_onblocked(Some(onblocked.as_ref().unchecked_ref()));
+    onupgradeneeded.forget();
+    onblocked.forget();
+
+    let db_value = JsFuture::from(idb_open_request_to_promise(&open_request))
+        .await
+        .map_err(|e| ConstructError::StorageError(format!("Failed to open database: {}", describe_js_error(&e))))?;
+
+    let db: IdbDatabase = db_value
+        .dyn_into()
+        .map_err(|_| ConstructError::StorageError("Invalid database object".to_string()))?;
+
+    // Уступить соединение вкладке, которая обновляет схему
+    let closing = db.clone();
+    let onversionchange = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        closing.close();
+    }) as Box<dyn FnMut(_)>);
+    db.set_onversionchange(Some(onversionchange.as_ref().unchecked_ref()));
+    onversionchange.forget();
+
+    Ok(db)
+}
+
+/// Создать недостающие object stores и индексы. Существующие stores
+/// не пересоздаются, поэтому повышение версии сохраняет данные
+#[cfg(target_arch = "wasm32")]
+fn upgrade_schema(request: &web_sys::IdbOpenDbRequest) -> std::result::Result<(), JsValue> {
+    let db: IdbDatabase = request.result()?.dyn_into()?;
+    let transaction = request
+        .transaction()
+        .ok_or_else(|| JsValue::from_str("upgradeneeded without versionchange transaction"))?;
+    let existing = db.object_store_names();
+
+    for (name, key_path, indexes) in OBJECT_STORES {
+        let store = if existing.contains(name) {
+            transaction.object_store(name)?
+        } else {
+            let params = web_sys::IdbObjectStoreParameters::new();
+            params.set_key_path(&JsValue::from_str(key_path));
+            db.create_object_store_with_optional_parameters(name, &params)?
+        };
+
+        let store_indexes = store.index_names();
+        for index in indexes.iter() {
+            if !store_indexes.contains(index) {
+                store.create_index_with_str(index, index)?;
+            }
+        }
+    }
+
+    Ok(())
 }
 
 impl Default for IndexedDbStorage {
@@ -494,3 +1069,113 @@ fn idb_open_request_to_promise(request: &web_sys::IdbOpenDbRequest) -> js_sys::P
 
 // Для совместимости с существующим кодом
 pub type KeyStorage = IndexedDbStorage;
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn encrypted_storage() -> IndexedDbStorage {
+        let mut storage = IndexedDbStorage::new();
+        storage.init().await.unwrap();
+        storage.set_at_rest_key(&[3u8; 32]).unwrap();
+        storage
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_private_keys_round_trip() {
+        use crate::crypto::master_key::{self, PrivateKeys};
+
+        let storage = encrypted_storage().await;
+        let keys = PrivateKeys::new([1u8; 32], [2u8; 32], [3u8; 32]);
+        let salt = master_key::generate_salt();
+        let key = master_key::derive_master_key("testpass123", &salt).unwrap();
+        let stored = master_key::encrypt_private_keys(&keys, &key, salt, "keys_user".to_string(), vec![9u8; 64])
+            .unwrap();
+        storage.save_private_keys(stored).await.unwrap();
+
+        // Новое соединение с той же БД видит сохраненные ключи
+        let mut reopened = IndexedDbStorage::new();
+        reopened.init().await.unwrap();
+        let loaded = reopened.load_private_keys("keys_user").await.unwrap().unwrap();
+        assert_eq!(loaded.prekey_signature, vec![9u8; 64]);
+
+        let decrypted = master_key::decrypt_private_keys(&loaded, &key).unwrap();
+        assert_eq!(decrypted.identity_secret, keys.identity_secret);
+        assert_eq!(decrypted.signing_key, keys.signing_key);
+        assert_eq!(decrypted.signed_prekey_secret, keys.signed_prekey_secret);
+
+        assert!(reopened.load_private_keys("missing_user").await.unwrap().is_none());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_message_content_encrypted_at_rest() {
+        let storage = encrypted_storage().await;
+        storage
+            .save_message(StoredMessage {
+                id: "at_rest_msg".to_string(),
+                conversation_id: "at_rest_conv".to_string(),
+                from: "alice".to_string(),
+                to: "bob".to_string(),
+                encrypted_content: "AQIDBAUG".to_string(),
+                timestamp: 100,
+                status: MessageStatus::Sent,
+                local_content: None,
+                ratchet_header: None,
+            })
+            .await
+            .unwrap();
+
+        // Сырая запись в IndexedDB не содержит исходный base64
+        let raw = storage
+            .get_value("messages", &JsValue::from_str("at_rest_msg"))
+            .await
+            .unwrap()
+            .unwrap();
+        let raw: StoredMessage = serde_wasm_bindgen::from_value(raw).unwrap();
+        assert!(!raw.encrypted_content.contains("AQIDBAUG"));
+        assert_eq!(raw.conversation_id, "at_rest_conv");
+
+        let loaded = storage
+            .load_messages_for_conversation("at_rest_conv", 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].encrypted_content, "AQIDBAUG");
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_session_data_encrypted_at_rest() {
+        let storage = encrypted_storage().await;
+        let session_data = b"serialized ratchet state".to_vec();
+        storage
+            .save_session(StoredSession {
+                session_id: "at_rest_session".to_string(),
+                contact_id: "bob".to_string(),
+                session_data: session_data.clone(),
+                last_used: 100,
+                created_at: 100,
+            })
+            .await
+            .unwrap();
+
+        let raw = storage
+            .get_value("sessions", &JsValue::from_str("at_rest_session"))
+            .await
+            .unwrap()
+            .unwrap();
+        let raw: StoredSession = serde_wasm_bindgen::from_value(raw).unwrap();
+        assert_ne!(raw.session_data, session_data);
+
+        let loaded = storage.load_session("at_rest_session").await.unwrap().unwrap();
+        assert_eq!(loaded.session_data, session_data);
+
+        // Без ключа запись не расшифровывается
+        let mut locked = IndexedDbStorage::new();
+        locked.init().await.unwrap();
+        let loaded = locked.load_session("at_rest_session").await.unwrap().unwrap();
+        assert_ne!(loaded.session_data, session_data);
+    }
+}
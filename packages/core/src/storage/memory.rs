@@ -1,8 +1,10 @@
 // In-memory storage для тестов и non-WASM платформ
 
 use crate::storage::models::*;
+use crate::storage::Storage;
 use crate::utils::error::Result;
 use std::collections::HashMap;
+use std::future::{ready, Ready};
 
 /// In-memory хранилище
 pub struct MemoryStorage {
@@ -11,6 +13,8 @@ pub struct MemoryStorage {
     contacts: HashMap<String, StoredContact>,
     messages: Vec<StoredMessage>,
     metadata: HashMap<String, StoredAppMetadata>,
+    drafts: HashMap<String, StoredDraft>,
+    clear_markers: HashMap<String, StoredClearMarker>,
 }
 
 impl MemoryStorage {
@@ -21,6 +25,8 @@ impl MemoryStorage {
             contacts: HashMap::new(),
             messages: Vec::new(),
             metadata: HashMap::new(),
+            drafts: HashMap::new(),
+            clear_markers: HashMap::new(),
         }
     }
 
@@ -46,6 +52,16 @@ impl MemoryStorage {
         Ok(self.sessions.get(session_id).cloned())
     }
 
+    /// Последняя использованная сессия с контактом
+    pub fn load_session_for_contact(&self, contact_id: &str) -> Result<Option<StoredSession>> {
+        Ok(self
+            .sessions
+            .values()
+            .filter(|s| s.contact_id == contact_id)
+            .max_by_key(|s| s.last_used)
+            .cloned())
+    }
+
     pub fn load_all_sessions(&self) -> Result<Vec<StoredSession>> {
         Ok(self.sessions.values().cloned().collect())
     }
@@ -77,8 +93,12 @@ impl MemoryStorage {
 
     // === Сообщения ===
 
+    /// Сохранить сообщение (сообщение с тем же id заменяется, как в IndexedDB)
     pub fn save_message(&mut self, msg: StoredMessage) -> Result<()> {
-        self.messages.push(msg);
+        match self.messages.iter_mut().find(|m| m.id == msg.id) {
+            Some(existing) => *existing = msg,
+            None => self.messages.push(msg),
+        }
         Ok(())
     }
 
@@ -95,8 +115,8 @@ impl MemoryStorage {
             .cloned()
             .collect();
 
-        // Сортировка по timestamp
-        messages.sort_by_key(|m| m.timestamp);
+        // Сортировка по timestamp, при равных - по id
+        messages.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
 
         // Пагинация
         let messages = messages
@@ -108,6 +128,47 @@ impl MemoryStorage {
         Ok(messages)
     }
 
+    /// Самые новые `limit` сообщений по всем беседам, от новых к старым
+    pub fn load_recent(&self, limit: usize) -> Result<Vec<StoredMessage>> {
+        let mut messages: Vec<&StoredMessage> = self.messages.iter().collect();
+        messages.sort_by(|a, b| (b.timestamp, &b.id).cmp(&(a.timestamp, &a.id)));
+
+        Ok(messages.into_iter().take(limit).cloned().collect())
+    }
+
+    /// Количество сообщений в беседе
+    pub fn count_messages(&self, conversation_id: &str) -> Result<usize> {
+        Ok(self
+            .messages
+            .iter()
+            .filter(|m| m.conversation_id == conversation_id)
+            .count())
+    }
+
+    pub fn load_all_messages(&self) -> Result<Vec<StoredMessage>> {
+        Ok(self.messages.clone())
+    }
+
+    pub fn load_message(&self, message_id: &str) -> Result<Option<StoredMessage>> {
+        Ok(self.messages.iter().find(|m| m.id == message_id).cloned())
+    }
+
+    /// Обновить статус сообщения; `None`, если сообщения нет
+    pub fn update_message_status(
+        &mut self,
+        message_id: &str,
+        status: MessageStatus,
+    ) -> Result<Option<StoredMessage>> {
+        Ok(self
+            .messages
+            .iter_mut()
+            .find(|m| m.id == message_id)
+            .map(|m| {
+                m.status = status;
+                m.clone()
+            }))
+    }
+
     pub fn delete_message(&mut self, message_id: &str) -> Result<()> {
         self.messages.retain(|m| m.id != message_id);
         Ok(())
@@ -124,6 +185,33 @@ impl MemoryStorage {
         Ok(self.metadata.get(user_id).cloned())
     }
 
+    // === Черновики ===
+
+    pub fn save_draft(&mut self, draft: StoredDraft) -> Result<()> {
+        self.drafts.insert(draft.contact_id.clone(), draft);
+        Ok(())
+    }
+
+    pub fn load_draft(&self, contact_id: &str) -> Result<Option<StoredDraft>> {
+        Ok(self.drafts.get(contact_id).cloned())
+    }
+
+    pub fn delete_draft(&mut self, contact_id: &str) -> Result<()> {
+        self.drafts.remove(contact_id);
+        Ok(())
+    }
+
+    // === Отметки об очистке бесед ===
+
+    pub fn save_clear_marker(&mut self, marker: StoredClearMarker) -> Result<()> {
+        self.clear_markers.insert(marker.conversation_id.clone(), marker);
+        Ok(())
+    }
+
+    pub fn load_clear_marker(&self, conversation_id: &str) -> Result<Option<StoredClearMarker>> {
+        Ok(self.clear_markers.get(conversation_id).cloned())
+    }
+
     // === Утилиты ===
 
     pub fn clear_all(&mut self) -> Result<()> {
@@ -132,6 +220,8 @@ impl MemoryStorage {
         self.contacts.clear();
         self.messages.clear();
         self.metadata.clear();
+        self.drafts.clear();
+        self.clear_markers.clear();
         Ok(())
     }
 }
@@ -142,6 +232,125 @@ impl Default for MemoryStorage {
     }
 }
 
+impl Storage for MemoryStorage {
+    type Reply<'a, T> = Ready<Result<T>> where T: 'a;
+
+    /// Данные не покидают память процесса, шифровать записи не нужно
+    fn set_at_rest_key(&mut self, _master_key: &[u8; 32]) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear_at_rest_key(&mut self) {}
+
+    fn save_private_keys(&mut self, keys: StoredPrivateKeys) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_private_keys(self, keys))
+    }
+
+    fn load_private_keys<'a>(&'a self, user_id: &'a str) -> Self::Reply<'a, Option<StoredPrivateKeys>> {
+        ready(MemoryStorage::load_private_keys(self, user_id))
+    }
+
+    fn save_session(&mut self, session: StoredSession) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_session(self, session))
+    }
+
+    fn load_session_for_contact<'a>(
+        &'a self,
+        contact_id: &'a str,
+    ) -> Self::Reply<'a, Option<StoredSession>> {
+        ready(MemoryStorage::load_session_for_contact(self, contact_id))
+    }
+
+    fn load_all_sessions(&self) -> Self::Reply<'_, Vec<StoredSession>> {
+        ready(MemoryStorage::load_all_sessions(self))
+    }
+
+    fn delete_session<'a>(&'a mut self, session_id: &'a str) -> Self::Reply<'a, ()> {
+        ready(MemoryStorage::delete_session(self, session_id))
+    }
+
+    fn save_contact(&mut self, contact: StoredContact) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_contact(self, contact))
+    }
+
+    fn load_all_contacts(&self) -> Self::Reply<'_, Vec<StoredContact>> {
+        ready(MemoryStorage::load_all_contacts(self))
+    }
+
+    fn save_message(&mut self, msg: StoredMessage) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_message(self, msg))
+    }
+
+    fn load_message<'a>(&'a self, message_id: &'a str) -> Self::Reply<'a, Option<StoredMessage>> {
+        ready(MemoryStorage::load_message(self, message_id))
+    }
+
+    fn load_messages_for_conversation<'a>(
+        &'a self,
+        conversation_id: &'a str,
+        limit: usize,
+        offset: usize,
+    ) -> Self::Reply<'a, Vec<StoredMessage>> {
+        ready(MemoryStorage::load_messages_for_conversation(self, conversation_id, limit, offset))
+    }
+
+    fn load_recent(&self, limit: usize) -> Self::Reply<'_, Vec<StoredMessage>> {
+        ready(MemoryStorage::load_recent(self, limit))
+    }
+
+    fn count_messages<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, usize> {
+        ready(MemoryStorage::count_messages(self, conversation_id))
+    }
+
+    fn load_all_messages(&self) -> Self::Reply<'_, Vec<StoredMessage>> {
+        ready(MemoryStorage::load_all_messages(self))
+    }
+
+    fn update_message_status<'a>(
+        &'a mut self,
+        message_id: &'a str,
+        status: MessageStatus,
+    ) -> Self::Reply<'a, Option<StoredMessage>> {
+        ready(MemoryStorage::update_message_status(self, message_id, status))
+    }
+
+    fn delete_message<'a>(&'a mut self, message_id: &'a str) -> Self::Reply<'a, ()> {
+        ready(MemoryStorage::delete_message(self, message_id))
+    }
+
+    fn save_metadata(&mut self, metadata: StoredAppMetadata) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_metadata(self, metadata))
+    }
+
+    fn load_metadata<'a>(&'a self, user_id: &'a str) -> Self::Reply<'a, Option<StoredAppMetadata>> {
+        ready(MemoryStorage::load_metadata(self, user_id))
+    }
+
+    fn save_draft(&mut self, draft: StoredDraft) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_draft(self, draft))
+    }
+
+    fn load_draft<'a>(&'a self, contact_id: &'a str) -> Self::Reply<'a, Option<StoredDraft>> {
+        ready(MemoryStorage::load_draft(self, contact_id))
+    }
+
+    fn delete_draft<'a>(&'a mut self, contact_id: &'a str) -> Self::Reply<'a, ()> {
+        ready(MemoryStorage::delete_draft(self, contact_id))
+    }
+
+    fn save_clear_marker(&mut self, marker: StoredClearMarker) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::save_clear_marker(self, marker))
+    }
+
+    fn load_clear_marker<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, Option<StoredClearMarker>> {
+        ready(MemoryStorage::load_clear_marker(self, conversation_id))
+    }
+
+    fn clear_all(&mut self) -> Self::Reply<'_, ()> {
+        ready(MemoryStorage::clear_all(self))
+    }
+}
+
 // Для совместимости с существующим кодом
 pub type KeyStorage = MemoryStorage;
 
@@ -161,6 +370,11 @@ mod tests {
             prekey_signature: vec![13, 14, 15],
             salt: vec![10, 11, 12],
             created_at: 12345,
+            verifying_key: Vec::new(),
+            signed_prekey_id: 0,
+            one_time_prekeys: Vec::new(),
+            next_one_time_prekey_id: 0,
+            old_signed_prekeys: Vec::new(),
         };
 
         storage.save_private_keys(keys.clone()).unwrap();
@@ -201,6 +415,8 @@ mod tests {
             encrypted_content: "AQID".to_string(),
             timestamp: 100,
             status: MessageStatus::Sent,
+            local_content: None,
+            ratchet_header: None,
         };
 
         let msg2 = StoredMessage {
@@ -211,6 +427,8 @@ mod tests {
             encrypted_content: "BAUG".to_string(),
             timestamp: 200,
             status: MessageStatus::Read,
+            local_content: None,
+            ratchet_header: None,
         };
 
         storage.save_message(msg1).unwrap();
@@ -224,4 +442,82 @@ mod tests {
         assert_eq!(messages[0].id, "msg1"); // Сортировка по timestamp
         assert_eq!(messages[1].id, "msg2");
     }
+
+    #[test]
+    fn test_memory_storage_paginates_and_clears() {
+        let mut storage = MemoryStorage::new();
+        for (id, timestamp) in [("m3", 300), ("m1", 100), ("m2b", 200), ("m2a", 200), ("m4", 400)] {
+            storage
+                .save_message(StoredMessage {
+                    id: id.to_string(),
+                    conversation_id: "bob".to_string(),
+                    from: "alice".to_string(),
+                    to: "bob".to_string(),
+                    encrypted_content: "AQID".to_string(),
+                    timestamp,
+                    status: MessageStatus::Sent,
+                    local_content: None,
+                    ratchet_header: None,
+                })
+                .unwrap();
+        }
+
+        let page = |storage: &MemoryStorage, limit, offset| -> Vec<String> {
+            storage
+                .load_messages_for_conversation("bob", limit, offset)
+                .unwrap()
+                .into_iter()
+                .map(|m| m.id)
+                .collect()
+        };
+        assert_eq!(page(&storage, 2, 0), vec!["m1", "m2a"]);
+        assert_eq!(page(&storage, 2, 2), vec!["m2b", "m3"]);
+        assert_eq!(page(&storage, 2, 4), vec!["m4"]);
+        assert!(page(&storage, 2, 5).is_empty());
+
+        // Повторное сохранение заменяет сообщение, а не дублирует его
+        let mut read = storage.load_message("m1").unwrap().unwrap();
+        read.status = MessageStatus::Read;
+        storage.save_message(read).unwrap();
+        assert_eq!(storage.count_messages("bob").unwrap(), 5);
+
+        storage
+            .save_metadata(StoredAppMetadata {
+                user_id: "alice".to_string(),
+                username: "alice".to_string(),
+                last_sync: 0,
+                settings: Vec::new(),
+            })
+            .unwrap();
+        storage.clear_all().unwrap();
+        assert!(page(&storage, 10, 0).is_empty());
+        assert!(storage.load_metadata("alice").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_memory_storage_count_messages() {
+        let mut storage = MemoryStorage::new();
+        assert_eq!(storage.count_messages("bob").unwrap(), 0);
+
+        for (id, conversation_id) in [("m1", "bob"), ("m2", "bob"), ("m3", "carol")] {
+            storage
+                .save_message(StoredMessage {
+                    id: id.to_string(),
+                    conversation_id: conversation_id.to_string(),
+                    from: "alice".to_string(),
+                    to: conversation_id.to_string(),
+                    encrypted_content: "AQID".to_string(),
+                    timestamp: 0,
+                    status: MessageStatus::Sent,
+                    local_content: None,
+                    ratchet_header: None,
+                })
+                .unwrap();
+        }
+        assert_eq!(storage.count_messages("bob").unwrap(), 2);
+        assert_eq!(storage.count_messages("carol").unwrap(), 1);
+
+        storage.delete_message("m1").unwrap();
+        assert_eq!(storage.count_messages("bob").unwrap(), 1);
+    }
 }
@@ -1,5 +1,6 @@
 // Модуль хранилища (IndexedDB для WASM)
 
+pub mod at_rest;
 pub mod indexeddb;
 pub mod memory;
 pub mod models;
@@ -9,3 +10,246 @@ pub use indexeddb::KeyStorage;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use memory::KeyStorage;
+
+use crate::storage::models::{
+    MessageStatus, StoredAppMetadata, StoredClearMarker, StoredContact, StoredDraft, StoredMessage,
+    StoredPrivateKeys, StoredSession,
+};
+use crate::utils::error::{ConstructError, Result};
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+/// Хранилище по умолчанию для текущей платформы
+#[cfg(target_arch = "wasm32")]
+pub type DefaultStorage = indexeddb::IndexedDbStorage;
+
+/// Хранилище по умолчанию для текущей платформы
+#[cfg(not(target_arch = "wasm32"))]
+pub type DefaultStorage = memory::MemoryStorage;
+
+/// Общий контракт хранилищ, на который опирается AppState
+///
+/// Операции возвращают future (`Reply`): у `IndexedDbStorage` это запрос к IndexedDB,
+/// у `MemoryStorage` - уже готовый результат. Сохранение записи с существующим
+/// ключом заменяет ее; сообщения беседы возвращаются отсортированными
+/// по (timestamp, id) с пагинацией `limit`/`offset`
+pub trait Storage {
+    type Reply<'a, T>: Future<Output = Result<T>> + 'a
+    where
+        Self: 'a,
+        T: 'a;
+
+    /// Включить шифрование записей ключом, выведенным из мастер-ключа
+    fn set_at_rest_key(&mut self, master_key: &[u8; 32]) -> Result<()>;
+    /// Забыть ключ шифрования записей
+    fn clear_at_rest_key(&mut self);
+
+    fn save_private_keys(&mut self, keys: StoredPrivateKeys) -> Self::Reply<'_, ()>;
+    fn load_private_keys<'a>(&'a self, user_id: &'a str) -> Self::Reply<'a, Option<StoredPrivateKeys>>;
+
+    fn save_session(&mut self, session: StoredSession) -> Self::Reply<'_, ()>;
+    fn load_session_for_contact<'a>(&'a self, contact_id: &'a str) -> Self::Reply<'a, Option<StoredSession>>;
+    fn load_all_sessions(&self) -> Self::Reply<'_, Vec<StoredSession>>;
+    fn delete_session<'a>(&'a mut self, session_id: &'a str) -> Self::Reply<'a, ()>;
+
+    fn save_contact(&mut self, contact: StoredContact) -> Self::Reply<'_, ()>;
+    fn load_all_contacts(&self) -> Self::Reply<'_, Vec<StoredContact>>;
+
+    fn save_message(&mut self, msg: StoredMessage) -> Self::Reply<'_, ()>;
+    fn load_message<'a>(&'a self, message_id: &'a str) -> Self::Reply<'a, Option<StoredMessage>>;
+    fn load_messages_for_conversation<'a>(
+        &'a self,
+        conversation_id: &'a str,
+        limit: usize,
+        offset: usize,
+    ) -> Self::Reply<'a, Vec<StoredMessage>>;
+    fn load_recent(&self, limit: usize) -> Self::Reply<'_, Vec<StoredMessage>>;
+    fn count_messages<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, usize>;
+    fn load_all_messages(&self) -> Self::Reply<'_, Vec<StoredMessage>>;
+    fn update_message_status<'a>(
+        &'a mut self,
+        message_id: &'a str,
+        status: MessageStatus,
+    ) -> Self::Reply<'a, Option<StoredMessage>>;
+    fn delete_message<'a>(&'a mut self, message_id: &'a str) -> Self::Reply<'a, ()>;
+
+    fn save_metadata(&mut self, metadata: StoredAppMetadata) -> Self::Reply<'_, ()>;
+    fn load_metadata<'a>(&'a self, user_id: &'a str) -> Self::Reply<'a, Option<StoredAppMetadata>>;
+
+    fn save_draft(&mut self, draft: StoredDraft) -> Self::Reply<'_, ()>;
+    fn load_draft<'a>(&'a self, contact_id: &'a str) -> Self::Reply<'a, Option<StoredDraft>>;
+    fn delete_draft<'a>(&'a mut self, contact_id: &'a str) -> Self::Reply<'a, ()>;
+
+    fn save_clear_marker(&mut self, marker: StoredClearMarker) -> Self::Reply<'_, ()>;
+    fn load_clear_marker<'a>(&'a self, conversation_id: &'a str) -> Self::Reply<'a, Option<StoredClearMarker>>;
+
+    fn clear_all(&mut self) -> Self::Reply<'_, ()>;
+}
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Выполнить операцию хранилища синхронно (non-WASM API поверх `Storage`).
+/// Подходит для backend'ов, чьи операции завершаются без ожидания (`MemoryStorage`);
+/// операция, которой нужно ждать, возвращает ошибку
+pub fn complete_now<T>(reply: impl Future<Output = Result<T>>) -> Result<T> {
+    let waker = Waker::from(Arc::new(NoopWake));
+    let mut cx = Context::from_waker(&waker);
+    let mut reply = std::pin::pin!(reply);
+
+    match reply.as_mut().poll(&mut cx) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(ConstructError::StorageError(
+            "Storage operation did not complete synchronously".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, conversation_id: &str, timestamp: i64) -> StoredMessage {
+        StoredMessage {
+            id: id.to_string(),
+            conversation_id: conversation_id.to_string(),
+            from: "alice".to_string(),
+            to: conversation_id.to_string(),
+            encrypted_content: "AQID".to_string(),
+            timestamp,
+            status: MessageStatus::Sent,
+            local_content: None,
+            ratchet_header: None,
+        }
+    }
+
+    fn session(session_id: &str, last_used: i64) -> StoredSession {
+        StoredSession {
+            session_id: session_id.to_string(),
+            contact_id: "bob".to_string(),
+            session_data: session_id.as_bytes().to_vec(),
+            last_used,
+            created_at: 100,
+        }
+    }
+
+    /// Один сценарий для всех backend'ов: поведение, на которое опирается AppState
+    async fn check_storage_contract<S: Storage>(storage: &mut S) -> Result<()> {
+        storage.clear_all().await?;
+
+        storage
+            .save_metadata(StoredAppMetadata {
+                user_id: "alice".to_string(),
+                username: "Alice".to_string(),
+                last_sync: 0,
+                settings: Vec::new(),
+            })
+            .await?;
+        assert_eq!(storage.load_metadata("alice").await?.unwrap().username, "Alice");
+        assert!(storage.load_metadata("nobody").await?.is_none());
+
+        let mut contact = StoredContact {
+            id: "bob".to_string(),
+            username: "Bob".to_string(),
+            public_key_bundle: None,
+            added_at: 100,
+            last_message_at: None,
+            verified: false,
+            notes: None,
+            metadata: Default::default(),
+            identity_key_history: Vec::new(),
+            muted: false,
+            blocked: false,
+        };
+        storage.save_contact(contact.clone()).await?;
+        contact.verified = true;
+        storage.save_contact(contact).await?;
+        let contacts = storage.load_all_contacts().await?;
+        assert_eq!(contacts.len(), 1);
+        assert!(contacts[0].verified);
+
+        storage.save_session(session("s1", 100)).await?;
+        storage.save_session(session("s2", 200)).await?;
+        let latest = storage.load_session_for_contact("bob").await?.unwrap();
+        assert_eq!(latest.session_data, b"s2");
+        assert_eq!(storage.load_all_sessions().await?.len(), 2);
+
+        for msg in [
+            message("m3", "bob", 300),
+            message("m1", "bob", 100),
+            message("m2", "bob", 200),
+            message("x1", "carol", 250),
+        ] {
+            storage.save_message(msg).await?;
+        }
+        let ids = |messages: Vec<StoredMessage>| -> Vec<String> {
+            messages.into_iter().map(|m| m.id).collect()
+        };
+        assert_eq!(ids(storage.load_messages_for_conversation("bob", 2, 1).await?), ["m2", "m3"]);
+        assert_eq!(ids(storage.load_recent(2).await?), ["m3", "x1"]);
+        assert_eq!(storage.count_messages("bob").await?, 3);
+
+        let updated = storage.update_message_status("m1", MessageStatus::Read).await?;
+        assert_eq!(updated.map(|m| m.status), Some(MessageStatus::Read));
+        assert!(storage.update_message_status("missing", MessageStatus::Read).await?.is_none());
+        assert_eq!(storage.load_message("m1").await?.unwrap().status, MessageStatus::Read);
+
+        // Повторное сохранение заменяет запись
+        storage.save_message(message("m1", "bob", 100)).await?;
+        assert_eq!(storage.count_messages("bob").await?, 3);
+        assert_eq!(storage.load_all_messages().await?.len(), 4);
+        storage.delete_message("m2").await?;
+        assert_eq!(ids(storage.load_messages_for_conversation("bob", 10, 0).await?), ["m1", "m3"]);
+        storage.delete_message("missing").await?;
+
+        let draft = |sealed_text: &[u8]| StoredDraft {
+            contact_id: "bob".to_string(),
+            sealed_text: sealed_text.to_vec(),
+            updated_at: 0,
+        };
+        storage.save_draft(draft(b"d1")).await?;
+        storage.save_draft(draft(b"d2")).await?;
+        assert_eq!(storage.load_draft("bob").await?.unwrap().sealed_text, b"d2");
+        storage.delete_draft("bob").await?;
+        assert!(storage.load_draft("bob").await?.is_none());
+        storage.save_draft(draft(b"d3")).await?;
+
+        let marker = |cleared_at: i64| StoredClearMarker {
+            conversation_id: "bob".to_string(),
+            cleared_at,
+        };
+        storage.save_clear_marker(marker(100)).await?;
+        storage.save_clear_marker(marker(300)).await?;
+        assert_eq!(storage.load_clear_marker("bob").await?.unwrap().cleared_at, 300);
+        assert!(storage.load_clear_marker("carol").await?.is_none());
+
+        storage.clear_all().await?;
+        assert!(storage.load_all_contacts().await?.is_empty());
+        assert!(storage.load_all_sessions().await?.is_empty());
+        assert!(storage.load_all_messages().await?.is_empty());
+        assert!(storage.load_metadata("alice").await?.is_none());
+        assert!(storage.load_draft("bob").await?.is_none());
+        assert!(storage.load_clear_marker("bob").await?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_memory_storage_contract() {
+        let mut storage = memory::MemoryStorage::new();
+        complete_now(check_storage_contract(&mut storage)).unwrap();
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    async fn test_indexeddb_storage_contract() {
+        let mut storage = indexeddb::IndexedDbStorage::new();
+        storage.init().await.unwrap();
+        storage.set_at_rest_key(&[5u8; 32]).unwrap();
+        check_storage_contract(&mut storage).await.unwrap();
+    }
+}
@@ -1,6 +1,7 @@
 // Модели данных для хранилища
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Статус сообщения
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,6 +23,42 @@ pub struct StoredMessage {
     pub encrypted_content: String, // Base64 зашифрованного Double Ratchet сообщения
     pub timestamp: i64,
     pub status: MessageStatus,
+    /// Plaintext, зашифрованный мастер-ключом (nonce || ciphertext).
+    /// Ключи Double Ratchet одноразовые, поэтому для повторного показа истории храним локальную копию
+    #[serde(default)]
+    pub local_content: Option<Vec<u8>>,
+    /// Заголовок исходящего ChatMessage - нужен, чтобы переотправить сообщение без изменений
+    #[serde(default)]
+    pub ratchet_header: Option<StoredRatchetHeader>,
+}
+
+/// Открытая часть ChatMessage, не входящая в encrypted_content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredRatchetHeader {
+    #[serde(alias = "ephemeral_public_key")]
+    pub ratchet_dh_public: Vec<u8>,
+    pub message_number: u32,
+}
+
+/// Направление сообщения относительно текущего пользователя
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+/// Сообщение в экспорте беседы
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub timestamp: i64,
+    pub direction: MessageDirection,
+    pub status: MessageStatus,
+    /// Base64 ciphertext или расшифрованный текст (None, если локальной копии нет)
+    pub content: Option<String>,
 }
 
 /// Контакт в хранилище
@@ -32,6 +69,29 @@ pub struct StoredContact {
     pub public_key_bundle: Option<Vec<u8>>, // JSON или Bincode
     pub added_at: i64,
     pub last_message_at: Option<i64>,
+    #[serde(default)]
+    pub verified: bool,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub identity_key_history: Vec<IdentityKeyRecord>,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+/// Identity ключ, который контакт когда-либо публиковал (журнал для аудита)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityKeyRecord {
+    /// Identity ключ (base64, как в bundle)
+    pub identity_public: String,
+    /// Когда ключ впервые появился в bundle контакта
+    pub first_seen: i64,
+    /// Когда пользователь принял смену ключа (None - не подтверждался)
+    pub accepted_at: Option<i64>,
 }
 
 /// Приватные ключи в хранилище (ЗАШИФРОВАННЫЕ!)
@@ -44,6 +104,36 @@ pub struct StoredPrivateKeys {
     pub prekey_signature: Vec<u8>, // Ed25519 подпись для prekey (не шифруется)
     pub salt: Vec<u8>, // Для PBKDF2
     pub created_at: i64,
+    // Поля ниже отсутствуют в записях старых версий
+    /// Verifying ключ (публичный, не шифруется)
+    #[serde(default)]
+    pub verifying_key: Vec<u8>,
+    #[serde(default)]
+    pub signed_prekey_id: u32,
+    /// Неиспользованные одноразовые prekey
+    #[serde(default)]
+    pub one_time_prekeys: Vec<StoredOneTimePrekey>,
+    #[serde(default)]
+    pub next_one_time_prekey_id: u32,
+    /// Signed prekey до ротации: нужны для X3DH по уже выданным bundle
+    #[serde(default)]
+    pub old_signed_prekeys: Vec<StoredSignedPrekey>,
+}
+
+/// Signed prekey после ротации в хранилище (ЗАШИФРОВАН!)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSignedPrekey {
+    pub key_id: u32,
+    pub encrypted_private: Vec<u8>, // Зашифровано мастер-ключом
+    pub signature: Vec<u8>,
+    pub created_at: i64,
+}
+
+/// Одноразовый prekey в хранилище (ЗАШИФРОВАН!)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredOneTimePrekey {
+    pub key_id: u32,
+    pub encrypted_private: Vec<u8>, // Зашифровано мастер-ключом
 }
 
 /// Сессия Double Ratchet в хранилище (СЕРИАЛИЗОВАННАЯ)
@@ -51,7 +141,7 @@ pub struct StoredPrivateKeys {
 pub struct StoredSession {
     pub session_id: String,
     pub contact_id: String,
-    pub session_data: Vec<u8>, // Bincode сериализация SerializableSession
+    pub session_data: Vec<u8>, // SerializableSession::to_bytes (версионированный формат)
     pub last_used: i64,
     pub created_at: i64,
 }
@@ -65,6 +155,71 @@ pub struct StoredAppMetadata {
     pub settings: Vec<u8>, // JSON настроек
 }
 
+/// Черновик сообщения в хранилище (текст зашифрован мастер-ключом)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredDraft {
+    pub contact_id: String,
+    pub sealed_text: Vec<u8>,
+    pub updated_at: i64,
+}
+
+/// Отметка об очистке беседы: сообщения с `timestamp <= cleared_at`
+/// не возвращаются в беседу при повторной доставке или синхронизации
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredClearMarker {
+    pub conversation_id: String,
+    pub cleared_at: i64,
+}
+
+/// Текущая версия формата архива состояния
+pub const ARCHIVE_VERSION: u32 = 2;
+
+/// Архив состояния клиента (экспорт, серверные бэкапы, привязка устройства)
+/// Перед выгрузкой шифруется паролем пользователя
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateArchive {
+    pub version: u32,
+    pub user_id: Option<String>,
+    pub username: Option<String>,
+    pub contacts: Vec<StoredContact>,
+    pub messages: Vec<StoredMessage>,
+    /// (session_id, сериализованная SerializableSession). Только в архиве привязки
+    /// устройства: устаревший снимок из бэкапа откатил бы цепочки ratchet
+    pub sessions: Vec<(String, Vec<u8>)>,
+    pub created_at: i64,
+    /// Приватные ключи, зашифрованные мастер-ключом из пароля архива (с версии 2,
+    /// только в бэкапах: при привязке устройства ключи передаются отдельно)
+    pub private_keys: Option<StoredPrivateKeys>,
+}
+
+/// Архив версии 1 (без приватных ключей). Bincode не допускает новых полей
+/// в старых данных, поэтому старый формат разбирается отдельно
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateArchiveV1 {
+    pub version: u32,
+    pub user_id: Option<String>,
+    pub username: Option<String>,
+    pub contacts: Vec<StoredContact>,
+    pub messages: Vec<StoredMessage>,
+    pub sessions: Vec<(String, Vec<u8>)>,
+    pub created_at: i64,
+}
+
+impl From<StateArchiveV1> for StateArchive {
+    fn from(archive: StateArchiveV1) -> Self {
+        Self {
+            version: archive.version,
+            user_id: archive.user_id,
+            username: archive.username,
+            contacts: archive.contacts,
+            messages: archive.messages,
+            sessions: archive.sessions,
+            created_at: archive.created_at,
+            private_keys: None,
+        }
+    }
+}
+
 /// Беседа
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conversation {
@@ -69,6 +69,10 @@ pub struct EncryptedMessageComponents {
     pub ephemeral_public_key: Vec<u8>,  // 32 bytes
     pub message_number: u32,
     pub content: String,  // Base64(nonce || ciphertext_with_tag)
+    pub previous_chain_length: u32,  // Authenticated as part of the AEAD associated data
+    pub x3dh_ephemeral_key: Option<Vec<u8>>,  // Only in initiator messages before the first reply
+    pub one_time_prekey_id: Option<u32>,
+    pub signed_prekey_id: Option<u32>,
 }
 
 // Key bundle for session initialization
@@ -79,6 +83,12 @@ struct KeyBundle {
     signature: Vec<u8>,
     verifying_key: Vec<u8>,
     suite_id: u16,
+    #[serde(default)]
+    one_time_prekey_public: Option<Vec<u8>>,
+    #[serde(default)]
+    one_time_prekey_id: Option<u32>,
+    #[serde(default)]
+    signed_prekey_id: Option<u32>,
 }
 
 // UniFFI interface implementation (exported via UDL, not proc-macros)
@@ -133,6 +143,9 @@ impl ClassicCryptoCore {
             signature: key_bundle.signature.clone(),
             verifying_key: key_bundle.verifying_key.clone(),
             suite_id: key_bundle.suite_id,
+            one_time_prekey_public: key_bundle.one_time_prekey_public.clone(),
+            one_time_prekey_id: key_bundle.one_time_prekey_id,
+            signed_prekey_id: key_bundle.signed_prekey_id,
         };
 
         eprintln!("[UniFFI] Internal bundle created, acquiring lock...");
@@ -185,6 +198,16 @@ impl ClassicCryptoCore {
             ephemeral_public_key: Vec<u8>,
             message_number: u32,
             content: String,  // Base64
+            #[serde(default)]
+            previous_chain_length: u32,
+            #[serde(default)]
+            key_confirmation: Option<Vec<u8>>,
+            #[serde(default)]
+            x3dh_ephemeral_key: Option<Vec<u8>>,
+            #[serde(default)]
+            one_time_prekey_id: Option<u32>,
+            #[serde(default)]
+            signed_prekey_id: Option<u32>,
         }
 
         let first_msg: FirstMessage = serde_json::from_str(message_str)
@@ -205,19 +228,20 @@ impl ClassicCryptoCore {
         let nonce = sealed_box[..12].to_vec();
         let ciphertext = sealed_box[12..].to_vec();
 
-        // Convert ephemeral_public_key to [u8; 32]
-        let dh_public_key: [u8; 32] = first_msg.ephemeral_public_key
-            .try_into()
-            .map_err(|_| CryptoError::InvalidKeyData)?;
-
-        // Create EncryptedRatchetMessage
+        // Create EncryptedRatchetMessage (длину DH ключа проверяет сессия по suite)
         let encrypted_first_message = crate::crypto::double_ratchet::EncryptedRatchetMessage {
-            dh_public_key,
+            dh_public_key: first_msg.ephemeral_public_key,
             message_number: first_msg.message_number,
             ciphertext,
             nonce,
-            previous_chain_length: 0,
+            previous_chain_length: first_msg.previous_chain_length,
             suite_id: key_bundle.suite_id,
+            key_confirmation: first_msg.key_confirmation,
+            x3dh_ephemeral_key: first_msg.x3dh_ephemeral_key,
+            one_time_prekey_id: first_msg.one_time_prekey_id,
+            signed_prekey_id: first_msg.signed_prekey_id,
+            transcript_tag: None,
+            encrypted_header: None,
         };
 
         // Convert to internal KeyBundle
@@ -227,6 +251,9 @@ impl ClassicCryptoCore {
             signature: key_bundle.signature.clone(),
             verifying_key: key_bundle.verifying_key.clone(),
             suite_id: key_bundle.suite_id,
+            one_time_prekey_public: key_bundle.one_time_prekey_public.clone(),
+            one_time_prekey_id: key_bundle.one_time_prekey_id,
+            signed_prekey_id: key_bundle.signed_prekey_id,
         };
 
         let mut core = self.inner.lock().unwrap();
@@ -254,9 +281,13 @@ impl ClassicCryptoCore {
         sealed_box.extend_from_slice(&encrypted_message.ciphertext);
 
         Ok(EncryptedMessageComponents {
-            ephemeral_public_key: encrypted_message.dh_public_key.to_vec(),
+            ephemeral_public_key: encrypted_message.dh_public_key,
             message_number: encrypted_message.message_number,
             content: base64::engine::general_purpose::STANDARD.encode(&sealed_box),
+            previous_chain_length: encrypted_message.previous_chain_length,
+            x3dh_ephemeral_key: encrypted_message.x3dh_ephemeral_key,
+            one_time_prekey_id: encrypted_message.one_time_prekey_id,
+            signed_prekey_id: encrypted_message.signed_prekey_id,
         })
     }
 
@@ -267,6 +298,7 @@ impl ClassicCryptoCore {
         ephemeral_public_key: Vec<u8>,
         message_number: u32,
         content: String,
+        previous_chain_length: u32,
     ) -> Result<String, CryptoError> {
         // Decode base64 sealed box
         let sealed_box = base64::engine::general_purpose::STANDARD
@@ -280,25 +312,55 @@ impl ClassicCryptoCore {
         let nonce = sealed_box[..12].to_vec();
         let ciphertext = sealed_box[12..].to_vec();
 
-        // Convert ephemeral_public_key to [u8; 32]
-        let dh_public_key: [u8; 32] = ephemeral_public_key
-            .try_into()
-            .map_err(|_| CryptoError::InvalidKeyData)?;
-
-        // Reconstruct EncryptedRatchetMessage
+        // Reconstruct EncryptedRatchetMessage (длину DH ключа проверяет сессия по suite)
         let encrypted_message = crate::crypto::double_ratchet::EncryptedRatchetMessage {
-            dh_public_key,
+            dh_public_key: ephemeral_public_key,
             message_number,
             ciphertext,
             nonce,
-            previous_chain_length: 0,  // Not used by decryption
+            previous_chain_length,
             suite_id: 1,  // Classic suite
+            key_confirmation: None,  // Not carried by the sealed-box format
+            x3dh_ephemeral_key: None,  // Only needed to create the receiving session
+            one_time_prekey_id: None,
+            signed_prekey_id: None,
+            transcript_tag: None,
+            encrypted_header: None,
         };
 
         let mut core = self.inner.lock().unwrap();
         core.decrypt_message(&session_id, &encrypted_message)
             .map_err(|_| CryptoError::DecryptionFailed)
     }
+
+    /// Encrypt a message for a session - returns canonical wire bytes
+    pub fn encrypt_message_wire(
+        &self,
+        session_id: String,
+        plaintext: String,
+    ) -> Result<Vec<u8>, CryptoError> {
+        let mut core = self.inner.lock().unwrap();
+        core.encrypt_message_wire(&session_id, &plaintext)
+            .map_err(|_| CryptoError::EncryptionFailed)
+    }
+
+    /// Decrypt a message from a session - accepts canonical wire bytes
+    pub fn decrypt_message_wire(
+        &self,
+        session_id: String,
+        wire_bytes: Vec<u8>,
+    ) -> Result<String, CryptoError> {
+        let mut core = self.inner.lock().unwrap();
+        core.decrypt_message_wire(&session_id, &wire_bytes)
+            .map_err(|_| CryptoError::DecryptionFailed)
+    }
+
+    /// 60-digit fingerprint of the local identity key
+    pub fn fingerprint(&self) -> Result<String, CryptoError> {
+        let core = self.inner.lock().unwrap();
+        core.fingerprint()
+            .map_err(|_| CryptoError::InitializationFailed)
+    }
 }
 
 /// Create a new CryptoCore instance (exported via UDL)
@@ -311,3 +373,44 @@ pub fn create_crypto_core() -> Result<Arc<ClassicCryptoCore>, CryptoError> {
         inner: Mutex::new(core),
     }))
 }
+
+/// Symmetric safety number for two identity keys (exported via UDL)
+pub fn combined_safety_number(local_identity: Vec<u8>, remote_identity: Vec<u8>) -> String {
+    crate::crypto::combined_safety_number(&local_identity, &remote_identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::double_ratchet::EncryptedRatchetMessage;
+    use crate::protocol::messages::ChatMessage;
+
+    #[test]
+    fn test_wire_bytes_match_app_state_path() {
+        let alice = create_crypto_core().unwrap();
+        let mut bob = CryptoCore::<ClassicSuiteProvider>::new().unwrap();
+        let bob_bundle = serde_json::to_vec(&bob.export_registration_bundle().unwrap()).unwrap();
+
+        let alice_session = alice.init_session("bob".to_string(), bob_bundle).unwrap();
+        let wire = alice
+            .encrypt_message_wire(alice_session, "hello".to_string())
+            .unwrap();
+
+        // AppState кладет те же байты в ChatMessage.content
+        let encrypted = EncryptedRatchetMessage::from_wire_bytes(&wire).unwrap();
+        let chat_msg = ChatMessage::from_encrypted("alice", "bob", &encrypted).unwrap();
+        let content = base64::engine::general_purpose::STANDARD
+            .decode(&chat_msg.content)
+            .unwrap();
+        assert_eq!(content, wire);
+
+        let alice_bundle = {
+            let core = alice.inner.lock().unwrap();
+            core.export_registration_bundle().unwrap()
+        };
+        let bob_session = bob
+            .init_receiving_session("alice", &alice_bundle, &chat_msg.to_encrypted().unwrap())
+            .unwrap();
+        assert_eq!(bob.decrypt_message_wire(&bob_session, &content).unwrap(), "hello");
+    }
+}
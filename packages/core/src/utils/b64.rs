@@ -11,3 +11,36 @@ pub fn decode(data: &str) -> Result<Vec<u8>, String> {
         .decode(data)
         .map_err(|e| format!("Base64 decode failed: {}", e))
 }
+
+/// Байтовое поле как base64 строка: `#[serde(with = "crate::utils::b64::bytes")]`
+pub mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        super::decode(&encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Необязательное байтовое поле как base64 строка:
+/// `#[serde(default, with = "crate::utils::b64::opt_bytes")]`
+pub mod opt_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match data {
+            Some(bytes) => serializer.serialize_some(&super::encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| super::decode(&encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
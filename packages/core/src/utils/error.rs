@@ -31,6 +31,12 @@ pub enum ConstructError {
 
 pub type Result<T> = std::result::Result<T, ConstructError>;
 
+impl From<crate::error::CryptoStringError> for ConstructError {
+    fn from(err: crate::error::CryptoStringError) -> Self {
+        ConstructError::CryptoError(err.0)
+    }
+}
+
 // Alias для совместимости
 pub type MessengerError = ConstructError;
 
@@ -0,0 +1,138 @@
+//! Типизированные идентификаторы сессий и контактов
+//!
+//! `SessionId` и `ContactId` - разные типы, поэтому перепутать их местами
+//! в вызове не получится:
+//!
+//! ```compile_fail
+//! use construct_core::utils::ids::{ContactId, SessionId};
+//!
+//! fn send(_to: &ContactId, _session: &SessionId) {}
+//!
+//! let contact = ContactId::new("bob").unwrap();
+//! let session = SessionId::new("s1").unwrap();
+//! send(&session, &contact);
+//! ```
+
+use crate::utils::error::{ConstructError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+/// Максимальная длина идентификатора
+pub const MAX_ID_LENGTH: usize = 128;
+
+/// Непустая строка не длиннее `MAX_ID_LENGTH` из видимых ASCII символов
+fn validate_id(kind: &str, value: &str) -> Result<()> {
+    if value.is_empty() {
+        return Err(ConstructError::ValidationError(format!("{} cannot be empty", kind)));
+    }
+    if value.len() > MAX_ID_LENGTH {
+        return Err(ConstructError::ValidationError(format!(
+            "{} is too long (max {} characters)",
+            kind, MAX_ID_LENGTH
+        )));
+    }
+    if !value.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(ConstructError::ValidationError(format!(
+            "{} contains invalid characters",
+            kind
+        )));
+    }
+    Ok(())
+}
+
+macro_rules! id_type {
+    ($(#[$meta:meta])* $name:ident, $kind:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Проверить формат и создать идентификатор
+            pub fn new(value: impl Into<String>) -> Result<Self> {
+                let value = value.into();
+                validate_id($kind, &value)?;
+                Ok(Self(value))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = ConstructError;
+
+            fn try_from(value: &str) -> Result<Self> {
+                Self::new(value)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = ConstructError;
+
+            fn try_from(value: String) -> Result<Self> {
+                Self::new(value)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ConstructError;
+
+            fn from_str(value: &str) -> Result<Self> {
+                Self::new(value)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Идентификатор ratchet-сессии
+    SessionId,
+    "Session id"
+);
+
+id_type!(
+    /// Идентификатор контакта (user id собеседника)
+    ContactId,
+    "Contact id"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_validate_format() {
+        let session = SessionId::new(crate::utils::uuid::generate_v4()).unwrap();
+        assert_eq!(session.to_string(), session.as_str());
+        assert_eq!(ContactId::try_from("bob_id").unwrap().as_str(), "bob_id");
+        assert_eq!("alice".parse::<ContactId>().unwrap().to_string(), "alice");
+
+        assert!(SessionId::new("").is_err());
+        assert!(ContactId::new("bob id").is_err());
+        assert!(ContactId::new("bob\n").is_err());
+        assert!(SessionId::new("s".repeat(MAX_ID_LENGTH + 1)).is_err());
+        assert!(SessionId::new("s".repeat(MAX_ID_LENGTH)).is_ok());
+
+        assert!(matches!(
+            ContactId::new(""),
+            Err(ConstructError::ValidationError(msg)) if msg == "Contact id cannot be empty"
+        ));
+    }
+}
@@ -5,5 +5,6 @@ pub mod logging;
 pub mod time;
 pub mod validation;
 pub mod uuid;
+pub mod ids;
 pub mod b64;
 pub mod serialization;
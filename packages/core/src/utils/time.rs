@@ -47,3 +47,12 @@ pub fn current_timestamp_millis() -> i64 {
             .as_millis() as i64
     }
 }
+
+/// Источник текущего времени (секунды с UNIX epoch).
+/// Позволяет подменять время в тестах
+pub type Clock = std::sync::Arc<dyn Fn() -> i64 + Send + Sync>;
+
+/// Системные часы
+pub fn system_clock() -> Clock {
+    std::sync::Arc::new(current_timestamp)
+}
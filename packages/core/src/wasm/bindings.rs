@@ -7,6 +7,8 @@ use crate::protocol::validation;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use crate::state::inbound::{InboundMessage, InboundQueue};
+use crate::utils::ids::{ContactId, SessionId};
 
 // Глобальное хранилище клиентов
 thread_local! {
@@ -14,6 +16,40 @@ thread_local! {
     static CRYPTO_MANAGERS: RefCell<HashMap<String, crypto::CryptoManager>> = RefCell::new(HashMap::new());
     static CONTACT_MANAGERS: RefCell<HashMap<String, contacts::ContactManager>> = RefCell::new(HashMap::new());
     static APP_STATES: RefCell<HashMap<String, Arc<Mutex<crate::state::app::AppState>>>> = RefCell::new(HashMap::new());
+    static INBOUND: RefCell<HashMap<String, InboundQueue>> = RefCell::new(HashMap::new());
+}
+
+/// Ошибка повторного входа: map клиентов уже заимствована выше по стеку
+/// (например, вызов из JS callback во время другой операции с клиентами)
+fn clients_busy() -> JsValue {
+    JsValue::from_str("Client store is busy (reentrant call)")
+}
+
+/// Выполнить `f` над клиентом. Повторный вход во время изменяющей операции
+/// возвращает ошибку вместо паники `RefCell` ("already borrowed")
+fn with_client<T>(
+    client_id: &str,
+    f: impl FnOnce(&ClientCrypto) -> Result<T, JsValue>,
+) -> Result<T, JsValue> {
+    CLIENTS.with(|clients| {
+        let clients_ref = clients.try_borrow().map_err(|_| clients_busy())?;
+        let client = clients_ref.get(client_id)
+            .ok_or_else(|| JsValue::from_str("Client not found"))?;
+        f(client)
+    })
+}
+
+/// Выполнить `f` над клиентом с изменяемым доступом (см. `with_client`)
+fn with_client_mut<T>(
+    client_id: &str,
+    f: impl FnOnce(&mut ClientCrypto) -> Result<T, JsValue>,
+) -> Result<T, JsValue> {
+    CLIENTS.with(|clients| {
+        let mut clients_ref = clients.try_borrow_mut().map_err(|_| clients_busy())?;
+        let client = clients_ref.get_mut(client_id)
+            .ok_or_else(|| JsValue::from_str("Client not found"))?;
+        f(client)
+    })
 }
 
 /// Создать нового криптографического клиента
@@ -25,8 +61,12 @@ pub fn create_crypto_client() -> Result<String, JsValue> {
     let client_id = uuid::Uuid::new_v4().to_string();
 
     CLIENTS.with(|clients| {
-        clients.borrow_mut().insert(client_id.clone(), client);
-    });
+        clients
+            .try_borrow_mut()
+            .map_err(|_| clients_busy())?
+            .insert(client_id.clone(), client);
+        Ok::<_, JsValue>(())
+    })?;
 
     Ok(client_id)
 }
@@ -34,11 +74,7 @@ pub fn create_crypto_client() -> Result<String, JsValue> {
 /// Получить публичные ключи клиента для регистрации (JSON)
 #[wasm_bindgen]
 pub fn get_registration_bundle(client_id: String) -> Result<String, JsValue> {
-    CLIENTS.with(|clients| {
-        let clients_ref = clients.borrow();
-        let client = clients_ref.get(&client_id)
-            .ok_or_else(|| JsValue::from_str("Client not found"))?;
-
+    with_client(&client_id, |client| {
         let bundle = crypto::get_registration_bundle(client)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
@@ -56,11 +92,7 @@ pub fn init_session(
     contact_id: String,
     remote_bundle_json: String,
 ) -> Result<String, JsValue> {
-    CLIENTS.with(|clients| {
-        let mut clients_ref = clients.borrow_mut();
-        let client = clients_ref.get_mut(&client_id)
-            .ok_or_else(|| JsValue::from_str("Client not found"))?;
-
+    with_client_mut(&client_id, |client| {
         let remote_bundle = crypto::deserialize_key_bundle(&remote_bundle_json)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
@@ -79,11 +111,7 @@ pub fn init_receiving_session(
     remote_bundle_json: String,
     first_message_json: String,
 ) -> Result<String, JsValue> {
-    CLIENTS.with(|clients| {
-        let mut clients_ref = clients.borrow_mut();
-        let client = clients_ref.get_mut(&client_id)
-            .ok_or_else(|| JsValue::from_str("Client not found"))?;
-
+    with_client_mut(&client_id, |client| {
         let remote_bundle = crypto::deserialize_key_bundle(&remote_bundle_json)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
@@ -103,11 +131,7 @@ pub fn encrypt_message(
     session_id: String,
     plaintext: String,
 ) -> Result<String, JsValue> {
-    CLIENTS.with(|clients| {
-        let mut clients_ref = clients.borrow_mut();
-        let client = clients_ref.get_mut(&client_id)
-            .ok_or_else(|| JsValue::from_str("Client not found"))?;
-
+    with_client_mut(&client_id, |client| {
         let encrypted = messaging::encrypt_message(client, &session_id, &plaintext)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
@@ -125,11 +149,7 @@ pub fn decrypt_message(
     session_id: String,
     encrypted_json: String,
 ) -> Result<String, JsValue> {
-    CLIENTS.with(|clients| {
-        let mut clients_ref = clients.borrow_mut();
-        let client = clients_ref.get_mut(&client_id)
-            .ok_or_else(|| JsValue::from_str("Client not found"))?;
-
+    with_client_mut(&client_id, |client| {
         let encrypted = messaging::deserialize_encrypted_message(&encrypted_json)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
@@ -138,11 +158,88 @@ pub fn decrypt_message(
     })
 }
 
+/// Контакты с активными сессиями, от недавно использованных
+/// Возвращает JSON массив `[{"contactId":"bob","lastUsed":1700000000}, ...]`
+#[wasm_bindgen]
+pub fn sessions_by_recency(client_id: String) -> Result<String, JsValue> {
+    with_client(&client_id, |client| {
+        let sessions: Vec<serde_json::Value> = client
+            .sessions_by_recency()
+            .into_iter()
+            .map(|(contact_id, last_used)| {
+                serde_json::json!({ "contactId": contact_id, "lastUsed": last_used })
+            })
+            .collect();
+
+        serde_json::to_string(&sessions)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    })
+}
+
+/// Положить расшифрованное сообщение в очередь клиента
+pub(crate) fn enqueue_inbound(client_id: &str, message: InboundMessage) {
+    INBOUND.with(|inbound| {
+        inbound
+            .borrow_mut()
+            .entry(client_id.to_string())
+            .or_default()
+            .push(message);
+    });
+}
+
+/// Обработчик входящего сообщения (вызывается из callback транспорта).
+/// Расшифровывает сообщение и кладет его в очередь для `take_inbound`
+#[wasm_bindgen]
+pub fn on_inbound_message(
+    client_id: String,
+    message_id: String,
+    from: String,
+    session_id: String,
+    encrypted_json: String,
+) -> Result<(), JsValue> {
+    let text = decrypt_message(client_id.clone(), session_id.clone(), encrypted_json)?;
+
+    enqueue_inbound(
+        &client_id,
+        InboundMessage {
+            message_id,
+            from,
+            session_id,
+            text,
+            timestamp: crate::utils::time::current_timestamp(),
+        },
+    );
+    Ok(())
+}
+
+/// Забрать все накопленные входящие сообщения клиента
+/// Возвращает JSON массив (пустой, если новых сообщений нет)
+#[wasm_bindgen]
+pub fn take_inbound(client_id: String) -> Result<String, JsValue> {
+    let messages = INBOUND.with(|inbound| {
+        inbound
+            .borrow_mut()
+            .get_mut(&client_id)
+            .map(InboundQueue::drain)
+            .unwrap_or_default()
+    });
+
+    serde_json::to_string(&messages)
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
 /// Удалить клиента из памяти
 #[wasm_bindgen]
 pub fn destroy_client(client_id: String) -> Result<(), JsValue> {
+    INBOUND.with(|inbound| {
+        inbound.borrow_mut().remove(&client_id);
+    });
+
     CLIENTS.with(|clients| {
-        clients.borrow_mut().remove(&client_id)
+        clients
+            .try_borrow_mut()
+            .map_err(|_| clients_busy())?
+            .remove(&client_id)
             .ok_or_else(|| JsValue::from_str("Client not found"))?;
         Ok(())
     })
@@ -210,6 +307,25 @@ pub fn crypto_manager_rotate_prekey(manager_id: String) -> Result<(), JsValue> {
     })
 }
 
+/// Отпечаток identity ключа (60 цифр)
+#[wasm_bindgen]
+pub fn crypto_manager_fingerprint(manager_id: String) -> Result<String, JsValue> {
+    CRYPTO_MANAGERS.with(|managers| {
+        let managers_ref = managers.borrow();
+        let manager = managers_ref.get(&manager_id)
+            .ok_or_else(|| JsValue::from_str("Manager not found"))?;
+
+        manager.fingerprint()
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    })
+}
+
+/// Общий safety number двух identity ключей (не зависит от порядка аргументов)
+#[wasm_bindgen]
+pub fn combined_safety_number(local_identity: Vec<u8>, remote_identity: Vec<u8>) -> String {
+    crate::crypto::combined_safety_number(&local_identity, &remote_identity)
+}
+
 /// Проверить наличие сессии
 #[wasm_bindgen]
 pub fn crypto_manager_has_session(manager_id: String, contact_id: String) -> Result<bool, JsValue> {
@@ -607,6 +723,8 @@ pub async fn app_state_send_message(
             .cloned()
             .ok_or_else(|| JsValue::from_str("AppState not found"))
     })?;
+    let to = ContactId::new(to).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    let session_id = SessionId::new(session_id).map_err(|e| JsValue::from_str(&e.to_string()))?;
 
     #[cfg(target_arch = "wasm32")]
     {
@@ -627,6 +745,53 @@ pub async fn app_state_send_message(
     }
 }
 
+/// Отправить сообщения, накопившиеся без соединения (вызывать после подключения)
+#[wasm_bindgen]
+pub async fn app_state_flush_outbound_queue(state_id: String) -> Result<u32, JsValue> {
+    let state_arc = APP_STATES.with(|states| {
+        states.borrow()
+            .get(&state_id)
+            .cloned()
+            .ok_or_else(|| JsValue::from_str("AppState not found"))
+    })?;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let mut state = state_arc.lock()
+            .map_err(|e| JsValue::from_str(&format!("Failed to lock state: {}", e)))?;
+
+        state.flush_outbound_queue().await
+            .map(|sent| sent as u32)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut state = state_arc.lock()
+            .map_err(|e| JsValue::from_str(&format!("Failed to lock state: {}", e)))?;
+
+        state.flush_outbound_queue()
+            .map(|sent| sent as u32)
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Количество сообщений, ждущих отправки
+#[wasm_bindgen]
+pub fn app_state_pending_message_count(state_id: String) -> Result<u32, JsValue> {
+    let state_arc = APP_STATES.with(|states| {
+        states.borrow()
+            .get(&state_id)
+            .cloned()
+            .ok_or_else(|| JsValue::from_str("AppState not found"))
+    })?;
+
+    let state = state_arc.lock()
+        .map_err(|e| JsValue::from_str(&format!("Failed to lock state: {}", e)))?;
+
+    Ok(state.pending_message_count() as u32)
+}
+
 /// Загрузить беседу с контактом (JSON)
 #[wasm_bindgen]
 pub async fn app_state_load_conversation(
@@ -801,7 +966,9 @@ pub fn app_state_set_auto_reconnect(state_id: String, enabled: bool) -> Result<(
     let mut state = state_arc.lock()
         .map_err(|e| JsValue::from_str(&format!("Failed to lock state: {}", e)))?;
 
-    state.reconnect_state_mut().set_enabled(enabled);
+    let mut policy = state.reconnect_state().policy().clone();
+    policy.enabled = enabled;
+    state.set_reconnect_policy(policy);
 
     Ok(())
 }
@@ -867,3 +1034,75 @@ pub fn destroy_app_state(state_id: String) -> Result<(), JsValue> {
         Ok(())
     })
 }
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use crate::state::inbound::DEFAULT_INBOUND_CAPACITY;
+    use wasm_bindgen_test::*;
+
+    fn inbound(n: usize) -> InboundMessage {
+        InboundMessage {
+            message_id: format!("m{}", n),
+            from: "bob".to_string(),
+            session_id: "s1".to_string(),
+            text: format!("text {}", n),
+            timestamp: n as i64,
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_take_inbound_drains_queue() {
+        enqueue_inbound("client_a", inbound(1));
+        enqueue_inbound("client_a", inbound(2));
+        enqueue_inbound("client_b", inbound(3));
+
+        let taken: Vec<InboundMessage> =
+            serde_json::from_str(&take_inbound("client_a".to_string()).unwrap()).unwrap();
+        assert_eq!(taken, vec![inbound(1), inbound(2)]);
+
+        // Очередь опустошена, чужая очередь не тронута
+        assert_eq!(take_inbound("client_a".to_string()).unwrap(), "[]");
+        let taken: Vec<InboundMessage> =
+            serde_json::from_str(&take_inbound("client_b".to_string()).unwrap()).unwrap();
+        assert_eq!(taken, vec![inbound(3)]);
+
+        // Неизвестный клиент - пустой массив
+        assert_eq!(take_inbound("unknown".to_string()).unwrap(), "[]");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_take_inbound_is_bounded() {
+        for n in 0..DEFAULT_INBOUND_CAPACITY + 10 {
+            enqueue_inbound("client_c", inbound(n));
+        }
+
+        let taken: Vec<InboundMessage> =
+            serde_json::from_str(&take_inbound("client_c".to_string()).unwrap()).unwrap();
+        assert_eq!(taken.len(), DEFAULT_INBOUND_CAPACITY);
+        assert_eq!(taken[0], inbound(10));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_reentrant_client_access_returns_error() {
+        let client_id = create_crypto_client().unwrap();
+
+        // "Callback" во время операции над клиентом снова обращается к клиентам
+        let nested = with_client_mut(&client_id, |_| {
+            Ok((
+                encrypt_message(client_id.clone(), "s1".to_string(), "hi".to_string()),
+                get_registration_bundle(client_id.clone()),
+            ))
+        })
+        .unwrap();
+        assert_eq!(
+            nested.0.unwrap_err().as_string().unwrap(),
+            "Client store is busy (reentrant call)"
+        );
+        assert!(nested.1.is_err());
+
+        // После выхода из внешней операции клиент снова доступен
+        assert!(get_registration_bundle(client_id.clone()).is_ok());
+        destroy_client(client_id).unwrap();
+    }
+}